@@ -0,0 +1,61 @@
+//! Benchmarks of bulk changes to the tape, comparing programs optimized with
+//! and without the `ranges` pass on synthetic programs which fill and clear
+//! many neighbouring cells.
+
+use std::io::{empty, sink};
+
+use bft_interp::ir::IrProgram;
+use bft_interp::optimizer::Pipeline;
+use bft_interp::VirtualMachine;
+use bft_types::BfProgram;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+
+/// A program which, 255 times over, adds to and then clears `width` cells.
+fn fill_and_clear(width: usize) -> BfProgram {
+    let source = format!(
+        "-[>{}{}{}<-]",
+        "+++>".repeat(width),
+        "<".repeat(width),
+        "[-]>".repeat(width) + &"<".repeat(width),
+    );
+    BfProgram::new(source, "bulk.bf").unwrap()
+}
+
+fn lower(program: &BfProgram, pipeline: &mut Pipeline) -> IrProgram {
+    let mut ir = IrProgram::from_program(program).unwrap();
+    pipeline.run(&mut ir);
+    ir
+}
+
+fn bench_ranges(c: &mut Criterion) {
+    let mut group = c.benchmark_group("fill_and_clear");
+    for width in [16, 256, 4096] {
+        let program = fill_and_clear(width);
+        let mut without_ranges = Pipeline::builtin();
+        without_ranges.remove("ranges");
+        let optimized = [
+            ("offsets", lower(&program, &mut without_ranges)),
+            ("ranges", lower(&program, &mut Pipeline::builtin())),
+        ];
+        for (name, ir) in &optimized {
+            group.bench_with_input(
+                BenchmarkId::new(*name, width),
+                ir,
+                |b, ir| {
+                    b.iter(|| {
+                        let mut vm = VirtualMachine::<u8>::new(
+                            &program,
+                            width + 2,
+                            false,
+                        );
+                        vm.interpret_ir(ir, &mut empty(), &mut sink()).unwrap();
+                    })
+                },
+            );
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_ranges);
+criterion_main!(benches);
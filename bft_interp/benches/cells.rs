@@ -0,0 +1,57 @@
+//! Benchmarks of the width of the cells, running the same program with 8 bit
+//! cells, which the Virtual Machine runs with loops specialized for them, and
+//! with 16 and 32 bit cells, which it runs with the generic loops.
+
+use std::io::{empty, sink};
+
+use bft_interp::ir::IrProgram;
+use bft_interp::optimizer::Pipeline;
+use bft_interp::{CellKind, VirtualMachine};
+use bft_types::BfProgram;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+
+/// A program of nested loops which shuffle values between cells, without any
+/// cell ever wrapping around, so that it runs the same with any width.
+fn shuffle() -> BfProgram {
+    let source = format!(
+        "{}[>{}[>{}[->+<]>[-<+>]<[-]<-]<-]",
+        "+".repeat(20),
+        "+".repeat(20),
+        "+".repeat(100),
+    );
+    BfProgram::new(source, "shuffle.bf").unwrap()
+}
+
+/// Runs the program directly, or lowered if there is a lowered program.
+fn run<T>(program: &BfProgram, ir: Option<&IrProgram>)
+where
+    T: CellKind + Default + Clone + Copy + PartialEq,
+{
+    let mut vm = VirtualMachine::<T>::new(program, 4, false);
+    match ir {
+        Some(ir) => vm.interpret_ir(ir, &mut empty(), &mut sink()).unwrap(),
+        None => vm.interpret(&mut empty(), &mut sink()).unwrap(),
+    }
+}
+
+fn bench_cells(c: &mut Criterion) {
+    let mut group = c.benchmark_group("shuffle");
+    let program = shuffle();
+    let mut optimized = IrProgram::from_program(&program).unwrap();
+    Pipeline::builtin().run(&mut optimized);
+    for (passes, ir) in [("none", None), ("builtin", Some(&optimized))] {
+        group.bench_function(BenchmarkId::new("u8", passes), |b| {
+            b.iter(|| run::<u8>(&program, ir))
+        });
+        group.bench_function(BenchmarkId::new("u16", passes), |b| {
+            b.iter(|| run::<u16>(&program, ir))
+        });
+        group.bench_function(BenchmarkId::new("u32", passes), |b| {
+            b.iter(|| run::<u32>(&program, ir))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_cells);
+criterion_main!(benches);
@@ -0,0 +1,50 @@
+//! Benchmarks of the ways of dispatching operations, running the same
+//! optimized programs with `match` and threaded dispatch. Programs are run
+//! without optimization too, where most of the time goes on dispatching.
+
+use std::io::{empty, sink};
+
+use bft_interp::dispatch::DispatchKind;
+use bft_interp::ir::IrProgram;
+use bft_interp::optimizer::Pipeline;
+use bft_interp::VirtualMachine;
+use bft_types::BfProgram;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+
+/// A program which counts down from 255 in two nested loops, adding to two
+/// cells on each pass of the inner loop.
+fn nested_loops() -> BfProgram {
+    let source = "-[>-[>+>+<<-]<-]";
+    BfProgram::new(source.to_string(), "nested.bf").unwrap()
+}
+
+fn bench_dispatch(c: &mut Criterion) {
+    let mut group = c.benchmark_group("dispatch");
+    let program = nested_loops();
+    let unoptimized = IrProgram::from_program(&program).unwrap();
+    let mut optimized = unoptimized.clone();
+    Pipeline::builtin().run(&mut optimized);
+    for (passes, ir) in [("none", &unoptimized), ("builtin", &optimized)] {
+        for (name, kind) in [
+            ("match", DispatchKind::Match),
+            ("threaded", DispatchKind::Threaded),
+        ] {
+            group.bench_with_input(
+                BenchmarkId::new(name, passes),
+                ir,
+                |b, ir| {
+                    b.iter(|| {
+                        let mut vm =
+                            VirtualMachine::<u8>::new(&program, 8, false)
+                                .with_dispatch(kind);
+                        vm.interpret_ir(ir, &mut empty(), &mut sink()).unwrap();
+                    })
+                },
+            );
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_dispatch);
+criterion_main!(benches);
@@ -0,0 +1,660 @@
+//! A Brainfuck assembly language, with named operations and labelled cells,
+//! which assembles down to Brainfuck source or directly to the intermediate
+//! representation.
+//!
+//! Each line holds at most one statement, and `;` starts a comment running to
+//! the end of the line. Mnemonics are not case sensitive:
+//!
+//! - `INC n` and `DEC n` add to or subtract from the current cell.
+//! - `LEFT n` and `RIGHT n` move the head.
+//! - `IN` and `OUT` read into and write out the current cell.
+//! - `LOOP` and `END` surround a loop, as `[` and `]` do.
+//! - `SET n` sets the current cell to the value.
+//! - `ADDTO target` adds the current cell to the target cell, and clears the
+//!   current cell.
+//! - `GOTO label` moves the head to the labelled cell.
+//!
+//! The counts of `INC`, `DEC`, `LEFT` and `RIGHT` default to 1. A line may
+//! start with `name:` to label the cell the head is on at that point, which
+//! is tracked as the program is assembled. The target of `ADDTO` is either a
+//! label or an offset from the head. Labels must be defined before they are
+//! used, and cannot be used once a loop has moved the head, as where the head
+//! is afterwards depends on how many times the loop ran.
+//!
+//! ```
+//! use bft_interp::asm::AsmProgram;
+//! let source = "
+//!     SET 2
+//!     sum:  RIGHT
+//!           SET 3
+//!           ADDTO sum   ; the sum is now 5
+//!           GOTO sum
+//!           OUT
+//! ";
+//! let program = AsmProgram::parse(source, "sum.bfa").unwrap();
+//! assert_eq!(
+//!     program.to_brainfuck(),
+//!     "[-]++\n>\n[-]+++\n[-<+>]\n<\n.\n"
+//! );
+//! ```
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use bft_types::ops::Operation;
+use bft_types::vm_error::VirtualMachineError;
+use bft_types::{BfProgram, InstructionInfo};
+
+use crate::ir::{IrNode, IrOp, IrProgram};
+
+/// The character which starts a comment running to the end of the line.
+const COMMENT: char = ';';
+
+/// A single operation of an assembly program, once its labels have been
+/// resolved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AsmOp {
+    /// Adds the amount to the current cell.
+    Inc(i32),
+    /// Subtracts the amount from the current cell.
+    Dec(i32),
+    /// Moves the head left by the number of cells.
+    Left(isize),
+    /// Moves the head right by the number of cells.
+    Right(isize),
+    /// Reads into the current cell.
+    In,
+    /// Writes out the current cell.
+    Out,
+    /// Starts a loop.
+    Loop,
+    /// Ends the innermost loop.
+    End,
+    /// Sets the current cell to the value.
+    Set(i32),
+    /// Adds the current cell to the cell at the offset from the head, and then
+    /// clears the current cell.
+    AddTo(isize),
+}
+
+impl AsmOp {
+    /// Appends the Brainfuck instructions for the operation to the code.
+    fn write_brainfuck(&self, code: &mut String) {
+        let moves = |offset: isize| {
+            let c = if offset < 0 { '<' } else { '>' };
+            c.to_string().repeat(offset.unsigned_abs())
+        };
+        match *self {
+            AsmOp::Inc(amount) => code.push_str(&"+".repeat(amount as usize)),
+            AsmOp::Dec(amount) => code.push_str(&"-".repeat(amount as usize)),
+            AsmOp::Left(count) => code.push_str(&moves(-count)),
+            AsmOp::Right(count) => code.push_str(&moves(count)),
+            AsmOp::In => code.push(','),
+            AsmOp::Out => code.push('.'),
+            AsmOp::Loop => code.push('['),
+            AsmOp::End => code.push(']'),
+            AsmOp::Set(value) => {
+                code.push_str("[-]");
+                code.push_str(&"+".repeat(value as usize));
+            }
+            AsmOp::AddTo(offset) => {
+                code.push_str("[-");
+                code.push_str(&moves(offset));
+                code.push('+');
+                code.push_str(&moves(-offset));
+                code.push(']');
+            }
+        }
+    }
+
+    /// The operations of the intermediate representation which the operation
+    /// assembles to.
+    fn ir_ops(&self) -> Vec<IrOp> {
+        match *self {
+            AsmOp::Inc(amount) => vec![IrOp::Add(amount)],
+            AsmOp::Dec(amount) => vec![IrOp::Add(-amount)],
+            AsmOp::Left(count) => vec![IrOp::Move(-count)],
+            AsmOp::Right(count) => vec![IrOp::Move(count)],
+            AsmOp::In => vec![IrOp::Input],
+            AsmOp::Out => vec![IrOp::Output],
+            AsmOp::Loop => vec![IrOp::LoopStart],
+            AsmOp::End => vec![IrOp::LoopEnd],
+            AsmOp::Set(0) => vec![IrOp::Clear],
+            AsmOp::Set(value) => vec![IrOp::Clear, IrOp::Add(value)],
+            AsmOp::AddTo(offset) => vec![IrOp::CopyLoop(vec![(offset, 1)])],
+        }
+    }
+
+    /// The first Brainfuck instruction which the operation assembles to, used
+    /// as the source of its nodes in the intermediate representation.
+    fn first_operation(&self) -> Operation {
+        match *self {
+            AsmOp::Inc(_) => Operation::IncrementByte,
+            AsmOp::Dec(_) => Operation::DecrementByte,
+            AsmOp::Left(_) => Operation::DecrementPointer,
+            AsmOp::Right(_) => Operation::IncrementPointer,
+            AsmOp::In => Operation::InputByte,
+            AsmOp::Out => Operation::OutputByte,
+            AsmOp::Loop | AsmOp::Set(_) | AsmOp::AddTo(_) => {
+                Operation::StartLoop
+            }
+            AsmOp::End => Operation::EndLoop,
+        }
+    }
+}
+
+/// An operation, along with the line and column of the statement it came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Statement {
+    op: AsmOp,
+    line: usize,
+    column: usize,
+}
+
+impl Statement {
+    /// Retrieves the operation of the statement.
+    pub fn op(&self) -> AsmOp {
+        self.op
+    }
+
+    /// Retrieves the line on which the statement is found.
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    /// Retrieves the column of the mnemonic of the statement.
+    pub fn column(&self) -> usize {
+        self.column
+    }
+}
+
+/// A loop which is still open while assembling.
+struct OpenLoop {
+    /// Where the head was when the loop started.
+    start: Option<isize>,
+    /// Whether a label was defined or used inside the loop.
+    uses_labels: bool,
+}
+
+/// The state kept while assembling a program.
+struct Assembler<'a> {
+    filename: &'a Path,
+    statements: Vec<Statement>,
+    /// The cell of each label, relative to where the head started.
+    labels: HashMap<String, isize>,
+    /// Where the head is, relative to where it started, or None once a loop
+    /// has moved it.
+    position: Option<isize>,
+    loops: Vec<OpenLoop>,
+    /// The line and column of the statement being assembled.
+    line: usize,
+    column: usize,
+}
+
+impl Assembler<'_> {
+    /// An error about the statement being assembled.
+    fn error(&self, message: String) -> VirtualMachineError {
+        VirtualMachineError::InvalidAssembly {
+            filename: self.filename.display().to_string(),
+            message,
+            line: self.line,
+            column: self.column,
+        }
+    }
+
+    /// Adds a statement, keeping track of where the head is.
+    fn push(&mut self, op: AsmOp) -> Result<(), VirtualMachineError> {
+        match op {
+            AsmOp::Left(count) => {
+                self.position = self.position.map(|p| p - count)
+            }
+            AsmOp::Right(count) => {
+                self.position = self.position.map(|p| p + count)
+            }
+            AsmOp::Loop => self.loops.push(OpenLoop {
+                start: self.position,
+                uses_labels: false,
+            }),
+            AsmOp::End => {
+                let open = self.loops.pop().ok_or_else(|| {
+                    self.error("END without a matching LOOP".to_string())
+                })?;
+                if open.start != self.position {
+                    if open.uses_labels {
+                        return Err(self.error(
+                            "a loop which uses labels must leave the head \
+                            where it started"
+                                .to_string(),
+                        ));
+                    }
+                    self.position = None;
+                }
+                if let Some(outer) = self.loops.last_mut() {
+                    outer.uses_labels |= open.uses_labels;
+                }
+            }
+            _ => {}
+        }
+        self.statements.push(Statement {
+            op,
+            line: self.line,
+            column: self.column,
+        });
+        Ok(())
+    }
+
+    /// Where the head is, for defining or using a label.
+    fn labelled_position(&mut self) -> Result<isize, VirtualMachineError> {
+        if let Some(open) = self.loops.last_mut() {
+            open.uses_labels = true;
+        }
+        self.position.ok_or_else(|| {
+            self.error(
+                "labels cannot be used once a loop has moved the head"
+                    .to_string(),
+            )
+        })
+    }
+
+    /// Defines a label for the cell the head is on.
+    fn define(&mut self, name: &str) -> Result<(), VirtualMachineError> {
+        if !is_label(name) {
+            return Err(self.error(format!("invalid label '{}'", name)));
+        }
+        let position = self.labelled_position()?;
+        if self.labels.insert(name.to_string(), position).is_some() {
+            return Err(self.error(format!("label '{}' defined twice", name)));
+        }
+        Ok(())
+    }
+
+    /// The offset from the head to the labelled cell.
+    fn offset_to(&mut self, name: &str) -> Result<isize, VirtualMachineError> {
+        let position = self.labelled_position()?;
+        match self.labels.get(name) {
+            Some(cell) => Ok(cell - position),
+            None => Err(self.error(format!("undefined label '{}'", name))),
+        }
+    }
+
+    /// Parses a count, which must be at least 1, defaulting to 1.
+    fn count(&self, operand: Option<&str>) -> Result<i32, VirtualMachineError> {
+        match operand.map(str::parse::<i32>) {
+            None => Ok(1),
+            Some(Ok(count)) if count > 0 => Ok(count),
+            _ => Err(self.error(format!(
+                "expected a count of at least 1, found '{}'",
+                operand.unwrap_or_default()
+            ))),
+        }
+    }
+
+    /// Assembles a single statement from its mnemonic and operand.
+    fn statement(
+        &mut self,
+        mnemonic: &str,
+        operand: Option<&str>,
+    ) -> Result<(), VirtualMachineError> {
+        let mnemonic = mnemonic.to_ascii_uppercase();
+        let takes_operand =
+            !matches!(mnemonic.as_str(), "IN" | "OUT" | "LOOP" | "END");
+        if let (false, Some(operand)) = (takes_operand, operand) {
+            return Err(self.error(format!(
+                "{} takes no operand, found '{}'",
+                mnemonic, operand
+            )));
+        }
+        let required = |assembler: &Self| {
+            operand.ok_or_else(|| {
+                assembler.error(format!("{} needs an operand", mnemonic))
+            })
+        };
+        let op = match mnemonic.as_str() {
+            "INC" => AsmOp::Inc(self.count(operand)?),
+            "DEC" => AsmOp::Dec(self.count(operand)?),
+            "LEFT" => AsmOp::Left(self.count(operand)? as isize),
+            "RIGHT" => AsmOp::Right(self.count(operand)? as isize),
+            "IN" => AsmOp::In,
+            "OUT" => AsmOp::Out,
+            "LOOP" => AsmOp::Loop,
+            "END" => AsmOp::End,
+            "SET" => {
+                let operand = required(self)?;
+                match operand.parse::<i32>() {
+                    Ok(value) if value >= 0 => AsmOp::Set(value),
+                    _ => {
+                        return Err(self.error(format!(
+                            "expected a value of at least 0, found '{}'",
+                            operand
+                        )))
+                    }
+                }
+            }
+            "ADDTO" => {
+                let operand = required(self)?;
+                let offset = match operand.parse::<i32>() {
+                    Ok(offset) => offset as isize,
+                    Err(_) if is_label(operand) => self.offset_to(operand)?,
+                    Err(_) => {
+                        return Err(self.error(format!(
+                            "expected a label or an offset, found '{}'",
+                            operand
+                        )))
+                    }
+                };
+                if offset == 0 {
+                    return Err(
+                        self.error("cannot add a cell to itself".to_string())
+                    );
+                }
+                AsmOp::AddTo(offset)
+            }
+            "GOTO" => {
+                let operand = required(self)?;
+                let offset = self.offset_to(operand)?;
+                match offset {
+                    0 => return Ok(()),
+                    offset if offset < 0 => AsmOp::Left(-offset),
+                    offset => AsmOp::Right(offset),
+                }
+            }
+            _ => {
+                return Err(
+                    self.error(format!("unknown mnemonic '{}'", mnemonic))
+                )
+            }
+        };
+        self.push(op)
+    }
+}
+
+/// Whether the name can be used as a label: a letter or underscore, followed
+/// by letters, digits and underscores.
+fn is_label(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Splits a line into its words, along with the column of each of them.
+fn words(line: &str) -> Vec<(usize, &str)> {
+    let mut words = Vec::new();
+    let mut start = None;
+    for (n, c) in line.char_indices().chain([(line.len(), ' ')]) {
+        match (start, c.is_whitespace()) {
+            (None, false) => start = Some(n),
+            (Some(from), true) => {
+                words.push((line[..from].chars().count() + 1, &line[from..n]));
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    words
+}
+
+/// A Brainfuck assembly program, with its labels resolved.
+#[derive(Debug, Clone)]
+pub struct AsmProgram {
+    statements: Vec<Statement>,
+    filename: PathBuf,
+}
+
+impl AsmProgram {
+    /// Assembles a program from its source, failing with an `InvalidAssembly`
+    /// error pointing at the first statement which cannot be assembled.
+    /// ```
+    /// use bft_interp::asm::AsmProgram;
+    /// assert!(AsmProgram::parse("LOOP\nJUMP 3\nEND", "bad.bfa").is_err());
+    /// ```
+    pub fn parse<P>(
+        source: &str,
+        filename: P,
+    ) -> Result<Self, VirtualMachineError>
+    where
+        P: AsRef<Path>,
+    {
+        let mut assembler = Assembler {
+            filename: filename.as_ref(),
+            statements: Vec::new(),
+            labels: HashMap::new(),
+            position: Some(0),
+            loops: Vec::new(),
+            line: 1,
+            column: 1,
+        };
+        for (n, line) in source.lines().enumerate() {
+            let code = line.split(COMMENT).next().unwrap_or_default();
+            let mut words = words(code).into_iter().peekable();
+            assembler.line = n + 1;
+            if let Some((column, label)) =
+                words.next_if(|(_, word)| word.ends_with(':'))
+            {
+                assembler.column = column;
+                assembler.define(label.trim_end_matches(':'))?;
+            }
+            let Some((column, mnemonic)) = words.next() else {
+                continue;
+            };
+            assembler.column = column;
+            let operand = words.next().map(|(_, operand)| operand);
+            if let Some((column, extra)) = words.next() {
+                assembler.column = column;
+                return Err(assembler.error(format!("unexpected '{}'", extra)));
+            }
+            assembler.statement(mnemonic, operand)?;
+        }
+        if !assembler.loops.is_empty() {
+            return Err(
+                assembler.error("LOOP without a matching END".to_string())
+            );
+        }
+        Ok(Self {
+            statements: assembler.statements,
+            filename: filename.as_ref().to_path_buf(),
+        })
+    }
+
+    /// Reads and assembles a program from a file.
+    pub fn from_file<P>(filename: P) -> Result<Self, Box<dyn Error>>
+    where
+        P: AsRef<Path>,
+    {
+        let source = fs::read_to_string(&filename)?;
+        Ok(AsmProgram::parse(&source, filename)?)
+    }
+
+    /// Retrieves the statements of the program.
+    pub fn statements(&self) -> &[Statement] {
+        &self.statements
+    }
+
+    /// Retrieves the filename of the program.
+    pub fn filename(&self) -> &Path {
+        &self.filename
+    }
+
+    /// Assembles the program down to Brainfuck source, with the instructions
+    /// of each statement on a line of their own, indented by how deeply they
+    /// are nested in loops.
+    pub fn to_brainfuck(&self) -> String {
+        let mut code = String::new();
+        let mut depth = 0;
+        for statement in &self.statements {
+            if statement.op == AsmOp::End {
+                depth -= 1;
+            }
+            code.push_str(&"  ".repeat(depth));
+            statement.op.write_brainfuck(&mut code);
+            code.push('\n');
+            if statement.op == AsmOp::Loop {
+                depth += 1;
+            }
+        }
+        code
+    }
+
+    /// Assembles the program down to a Brainfuck program, by way of its
+    /// source.
+    pub fn to_program(&self) -> Result<BfProgram, VirtualMachineError> {
+        BfProgram::new(self.to_brainfuck(), &self.filename)
+    }
+
+    /// Assembles the program directly to the intermediate representation,
+    /// where `SET` and `ADDTO` become single nodes, as the optimizer would
+    /// make them. Each node reports the position of the statement it came from.
+    /// ```
+    /// use bft_interp::asm::AsmProgram;
+    /// use bft_interp::ir::IrOp;
+    /// let program = AsmProgram::parse("SET 3\nADDTO 2", "copy.bfa").unwrap();
+    /// let ir = program.to_ir();
+    /// assert_eq!(ir.nodes()[1].op(), &IrOp::Add(3));
+    /// assert_eq!(ir.nodes()[2].op(), &IrOp::CopyLoop(vec![(2, 1)]));
+    /// assert_eq!(ir.nodes()[2].source().line(), 2);
+    /// ```
+    pub fn to_ir(&self) -> IrProgram {
+        let nodes = self
+            .statements
+            .iter()
+            .flat_map(|statement| {
+                let source = InstructionInfo::new(
+                    statement.op.first_operation(),
+                    statement.line,
+                    statement.column,
+                );
+                statement
+                    .op
+                    .ir_ops()
+                    .into_iter()
+                    .map(move |op| IrNode::new(op, source))
+            })
+            .collect();
+        IrProgram::from_nodes(nodes, &self.filename)
+            .expect("assembled loops are always balanced")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AsmOp, AsmProgram};
+    use crate::optimizer::Pipeline;
+    use crate::VirtualMachine;
+    use bft_types::vm_error::VirtualMachineError;
+    use std::io::Cursor;
+
+    /// Asserts that assembling the source fails on the given line and column.
+    fn assert_error_at(source: &str, line: usize, column: usize) {
+        match AsmProgram::parse(source, "test.bfa") {
+            Err(VirtualMachineError::InvalidAssembly {
+                line: l,
+                column: c,
+                ..
+            }) => assert_eq!((l, c), (line, column), "{}", source),
+            other => {
+                panic!("expected an error for {:?}, got {:?}", source, other)
+            }
+        }
+    }
+
+    #[test]
+    fn test_statements() {
+        let source = "  top: inc 3 ; comment\n\nLOOP\n  DEC\n  right 2\nEND";
+        let program = AsmProgram::parse(source, "test.bfa").unwrap();
+        let ops: Vec<AsmOp> =
+            program.statements().iter().map(|s| s.op()).collect();
+        assert_eq!(
+            ops,
+            [
+                AsmOp::Inc(3),
+                AsmOp::Loop,
+                AsmOp::Dec(1),
+                AsmOp::Right(2),
+                AsmOp::End
+            ]
+        );
+        assert_eq!(program.statements()[0].column(), 8);
+        assert_eq!(program.statements()[3].line(), 5);
+        assert_eq!(program.statements()[3].column(), 3);
+        assert_eq!(program.to_brainfuck(), "+++\n[\n  -\n  >>\n]\n");
+    }
+
+    #[test]
+    fn test_labels() {
+        let source =
+            "a: RIGHT 3\nb: GOTO a\nADDTO b\nLOOP\nGOTO b\nGOTO a\nEND";
+        let program = AsmProgram::parse(source, "test.bfa").unwrap();
+        let ops: Vec<AsmOp> =
+            program.statements().iter().map(|s| s.op()).collect();
+        assert_eq!(
+            ops,
+            [
+                AsmOp::Right(3),
+                AsmOp::Left(3),
+                AsmOp::AddTo(3),
+                AsmOp::Loop,
+                AsmOp::Right(3),
+                AsmOp::Left(3),
+                AsmOp::End
+            ]
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_error_at("INC\n  JUMP 3", 2, 3);
+        assert_error_at("INC 0", 1, 1);
+        assert_error_at("SET -1", 1, 1);
+        assert_error_at("OUT 2", 1, 1);
+        assert_error_at("SET 1 2", 1, 7);
+        assert_error_at("ADDTO 0", 1, 1);
+        assert_error_at("GOTO nowhere", 1, 1);
+        assert_error_at("a:\na: INC", 2, 1);
+        assert_error_at("END", 1, 1);
+        assert_error_at("LOOP\nINC", 2, 1);
+        // The head could be anywhere after a loop which moves it.
+        assert_error_at("a: LOOP\nRIGHT\nEND\nGOTO a", 4, 1);
+        assert_error_at("a: LOOP\nGOTO a\nRIGHT\nEND", 4, 1);
+        assert!(AsmProgram::parse("LOOP\nRIGHT\nEND\nOUT", "t.bfa").is_ok());
+    }
+
+    #[test]
+    fn test_brainfuck_and_ir_agree() {
+        let source = "
+            input: IN
+                   RIGHT
+            x:     SET 7
+            LOOP
+                DEC
+                RIGHT
+                INC 2
+                GOTO x
+            END
+            GOTO input
+            ADDTO 2
+            RIGHT 2
+            OUT
+            GOTO x
+            OUT
+        ";
+        let program = AsmProgram::parse(source, "test.bfa").unwrap();
+        let bf = program.to_program().unwrap();
+        let mut ir = program.to_ir();
+        let mut expected = Vec::new();
+        VirtualMachine::<u8>::new(&bf, 0, false)
+            .interpret(&mut Cursor::new(vec![5]), &mut expected)
+            .unwrap();
+        assert_eq!(expected, [19, 0]);
+        for mut pipeline in [Pipeline::new(), Pipeline::builtin()] {
+            pipeline.run(&mut ir);
+            let mut output = Vec::new();
+            VirtualMachine::<u8>::new(&bf, 0, false)
+                .interpret_ir(&ir, &mut Cursor::new(vec![5]), &mut output)
+                .unwrap();
+            assert_eq!(output, expected);
+        }
+    }
+}
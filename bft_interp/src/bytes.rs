@@ -0,0 +1,547 @@
+//! Interpreter loops specialized for tapes of 8 bit cells, which are by far
+//! the most common, and which the Virtual Machine switches to by itself.
+//!
+//! The generic loops pay for their flexibility on every instruction: each
+//! operation goes through the methods of the Virtual Machine, and each byte of
+//! output is flushed as soon as it is written. These loops keep the state they
+//! need in locals, and hold output back until a newline, until the program
+//! reads input or until it stops.
+//!
+//! Anything out of the ordinary, such as the head leaving a fixed size tape,
+//! reaching the step limit, an unmatched bracket or an extension instruction,
+//! hands the rest of the program back to the generic loop, which deals with
+//! it exactly as it always has.
+
+use std::io::{Read, Write};
+use std::ops::Range;
+use std::path::Path;
+
+use bft_types::jumps::JumpTable;
+use bft_types::ops::Operation;
+use bft_types::vm_error::VirtualMachineError;
+use bft_types::InstructionInfo;
+
+use crate::eof::EofBehavior;
+use crate::ir::{IrNode, IrOp, IrProgram};
+use crate::{read_byte, CellKind, VirtualMachine};
+
+/// The most output held back before it is written, even without a newline.
+const OUTPUT_BUFFER_LEN: usize = 8 * 1024;
+
+/// Output which is held back until a newline, rather than flushed after every
+/// byte, so that interactive programs still show each line as it is written.
+struct LineBuffer<'w, W: ?Sized> {
+    writer: &'w mut W,
+    buffer: Vec<u8>,
+}
+
+impl<'w, W> LineBuffer<'w, W>
+where
+    W: Write + ?Sized,
+{
+    fn new(writer: &'w mut W) -> Self {
+        Self {
+            writer,
+            buffer: Vec::new(),
+        }
+    }
+
+    fn push(&mut self, byte: u8) -> Result<(), VirtualMachineError> {
+        self.buffer.push(byte);
+        if byte == b'\n' || self.buffer.len() >= OUTPUT_BUFFER_LEN {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn extend(&mut self, bytes: &[u8]) -> Result<(), VirtualMachineError> {
+        self.buffer.extend_from_slice(bytes);
+        if bytes.contains(&b'\n') || self.buffer.len() >= OUTPUT_BUFFER_LEN {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), VirtualMachineError> {
+        if !self.buffer.is_empty() {
+            self.writer.write_all(&self.buffer)?;
+            self.writer.flush()?;
+            self.buffer.clear();
+        }
+        Ok(())
+    }
+}
+
+/// The state of a Virtual Machine with 8 bit cells, taken out of it while one
+/// of the loops runs.
+struct Bytes<'t> {
+    tape: &'t mut Vec<u8>,
+    filename: &'t Path,
+    head: usize,
+    position: usize,
+    steps: u64,
+    step_limit: u64,
+    output_written: u64,
+    output_limit: u64,
+    input_read: u64,
+    input_limit: u64,
+    loop_counts: &'t mut [u64],
+    last_io_step: Option<u64>,
+    entering: bool,
+    growable: bool,
+    eof_behavior: EofBehavior,
+}
+
+impl Bytes<'_> {
+    /// Takes back the step counted for the operation at the current position,
+    /// so that the generic loop can carry on from it.
+    fn hand_over(&mut self) -> Result<bool, VirtualMachineError> {
+        self.steps -= 1;
+        Ok(false)
+    }
+
+    /// Counts the bytes about to be written, returning whether they fit within
+    /// the output limit. If they do not, the generic loop fails on them.
+    fn count_output(&mut self, len: usize) -> bool {
+        let fits = self.output_limit - self.output_written >= len as u64;
+        if fits {
+            self.output_written += len as u64;
+            self.last_io_step = Some(self.steps);
+        }
+        fits
+    }
+
+    /// Finds the position on the tape at the given offset from the head,
+    /// growing the tape to reach it if it is extensible.
+    fn offset(&mut self, offset: isize) -> Option<usize> {
+        let target = self.head.checked_add_signed(offset)?;
+        if target >= self.tape.len() {
+            if !self.growable {
+                return None;
+            }
+            self.tape.resize(target + 1, 0);
+        }
+        Some(target)
+    }
+
+    /// Finds the positions on the tape of `len` cells from the given offset
+    /// from the head, growing the tape to reach them if it is extensible.
+    fn range(&mut self, offset: isize, len: usize) -> Option<Range<usize>> {
+        let start = self.offset(offset)?;
+        if let Some(last) = len.checked_sub(1) {
+            self.offset(offset + last as isize)?;
+        }
+        Some(start..start + len)
+    }
+
+    /// Reads into the cell at the head for the input operation at `source`,
+    /// as `read_input` does.
+    fn read<R>(
+        &mut self,
+        input: &mut R,
+        source: InstructionInfo,
+    ) -> Result<(), VirtualMachineError>
+    where
+        R: Read + ?Sized,
+    {
+        self.last_io_step = Some(self.steps);
+        let byte = if self.input_read == self.input_limit {
+            None
+        } else {
+            read_byte(input)?
+        };
+        let cell = &mut self.tape[self.head];
+        match byte {
+            Some(byte) => {
+                *cell = byte;
+                self.input_read += 1;
+            }
+            None => match self.eof_behavior {
+                EofBehavior::Error => {
+                    return Err(VirtualMachineError::InputExhausted {
+                        line: source.line(),
+                        column: source.column(),
+                        filename: self.filename.display().to_string(),
+                        read: self.input_read,
+                    })
+                }
+                EofBehavior::Zero => *cell = 0,
+                EofBehavior::Unchanged => {}
+                EofBehavior::MaxValue => *cell = u8::MAX,
+            },
+        }
+        Ok(())
+    }
+
+    /// Runs the instructions of a program, returning whether it ran to the
+    /// end rather than handing over to the generic loop.
+    fn run_instructions<R, W>(
+        &mut self,
+        instructions: &[InstructionInfo],
+        jumps: &JumpTable,
+        input: &mut R,
+        output: &mut LineBuffer<'_, W>,
+    ) -> Result<bool, VirtualMachineError>
+    where
+        R: Read + ?Sized,
+        W: Write + ?Sized,
+    {
+        while let Some(instruction) = instructions.get(self.position) {
+            if self.steps == self.step_limit {
+                return Ok(false);
+            }
+            self.steps += 1;
+            let head = self.head;
+            let entering = std::mem::take(&mut self.entering);
+            match instruction.operation() {
+                Operation::IncrementByte => {
+                    self.tape[head] = self.tape[head].wrapping_add(1);
+                }
+                Operation::DecrementByte => {
+                    self.tape[head] = self.tape[head].wrapping_sub(1);
+                }
+                Operation::IncrementPointer => {
+                    if head + 1 == self.tape.len() {
+                        if !self.growable {
+                            return self.hand_over();
+                        }
+                        self.tape.push(0);
+                    }
+                    self.head += 1;
+                }
+                Operation::DecrementPointer => {
+                    if head == 0 {
+                        return self.hand_over();
+                    }
+                    self.head -= 1;
+                }
+                Operation::OutputByte => {
+                    if !self.count_output(1) {
+                        return self.hand_over();
+                    }
+                    output.push(self.tape[head])?;
+                }
+                Operation::InputByte => {
+                    output.flush()?;
+                    self.read(input, *instruction)?;
+                }
+                // As in the generic loop, an opening bracket always jumps to
+                // its closing bracket, which decides whether to loop. Only the
+                // unmatched brackets of lazily validated programs map to
+                // themselves, and the generic loop fails on them.
+                Operation::StartLoop => match jumps.target(self.position) {
+                    close if close == self.position => return self.hand_over(),
+                    close => {
+                        self.entering = true;
+                        self.position = close;
+                        continue;
+                    }
+                },
+                Operation::EndLoop => match jumps.target(self.position) {
+                    open if open == self.position => return self.hand_over(),
+                    open if self.tape[head] != 0 => {
+                        if !entering {
+                            self.loop_counts[open] += 1;
+                        }
+                        self.position = open + 1;
+                        continue;
+                    }
+                    _ => {}
+                },
+                Operation::Extension(_) => return self.hand_over(),
+            }
+            self.position += 1;
+        }
+        Ok(true)
+    }
+
+    /// Runs the nodes of a lowered program, returning whether it ran to the
+    /// end rather than handing over to the generic loop. The back edges of
+    /// each loop are counted against the opening bracket found for it.
+    fn run_nodes<R, W>(
+        &mut self,
+        nodes: &[IrNode],
+        jumps: &[usize],
+        openings: &[Option<usize>],
+        input: &mut R,
+        output: &mut LineBuffer<'_, W>,
+    ) -> Result<bool, VirtualMachineError>
+    where
+        R: Read + ?Sized,
+        W: Write + ?Sized,
+    {
+        while let Some(node) = nodes.get(self.position) {
+            if self.steps == self.step_limit {
+                return Ok(false);
+            }
+            self.steps += 1;
+            let head = self.head;
+            match node.op() {
+                IrOp::Add(delta) => {
+                    self.tape[head] =
+                        self.tape[head].wrapping_add(*delta as u8);
+                }
+                IrOp::Move(delta) => match self.offset(*delta) {
+                    Some(target) => self.head = target,
+                    None => return self.hand_over(),
+                },
+                IrOp::Output => {
+                    if !self.count_output(1) {
+                        return self.hand_over();
+                    }
+                    output.push(self.tape[head])?;
+                }
+                IrOp::Input => {
+                    output.flush()?;
+                    self.read(input, node.source())?;
+                }
+                IrOp::LoopStart => {
+                    if self.tape[head] == 0 {
+                        self.position = jumps[self.position];
+                    }
+                }
+                IrOp::LoopEnd => {
+                    if self.tape[head] != 0 {
+                        if let Some(open) = openings[self.position] {
+                            self.loop_counts[open] += 1;
+                        }
+                        self.position = jumps[self.position];
+                    }
+                }
+                IrOp::If => {
+                    if self.tape[head] == 0 {
+                        self.position = jumps[self.position];
+                    }
+                }
+                IrOp::Else => self.position = jumps[self.position],
+                IrOp::EndIf => {}
+                IrOp::Clear => self.tape[head] = 0,
+                IrOp::AddAt(offset, delta) => match self.offset(*offset) {
+                    Some(target) => {
+                        self.tape[target] =
+                            self.tape[target].wrapping_add(*delta as u8);
+                    }
+                    None => return self.hand_over(),
+                },
+                IrOp::ClearAt(offset) => match self.offset(*offset) {
+                    Some(target) => self.tape[target] = 0,
+                    None => return self.hand_over(),
+                },
+                IrOp::AddRange(offset, len, delta) => {
+                    match self.range(*offset, *len) {
+                        Some(cells) => {
+                            u8::add_to_all(&mut self.tape[cells], *delta)
+                        }
+                        None => return self.hand_over(),
+                    }
+                }
+                IrOp::ClearRange(offset, len) => {
+                    match self.range(*offset, *len) {
+                        Some(cells) => self.tape[cells].fill(0),
+                        None => return self.hand_over(),
+                    }
+                }
+                IrOp::CopyLoop(targets) => {
+                    let value = self.tape[head];
+                    if value != 0 {
+                        // Every target is reached before any is changed, so
+                        // that the generic loop can run the whole operation
+                        // again if one of them is off the tape.
+                        for (offset, _) in targets {
+                            if self.offset(*offset).is_none() {
+                                return self.hand_over();
+                            }
+                        }
+                        for (offset, factor) in targets {
+                            let target = head.wrapping_add_signed(*offset);
+                            self.tape[target] = self.tape[target].wrapping_add(
+                                value.wrapping_mul(*factor as u8),
+                            );
+                        }
+                        self.tape[head] = 0;
+                    }
+                }
+                IrOp::MulAdd(mul) => {
+                    if mul.offsets().any(|offset| self.offset(offset).is_none())
+                    {
+                        return self.hand_over();
+                    }
+                    mul.apply(self.tape, head);
+                }
+                IrOp::ScanRight(step) => {
+                    match u8::find_zero(&self.tape[head..], *step) {
+                        Some(offset) => self.head += offset,
+                        None => return self.hand_over(),
+                    }
+                }
+                IrOp::ScanLeft(step) => {
+                    match u8::rfind_zero(&self.tape[..=head], *step) {
+                        Some(position) => self.head = position,
+                        None => return self.hand_over(),
+                    }
+                }
+                IrOp::OutputBytes(bytes) => {
+                    if !self.count_output(bytes.len()) {
+                        return self.hand_over();
+                    }
+                    output.extend(bytes)?;
+                }
+                IrOp::LoadTape(values) => match self.range(0, values.len()) {
+                    Some(cells) => {
+                        for (cell, value) in
+                            self.tape[cells].iter_mut().zip(values)
+                        {
+                            *cell = *value as u8;
+                        }
+                    }
+                    None => return self.hand_over(),
+                },
+                IrOp::Extension(_) => return self.hand_over(),
+            }
+            self.position += 1;
+        }
+        Ok(true)
+    }
+}
+
+impl<T> VirtualMachine<'_, T>
+where
+    T: CellKind + Default + Clone + Copy + PartialEq,
+{
+    /// Takes the state of the Virtual Machine out for one of the loops, if its
+    /// cells are 8 bit.
+    fn bytes<'s>(
+        &'s mut self,
+        position: usize,
+        filename: &'s Path,
+    ) -> Option<Bytes<'s>> {
+        if self.traces() || self.sandbox.watches_steps() {
+            return None;
+        }
+        Some(Bytes {
+            tape: T::as_bytes_mut(&mut self.tape)?,
+            filename,
+            head: self.tape_head,
+            position,
+            steps: self.steps,
+            step_limit: self.sandbox.step_limit.unwrap_or(u64::MAX),
+            output_written: self.output_written,
+            output_limit: self.sandbox.output_limit.unwrap_or(u64::MAX),
+            input_read: self.input_read,
+            input_limit: self.sandbox.input_limit.unwrap_or(u64::MAX),
+            loop_counts: &mut self.loop_counts,
+            last_io_step: self.last_io_step,
+            entering: self.entering,
+            // Tapes from an allocator are grown by the generic loop, which asks
+            // the allocator first.
+            growable: self.sandbox.tape_growth && self.allocator.is_none(),
+            eof_behavior: self.eof_behavior,
+        })
+    }
+
+    /// Interprets the program from the current position with the loop for 8
+    /// bit cells, if the cells are 8 bit. Returns whether the program ran to
+    /// the end, and if it did not, the generic loop carries on from the
+    /// position the program reached.
+    pub(crate) fn interpret_bytes<R, W>(
+        &mut self,
+        input: &mut R,
+        output: &mut W,
+    ) -> Result<bool, VirtualMachineError>
+    where
+        R: Read + ?Sized,
+        W: Write + ?Sized,
+    {
+        let program = self.program;
+        let position = self.program_position;
+        let Some(mut bytes) = self.bytes(position, program.filename()) else {
+            return Ok(false);
+        };
+        let mut output = LineBuffer::new(output);
+        let finished = bytes.run_instructions(
+            program.instructions(),
+            program.jump_table(),
+            input,
+            &mut output,
+        );
+        let (head, position, steps) = (bytes.head, bytes.position, bytes.steps);
+        let (written, read) = (bytes.output_written, bytes.input_read);
+        let entering = bytes.entering;
+        self.last_io_step = bytes.last_io_step;
+        self.entering = entering;
+        self.output_written = written;
+        self.input_read = read;
+        self.tape_head = head;
+        self.program_position = position;
+        self.steps = steps;
+        output.flush()?;
+        finished
+    }
+
+    /// Interprets a lowered program from the start with the loop for 8 bit
+    /// cells, if the cells are 8 bit. Returns the position the generic loop
+    /// must carry on from, if the program did not run to the end.
+    pub(crate) fn interpret_ir_bytes<R, W>(
+        &mut self,
+        program: &IrProgram,
+        jumps: &[usize],
+        openings: &[Option<usize>],
+        input: &mut R,
+        output: &mut W,
+    ) -> Result<Option<usize>, VirtualMachineError>
+    where
+        R: Read + ?Sized,
+        W: Write + ?Sized,
+    {
+        let Some(mut bytes) = self.bytes(0, program.filename()) else {
+            return Ok(Some(0));
+        };
+        let mut output = LineBuffer::new(output);
+        let nodes = program.nodes();
+        let finished =
+            bytes.run_nodes(nodes, jumps, openings, input, &mut output);
+        let (head, position, steps) = (bytes.head, bytes.position, bytes.steps);
+        let (written, read) = (bytes.output_written, bytes.input_read);
+        self.last_io_step = bytes.last_io_step;
+        self.output_written = written;
+        self.input_read = read;
+        self.tape_head = head;
+        self.steps = steps;
+        output.flush()?;
+        Ok((!finished?).then_some(position))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LineBuffer;
+    use std::io::{self, Write};
+
+    /// A writer which keeps each write separately.
+    #[derive(Default)]
+    struct Writes(Vec<Vec<u8>>);
+
+    impl Write for Writes {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.push(buf.to_vec());
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_output_written_by_line() {
+        let mut writes = Writes::default();
+        let mut output = LineBuffer::new(&mut writes);
+        for byte in b"ab\ncd" {
+            output.push(*byte).unwrap();
+        }
+        output.extend(b"e\nf").unwrap();
+        output.flush().unwrap();
+        output.flush().unwrap();
+        assert_eq!(writes.0, [&b"ab\n"[..], b"cde\nf"]);
+    }
+}
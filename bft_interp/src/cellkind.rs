@@ -8,30 +8,216 @@ pub trait CellKind {
     /// Wrapped decrementation of the value in a given cell
     fn decrement(&self) -> Self;
 
+    /// Wrapped addition of a signed amount to the value in a given cell, the
+    /// same as incrementing or decrementing it that many times
+    fn add_signed(&self, delta: i32) -> Self;
+
+    /// Wrapped addition of `value` multiplied by `factor` to the value in a
+    /// given cell
+    fn add_product(&self, value: Self, factor: i32) -> Self;
+
     /// Converts from u8 for IO
     fn from_u8(value: u8) -> Self;
 
     /// Converts to u8 for IO
     fn to_u8(&self) -> u8;
+
+    /// Converts from u32, keeping only as many of the lowest bits as fit
+    fn from_u32(value: u32) -> Self;
+
+    /// Converts to u32, which every cell fits into
+    fn to_u32(&self) -> u32;
+
+    /// Wrapped addition of a signed amount to the value in each of the cells
+    fn add_to_all(cells: &mut [Self], delta: i32)
+    where
+        Self: Sized;
+
+    /// Finds the first zero cell at a multiple of `step` from the start of
+    /// the cells, returning its index
+    fn find_zero(cells: &[Self], step: usize) -> Option<usize>
+    where
+        Self: Sized;
+
+    /// Finds the last zero cell at a multiple of `step` back from the end of
+    /// the cells, returning its index
+    fn rfind_zero(cells: &[Self], step: usize) -> Option<usize>
+    where
+        Self: Sized;
+
+    /// Views the cells as bytes if they are 8 bit, so that the Virtual
+    /// Machine can use the interpreter loops specialized for them
+    fn as_bytes_mut(cells: &mut Vec<Self>) -> Option<&mut Vec<u8>>
+    where
+        Self: Sized;
 }
 
-impl CellKind for u8 {
-    fn increment(&self) -> Self {
-        self.wrapping_add(1)
+/// Finds the first zero cell at a multiple of `step` from the start, one cell
+/// at a time.
+fn find_zero_by_step<T>(cells: &[T], step: usize) -> Option<usize>
+where
+    T: Default + PartialEq,
+{
+    let zero = T::default();
+    cells
+        .iter()
+        .step_by(step)
+        .position(|cell| *cell == zero)
+        .map(|index| index * step)
+}
+
+/// Finds the last zero cell at a multiple of `step` back from the end, one
+/// cell at a time.
+fn rfind_zero_by_step<T>(cells: &[T], step: usize) -> Option<usize>
+where
+    T: Default + PartialEq,
+{
+    let zero = T::default();
+    cells
+        .iter()
+        .rev()
+        .step_by(step)
+        .position(|cell| *cell == zero)
+        .map(|index| cells.len() - 1 - index * step)
+}
+
+/// Adds to each of the cells, one cell at a time.
+fn add_to_all_by_cell<T>(cells: &mut [T], delta: i32)
+where
+    T: CellKind,
+{
+    for cell in cells {
+        *cell = cell.add_signed(delta);
     }
+}
+
+/// Implements `CellKind` for the unsigned integer types, where the arithmetic
+/// wraps around, and only the lowest byte of a cell is used for output. Each
+/// type gives the functions used to search for zero cells, to add to many
+/// cells at once, and to view its cells as bytes.
+macro_rules! impl_cell_kind {
+    ($($t:ty => $find:path, $rfind:path, $add_all:path, $bytes:path);* $(;)?) => {
+        $(
+            impl CellKind for $t {
+                fn increment(&self) -> Self {
+                    self.wrapping_add(1)
+                }
+
+                fn decrement(&self) -> Self {
+                    self.wrapping_sub(1)
+                }
+
+                fn add_signed(&self, delta: i32) -> Self {
+                    // Truncating the two's complement delta gives the same
+                    // result modulo the size of the cell.
+                    self.wrapping_add(delta as $t)
+                }
 
-    fn decrement(&self) -> Self {
-        self.wrapping_sub(1)
+                fn add_product(&self, value: Self, factor: i32) -> Self {
+                    self.wrapping_add(value.wrapping_mul(factor as $t))
+                }
+
+                fn from_u8(value: u8) -> Self {
+                    value as $t
+                }
+
+                fn to_u8(&self) -> u8 {
+                    *self as u8
+                }
+
+                fn from_u32(value: u32) -> Self {
+                    value as $t
+                }
+
+                fn to_u32(&self) -> u32 {
+                    *self as u32
+                }
+
+                fn add_to_all(cells: &mut [Self], delta: i32) {
+                    $add_all(cells, delta)
+                }
+
+                fn find_zero(cells: &[Self], step: usize) -> Option<usize> {
+                    $find(cells, step)
+                }
+
+                fn rfind_zero(cells: &[Self], step: usize) -> Option<usize> {
+                    $rfind(cells, step)
+                }
+
+                fn as_bytes_mut(cells: &mut Vec<Self>) -> Option<&mut Vec<u8>> {
+                    $bytes(cells)
+                }
+            }
+        )*
+    };
+}
+
+/// Finds the first zero byte, using `memchr` when every byte is searched.
+fn find_zero_byte(cells: &[u8], step: usize) -> Option<usize> {
+    match step {
+        1 => memchr::memchr(0, cells),
+        _ => find_zero_by_step(cells, step),
     }
+}
 
-    fn from_u8(value: u8) -> Self {
-        value
+/// Finds the last zero byte, using `memrchr` when every byte is searched.
+fn rfind_zero_byte(cells: &[u8], step: usize) -> Option<usize> {
+    match step {
+        1 => memchr::memrchr(0, cells),
+        _ => rfind_zero_by_step(cells, step),
     }
+}
 
-    fn to_u8(&self) -> u8 {
-        *self
+/// Adds to each of the bytes, sixteen bytes at a time with SSE2 when the
+/// `simd` feature is enabled.
+fn add_to_bytes(cells: &mut [u8], delta: i32) {
+    #[cfg(all(feature = "simd", target_arch = "x86_64"))]
+    add_to_bytes_sse2(cells, delta as u8);
+    #[cfg(not(all(feature = "simd", target_arch = "x86_64")))]
+    add_to_all_by_cell(cells, delta);
+}
+
+/// Adds to each of the bytes with SSE2, which every x86-64 processor has.
+#[cfg(all(feature = "simd", target_arch = "x86_64"))]
+fn add_to_bytes_sse2(cells: &mut [u8], delta: u8) {
+    use std::arch::x86_64::{
+        __m128i, _mm_add_epi8, _mm_loadu_si128, _mm_set1_epi8, _mm_storeu_si128,
+    };
+
+    let mut chunks = cells.chunks_exact_mut(16);
+    // SAFETY: SSE2 is part of the x86-64 baseline, each chunk is exactly 16
+    // bytes long, and the load and store do not need the pointer to be
+    // aligned.
+    unsafe {
+        let amount = _mm_set1_epi8(delta as i8);
+        for chunk in &mut chunks {
+            let pointer = chunk.as_mut_ptr() as *mut __m128i;
+            let sum = _mm_add_epi8(_mm_loadu_si128(pointer), amount);
+            _mm_storeu_si128(pointer, sum);
+        }
+    }
+    for cell in chunks.into_remainder() {
+        *cell = cell.wrapping_add(delta);
     }
 }
+
+/// Bytes are already bytes.
+fn bytes_as_bytes(cells: &mut Vec<u8>) -> Option<&mut Vec<u8>> {
+    Some(cells)
+}
+
+/// Wider cells cannot be viewed as bytes.
+fn not_bytes<T>(_cells: &mut Vec<T>) -> Option<&mut Vec<u8>> {
+    None
+}
+
+impl_cell_kind!(
+    u8 => find_zero_byte, rfind_zero_byte, add_to_bytes, bytes_as_bytes;
+    u16 => find_zero_by_step, rfind_zero_by_step, add_to_all_by_cell, not_bytes;
+    u32 => find_zero_by_step, rfind_zero_by_step, add_to_all_by_cell, not_bytes;
+);
+
 #[cfg(test)]
 mod tests {
     use super::CellKind;
@@ -58,4 +244,78 @@ mod tests {
         let t = 0u8;
         assert_eq!(t.decrement(), 255u8);
     }
+
+    #[test]
+    fn test_add_signed() {
+        assert_eq!(250u8.add_signed(10), 4u8);
+        assert_eq!(3u8.add_signed(-5), 254u8);
+        assert_eq!(3u16.add_signed(-5), u16::MAX - 1);
+        assert_eq!(7u32.add_signed(i32::MIN), 7u32 + (1 << 31));
+    }
+
+    #[test]
+    fn test_add_product() {
+        assert_eq!(1u8.add_product(3, 2), 7u8);
+        assert_eq!(1u8.add_product(3, -1), 254u8);
+        assert_eq!(0u16.add_product(300, 300), 24464u16);
+    }
+
+    #[test]
+    fn test_u32_round_trip() {
+        assert_eq!(u8::from_u32(0x1ff), 0xff);
+        assert_eq!(u16::from_u32(0x1_0002), 2);
+        assert_eq!(u32::from_u32(7).to_u32(), 7);
+        assert_eq!(300u16.to_u32(), 300);
+    }
+
+    #[test]
+    fn test_add_to_all() {
+        let mut bytes: Vec<u8> = (0..40).collect();
+        u8::add_to_all(&mut bytes, -3);
+        assert_eq!(bytes[..4], [253, 254, 255, 0]);
+        assert_eq!(bytes[39], 36);
+        u8::add_to_all(&mut bytes, 259);
+        assert_eq!(bytes[..2], [0, 1]);
+
+        let mut cells = [0u16, 65535];
+        u16::add_to_all(&mut cells, 2);
+        assert_eq!(cells, [2, 1]);
+    }
+
+    #[test]
+    fn test_find_zero() {
+        let bytes = [1u8, 0, 2, 3, 0, 4];
+        assert_eq!(u8::find_zero(&bytes, 1), Some(1));
+        assert_eq!(u8::find_zero(&bytes, 2), Some(4));
+        assert_eq!(u8::find_zero(&bytes, 3), None);
+        assert_eq!(u8::rfind_zero(&bytes, 1), Some(4));
+        assert_eq!(u8::rfind_zero(&bytes, 2), Some(1));
+        assert_eq!(u8::rfind_zero(&bytes[..4], 2), Some(1));
+        assert_eq!(u8::rfind_zero(&bytes[..3], 2), None);
+
+        let wide = [1u16, 0, 2, 3, 0, 4];
+        assert_eq!(u16::find_zero(&wide, 2), Some(4));
+        assert_eq!(u16::rfind_zero(&wide, 1), Some(4));
+        assert_eq!(u32::find_zero(&[], 1), None);
+    }
+
+    #[test]
+    fn test_as_bytes_mut() {
+        assert_eq!(u8::as_bytes_mut(&mut vec![1, 2]), Some(&mut vec![1, 2]));
+        assert_eq!(u16::as_bytes_mut(&mut vec![1, 2]), None);
+        assert_eq!(u32::as_bytes_mut(&mut Vec::new()), None);
+    }
+
+    #[test]
+    fn test_wide_cells_wrap() {
+        assert_eq!(255u16.increment(), 256u16);
+        assert_eq!(0u16.decrement(), u16::MAX);
+        assert_eq!(u32::MAX.increment(), 0u32);
+    }
+
+    #[test]
+    fn test_wide_cells_output_lowest_byte() {
+        assert_eq!(0x1234u16.to_u8(), 0x34);
+        assert_eq!(u32::from_u8(200), 200u32);
+    }
 }
@@ -0,0 +1,249 @@
+//! Programs compiled once, ahead of being run many times, such as by a judge
+//! running a submission against each of its test cases, or a server running
+//! the same program for each request.
+//!
+//! `compile` lowers and optimizes a program into a `CompiledProgram`, which
+//! owns everything it needs and can be shared between threads. Each run then
+//! only has to create a Virtual Machine for it with `spawn_vm`, which is cheap,
+//! rather than parsing and optimizing the program all over again:
+//! ```
+//! use std::sync::Arc;
+//! use std::thread;
+//! use bft_types::BfProgram;
+//! use bft_interp::compiled::{compile, OptLevel};
+//! use bft_interp::sandbox::Sandbox;
+//!
+//! let program = BfProgram::new(",[->++<]>.".to_string(), "double.bf").unwrap();
+//! let compiled = Arc::new(compile(&program, OptLevel::Full).unwrap());
+//! let runs: Vec<_> = (1..=3u8)
+//!     .map(|n| {
+//!         let compiled = Arc::clone(&compiled);
+//!         thread::spawn(move || {
+//!             let mut input = Some(n);
+//!             let mut output = Vec::new();
+//!             let mut vm = compiled.spawn_vm::<u8>(
+//!                 Sandbox::default(),
+//!                 move || input.take(),
+//!                 |byte| output.push(byte),
+//!             );
+//!             vm.run().unwrap();
+//!             drop(vm);
+//!             output
+//!         })
+//!     })
+//!     .collect();
+//! let outputs: Vec<Vec<u8>> =
+//!     runs.into_iter().map(|run| run.join().unwrap()).collect();
+//! assert_eq!(outputs, [[2], [4], [6]]);
+//! ```
+
+use bft_types::vm_error::VirtualMachineError;
+use bft_types::BfProgram;
+
+use crate::io::{InputSource, OutputSink, SinkWriter, SourceReader};
+use crate::ir::IrProgram;
+use crate::optimizer::Pipeline;
+use crate::sandbox::Sandbox;
+use crate::{CellKind, VirtualMachine, DEFAULT_TAPE_LENGTH};
+
+/// How much a program is optimized when it is compiled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum OptLevel {
+    /// The program is lowered one node per instruction, without optimizing.
+    #[default]
+    None,
+    /// Runs of the same instruction are folded together, with `rle`.
+    Basic,
+    /// All of the built-in passes are run, as with `Pipeline::builtin`.
+    Full,
+}
+
+impl OptLevel {
+    /// The passes run over programs compiled at this level.
+    pub fn pipeline(self) -> Pipeline {
+        match self {
+            OptLevel::None => Pipeline::new(),
+            OptLevel::Basic => {
+                Pipeline::from_spec("rle").expect("rle is a built-in pass")
+            }
+            OptLevel::Full => Pipeline::builtin(),
+        }
+    }
+}
+
+/// A program which has been lowered and optimized, ready to be run any number
+/// of times, on any thread. It keeps its own copy of the instructions of the
+/// program, without its comments, so does not borrow the program it was
+/// compiled from.
+#[derive(Debug)]
+pub struct CompiledProgram {
+    program: BfProgram,
+    ir: IrProgram,
+    tape_length: usize,
+}
+
+/// Compiles the program at the optimization level. Fails if the brackets of
+/// the program are not balanced, even if it was parsed with lazy bracket
+/// validation.
+pub fn compile(
+    program: &BfProgram,
+    level: OptLevel,
+) -> Result<CompiledProgram, VirtualMachineError> {
+    compile_with(program, &mut level.pipeline())
+}
+
+/// Compiles the program with the passes of the pipeline, rather than those of
+/// an optimization level.
+pub fn compile_with(
+    program: &BfProgram,
+    pipeline: &mut Pipeline,
+) -> Result<CompiledProgram, VirtualMachineError> {
+    let mut ir = IrProgram::from_program(program)?;
+    pipeline.run(&mut ir);
+    Ok(CompiledProgram {
+        program: program.slice(..)?,
+        ir,
+        tape_length: DEFAULT_TAPE_LENGTH,
+    })
+}
+
+impl CompiledProgram {
+    /// Sets the number of cells the tape of each Virtual Machine spawned for
+    /// the program starts with, which is 30,000 by default.
+    pub fn with_tape_length(mut self, tape_length: usize) -> Self {
+        self.tape_length = tape_length;
+        self
+    }
+
+    /// The program, without its comments.
+    pub fn program(&self) -> &BfProgram {
+        &self.program
+    }
+
+    /// The lowered and optimized program, which Virtual Machines spawned for
+    /// the program run.
+    pub fn ir(&self) -> &IrProgram {
+        &self.ir
+    }
+
+    /// The number of cells the tape of each Virtual Machine starts with.
+    pub fn tape_length(&self) -> usize {
+        self.tape_length
+    }
+
+    /// Creates a Virtual Machine for a run of the program, in the sandbox,
+    /// reading from and writing to the given source and sink as `with_io`
+    /// would. `run` then runs the compiled program, rather than interpreting
+    /// its instructions one by one.
+    pub fn spawn_vm<'a, T>(
+        &'a self,
+        sandbox: Sandbox,
+        input: impl InputSource + 'a,
+        output: impl OutputSink + 'a,
+    ) -> VirtualMachine<'a, T>
+    where
+        T: CellKind + Default + Clone + Copy + PartialEq,
+    {
+        let mut vm =
+            VirtualMachine::new(&self.program, self.tape_length, false)
+                .with_sandbox(sandbox);
+        vm.io = Some((
+            Box::new(SourceReader::new(input)),
+            Box::new(SinkWriter::new(output)),
+        ));
+        vm.ir = Some(&self.ir);
+        vm
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{compile, CompiledProgram, OptLevel};
+    use crate::sandbox::Sandbox;
+    use bft_types::options::{BracketValidation, ParseOptions};
+    use bft_types::vm_error::VirtualMachineError;
+    use bft_types::BfProgram;
+
+    fn run(compiled: &CompiledProgram, sandbox: Sandbox) -> (Vec<u8>, u64) {
+        let mut output = Vec::new();
+        let mut vm =
+            compiled.spawn_vm::<u8>(sandbox, || None, |byte| output.push(byte));
+        vm.run().unwrap();
+        let steps = vm.steps();
+        drop(vm);
+        (output, steps)
+    }
+
+    #[test]
+    fn test_compile() {
+        fn is_send_sync<T: Send + Sync>() {}
+        is_send_sync::<CompiledProgram>();
+
+        let source = "loop\n++++[->++<]>.".to_string();
+        let program = BfProgram::new(source, "double.bf").unwrap();
+        let steps: Vec<u64> = [OptLevel::None, OptLevel::Basic, OptLevel::Full]
+            .into_iter()
+            .map(|level| {
+                let compiled = compile(&program, level).unwrap();
+                // Every run starts afresh.
+                let first = run(&compiled, Sandbox::default());
+                assert_eq!(first, run(&compiled, Sandbox::default()));
+                assert_eq!(first.0, [8]);
+                first.1
+            })
+            .collect();
+        assert!(steps[0] > steps[1] && steps[1] > steps[2]);
+    }
+
+    #[test]
+    fn test_spawn_vm() {
+        let program = BfProgram::new("+[>+]".to_string(), "far.bf").unwrap();
+        let compiled = compile(&program, OptLevel::Full)
+            .unwrap()
+            .with_tape_length(4);
+        assert_eq!(compiled.tape_length(), 4);
+        assert_eq!(compiled.program().instructions().len(), 5);
+        let mut vm =
+            compiled.spawn_vm::<u8>(Sandbox::default(), || None, |_| {});
+        assert!(matches!(
+            vm.run(),
+            Err(VirtualMachineError::InvalidHeadPosition { position: 4, .. })
+        ));
+        let sandbox = Sandbox::builder()
+            .with_tape_growth(true)
+            .with_memory_limit(8)
+            .build()
+            .unwrap();
+        let mut vm = compiled.spawn_vm::<u8>(sandbox, || None, |_| {});
+        assert!(matches!(
+            vm.run(),
+            Err(VirtualMachineError::TapeGrowthRefused { cells: 9, .. })
+        ));
+
+        let lazy =
+            ParseOptions::new().bracket_validation(BracketValidation::Lazy);
+        let unmatched =
+            BfProgram::new_with_options("+[".to_string(), "open.bf", &lazy)
+                .unwrap();
+        assert!(compile(&unmatched, OptLevel::None).is_err());
+    }
+
+    #[test]
+    fn test_swap_program() {
+        let first = BfProgram::new("+++.>".to_string(), "first.bf").unwrap();
+        let second = BfProgram::new("+<+.".to_string(), "second.bf").unwrap();
+        let compiled = compile(&first, OptLevel::Full).unwrap();
+        let mut output = Vec::new();
+        let mut vm = compiled.spawn_vm::<u8>(
+            Sandbox::default(),
+            || None,
+            |byte| output.push(byte),
+        );
+        vm.run().unwrap();
+        // The swapped in program runs, rather than the compiled one.
+        vm.swap_program(&second);
+        vm.run().unwrap();
+        drop(vm);
+        assert_eq!(output, [3, 4]);
+    }
+}
@@ -0,0 +1,354 @@
+//! Strategies for dispatching the operations of a lowered program, which the
+//! Virtual Machine picks between with `DispatchKind`.
+//!
+//! - `Match` runs a loop over the nodes with a single `match` on each
+//!   operation, switching to a loop specialized for 8 bit cells when it can.
+//! - `Threaded` first turns each node into a closure which carries out its
+//!   operation and returns the position of the next node, so that running
+//!   the program is one indirect call per node, without decoding any
+//!   operations.
+//!
+//! Both implement `Dispatch`, which any other strategy can implement too.
+
+use std::io::{Read, Write};
+
+use bft_types::vm_error::VirtualMachineError;
+
+use crate::ir::{IrOp, IrProgram};
+use crate::{CellKind, VirtualMachine};
+
+/// The strategies which the Virtual Machine can dispatch operations with.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum DispatchKind {
+    /// A loop with a `match` on each operation.
+    #[default]
+    Match,
+    /// A list of closures, one per operation.
+    Threaded,
+}
+
+/// A way of running a lowered program on a Virtual Machine.
+pub trait Dispatch<T> {
+    /// Runs the program from the start on the Virtual Machine, using its tape,
+    /// step limit and extensions.
+    fn run(
+        &self,
+        vm: &mut VirtualMachine<'_, T>,
+        program: &IrProgram,
+        input: &mut dyn Read,
+        output: &mut dyn Write,
+    ) -> Result<(), VirtualMachineError>;
+}
+
+/// Dispatches with a `match` on each operation, using the loop specialized
+/// for 8 bit cells if the cells are 8 bit.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct MatchDispatch;
+
+impl<T> Dispatch<T> for MatchDispatch
+where
+    T: CellKind + Default + Clone + Copy + PartialEq,
+{
+    fn run(
+        &self,
+        vm: &mut VirtualMachine<'_, T>,
+        program: &IrProgram,
+        input: &mut dyn Read,
+        output: &mut dyn Write,
+    ) -> Result<(), VirtualMachineError> {
+        let jumps = program.jump_table()?;
+        let openings = vm.loop_openings(program, &jumps);
+        let nodes = program.nodes();
+        let Some(mut position) =
+            vm.interpret_ir_bytes(program, &jumps, &openings, input, output)?
+        else {
+            return Ok(());
+        };
+        while position < nodes.len() {
+            let node = &nodes[position];
+            let source = node.source();
+            vm.count_step(node, program)?;
+            let head = vm.tape_head;
+            match node.op() {
+                IrOp::Add(delta) => {
+                    vm.tape[head] = vm.tape[head].add_signed(*delta);
+                }
+                IrOp::Move(delta) => {
+                    vm.tape_head = vm.offset_head(*delta, source, program)?;
+                }
+                IrOp::Output => {
+                    vm.write_out_of_cell(output)?;
+                }
+                IrOp::Input => {
+                    vm.read_input(&mut *input, source, program.filename())?;
+                }
+                IrOp::LoopStart => {
+                    if vm.tape[head] == T::default() {
+                        position = jumps[position];
+                    }
+                }
+                IrOp::LoopEnd => {
+                    if vm.tape[head] != T::default() {
+                        vm.count_back_edge(openings[position]);
+                        position = jumps[position];
+                    }
+                }
+                IrOp::If => {
+                    if vm.tape[head] == T::default() {
+                        position = jumps[position];
+                    }
+                }
+                IrOp::Else => position = jumps[position],
+                IrOp::EndIf => {}
+                IrOp::Clear => vm.tape[head] = T::default(),
+                IrOp::AddAt(offset, delta) => {
+                    let target = vm.offset_head(*offset, source, program)?;
+                    vm.tape[target] = vm.tape[target].add_signed(*delta);
+                }
+                IrOp::ClearAt(offset) => {
+                    let target = vm.offset_head(*offset, source, program)?;
+                    vm.tape[target] = T::default();
+                }
+                IrOp::AddRange(offset, len, delta) => {
+                    let cells = vm.range(*offset, *len, source, program)?;
+                    T::add_to_all(&mut vm.tape[cells], *delta);
+                }
+                IrOp::ClearRange(offset, len) => {
+                    let cells = vm.range(*offset, *len, source, program)?;
+                    vm.tape[cells].fill(T::default());
+                }
+                IrOp::CopyLoop(targets) => {
+                    vm.copy_loop(targets, source, program)?;
+                }
+                IrOp::MulAdd(mul) => vm.mul_add(mul, source, program)?,
+                IrOp::ScanRight(step) => {
+                    vm.tape_head = vm.scan_right(*step, source, program)?;
+                }
+                IrOp::ScanLeft(step) => {
+                    vm.tape_head = vm.scan_left(*step, source, program)?;
+                }
+                IrOp::OutputBytes(bytes) => {
+                    vm.write_bytes(bytes, source, program.filename(), output)?;
+                }
+                IrOp::LoadTape(values) => {
+                    vm.load_tape(values, source, program)?;
+                }
+                IrOp::Extension(name) => {
+                    vm.run_extension(*name, source, input, output)?;
+                }
+            }
+            position += 1;
+        }
+        Ok(())
+    }
+}
+
+/// A single operation, turned into a closure which carries it out and returns
+/// the position of the next operation to run.
+type Threaded<'p, T> = Box<
+    dyn for<'v> Fn(
+            &mut VirtualMachine<'v, T>,
+            &mut dyn Read,
+            &mut dyn Write,
+        ) -> Result<usize, VirtualMachineError>
+        + 'p,
+>;
+
+/// Dispatches by turning each operation into a closure before running any of
+/// them.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ThreadedDispatch;
+
+impl ThreadedDispatch {
+    /// Turns each node of the program into a closure, counting the back edges
+    /// of each loop against the opening bracket found for it.
+    fn thread<'p, T>(
+        program: &'p IrProgram,
+        jumps: &[usize],
+        openings: &[Option<usize>],
+    ) -> Vec<Threaded<'p, T>>
+    where
+        T: CellKind + Default + Clone + Copy + PartialEq + 'p,
+    {
+        program
+            .nodes()
+            .iter()
+            .enumerate()
+            .map(|(position, node)| -> Threaded<'p, T> {
+                let next = position + 1;
+                let source = node.source();
+                match node.op() {
+                    IrOp::Add(delta) => {
+                        let delta = *delta;
+                        Box::new(move |vm, _, _| {
+                            let head = vm.tape_head;
+                            vm.tape[head] = vm.tape[head].add_signed(delta);
+                            Ok(next)
+                        })
+                    }
+                    IrOp::Move(delta) => {
+                        let delta = *delta;
+                        Box::new(move |vm, _, _| {
+                            vm.tape_head =
+                                vm.offset_head(delta, source, program)?;
+                            Ok(next)
+                        })
+                    }
+                    IrOp::Output => Box::new(move |vm, _, output| {
+                        vm.write_out_of_cell(output)?;
+                        Ok(next)
+                    }),
+                    IrOp::Input => Box::new(move |vm, input, _| {
+                        vm.read_input(&mut *input, source, program.filename())?;
+                        Ok(next)
+                    }),
+                    IrOp::LoopStart => {
+                        let past_end = jumps[position] + 1;
+                        Box::new(move |vm, _, _| {
+                            if vm.tape[vm.tape_head] == T::default() {
+                                Ok(past_end)
+                            } else {
+                                Ok(next)
+                            }
+                        })
+                    }
+                    IrOp::LoopEnd => {
+                        let past_start = jumps[position] + 1;
+                        let open = openings[position];
+                        Box::new(move |vm, _, _| {
+                            if vm.tape[vm.tape_head] != T::default() {
+                                vm.count_back_edge(open);
+                                Ok(past_start)
+                            } else {
+                                Ok(next)
+                            }
+                        })
+                    }
+                    IrOp::If => {
+                        let past_else = jumps[position] + 1;
+                        Box::new(move |vm, _, _| {
+                            if vm.tape[vm.tape_head] == T::default() {
+                                Ok(past_else)
+                            } else {
+                                Ok(next)
+                            }
+                        })
+                    }
+                    IrOp::Else => {
+                        let past_end = jumps[position] + 1;
+                        Box::new(move |_, _, _| Ok(past_end))
+                    }
+                    IrOp::EndIf => Box::new(move |_, _, _| Ok(next)),
+                    IrOp::Clear => Box::new(move |vm, _, _| {
+                        let head = vm.tape_head;
+                        vm.tape[head] = T::default();
+                        Ok(next)
+                    }),
+                    IrOp::AddAt(offset, delta) => {
+                        let (offset, delta) = (*offset, *delta);
+                        Box::new(move |vm, _, _| {
+                            let target =
+                                vm.offset_head(offset, source, program)?;
+                            vm.tape[target] = vm.tape[target].add_signed(delta);
+                            Ok(next)
+                        })
+                    }
+                    IrOp::ClearAt(offset) => {
+                        let offset = *offset;
+                        Box::new(move |vm, _, _| {
+                            let target =
+                                vm.offset_head(offset, source, program)?;
+                            vm.tape[target] = T::default();
+                            Ok(next)
+                        })
+                    }
+                    IrOp::AddRange(offset, len, delta) => {
+                        let (offset, len, delta) = (*offset, *len, *delta);
+                        Box::new(move |vm, _, _| {
+                            let cells =
+                                vm.range(offset, len, source, program)?;
+                            T::add_to_all(&mut vm.tape[cells], delta);
+                            Ok(next)
+                        })
+                    }
+                    IrOp::ClearRange(offset, len) => {
+                        let (offset, len) = (*offset, *len);
+                        Box::new(move |vm, _, _| {
+                            let cells =
+                                vm.range(offset, len, source, program)?;
+                            vm.tape[cells].fill(T::default());
+                            Ok(next)
+                        })
+                    }
+                    IrOp::CopyLoop(targets) => Box::new(move |vm, _, _| {
+                        vm.copy_loop(targets, source, program)?;
+                        Ok(next)
+                    }),
+                    IrOp::MulAdd(mul) => Box::new(move |vm, _, _| {
+                        vm.mul_add(mul, source, program)?;
+                        Ok(next)
+                    }),
+                    IrOp::ScanRight(step) => {
+                        let step = *step;
+                        Box::new(move |vm, _, _| {
+                            vm.tape_head =
+                                vm.scan_right(step, source, program)?;
+                            Ok(next)
+                        })
+                    }
+                    IrOp::ScanLeft(step) => {
+                        let step = *step;
+                        Box::new(move |vm, _, _| {
+                            vm.tape_head =
+                                vm.scan_left(step, source, program)?;
+                            Ok(next)
+                        })
+                    }
+                    IrOp::OutputBytes(bytes) => {
+                        Box::new(move |vm, _, output| {
+                            let filename = program.filename();
+                            vm.write_bytes(bytes, source, filename, output)?;
+                            Ok(next)
+                        })
+                    }
+                    IrOp::LoadTape(values) => Box::new(move |vm, _, _| {
+                        vm.load_tape(values, source, program)?;
+                        Ok(next)
+                    }),
+                    IrOp::Extension(name) => {
+                        let name = *name;
+                        Box::new(move |vm, input, output| {
+                            vm.run_extension(name, source, input, output)?;
+                            Ok(next)
+                        })
+                    }
+                }
+            })
+            .collect()
+    }
+}
+
+impl<T> Dispatch<T> for ThreadedDispatch
+where
+    T: CellKind + Default + Clone + Copy + PartialEq,
+{
+    fn run(
+        &self,
+        vm: &mut VirtualMachine<'_, T>,
+        program: &IrProgram,
+        input: &mut dyn Read,
+        output: &mut dyn Write,
+    ) -> Result<(), VirtualMachineError> {
+        let jumps = program.jump_table()?;
+        let openings = vm.loop_openings(program, &jumps);
+        let threaded =
+            ThreadedDispatch::thread::<T>(program, &jumps, &openings);
+        let nodes = program.nodes();
+        let mut position = 0;
+        while let Some(operation) = threaded.get(position) {
+            vm.count_step(&nodes[position], program)?;
+            position = operation(vm, input, output)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,197 @@
+//! How the values of cells are written out when the tape is shown to a person,
+//! as the bytes a program working on text stores are far easier to read as
+//! characters than as numbers. Given a `MemoryMap`, cells are grouped by the
+//! regions of the tape they are in, and errors name the cells they happened
+//! at.
+
+use std::fmt;
+use std::str::FromStr;
+
+use bft_types::memory::MemoryMap;
+use bft_types::vm_error::VirtualMachineError;
+
+/// Writes out the values of cells.
+pub trait CellFormatter {
+    /// Writes out the value of a single cell, which is `bits` wide.
+    fn format_cell(&self, value: u32, bits: u32) -> String;
+
+    /// Writes out the values of a run of cells, each `bits` wide, as a list.
+    fn format_cells(&self, cells: &[u32], bits: u32) -> String {
+        let cells: Vec<String> = cells
+            .iter()
+            .map(|value| self.format_cell(*value, bits))
+            .collect();
+        format!("[{}]", cells.join(", "))
+    }
+}
+
+/// The conventions for writing out the value of a cell.
+/// ```
+/// use bft_interp::display::{CellDisplay, CellFormatter};
+///
+/// let cells = [104, 105, 10, 0];
+/// assert_eq!(CellDisplay::Decimal.format_cells(&cells, 8), "[104, 105, 10, 0]");
+/// assert_eq!(
+///     CellDisplay::Hex.format_cells(&cells, 16),
+///     "[0x0068, 0x0069, 0x000a, 0x0000]"
+/// );
+/// assert_eq!(
+///     CellDisplay::Ascii.format_cells(&cells, 8),
+///     r"['h', 'i', '\n', '\x00']"
+/// );
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CellDisplay {
+    /// The value in decimal.
+    #[default]
+    Decimal,
+    /// The value in hexadecimal, padded to the width of the cell.
+    Hex,
+    /// The value as a quoted character, with anything which is not printable
+    /// ASCII escaped, and values too large to be a byte written as the
+    /// Unicode escape of their hexadecimal value.
+    Ascii,
+}
+
+impl CellFormatter for CellDisplay {
+    fn format_cell(&self, value: u32, bits: u32) -> String {
+        match self {
+            CellDisplay::Decimal => value.to_string(),
+            CellDisplay::Hex => {
+                format!("{:#0width$x}", value, width = bits as usize / 4 + 2)
+            }
+            CellDisplay::Ascii => match u8::try_from(value) {
+                Ok(byte) => match byte {
+                    b'\'' => r"'\''".to_string(),
+                    _ => format!("'{}'", byte.escape_ascii()),
+                },
+                Err(_) => format!("'\\u{{{:x}}}'", value),
+            },
+        }
+    }
+}
+
+/// Writes out the values of cells grouped by the regions of the map they are
+/// in, each labelled with the name of its region, or with its range of
+/// indices for cells in no region.
+/// ```
+/// use bft_interp::display::{format_mapped_cells, CellDisplay};
+/// use bft_types::memory::MemoryMap;
+///
+/// let map: MemoryMap = "0..2 = \"counters\"\n3..6 = \"buffer\"".parse().unwrap();
+/// let cells = [1, 2, 0, 104, 105];
+/// assert_eq!(
+///     format_mapped_cells(&CellDisplay::Ascii, &cells, 8, &map),
+///     r"counters ['\x01', '\x02'], 2..3 ['\x00'], buffer ['h', 'i']"
+/// );
+/// ```
+pub fn format_mapped_cells(
+    formatter: &dyn CellFormatter,
+    cells: &[u32],
+    bits: u32,
+    map: &MemoryMap,
+) -> String {
+    let mut groups = Vec::new();
+    let mut start = 0;
+    while start < cells.len() {
+        let (end, label) = match map.region_of(start) {
+            Some(region) => (region.range().end, region.name().to_string()),
+            None => {
+                let end = map
+                    .regions()
+                    .iter()
+                    .map(|region| region.range().start)
+                    .find(|&next| next > start)
+                    .unwrap_or(cells.len())
+                    .min(cells.len());
+                (end, format!("{}..{}", start, end))
+            }
+        };
+        let end = end.min(cells.len());
+        groups.push(format!(
+            "{} {}",
+            label,
+            formatter.format_cells(&cells[start..end], bits)
+        ));
+        start = end;
+    }
+    groups.join(", ")
+}
+
+/// Describes the error, adding the symbolic name of the cell it happened at
+/// if the cell is in a region of the map.
+/// ```
+/// use bft_interp::VirtualMachine;
+/// use bft_interp::display::describe_error;
+/// use bft_types::BfProgram;
+/// use bft_types::memory::MemoryMap;
+///
+/// // The program expects a longer tape than it is given.
+/// let map: MemoryMap = "2..8 = \"buffer\"".parse().unwrap();
+/// let program = BfProgram::new(">>>>".to_string(), "far.bf").unwrap();
+/// let mut vm = VirtualMachine::<u8>::new(&program, 4, false);
+/// let err = vm.run().unwrap_err();
+/// assert!(describe_error(&err, &map).ends_with("Cell 4 is buffer+2."));
+/// ```
+pub fn describe_error(err: &VirtualMachineError, map: &MemoryMap) -> String {
+    let cell = match err {
+        VirtualMachineError::InvalidHeadPosition { position, .. } => {
+            Some(*position)
+        }
+        VirtualMachineError::TapeGrowthRefused { cells, .. } => Some(cells - 1),
+        _ => None,
+    };
+    match cell.filter(|&cell| map.region_of(cell).is_some()) {
+        Some(cell) => {
+            format!("{} Cell {} is {}.", err, cell, map.name_of(cell))
+        }
+        None => err.to_string(),
+    }
+}
+
+impl FromStr for CellDisplay {
+    type Err = String;
+
+    /// Parses the names used on the command line.
+    /// ```
+    /// use bft_interp::display::CellDisplay;
+    /// assert_eq!("hex".parse(), Ok(CellDisplay::Hex));
+    /// ```
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "decimal" => Ok(CellDisplay::Decimal),
+            "hex" => Ok(CellDisplay::Hex),
+            "ascii" => Ok(CellDisplay::Ascii),
+            _ => Err(format!(
+                "unknown cell display '{}', expected one of decimal, hex or \
+                ascii",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for CellDisplay {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CellDisplay::Decimal => write!(f, "decimal"),
+            CellDisplay::Hex => write!(f, "hex"),
+            CellDisplay::Ascii => write!(f, "ascii"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CellDisplay, CellFormatter};
+
+    #[test]
+    fn test_wide_cells() {
+        assert_eq!(CellDisplay::Hex.format_cell(0xbeef, 32), "0x0000beef");
+        assert_eq!(CellDisplay::Hex.format_cell(0xff, 8), "0xff");
+        assert_eq!(CellDisplay::Ascii.format_cell(0x263a, 16), r"'\u{263a}'");
+        assert_eq!(CellDisplay::Ascii.format_cell(0xe9, 16), r"'\xe9'");
+        assert_eq!(CellDisplay::Ascii.format_cell(39, 8), r"'\''");
+        assert_eq!(CellDisplay::Decimal.format_cell(70000, 32), "70000");
+    }
+}
@@ -0,0 +1,56 @@
+//! What the Virtual Machine should do when a program asks for input and there
+//! is none left.
+
+use std::fmt;
+use std::str::FromStr;
+
+/// The conventions for handling the end of the input, as different Brainfuck
+/// interpreters disagree on what `,` should do once the input has run out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EofBehavior {
+    /// Stop interpreting the program with an error.
+    #[default]
+    Error,
+    /// Set the cell at the head of the tape to zero.
+    Zero,
+    /// Leave the cell at the head of the tape as it is.
+    Unchanged,
+    /// Set the cell at the head of the tape to its maximum value, which is the
+    /// same as -1 for wrapping cells.
+    MaxValue,
+}
+
+impl FromStr for EofBehavior {
+    type Err = String;
+
+    /// Parses the names used on the command line and in config files.
+    /// ```
+    /// use bft_interp::eof::EofBehavior;
+    /// assert_eq!("unchanged".parse(), Ok(EofBehavior::Unchanged));
+    /// assert_eq!("-1".parse(), Ok(EofBehavior::MaxValue));
+    /// ```
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "error" => Ok(EofBehavior::Error),
+            "zero" | "0" => Ok(EofBehavior::Zero),
+            "unchanged" => Ok(EofBehavior::Unchanged),
+            "max" | "-1" => Ok(EofBehavior::MaxValue),
+            _ => Err(format!(
+                "unknown EOF behavior '{}', expected one of error, zero, \
+                unchanged or max",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for EofBehavior {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EofBehavior::Error => write!(f, "error"),
+            EofBehavior::Zero => write!(f, "zero"),
+            EofBehavior::Unchanged => write!(f, "unchanged"),
+            EofBehavior::MaxValue => write!(f, "max"),
+        }
+    }
+}
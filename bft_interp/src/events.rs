@@ -0,0 +1,196 @@
+//! A stream of the changes a program makes to the Virtual Machine as it runs,
+//! for visualizations which would rather be told what changed than diff
+//! snapshots of the tape.
+//!
+//! Events are given to an `EventSink`, set with
+//! `VirtualMachine::with_event_sink`. Closures taking each event are sinks, as
+//! are the sending ends of channels, so events can be handed to another
+//! thread, such as the UI thread of a GUI. `Throttle` cuts down how many events
+//! of each kind reach a sink, for programs which run far faster than anything
+//! could be drawn.
+
+use std::sync::mpsc::Sender;
+
+/// The number of kinds of event.
+const EVENT_KINDS: usize = 7;
+
+/// A change to the Virtual Machine, made by running a single instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VmEvent {
+    /// The cell at the index on the tape was changed.
+    CellChanged {
+        /// The position of the cell on the tape.
+        index: usize,
+        /// The value of the cell before it was changed.
+        old: u32,
+        /// The value of the cell after it was changed.
+        new: u32,
+    },
+    /// The head of the tape moved.
+    HeadMoved {
+        /// Where the head was.
+        from: usize,
+        /// Where the head is now.
+        to: usize,
+    },
+    /// The body of a loop started to run, having been skipped over until now.
+    LoopEntered {
+        /// The position in the program of the `[` of the loop.
+        start: usize,
+    },
+    /// A loop which was running was left.
+    LoopLeft {
+        /// The position in the program of the `[` of the loop.
+        start: usize,
+    },
+    /// The program read a byte of input.
+    Input(u8),
+    /// The program wrote a byte of output.
+    Output(u8),
+    /// The program ran to the end.
+    Halted,
+}
+
+impl VmEvent {
+    /// The kind of the event.
+    pub fn kind(&self) -> EventKind {
+        match self {
+            VmEvent::CellChanged { .. } => EventKind::CellChanged,
+            VmEvent::HeadMoved { .. } => EventKind::HeadMoved,
+            VmEvent::LoopEntered { .. } => EventKind::LoopEntered,
+            VmEvent::LoopLeft { .. } => EventKind::LoopLeft,
+            VmEvent::Input(_) => EventKind::Input,
+            VmEvent::Output(_) => EventKind::Output,
+            VmEvent::Halted => EventKind::Halted,
+        }
+    }
+}
+
+/// The kinds of `VmEvent`, without what they carry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EventKind {
+    /// `VmEvent::CellChanged`.
+    CellChanged,
+    /// `VmEvent::HeadMoved`.
+    HeadMoved,
+    /// `VmEvent::LoopEntered`.
+    LoopEntered,
+    /// `VmEvent::LoopLeft`.
+    LoopLeft,
+    /// `VmEvent::Input`.
+    Input,
+    /// `VmEvent::Output`.
+    Output,
+    /// `VmEvent::Halted`.
+    Halted,
+}
+
+/// Something which is given the events of a running program.
+pub trait EventSink {
+    /// Takes the next event.
+    fn event(&mut self, event: &VmEvent);
+
+    /// Whether events of the kind are wanted at all. Events which are not
+    /// wanted are never given to `event`.
+    fn wants(&self, _kind: EventKind) -> bool {
+        true
+    }
+}
+
+impl<F> EventSink for F
+where
+    F: FnMut(&VmEvent),
+{
+    fn event(&mut self, event: &VmEvent) {
+        self(event)
+    }
+}
+
+impl EventSink for Sender<VmEvent> {
+    /// Sends the event, unless the receiving end has gone, in which case
+    /// there is no one left to tell.
+    fn event(&mut self, event: &VmEvent) {
+        let _ = self.send(*event);
+    }
+}
+
+/// Passes on only some of the events of each kind to another sink, such as
+/// every hundredth cell change, or none at all.
+/// ```
+/// use std::io::Cursor;
+/// use bft_types::BfProgram;
+/// use bft_interp::VirtualMachine;
+/// use bft_interp::events::{EventKind, Throttle, VmEvent};
+///
+/// let program = BfProgram::new("++++>.".to_string(), "test.bf").unwrap();
+/// let mut events = Vec::new();
+/// let mut sink = Throttle::new(|event: &VmEvent| events.push(*event))
+///     .every(EventKind::CellChanged, 2)
+///     .ignore(EventKind::Halted);
+/// let mut vm = VirtualMachine::<u8>::new(&program, 2, false)
+///     .with_event_sink(&mut sink);
+/// vm.interpret(&mut Cursor::new(Vec::new()), &mut Vec::new()).unwrap();
+/// drop(vm);
+/// drop(sink);
+/// assert_eq!(
+///     events,
+///     [
+///         VmEvent::CellChanged { index: 0, old: 0, new: 1 },
+///         VmEvent::CellChanged { index: 0, old: 2, new: 3 },
+///         VmEvent::HeadMoved { from: 0, to: 1 },
+///         VmEvent::Output(0),
+///     ]
+/// );
+/// ```
+#[derive(Debug)]
+pub struct Throttle<S> {
+    sink: S,
+    /// How often each kind of event is passed on, where 0 is never.
+    every: [u64; EVENT_KINDS],
+    /// How many events of each kind have been seen.
+    seen: [u64; EVENT_KINDS],
+}
+
+impl<S> Throttle<S>
+where
+    S: EventSink,
+{
+    /// Passes every event on to the sink, until told otherwise.
+    pub fn new(sink: S) -> Self {
+        Self {
+            sink,
+            every: [1; EVENT_KINDS],
+            seen: [0; EVENT_KINDS],
+        }
+    }
+
+    /// Only passes on the first event of the kind and then every `n`th one
+    /// after it, or none of them if `n` is 0.
+    pub fn every(mut self, kind: EventKind, n: u64) -> Self {
+        self.every[kind as usize] = n;
+        self
+    }
+
+    /// Never passes on events of the kind.
+    pub fn ignore(self, kind: EventKind) -> Self {
+        self.every(kind, 0)
+    }
+}
+
+impl<S> EventSink for Throttle<S>
+where
+    S: EventSink,
+{
+    fn event(&mut self, event: &VmEvent) {
+        let kind = event.kind() as usize;
+        let seen = self.seen[kind];
+        self.seen[kind] += 1;
+        if self.every[kind] != 0 && seen.is_multiple_of(self.every[kind]) {
+            self.sink.event(event);
+        }
+    }
+
+    fn wants(&self, kind: EventKind) -> bool {
+        self.every[kind as usize] != 0 && self.sink.wants(kind)
+    }
+}
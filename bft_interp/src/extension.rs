@@ -0,0 +1,156 @@
+//! Custom instructions, which extend Brainfuck with new characters so that
+//! dialects can be prototyped on top of the Virtual Machine.
+
+use std::io::{Read, Write};
+use std::path::Path;
+
+use bft_types::vm_error::VirtualMachineError;
+use bft_types::InstructionInfo;
+
+use crate::tape::{self, TapeAllocator};
+
+/// The signature of the handler run for an extension instruction.
+pub type ExtensionHandler<'a, T> = Box<
+    dyn FnMut(&mut VmContext<'_, T>) -> Result<(), VirtualMachineError> + 'a,
+>;
+
+/// The parts of the Virtual Machine which an extension instruction can see and
+/// change while it runs.
+pub struct VmContext<'v, T> {
+    pub(crate) tape: &'v mut Vec<T>,
+    pub(crate) tape_head: &'v mut usize,
+    pub(crate) growable: bool,
+    pub(crate) memory_limit: Option<usize>,
+    pub(crate) allocator: Option<&'v mut dyn TapeAllocator<T>>,
+    pub(crate) input: &'v mut dyn Read,
+    pub(crate) output: &'v mut dyn Write,
+    pub(crate) instruction: InstructionInfo,
+    pub(crate) filename: &'v Path,
+    pub(crate) steps: u64,
+}
+
+impl<T> VmContext<'_, T>
+where
+    T: Default + Clone + Copy,
+{
+    /// Provides the cells of the tape.
+    pub fn tape(&self) -> &[T] {
+        self.tape
+    }
+
+    /// Provides the cells of the tape, so that they can be changed.
+    pub fn tape_mut(&mut self) -> &mut [T] {
+        self.tape
+    }
+
+    /// Provides the position of the head of the tape.
+    pub fn head(&self) -> usize {
+        *self.tape_head
+    }
+
+    /// Provides the value in the cell at the head of the tape.
+    pub fn cell(&self) -> T {
+        self.tape[*self.tape_head]
+    }
+
+    /// Sets the value in the cell at the head of the tape.
+    pub fn set_cell(&mut self, value: T) {
+        self.tape[*self.tape_head] = value;
+    }
+
+    /// Moves the head of the tape to the given position. If the position lies
+    /// beyond the end of the tape, then the tape grows to reach it if it is
+    /// extensible, otherwise this fails just as `>` would.
+    pub fn set_head(
+        &mut self,
+        position: usize,
+    ) -> Result<(), VirtualMachineError> {
+        if position >= self.tape.len() {
+            if !self.growable {
+                return Err(VirtualMachineError::InvalidHeadPosition {
+                    line: self.instruction.line(),
+                    column: self.instruction.column(),
+                    operation: self.instruction.operation(),
+                    filename: self.filename.display().to_string(),
+                    position,
+                    tape_length: self.tape.len(),
+                });
+            }
+            let allocator = self.allocator.as_deref_mut();
+            let limit = self.memory_limit;
+            if !tape::grow(self.tape, allocator, position + 1, limit) {
+                return Err(VirtualMachineError::TapeGrowthRefused {
+                    line: self.instruction.line(),
+                    column: self.instruction.column(),
+                    filename: self.filename.display().to_string(),
+                    cells: position + 1,
+                });
+            }
+        }
+        *self.tape_head = position;
+        Ok(())
+    }
+
+    /// Provides the `len` cells after the head, such as the arguments and
+    /// results of a host function call. If they run past the end of the tape,
+    /// then the tape grows to fit them if it is extensible, otherwise this
+    /// fails.
+    pub fn window(
+        &mut self,
+        len: usize,
+    ) -> Result<&mut [T], VirtualMachineError> {
+        let start = *self.tape_head + 1;
+        if start + len > self.tape.len() {
+            if !self.growable {
+                return Err(self.fail(format!(
+                    "{} cells after the head run past the end of the tape",
+                    len
+                )));
+            }
+            let allocator = self.allocator.as_deref_mut();
+            let limit = self.memory_limit;
+            if !tape::grow(self.tape, allocator, start + len, limit) {
+                return Err(VirtualMachineError::TapeGrowthRefused {
+                    line: self.instruction.line(),
+                    column: self.instruction.column(),
+                    filename: self.filename.display().to_string(),
+                    cells: start + len,
+                });
+            }
+        }
+        Ok(&mut self.tape[start..start + len])
+    }
+
+    /// Provides the input of the Virtual Machine.
+    pub fn input(&mut self) -> &mut dyn Read {
+        self.input
+    }
+
+    /// Provides the output of the Virtual Machine.
+    pub fn output(&mut self) -> &mut dyn Write {
+        self.output
+    }
+
+    /// Provides the instruction being run.
+    pub fn instruction(&self) -> InstructionInfo {
+        self.instruction
+    }
+
+    /// Provides the number of steps taken, including the one running the
+    /// extension.
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /// Creates an error reporting that the extension failed for the given
+    /// reason, pointing at the instruction being run.
+    pub fn fail(&self, message: impl Into<String>) -> VirtualMachineError {
+        VirtualMachineError::ExtensionFailed {
+            name: self.instruction.operation().to_char(),
+            line: self.instruction.line(),
+            column: self.instruction.column(),
+            filename: self.filename.display().to_string(),
+            message: message.into(),
+        }
+    }
+}
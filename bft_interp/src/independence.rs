@@ -0,0 +1,353 @@
+//! Independence analysis, which splits a program into segments, finds the
+//! cells of the tape each of them reads and writes, and groups consecutive
+//! segments which do not interfere with each other into sections which could
+//! run in parallel. This is only an analysis, for code generation and for
+//! restructuring programs, and nothing is run in parallel.
+//!
+//! The segments are the loops at the top level of the program, and the runs
+//! of commands between them which work on a single cell. The cells are known
+//! for as long as the position of the head is: that is, until the first loop
+//! which moves the head by an amount which depends on the tape, such as
+//! `[>]`. That loop, and every segment after it, may touch any cell.
+
+use std::collections::BTreeSet;
+use std::ops::Range;
+
+use bft_types::ops::Operation;
+use bft_types::{BfProgram, InstructionInfo};
+
+/// Cells of the tape, numbered from where the head starts, or None for any
+/// cell at all.
+type Cells = Option<BTreeSet<i64>>;
+
+/// What a run of instructions does, relative to where the head starts.
+#[derive(Debug, Default)]
+struct Effect {
+    reads: BTreeSet<i64>,
+    writes: BTreeSet<i64>,
+    input: bool,
+    output: bool,
+    /// How far the head moves.
+    moved: i64,
+}
+
+impl Effect {
+    /// Adds the effect of a loop body which starts with the head `by` cells
+    /// along, and leaves it where it started.
+    fn merge(&mut self, body: Effect, by: i64) {
+        self.reads.extend(body.reads.iter().map(|cell| cell + by));
+        self.writes.extend(body.writes.iter().map(|cell| cell + by));
+        self.input |= body.input;
+        self.output |= body.output;
+    }
+}
+
+/// Works out the effect of the instructions from `start` up to `end`, or
+/// None if a loop among them moves the head by an amount which depends on the
+/// tape, or an extension instruction runs.
+fn effect(program: &BfProgram, start: usize, end: usize) -> Option<Effect> {
+    let mut effect = Effect::default();
+    let mut position = start;
+    while position < end {
+        let head = effect.moved;
+        match program.instructions()[position].operation() {
+            Operation::IncrementByte | Operation::DecrementByte => {
+                effect.reads.insert(head);
+                effect.writes.insert(head);
+            }
+            Operation::IncrementPointer => effect.moved += 1,
+            Operation::DecrementPointer => effect.moved -= 1,
+            Operation::OutputByte => {
+                effect.reads.insert(head);
+                effect.output = true;
+            }
+            Operation::InputByte => {
+                effect.writes.insert(head);
+                effect.input = true;
+            }
+            Operation::StartLoop => {
+                let end = program.jump_target(position)?;
+                let body = self::effect(program, position + 1, end)?;
+                if body.moved != 0 {
+                    return None;
+                }
+                effect.reads.insert(head);
+                effect.merge(body, head);
+                position = end;
+            }
+            Operation::EndLoop | Operation::Extension(_) => return None,
+        }
+        position += 1;
+    }
+    Some(effect)
+}
+
+/// A part of the program, and what it touches.
+#[derive(Debug, Clone)]
+pub struct Segment {
+    start: InstructionInfo,
+    end: InstructionInfo,
+    reads: Cells,
+    writes: Cells,
+    input: bool,
+    output: bool,
+}
+
+impl Segment {
+    /// The first instruction of the segment.
+    pub fn start(&self) -> InstructionInfo {
+        self.start
+    }
+
+    /// The last instruction of the segment.
+    pub fn end(&self) -> InstructionInfo {
+        self.end
+    }
+
+    /// The cells the segment reads, numbered from where the head starts, or
+    /// None if it may read any cell.
+    pub fn reads(&self) -> Option<&BTreeSet<i64>> {
+        self.reads.as_ref()
+    }
+
+    /// The cells the segment writes, numbered from where the head starts, or
+    /// None if it may write any cell.
+    pub fn writes(&self) -> Option<&BTreeSet<i64>> {
+        self.writes.as_ref()
+    }
+
+    /// Whether the segment reads input.
+    pub fn input(&self) -> bool {
+        self.input
+    }
+
+    /// Whether the segment writes output.
+    pub fn output(&self) -> bool {
+        self.output
+    }
+
+    /// Every cell the segment touches, or None if it may touch any cell.
+    pub fn touches(&self) -> Cells {
+        Some(
+            self.reads
+                .as_ref()?
+                .union(self.writes.as_ref()?)
+                .copied()
+                .collect(),
+        )
+    }
+
+    /// Whether the two segments must run in order: because one writes a
+    /// cell the other touches, or both read input or write output, which
+    /// must happen in order.
+    pub fn interferes(&self, other: &Segment) -> bool {
+        let overlaps = |writes: &Cells, touches: Cells| match (writes, touches)
+        {
+            (Some(writes), Some(touches)) => !writes.is_disjoint(&touches),
+            (Some(writes), None) => !writes.is_empty(),
+            (None, _) => true,
+        };
+        overlaps(&self.writes, other.touches())
+            || overlaps(&other.writes, self.touches())
+            || ((self.input || self.output) && (other.input || other.output))
+    }
+}
+
+/// The segments of a program, and the sections of consecutive segments which
+/// do not interfere with each other.
+/// ```
+/// use bft_types::BfProgram;
+/// use bft_interp::independence::Independence;
+///
+/// // Sets up three cells, then doubles the first into the fourth.
+/// let program = BfProgram::new("+>++>+++<<[->>>++<<<]".to_string(), "i.bf")
+///     .unwrap();
+/// let independence = Independence::of(&program);
+/// assert_eq!(independence.segments().len(), 4);
+/// // The first three segments each write their own cell, but the loop
+/// // reads the first.
+/// assert_eq!(independence.sections().len(), 1);
+/// assert_eq!(independence.sections()[0], 0..3);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Independence {
+    segments: Vec<Segment>,
+    sections: Vec<Range<usize>>,
+}
+
+impl Independence {
+    /// Splits the program into segments, and groups them into sections.
+    pub fn of(program: &BfProgram) -> Self {
+        let segments = Self::segments_of(program);
+        let mut sections = Vec::new();
+        let mut start = 0;
+        for (index, segment) in segments.iter().enumerate() {
+            if segments[start..index]
+                .iter()
+                .any(|earlier| earlier.interferes(segment))
+            {
+                if index - start > 1 {
+                    sections.push(start..index);
+                }
+                start = index;
+            }
+        }
+        if segments.len() - start > 1 {
+            sections.push(start..segments.len());
+        }
+        Self { segments, sections }
+    }
+
+    /// Splits the program into the loops at its top level, and the runs of
+    /// commands between them which work on a single cell.
+    fn segments_of(program: &BfProgram) -> Vec<Segment> {
+        let instructions = program.instructions();
+        let mut segments = Vec::new();
+        // Where the head is, if it is known.
+        let mut head = Some(0i64);
+        let mut position = 0;
+        while position < instructions.len() {
+            let operation = instructions[position].operation();
+            let moved = match operation {
+                Operation::IncrementPointer => 1,
+                Operation::DecrementPointer => -1,
+                _ => 0,
+            };
+            if moved != 0 {
+                head = head.map(|head| head + moved);
+                position += 1;
+                continue;
+            }
+            // A loop, or the run of commands up to the next move or loop.
+            let end = match operation {
+                Operation::StartLoop => program
+                    .jump_target(position)
+                    .map_or(instructions.len(), |end| end + 1),
+                _ => instructions[position..]
+                    .iter()
+                    .position(|instruction| {
+                        matches!(
+                            instruction.operation(),
+                            Operation::IncrementPointer
+                                | Operation::DecrementPointer
+                                | Operation::StartLoop
+                        )
+                    })
+                    .map_or(instructions.len(), |length| position + length),
+            };
+            let mut segment = Segment {
+                start: instructions[position],
+                end: instructions[end - 1],
+                reads: None,
+                writes: None,
+                input: false,
+                output: false,
+            };
+            match (head, effect(program, position, end)) {
+                (Some(head), Some(effect)) => {
+                    let shift = |cells: BTreeSet<i64>| {
+                        cells.into_iter().map(|cell| cell + head).collect()
+                    };
+                    segment.reads = Some(shift(effect.reads));
+                    segment.writes = Some(shift(effect.writes));
+                    segment.input = effect.input;
+                    segment.output = effect.output;
+                }
+                _ => {
+                    head = None;
+                    for instruction in &instructions[position..end] {
+                        match instruction.operation() {
+                            Operation::InputByte => segment.input = true,
+                            Operation::OutputByte => segment.output = true,
+                            // Extensions may read input and write output.
+                            Operation::Extension(_) => {
+                                segment.input = true;
+                                segment.output = true;
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+            }
+            segments.push(segment);
+            position = end;
+        }
+        segments
+    }
+
+    /// The segments of the program, in order.
+    pub fn segments(&self) -> &[Segment] {
+        &self.segments
+    }
+
+    /// The ranges of consecutive segments, of at least two segments each,
+    /// which do not interfere with each other, so could run in parallel.
+    pub fn sections(&self) -> &[Range<usize>] {
+        &self.sections
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Independence;
+    use bft_types::BfProgram;
+    use std::collections::BTreeSet;
+
+    fn independence(source: &str) -> Independence {
+        let program = BfProgram::new(source.to_string(), "i.bf").unwrap();
+        Independence::of(&program)
+    }
+
+    fn sections(independence: &Independence) -> Vec<(usize, usize)> {
+        independence
+            .sections()
+            .iter()
+            .map(|section| (section.start, section.end))
+            .collect()
+    }
+
+    fn cells(cells: &[i64]) -> BTreeSet<i64> {
+        cells.iter().copied().collect()
+    }
+
+    #[test]
+    fn test_segments() {
+        let independence = independence("++>,<[->+<]>.");
+        let segments = independence.segments();
+        assert_eq!(segments.len(), 4);
+        assert_eq!(segments[1].writes(), Some(&cells(&[1])));
+        assert!(segments[1].input());
+        assert_eq!(segments[2].reads(), Some(&cells(&[0, 1])));
+        assert_eq!(segments[2].writes(), Some(&cells(&[0, 1])));
+        assert_eq!(
+            (segments[2].start().column(), segments[2].end().column()),
+            (6, 11)
+        );
+        assert!(segments[3].output());
+        // Reading input and setting up an unrelated cell do not interfere,
+        // but the loop uses both.
+        assert!(!segments[0].interferes(&segments[1]));
+        assert!(segments[2].interferes(&segments[0]));
+        assert_eq!(sections(&independence), [(0, 2)]);
+    }
+
+    #[test]
+    fn test_unknown_head() {
+        // After the scan, the head could be anywhere.
+        let independence = independence("+>+[>]+>+");
+        let segments = independence.segments();
+        assert_eq!(segments.len(), 5);
+        assert_eq!(segments[2].touches(), None);
+        assert_eq!(segments[3].touches(), None);
+        assert!(segments[3].interferes(&segments[4]));
+        assert_eq!(sections(&independence), [(0, 2)]);
+    }
+
+    #[test]
+    fn test_io_is_ordered() {
+        // Each output is of a different cell, but they must stay in order.
+        let independence = independence(".>.>+>+");
+        assert_eq!(independence.segments().len(), 4);
+        assert_eq!(sections(&independence), [(1, 4)]);
+    }
+}
@@ -0,0 +1,1397 @@
+//! Streams for the input and output of Virtual Machines.
+//!
+//! - `pipe` creates in-memory streams for connecting Virtual Machines
+//!   together, so that the output of one program can be fed into the input of
+//!   another while both are running.
+//! - `NewlineReader` and `NewlineWriter` wrap any other streams to translate
+//!   their newlines, as programs written on different platforms disagree
+//!   about which bytes `,` reads when Enter is pressed.
+//! - `echo` copies the input which a program reads into its output, set apart
+//!   from what the program writes, so that the output reads as a transcript of
+//!   the whole session.
+//! - `TrailingNewline` ends the output with a newline according to a
+//!   `NewlinePolicy`, so that a shell prompt does not end up on the same line
+//!   as the last line of output.
+//! - `HexDump` renders the bytes written through it as a hex dump, for looking
+//!   at binary data without sending it to a terminal.
+//! - `InputSource` and `OutputSink` are byte at a time input and output, which
+//!   closures implement, for hosts such as game engines, GUIs and wasm which
+//!   have no streams to hand. `SourceReader` and `SinkWriter` turn them into
+//!   streams, so they run on the same path as any other.
+//! - `WaitStrategy` decides how `,` waits for input which has not arrived
+//!   yet: blocking in the read, or `PollingReader`, which sleeps in slices so
+//!   that the wait can time out or be cancelled with a `CancelToken`.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, ErrorKind, Read, Write};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The state shared between the two ends of a pipe.
+#[derive(Debug)]
+struct Shared {
+    /// The bytes written, but not yet read.
+    buffer: VecDeque<u8>,
+    /// The most bytes which may be waiting in the buffer at once.
+    capacity: usize,
+    /// Whether the writing end has been dropped.
+    writer_closed: bool,
+    /// Whether the reading end has been dropped.
+    reader_closed: bool,
+}
+
+#[derive(Debug)]
+struct Pipe {
+    state: Mutex<Shared>,
+    /// Signalled whenever bytes are written, read, or either end is dropped.
+    changed: Condvar,
+}
+
+impl Pipe {
+    fn lock(&self) -> MutexGuard<'_, Shared> {
+        // The lock is never held across anything which could panic, so a
+        // poisoned lock still holds a consistent buffer.
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn wait<'a>(
+        &self,
+        guard: MutexGuard<'a, Shared>,
+    ) -> MutexGuard<'a, Shared> {
+        self.changed.wait(guard).unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Creates a pipe which holds at most `capacity` bytes at a time, returning
+/// its writing and reading ends.
+///
+/// Writes block while the pipe is full, and reads block while it is empty.
+/// Once the writer is dropped, the reader sees the end of the input; once the
+/// reader is dropped, writes fail with `ErrorKind::BrokenPipe`. The two ends
+/// can be sent to different threads.
+/// ```
+/// use std::io::{Read, Write};
+/// use bft_interp::io::pipe;
+///
+/// let (mut writer, mut reader) = pipe(16);
+/// writer.write_all(b"hi").unwrap();
+/// drop(writer);
+///
+/// let mut received = Vec::new();
+/// reader.read_to_end(&mut received).unwrap();
+/// assert_eq!(received, b"hi");
+/// ```
+pub fn pipe(capacity: usize) -> (PipeWriter, PipeReader) {
+    let pipe = Arc::new(Pipe {
+        state: Mutex::new(Shared {
+            buffer: VecDeque::with_capacity(capacity.max(1)),
+            capacity: capacity.max(1),
+            writer_closed: false,
+            reader_closed: false,
+        }),
+        changed: Condvar::new(),
+    });
+    (
+        PipeWriter {
+            pipe: Arc::clone(&pipe),
+        },
+        PipeReader { pipe },
+    )
+}
+
+/// The writing end of a pipe.
+#[derive(Debug)]
+pub struct PipeWriter {
+    pipe: Arc<Pipe>,
+}
+
+impl Write for PipeWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let mut state = self.pipe.lock();
+        while state.buffer.len() == state.capacity && !state.reader_closed {
+            state = self.pipe.wait(state);
+        }
+        if state.reader_closed {
+            return Err(io::Error::new(
+                ErrorKind::BrokenPipe,
+                "the reading end of the pipe has been closed",
+            ));
+        }
+        let space = state.capacity - state.buffer.len();
+        let written = space.min(buf.len());
+        state.buffer.extend(&buf[..written]);
+        self.pipe.changed.notify_all();
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for PipeWriter {
+    fn drop(&mut self) {
+        self.pipe.lock().writer_closed = true;
+        self.pipe.changed.notify_all();
+    }
+}
+
+/// The reading end of a pipe.
+#[derive(Debug)]
+pub struct PipeReader {
+    pipe: Arc<Pipe>,
+}
+
+impl Read for PipeReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let mut state = self.pipe.lock();
+        while state.buffer.is_empty() && !state.writer_closed {
+            state = self.pipe.wait(state);
+        }
+        let read = state.buffer.len().min(buf.len());
+        for (slot, byte) in buf.iter_mut().zip(state.buffer.drain(..read)) {
+            *slot = byte;
+        }
+        self.pipe.changed.notify_all();
+        Ok(read)
+    }
+}
+
+impl Drop for PipeReader {
+    fn drop(&mut self) {
+        self.pipe.lock().reader_closed = true;
+        self.pipe.changed.notify_all();
+    }
+}
+
+/// The ways of translating the newlines of a stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Newlines {
+    /// Leave the bytes as they are.
+    #[default]
+    Unchanged,
+    /// Turn each CR LF pair into a single LF.
+    CrlfToLf,
+    /// Turn each LF into a CR LF pair, unless it already follows a CR.
+    LfToCrlf,
+}
+
+impl Newlines {
+    /// The opposite translation, which is the one to use for the output of a
+    /// program whose input is translated this way.
+    /// ```
+    /// use bft_interp::io::Newlines;
+    /// assert_eq!(Newlines::CrlfToLf.reverse(), Newlines::LfToCrlf);
+    /// assert_eq!(Newlines::Unchanged.reverse(), Newlines::Unchanged);
+    /// ```
+    pub fn reverse(self) -> Self {
+        match self {
+            Newlines::Unchanged => Newlines::Unchanged,
+            Newlines::CrlfToLf => Newlines::LfToCrlf,
+            Newlines::LfToCrlf => Newlines::CrlfToLf,
+        }
+    }
+}
+
+impl FromStr for Newlines {
+    type Err = String;
+
+    /// Parses the names used in config files.
+    /// ```
+    /// use bft_interp::io::Newlines;
+    /// assert_eq!("crlf-to-lf".parse(), Ok(Newlines::CrlfToLf));
+    /// ```
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "unchanged" => Ok(Newlines::Unchanged),
+            "crlf-to-lf" => Ok(Newlines::CrlfToLf),
+            "lf-to-crlf" => Ok(Newlines::LfToCrlf),
+            _ => Err(format!(
+                "unknown newline translation '{}', expected one of \
+                unchanged, crlf-to-lf or lf-to-crlf",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Newlines {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Newlines::Unchanged => write!(f, "unchanged"),
+            Newlines::CrlfToLf => write!(f, "crlf-to-lf"),
+            Newlines::LfToCrlf => write!(f, "lf-to-crlf"),
+        }
+    }
+}
+
+/// A wrapper around Read which translates the newlines read through it.
+///
+/// When turning CR LF into LF, reading a CR has to wait for the byte after it,
+/// to see whether it is an LF.
+/// ```
+/// use std::io::Read;
+/// use bft_interp::io::{NewlineReader, Newlines};
+///
+/// let mut reader = NewlineReader::new(&b"a\r\nb\r"[..], Newlines::CrlfToLf);
+/// let mut translated = Vec::new();
+/// reader.read_to_end(&mut translated).unwrap();
+/// assert_eq!(translated, b"a\nb\r");
+/// ```
+#[derive(Debug)]
+pub struct NewlineReader<R> {
+    reader: R,
+    newlines: Newlines,
+    /// A byte which has been read but not yet handed on.
+    held: Option<u8>,
+    /// The last byte handed on.
+    last: u8,
+}
+
+impl<R> NewlineReader<R>
+where
+    R: Read,
+{
+    /// Wraps the reader, translating its newlines as given.
+    pub fn new(reader: R, newlines: Newlines) -> Self {
+        Self {
+            reader,
+            newlines,
+            held: None,
+            last: 0,
+        }
+    }
+
+    /// Reads the next byte, or the held byte if there is one.
+    fn next_byte(&mut self) -> io::Result<Option<u8>> {
+        if let Some(byte) = self.held.take() {
+            return Ok(Some(byte));
+        }
+        let mut buffer = [0; 1];
+        loop {
+            match self.reader.read(&mut buffer) {
+                Ok(0) => return Ok(None),
+                Ok(_) => return Ok(Some(buffer[0])),
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+impl<R> Read for NewlineReader<R>
+where
+    R: Read,
+{
+    /// Reads a single byte at a time, as translating needs to look at each of
+    /// them.
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.newlines == Newlines::Unchanged {
+            return self.reader.read(buf);
+        }
+        let Some(slot) = buf.first_mut() else {
+            return Ok(0);
+        };
+        let byte = match (self.newlines, self.next_byte()?) {
+            (_, None) => return Ok(0),
+            (Newlines::CrlfToLf, Some(b'\r')) => match self.next_byte()? {
+                Some(b'\n') => b'\n',
+                next => {
+                    self.held = next;
+                    b'\r'
+                }
+            },
+            (Newlines::LfToCrlf, Some(b'\n')) if self.last != b'\r' => {
+                self.held = Some(b'\n');
+                b'\r'
+            }
+            (_, Some(byte)) => byte,
+        };
+        self.last = byte;
+        *slot = byte;
+        Ok(1)
+    }
+}
+
+/// A wrapper around Write which translates the newlines written through it.
+///
+/// When turning CR LF into LF, a CR is held back until the next byte is
+/// written, to see whether it is an LF, or until the writer is dropped.
+/// ```
+/// use std::io::Write;
+/// use bft_interp::io::{NewlineWriter, Newlines};
+///
+/// let mut written = Vec::new();
+/// let mut writer = NewlineWriter::new(&mut written, Newlines::LfToCrlf);
+/// writer.write_all(b"a\nb\r\n").unwrap();
+/// drop(writer);
+/// assert_eq!(written, b"a\r\nb\r\n");
+/// ```
+#[derive(Debug)]
+pub struct NewlineWriter<W>
+where
+    W: Write,
+{
+    writer: W,
+    newlines: Newlines,
+    /// Whether a CR has been written but not yet passed on.
+    held_cr: bool,
+    /// The last byte written.
+    last: u8,
+}
+
+impl<W> NewlineWriter<W>
+where
+    W: Write,
+{
+    /// Wraps the writer, translating newlines as given.
+    pub fn new(writer: W, newlines: Newlines) -> Self {
+        Self {
+            writer,
+            newlines,
+            held_cr: false,
+            last: 0,
+        }
+    }
+}
+
+impl<W> Write for NewlineWriter<W>
+where
+    W: Write,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut translated = Vec::with_capacity(buf.len() + 1);
+        for byte in buf.iter().copied() {
+            match self.newlines {
+                Newlines::Unchanged => translated.push(byte),
+                Newlines::CrlfToLf => {
+                    if std::mem::take(&mut self.held_cr) && byte != b'\n' {
+                        translated.push(b'\r');
+                    }
+                    if byte == b'\r' {
+                        self.held_cr = true;
+                    } else {
+                        translated.push(byte);
+                    }
+                }
+                Newlines::LfToCrlf => {
+                    if byte == b'\n' && self.last != b'\r' {
+                        translated.push(b'\r');
+                    }
+                    translated.push(byte);
+                }
+            }
+            self.last = byte;
+        }
+        self.writer.write_all(&translated)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+impl<W> Drop for NewlineWriter<W>
+where
+    W: Write,
+{
+    /// Passes on any CR still being held back, as nothing else can follow it.
+    fn drop(&mut self) {
+        if self.held_cr {
+            let _ = self.writer.write_all(b"\r");
+            let _ = self.writer.flush();
+        }
+    }
+}
+
+/// How input echoed into the output is set apart from the output of the
+/// program.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EchoStyle {
+    /// Dimmed with ANSI escape codes, for output shown on a terminal.
+    Dim,
+    /// Wrapped in square brackets, for output which is piped or saved, where
+    /// escape codes would get in the way.
+    Brackets,
+}
+
+impl EchoStyle {
+    /// The bytes written before a run of echoed input.
+    fn open(&self) -> &'static [u8] {
+        match self {
+            EchoStyle::Dim => b"\x1b[2m",
+            EchoStyle::Brackets => b"[",
+        }
+    }
+
+    /// The bytes written after a run of echoed input.
+    fn close(&self) -> &'static [u8] {
+        match self {
+            EchoStyle::Dim => b"\x1b[22m",
+            EchoStyle::Brackets => b"]",
+        }
+    }
+}
+
+/// The output shared between the two ends of an echo.
+#[derive(Debug)]
+struct Transcript<W>
+where
+    W: Write,
+{
+    output: W,
+    style: EchoStyle,
+    /// Whether input is being echoed, so that the next output ends the run.
+    echoing: bool,
+}
+
+impl<W> Transcript<W>
+where
+    W: Write,
+{
+    fn echo(&mut self, bytes: &[u8]) -> io::Result<()> {
+        if bytes.is_empty() {
+            return Ok(());
+        }
+        if !self.echoing {
+            self.output.write_all(self.style.open())?;
+            self.echoing = true;
+        }
+        self.output.write_all(bytes)?;
+        self.output.flush()
+    }
+
+    fn end_echo(&mut self) -> io::Result<()> {
+        if std::mem::take(&mut self.echoing) {
+            self.output.write_all(self.style.close())?;
+        }
+        Ok(())
+    }
+}
+
+impl<W> Drop for Transcript<W>
+where
+    W: Write,
+{
+    /// Ends any run of echoed input, as nothing else can follow it.
+    fn drop(&mut self) {
+        let _ = self.end_echo();
+        let _ = self.output.flush();
+    }
+}
+
+/// Wraps the input and output of a program so that every byte read from the
+/// input is also written to the output, set apart in the given style.
+///
+/// Each run of input read between two writes is set apart as a whole. The two
+/// ends can be sent to different threads, as long as the output can.
+/// ```
+/// use std::io::{Read, Write};
+/// use bft_interp::io::{echo, EchoStyle};
+///
+/// let mut transcript = Vec::new();
+/// let (mut input, mut output) =
+///     echo(&b"hi"[..], &mut transcript, EchoStyle::Brackets);
+/// output.write_all(b"> ").unwrap();
+/// let mut read = [0; 2];
+/// input.read_exact(&mut read).unwrap();
+/// output.write_all(b"!").unwrap();
+/// drop((input, output));
+/// assert_eq!(transcript, b"> [hi]!");
+/// ```
+pub fn echo<R, W>(
+    input: R,
+    output: W,
+    style: EchoStyle,
+) -> (EchoReader<R, W>, EchoWriter<W>)
+where
+    R: Read,
+    W: Write,
+{
+    let transcript = Arc::new(Mutex::new(Transcript {
+        output,
+        style,
+        echoing: false,
+    }));
+    (
+        EchoReader {
+            reader: input,
+            transcript: Arc::clone(&transcript),
+        },
+        EchoWriter { transcript },
+    )
+}
+
+/// Locks the transcript, which holds consistent state even if poisoned, as
+/// nothing which could panic happens while it is held.
+fn lock<W>(transcript: &Mutex<Transcript<W>>) -> MutexGuard<'_, Transcript<W>>
+where
+    W: Write,
+{
+    transcript.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// The reading end of an echo, which echoes what it reads.
+#[derive(Debug)]
+pub struct EchoReader<R, W>
+where
+    W: Write,
+{
+    reader: R,
+    transcript: Arc<Mutex<Transcript<W>>>,
+}
+
+impl<R, W> Read for EchoReader<R, W>
+where
+    R: Read,
+    W: Write,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.reader.read(buf)?;
+        lock(&self.transcript).echo(&buf[..read])?;
+        Ok(read)
+    }
+}
+
+/// The writing end of an echo, which the program writes its output to.
+#[derive(Debug)]
+pub struct EchoWriter<W>
+where
+    W: Write,
+{
+    transcript: Arc<Mutex<Transcript<W>>>,
+}
+
+impl<W> Write for EchoWriter<W>
+where
+    W: Write,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut transcript = lock(&self.transcript);
+        if !buf.is_empty() {
+            transcript.end_echo()?;
+        }
+        transcript.output.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        lock(&self.transcript).output.flush()
+    }
+}
+
+/// Whether a newline is written once the output of a program has finished.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NewlinePolicy {
+    /// Always write a newline at the end.
+    Always,
+    /// Write a newline at the end unless the output already ends with one.
+    #[default]
+    IfMissing,
+    /// Leave the output as the program wrote it.
+    Never,
+}
+
+impl FromStr for NewlinePolicy {
+    type Err = String;
+
+    /// Parses the names used on the command line and in config files.
+    /// ```
+    /// use bft_interp::io::NewlinePolicy;
+    /// assert_eq!("if-missing".parse(), Ok(NewlinePolicy::IfMissing));
+    /// ```
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "always" => Ok(NewlinePolicy::Always),
+            "if-missing" => Ok(NewlinePolicy::IfMissing),
+            "never" => Ok(NewlinePolicy::Never),
+            _ => Err(format!(
+                "unknown newline policy '{}', expected one of always, \
+                if-missing or never",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for NewlinePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NewlinePolicy::Always => write!(f, "always"),
+            NewlinePolicy::IfMissing => write!(f, "if-missing"),
+            NewlinePolicy::Never => write!(f, "never"),
+        }
+    }
+}
+
+/// A wrapper around Write which writes a newline to the same writer once it is
+/// dropped, according to its `NewlinePolicy`.
+/// ```
+/// use std::io::Write;
+/// use bft_interp::io::{NewlinePolicy, TrailingNewline};
+///
+/// let mut written = Vec::new();
+/// let mut writer = TrailingNewline::new(&mut written, NewlinePolicy::IfMissing);
+/// writer.write_all(b"no newline").unwrap();
+/// drop(writer);
+/// assert_eq!(written, b"no newline\n");
+/// ```
+#[derive(Debug)]
+pub struct TrailingNewline<W>
+where
+    W: Write,
+{
+    writer: W,
+    policy: NewlinePolicy,
+    /// The last byte written, if any have been.
+    last: Option<u8>,
+}
+
+impl<W> TrailingNewline<W>
+where
+    W: Write,
+{
+    /// Wraps the writer, ending it with a newline as given.
+    pub fn new(writer: W, policy: NewlinePolicy) -> Self {
+        Self {
+            writer,
+            policy,
+            last: None,
+        }
+    }
+}
+
+impl<W> Write for TrailingNewline<W>
+where
+    W: Write,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.writer.write(buf)?;
+        if written > 0 {
+            self.last = Some(buf[written - 1]);
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+impl<W> Drop for TrailingNewline<W>
+where
+    W: Write,
+{
+    /// Writes the newline, if the policy asks for one. An empty output does
+    /// not end with a newline, so gets one unless the policy is `Never`.
+    fn drop(&mut self) {
+        let newline = match self.policy {
+            NewlinePolicy::Always => true,
+            NewlinePolicy::IfMissing => self.last != Some(b'\n'),
+            NewlinePolicy::Never => false,
+        };
+        if newline {
+            let _ = self.writer.write_all(b"\n");
+        }
+        let _ = self.writer.flush();
+    }
+}
+
+/// The number of bytes shown on each line of a hex dump.
+const HEX_DUMP_WIDTH: usize = 16;
+
+/// A wrapper around Write which renders the bytes written through it as a hex
+/// dump, in the layout of `hexdump -C`.
+///
+/// Each line shows the offset of its first byte, then sixteen bytes in hex,
+/// then the same bytes as ASCII, with anything unprintable shown as `.`. Lines
+/// are written as they fill up, and the last partial line, followed by the
+/// total number of bytes, once the writer is dropped.
+/// ```
+/// use std::io::Write;
+/// use bft_interp::io::HexDump;
+///
+/// let mut dump = Vec::new();
+/// let mut writer = HexDump::new(&mut dump);
+/// writer.write_all(b"Hello, World!\n\0\xff\x07").unwrap();
+/// drop(writer);
+/// assert_eq!(
+///     String::from_utf8(dump).unwrap(),
+///     "00000000  48 65 6c 6c 6f 2c 20 57  6f 72 6c 64 21 0a 00 ff  \
+///      |Hello, World!...|\n\
+///      00000010  07                                                \
+///      |.|\n\
+///      00000011\n"
+/// );
+/// ```
+#[derive(Debug)]
+pub struct HexDump<W>
+where
+    W: Write,
+{
+    writer: W,
+    /// The offset of the first byte of the current line.
+    offset: usize,
+    /// The bytes of the current line, which is written once it is full.
+    line: Vec<u8>,
+}
+
+impl<W> HexDump<W>
+where
+    W: Write,
+{
+    /// Wraps the writer, which the hex dump is written to.
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            offset: 0,
+            line: Vec::with_capacity(HEX_DUMP_WIDTH),
+        }
+    }
+
+    /// Writes the current line, however full it is, and starts the next.
+    fn write_line(&mut self) -> io::Result<()> {
+        let mut rendered = format!("{:08x} ", self.offset);
+        for index in 0..HEX_DUMP_WIDTH {
+            if index == HEX_DUMP_WIDTH / 2 {
+                rendered.push(' ');
+            }
+            match self.line.get(index) {
+                Some(byte) => rendered.push_str(&format!(" {:02x}", byte)),
+                None => rendered.push_str("   "),
+            }
+        }
+        rendered.push_str("  |");
+        rendered.extend(self.line.iter().map(|byte| {
+            if byte.is_ascii_graphic() || *byte == b' ' {
+                char::from(*byte)
+            } else {
+                '.'
+            }
+        }));
+        rendered.push_str("|\n");
+        self.writer.write_all(rendered.as_bytes())?;
+        self.offset += self.line.len();
+        self.line.clear();
+        Ok(())
+    }
+}
+
+impl<W> Write for HexDump<W>
+where
+    W: Write,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for byte in buf.iter().copied() {
+            self.line.push(byte);
+            if self.line.len() == HEX_DUMP_WIDTH {
+                self.write_line()?;
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+impl<W> Drop for HexDump<W>
+where
+    W: Write,
+{
+    /// Writes the last partial line and the total number of bytes written.
+    fn drop(&mut self) {
+        if !self.line.is_empty() {
+            let _ = self.write_line();
+        }
+        if self.offset > 0 {
+            let _ = writeln!(self.writer, "{:08x}", self.offset);
+        }
+        let _ = self.writer.flush();
+    }
+}
+
+/// Where a Virtual Machine reads its input from, one byte at a time.
+///
+/// This is implemented for closures returning the next byte, or None at the
+/// end of the input, after which what `,` does depends on the `EofBehavior`.
+pub trait InputSource {
+    /// Reads the next byte of the input, or None at the end of the input.
+    fn next_byte(&mut self) -> io::Result<Option<u8>>;
+}
+
+impl<F> InputSource for F
+where
+    F: FnMut() -> Option<u8>,
+{
+    fn next_byte(&mut self) -> io::Result<Option<u8>> {
+        Ok(self())
+    }
+}
+
+/// Where a Virtual Machine writes its output to, one byte at a time.
+///
+/// This is implemented for closures taking each byte written.
+pub trait OutputSink {
+    /// Writes a single byte of the output.
+    fn write_byte(&mut self, byte: u8) -> io::Result<()>;
+
+    /// Makes sure that every byte written so far has reached its destination.
+    /// Sinks which do not buffer have nothing to do.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<F> OutputSink for F
+where
+    F: FnMut(u8),
+{
+    fn write_byte(&mut self, byte: u8) -> io::Result<()> {
+        self(byte);
+        Ok(())
+    }
+}
+
+/// Reads from an `InputSource`, a byte at a time so that the source is never
+/// asked for more than the program reads.
+/// ```
+/// use std::io::Read;
+/// use bft_interp::io::SourceReader;
+///
+/// let mut bytes = b"hi".iter().copied();
+/// let mut reader = SourceReader::new(move || bytes.next());
+/// let mut read = Vec::new();
+/// reader.read_to_end(&mut read).unwrap();
+/// assert_eq!(read, b"hi");
+/// ```
+#[derive(Debug)]
+pub struct SourceReader<S> {
+    source: S,
+}
+
+impl<S> SourceReader<S>
+where
+    S: InputSource,
+{
+    /// Reads from the given source.
+    pub fn new(source: S) -> Self {
+        Self { source }
+    }
+}
+
+impl<S> Read for SourceReader<S>
+where
+    S: InputSource,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let Some(first) = buf.first_mut() else {
+            return Ok(0);
+        };
+        match self.source.next_byte()? {
+            Some(byte) => {
+                *first = byte;
+                Ok(1)
+            }
+            None => Ok(0),
+        }
+    }
+}
+
+/// Writes to an `OutputSink`.
+/// ```
+/// use std::io::Write;
+/// use bft_interp::io::SinkWriter;
+///
+/// let mut written = Vec::new();
+/// SinkWriter::new(|byte| written.push(byte)).write_all(b"hi").unwrap();
+/// assert_eq!(written, b"hi");
+/// ```
+#[derive(Debug)]
+pub struct SinkWriter<S> {
+    sink: S,
+}
+
+impl<S> SinkWriter<S>
+where
+    S: OutputSink,
+{
+    /// Writes to the given sink.
+    pub fn new(sink: S) -> Self {
+        Self { sink }
+    }
+}
+
+impl<S> Write for SinkWriter<S>
+where
+    S: OutputSink,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for byte in buf {
+            self.sink.write_byte(*byte)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.sink.flush()
+    }
+}
+
+/// The number of bytes the thread behind a `PollingReader` reads at a time.
+const POLL_CHUNK: usize = 4096;
+
+/// How a read waits for input which has not arrived yet, such as a `,` on a
+/// terminal which is waiting for the user to type.
+#[derive(Debug, Clone, Default)]
+pub enum WaitStrategy {
+    /// Block in the read of the underlying stream until input arrives, which
+    /// takes no CPU, but cannot be cut short.
+    #[default]
+    Block,
+    /// Wait for input a slice at a time, checking between slices whether the
+    /// wait has been cancelled or has gone on for too long. Takes no CPU while
+    /// asleep, and wakes once per slice.
+    Poll {
+        /// How long each slice of the wait is.
+        interval: Duration,
+        /// How long to wait for each read before failing it with
+        /// `ErrorKind::TimedOut`, if at all.
+        timeout: Option<Duration>,
+        /// Cancels any wait in progress, failing it with `Cancelled`.
+        cancel: CancelToken,
+    },
+}
+
+impl WaitStrategy {
+    /// Wraps the reader so that reads wait for input in this way.
+    pub fn reader<R>(self, reader: R) -> Box<dyn Read + Send>
+    where
+        R: Read + Send + 'static,
+    {
+        match self {
+            WaitStrategy::Block => Box::new(reader),
+            WaitStrategy::Poll {
+                interval,
+                timeout,
+                cancel,
+            } => {
+                Box::new(PollingReader::new(reader, interval, timeout, cancel))
+            }
+        }
+    }
+}
+
+/// Cancels the waits of the `PollingReader`s it is given to, from any thread,
+/// such as from a Ctrl-C handler. Clones cancel the same waits.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    /// Creates a token which has not been cancelled.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancels any wait in progress, and every wait after it.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    /// Whether the token has been cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// The error a read from a `PollingReader` fails with once its `CancelToken`
+/// has been cancelled, as the payload of an `io::Error` of kind
+/// `ErrorKind::Other`. Unlike `ErrorKind::Interrupted`, nothing retries it,
+/// so a Virtual Machine waiting at a `,` stops with it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cancelled;
+
+impl Cancelled {
+    /// Whether the error is a cancelled wait.
+    /// ```
+    /// use std::io;
+    /// use bft_interp::io::Cancelled;
+    ///
+    /// assert!(Cancelled::is(&io::Error::other(Cancelled)));
+    /// assert!(!Cancelled::is(&io::Error::other("cancelled")));
+    /// ```
+    pub fn is(err: &io::Error) -> bool {
+        err.get_ref().is_some_and(|inner| inner.is::<Cancelled>())
+    }
+}
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "waiting for input was cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+/// Reads from another reader on a thread of its own, so that waiting for its
+/// input can be done a slice at a time, with a timeout and a `CancelToken`
+/// checked between slices.
+///
+/// The thread blocks in the reader for as long as it has to, so the thread
+/// may be left waiting on input if the `PollingReader` is dropped first.
+/// ```
+/// use std::io::{ErrorKind, Read};
+/// use std::time::Duration;
+/// use bft_interp::io::{pipe, CancelToken, PollingReader};
+///
+/// let (writer, reader) = pipe(16);
+/// let interval = Duration::from_millis(5);
+/// let timeout = Some(Duration::from_millis(20));
+/// let mut reader =
+///     PollingReader::new(reader, interval, timeout, CancelToken::new());
+/// let error = reader.read(&mut [0; 1]).unwrap_err();
+/// assert_eq!(error.kind(), ErrorKind::TimedOut);
+/// # drop(writer);
+/// ```
+#[derive(Debug)]
+pub struct PollingReader {
+    /// The chunks read by the thread, with an empty chunk at the end of the
+    /// input.
+    chunks: Receiver<io::Result<Vec<u8>>>,
+    /// The bytes of the last chunk which have not been read yet.
+    pending: VecDeque<u8>,
+    interval: Duration,
+    timeout: Option<Duration>,
+    cancel: CancelToken,
+    /// Whether the end of the input has been reached.
+    finished: bool,
+}
+
+impl PollingReader {
+    /// Starts reading from the reader on a thread of its own.
+    pub fn new<R>(
+        mut reader: R,
+        interval: Duration,
+        timeout: Option<Duration>,
+        cancel: CancelToken,
+    ) -> Self
+    where
+        R: Read + Send + 'static,
+    {
+        let (sender, chunks) = mpsc::channel();
+        thread::spawn(move || loop {
+            let mut chunk = vec![0; POLL_CHUNK];
+            let result = match reader.read(&mut chunk) {
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Ok(read) => {
+                    chunk.truncate(read);
+                    Ok(chunk)
+                }
+                Err(e) => Err(e),
+            };
+            let last = !matches!(&result, Ok(chunk) if !chunk.is_empty());
+            if sender.send(result).is_err() || last {
+                return;
+            }
+        });
+        Self {
+            chunks,
+            pending: VecDeque::new(),
+            interval,
+            timeout,
+            cancel,
+            finished: false,
+        }
+    }
+}
+
+impl Read for PollingReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let started = Instant::now();
+        while self.pending.is_empty() && !self.finished {
+            if self.cancel.is_cancelled() {
+                return Err(io::Error::other(Cancelled));
+            }
+            if let Some(timeout) = self.timeout {
+                if started.elapsed() >= timeout {
+                    return Err(io::Error::new(
+                        ErrorKind::TimedOut,
+                        format!("no input arrived within {:?}", timeout),
+                    ));
+                }
+            }
+            match self.chunks.recv_timeout(self.interval) {
+                Ok(Ok(chunk)) if chunk.is_empty() => self.finished = true,
+                Ok(Ok(chunk)) => self.pending.extend(chunk),
+                Ok(Err(e)) => {
+                    self.finished = true;
+                    return Err(e);
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => self.finished = true,
+            }
+        }
+        let read = buf.len().min(self.pending.len());
+        for (slot, byte) in buf.iter_mut().zip(self.pending.drain(..read)) {
+            *slot = byte;
+        }
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        echo, pipe, CancelToken, Cancelled, EchoStyle, HexDump, NewlinePolicy,
+        NewlineReader, NewlineWriter, Newlines, PollingReader, TrailingNewline,
+        WaitStrategy,
+    };
+    use crate::VirtualMachine;
+    use bft_types::vm_error::VirtualMachineError;
+    use bft_types::BfProgram;
+    use std::io::{ErrorKind, Read, Write};
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    fn read_translated(input: &[u8], newlines: Newlines) -> Vec<u8> {
+        let mut translated = Vec::new();
+        NewlineReader::new(input, newlines)
+            .read_to_end(&mut translated)
+            .unwrap();
+        translated
+    }
+
+    fn write_translated(bytes: &[u8], newlines: Newlines) -> Vec<u8> {
+        let mut written = Vec::new();
+        let mut writer = NewlineWriter::new(&mut written, newlines);
+        // One byte at a time, as the Virtual Machine writes.
+        for byte in bytes {
+            writer.write_all(&[*byte]).unwrap();
+            writer.flush().unwrap();
+        }
+        drop(writer);
+        written
+    }
+
+    #[test]
+    fn test_pipe_streams_more_than_capacity() {
+        let (mut writer, mut reader) = pipe(3);
+        let sent: Vec<u8> = (0..=255).collect();
+        let expected = sent.clone();
+        let handle = thread::spawn(move || writer.write_all(&sent));
+        let mut received = Vec::new();
+        reader.read_to_end(&mut received).unwrap();
+        handle.join().unwrap().unwrap();
+        assert_eq!(received, expected);
+    }
+
+    #[test]
+    fn test_pipe_broken_when_reader_dropped() {
+        let (mut writer, reader) = pipe(1);
+        drop(reader);
+        let err = writer.write_all(b"x").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::BrokenPipe);
+    }
+
+    #[test]
+    fn test_pipe_end_of_input() {
+        let (writer, mut reader) = pipe(1);
+        drop(writer);
+        let mut buffer = [0; 1];
+        assert_eq!(reader.read(&mut buffer).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_newline_reader() {
+        let input = b"a\r\n\r\r\nb\n\r";
+        assert_eq!(read_translated(input, Newlines::Unchanged), input);
+        assert_eq!(read_translated(input, Newlines::CrlfToLf), b"a\n\r\nb\n\r");
+        assert_eq!(
+            read_translated(input, Newlines::LfToCrlf),
+            b"a\r\n\r\r\nb\r\n\r"
+        );
+    }
+
+    #[test]
+    fn test_newline_writer() {
+        let output = b"a\r\n\r\r\nb\n\r";
+        assert_eq!(write_translated(output, Newlines::Unchanged), output);
+        assert_eq!(
+            write_translated(output, Newlines::CrlfToLf),
+            b"a\n\r\nb\n\r"
+        );
+        assert_eq!(
+            write_translated(output, Newlines::LfToCrlf),
+            b"a\r\n\r\r\nb\r\n\r"
+        );
+    }
+
+    #[test]
+    fn test_newlines_round_trip() {
+        let unix = b"one\ntwo\n";
+        let windows = write_translated(unix, Newlines::LfToCrlf);
+        assert_eq!(windows, b"one\r\ntwo\r\n");
+        assert_eq!(read_translated(&windows, Newlines::CrlfToLf), unix);
+    }
+
+    #[test]
+    fn test_echo() {
+        let mut transcript = Vec::new();
+        let (mut input, mut output) =
+            echo(&b"ab\n"[..], &mut transcript, EchoStyle::Dim);
+        // One byte at a time, as the Virtual Machine reads and writes.
+        let mut byte = [0];
+        for _ in 0..2 {
+            input.read_exact(&mut byte).unwrap();
+        }
+        output.write_all(b"?").unwrap();
+        input.read_exact(&mut byte).unwrap();
+        assert_eq!(
+            input.read(&mut byte).unwrap(),
+            0,
+            "the end of the input is not echoed"
+        );
+        drop((input, output));
+        assert_eq!(transcript, b"\x1b[2mab\x1b[22m?\x1b[2m\n\x1b[22m");
+    }
+
+    #[test]
+    fn test_echo_across_threads() {
+        let (writer, reader) = pipe(4);
+        let (mut input, output) =
+            echo(&b"xyz"[..], writer, EchoStyle::Brackets);
+        let reading = thread::spawn(move || {
+            let mut transcript = Vec::new();
+            let mut reader = reader;
+            reader.read_to_end(&mut transcript).unwrap();
+            transcript
+        });
+        let writing = thread::spawn(move || {
+            let mut output = output;
+            output.write_all(b"ok").unwrap();
+        });
+        writing.join().unwrap();
+        let mut read = Vec::new();
+        input.read_to_end(&mut read).unwrap();
+        drop(input);
+        assert_eq!(read, b"xyz");
+        assert_eq!(reading.join().unwrap(), b"ok[xyz]");
+    }
+
+    #[test]
+    fn test_hex_dump() {
+        let render = |bytes: &[u8]| {
+            let mut dump = Vec::new();
+            let mut writer = HexDump::new(&mut dump);
+            // One byte at a time, as the Virtual Machine writes.
+            for byte in bytes {
+                writer.write_all(&[*byte]).unwrap();
+                writer.flush().unwrap();
+            }
+            drop(writer);
+            String::from_utf8(dump).unwrap()
+        };
+        assert_eq!(render(b""), "");
+        let bytes: Vec<u8> = (b'0'..b'0' + 32).collect();
+        assert_eq!(
+            render(&bytes),
+            "00000000  30 31 32 33 34 35 36 37  38 39 3a 3b 3c 3d 3e 3f  \
+             |0123456789:;<=>?|\n\
+             00000010  40 41 42 43 44 45 46 47  48 49 4a 4b 4c 4d 4e 4f  \
+             |@ABCDEFGHIJKLMNO|\n\
+             00000020\n"
+        );
+    }
+
+    #[test]
+    fn test_trailing_newline() {
+        let finish = |bytes: &[u8], policy: NewlinePolicy| {
+            let mut written = Vec::new();
+            let mut writer = TrailingNewline::new(&mut written, policy);
+            writer.write_all(bytes).unwrap();
+            drop(writer);
+            written
+        };
+        assert_eq!(finish(b"a", NewlinePolicy::IfMissing), b"a\n");
+        assert_eq!(finish(b"a\n", NewlinePolicy::IfMissing), b"a\n");
+        assert_eq!(finish(b"", NewlinePolicy::IfMissing), b"\n");
+        assert_eq!(finish(b"a\n", NewlinePolicy::Always), b"a\n\n");
+        assert_eq!(finish(b"a", NewlinePolicy::Never), b"a");
+        assert_eq!(finish(b"", NewlinePolicy::Never), b"");
+    }
+
+    #[test]
+    fn test_polling_reader() {
+        let interval = Duration::from_millis(1);
+        let (mut writer, reader) = pipe(16);
+        let cancel = CancelToken::new();
+        let mut reader = WaitStrategy::Poll {
+            interval,
+            timeout: None,
+            cancel: cancel.clone(),
+        }
+        .reader(reader);
+        let feeder = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            writer.write_all(b"hi").unwrap();
+        });
+        let mut read = Vec::new();
+        reader.read_to_end(&mut read).unwrap();
+        assert_eq!(read, b"hi");
+        feeder.join().unwrap();
+
+        // Cancelling from another thread ends the wait.
+        let (_writer, reader) = pipe(16);
+        let mut reader =
+            PollingReader::new(reader, interval, None, cancel.clone());
+        let canceller = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            cancel.cancel();
+        });
+        let error = reader.read(&mut [0; 1]).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Other);
+        assert!(Cancelled::is(&error));
+        canceller.join().unwrap();
+    }
+
+    #[test]
+    fn test_cancel_interpret() {
+        let (done, finished) = mpsc::channel();
+        let cancel = CancelToken::new();
+        let token = cancel.clone();
+        thread::spawn(move || {
+            let program = BfProgram::new(",".to_string(), "wait.bf").unwrap();
+            let mut vm = VirtualMachine::<u8>::new(&program, 1, false);
+            let (_writer, reader) = pipe(16);
+            let interval = Duration::from_millis(1);
+            let mut reader = NewlineReader::new(
+                PollingReader::new(reader, interval, None, token),
+                Newlines::CrlfToLf,
+            );
+            let _ = done.send(vm.interpret(&mut reader, &mut Vec::new()));
+        });
+        thread::sleep(Duration::from_millis(20));
+        cancel.cancel();
+        let result = finished.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(matches!(
+            result,
+            Err(VirtualMachineError::IOError(e)) if Cancelled::is(&e)
+        ));
+    }
+}
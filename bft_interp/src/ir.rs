@@ -0,0 +1,358 @@
+//! An intermediate representation of Brainfuck programs, in which runs of
+//! instructions and common loop idioms can be replaced by single operations,
+//! so that the program can be run more quickly.
+
+use std::path::{Path, PathBuf};
+
+use bft_types::ops::Operation;
+use bft_types::vm_error::VirtualMachineError;
+use bft_types::{BfProgram, InstructionInfo};
+
+use crate::CellKind;
+
+/// A single operation of the intermediate representation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IrOp {
+    /// Adds the amount to the cell at the head of the tape, wrapping around.
+    Add(i32),
+    /// Moves the head of the tape by the given number of cells.
+    Move(isize),
+    /// Writes out the cell at the head of the tape.
+    Output,
+    /// Reads into the cell at the head of the tape.
+    Input,
+    /// Jumps past the matching `LoopEnd` if the cell at the head of the tape
+    /// is zero.
+    LoopStart,
+    /// Jumps back past the matching `LoopStart` if the cell at the head of the
+    /// tape is not zero.
+    LoopEnd,
+    /// Jumps past the matching `Else` if the cell at the head of the tape is
+    /// zero, so that only one of the two branches runs.
+    If,
+    /// Ends the branch run when the cell at the `If` was not zero, jumping past
+    /// the matching `EndIf`.
+    Else,
+    /// Ends the branch run when the cell at the `If` was zero.
+    EndIf,
+    /// Sets the cell at the head of the tape to zero.
+    Clear,
+    /// Adds the amount to the cell at the offset from the head of the tape,
+    /// without moving the head.
+    AddAt(isize, i32),
+    /// Sets the cell at the offset from the head of the tape to zero, without
+    /// moving the head.
+    ClearAt(isize),
+    /// Adds the amount to each of the given number of cells, starting from the
+    /// offset from the head of the tape, without moving the head.
+    AddRange(isize, usize, i32),
+    /// Sets each of the given number of cells, starting from the offset from
+    /// the head of the tape, to zero, without moving the head.
+    ClearRange(isize, usize),
+    /// If the cell at the head of the tape is not zero, adds its value times
+    /// each factor to the cell at each offset from the head, and then sets it
+    /// to zero.
+    CopyLoop(Vec<(isize, i32)>),
+    /// If the cell at the head of the tape is not zero, makes the changes of a
+    /// loop which counts it down to zero, as many times as its value, and
+    /// then sets it to zero.
+    MulAdd(Box<MulLoop>),
+    /// Moves the head of the tape right by the step until it reaches a zero
+    /// cell, which may be the cell it starts on.
+    ScanRight(usize),
+    /// Moves the head of the tape left by the step until it reaches a zero
+    /// cell, which may be the cell it starts on.
+    ScanLeft(usize),
+    /// Writes out the bytes, which were worked out ahead of time.
+    OutputBytes(Vec<u8>),
+    /// Sets the cells starting from the head of the tape to the values, which
+    /// were worked out ahead of time.
+    LoadTape(Vec<u32>),
+    /// Runs an extension instruction.
+    Extension(char),
+}
+
+/// What a loop lowered to a `MulAdd` does to the cells around the head of the
+/// tape each time it goes around, at offsets from the head. Cells which are
+/// multiplied by are never changed by the loop, so their values are the same
+/// each time around.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct MulLoop {
+    /// Adds the value of the cell at the second offset times the factor to
+    /// the cell at the first offset.
+    pub products: Vec<(isize, isize, i32)>,
+    /// Adds the amount to the cell at the offset.
+    pub adds: Vec<(isize, i32)>,
+    /// Sets the cell at the offset to the value, wrapping around.
+    pub sets: Vec<(isize, i32)>,
+}
+
+impl MulLoop {
+    /// The offsets of every cell the loop reads or changes, other than the
+    /// cell at the head.
+    pub fn offsets(&self) -> impl Iterator<Item = isize> + '_ {
+        self.products
+            .iter()
+            .flat_map(|&(offset, by, _)| [offset, by])
+            .chain(self.adds.iter().map(|&(offset, _)| offset))
+            .chain(self.sets.iter().map(|&(offset, _)| offset))
+    }
+
+    /// Makes the changes to the tape, if the cell at the head is not zero.
+    /// Every offset must be on the tape.
+    pub(crate) fn apply<T>(&self, tape: &mut [T], head: usize)
+    where
+        T: CellKind + Default + Copy + PartialEq,
+    {
+        let count = tape[head];
+        if count == T::default() {
+            return;
+        }
+        let at = |offset: isize| head.wrapping_add_signed(offset);
+        for &(offset, by, factor) in &self.products {
+            let by = tape[at(by)].to_u32();
+            let product = T::from_u32(count.to_u32().wrapping_mul(by));
+            tape[at(offset)] = tape[at(offset)].add_product(product, factor);
+        }
+        for &(offset, amount) in &self.adds {
+            tape[at(offset)] = tape[at(offset)].add_product(count, amount);
+        }
+        for &(offset, value) in &self.sets {
+            tape[at(offset)] = T::default().add_signed(value);
+        }
+        tape[head] = T::default();
+    }
+}
+
+/// An operation, along with the instruction in the original program that it
+/// came from, which is used when reporting errors.
+#[derive(Debug, Clone)]
+pub struct IrNode {
+    op: IrOp,
+    source: InstructionInfo,
+}
+
+impl IrNode {
+    /// Creates a node for an operation which came from the given instruction.
+    pub fn new(op: IrOp, source: InstructionInfo) -> Self {
+        Self { op, source }
+    }
+
+    /// Retrieves the operation of the node.
+    pub fn op(&self) -> &IrOp {
+        &self.op
+    }
+
+    /// Retrieves the instruction in the original program which the node came
+    /// from. For nodes which replaced several instructions, this is the first
+    /// of them.
+    pub fn source(&self) -> InstructionInfo {
+        self.source
+    }
+}
+
+/// A Brainfuck program in the intermediate representation.
+///
+/// Passes which change the nodes of the program must keep its loops balanced,
+/// with every `LoopStart` matched by a later `LoopEnd`.
+/// ```
+/// use bft_types::BfProgram;
+/// use bft_interp::ir::{IrOp, IrProgram};
+///
+/// let program = BfProgram::new("+[-]".to_string(), "clear.bf").unwrap();
+/// let ir = IrProgram::from_program(&program).unwrap();
+/// assert_eq!(ir.nodes().len(), 4);
+/// assert_eq!(ir.nodes()[0].op(), &IrOp::Add(1));
+/// ```
+#[derive(Debug, Clone)]
+pub struct IrProgram {
+    nodes: Vec<IrNode>,
+    filename: PathBuf,
+    evaluated_steps: u64,
+}
+
+impl IrProgram {
+    /// Lowers a program into the intermediate representation, with one node
+    /// per instruction. Fails if the brackets of the program are not balanced,
+    /// even if it was parsed with lazy bracket validation.
+    pub fn from_program(
+        program: &BfProgram,
+    ) -> Result<Self, VirtualMachineError> {
+        program.checked_jump_table()?;
+        let nodes = program
+            .iter()
+            .map(|instruction| {
+                let op = match instruction.operation() {
+                    Operation::IncrementPointer => IrOp::Move(1),
+                    Operation::DecrementPointer => IrOp::Move(-1),
+                    Operation::IncrementByte => IrOp::Add(1),
+                    Operation::DecrementByte => IrOp::Add(-1),
+                    Operation::OutputByte => IrOp::Output,
+                    Operation::InputByte => IrOp::Input,
+                    Operation::StartLoop => IrOp::LoopStart,
+                    Operation::EndLoop => IrOp::LoopEnd,
+                    Operation::Extension(name) => IrOp::Extension(name),
+                };
+                IrNode::new(op, *instruction)
+            })
+            .collect();
+        Ok(Self {
+            nodes,
+            filename: program.filename().to_path_buf(),
+            evaluated_steps: 0,
+        })
+    }
+
+    /// Creates a program directly from its nodes, for front ends which do not
+    /// start from Brainfuck source. Fails if the loops of the nodes are not
+    /// balanced.
+    pub fn from_nodes<P>(
+        nodes: Vec<IrNode>,
+        filename: P,
+    ) -> Result<Self, VirtualMachineError>
+    where
+        P: AsRef<Path>,
+    {
+        let program = Self {
+            nodes,
+            filename: filename.as_ref().to_path_buf(),
+            evaluated_steps: 0,
+        };
+        program.jump_table()?;
+        Ok(program)
+    }
+
+    /// Retrieves the nodes of the program.
+    pub fn nodes(&self) -> &[IrNode] {
+        &self.nodes
+    }
+
+    /// Retrieves the nodes of the program, so that a pass can change them.
+    pub fn nodes_mut(&mut self) -> &mut Vec<IrNode> {
+        &mut self.nodes
+    }
+
+    /// Retrieves the filename of the original program.
+    pub fn filename(&self) -> &Path {
+        &self.filename
+    }
+
+    /// The number of steps of the program which were run ahead of time by
+    /// partial evaluation, which are charged to the step limit of the Virtual
+    /// Machine before it runs the rest of the program.
+    pub fn evaluated_steps(&self) -> u64 {
+        self.evaluated_steps
+    }
+
+    /// Records that a pass ran more steps of the program ahead of time.
+    pub fn add_evaluated_steps(&mut self, steps: u64) {
+        self.evaluated_steps += steps;
+    }
+
+    /// Whether the program could read any input, either with an `Input` or by
+    /// running an extension instruction.
+    pub fn reads_input(&self) -> bool {
+        self.nodes
+            .iter()
+            .any(|node| matches!(node.op, IrOp::Input | IrOp::Extension(_)))
+    }
+
+    /// Finds the matching loop node for each `LoopStart` and `LoopEnd`, the
+    /// matching `Else` for each `If`, and the matching `EndIf` for each
+    /// `Else`, with every other position mapping to itself.
+    pub(crate) fn jump_table(&self) -> Result<Vec<usize>, VirtualMachineError> {
+        let mut jumps: Vec<usize> = (0..self.nodes.len()).collect();
+        let mut open: Vec<usize> = Vec::new();
+        for (position, node) in self.nodes.iter().enumerate() {
+            match node.op {
+                IrOp::LoopStart => open.push(position),
+                IrOp::LoopEnd => {
+                    let start = open.pop().ok_or(
+                        VirtualMachineError::UnbalancedNodes { position },
+                    )?;
+                    jumps[start] = position;
+                    jumps[position] = start;
+                }
+                IrOp::If => open.push(position),
+                IrOp::Else => {
+                    let start = open.pop().ok_or(
+                        VirtualMachineError::UnbalancedNodes { position },
+                    )?;
+                    jumps[start] = position;
+                    open.push(position);
+                }
+                IrOp::EndIf => {
+                    let start = open.pop().ok_or(
+                        VirtualMachineError::UnbalancedNodes { position },
+                    )?;
+                    jumps[start] = position;
+                }
+                _ => {}
+            }
+        }
+        match open.pop() {
+            Some(position) => {
+                Err(VirtualMachineError::UnbalancedNodes { position })
+            }
+            None => Ok(jumps),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{IrNode, IrOp, IrProgram};
+    use bft_types::options::{BracketValidation, ParseOptions};
+    use bft_types::vm_error::VirtualMachineError;
+    use bft_types::BfProgram;
+
+    #[test]
+    fn test_lowering_keeps_positions() {
+        let program =
+            BfProgram::new(String::from("+\n [>]"), "lower.bf").unwrap();
+        let ir = IrProgram::from_program(&program).unwrap();
+        let ops: Vec<&IrOp> = ir.nodes().iter().map(IrNode::op).collect();
+        assert_eq!(
+            ops,
+            [
+                &IrOp::Add(1),
+                &IrOp::LoopStart,
+                &IrOp::Move(1),
+                &IrOp::LoopEnd
+            ]
+        );
+        assert_eq!(ir.nodes()[2].source().line(), 2);
+        assert_eq!(ir.nodes()[2].source().column(), 3);
+        assert_eq!(ir.jump_table().unwrap(), [0, 3, 2, 1]);
+        assert!(!ir.reads_input());
+    }
+
+    #[test]
+    fn test_lowering_needs_balanced_brackets() {
+        let options =
+            ParseOptions::new().bracket_validation(BracketValidation::Lazy);
+        let program = BfProgram::new_with_options(
+            String::from("+]"),
+            "lazy.bf",
+            &options,
+        )
+        .unwrap();
+        assert!(IrProgram::from_program(&program).is_err());
+    }
+
+    #[test]
+    fn test_unbalanced_nodes() {
+        let source = BfProgram::new(String::from("+"), "nodes.bf").unwrap();
+        let node = |op| IrNode::new(op, source.instructions()[0]);
+        let nodes = vec![node(IrOp::LoopStart), node(IrOp::Add(1))];
+        assert!(matches!(
+            IrProgram::from_nodes(nodes, "nodes.bf"),
+            Err(VirtualMachineError::UnbalancedNodes { position: 0 })
+        ));
+        let nodes = vec![node(IrOp::Add(1)), node(IrOp::LoopEnd)];
+        assert!(matches!(
+            IrProgram::from_nodes(nodes, "nodes.bf"),
+            Err(VirtualMachineError::UnbalancedNodes { position: 1 })
+        ));
+    }
+}
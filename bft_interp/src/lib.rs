@@ -3,17 +3,64 @@
 
 #![deny(missing_docs)]
 
+use std::collections::HashMap;
+use std::io::ErrorKind;
 use std::io::Read;
 use std::io::Write;
+use std::io::{empty, sink};
+use std::ops::Range;
+use std::path::Path;
+use std::time::Instant;
 
-use bft_types::BfProgram;
+use bft_types::options::BracketValidation;
 use bft_types::{ops::Operation, vm_error::VirtualMachineError};
+use bft_types::{BfProgram, InstructionInfo};
 
+pub mod asm;
+mod bytes;
 mod cellkind;
-use cellkind::CellKind;
+pub use cellkind::CellKind;
+
+pub mod compiled;
+pub mod dispatch;
+pub mod display;
+pub mod eof;
+pub mod events;
+pub mod extension;
+pub mod independence;
+pub mod io;
+pub mod ir;
+pub mod metrics;
+pub mod optimizer;
+pub mod partial;
+pub mod plugin;
+pub mod preset;
+pub mod report;
+pub mod resume;
+pub mod sandbox;
+pub mod scheduler;
+pub mod summary;
+pub mod symexec;
+pub mod syscall;
+pub mod tape;
+use dispatch::{Dispatch, DispatchKind, MatchDispatch, ThreadedDispatch};
+use eof::EofBehavior;
+use events::{EventSink, VmEvent};
+use extension::{ExtensionHandler, VmContext};
+use io::{InputSource, OutputSink, SinkWriter, SourceReader};
+use ir::{IrNode, IrOp, IrProgram, MulLoop};
+use report::{Reporter, Step, TracedOp};
+use resume::Event;
+use sandbox::{gas_cost, Sandbox, Syscalls};
+use summary::RunSummary;
+use tape::{HeapAllocator, TapeAllocator};
 
 const DEFAULT_TAPE_LENGTH: usize = 30_000;
 
+/// How many steps are taken between reading the clock, when the time a program
+/// may run for is limited.
+const TIME_CHECK_INTERVAL: u64 = 1024;
+
 /// A "Virtual Machine" for the Brainfuck program to be interpreted in.
 /// This struct consists of a Tape (an array of numbers) and a Head (a pointer
 /// to the a position in the array).
@@ -29,8 +76,60 @@ pub struct VirtualMachine<'a, T> {
     tape_head: usize,
     /// The position of the interpreter in the program
     program_position: usize,
-    /// Bool to indicate whether the tape can grow
-    growable: bool,
+    /// The limits on what the program may do, including whether the tape can
+    /// grow and which host functions it may call
+    sandbox: Sandbox,
+    /// The number of instructions executed so far
+    steps: u64,
+    /// The gas used by the instructions executed so far
+    gas_used: u64,
+    /// When the first instruction was executed, if the time is limited
+    started: Option<Instant>,
+    /// The number of bytes of output written so far
+    output_written: u64,
+    /// The number of bytes of input read so far
+    input_read: u64,
+    /// The number of times each loop has jumped back to its start, by the
+    /// position of its opening bracket
+    loop_counts: Vec<u64>,
+    /// The step which last read input or wrote output, if any has
+    last_io_step: Option<u64>,
+    /// Whether the last step was a `[`, which jumps to its `]` to decide
+    /// whether the loop is entered, so that jumping back from that `]` is
+    /// not counted as going round the loop
+    entering: bool,
+    /// What to do when the program reads past the end of its input
+    eof_behavior: EofBehavior,
+    /// The handlers for the extension instructions, keyed by their character
+    extensions: HashMap<char, ExtensionHandler<'a, T>>,
+    /// The host functions the program may call, keyed by their number
+    syscalls: HashMap<u32, ExtensionHandler<'a, T>>,
+    /// How operations are dispatched when running a lowered program
+    dispatch: DispatchKind,
+    /// What each step is reported to, if anything
+    reporter: Option<&'a mut dyn Reporter>,
+    /// What the changes made by each step are given to, if anything
+    event_sink: Option<&'a mut dyn EventSink>,
+    /// The input and output given when the Virtual Machine was created, used
+    /// by `run` and `run_ir`
+    io: Option<(Box<dyn Read + 'a>, Box<dyn Write + 'a>)>,
+    /// The lowered program run by `run`, for Virtual Machines spawned for a
+    /// `CompiledProgram`
+    ir: Option<&'a IrProgram>,
+    /// The byte given by `provide_input` for the `,` which is waiting for it,
+    /// or None for the end of the input
+    pending_input: Option<Option<u8>>,
+    /// Where the tape came from, if not the heap
+    allocator: Option<Box<dyn TapeAllocator<T> + 'a>>,
+}
+
+impl<T> Drop for VirtualMachine<'_, T> {
+    /// Gives the tape back to the allocator it came from.
+    fn drop(&mut self) {
+        if let Some(allocator) = &mut self.allocator {
+            allocator.release(std::mem::take(&mut self.tape));
+        }
+    }
 }
 
 impl<'a, T> VirtualMachine<'a, T>
@@ -62,17 +161,472 @@ where
         if tape_length == 0 {
             tape_length = DEFAULT_TAPE_LENGTH;
         }
+        Self::with_tape(program, HeapAllocator.allocate(tape_length), growable)
+    }
+
+    /// Creates a Virtual Machine whose tape comes from the given allocator,
+    /// which also decides whether the tape may grow, and is given the tape
+    /// back once the Virtual Machine is dropped. See `tape` for more.
+    /// ```
+    /// use std::io::Cursor;
+    /// use bft_types::BfProgram;
+    /// use bft_interp::VirtualMachine;
+    /// use bft_interp::tape::TapeAllocator;
+    ///
+    /// /// Lets the tape grow to at most 8 cells.
+    /// struct Capped;
+    ///
+    /// impl TapeAllocator<u8> for Capped {
+    ///     fn allocate(&mut self, len: usize) -> Vec<u8> {
+    ///         vec![0; len]
+    ///     }
+    ///
+    ///     fn grow(&mut self, tape: &mut Vec<u8>, len: usize) -> bool {
+    ///         tape.resize(len, 0);
+    ///         len <= 8
+    ///     }
+    /// }
+    ///
+    /// let program = BfProgram::new("+[>+]".to_string(), "forever.bf").unwrap();
+    /// let mut vm = VirtualMachine::new_in(&program, 4, true, Capped);
+    /// let mut input = Cursor::new(Vec::new());
+    /// assert!(vm.interpret(&mut input, &mut Vec::new()).is_err());
+    /// assert_eq!(vm.tape_head(), 8);
+    /// ```
+    pub fn new_in(
+        program: &'a BfProgram,
+        mut tape_length: usize,
+        growable: bool,
+        mut allocator: impl TapeAllocator<T> + 'a,
+    ) -> Self {
+        if tape_length == 0 {
+            tape_length = DEFAULT_TAPE_LENGTH;
+        }
+        let tape = allocator.allocate(tape_length);
+        let mut vm = Self::with_tape(program, tape, growable);
+        vm.allocator = Some(Box::new(allocator));
+        vm
+    }
+
+    /// Creates a Virtual Machine on the given tape, which must not be empty.
+    fn with_tape(program: &'a BfProgram, tape: Vec<T>, growable: bool) -> Self {
         Self {
             program,
-            tape: vec![Default::default(); tape_length],
+            tape,
             tape_head: 0,
             program_position: 0,
-            growable,
+            sandbox: Sandbox {
+                tape_growth: growable,
+                ..Sandbox::default()
+            },
+            steps: 0,
+            gas_used: 0,
+            started: None,
+            output_written: 0,
+            input_read: 0,
+            loop_counts: vec![0; program.instructions().len()],
+            last_io_step: None,
+            entering: false,
+            eof_behavior: EofBehavior::default(),
+            extensions: HashMap::new(),
+            syscalls: HashMap::new(),
+            dispatch: DispatchKind::default(),
+            reporter: None,
+            event_sink: None,
+            io: None,
+            ir: None,
+            pending_input: None,
+            allocator: None,
+        }
+    }
+
+    /// Creates a Virtual Machine which reads its input from, and writes its
+    /// output to, the given source and sink, rather than streams given to
+    /// each call of `interpret`. Closures make handy sources and sinks, for
+    /// hosts such as game engines, GUIs and wasm which have no streams to
+    /// hand. The program is then run with `run`, or `run_ir`.
+    /// ```
+    /// use std::collections::VecDeque;
+    /// use bft_types::BfProgram;
+    /// use bft_interp::VirtualMachine;
+    /// use bft_interp::eof::EofBehavior;
+    ///
+    /// let program = BfProgram::new(",[+.,]".to_string(), "next.bf").unwrap();
+    /// let mut input = VecDeque::from(b"HAL".to_vec());
+    /// let mut output = Vec::new();
+    /// let mut vm = VirtualMachine::<u8>::with_io(
+    ///     &program,
+    ///     1,
+    ///     false,
+    ///     move || input.pop_front(),
+    ///     |byte| output.push(byte),
+    /// )
+    /// .with_eof_behavior(EofBehavior::Zero);
+    /// vm.run().unwrap();
+    /// drop(vm);
+    /// assert_eq!(output, b"IBM");
+    /// ```
+    pub fn with_io(
+        program: &'a BfProgram,
+        tape_length: usize,
+        growable: bool,
+        input: impl InputSource + 'a,
+        output: impl OutputSink + 'a,
+    ) -> Self {
+        let mut vm = Self::new(program, tape_length, growable);
+        vm.io = Some((
+            Box::new(SourceReader::new(input)),
+            Box::new(SinkWriter::new(output)),
+        ));
+        vm
+    }
+
+    /// Creates a Virtual Machine which starts on an existing tape, with its
+    /// head at the given position, rather than on a tape of zeroed cells. This
+    /// is the counterpart to `into_tape`, allowing one program to carry on
+    /// from where another left off.
+    ///
+    /// If the head lies beyond the end of the tape, the tape is extended with
+    /// zeroed cells to reach it. An empty tape is given the default length.
+    /// ```
+    /// use bft_types::BfProgram;
+    /// use bft_interp::VirtualMachine;
+    ///
+    /// let program = BfProgram::new("+".to_string(), "test.bf").unwrap();
+    /// let vm = VirtualMachine::<u8>::from_tape(&program, vec![1, 2, 3], 1, false);
+    /// assert_eq!(vm.tape_head(), 1);
+    /// assert_eq!(vm.value_at_tape_head(), 2);
+    /// ```
+    pub fn from_tape(
+        program: &'a BfProgram,
+        mut tape: Vec<T>,
+        tape_head: usize,
+        growable: bool,
+    ) -> Self {
+        if tape.is_empty() {
+            tape.resize(DEFAULT_TAPE_LENGTH, Default::default());
+        }
+        if tape_head >= tape.len() {
+            tape.resize(tape_head + 1, Default::default());
         }
+        let mut vm = Self::with_tape(program, tape, growable);
+        vm.tape_head = tape_head;
+        vm
+    }
+
+    /// Sets what the Virtual Machine does when the program reads past the end
+    /// of its input. By default this is an error.
+    /// ```
+    /// use std::io::Cursor;
+    /// use bft_types::BfProgram;
+    /// use bft_interp::VirtualMachine;
+    /// use bft_interp::eof::EofBehavior;
+    ///
+    /// let program = BfProgram::new("+,".to_string(), "eof.bf").unwrap();
+    /// let mut vm = VirtualMachine::<u8>::new(&program, 1, false)
+    ///     .with_eof_behavior(EofBehavior::MaxValue);
+    /// let mut input = Cursor::new(Vec::<u8>::new());
+    /// let mut output = Cursor::new(Vec::<u8>::new());
+    /// vm.interpret(&mut input, &mut output).unwrap();
+    /// assert_eq!(vm.value_at_tape_head(), 255);
+    /// ```
+    pub fn with_eof_behavior(mut self, eof_behavior: EofBehavior) -> Self {
+        self.eof_behavior = eof_behavior;
+        self
+    }
+
+    /// Limits the number of instructions the Virtual Machine may execute, after
+    /// which interpreting the program fails with a `StepLimitExceeded` error.
+    /// This is useful for programs which may never halt.
+    /// ```
+    /// use std::io::Cursor;
+    /// use bft_types::BfProgram;
+    /// use bft_interp::VirtualMachine;
+    ///
+    /// let program = BfProgram::new("+[]".to_string(), "forever.bf").unwrap();
+    /// let mut vm = VirtualMachine::<u8>::new(&program, 1, false).with_step_limit(100);
+    /// let mut input = Cursor::new(Vec::<u8>::new());
+    /// let mut output = Cursor::new(Vec::<u8>::new());
+    /// assert!(vm.interpret(&mut input, &mut output).is_err());
+    /// assert_eq!(vm.steps(), 100);
+    /// ```
+    pub fn with_step_limit(mut self, limit: u64) -> Self {
+        self.sandbox.step_limit = Some(limit);
+        self
+    }
+
+    /// Limits the number of bytes the program may write, after which
+    /// interpreting it fails with an `OutputLimitExceeded` error, before the
+    /// output which would have gone over the limit is written. This protects
+    /// whatever reads the output from programs which print forever.
+    /// ```
+    /// use std::io::Cursor;
+    /// use bft_types::BfProgram;
+    /// use bft_interp::VirtualMachine;
+    ///
+    /// let program = BfProgram::new("+[.]".to_string(), "chatty.bf").unwrap();
+    /// let mut vm = VirtualMachine::<u8>::new(&program, 1, false).with_output_limit(3);
+    /// let mut input = Cursor::new(Vec::<u8>::new());
+    /// let mut output = Vec::new();
+    /// assert!(vm.interpret(&mut input, &mut output).is_err());
+    /// assert_eq!(output, [1, 1, 1]);
+    /// ```
+    pub fn with_output_limit(mut self, limit: u64) -> Self {
+        self.sandbox.output_limit = Some(limit);
+        self
+    }
+
+    /// Limits the number of bytes the program may read. Once it has read
+    /// them, the input is not read any further, and the program is at the end
+    /// of its input as far as the `EofBehavior` is concerned. This keeps a
+    /// program from waiting forever on input which is never closed.
+    /// ```
+    /// use std::io::Cursor;
+    /// use bft_types::BfProgram;
+    /// use bft_interp::VirtualMachine;
+    /// use bft_interp::eof::EofBehavior;
+    ///
+    /// let program = BfProgram::new(",[.,]".to_string(), "cat.bf").unwrap();
+    /// let mut vm = VirtualMachine::<u8>::new(&program, 1, false)
+    ///     .with_input_limit(2)
+    ///     .with_eof_behavior(EofBehavior::Zero);
+    /// let mut output = Vec::new();
+    /// vm.interpret(&mut Cursor::new(b"abc".to_vec()), &mut output).unwrap();
+    /// assert_eq!(output, b"ab");
+    /// ```
+    pub fn with_input_limit(mut self, limit: u64) -> Self {
+        self.sandbox.input_limit = Some(limit);
+        self
+    }
+
+    /// Puts the Virtual Machine in the sandbox, replacing all of its limits,
+    /// whether its tape can grow and which host functions the program may
+    /// call with those of the sandbox. See `sandbox` for more.
+    /// ```
+    /// use std::io::Cursor;
+    /// use bft_types::BfProgram;
+    /// use bft_types::vm_error::VirtualMachineError;
+    /// use bft_interp::VirtualMachine;
+    /// use bft_interp::sandbox::Sandbox;
+    ///
+    /// let sandbox = Sandbox::builder().with_gas_limit(50).build().unwrap();
+    /// let program = BfProgram::new("+[]".to_string(), "forever.bf").unwrap();
+    /// let mut vm = VirtualMachine::<u8>::new(&program, 1, false).with_sandbox(sandbox);
+    /// let result = vm.interpret(&mut Cursor::new(Vec::new()), &mut Vec::new());
+    /// assert!(matches!(result, Err(VirtualMachineError::GasExhausted { .. })));
+    /// assert_eq!(vm.gas_used(), 50);
+    /// ```
+    pub fn with_sandbox(mut self, sandbox: Sandbox) -> Self {
+        self.sandbox = sandbox;
+        self
+    }
+
+    /// Chooses how operations are dispatched when interpreting a lowered
+    /// program with `interpret_ir`. By default this is a `match` on each
+    /// operation.
+    /// ```
+    /// use std::io::Cursor;
+    /// use bft_types::BfProgram;
+    /// use bft_interp::VirtualMachine;
+    /// use bft_interp::dispatch::DispatchKind;
+    /// use bft_interp::ir::IrProgram;
+    ///
+    /// let program = BfProgram::new("+++.".to_string(), "three.bf").unwrap();
+    /// let ir = IrProgram::from_program(&program).unwrap();
+    /// let mut vm = VirtualMachine::<u8>::new(&program, 1, false)
+    ///     .with_dispatch(DispatchKind::Threaded);
+    /// let mut output = Vec::new();
+    /// vm.interpret_ir(&ir, &mut Cursor::new(Vec::new()), &mut output).unwrap();
+    /// assert_eq!(output, [3]);
+    /// ```
+    pub fn with_dispatch(mut self, dispatch: DispatchKind) -> Self {
+        self.dispatch = dispatch;
+        self
     }
+
+    /// Sets the reporter which is given each step of the program, if it
+    /// traces steps.
+    /// ```
+    /// use std::io::Cursor;
+    /// use bft_types::BfProgram;
+    /// use bft_interp::VirtualMachine;
+    /// use bft_interp::report::{Reporter, Step};
+    ///
+    /// struct Cells(Vec<u32>);
+    ///
+    /// impl Reporter for Cells {
+    ///     fn traces(&self) -> bool {
+    ///         true
+    ///     }
+    ///
+    ///     fn step(&mut self, step: &Step<'_>) {
+    ///         self.0.push(step.cell());
+    ///     }
+    /// }
+    ///
+    /// let program = BfProgram::new("++-".to_string(), "trace.bf").unwrap();
+    /// let mut cells = Cells(Vec::new());
+    /// let mut vm =
+    ///     VirtualMachine::<u8>::new(&program, 1, false).with_reporter(&mut cells);
+    /// vm.interpret(&mut Cursor::new(Vec::new()), &mut Vec::new()).unwrap();
+    /// drop(vm);
+    /// assert_eq!(cells.0, [0, 1, 2]);
+    /// ```
+    pub fn with_reporter(mut self, reporter: &'a mut dyn Reporter) -> Self {
+        self.reporter = Some(reporter);
+        self
+    }
+
+    /// Sets the sink which is given the changes each step makes to the
+    /// Virtual Machine, such as cells changing and the head moving, for
+    /// visualizations. See `events` for more.
+    ///
+    /// Events are only given when the original program is interpreted, with
+    /// `interpret` or `interpret_resumable`, as the nodes of a lowered program
+    /// change many cells at once. Extension instructions only report changes
+    /// to the cell at the head. Without a sink, there is nothing to pay for.
+    pub fn with_event_sink(mut self, sink: &'a mut dyn EventSink) -> Self {
+        self.event_sink = Some(sink);
+        self
+    }
+
+    /// Whether each step is being traced, or its changes given to an event
+    /// sink, in which case the loops specialized for 8 bit cells are not used.
+    fn traces(&self) -> bool {
+        self.event_sink.is_some()
+            || self
+                .reporter
+                .as_ref()
+                .is_some_and(|reporter| reporter.traces())
+    }
+
+    /// Gives the event to the event sink, if there is one which wants it.
+    fn emit(&mut self, event: VmEvent) {
+        if let Some(sink) = self.event_sink.as_deref_mut() {
+            if sink.wants(event.kind()) {
+                sink.event(&event);
+            }
+        }
+    }
+
+    /// Gives the changes made by the instruction at the position to the event
+    /// sink, from the head and the cell at the head before it ran. `entering`
+    /// is set when a `[` has just jumped to its `]`, which then decides
+    /// whether the loop is entered.
+    fn emit_changes(
+        &mut self,
+        position: usize,
+        (head, cell): (usize, T),
+        entering: bool,
+    ) {
+        match self.program.instructions()[position].operation() {
+            Operation::StartLoop => return,
+            Operation::EndLoop => {
+                let repeats = self.program_position != position + 1;
+                if repeats && entering {
+                    let start = self.program_position - 1;
+                    self.emit(VmEvent::LoopEntered { start });
+                } else if !repeats && !entering {
+                    if let Some(start) = self.program.jump_target(position) {
+                        self.emit(VmEvent::LoopLeft { start });
+                    }
+                }
+            }
+            _ => {}
+        }
+        if self.tape_head != head {
+            let to = self.tape_head;
+            self.emit(VmEvent::HeadMoved { from: head, to });
+        }
+        if self.tape[head] != cell {
+            self.emit(VmEvent::CellChanged {
+                index: head,
+                old: cell.to_u32(),
+                new: self.tape[head].to_u32(),
+            });
+        }
+    }
+
+    /// Reports the step about to be run, if steps are being traced.
+    fn trace(&mut self, operation: TracedOp<'_>, source: InstructionInfo) {
+        if let Some(reporter) = self.reporter.as_deref_mut() {
+            if reporter.traces() {
+                reporter.step(&Step {
+                    number: self.steps,
+                    source,
+                    operation,
+                    head: self.tape_head,
+                    cell: self.tape[self.tape_head].to_u32(),
+                    tape_length: self.tape.len(),
+                });
+            }
+        }
+    }
+
+    /// Registers the handler to run whenever the program reaches the extension
+    /// instruction for the given character. The program must have been parsed
+    /// with the character registered in its `ParseOptions`, otherwise the
+    /// character is treated as a comment. Registering a character again
+    /// replaces its handler.
+    /// ```
+    /// use std::io::Cursor;
+    /// use bft_types::BfProgram;
+    /// use bft_types::options::ParseOptions;
+    /// use bft_interp::VirtualMachine;
+    ///
+    /// // A `#` which doubles the value of the current cell.
+    /// let options = ParseOptions::new().extension('#');
+    /// let program =
+    ///     BfProgram::new_with_options("+++#.".to_string(), "double.bf", &options)
+    ///         .unwrap();
+    /// let mut vm = VirtualMachine::<u8>::new(&program, 1, false);
+    /// vm.register_extension('#', |context| {
+    ///     let value = context.cell();
+    ///     context.set_cell(value * 2);
+    ///     Ok(())
+    /// });
+    ///
+    /// let mut input = Cursor::new(Vec::<u8>::new());
+    /// let mut output = Vec::new();
+    /// vm.interpret(&mut input, &mut output).unwrap();
+    /// assert_eq!(output, [6]);
+    /// ```
+    pub fn register_extension<F>(&mut self, name: char, handler: F)
+    where
+        F: FnMut(&mut VmContext<'_, T>) -> Result<(), VirtualMachineError> + 'a,
+    {
+        self.extensions.insert(name, Box::new(handler));
+    }
+
+    /// Gives the program the capability to call host functions with `%`, which
+    /// it does not have by default. See `syscall` for more.
+    pub fn with_syscalls(mut self, enabled: bool) -> Self {
+        self.sandbox.syscalls = if enabled {
+            Syscalls::All
+        } else {
+            Syscalls::None
+        };
+        self
+    }
+
+    /// Registers the host function to run whenever the program reaches a `%`
+    /// with the given number in the cell at the head. Registering a number
+    /// again replaces its function. See `syscall` for more.
+    pub fn register_syscall<F>(&mut self, id: u32, handler: F)
+    where
+        F: FnMut(&mut VmContext<'_, T>) -> Result<(), VirtualMachineError> + 'a,
+    {
+        self.syscalls.insert(id, Box::new(handler));
+    }
+
     /// Interpreter method for the Virtual Machine. This will take and input and
     /// output and will read and write from these. This is where the magic
     /// happens, and results in the full interpretation of a Brainfuck Program.
+    /// A program with no instructions, such as a file of only comments, halts
+    /// successfully straight away.
+    ///
+    /// This blocks on the input and output, running the program with
+    /// `interpret_resumable` and passing each byte between it and the streams.
     ///
     /// ```
     /// use std::io::Cursor;
@@ -97,27 +651,561 @@ where
     /// ```
     pub fn interpret(
         &mut self,
-        mut input: &mut impl Read,
-        mut output: &mut impl Write,
+        input: &mut impl Read,
+        output: &mut impl Write,
     ) -> Result<(), VirtualMachineError> {
+        if self.interpret_bytes(input, output)? {
+            return Ok(());
+        }
+        loop {
+            match self.resume(input, output)? {
+                Event::NeedsInput => self.provide_input(read_byte(input)?),
+                Event::ProducedOutput(byte) => {
+                    output.write_all(&[byte])?;
+                    output.flush()?;
+                }
+                Event::Halted => return Ok(()),
+            }
+        }
+    }
+
+    /// Runs the program until it reads input, writes output or halts, without
+    /// ever blocking, so that hosts such as GUIs and async runtimes can drive
+    /// the Virtual Machine as a state machine. After `Event::NeedsInput`, the
+    /// byte read is given with `provide_input`, and calling this again carries
+    /// on from the `,` which asked for it.
+    ///
+    /// Extension instructions run this way see an empty input, and anything
+    /// they write is thrown away.
+    /// ```
+    /// use bft_types::BfProgram;
+    /// use bft_interp::VirtualMachine;
+    /// use bft_interp::eof::EofBehavior;
+    /// use bft_interp::resume::Event;
+    ///
+    /// let program = BfProgram::new(",[.,]".to_string(), "cat.bf").unwrap();
+    /// let mut vm = VirtualMachine::<u8>::new(&program, 1, false)
+    ///     .with_eof_behavior(EofBehavior::Zero);
+    /// let mut input = b"hi".iter().copied();
+    /// let mut output = Vec::new();
+    /// loop {
+    ///     match vm.interpret_resumable().unwrap() {
+    ///         Event::NeedsInput => vm.provide_input(input.next()),
+    ///         Event::ProducedOutput(byte) => output.push(byte),
+    ///         Event::Halted => break,
+    ///     }
+    /// }
+    /// assert_eq!(output, b"hi");
+    /// ```
+    pub fn interpret_resumable(
+        &mut self,
+    ) -> Result<Event, VirtualMachineError> {
+        self.resume(&mut empty(), &mut sink())
+    }
+
+    /// Gives the byte read by the `,` which `interpret_resumable` is waiting
+    /// at, or None at the end of the input, in which case what happens is up
+    /// to the `EofBehavior`.
+    pub fn provide_input(&mut self, byte: Option<u8>) {
+        self.pending_input = Some(byte);
+    }
+
+    /// Runs the program until it reads input, writes output or halts, giving
+    /// extension instructions the input and output.
+    fn resume(
+        &mut self,
+        mut input: &mut dyn Read,
+        mut output: &mut dyn Write,
+    ) -> Result<Event, VirtualMachineError> {
         let instructions = self.program.instructions();
-        let last_position = instructions.len() - 1;
-        while self.program_position <= last_position {
+        while self.program_position < instructions.len() {
             let instruction = instructions[self.program_position];
-            self.program_position = match instruction.operation() {
+            let operation = instruction.operation();
+            // A `,` carried on from after `Event::NeedsInput` was checked,
+            // and charged its gas, before asking for the input.
+            if operation != Operation::InputByte || self.pending_input.is_none()
+            {
+                self.check_limits(1, instruction, self.program.filename())?;
+            }
+            // The step is only taken once the input has been given.
+            if operation == Operation::InputByte && self.pending_input.is_none()
+            {
+                if !self.input_spent() {
+                    return Ok(Event::NeedsInput);
+                }
+                self.pending_input = Some(None);
+            }
+            if operation == Operation::OutputByte {
+                self.count_output(1, instruction, self.program.filename())?;
+            }
+            self.steps += 1;
+            self.trace(TracedOp::Instruction(operation), instruction);
+            let position = self.program_position;
+            let entering = std::mem::replace(
+                &mut self.entering,
+                operation == Operation::StartLoop,
+            );
+            let before = self
+                .event_sink
+                .is_some()
+                .then(|| (self.tape_head, self.tape[self.tape_head]));
+            self.program_position = match operation {
                 Operation::IncrementByte => self.increment_cell_at_head(),
                 Operation::DecrementByte => self.decrement_cell_at_head(),
                 Operation::IncrementPointer => self.move_right(),
                 Operation::DecrementPointer => self.move_left(),
-                Operation::OutputByte => self.write_out_of_cell(&mut output),
-                Operation::InputByte => self.read_into_cell(&mut input),
+                Operation::OutputByte => {
+                    self.program_position += 1;
+                    let byte = self.tape[self.tape_head].to_u8();
+                    self.emit(VmEvent::Output(byte));
+                    return Ok(Event::ProducedOutput(byte));
+                }
+                Operation::InputByte => {
+                    let byte = self.pending_input.take().flatten();
+                    if let Some(byte) = byte {
+                        self.emit(VmEvent::Input(byte));
+                    }
+                    let filename = self.program.filename();
+                    self.store_input(byte).ok_or_else(|| {
+                        self.input_exhausted(instruction, filename)
+                    })
+                }
                 Operation::StartLoop => self.start_loop(),
-                Operation::EndLoop => self.end_loop(),
+                Operation::EndLoop => self.end_loop().inspect(|&next| {
+                    if next != position + 1 && !entering {
+                        self.count_back_edge(Some(next - 1));
+                    }
+                }),
+                Operation::Extension(name) => self
+                    .run_extension(name, instruction, &mut input, &mut output)
+                    .map(|()| self.program_position + 1),
             }?;
+            if let Some(before) = before {
+                self.emit_changes(position, before, entering);
+            }
+        }
+        self.emit(VmEvent::Halted);
+        Ok(Event::Halted)
+    }
+
+    /// Interprets a program which has been lowered into the intermediate
+    /// representation, and usually optimized, rather than the original
+    /// program. It must have come from the same program as the Virtual
+    /// Machine was created with.
+    ///
+    /// Each node of the intermediate representation counts as a single step
+    /// towards the step limit, so optimized programs take fewer steps. Any
+    /// steps which partial evaluation ran ahead of time are counted before
+    /// the program starts. The nodes are dispatched as chosen with
+    /// `with_dispatch`.
+    /// ```
+    /// use std::io::Cursor;
+    /// use bft_types::BfProgram;
+    /// use bft_interp::VirtualMachine;
+    /// use bft_interp::ir::IrProgram;
+    /// use bft_interp::optimizer::Pipeline;
+    ///
+    /// let program = BfProgram::new("++++[->++<]>.".to_string(), "double.bf").unwrap();
+    /// let mut ir = IrProgram::from_program(&program).unwrap();
+    /// Pipeline::builtin().run(&mut ir);
+    ///
+    /// let mut vm = VirtualMachine::<u8>::new(&program, 2, false);
+    /// let mut input = Cursor::new(Vec::<u8>::new());
+    /// let mut output = Vec::new();
+    /// vm.interpret_ir(&ir, &mut input, &mut output).unwrap();
+    /// assert_eq!(output, [8]);
+    /// assert_eq!(vm.steps(), 4);
+    /// ```
+    pub fn interpret_ir(
+        &mut self,
+        program: &IrProgram,
+        input: &mut impl Read,
+        output: &mut impl Write,
+    ) -> Result<(), VirtualMachineError> {
+        self.count_evaluated_steps(program)?;
+        let (input, output): (&mut dyn Read, &mut dyn Write) = (input, output);
+        match self.dispatch {
+            DispatchKind::Match => {
+                MatchDispatch.run(self, program, input, output)
+            }
+            DispatchKind::Threaded => {
+                ThreadedDispatch.run(self, program, input, output)
+            }
+        }
+    }
+
+    /// Interprets the program with the input and output the Virtual Machine
+    /// was created with by `with_io`, just as `interpret` would. A Virtual
+    /// Machine created without them reads an empty input and throws its
+    /// output away. One spawned for a `CompiledProgram` runs the compiled
+    /// program, just as `run_ir` would.
+    pub fn run(&mut self) -> Result<(), VirtualMachineError> {
+        let (mut input, mut output) = self.take_io();
+        let result = match self.ir {
+            Some(ir) => self.interpret_ir(ir, &mut input, &mut output),
+            None => self.interpret(&mut input, &mut output),
+        };
+        self.io = Some((input, output));
+        result
+    }
+
+    /// Interprets a lowered program with the input and output the Virtual
+    /// Machine was created with by `with_io`, just as `interpret_ir` would.
+    pub fn run_ir(
+        &mut self,
+        program: &IrProgram,
+    ) -> Result<(), VirtualMachineError> {
+        let (mut input, mut output) = self.take_io();
+        let result = self.interpret_ir(program, &mut input, &mut output);
+        self.io = Some((input, output));
+        result
+    }
+
+    /// Takes the input and output given to `with_io`, to be put back once the
+    /// program has been run.
+    fn take_io(&mut self) -> (Box<dyn Read + 'a>, Box<dyn Write + 'a>) {
+        self.io
+            .take()
+            .unwrap_or_else(|| (Box::new(empty()), Box::new(sink())))
+    }
+
+    /// Counts the steps of the program which partial evaluation ran ahead of
+    /// time, failing at its first node if they go over the step limit, as
+    /// running them would have.
+    fn count_evaluated_steps(
+        &mut self,
+        program: &IrProgram,
+    ) -> Result<(), VirtualMachineError> {
+        let evaluated = program.evaluated_steps();
+        let steps = self.steps.saturating_add(evaluated);
+        match (self.sandbox.step_limit, program.nodes().first()) {
+            (Some(limit), Some(node)) if steps > limit => {
+                Err(VirtualMachineError::StepLimitExceeded {
+                    line: node.source().line(),
+                    column: node.source().column(),
+                    filename: program.filename().display().to_string(),
+                    limit,
+                })
+            }
+            _ => {
+                self.steps = steps;
+                Ok(())
+            }
+        }
+    }
+
+    /// Checks the step about to be taken at `source` is within the limits of
+    /// the sandbox, and charges it the gas it costs. Fails if the step limit
+    /// has been reached, or the gas or the time has run out.
+    fn check_limits(
+        &mut self,
+        cost: u64,
+        source: InstructionInfo,
+        filename: &Path,
+    ) -> Result<(), VirtualMachineError> {
+        if self.sandbox.step_limit == Some(self.steps) {
+            return Err(VirtualMachineError::StepLimitExceeded {
+                line: source.line(),
+                column: source.column(),
+                filename: filename.display().to_string(),
+                limit: self.steps,
+            });
+        }
+        if let Some(limit) = self.sandbox.gas_limit {
+            if limit.saturating_sub(self.gas_used) < cost {
+                return Err(VirtualMachineError::GasExhausted {
+                    line: source.line(),
+                    column: source.column(),
+                    filename: filename.display().to_string(),
+                    limit,
+                });
+            }
+        }
+        self.gas_used += cost;
+        if let Some(limit) = self.sandbox.time_limit {
+            let started = *self.started.get_or_insert_with(Instant::now);
+            // Reading the clock takes longer than most steps, so it is only
+            // read every so often.
+            if self.steps.is_multiple_of(TIME_CHECK_INTERVAL)
+                && started.elapsed() >= limit
+            {
+                return Err(VirtualMachineError::TimeLimitExceeded {
+                    line: source.line(),
+                    column: source.column(),
+                    filename: filename.display().to_string(),
+                    limit_ms: limit.as_millis() as u64,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Counts a step of the program, failing if it would go over the limits
+    /// of the sandbox, and traces it.
+    fn count_step(
+        &mut self,
+        node: &IrNode,
+        program: &IrProgram,
+    ) -> Result<(), VirtualMachineError> {
+        let source = node.source();
+        self.check_limits(gas_cost(node.op()), source, program.filename())?;
+        // The bytes written ahead of time are counted as they are written, by
+        // `write_bytes`.
+        if let IrOp::Output = node.op() {
+            self.count_output(1, source, program.filename())?;
+        }
+        self.steps += 1;
+        self.trace(TracedOp::Node(node.op()), source);
+        Ok(())
+    }
+
+    /// Counts a jump from the end of a loop back to its start, for the loop
+    /// whose opening bracket is at the position, if it is known.
+    fn count_back_edge(&mut self, open: Option<usize>) {
+        if let Some(count) =
+            open.and_then(|open| self.loop_counts.get_mut(open))
+        {
+            *count += 1;
+        }
+    }
+
+    /// Finds the opening bracket of the program each `LoopEnd` of a lowered
+    /// program jumps back to, so that its back edges can be counted. Nodes
+    /// which are not the end of a loop, and loops whose start cannot be found
+    /// in the program, have none.
+    fn loop_openings(
+        &self,
+        program: &IrProgram,
+        jumps: &[usize],
+    ) -> Vec<Option<usize>> {
+        let instructions = self.program.instructions();
+        let nodes = program.nodes();
+        nodes
+            .iter()
+            .enumerate()
+            .map(|(position, node)| {
+                if !matches!(node.op(), IrOp::LoopEnd) {
+                    return None;
+                }
+                let start = nodes[jumps[position]].source();
+                let key = (start.line(), start.column());
+                instructions
+                    .binary_search_by_key(&key, |instruction| {
+                        (instruction.line(), instruction.column())
+                    })
+                    .ok()
+                    .filter(|&open| {
+                        instructions[open].operation() == Operation::StartLoop
+                    })
+            })
+            .collect()
+    }
+
+    /// Counts the bytes the instruction is about to write, failing if they
+    /// would go over the output limit.
+    fn count_output(
+        &mut self,
+        len: u64,
+        source: InstructionInfo,
+        filename: &Path,
+    ) -> Result<(), VirtualMachineError> {
+        if let Some(limit) = self.sandbox.output_limit {
+            if limit - self.output_written < len {
+                return Err(VirtualMachineError::OutputLimitExceeded {
+                    line: source.line(),
+                    column: source.column(),
+                    filename: filename.display().to_string(),
+                    limit,
+                });
+            }
         }
+        self.output_written += len;
+        // The step writing the output has not been counted yet.
+        self.last_io_step = Some(self.steps + 1);
         Ok(())
     }
 
+    /// Writes out bytes which were worked out ahead of time. If they would go
+    /// over the output limit, the ones which fit are written before failing,
+    /// as they would have been by the instructions which wrote them.
+    fn write_bytes<W>(
+        &mut self,
+        bytes: &[u8],
+        source: InstructionInfo,
+        filename: &Path,
+        output: &mut W,
+    ) -> Result<(), VirtualMachineError>
+    where
+        W: Write + ?Sized,
+    {
+        let len = bytes.len() as u64;
+        let room = self
+            .sandbox
+            .output_limit
+            .map_or(len, |limit| len.min(limit - self.output_written));
+        output.write_all(&bytes[..room as usize])?;
+        output.flush()?;
+        self.output_written += room;
+        if room < len {
+            // There is no room left, so this fails.
+            return self.count_output(len - room, source, filename);
+        }
+        // The step writing the output has been counted already.
+        self.last_io_step = Some(self.steps);
+        Ok(())
+    }
+
+    /// Adds the value of the cell at the head times each factor to the cell at
+    /// each offset, and then clears it, if it is not already zero.
+    fn copy_loop(
+        &mut self,
+        targets: &[(isize, i32)],
+        source: InstructionInfo,
+        program: &IrProgram,
+    ) -> Result<(), VirtualMachineError> {
+        let value = self.tape[self.tape_head];
+        if value != T::default() {
+            for (offset, factor) in targets {
+                let target = self.offset_head(*offset, source, program)?;
+                self.tape[target] =
+                    self.tape[target].add_product(value, *factor);
+            }
+            self.tape[self.tape_head] = T::default();
+        }
+        Ok(())
+    }
+
+    /// Makes the changes of a multiply loop, if the cell at the head is not
+    /// zero.
+    fn mul_add(
+        &mut self,
+        mul: &MulLoop,
+        source: InstructionInfo,
+        program: &IrProgram,
+    ) -> Result<(), VirtualMachineError> {
+        if self.tape[self.tape_head] != T::default() {
+            for offset in mul.offsets() {
+                self.offset_head(offset, source, program)?;
+            }
+            mul.apply(&mut self.tape, self.tape_head);
+        }
+        Ok(())
+    }
+
+    /// Sets the cells from the head onwards to the values.
+    fn load_tape(
+        &mut self,
+        values: &[u32],
+        source: InstructionInfo,
+        program: &IrProgram,
+    ) -> Result<(), VirtualMachineError> {
+        let cells = self.range(0, values.len(), source, program)?;
+        for (cell, value) in self.tape[cells].iter_mut().zip(values) {
+            *cell = T::from_u32(*value);
+        }
+        Ok(())
+    }
+
+    /// Finds the first zero cell at or to the right of the head, moving by
+    /// the step, growing the tape to reach one if it is extensible.
+    fn scan_right(
+        &mut self,
+        step: usize,
+        source: InstructionInfo,
+        program: &IrProgram,
+    ) -> Result<usize, VirtualMachineError> {
+        match T::find_zero(&self.tape[self.tape_head..], step) {
+            Some(offset) => Ok(self.tape_head + offset),
+            None => {
+                // Every cell in reach is non-zero, so the head runs off the
+                // end of the tape, onto a new zeroed cell if it can grow.
+                let remaining = self.tape.len() - self.tape_head;
+                let offset = remaining.div_ceil(step) * step;
+                self.offset_head(offset as isize, source, program)
+            }
+        }
+    }
+
+    /// Finds the first zero cell at or to the left of the head, moving by the
+    /// step.
+    fn scan_left(
+        &mut self,
+        step: usize,
+        source: InstructionInfo,
+        program: &IrProgram,
+    ) -> Result<usize, VirtualMachineError> {
+        match T::rfind_zero(&self.tape[..=self.tape_head], step) {
+            Some(position) => Ok(position),
+            None => Err(VirtualMachineError::InvalidHeadPosition {
+                line: source.line(),
+                column: source.column(),
+                operation: source.operation(),
+                filename: program.filename().display().to_string(),
+                position: self.tape_head,
+                tape_length: self.tape.len(),
+            }),
+        }
+    }
+
+    /// Finds the position on the tape at the given offset from the head,
+    /// growing the tape to reach it if it is extensible.
+    fn offset_head(
+        &mut self,
+        offset: isize,
+        source: InstructionInfo,
+        program: &IrProgram,
+    ) -> Result<usize, VirtualMachineError> {
+        let invalid = |position: usize, tape_length: usize| {
+            VirtualMachineError::InvalidHeadPosition {
+                line: source.line(),
+                column: source.column(),
+                operation: source.operation(),
+                filename: program.filename().display().to_string(),
+                position,
+                tape_length,
+            }
+        };
+        let target = self
+            .tape_head
+            .checked_add_signed(offset)
+            .ok_or_else(|| invalid(self.tape_head, self.tape.len()))?;
+        if target >= self.tape.len() {
+            if !self.sandbox.tape_growth {
+                return Err(invalid(target, self.tape.len()));
+            }
+            if !tape::grow(
+                &mut self.tape,
+                self.allocator.as_deref_mut(),
+                target + 1,
+                self.sandbox.memory_limit,
+            ) {
+                return Err(VirtualMachineError::TapeGrowthRefused {
+                    line: source.line(),
+                    column: source.column(),
+                    filename: program.filename().display().to_string(),
+                    cells: target + 1,
+                });
+            }
+        }
+        Ok(target)
+    }
+
+    /// Finds the positions on the tape of `len` cells from the given offset
+    /// from the head, growing the tape to reach them if it is extensible.
+    fn range(
+        &mut self,
+        offset: isize,
+        len: usize,
+        source: InstructionInfo,
+        program: &IrProgram,
+    ) -> Result<Range<usize>, VirtualMachineError> {
+        let start = self.offset_head(offset, source, program)?;
+        if let Some(last) = len.checked_sub(1) {
+            self.offset_head(offset + last as isize, source, program)?;
+        }
+        Ok(start..start + len)
+    }
+
     /// Provides the value of the tape at the head position (The data pointer).
     /// ```
     /// use std::io::Cursor;
@@ -136,42 +1224,345 @@ where
     /// // will be zero.
     /// assert_eq!(vm.value_at_tape_head(), 0);
     /// ```
-    pub fn value_at_tape_head(&self) -> T {
-        self.tape[self.tape_head]
+    pub fn value_at_tape_head(&self) -> T {
+        self.tape[self.tape_head]
+    }
+
+    /// Provides the location of the tape head (data pointer)
+    /// ```
+    /// use std::io::Cursor;
+    /// use bft_types::BfProgram;
+    /// use bft_interp::VirtualMachine;
+    ///
+    /// // Create the program.
+    /// let filename = "test.bf";
+    /// let contents = "++><[],.".to_string();
+    /// let new_program: BfProgram = BfProgram::new(contents, filename).unwrap();
+    ///
+    /// // Create the VM
+    /// let mut vm = VirtualMachine::<u8>::new(&new_program, 1, false);
+    ///
+    /// // As the VM has not been modified yet, the tape head will be zero.
+    /// assert_eq!(vm.tape_head(), 0);
+    /// ```
+    pub fn tape_head(&self) -> usize {
+        self.tape_head
+    }
+
+    /// Provides the number of instructions the Virtual Machine has executed.
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /// The position in the program of the next instruction to run, which is
+    /// the number of instructions once the program has halted.
+    pub fn program_position(&self) -> usize {
+        self.program_position
+    }
+
+    /// Sets or clears the step limit, so that a program can be run a few
+    /// steps at a time, such as by a debugger. Running up to the limit stops
+    /// the program with `StepLimitExceeded` before the step it would have
+    /// taken next, and running it again with a higher limit carries on from
+    /// there.
+    /// ```
+    /// use std::io::Cursor;
+    /// use bft_types::BfProgram;
+    /// use bft_interp::VirtualMachine;
+    ///
+    /// let program = BfProgram::new("+++.".to_string(), "three.bf").unwrap();
+    /// let mut vm = VirtualMachine::<u8>::new(&program, 1, false);
+    /// let mut output = Vec::new();
+    /// vm.set_step_limit(Some(2));
+    /// assert!(vm.interpret(&mut Cursor::new(Vec::new()), &mut output).is_err());
+    /// assert_eq!((vm.steps(), vm.program_position()), (2, 2));
+    /// vm.set_step_limit(None);
+    /// vm.interpret(&mut Cursor::new(Vec::new()), &mut output).unwrap();
+    /// assert_eq!(output, [3]);
+    /// ```
+    pub fn set_step_limit(&mut self, limit: Option<u64>) {
+        self.sandbox.step_limit = limit;
+    }
+
+    /// The step limit, if there is one.
+    pub fn step_limit(&self) -> Option<u64> {
+        self.sandbox.step_limit
+    }
+
+    /// Provides the gas the program has used, towards the gas limit of the
+    /// sandbox.
+    pub fn gas_used(&self) -> u64 {
+        self.gas_used
+    }
+
+    /// Provides the sandbox the Virtual Machine runs programs in.
+    pub fn sandbox(&self) -> &Sandbox {
+        &self.sandbox
+    }
+
+    /// Provides the number of bytes of output the program has written.
+    pub fn output_written(&self) -> u64 {
+        self.output_written
+    }
+
+    /// Sums up the run so far: the steps it has taken, the gas it has used,
+    /// the bytes it has read and written, and how many times each loop of the
+    /// program has gone round. See `summary` for more.
+    /// ```
+    /// use bft_types::BfProgram;
+    /// use bft_interp::VirtualMachine;
+    ///
+    /// let program = BfProgram::new("+++[-]".to_string(), "clear.bf").unwrap();
+    /// let mut vm = VirtualMachine::<u8>::new(&program, 1, false);
+    /// vm.run().unwrap();
+    /// let summary = vm.summary();
+    /// assert_eq!(summary.steps(), 11);
+    /// assert_eq!(summary.loop_counts()[0].iterations(), 2);
+    /// ```
+    pub fn summary(&self) -> RunSummary {
+        RunSummary::new(
+            self.steps,
+            self.gas_used,
+            self.output_written,
+            self.input_read,
+            self.last_io_step,
+            self.program,
+            &self.loop_counts,
+        )
+    }
+
+    /// Provides the cells of the tape, from the start of the tape.
+    /// ```
+    /// use std::io::Cursor;
+    /// use bft_types::BfProgram;
+    /// use bft_interp::VirtualMachine;
+    ///
+    /// let program = BfProgram::new("+>++".to_string(), "test.bf").unwrap();
+    /// let mut vm = VirtualMachine::<u8>::new(&program, 3, false);
+    /// let mut input = Cursor::new(Vec::<u8>::new());
+    /// let mut output = Cursor::new(Vec::<u8>::new());
+    /// vm.interpret(&mut input, &mut output).unwrap();
+    /// assert_eq!(vm.tape(), &[1, 2, 0]);
+    /// ```
+    pub fn tape(&self) -> &[T] {
+        &self.tape
+    }
+
+    /// The number of bytes allocated for the tape. The tape never shrinks, so
+    /// this is also the most it has taken up.
+    /// ```
+    /// use bft_types::BfProgram;
+    /// use bft_interp::VirtualMachine;
+    ///
+    /// let program = BfProgram::new("+".to_string(), "test.bf").unwrap();
+    /// let vm = VirtualMachine::<u16>::new(&program, 3, false);
+    /// assert!(vm.tape_allocation() >= 6);
+    /// ```
+    pub fn tape_allocation(&self) -> usize {
+        self.tape.capacity() * std::mem::size_of::<T>()
+    }
+
+    /// Consumes the Virtual Machine, returning its tape along with the position
+    /// of its head, so that they can be handed on to another Virtual Machine
+    /// with `from_tape`.
+    /// ```
+    /// use std::io::Cursor;
+    /// use bft_types::BfProgram;
+    /// use bft_interp::VirtualMachine;
+    ///
+    /// let program = BfProgram::new("+>++".to_string(), "test.bf").unwrap();
+    /// let mut vm = VirtualMachine::<u8>::new(&program, 3, false);
+    /// let mut input = Cursor::new(Vec::<u8>::new());
+    /// let mut output = Cursor::new(Vec::<u8>::new());
+    /// vm.interpret(&mut input, &mut output).unwrap();
+    /// assert_eq!(vm.into_tape(), (vec![1, 2, 0], 1));
+    /// ```
+    pub fn into_tape(mut self) -> (Vec<T>, usize) {
+        // Taken, as the allocator is only given back the tapes which are not.
+        let tape = std::mem::take(&mut self.tape);
+        self.allocator = None;
+        (tape, self.tape_head)
+    }
+
+    /// Puts the Virtual Machine back as it was before the program was run, so
+    /// that it can be run again without creating a new one. Every cell is set
+    /// back to its default value, and the head, the position in the program
+    /// and the number of steps taken go back to zero. The tape keeps its
+    /// length and its allocation, along with everything the Virtual Machine
+    /// was configured with.
+    /// ```
+    /// use std::io::Cursor;
+    /// use bft_types::BfProgram;
+    /// use bft_interp::VirtualMachine;
+    ///
+    /// let program = BfProgram::new(",+.".to_string(), "next.bf").unwrap();
+    /// let mut vm = VirtualMachine::<u8>::new(&program, 1, false);
+    /// let mut output = Vec::new();
+    /// for byte in [b'a', b'x'] {
+    ///     vm.reset();
+    ///     vm.interpret(&mut Cursor::new(vec![byte]), &mut output).unwrap();
+    /// }
+    /// assert_eq!(output, b"by");
+    /// ```
+    pub fn reset(&mut self) {
+        self.tape.fill(Default::default());
+        self.tape_head = 0;
+        self.program_position = 0;
+        self.steps = 0;
+        self.gas_used = 0;
+        self.started = None;
+        self.output_written = 0;
+        self.input_read = 0;
+        self.loop_counts.fill(0);
+        self.last_io_step = None;
+        self.entering = false;
+        self.pending_input = None;
+    }
+
+    /// Resets the Virtual Machine just as `reset` would, then starts the tape
+    /// with the given cells. If there are more of them than there are cells on
+    /// the tape, the tape grows to fit them.
+    ///
+    /// # Errors
+    ///
+    /// Fails with `TapeGrowthRefused`, at the first instruction of the
+    /// program, if the tape needs to grow but its `TapeAllocator` refuses, or
+    /// it would go over the memory limit of the sandbox.
+    /// ```
+    /// use bft_types::BfProgram;
+    /// use bft_interp::VirtualMachine;
+    ///
+    /// let program = BfProgram::new("[->+<]".to_string(), "move.bf").unwrap();
+    /// let mut vm = VirtualMachine::<u8>::new(&program, 2, false);
+    /// vm.reset_with_tape(&[3]).unwrap();
+    /// vm.run().unwrap();
+    /// assert_eq!(vm.tape(), [0, 3]);
+    /// ```
+    pub fn reset_with_tape(
+        &mut self,
+        cells: &[T],
+    ) -> Result<(), VirtualMachineError> {
+        self.reset();
+        if cells.len() > self.tape.len() {
+            let allocator = self.allocator.as_deref_mut();
+            let limit = self.sandbox.memory_limit;
+            if !tape::grow(&mut self.tape, allocator, cells.len(), limit) {
+                let (line, column) = self
+                    .program
+                    .instructions()
+                    .first()
+                    .map_or((1, 1), |first| (first.line(), first.column()));
+                return Err(VirtualMachineError::TapeGrowthRefused {
+                    line,
+                    column,
+                    filename: self.program.filename().display().to_string(),
+                    cells: cells.len(),
+                });
+            }
+        }
+        self.tape[..cells.len()].copy_from_slice(cells);
+        Ok(())
+    }
+
+    /// Resets the Virtual Machine with the given cells, just as
+    /// `reset_with_tape` would, then puts it part way through a run: the head
+    /// is at `head`, the next instruction to run is the one at `position` and
+    /// `steps` steps have been taken. Running the program carries on from
+    /// there, so a run can be picked up from a recording of it.
+    ///
+    /// # Errors
+    ///
+    /// Fails as `reset_with_tape` does if the tape cannot grow to fit the
+    /// cells.
+    /// ```
+    /// use std::io::Cursor;
+    /// use bft_types::BfProgram;
+    /// use bft_interp::VirtualMachine;
+    ///
+    /// let program = BfProgram::new("+>+.".to_string(), "test.bf").unwrap();
+    /// let mut vm = VirtualMachine::<u8>::new(&program, 2, false);
+    /// vm.restore(&[1, 6], 1, 2, 2).unwrap();
+    /// let mut output = Vec::new();
+    /// vm.interpret(&mut Cursor::new(Vec::new()), &mut output).unwrap();
+    /// assert_eq!(output, [7]);
+    /// assert_eq!(vm.steps(), 4);
+    /// ```
+    pub fn restore(
+        &mut self,
+        cells: &[T],
+        head: usize,
+        position: usize,
+        steps: u64,
+    ) -> Result<(), VirtualMachineError> {
+        self.reset_with_tape(cells)?;
+        self.tape_head = head;
+        self.program_position = position;
+        self.steps = steps;
+        Ok(())
     }
 
-    /// Provides the location of the tape head (data pointer)
+    /// Replaces the program, leaving the tape and its head just as the last
+    /// program left them, so that programs can be run one after another on
+    /// the same memory, as a REPL would with each line it is given. The new
+    /// program starts from its first instruction, with no steps taken. A
+    /// Virtual Machine spawned for a `CompiledProgram` stops running the
+    /// compiled program, and interprets the new one instead.
+    ///
+    /// The brackets of the new program are jumped between using its own jump
+    /// table, which was validated when the program was created, so a program
+    /// with unmatched brackets can only be swapped in if it was created with
+    /// lazy validation, and fails only if it reaches one.
     /// ```
     /// use std::io::Cursor;
     /// use bft_types::BfProgram;
     /// use bft_interp::VirtualMachine;
     ///
-    /// // Create the program.
-    /// let filename = "test.bf";
-    /// let contents = "++><[],.".to_string();
-    /// let new_program: BfProgram = BfProgram::new(contents, filename).unwrap();
-    ///
-    /// // Create the VM
-    /// let mut vm = VirtualMachine::<u8>::new(&new_program, 1, false);
-    ///
-    /// // As the VM has not been modified yet, the tape head will be zero.
-    /// assert_eq!(vm.tape_head(), 0);
+    /// let first = BfProgram::new("+++>++".to_string(), "first.bf").unwrap();
+    /// let second = BfProgram::new("[-<+>]<.".to_string(), "second.bf").unwrap();
+    /// let mut vm = VirtualMachine::<u8>::new(&first, 2, false);
+    /// let mut output = Vec::new();
+    /// vm.interpret(&mut Cursor::new(Vec::new()), &mut output).unwrap();
+    /// vm.swap_program(&second);
+    /// vm.interpret(&mut Cursor::new(Vec::new()), &mut output).unwrap();
+    /// assert_eq!(output, [5]);
     /// ```
-    pub fn tape_head(&self) -> usize {
-        self.tape_head
+    pub fn swap_program(&mut self, program: &'a BfProgram) {
+        self.program = program;
+        self.ir = None;
+        self.program_position = 0;
+        self.steps = 0;
+        self.gas_used = 0;
+        self.started = None;
+        self.output_written = 0;
+        self.input_read = 0;
+        self.loop_counts.clear();
+        self.loop_counts.resize(program.instructions().len(), 0);
+        self.last_io_step = None;
+        self.entering = false;
+        self.pending_input = None;
     }
 
     /// Checks that the head of the tape has not moved into an invalid location.
     /// If it has, then it will throw a `VirtualMachineError` back out.
     fn check_head_location(&mut self) -> Result<usize, VirtualMachineError> {
-        // This needs the `- 1` due to the fact that the tape_head is an integer
-        // and the tape itself is being indexed from 0.
         // This should return an error if the head of the tape has moved to an
         // invalid location, and the tape is not allowed to grow.
-        if self.tape_head > self.tape.len() - 1 {
+        if self.tape_head >= self.tape.len() {
             // If the tape is growable, increase the length of the tape
-            if self.growable {
-                self.tape.push(Default::default());
+            if self.sandbox.tape_growth {
+                let cells = self.tape_head + 1;
+                let allocator = self.allocator.as_deref_mut();
+                let limit = self.sandbox.memory_limit;
+                if !tape::grow(&mut self.tape, allocator, cells, limit) {
+                    let instruction =
+                        self.program.instructions()[self.program_position];
+                    return Err(VirtualMachineError::TapeGrowthRefused {
+                        line: instruction.line(),
+                        column: instruction.column(),
+                        filename: self.program.filename().display().to_string(),
+                        cells,
+                    });
+                }
             } else {
                 return Err(VirtualMachineError::InvalidHeadPosition {
                     line: self.program.instructions()[self.program_position]
@@ -258,18 +1649,91 @@ where
         &mut self,
         mut reader: impl Read,
     ) -> Result<usize, VirtualMachineError> {
-        let mut buffer: [u8; 1] = [0; 1];
-        match reader.read_exact(&mut buffer) {
-            Ok(()) => {
-                println!(
-                    "self.tape_head = {}, tape_length = {}",
-                    self.tape_head,
-                    self.tape.len()
-                );
-                self.tape[self.tape_head] = T::from_u8(buffer[0]);
-                Ok(self.program_position + 1)
+        let byte = self.next_input(&mut reader)?;
+        self.store_input(byte).ok_or_else(|| {
+            // The same error as `read_exact` gives, as there is no
+            // instruction to say the program was at.
+            VirtualMachineError::IOError(std::io::Error::new(
+                ErrorKind::UnexpectedEof,
+                "failed to fill whole buffer",
+            ))
+        })
+    }
+
+    /// Reads into the cell at the head of the tape for the input operation at
+    /// `source`, failing with `InputExhausted` if the program may not read
+    /// past the end of its input.
+    fn read_input(
+        &mut self,
+        reader: &mut dyn Read,
+        source: InstructionInfo,
+        filename: &Path,
+    ) -> Result<(), VirtualMachineError> {
+        let byte = self.next_input(reader)?;
+        self.store_input(byte)
+            .map(|_| ())
+            .ok_or_else(|| self.input_exhausted(source, filename))
+    }
+
+    /// Reads the next byte of input, or None at the end of the input or once
+    /// the input limit has been read, in which case nothing more is read.
+    fn next_input<R>(
+        &mut self,
+        reader: &mut R,
+    ) -> Result<Option<u8>, VirtualMachineError>
+    where
+        R: Read + ?Sized,
+    {
+        if self.input_spent() {
+            return Ok(None);
+        }
+        read_byte(reader)
+    }
+
+    /// Whether the program has read as much input as it may.
+    fn input_spent(&self) -> bool {
+        self.sandbox.input_limit == Some(self.input_read)
+    }
+
+    /// The error for reading past the end of the input at `source`.
+    fn input_exhausted(
+        &self,
+        source: InstructionInfo,
+        filename: &Path,
+    ) -> VirtualMachineError {
+        VirtualMachineError::InputExhausted {
+            line: source.line(),
+            column: source.column(),
+            filename: filename.display().to_string(),
+            read: self.input_read,
+        }
+    }
+
+    /// Stores the byte read into the cell at the head of the tape, or does
+    /// what the `EofBehavior` says to at the end of the input. Returns the
+    /// position of the next instruction, or None if the end of the input is
+    /// an error.
+    fn store_input(&mut self, byte: Option<u8>) -> Option<usize> {
+        self.last_io_step = Some(self.steps);
+        match byte {
+            Some(byte) => {
+                self.input_read += 1;
+                self.tape[self.tape_head] = T::from_u8(byte);
+                Some(self.program_position + 1)
+            }
+            None => {
+                match self.eof_behavior {
+                    EofBehavior::Error => return None,
+                    EofBehavior::Zero => {
+                        self.tape[self.tape_head] = T::from_u8(0);
+                    }
+                    EofBehavior::Unchanged => {}
+                    EofBehavior::MaxValue => {
+                        self.tape[self.tape_head] = T::from_u8(0).decrement();
+                    }
+                }
+                Some(self.program_position + 1)
             }
-            Err(e) => Err(VirtualMachineError::IOError(e)),
         }
     }
 
@@ -277,10 +1741,13 @@ where
     /// VirtualMachineError if there is a failure to write.
     /// Will return the location of the next position within the program to take
     /// if successful.
-    pub fn write_out_of_cell(
+    pub fn write_out_of_cell<W>(
         &mut self,
-        writer: &mut impl Write,
-    ) -> Result<usize, VirtualMachineError> {
+        writer: &mut W,
+    ) -> Result<usize, VirtualMachineError>
+    where
+        W: Write + ?Sized,
+    {
         let mut buffer: [u8; 1] = [0; 1];
         buffer[0] = self.tape[self.tape_head].to_u8();
 
@@ -290,6 +1757,70 @@ where
         Ok(self.program_position + 1)
     }
 
+    /// Runs the handler registered for the extension instruction, or for a
+    /// `%` without one, the host function selected by the cell at the head.
+    fn run_extension(
+        &mut self,
+        name: char,
+        instruction: InstructionInfo,
+        input: &mut dyn Read,
+        output: &mut dyn Write,
+    ) -> Result<(), VirtualMachineError> {
+        let handler = match self.extensions.get_mut(&name) {
+            Some(handler) => handler,
+            None if name == syscall::SYSCALL => {
+                let id = self.tape[self.tape_head].to_u32();
+                if !self.sandbox.syscalls.enabled() {
+                    return Err(VirtualMachineError::SyscallsDisabled {
+                        line: instruction.line(),
+                        column: instruction.column(),
+                        filename: self.program.filename().display().to_string(),
+                    });
+                }
+                if !self.sandbox.syscalls.allows(id) {
+                    return Err(VirtualMachineError::SyscallDenied {
+                        id,
+                        line: instruction.line(),
+                        column: instruction.column(),
+                        filename: self.program.filename().display().to_string(),
+                    });
+                }
+                self.syscalls.get_mut(&id).ok_or_else(|| {
+                    VirtualMachineError::UnknownSyscall {
+                        id,
+                        line: instruction.line(),
+                        column: instruction.column(),
+                        filename: self.program.filename().display().to_string(),
+                    }
+                })?
+            }
+            None => {
+                return Err(VirtualMachineError::UnknownExtension {
+                    name,
+                    line: instruction.line(),
+                    column: instruction.column(),
+                    filename: self.program.filename().display().to_string(),
+                })
+            }
+        };
+        let mut context = VmContext {
+            tape: &mut self.tape,
+            tape_head: &mut self.tape_head,
+            growable: self.sandbox.tape_growth,
+            memory_limit: self.sandbox.memory_limit,
+            allocator: self
+                .allocator
+                .as_deref_mut()
+                .map(|allocator| allocator as &mut dyn TapeAllocator<T>),
+            input,
+            output,
+            instruction,
+            filename: self.program.filename(),
+            steps: self.steps,
+        };
+        handler(&mut context)
+    }
+
     /// Moves the head of the tape to the right. Will return the location of the
     /// next position within the program to take if successful.
     /// ```
@@ -362,7 +1893,7 @@ where
     pub fn move_left(&mut self) -> Result<usize, VirtualMachineError> {
         self.check_head_location()?;
         if self.tape_head == 0 {
-            return Err(VirtualMachineError::InvalidHeadPosition {
+            Err(VirtualMachineError::InvalidHeadPosition {
                 line: self.program.instructions()[self.program_position].line(),
                 column: self.program.instructions()[self.program_position]
                     .column(),
@@ -371,7 +1902,7 @@ where
                 filename: self.program.filename().display().to_string(),
                 position: self.tape_head,
                 tape_length: self.tape.len(),
-            });
+            })
         } else {
             self.tape_head -= 1;
             self.check_head_location()?;
@@ -395,43 +1926,84 @@ where
     /// assert_eq!(vm.start_loop().unwrap(), 5);
     /// ```
     pub fn start_loop(&mut self) -> Result<usize, VirtualMachineError> {
-        if self
-            .program
-            .bracket_matching_positions()
-            .contains_key(&self.program_position)
-        {
-            Ok(self.program.bracket_matching_positions()
-                [&self.program_position])
-        } else {
-            Err(VirtualMachineError::BracketFailure)
-        }
+        self.loop_target()
     }
 
     /// If the value of the cell at the head of the tape is non-zero, then this
     /// function will find the instruction after the corresponding opening
     /// bracket.
     pub fn end_loop(&mut self) -> Result<usize, VirtualMachineError> {
-        let zero_value = T::from_u8(0u8);
-        if self.value_at_tape_head() != zero_value {
-            for (key, value) in self.program.bracket_matching_positions().iter()
-            {
-                if *value == self.program_position {
-                    return Ok(*key + 1);
-                }
-            }
+        // A lazily validated program may reach an unmatched closing bracket
+        // even when the loop would be left, which is still an error.
+        let open = self.loop_target()?;
+        if self.value_at_tape_head() != T::from_u8(0u8) {
+            Ok(open + 1)
+        } else {
+            Ok(self.program_position + 1)
         }
-        Ok(self.program_position + 1)
+    }
+
+    /// Finds the position of the bracket matching the bracket at the current
+    /// position in the program. Only the unmatched brackets of a lazily
+    /// validated program have none, as every bracket of a strictly validated
+    /// program is paired up when it is created.
+    fn loop_target(&self) -> Result<usize, VirtualMachineError> {
+        let position = self.program_position;
+        let target = self.program.jump_table().target(position);
+        if target != position {
+            return Ok(target);
+        }
+        debug_assert_ne!(
+            self.program.bracket_validation(),
+            BracketValidation::Strict,
+            "a strictly validated program has an unmatched bracket"
+        );
+        let instruction = self.program.instructions()[position];
+        Err(VirtualMachineError::UnmatchedBracket {
+            bracket: instruction.operation().to_char(),
+            line: instruction.line(),
+            column: instruction.column(),
+        })
+    }
+}
+
+/// Reads the next byte of input, or None at the end of the input.
+pub(crate) fn read_byte<R>(
+    reader: &mut R,
+) -> Result<Option<u8>, VirtualMachineError>
+where
+    R: Read + ?Sized,
+{
+    let mut buffer = [0; 1];
+    match reader.read_exact(&mut buffer) {
+        Ok(()) => Ok(Some(buffer[0])),
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => Ok(None),
+        Err(e) => Err(VirtualMachineError::IOError(e)),
     }
 }
 
 #[cfg(test)]
 mod tests {
     use bft_types::ops::Operation;
+    use bft_types::options::{BracketValidation, ParseOptions};
+    use bft_types::vm_error::VirtualMachineError;
     use bft_types::BfProgram;
 
-    use crate::VirtualMachine;
-
-    use std::io::Cursor;
+    use crate::dispatch::DispatchKind;
+    use crate::eof::EofBehavior;
+    use crate::events::{EventKind, VmEvent};
+    use crate::ir::{IrOp, IrProgram};
+    use crate::optimizer::{IrPass, Pipeline};
+    use crate::partial::PartialEvaluation;
+    use crate::report::{Reporter, Step};
+    use crate::resume::Event;
+    use crate::sandbox::{Sandbox, Syscalls};
+    use crate::syscall::SYSCALL;
+    use crate::tape::TapeAllocator;
+    use crate::{CellKind, VirtualMachine};
+
+    use std::io::{Cursor, Read};
+    use std::time::Duration;
 
     /// A function to mock a program with instructions for associated tests.
     fn mock_working_program() -> BfProgram {
@@ -506,6 +2078,19 @@ mod tests {
         assert_eq!(instructions[7].column(), 27);
     }
 
+    /// Test that multi-byte characters in comments do not throw off the
+    /// columns of the instructions which follow them.
+    #[test]
+    fn test_columns_after_unicode_comment() {
+        let program =
+            BfProgram::new(String::from("é+\n£€.\n"), "test.bf").unwrap();
+        assert_eq!(program.instructions()[0].line(), 1);
+        assert_eq!(program.instructions()[0].column(), 3);
+        assert_eq!(program.instructions()[1].line(), 2);
+        assert_eq!(program.instructions()[1].column(), 6);
+        assert!(program.instruction_at(2, 6).is_some());
+    }
+
     /// A test which mocks the failure of the program to be created if the
     /// brackets are not balanced. In this case, this is due to there being
     /// too few closing brackets.
@@ -533,7 +2118,7 @@ mod tests {
     fn test_bracket_matcher() {
         let good_program = mock_working_program();
 
-        assert!(good_program.bracket_check().is_ok());
+        assert!(good_program.checked_jump_table().is_ok());
     }
 
     /// A test to check that the program head can move backwards, it should
@@ -601,6 +2186,27 @@ mod tests {
         assert_eq!(vm.value_at_tape_head(), 1u8);
     }
 
+    /// A test to check each of the ways of handling the end of the input
+    #[test]
+    fn test_read_eof_behavior() {
+        let good_program = mock_working_program();
+        let cases = [
+            (EofBehavior::Zero, 0u16),
+            (EofBehavior::Unchanged, 7u16),
+            (EofBehavior::MaxValue, u16::MAX),
+        ];
+        for (eof_behavior, expected) in cases {
+            let mut vm = VirtualMachine::<u16>::new(&good_program, 0, false)
+                .with_eof_behavior(eof_behavior);
+            vm.tape[0] = 7;
+            assert!(vm.read_into_cell(Cursor::new(vec![])).is_ok());
+            assert_eq!(vm.value_at_tape_head(), expected);
+        }
+
+        let mut vm = VirtualMachine::<u8>::new(&good_program, 0, false);
+        assert!(vm.read_into_cell(Cursor::new(vec![])).is_err());
+    }
+
     /// A test to check that the write method works properly
     #[test]
     fn test_write() {
@@ -708,6 +2314,66 @@ mod tests {
         assert_eq!(virtual_machine.end_loop().unwrap(), 1);
     }
 
+    /// A test to check that a lazily validated program with an unmatched
+    /// closing bracket only fails once execution reaches that bracket.
+    #[test]
+    fn test_lazy_unmatched_closing() {
+        let options =
+            ParseOptions::new().bracket_validation(BracketValidation::Lazy);
+        let program = BfProgram::new_with_options(
+            String::from("+.\n+]"),
+            "lazy.bf",
+            &options,
+        )
+        .unwrap();
+        let mut virtual_machine =
+            VirtualMachine::<u8>::new(&program, 10, false);
+        let mut input = Cursor::new(Vec::<u8>::new());
+        let mut output = Cursor::new(Vec::<u8>::new());
+
+        let result = virtual_machine.interpret(&mut input, &mut output);
+        assert!(matches!(
+            result,
+            Err(VirtualMachineError::UnmatchedBracket {
+                bracket: ']',
+                line: 2,
+                column: 2
+            })
+        ));
+        // Everything before the unmatched bracket should have been run.
+        assert_eq!(output.into_inner(), vec![1u8]);
+    }
+
+    /// A test to check that a lazily validated program with an unmatched
+    /// opening bracket fails when it reaches it, while the matched brackets
+    /// still work as usual.
+    #[test]
+    fn test_lazy_unmatched_opening() {
+        let options =
+            ParseOptions::new().bracket_validation(BracketValidation::Lazy);
+        let program = BfProgram::new_with_options(
+            String::from("++[-]["),
+            "lazy.bf",
+            &options,
+        )
+        .unwrap();
+        let mut virtual_machine =
+            VirtualMachine::<u8>::new(&program, 10, false);
+        let mut input = Cursor::new(Vec::<u8>::new());
+        let mut output = Cursor::new(Vec::<u8>::new());
+
+        let result = virtual_machine.interpret(&mut input, &mut output);
+        assert!(matches!(
+            result,
+            Err(VirtualMachineError::UnmatchedBracket {
+                bracket: '[',
+                line: 1,
+                column: 6
+            })
+        ));
+        assert_eq!(virtual_machine.value_at_tape_head(), 0);
+    }
+
     #[test]
     fn test_value_at_tape_head() {
         let contents = String::from("[some,.],.program");
@@ -725,4 +2391,1083 @@ mod tests {
         let virtual_machine = VirtualMachine::<u8>::new(&program, 10, false);
         assert_eq!(virtual_machine.tape_head(), 0);
     }
+
+    fn extension_program(contents: &str) -> BfProgram {
+        let options = ParseOptions::new().extension('#').extension('@');
+        BfProgram::new_with_options(contents.to_string(), "ext.bf", &options)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_extension_reads_and_writes() {
+        // `#` echoes the input twice, without touching the tape.
+        let program = extension_program("#");
+        let mut virtual_machine = VirtualMachine::<u8>::new(&program, 1, false);
+        virtual_machine.register_extension('#', |context| {
+            assert_eq!(
+                (context.steps(), context.instruction().column()),
+                (1, 1)
+            );
+            let mut buffer = [0; 1];
+            context.input().read_exact(&mut buffer)?;
+            context.output().write_all(&[buffer[0], buffer[0]])?;
+            Ok(())
+        });
+        let mut input = Cursor::new(vec![b'x']);
+        let mut output = Vec::new();
+        virtual_machine.interpret(&mut input, &mut output).unwrap();
+        assert_eq!(output, b"xx");
+        assert_eq!(virtual_machine.tape(), [0]);
+    }
+
+    #[test]
+    fn test_extension_moves_head() {
+        let program = extension_program("#+");
+        let mut virtual_machine = VirtualMachine::<u8>::new(&program, 1, true);
+        virtual_machine.register_extension('#', |context| context.set_head(3));
+        let mut input = Cursor::new(Vec::<u8>::new());
+        let mut output = Vec::new();
+        virtual_machine.interpret(&mut input, &mut output).unwrap();
+        assert_eq!(virtual_machine.tape(), [0, 0, 0, 1]);
+
+        let mut virtual_machine = VirtualMachine::<u8>::new(&program, 1, false);
+        virtual_machine.register_extension('#', |context| context.set_head(3));
+        assert!(matches!(
+            virtual_machine.interpret(&mut input, &mut output),
+            Err(VirtualMachineError::InvalidHeadPosition {
+                operation: Operation::Extension('#'),
+                position: 3,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_extension_errors() {
+        let program = extension_program("+@\n #");
+        let mut virtual_machine = VirtualMachine::<u8>::new(&program, 1, false);
+        virtual_machine.register_extension('@', |_| Ok(()));
+        virtual_machine
+            .register_extension('@', |context| Err(context.fail("oops")));
+        let mut input = Cursor::new(Vec::<u8>::new());
+        let mut output = Vec::new();
+        assert!(matches!(
+            virtual_machine.interpret(&mut input, &mut output),
+            Err(VirtualMachineError::ExtensionFailed {
+                name: '@',
+                line: 1,
+                column: 2,
+                message,
+                ..
+            }) if message == "oops"
+        ));
+
+        let mut virtual_machine = VirtualMachine::<u8>::new(&program, 1, false);
+        virtual_machine.register_extension('@', |_| Ok(()));
+        assert!(matches!(
+            virtual_machine.interpret(&mut input, &mut output),
+            Err(VirtualMachineError::UnknownExtension {
+                name: '#',
+                line: 2,
+                column: 2,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_syscalls() {
+        let options = ParseOptions::new().extension(crate::syscall::SYSCALL);
+        let program = BfProgram::new_with_options(
+            "++%>%".to_string(),
+            "sys.bf",
+            &options,
+        )
+        .unwrap();
+        let mut input = Cursor::new(Vec::<u8>::new());
+        let mut output = Vec::new();
+
+        // Calls fail unless the capability is given.
+        let mut virtual_machine = VirtualMachine::<u8>::new(&program, 1, true);
+        virtual_machine.register_syscall(2, |_| Ok(()));
+        assert!(matches!(
+            virtual_machine.interpret(&mut input, &mut output),
+            Err(VirtualMachineError::SyscallsDisabled {
+                line: 1,
+                column: 3,
+                ..
+            })
+        ));
+
+        // Function 2 fills a window which grows the tape, and the second call
+        // is to function 7, from the window, which was never registered.
+        let mut virtual_machine =
+            VirtualMachine::<u8>::new(&program, 1, true).with_syscalls(true);
+        virtual_machine.register_syscall(2, |context| {
+            context.window(2)?.copy_from_slice(&[7, 9]);
+            Ok(())
+        });
+        assert!(matches!(
+            virtual_machine.interpret(&mut input, &mut output),
+            Err(VirtualMachineError::UnknownSyscall {
+                id: 7,
+                column: 5,
+                ..
+            })
+        ));
+        assert_eq!(virtual_machine.tape(), [2, 7, 9]);
+
+        // The same calls are made from a lowered program, and a window past
+        // the end of a fixed tape fails.
+        let ir = IrProgram::from_program(&program).unwrap();
+        let mut virtual_machine =
+            VirtualMachine::<u8>::new(&program, 2, false).with_syscalls(true);
+        virtual_machine
+            .register_syscall(2, |context| context.window(2).map(|_| ()));
+        assert!(matches!(
+            virtual_machine.interpret_ir(&ir, &mut input, &mut output),
+            Err(VirtualMachineError::ExtensionFailed { name: '%', .. })
+        ));
+    }
+
+    /// Runs the program both directly and through the optimizer, checking
+    /// that the output and tape are the same, and returning the output.
+    fn run_both_ways(contents: &str, input: &[u8], growable: bool) -> Vec<u8> {
+        let program = BfProgram::new(contents.to_string(), "ir.bf").unwrap();
+        let mut ir = IrProgram::from_program(&program).unwrap();
+        Pipeline::builtin().run(&mut ir);
+
+        let mut direct = VirtualMachine::<u8>::new(&program, 4, growable)
+            .with_eof_behavior(EofBehavior::Zero);
+        let mut direct_output = Vec::new();
+        direct
+            .interpret(&mut Cursor::new(input), &mut direct_output)
+            .unwrap();
+
+        let mut optimized = VirtualMachine::<u8>::new(&program, 4, growable)
+            .with_eof_behavior(EofBehavior::Zero);
+        let mut optimized_output = Vec::new();
+        optimized
+            .interpret_ir(&ir, &mut Cursor::new(input), &mut optimized_output)
+            .unwrap();
+
+        assert_eq!(direct_output, optimized_output);
+        assert_eq!(direct.tape(), optimized.tape());
+        assert_eq!(direct.tape_head(), optimized.tape_head());
+        assert!(optimized.steps() <= direct.steps());
+
+        let mut threaded = VirtualMachine::<u8>::new(&program, 4, growable)
+            .with_eof_behavior(EofBehavior::Zero)
+            .with_dispatch(DispatchKind::Threaded);
+        let mut threaded_output = Vec::new();
+        threaded
+            .interpret_ir(&ir, &mut Cursor::new(input), &mut threaded_output)
+            .unwrap();
+        assert_eq!(optimized_output, threaded_output);
+        assert_eq!(optimized.tape(), threaded.tape());
+        assert_eq!(optimized.tape_head(), threaded.tape_head());
+        assert_eq!(optimized.steps(), threaded.steps());
+
+        // Running the start of the program ahead of time only leaves out cells
+        // which were never changed from the end of the tape.
+        PartialEvaluation::<u8>::new(1000, 4, growable).run(&mut ir);
+        let mut evaluated = VirtualMachine::<u8>::new(&program, 4, growable)
+            .with_eof_behavior(EofBehavior::Zero);
+        let mut evaluated_output = Vec::new();
+        evaluated
+            .interpret_ir(&ir, &mut Cursor::new(input), &mut evaluated_output)
+            .unwrap();
+        let used = |tape: &[u8]| {
+            let end = tape.iter().rposition(|cell| *cell != 0);
+            tape[..end.map_or(0, |last| last + 1)].to_vec()
+        };
+        assert_eq!(direct_output, evaluated_output);
+        assert_eq!(used(direct.tape()), used(evaluated.tape()));
+        assert_eq!(direct.tape_head(), evaluated.tape_head());
+        optimized_output
+    }
+
+    #[test]
+    fn test_interpret_ir_matches_interpret() {
+        let hello = include_str!("../../bf-programs/hello-world.bf");
+        assert_eq!(run_both_ways(hello, b"", true), b"hello world");
+        assert_eq!(run_both_ways(",[.,]", b"echo", false), b"echo");
+        assert_eq!(
+            run_both_ways(",>,<[->>+<<]>[->+<]>.", b"\x03\x04", false),
+            [7]
+        );
+        // Multiplying into a cell beyond the end of an extensible tape.
+        assert_eq!(run_both_ways("+++[->>>>>++<<<<<]>>>>>.", b"", true), [6]);
+        // Scanning in both directions, and off the end of the tape.
+        assert_eq!(run_both_ways(">+>+<[>]+.[<]>.", b"", false), [1, 1]);
+        assert_eq!(run_both_ways("+>>+>>+<<<<[>>]+>.", b"", true), [0]);
+        assert_eq!(run_both_ways("+>+>+>+[>]+.", b"", true), [1]);
+        // Changing cells at offsets from the head.
+        assert_eq!(
+            run_both_ways(">>+++<.>>[-]+<<<+[>>.<-<++>]>>>+>.", b"", true),
+            [0, 3, 1, 0, 0]
+        );
+        // Changing ranges of cells, past the end of an extensible tape.
+        assert_eq!(
+            run_both_ways(
+                "+>+>+>+>+<<<<[-]>[-]>[-]>[-]>.<<<<+>+>+>+>+.",
+                b"",
+                true
+            ),
+            [1, 2]
+        );
+    }
+
+    /// Runs the program both directly and with its multiply loops lowered,
+    /// with cells of type `T`, checking that the tapes end up the same.
+    fn assert_multiplies_match<T>(contents: &str, input: &[u8])
+    where
+        T: CellKind + Default + Clone + Copy + PartialEq + std::fmt::Debug,
+    {
+        let program = BfProgram::new(contents.to_string(), "mul.bf").unwrap();
+        let mut ir = IrProgram::from_program(&program).unwrap();
+        let stats = Pipeline::from_spec("rle,copyloop,mulloop")
+            .unwrap()
+            .run(&mut ir);
+        assert!(stats[2].rewritten() > 0);
+        let mut direct = VirtualMachine::<T>::new(&program, 8, true);
+        direct
+            .interpret(&mut Cursor::new(input), &mut Vec::new())
+            .unwrap();
+        let mut lowered = VirtualMachine::<T>::new(&program, 8, true);
+        lowered
+            .interpret_ir(&ir, &mut Cursor::new(input), &mut Vec::new())
+            .unwrap();
+        assert_eq!(direct.tape(), lowered.tape(), "{:?}", input);
+        assert_eq!(direct.tape_head(), lowered.tape_head());
+    }
+
+    #[test]
+    fn test_multiply_loops_match_interpret() {
+        // Multiplying two cells read from the input, wrapping around.
+        let multiply = ",>,<[>[->+>+<<]>>[-<<+>>]<<<-]>>.";
+        for a in (0..=255u8).step_by(15) {
+            for b in [0, 1, 2, 3, 16, 255] {
+                assert_eq!(
+                    run_both_ways(multiply, &[a, b], true),
+                    [a.wrapping_mul(b)]
+                );
+            }
+        }
+        // With a factor, an amount added each time around, and a cell set.
+        let scaled = ",>,<[>[->++>+<<]>>[-<<+>>]>[-]+>+++<<<<<-]>>.>>.>.";
+        assert_eq!(run_both_ways(scaled, &[3, 4], true), [24, 1, 9]);
+        assert_eq!(run_both_ways(scaled, &[0, 4], true), [0, 0, 0]);
+        // Loops which multiply by a cell they change, or by their own count,
+        // are left as loops.
+        assert_eq!(run_both_ways(",>,<[>[->+<]<-]>>.", b"\x03\x04", true), [4]);
+        assert_eq!(
+            run_both_ways(",[[->+>+<<]>>[-<<+>>]<<-]>.", b"\x04", true),
+            [10]
+        );
+        // Multiplying the product by a third cell, for every width of cell.
+        let cube =
+            ",>,<[>[->+>+<<]>>[-<<+>>]<<<-]>>>,<[>[->+>+<<]>>[-<<+>>]<<<-]";
+        for input in [[3, 5, 7], [40, 40, 41], [255, 2, 0], [17, 255, 13]] {
+            assert_multiplies_match::<u8>(cube, &input);
+            assert_multiplies_match::<u16>(cube, &input);
+            assert_multiplies_match::<u32>(cube, &input);
+        }
+    }
+
+    #[test]
+    fn test_branches_match_interpret() {
+        // Prints the input, or three if it is zero.
+        let or_three = ",>[-]+<[>-<.[-]]>[-<+++.>]";
+        assert_eq!(run_both_ways(or_three, b"\x05", false), [5]);
+        assert_eq!(run_both_ways(or_three, b"\x00", false), [3]);
+        // Prints each input, or zero for a one, with the branches nested in a
+        // loop and in each other.
+        let nested = ",[->[-]+<[>>[-]+<<[>>-<<+.[-]]>>[-.]<-<[-]]>[-<.>]<,]";
+        assert_eq!(run_both_ways(nested, &[3, 1, 7, 2], false), [3, 0, 7, 2]);
+    }
+
+    #[test]
+    fn test_interpret_ir_errors() {
+        let program =
+            BfProgram::new(String::from("+\n[->>>>+<<<<]"), "ir.bf").unwrap();
+        let mut ir = IrProgram::from_program(&program).unwrap();
+        Pipeline::builtin().run(&mut ir);
+        let mut virtual_machine = VirtualMachine::<u8>::new(&program, 2, false);
+        let mut input = Cursor::new(Vec::<u8>::new());
+        let mut output = Vec::new();
+        assert!(matches!(
+            virtual_machine.interpret_ir(&ir, &mut input, &mut output),
+            Err(VirtualMachineError::InvalidHeadPosition {
+                line: 2,
+                column: 1,
+                position: 4,
+                tape_length: 2,
+                ..
+            })
+        ));
+
+        let program =
+            BfProgram::new(String::from(">>+<+<+[<<]"), "ir.bf").unwrap();
+        let mut ir = IrProgram::from_program(&program).unwrap();
+        Pipeline::builtin().run(&mut ir);
+        let mut virtual_machine = VirtualMachine::<u8>::new(&program, 3, false);
+        assert!(matches!(
+            virtual_machine.interpret_ir(&ir, &mut input, &mut output),
+            Err(VirtualMachineError::InvalidHeadPosition {
+                column: 8,
+                position: 0,
+                ..
+            })
+        ));
+
+        // The position is where the head was when the scan started.
+        let program =
+            BfProgram::new(String::from(">>+<+<+>>[<<]"), "ir.bf").unwrap();
+        let mut ir = IrProgram::from_program(&program).unwrap();
+        Pipeline::builtin().run(&mut ir);
+        let mut virtual_machine = VirtualMachine::<u8>::new(&program, 3, false);
+        assert!(matches!(
+            virtual_machine.interpret_ir(&ir, &mut input, &mut output),
+            Err(VirtualMachineError::InvalidHeadPosition {
+                column: 10,
+                position: 2,
+                ..
+            })
+        ));
+
+        let program = BfProgram::new(String::from("+[]"), "ir.bf").unwrap();
+        let ir = IrProgram::from_program(&program).unwrap();
+        let mut virtual_machine =
+            VirtualMachine::<u8>::new(&program, 1, false).with_step_limit(10);
+        assert!(matches!(
+            virtual_machine.interpret_ir(&ir, &mut input, &mut output),
+            Err(VirtualMachineError::StepLimitExceeded { limit: 10, .. })
+        ));
+
+        // Threaded dispatch reports the same errors.
+        let mut virtual_machine = VirtualMachine::<u8>::new(&program, 1, false)
+            .with_step_limit(10)
+            .with_dispatch(DispatchKind::Threaded);
+        assert!(matches!(
+            virtual_machine.interpret_ir(&ir, &mut input, &mut output),
+            Err(VirtualMachineError::StepLimitExceeded { limit: 10, .. })
+        ));
+        let program = BfProgram::new(String::from("<+"), "ir.bf").unwrap();
+        let ir = IrProgram::from_program(&program).unwrap();
+        let mut virtual_machine = VirtualMachine::<u8>::new(&program, 1, false)
+            .with_dispatch(DispatchKind::Threaded);
+        assert!(matches!(
+            virtual_machine.interpret_ir(&ir, &mut input, &mut output),
+            Err(VirtualMachineError::InvalidHeadPosition { column: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn test_output_limit() {
+        fn limited<T>(
+            program: &BfProgram,
+            ir: Option<&IrProgram>,
+            dispatch: DispatchKind,
+        ) -> (Vec<u8>, Result<(), VirtualMachineError>)
+        where
+            T: CellKind + Default + Clone + Copy + PartialEq,
+        {
+            let mut vm = VirtualMachine::<T>::new(program, 2, false)
+                .with_output_limit(4)
+                .with_dispatch(dispatch);
+            let mut input = Cursor::new(Vec::new());
+            let mut output = Vec::new();
+            let result = match ir {
+                Some(ir) => vm.interpret_ir(ir, &mut input, &mut output),
+                None => vm.interpret(&mut input, &mut output),
+            };
+            (output, result)
+        }
+
+        // Printing forever stops at the limit on every path through the
+        // interpreter, having written exactly as much as was allowed.
+        let program =
+            BfProgram::new(String::from("++++++\n+[.]"), "loop.bf").unwrap();
+        let ir = IrProgram::from_program(&program).unwrap();
+        for dispatch in [DispatchKind::Match, DispatchKind::Threaded] {
+            for (output, result) in [
+                limited::<u8>(&program, None, dispatch),
+                limited::<u16>(&program, None, dispatch),
+                limited::<u8>(&program, Some(&ir), dispatch),
+                limited::<u16>(&program, Some(&ir), dispatch),
+            ] {
+                assert_eq!(output, [7; 4]);
+                assert!(matches!(
+                    result,
+                    Err(VirtualMachineError::OutputLimitExceeded {
+                        line: 2,
+                        column: 3,
+                        limit: 4,
+                        ..
+                    })
+                ));
+            }
+        }
+
+        // Output folded into a single node is written up to the limit before
+        // failing, and output within the limit is not refused at all.
+        let program =
+            BfProgram::new(String::from("+.+.+.+.+."), "five.bf").unwrap();
+        let mut ir = IrProgram::from_program(&program).unwrap();
+        let mut pipeline = Pipeline::builtin();
+        let evaluation = PartialEvaluation::<u8>::new(100, 2, false);
+        pipeline.push(Box::new(evaluation));
+        pipeline.run(&mut ir);
+        assert!(matches!(ir.nodes()[0].op(), IrOp::OutputBytes(_)));
+        for dispatch in [DispatchKind::Match, DispatchKind::Threaded] {
+            let (output, result) = limited::<u8>(&program, Some(&ir), dispatch);
+            assert_eq!(output, [1, 2, 3, 4]);
+            assert!(result.is_err());
+        }
+        let program =
+            BfProgram::new(String::from("+.+.+.+."), "four.bf").unwrap();
+        let (output, result) =
+            limited::<u16>(&program, None, DispatchKind::Match);
+        assert_eq!(output, [1, 2, 3, 4]);
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_sandbox() {
+        fn sandboxed(
+            program: &BfProgram,
+            ir: Option<&IrProgram>,
+            sandbox: &Sandbox,
+        ) -> (u64, Result<(), VirtualMachineError>) {
+            let mut vm = VirtualMachine::<u8>::new(program, 2, false)
+                .with_sandbox(sandbox.clone());
+            let mut input = Cursor::new(Vec::new());
+            let result = match ir {
+                Some(ir) => vm.interpret_ir(ir, &mut input, &mut Vec::new()),
+                None => vm.interpret(&mut input, &mut Vec::new()),
+            };
+            (vm.gas_used(), result)
+        }
+
+        // Gas runs out on both paths, but a node clearing many cells costs
+        // as much as clearing them one by one would.
+        let program =
+            BfProgram::new(String::from("+[>[-]+]"), "fill.bf").unwrap();
+        let mut ir = IrProgram::from_program(&program).unwrap();
+        Pipeline::builtin().run(&mut ir);
+        let gas = Sandbox::builder()
+            .with_gas_limit(100)
+            .with_tape_growth(true)
+            .build()
+            .unwrap();
+        for ir in [None, Some(&ir)] {
+            let (used, result) = sandboxed(&program, ir, &gas);
+            assert!(used <= 100);
+            assert!(matches!(
+                result,
+                Err(VirtualMachineError::GasExhausted { limit: 100, .. })
+            ));
+        }
+
+        // The tape may not grow past the memory limit.
+        let memory = Sandbox::builder()
+            .with_memory_limit(5)
+            .with_tape_growth(true)
+            .build()
+            .unwrap();
+        let (_, result) = sandboxed(&program, None, &memory);
+        assert!(matches!(
+            result,
+            Err(VirtualMachineError::TapeGrowthRefused { cells: 6, .. })
+        ));
+
+        // A program which never halts runs out of time.
+        let forever =
+            BfProgram::new(String::from("+[]"), "forever.bf").unwrap();
+        let time = Sandbox::builder()
+            .with_time_limit(Duration::from_millis(10))
+            .build()
+            .unwrap();
+        let (_, result) = sandboxed(&forever, None, &time);
+        assert!(matches!(
+            result,
+            Err(VirtualMachineError::TimeLimitExceeded { limit_ms: 10, .. })
+        ));
+    }
+
+    /// A test to check that a `,` is charged gas once, not again when it
+    /// carries on after asking for its input.
+    #[test]
+    fn test_gas_input() {
+        let program = BfProgram::new(String::from(",,,,"), "read.bf").unwrap();
+        let gas = Sandbox::builder().with_gas_limit(4).build().unwrap();
+        let mut vm = VirtualMachine::<u8>::new(&program, 1, false)
+            .with_sandbox(gas.clone());
+        vm.interpret(&mut Cursor::new(b"abcd".to_vec()), &mut Vec::new())
+            .unwrap();
+        assert_eq!(vm.gas_used(), 4);
+        assert_eq!(vm.steps(), 4);
+
+        let mut vm =
+            VirtualMachine::<u8>::new(&program, 1, false).with_sandbox(gas);
+        let mut input = b"abcd".iter().copied();
+        loop {
+            match vm.interpret_resumable().unwrap() {
+                Event::NeedsInput => vm.provide_input(input.next()),
+                Event::ProducedOutput(_) => {}
+                Event::Halted => break,
+            }
+        }
+        assert_eq!(vm.gas_used(), 4);
+        assert_eq!(vm.steps(), 4);
+    }
+
+    #[test]
+    fn test_sandbox_syscalls() {
+        let options = ParseOptions::new().extension(SYSCALL);
+        let program = BfProgram::new_with_options(
+            "+%+%".to_string(),
+            "calls.bf",
+            &options,
+        )
+        .unwrap();
+        let sandbox = Sandbox::builder()
+            .with_syscalls(Syscalls::only([1]))
+            .build()
+            .unwrap();
+        let mut vm =
+            VirtualMachine::<u8>::new(&program, 1, false).with_sandbox(sandbox);
+        vm.register_syscall(1, |_| Ok(()));
+        vm.register_syscall(2, |_| Ok(()));
+        let result =
+            vm.interpret(&mut Cursor::new(Vec::new()), &mut Vec::new());
+        assert!(matches!(
+            result,
+            Err(VirtualMachineError::SyscallDenied {
+                id: 2,
+                column: 4,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_input_limit() {
+        /// Input which never ends, as a terminal left open would.
+        struct Endless;
+        impl Read for Endless {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                buf.fill(b'x');
+                Ok(buf.len())
+            }
+        }
+
+        fn limited<T>(
+            program: &BfProgram,
+            ir: Option<&IrProgram>,
+            dispatch: DispatchKind,
+        ) -> (Vec<u8>, Result<(), VirtualMachineError>)
+        where
+            T: CellKind + Default + Clone + Copy + PartialEq,
+        {
+            let mut vm = VirtualMachine::<T>::new(program, 2, false)
+                .with_input_limit(3)
+                .with_dispatch(dispatch);
+            let mut output = Vec::new();
+            let result = match ir {
+                Some(ir) => vm.interpret_ir(ir, &mut Endless, &mut output),
+                None => vm.interpret(&mut Endless, &mut output),
+            };
+            (output, result)
+        }
+
+        // Reading from input which never ends stops at the limit on every
+        // path through the interpreter, and reading past it is an error at
+        // the `,` which did so.
+        let program =
+            BfProgram::new(String::from("+\n[,.]"), "cat.bf").unwrap();
+        let ir = IrProgram::from_program(&program).unwrap();
+        for dispatch in [DispatchKind::Match, DispatchKind::Threaded] {
+            for (output, result) in [
+                limited::<u8>(&program, None, dispatch),
+                limited::<u16>(&program, None, dispatch),
+                limited::<u8>(&program, Some(&ir), dispatch),
+                limited::<u16>(&program, Some(&ir), dispatch),
+            ] {
+                assert_eq!(output, b"xxx");
+                assert!(matches!(
+                    result,
+                    Err(VirtualMachineError::InputExhausted {
+                        line: 2,
+                        column: 2,
+                        read: 3,
+                        ..
+                    })
+                ));
+            }
+        }
+
+        // With another way of handling the end of the input, the program
+        // carries on past the limit.
+        let mut vm = VirtualMachine::<u8>::new(&program, 1, false)
+            .with_input_limit(2)
+            .with_eof_behavior(EofBehavior::Zero);
+        let mut output = Vec::new();
+        vm.interpret(&mut Endless, &mut output).unwrap();
+        assert_eq!(output, b"xx\0");
+
+        // The resumable interpreter stops asking for input at the limit.
+        let program = BfProgram::new(String::from(",,."), "two.bf").unwrap();
+        let mut vm = VirtualMachine::<u8>::new(&program, 1, false)
+            .with_input_limit(1)
+            .with_eof_behavior(EofBehavior::MaxValue);
+        assert_eq!(vm.interpret_resumable().unwrap(), Event::NeedsInput);
+        vm.provide_input(Some(1));
+        assert_eq!(
+            vm.interpret_resumable().unwrap(),
+            Event::ProducedOutput(255)
+        );
+    }
+
+    /// What a program did on a Virtual Machine: its output, tape, head and
+    /// steps, or its error.
+    type Run = Result<(Vec<u8>, Vec<u32>, usize, u64), String>;
+
+    fn record<T>(
+        vm: VirtualMachine<'_, T>,
+        result: Result<(), VirtualMachineError>,
+        output: Vec<u8>,
+    ) -> Run
+    where
+        T: CellKind + Default + Clone + Copy + PartialEq,
+    {
+        result.map_err(|e| e.to_string())?;
+        let steps = vm.steps();
+        let (tape, head) = vm.into_tape();
+        Ok((output, tape.iter().map(T::to_u32).collect(), head, steps))
+    }
+
+    /// Runs the program directly, and lowered if its brackets are balanced,
+    /// with the given cells.
+    fn run_with<T>(
+        program: &BfProgram,
+        input: &[u8],
+        growable: bool,
+    ) -> Vec<Run>
+    where
+        T: CellKind + Default + Clone + Copy + PartialEq,
+    {
+        let vm = || {
+            VirtualMachine::<T>::new(program, 4, growable).with_step_limit(500)
+        };
+        let mut direct = vm();
+        let mut direct_output = Vec::new();
+        let result =
+            direct.interpret(&mut Cursor::new(input), &mut direct_output);
+        let direct = record(direct, result, direct_output);
+
+        let Ok(mut ir) = IrProgram::from_program(program) else {
+            return vec![direct];
+        };
+        Pipeline::builtin().run(&mut ir);
+        let mut lowered = vm();
+        let mut lowered_output = Vec::new();
+        let result = lowered.interpret_ir(
+            &ir,
+            &mut Cursor::new(input),
+            &mut lowered_output,
+        );
+        vec![direct, record(lowered, result, lowered_output)]
+    }
+
+    /// Checks that 8 bit cells, which are run by the loops specialized for
+    /// them, behave the same as 32 bit cells, which are not, for programs
+    /// whose cells never wrap around.
+    fn assert_specialized_matches(
+        contents: &str,
+        input: &[u8],
+        growable: bool,
+    ) {
+        let options =
+            ParseOptions::new().bracket_validation(BracketValidation::Lazy);
+        let program = BfProgram::new_with_options(
+            contents.to_string(),
+            "bytes.bf",
+            &options,
+        )
+        .unwrap();
+        assert_eq!(
+            run_with::<u8>(&program, input, growable),
+            run_with::<u32>(&program, input, growable),
+            "{}",
+            contents
+        );
+    }
+
+    #[test]
+    fn test_specialized_loops_match_generic() {
+        let hello = include_str!("../../bf-programs/hello-world.bf");
+        assert_specialized_matches(hello, b"", true);
+        assert_specialized_matches(",[.,]", b"echo\nlines\n", false);
+        assert_specialized_matches("+>>+>+<<<[>]>.<<.", b"", true);
+        assert_specialized_matches("++[->>>>>+<<<<<]>>>>>.", b"", true);
+        assert_specialized_matches(
+            ",>,<[>[->+>+<<]>>[-<<+>>]<<<-]>>.",
+            b"\x03\x05",
+            false,
+        );
+        // Multiplying into cells off the end of the tape.
+        assert_specialized_matches("+>+<[>[->>>>+<<<<]<-]", b"", false);
+        // Handing over to the generic loop, which reports errors.
+        assert_specialized_matches("+>>>>>+", b"", false);
+        assert_specialized_matches("+.<", b"", false);
+        assert_specialized_matches(",,", b"a", false);
+        assert_specialized_matches("+[]", b"", false);
+        assert_specialized_matches("+[-]]+", b"", false);
+        assert_specialized_matches("+>+>+>+[<]", b"", false);
+    }
+
+    /// Records the step number and head of each step traced.
+    #[derive(Default)]
+    struct Tracer(Vec<(u64, usize)>);
+
+    impl Reporter for Tracer {
+        fn traces(&self) -> bool {
+            true
+        }
+
+        fn step(&mut self, step: &Step<'_>) {
+            self.0.push((step.number(), step.head()));
+        }
+    }
+
+    #[test]
+    fn test_tracing_reports_every_step() {
+        let program =
+            BfProgram::new("++[>+<-]>.".to_string(), "trace.bf").unwrap();
+        let mut ir = IrProgram::from_program(&program).unwrap();
+        Pipeline::builtin().run(&mut ir);
+        for lowered in [None, Some(&ir)] {
+            for dispatch in [DispatchKind::Match, DispatchKind::Threaded] {
+                let mut tracer = Tracer::default();
+                let mut vm = VirtualMachine::<u8>::new(&program, 2, false)
+                    .with_dispatch(dispatch)
+                    .with_reporter(&mut tracer);
+                let mut output = Vec::new();
+                let mut input = Cursor::new(Vec::new());
+                match lowered {
+                    Some(ir) => vm.interpret_ir(ir, &mut input, &mut output),
+                    None => vm.interpret(&mut input, &mut output),
+                }
+                .unwrap();
+                let steps = vm.steps();
+                drop(vm);
+                assert_eq!(output, [2]);
+                assert_eq!(tracer.0.len() as u64, steps);
+                assert_eq!(tracer.0.first(), Some(&(1, 0)));
+                assert_eq!(tracer.0.last(), Some(&(steps, 1)));
+            }
+        }
+    }
+
+    #[test]
+    fn test_empty_program_halts() {
+        let program =
+            BfProgram::new("nothing to see here".to_string(), "empty.bf")
+                .unwrap();
+        let ir = IrProgram::from_program(&program).unwrap();
+        for lowered in [None, Some(&ir)] {
+            for dispatch in [DispatchKind::Match, DispatchKind::Threaded] {
+                let mut vm = VirtualMachine::<u16>::new(&program, 0, false)
+                    .with_dispatch(dispatch);
+                let mut output = Vec::new();
+                let mut input = Cursor::new(Vec::new());
+                match lowered {
+                    Some(ir) => vm.interpret_ir(ir, &mut input, &mut output),
+                    None => vm.interpret(&mut input, &mut output),
+                }
+                .unwrap();
+                assert_eq!(vm.steps(), 0);
+                assert!(output.is_empty());
+            }
+        }
+        let mut vm = VirtualMachine::<u8>::new(&program, 0, false);
+        vm.interpret(&mut Cursor::new(Vec::new()), &mut Vec::new())
+            .unwrap();
+        assert_eq!(vm.steps(), 0);
+    }
+
+    #[test]
+    fn test_callback_io() {
+        // Echoes the input back doubled, until the input runs out.
+        let program =
+            BfProgram::new(",[[->++<]>.[-]<,]".to_string(), "d.bf").unwrap();
+        let mut ir = IrProgram::from_program(&program).unwrap();
+        Pipeline::builtin().run(&mut ir);
+        for lowered in [None, Some(&ir)] {
+            let mut input = vec![3, 1, 20].into_iter();
+            let mut output = Vec::new();
+            let mut vm = VirtualMachine::<u8>::with_io(
+                &program,
+                2,
+                false,
+                move || input.next(),
+                |byte| output.push(byte),
+            )
+            .with_eof_behavior(EofBehavior::Zero);
+            match lowered {
+                Some(ir) => vm.run_ir(ir),
+                None => vm.run(),
+            }
+            .unwrap();
+            // The input and output are kept, but the program has halted.
+            vm.run().unwrap();
+            drop(vm);
+            assert_eq!(output, [6, 2, 40]);
+        }
+
+        // Without any input or output, the end of the input is reached
+        // straight away.
+        let mut vm = VirtualMachine::<u8>::new(&program, 2, false);
+        assert!(vm.run().is_err());
+    }
+
+    #[test]
+    fn test_events() {
+        // A loop which is skipped, and then one which runs twice.
+        let program =
+            BfProgram::new("[-]++[>+<-],.".to_string(), "loops.bf").unwrap();
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let mut sink = sender;
+        let mut vm = VirtualMachine::<u8>::new(&program, 2, false)
+            .with_event_sink(&mut sink);
+        vm.interpret(&mut Cursor::new(vec![9]), &mut Vec::new())
+            .unwrap();
+        drop(vm);
+        drop(sink);
+        let events: Vec<VmEvent> = receiver.iter().collect();
+        let loops: Vec<&VmEvent> = events
+            .iter()
+            .filter(|event| {
+                matches!(
+                    event.kind(),
+                    EventKind::LoopEntered | EventKind::LoopLeft
+                )
+            })
+            .collect();
+        assert_eq!(
+            loops,
+            [
+                &VmEvent::LoopEntered { start: 5 },
+                &VmEvent::LoopLeft { start: 5 }
+            ]
+        );
+        assert_eq!(
+            events[events.len() - 4..],
+            [
+                VmEvent::Input(9),
+                VmEvent::CellChanged {
+                    index: 0,
+                    old: 0,
+                    new: 9
+                },
+                VmEvent::Output(9),
+                VmEvent::Halted,
+            ]
+        );
+        let moves = events
+            .iter()
+            .filter(|event| event.kind() == EventKind::HeadMoved)
+            .count();
+        assert_eq!(moves, 4);
+    }
+
+    /// Writes down each length the tape is grown to, and refuses to grow it
+    /// past `max`.
+    struct Watcher<'w> {
+        grown: &'w mut Vec<usize>,
+        max: usize,
+    }
+
+    impl TapeAllocator<u8> for Watcher<'_> {
+        fn allocate(&mut self, len: usize) -> Vec<u8> {
+            vec![0; len]
+        }
+
+        fn grow(&mut self, tape: &mut Vec<u8>, len: usize) -> bool {
+            if len > self.max {
+                return false;
+            }
+            self.grown.push(len);
+            tape.resize(len, 0);
+            true
+        }
+
+        fn release(&mut self, tape: Vec<u8>) {
+            self.grown.push(tape.len());
+        }
+    }
+
+    #[test]
+    fn test_tape_allocator() {
+        let program = BfProgram::new(">>+>".to_string(), "far.bf").unwrap();
+        let mut grown = Vec::new();
+        let watcher = Watcher {
+            grown: &mut grown,
+            max: 10,
+        };
+        let mut vm = VirtualMachine::new_in(&program, 1, true, watcher);
+        vm.interpret(&mut Cursor::new(Vec::new()), &mut Vec::new())
+            .unwrap();
+        drop(vm);
+        // Grown a cell at a time, then given back when dropped.
+        assert_eq!(grown, [2, 3, 4, 4]);
+
+        let mut grown = Vec::new();
+        let watcher = Watcher {
+            grown: &mut grown,
+            max: 2,
+        };
+        let mut vm = VirtualMachine::new_in(&program, 1, true, watcher);
+        assert!(matches!(
+            vm.interpret(&mut Cursor::new(Vec::new()), &mut Vec::new()),
+            Err(VirtualMachineError::TapeGrowthRefused {
+                line: 1,
+                column: 2,
+                cells: 3,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_reset() {
+        let program =
+            BfProgram::new(",[>+<-]>.".to_string(), "sum.bf").unwrap();
+        let mut vm = VirtualMachine::<u8>::new(&program, 2, true)
+            .with_eof_behavior(EofBehavior::Zero)
+            .with_step_limit(100);
+        let mut output = Vec::new();
+        vm.interpret(&mut Cursor::new(vec![5]), &mut output)
+            .unwrap();
+        let steps = vm.steps();
+
+        vm.reset();
+        assert_eq!(vm.tape(), [0, 0]);
+        assert_eq!((vm.tape_head(), vm.steps()), (0, 0));
+        vm.interpret(&mut Cursor::new(vec![5]), &mut output)
+            .unwrap();
+        assert_eq!(vm.steps(), steps);
+        assert_eq!(output, [5, 5]);
+
+        // The configuration is kept, so the step limit still applies.
+        vm.reset();
+        assert!(matches!(
+            vm.interpret(&mut Cursor::new(vec![50]), &mut output),
+            Err(VirtualMachineError::StepLimitExceeded { .. })
+        ));
+
+        vm.reset_with_tape(&[0, 1, 2]).unwrap();
+        assert_eq!(vm.tape(), [0, 1, 2]);
+        vm.interpret(&mut Cursor::new(Vec::new()), &mut output)
+            .unwrap();
+        assert_eq!(output, [5, 5, 1]);
+
+        // A tape which would go over the memory limit is refused.
+        let memory = Sandbox::builder()
+            .with_memory_limit(2)
+            .with_tape_growth(true)
+            .build()
+            .unwrap();
+        let mut vm =
+            VirtualMachine::<u8>::new(&program, 2, false).with_sandbox(memory);
+        assert!(matches!(
+            vm.reset_with_tape(&[0, 1, 2]),
+            Err(VirtualMachineError::TapeGrowthRefused { cells: 3, .. })
+        ));
+    }
+
+    #[test]
+    fn test_swap_program() {
+        // Each line is its own program, as in a REPL.
+        let lines: Vec<BfProgram> = [">+++", "[<++>-]", "<."]
+            .iter()
+            .enumerate()
+            .map(|(n, line)| {
+                BfProgram::new(line.to_string(), format!("line {}", n + 1))
+                    .unwrap()
+            })
+            .collect();
+        let options =
+            ParseOptions::new().bracket_validation(BracketValidation::Lazy);
+        let unmatched =
+            BfProgram::new_with_options("+]".to_string(), "bad.bf", &options)
+                .unwrap();
+        let mut vm = VirtualMachine::<u8>::new(&lines[0], 0, true);
+        let mut output = Vec::new();
+        for line in &lines {
+            vm.swap_program(line);
+            vm.interpret(&mut Cursor::new(Vec::new()), &mut output)
+                .unwrap();
+        }
+        assert_eq!(output, [6]);
+        assert_eq!(vm.tape_head(), 0);
+
+        // A lazily validated program fails only at its unmatched bracket.
+        vm.swap_program(&unmatched);
+        assert!(matches!(
+            vm.interpret(&mut Cursor::new(Vec::new()), &mut output),
+            Err(VirtualMachineError::UnmatchedBracket { bracket: ']', .. })
+        ));
+        assert_eq!(vm.value_at_tape_head(), 7);
+    }
+
+    #[test]
+    fn test_resumable() {
+        let program = BfProgram::new(",[.,]+.".to_string(), "cat.bf").unwrap();
+        let mut vm = VirtualMachine::<u8>::new(&program, 1, false);
+        assert_eq!(vm.interpret_resumable().unwrap(), Event::NeedsInput);
+        // Nothing happens until the input is given.
+        assert_eq!(vm.interpret_resumable().unwrap(), Event::NeedsInput);
+        assert_eq!(vm.steps(), 0);
+        vm.provide_input(Some(7));
+        assert_eq!(vm.interpret_resumable().unwrap(), Event::ProducedOutput(7));
+        assert_eq!(vm.interpret_resumable().unwrap(), Event::NeedsInput);
+        vm.provide_input(None);
+        assert!(matches!(
+            vm.interpret_resumable(),
+            Err(VirtualMachineError::InputExhausted {
+                column: 4,
+                read: 1,
+                ..
+            })
+        ));
+
+        // The blocking interpreter takes exactly the same steps.
+        let mut vm = VirtualMachine::<u8>::new(&program, 1, false)
+            .with_eof_behavior(EofBehavior::Zero)
+            .with_step_limit(9);
+        let mut events = Vec::new();
+        loop {
+            match vm.interpret_resumable() {
+                Ok(Event::NeedsInput) => vm.provide_input(None),
+                Ok(event) => events.push(event),
+                Err(err) => panic!("{}", err),
+            }
+            if events.last() == Some(&Event::Halted) {
+                break;
+            }
+        }
+        assert_eq!(events, [Event::ProducedOutput(1), Event::Halted]);
+        assert_eq!(vm.steps(), 5);
+        let mut blocking = VirtualMachine::<u16>::new(&program, 1, false)
+            .with_eof_behavior(EofBehavior::Zero);
+        let mut output = Vec::new();
+        blocking
+            .interpret(&mut Cursor::new(Vec::new()), &mut output)
+            .unwrap();
+        assert_eq!(output, [1]);
+        assert_eq!(blocking.steps(), 5);
+    }
 }
@@ -0,0 +1,376 @@
+//! Sampling statistics about a run every so many steps, for dashboards which
+//! watch long running programs, such as those run by a server.
+//!
+//! `Metrics` is a `Reporter` which counts the steps of a run, the output it
+//! has written, how far the tape has grown and how often each kind of
+//! operation has run, and hands a `Sample` of them to a `MetricsSink` every
+//! so many steps. `CsvSink` writes each sample as a row of a CSV time series,
+//! and `PrometheusSink` keeps a file of the latest sample in the Prometheus
+//! text format, for the textfile collector of the node exporter. Anything
+//! else can be plugged in by implementing `MetricsSink`.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use crate::report::{Reporter, Step};
+
+/// The statistics of a run, so far.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Sample {
+    steps: u64,
+    output_bytes: u64,
+    tape_cells: usize,
+    elapsed: Duration,
+    operations: BTreeMap<&'static str, u64>,
+}
+
+impl Sample {
+    /// The number of steps run.
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /// The number of bytes of output written.
+    pub fn output_bytes(&self) -> u64 {
+        self.output_bytes
+    }
+
+    /// The number of cells on the tape.
+    pub fn tape_cells(&self) -> usize {
+        self.tape_cells
+    }
+
+    /// How long the run has been going.
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    /// The number of steps run of each kind of operation, as named by
+    /// `TracedOp::kind`.
+    pub fn operations(&self) -> &BTreeMap<&'static str, u64> {
+        &self.operations
+    }
+}
+
+/// Receives the samples taken of a run.
+pub trait MetricsSink {
+    /// Called with each sample, every so many steps.
+    fn sample(&mut self, sample: &Sample) -> io::Result<()>;
+
+    /// Called with the last sample, once the run has finished. By default it
+    /// is handled just as any other sample.
+    fn finish(&mut self, sample: &Sample) -> io::Result<()> {
+        self.sample(sample)
+    }
+}
+
+/// Hands the samples to the boxed sink, so that which sink to use can be picked
+/// while running.
+impl<S: MetricsSink + ?Sized> MetricsSink for Box<S> {
+    fn sample(&mut self, sample: &Sample) -> io::Result<()> {
+        (**self).sample(sample)
+    }
+
+    fn finish(&mut self, sample: &Sample) -> io::Result<()> {
+        (**self).finish(sample)
+    }
+}
+
+/// Samples the statistics of a run every so many steps, handing each sample
+/// to a `MetricsSink`. Turns on tracing, so programs run more slowly.
+/// ```
+/// use std::io::Cursor;
+/// use bft_types::BfProgram;
+/// use bft_interp::VirtualMachine;
+/// use bft_interp::metrics::{CsvSink, Metrics};
+///
+/// let program = BfProgram::new("++[>.<-]".to_string(), "two.bf").unwrap();
+/// let mut metrics = Metrics::new(CsvSink::new(Vec::new()), 4);
+/// let mut vm = VirtualMachine::<u8>::new(&program, 2, false)
+///     .with_reporter(&mut metrics);
+/// vm.interpret(&mut Cursor::new(Vec::new()), &mut Vec::new()).unwrap();
+/// drop(vm);
+/// assert_eq!(metrics.sample().output_bytes(), 2);
+/// assert_eq!(metrics.sample().operations()["loop_end"], 3);
+/// let csv = metrics.finish().unwrap().into_inner();
+/// // The header, a row every 4 of the 14 steps, and the last row.
+/// assert_eq!(csv.split(|&byte| byte == b'\n').count() - 1, 1 + 3 + 1);
+/// ```
+#[derive(Debug)]
+pub struct Metrics<S> {
+    sink: S,
+    interval: u64,
+    start: Instant,
+    sample: Sample,
+    /// The first error from the sink, which is given back by `finish`.
+    error: Option<io::Error>,
+}
+
+impl<S: MetricsSink> Metrics<S> {
+    /// Samples the run every `interval` steps, or only once it has finished
+    /// if the interval is 0.
+    pub fn new(sink: S, interval: u64) -> Self {
+        Self {
+            sink,
+            interval,
+            start: Instant::now(),
+            sample: Sample::default(),
+            error: None,
+        }
+    }
+
+    /// The statistics of the run so far.
+    pub fn sample(&self) -> &Sample {
+        &self.sample
+    }
+
+    /// Hands the last sample to the sink, and gives the sink back, or the
+    /// first error the sink failed with.
+    pub fn finish(mut self) -> io::Result<S> {
+        if let Some(err) = self.error {
+            return Err(err);
+        }
+        self.sample.elapsed = self.start.elapsed();
+        self.sink.finish(&self.sample)?;
+        Ok(self.sink)
+    }
+}
+
+impl<S: MetricsSink> Reporter for Metrics<S> {
+    fn traces(&self) -> bool {
+        true
+    }
+
+    fn step(&mut self, step: &Step<'_>) {
+        let operation = step.operation();
+        let sample = &mut self.sample;
+        sample.steps += 1;
+        sample.output_bytes += operation.output_len() as u64;
+        sample.tape_cells = step.tape_length();
+        *sample.operations.entry(operation.kind()).or_insert(0) += 1;
+        // Only 0 is a multiple of 0, so an interval of 0 never samples here.
+        if sample.steps.is_multiple_of(self.interval) {
+            sample.elapsed = self.start.elapsed();
+            if let Err(err) = self.sink.sample(sample) {
+                self.error.get_or_insert(err);
+            }
+        }
+    }
+}
+
+/// Writes each sample as a row of CSV, with the steps, output bytes, tape
+/// cells and milliseconds elapsed, after a header.
+#[derive(Debug)]
+pub struct CsvSink<W> {
+    writer: W,
+    header: bool,
+}
+
+impl<W: Write> CsvSink<W> {
+    /// Writes the samples to the writer.
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            header: false,
+        }
+    }
+
+    /// Gives back the writer.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write> MetricsSink for CsvSink<W> {
+    fn sample(&mut self, sample: &Sample) -> io::Result<()> {
+        if !self.header {
+            writeln!(self.writer, "steps,output_bytes,tape_cells,elapsed_ms")?;
+            self.header = true;
+        }
+        writeln!(
+            self.writer,
+            "{},{},{},{}",
+            sample.steps,
+            sample.output_bytes,
+            sample.tape_cells,
+            sample.elapsed.as_millis()
+        )
+    }
+
+    fn finish(&mut self, sample: &Sample) -> io::Result<()> {
+        self.sample(sample)?;
+        self.writer.flush()
+    }
+}
+
+/// Keeps a file of the latest sample in the Prometheus text format, replacing
+/// it whole each time so that it is never read half written.
+#[derive(Debug)]
+pub struct PrometheusSink {
+    path: PathBuf,
+}
+
+impl PrometheusSink {
+    /// Keeps the sample in the file at the path.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+/// The sample in the Prometheus text format.
+/// ```
+/// use bft_interp::metrics::{prometheus, Sample};
+///
+/// let text = prometheus(&Sample::default());
+/// assert!(text.contains("\nbft_steps_total 0\n"));
+/// ```
+pub fn prometheus(sample: &Sample) -> String {
+    let mut text = String::new();
+    let mut metric = |name: &str, kind: &str, help: &str, values: &[_]| {
+        text.push_str(&format!("# HELP {} {}\n", name, help));
+        text.push_str(&format!("# TYPE {} {}\n", name, kind));
+        for (labels, value) in values {
+            text.push_str(&format!("{}{} {}\n", name, labels, value));
+        }
+    };
+    metric(
+        "bft_steps_total",
+        "counter",
+        "Steps the program has run.",
+        &[(String::new(), sample.steps)],
+    );
+    metric(
+        "bft_output_bytes_total",
+        "counter",
+        "Bytes of output the program has written.",
+        &[(String::new(), sample.output_bytes)],
+    );
+    metric(
+        "bft_tape_cells",
+        "gauge",
+        "Cells on the tape.",
+        &[(String::new(), sample.tape_cells as u64)],
+    );
+    let operations: Vec<_> = sample
+        .operations
+        .iter()
+        .map(|(kind, count)| (format!("{{op=\"{}\"}}", kind), *count))
+        .collect();
+    metric(
+        "bft_operations_total",
+        "counter",
+        "Steps run of each kind of operation.",
+        &operations,
+    );
+    text
+}
+
+impl MetricsSink for PrometheusSink {
+    fn sample(&mut self, sample: &Sample) -> io::Result<()> {
+        let mut partial = self.path.clone().into_os_string();
+        partial.push(".tmp");
+        fs::write(&partial, prometheus(sample))?;
+        fs::rename(&partial, &self.path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{prometheus, CsvSink, Metrics, MetricsSink, Sample};
+    use crate::ir::IrProgram;
+    use crate::optimizer::Pipeline;
+    use crate::VirtualMachine;
+    use bft_types::BfProgram;
+    use std::io::{self, Cursor};
+
+    /// Keeps every sample it is given, failing after the first if asked to.
+    #[derive(Default)]
+    struct Samples(Vec<Sample>, bool);
+
+    impl MetricsSink for Samples {
+        fn sample(&mut self, sample: &Sample) -> io::Result<()> {
+            self.0.push(sample.clone());
+            if self.1 && self.0.len() > 1 {
+                return Err(io::Error::other("full"));
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_samples() {
+        let program =
+            BfProgram::new("+++[>+<-]>[.-]".to_string(), "test.bf").unwrap();
+        let mut metrics = Metrics::new(Samples::default(), 10);
+        let mut vm = VirtualMachine::<u8>::new(&program, 1, true)
+            .with_reporter(&mut metrics);
+        vm.interpret(&mut Cursor::new(Vec::new()), &mut Vec::new())
+            .unwrap();
+        drop(vm);
+        let samples = metrics.finish().unwrap().0;
+        let steps: Vec<u64> = samples.iter().map(Sample::steps).collect();
+        assert_eq!(steps, [10, 20, 30, 32]);
+        let last = &samples[3];
+        assert_eq!((last.output_bytes(), last.tape_cells()), (3, 2));
+        assert_eq!(samples[0].tape_cells(), 2);
+        assert_eq!(last.operations()["output"], 3);
+        assert_eq!(last.operations().values().sum::<u64>(), 32);
+
+        // A lowered program is counted by its nodes.
+        let mut ir = IrProgram::from_program(&program).unwrap();
+        Pipeline::builtin().run(&mut ir);
+        let mut metrics = Metrics::new(Samples::default(), 0);
+        let mut vm = VirtualMachine::<u8>::new(&program, 1, true)
+            .with_reporter(&mut metrics);
+        vm.interpret_ir(&ir, &mut Cursor::new(Vec::new()), &mut Vec::new())
+            .unwrap();
+        drop(vm);
+        let samples = metrics.finish().unwrap().0;
+        assert_eq!(samples.len(), 1);
+        assert_eq!(samples[0].output_bytes(), 3);
+        assert_eq!(samples[0].operations()["copy_loop"], 1);
+
+        // The first error from the sink is given back once the run is over.
+        let mut metrics = Metrics::new(Samples(Vec::new(), true), 1);
+        let mut vm = VirtualMachine::<u8>::new(&program, 1, true)
+            .with_reporter(&mut metrics);
+        vm.interpret(&mut Cursor::new(Vec::new()), &mut Vec::new())
+            .unwrap();
+        drop(vm);
+        assert!(metrics.finish().is_err());
+    }
+
+    #[test]
+    fn test_formats() {
+        let mut sample = Sample {
+            steps: 12,
+            output_bytes: 3,
+            tape_cells: 2,
+            ..Sample::default()
+        };
+        sample.operations.insert("add", 10);
+        sample.operations.insert("output", 2);
+        let text = prometheus(&sample);
+        assert!(text.starts_with(
+            "# HELP bft_steps_total Steps the program has run.\n\
+             # TYPE bft_steps_total counter\n\
+             bft_steps_total 12\n"
+        ));
+        assert!(text.contains("\nbft_tape_cells 2\n"));
+        assert!(text.ends_with(
+            "bft_operations_total{op=\"add\"} 10\n\
+             bft_operations_total{op=\"output\"} 2\n"
+        ));
+
+        let mut csv = CsvSink::new(Vec::new());
+        csv.sample(&sample).unwrap();
+        csv.finish(&sample).unwrap();
+        assert_eq!(
+            String::from_utf8(csv.into_inner()).unwrap(),
+            "steps,output_bytes,tape_cells,elapsed_ms\n12,3,2,0\n12,3,2,0\n"
+        );
+    }
+}
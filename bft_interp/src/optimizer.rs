@@ -0,0 +1,2082 @@
+//! Optimization passes over the intermediate representation, and the pipeline
+//! which runs them in order.
+//!
+//! The built-in passes are:
+//! - `rle`: merges runs of `+`/`-` and of `>`/`<` into single operations.
+//! - `clearloop`: replaces loops such as `[-]` with a single `Clear`.
+//! - `copyloop`: replaces loops such as `[->+>++<<]`, which add multiples of
+//!   the current cell to nearby cells, with a single `CopyLoop`.
+//! - `scanloop`: replaces loops such as `[>]` and `[<<]`, which search for a
+//!   zero cell, with a single `ScanRight` or `ScanLeft`.
+//! - `mulloop`: replaces loops which count a cell down while adding to and
+//!   copying between nearby cells, such as `[>[->+>+<<]>>[-<<+>>]<<<-]`, with
+//!   a single `MulAdd`, which multiplies cells rather than looping.
+//! - `ifelse`: replaces the two loops of the usual if/else idiom, such as
+//!   `t[-]+x[a t-x[-]]t[b t-]`, with an `If`, `Else` and `EndIf`, which run
+//!   one branch or the other without looping.
+//! - `offsets`: rewrites each basic block of adds, moves and clears so that
+//!   cells are changed at offsets from the head, such as `>+++<` becoming
+//!   `AddAt(1, 3)`, with a single `Move` at the end of the block.
+//! - `ranges`: replaces changes to runs of neighbouring cells, such as
+//!   `[-]>[-]>[-]>[-]` or `+>+>+>+`, with a single `ClearRange` or `AddRange`.
+//! - `deadstore`: drops changes to cells which are cleared again before
+//!   anything reads them, within straight-line code.
+//! - `unreachable`: drops the code after a loop which can never end, such as
+//!   the `[]` in `[-]+[]`.
+//!
+//! `DeadLoop` is not built in, as whether it can drop the loops at the start
+//! of a program depends on whether the program starts on a fresh tape. It
+//! drops loops which can never run, such as a second loop straight after the
+//! first, as the cell at the head is always zero when a loop is left.
+//!
+//! Merging moves means that an optimized program will not fail if its head
+//! only briefly leaves the tape, as in `<>` at the start of the tape.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::ir::{IrNode, IrOp, IrProgram, MulLoop};
+
+/// The names of the built-in passes, in the order they run by default.
+pub const BUILTIN_PASSES: [&str; 10] = [
+    "rle",
+    "clearloop",
+    "copyloop",
+    "scanloop",
+    "mulloop",
+    "ifelse",
+    "offsets",
+    "ranges",
+    "deadstore",
+    "unreachable",
+];
+
+/// The fewest neighbouring cells which the `ranges` pass replaces with a range.
+const MIN_RANGE_LEN: usize = 4;
+
+/// A single optimization pass, which rewrites the nodes of a program into a
+/// form which behaves the same way.
+/// ```
+/// use bft_types::BfProgram;
+/// use bft_interp::ir::{IrOp, IrProgram};
+/// use bft_interp::optimizer::{IrPass, Pipeline};
+///
+/// /// Removes any output, for benchmarking without the cost of printing.
+/// struct Silence;
+///
+/// impl IrPass for Silence {
+///     fn name(&self) -> &str {
+///         "silence"
+///     }
+///
+///     fn run(&mut self, program: &mut IrProgram) -> usize {
+///         program.nodes_mut().retain(|node| node.op() != &IrOp::Output);
+///         0
+///     }
+/// }
+///
+/// let program = BfProgram::new("++.".to_string(), "quiet.bf").unwrap();
+/// let mut ir = IrProgram::from_program(&program).unwrap();
+/// let mut pipeline = Pipeline::builtin();
+/// pipeline.push(Box::new(Silence));
+/// let stats = pipeline.run(&mut ir);
+///
+/// assert_eq!(ir.nodes().len(), 1);
+/// assert_eq!(stats[10].name(), "silence");
+/// assert_eq!(stats[10].removed(), 1);
+/// ```
+pub trait IrPass {
+    /// The name of the pass, used to select it and in its statistics.
+    fn name(&self) -> &str;
+
+    /// Runs the pass over the program, returning the number of nodes it
+    /// created to replace existing ones.
+    fn run(&mut self, program: &mut IrProgram) -> usize;
+}
+
+/// Merges runs of additions, and runs of moves, into single operations,
+/// dropping any which cancel out.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RunLength;
+
+impl IrPass for RunLength {
+    fn name(&self) -> &str {
+        "rle"
+    }
+
+    fn run(&mut self, program: &mut IrProgram) -> usize {
+        let mut rewritten = 0;
+        let mut merged: Vec<IrNode> = Vec::with_capacity(program.nodes().len());
+        let mut run_length = 0;
+        for node in program.nodes_mut().drain(..) {
+            let combined = match (merged.last().map(IrNode::op), node.op()) {
+                (Some(IrOp::Add(a)), IrOp::Add(b)) => {
+                    Some(IrOp::Add(a.wrapping_add(*b)))
+                }
+                (Some(IrOp::Move(a)), IrOp::Move(b)) => {
+                    Some(IrOp::Move(a.wrapping_add(*b)))
+                }
+                _ => None,
+            };
+            match combined {
+                Some(op) => {
+                    let source = merged.pop().map(|last| last.source());
+                    merged.push(IrNode::new(op, source.unwrap()));
+                    run_length += 1;
+                }
+                None => {
+                    if run_length > 0 {
+                        rewritten += 1;
+                    }
+                    run_length = 0;
+                    merged.push(node);
+                }
+            }
+        }
+        if run_length > 0 {
+            rewritten += 1;
+        }
+        merged
+            .retain(|node| !matches!(node.op(), IrOp::Add(0) | IrOp::Move(0)));
+        *program.nodes_mut() = merged;
+        rewritten
+    }
+}
+
+/// Replaces loops whose body only adds an odd amount to the current cell, such
+/// as `[-]`, with a `Clear`. Adding an odd amount always reaches zero
+/// eventually, whatever the width of the cells.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ClearLoop;
+
+impl IrPass for ClearLoop {
+    fn name(&self) -> &str {
+        "clearloop"
+    }
+
+    fn run(&mut self, program: &mut IrProgram) -> usize {
+        let mut rewritten = 0;
+        let nodes = std::mem::take(program.nodes_mut());
+        let mut cleared: Vec<IrNode> = Vec::with_capacity(nodes.len());
+        let mut position = 0;
+        while position < nodes.len() {
+            let window = &nodes[position..nodes.len().min(position + 3)];
+            if let [start, body, end] = window {
+                if start.op() == &IrOp::LoopStart
+                    && matches!(body.op(), IrOp::Add(n) if n % 2 != 0)
+                    && end.op() == &IrOp::LoopEnd
+                {
+                    cleared.push(IrNode::new(IrOp::Clear, start.source()));
+                    rewritten += 1;
+                    position += 3;
+                    continue;
+                }
+            }
+            cleared.push(nodes[position].clone());
+            position += 1;
+        }
+        *program.nodes_mut() = cleared;
+        rewritten
+    }
+}
+
+/// Replaces innermost loops which only add and move, return the head to where
+/// it started, and subtract one from the current cell each time around, with
+/// a `CopyLoop`.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct CopyLoop;
+
+impl CopyLoop {
+    /// Works out the factor to add to each offset from the head, if the body
+    /// of the loop is in the right form.
+    fn targets(body: &[IrNode]) -> Option<Vec<(isize, i32)>> {
+        let mut offset: isize = 0;
+        let mut deltas: BTreeMap<isize, i32> = BTreeMap::new();
+        for node in body {
+            match node.op() {
+                IrOp::Add(n) => {
+                    let delta = deltas.entry(offset).or_insert(0);
+                    *delta = delta.wrapping_add(*n);
+                }
+                IrOp::Move(n) => offset += n,
+                _ => return None,
+            }
+        }
+        if offset != 0 || deltas.remove(&0) != Some(-1) {
+            return None;
+        }
+        Some(
+            deltas
+                .into_iter()
+                .filter(|(_, factor)| *factor != 0)
+                .collect(),
+        )
+    }
+}
+
+impl IrPass for CopyLoop {
+    fn name(&self) -> &str {
+        "copyloop"
+    }
+
+    fn run(&mut self, program: &mut IrProgram) -> usize {
+        let mut rewritten = 0;
+        let nodes = std::mem::take(program.nodes_mut());
+        let mut copied: Vec<IrNode> = Vec::with_capacity(nodes.len());
+        let mut position = 0;
+        while position < nodes.len() {
+            if nodes[position].op() == &IrOp::LoopStart {
+                let body_end = nodes[position + 1..]
+                    .iter()
+                    .position(|node| {
+                        !matches!(node.op(), IrOp::Add(_) | IrOp::Move(_))
+                    })
+                    .map(|length| position + 1 + length);
+                if let Some(end) = body_end {
+                    if nodes[end].op() == &IrOp::LoopEnd {
+                        if let Some(targets) =
+                            CopyLoop::targets(&nodes[position + 1..end])
+                        {
+                            copied.push(IrNode::new(
+                                IrOp::CopyLoop(targets),
+                                nodes[position].source(),
+                            ));
+                            rewritten += 1;
+                            position = end + 1;
+                            continue;
+                        }
+                    }
+                }
+            }
+            copied.push(nodes[position].clone());
+            position += 1;
+        }
+        *program.nodes_mut() = copied;
+        rewritten
+    }
+}
+
+/// Replaces loops whose body only moves the head, such as `[>]`, with a scan
+/// for the next zero cell in that direction.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ScanLoop;
+
+impl ScanLoop {
+    /// Works out the scan for the body of a loop, if it only moves the head.
+    fn scan(body: &[IrNode]) -> Option<IrOp> {
+        let mut offset: isize = 0;
+        for node in body {
+            match node.op() {
+                IrOp::Move(n) => offset += n,
+                _ => return None,
+            }
+        }
+        match offset {
+            0 => None,
+            right if right > 0 => Some(IrOp::ScanRight(right.unsigned_abs())),
+            left => Some(IrOp::ScanLeft(left.unsigned_abs())),
+        }
+    }
+}
+
+impl IrPass for ScanLoop {
+    fn name(&self) -> &str {
+        "scanloop"
+    }
+
+    fn run(&mut self, program: &mut IrProgram) -> usize {
+        let mut rewritten = 0;
+        let nodes = std::mem::take(program.nodes_mut());
+        let mut scanned: Vec<IrNode> = Vec::with_capacity(nodes.len());
+        let mut position = 0;
+        while position < nodes.len() {
+            if nodes[position].op() == &IrOp::LoopStart {
+                let end = nodes[position + 1..]
+                    .iter()
+                    .position(|node| !matches!(node.op(), IrOp::Move(_)))
+                    .map(|length| position + 1 + length);
+                if let Some(end) = end {
+                    let scan = (nodes[end].op() == &IrOp::LoopEnd)
+                        .then(|| ScanLoop::scan(&nodes[position + 1..end]))
+                        .flatten();
+                    if let Some(scan) = scan {
+                        scanned
+                            .push(IrNode::new(scan, nodes[position].source()));
+                        rewritten += 1;
+                        position = end + 1;
+                        continue;
+                    }
+                }
+            }
+            scanned.push(nodes[position].clone());
+            position += 1;
+        }
+        *program.nodes_mut() = scanned;
+        rewritten
+    }
+}
+
+/// The value of a cell after running part of a loop's body, as a sum of
+/// multiples of the values the cells had at the start of the body, keyed by
+/// their offsets from the head, plus a constant. Arithmetic wraps around, as
+/// it does for every width of cell.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+struct Affine {
+    terms: BTreeMap<isize, i32>,
+    constant: i32,
+}
+
+impl Affine {
+    /// The value the cell at the offset had at the start of the body.
+    fn cell(offset: isize) -> Self {
+        Self {
+            terms: BTreeMap::from([(offset, 1)]),
+            constant: 0,
+        }
+    }
+
+    /// Adds the other value times the factor.
+    fn add_scaled(&mut self, other: &Affine, factor: i32) {
+        for (&offset, &coefficient) in &other.terms {
+            let term = self.terms.entry(offset).or_insert(0);
+            *term = term.wrapping_add(coefficient.wrapping_mul(factor));
+        }
+        self.terms.retain(|_, coefficient| *coefficient != 0);
+        self.constant = self
+            .constant
+            .wrapping_add(other.constant.wrapping_mul(factor));
+    }
+}
+
+/// Replaces loops which count the cell at the head down by one each time
+/// around, and otherwise only add to, clear and copy between nearby cells,
+/// with a single `MulAdd`. Nested loops such as `[>[->+>+<<]>>[-<<+>>]<<<-]`,
+/// which add the product of two cells to a third, become a multiplication.
+/// This works on the `CopyLoop`s left by the `copyloop` pass, so it runs
+/// after it.
+///
+/// Most such loops copy through a spare cell which is only known to be zero
+/// once the loop has been around once, so the first time around is left as it
+/// is, followed by a `MulAdd` for the rest.
+/// ```
+/// use bft_types::BfProgram;
+/// use bft_interp::ir::{IrOp, IrProgram, MulLoop};
+/// use bft_interp::optimizer::Pipeline;
+///
+/// let source = "[>[->+>+<<]>>[-<<+>>]<<<-]";
+/// let program = BfProgram::new(source.to_string(), "mul.bf").unwrap();
+/// let mut ir = IrProgram::from_program(&program).unwrap();
+/// Pipeline::from_spec("rle,copyloop,mulloop").unwrap().run(&mut ir);
+/// let mul = MulLoop {
+///     products: vec![(2, 1, 1)],
+///     adds: vec![],
+///     sets: vec![],
+/// };
+/// assert_eq!(ir.nodes()[7].op(), &IrOp::MulAdd(Box::new(mul)));
+/// assert_eq!(ir.nodes()[8].op(), &IrOp::LoopEnd);
+/// ```
+#[derive(Debug, Default, Clone, Copy)]
+pub struct MultiplyLoop;
+
+impl MultiplyLoop {
+    /// Works out the value of each cell the body of a loop changes, if it only
+    /// adds, clears and copies, and leaves the head where it started. Cells
+    /// with known values at the start of the body are given in `known`.
+    fn effect(
+        body: &[IrNode],
+        known: &BTreeMap<isize, i32>,
+    ) -> Option<BTreeMap<isize, Affine>> {
+        let initial = |offset: isize| match known.get(&offset) {
+            Some(&constant) => Affine {
+                terms: BTreeMap::new(),
+                constant,
+            },
+            None => Affine::cell(offset),
+        };
+        let mut cells: BTreeMap<isize, Affine> = BTreeMap::new();
+        let mut head: isize = 0;
+        for node in body {
+            if let IrOp::Move(by) = node.op() {
+                head += by;
+                continue;
+            }
+            if let IrOp::CopyLoop(targets) = node.op() {
+                let value = cells.remove(&head).unwrap_or(initial(head));
+                for (offset, factor) in targets {
+                    cells
+                        .entry(head + offset)
+                        .or_insert_with(|| initial(head + offset))
+                        .add_scaled(&value, *factor);
+                }
+                cells.insert(head, Affine::default());
+                continue;
+            }
+            let (offset, len, clear, amount) = match node.op() {
+                IrOp::Add(amount) => (0, 1, false, *amount),
+                IrOp::Clear => (0, 1, true, 0),
+                IrOp::AddAt(offset, amount) => (*offset, 1, false, *amount),
+                IrOp::ClearAt(offset) => (*offset, 1, true, 0),
+                IrOp::AddRange(offset, len, amount) => {
+                    (*offset, *len, false, *amount)
+                }
+                IrOp::ClearRange(offset, len) => (*offset, *len, true, 0),
+                _ => return None,
+            };
+            for cell in head + offset..head + offset + len as isize {
+                let value = cells.entry(cell).or_insert_with(|| initial(cell));
+                if clear {
+                    *value = Affine::default();
+                }
+                value.constant = value.constant.wrapping_add(amount);
+            }
+        }
+        if head != 0 {
+            return None;
+        }
+        // Cells which end up as they started are left out.
+        cells.retain(|&offset, value| *value != initial(offset));
+        Some(cells)
+    }
+
+    /// Works out the `MulAdd` for going around the loop with the body, given
+    /// the values of any cells known at the start of each time around, if it
+    /// counts the cell at the head down by one and multiplies by at least one
+    /// other cell.
+    fn lower(body: &[IrNode], known: &BTreeMap<isize, i32>) -> Option<MulLoop> {
+        let cells = Self::effect(body, known)?;
+        let counter = Affine {
+            terms: BTreeMap::from([(0, 1)]),
+            constant: -1,
+        };
+        if cells.get(&0) != Some(&counter) {
+            return None;
+        }
+        let mut mul = MulLoop::default();
+        for (&offset, value) in cells.iter().filter(|(&offset, _)| offset != 0)
+        {
+            if value.terms.is_empty() {
+                // The cell is set to the same value each time around.
+                mul.sets.push((offset, value.constant));
+                continue;
+            }
+            // Otherwise, the cell must be added to, by amounts which are the
+            // same each time around.
+            if value.terms.get(&offset) != Some(&1) {
+                return None;
+            }
+            for (&by, &factor) in &value.terms {
+                if by == offset {
+                    continue;
+                }
+                if by == 0 || cells.contains_key(&by) {
+                    return None;
+                }
+                mul.products.push((offset, by, factor));
+            }
+            if value.constant != 0 {
+                mul.adds.push((offset, value.constant));
+            }
+        }
+        (!mul.products.is_empty()).then_some(mul)
+    }
+
+    /// Works out the `MulAdd` for the whole loop, if the body can be
+    /// multiplied out from the start, or otherwise for every time around
+    /// after the first, once the cells it sets are known. Returns whether the
+    /// first time around must be run as it is.
+    fn multiply(body: &[IrNode]) -> Option<(MulLoop, bool)> {
+        if let Some(mul) = Self::lower(body, &BTreeMap::new()) {
+            return Some((mul, false));
+        }
+        let sets: BTreeMap<isize, i32> = Self::effect(body, &BTreeMap::new())?
+            .into_iter()
+            .filter(|(_, value)| value.terms.is_empty())
+            .map(|(offset, value)| (offset, value.constant))
+            .collect();
+        if sets.is_empty() {
+            return None;
+        }
+        Self::lower(body, &sets).map(|mul| (mul, true))
+    }
+}
+
+impl IrPass for MultiplyLoop {
+    fn name(&self) -> &str {
+        "mulloop"
+    }
+
+    fn run(&mut self, program: &mut IrProgram) -> usize {
+        let mut rewritten = 0;
+        let nodes = std::mem::take(program.nodes_mut());
+        let mut multiplied: Vec<IrNode> = Vec::with_capacity(nodes.len());
+        let mut position = 0;
+        while position < nodes.len() {
+            if nodes[position].op() == &IrOp::LoopStart {
+                let body_end = nodes[position + 1..]
+                    .iter()
+                    .position(|node| {
+                        matches!(node.op(), IrOp::LoopStart | IrOp::LoopEnd)
+                    })
+                    .map(|length| position + 1 + length);
+                if let Some(end) = body_end {
+                    if nodes[end].op() == &IrOp::LoopEnd {
+                        if let Some((mul, peeled)) =
+                            MultiplyLoop::multiply(&nodes[position + 1..end])
+                        {
+                            let source = nodes[position].source();
+                            if peeled {
+                                multiplied
+                                    .extend_from_slice(&nodes[position..end]);
+                            }
+                            multiplied.push(IrNode::new(
+                                IrOp::MulAdd(Box::new(mul)),
+                                source,
+                            ));
+                            if peeled {
+                                multiplied.push(nodes[end].clone());
+                            }
+                            rewritten += 1;
+                            position = end + 1;
+                            continue;
+                        }
+                    }
+                }
+            }
+            multiplied.push(nodes[position].clone());
+            position += 1;
+        }
+        *program.nodes_mut() = multiplied;
+        rewritten
+    }
+}
+
+/// Works out how far the nodes move the head, and how much they add to the
+/// cell at the offset from where the head starts, if they do nothing else to
+/// it. Loops must leave the head where they found it and the cell alone, and
+/// both branches of an `If` must do the same to both.
+fn cell_delta(nodes: &[IrNode], cell: isize) -> Option<(isize, i32)> {
+    let mut head: isize = 0;
+    let mut delta: i32 = 0;
+    let mut position = 0;
+    while let Some(node) = nodes.get(position) {
+        let at = cell - head;
+        match node.op() {
+            IrOp::Move(by) => head += by,
+            IrOp::LoopStart => {
+                let end = matching_end(nodes, position);
+                if cell_delta(nodes.get(position + 1..end)?, at)? != (0, 0) {
+                    return None;
+                }
+                position = end;
+            }
+            IrOp::If => {
+                let (middle, end) = matching_else(nodes, position)?;
+                let then = cell_delta(&nodes[position + 1..middle], at)?;
+                if cell_delta(&nodes[middle + 1..end], at)? != then {
+                    return None;
+                }
+                head += then.0;
+                delta = delta.wrapping_add(then.1);
+                position = end;
+            }
+            IrOp::Output | IrOp::OutputBytes(_) => {}
+            IrOp::Input if at != 0 => {}
+            IrOp::CopyLoop(targets)
+                if at != 0
+                    && targets.iter().all(|(offset, _)| *offset != at) => {}
+            IrOp::MulAdd(mul) if at != 0 && mul.offsets().all(|o| o != at) => {}
+            op => match stored_cells(op) {
+                Some((cells, _)) if !cells.contains(&at) => {}
+                Some((_, false)) => {
+                    let (IrOp::Add(amount)
+                    | IrOp::AddAt(_, amount)
+                    | IrOp::AddRange(_, _, amount)) = op
+                    else {
+                        return None;
+                    };
+                    delta = delta.wrapping_add(*amount);
+                }
+                _ => return None,
+            },
+        }
+        position += 1;
+    }
+    Some((head, delta))
+}
+
+/// The indices of the `Else` and `EndIf` matching the `If` at the index.
+fn matching_else(nodes: &[IrNode], start: usize) -> Option<(usize, usize)> {
+    let mut depth = 0;
+    let mut middle = None;
+    for (index, node) in nodes.iter().enumerate().skip(start) {
+        match node.op() {
+            IrOp::If => depth += 1,
+            IrOp::Else if depth == 1 => middle = Some(index),
+            IrOp::EndIf => {
+                depth -= 1;
+                if depth == 0 {
+                    return middle.map(|middle| (middle, index));
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Whether the cell at the offset from the head is known to be one after the
+/// nodes run, looking back through them to where it was last cleared.
+fn known_one(nodes: &[IrNode], cell: isize) -> bool {
+    let mut cell = cell;
+    let mut amount: i32 = 0;
+    for node in nodes.iter().rev() {
+        match node.op() {
+            IrOp::Move(by) => cell += by,
+            IrOp::LoopEnd
+            | IrOp::CopyLoop(_)
+            | IrOp::MulAdd(_)
+            | IrOp::ScanRight(_)
+            | IrOp::ScanLeft(_)
+                if cell == 0 =>
+            {
+                // Each of these leaves the cell at the head at zero.
+                return amount == 1;
+            }
+            IrOp::Output | IrOp::OutputBytes(_) => {}
+            IrOp::Input if cell != 0 => {}
+            IrOp::CopyLoop(targets)
+                if targets.iter().all(|(offset, _)| *offset != cell) => {}
+            IrOp::MulAdd(mul) if mul.offsets().all(|o| o != cell) => {}
+            op => match stored_cells(op) {
+                Some((cells, _)) if !cells.contains(&cell) => {}
+                Some((_, true)) => return amount == 1,
+                Some((_, false)) => {
+                    let (IrOp::Add(added)
+                    | IrOp::AddAt(_, added)
+                    | IrOp::AddRange(_, _, added)) = op
+                    else {
+                        return false;
+                    };
+                    amount = amount.wrapping_add(*added);
+                }
+                None => return false,
+            },
+        }
+    }
+    false
+}
+
+/// Replaces the usual if/else idiom, which sets a flag before a loop that
+/// clears the cell it tests, and then runs a second loop on the flag if the
+/// first loop did not clear it, with an `If`, `Else` and `EndIf`:
+///
+/// ```text
+/// flag[-]+ x[ then flag- x[-] ] flag[ else flag- ]
+/// ```
+///
+/// Code between the two loops, such as moving a copy of `x` back, runs on both
+/// branches. Leaving the pass out of the pipeline keeps the loops as they are,
+/// should it ever misfire.
+/// ```
+/// use bft_types::BfProgram;
+/// use bft_interp::ir::{IrOp, IrProgram};
+/// use bft_interp::optimizer::Pipeline;
+///
+/// let source = ">[-]+<,[>-<.[-]]>[-<.>]";
+/// let program = BfProgram::new(source.to_string(), "if.bf").unwrap();
+/// let mut ir = IrProgram::from_program(&program).unwrap();
+/// Pipeline::from_spec("rle,clearloop,ifelse").unwrap().run(&mut ir);
+/// let ops: Vec<&IrOp> = ir.nodes().iter().map(|node| node.op()).collect();
+/// assert_eq!(ops[5], &IrOp::If);
+/// assert!(ops.contains(&&IrOp::Else));
+/// assert_eq!(ops.last(), Some(&&IrOp::EndIf));
+/// ```
+#[derive(Debug, Default, Clone, Copy)]
+pub struct IfElse;
+
+impl IfElse {
+    /// Rewrites the nodes, including the bodies of any loops, counting the
+    /// if/else idioms replaced.
+    fn rewrite(nodes: &[IrNode], rewritten: &mut usize) -> Vec<IrNode> {
+        let mut branched: Vec<IrNode> = Vec::with_capacity(nodes.len());
+        let mut position = 0;
+        while let Some(node) = nodes.get(position) {
+            if node.op() != &IrOp::LoopStart {
+                branched.push(node.clone());
+                position += 1;
+                continue;
+            }
+            let end = matching_end(nodes, position);
+            let then = Self::rewrite(&nodes[position + 1..end], rewritten);
+            if let Some((between, otherwise, last)) =
+                Self::branches(&branched, &then, &nodes[end + 1..], rewritten)
+            {
+                branched.push(IrNode::new(IrOp::If, node.source()));
+                branched.extend(then);
+                branched.extend_from_slice(between);
+                branched.push(IrNode::new(
+                    IrOp::Else,
+                    nodes[end + 1 + between.len()].source(),
+                ));
+                branched.extend_from_slice(between);
+                branched.extend(otherwise);
+                branched.push(IrNode::new(
+                    IrOp::EndIf,
+                    nodes[end + 1 + last].source(),
+                ));
+                position = end + 1 + last + 1;
+                continue;
+            }
+            branched.push(node.clone());
+            branched.extend(then);
+            branched.push(nodes[end].clone());
+            position = end + 1;
+        }
+        branched
+    }
+
+    /// Checks whether the loop with the body `then`, coming after the nodes
+    /// `before`, starts the idiom with the nodes `after` it. If so, returns the
+    /// code between the two loops, the rewritten body of the second loop, and
+    /// the index in `after` of the end of the second loop.
+    fn branches<'n>(
+        before: &[IrNode],
+        then: &[IrNode],
+        after: &'n [IrNode],
+        rewritten: &mut usize,
+    ) -> Option<(&'n [IrNode], Vec<IrNode>, usize)> {
+        // The code between the loops must be straight-line, and leave the
+        // head on the flag.
+        let start = after.iter().position(|node| {
+            matches!(
+                node.op(),
+                IrOp::LoopStart
+                    | IrOp::LoopEnd
+                    | IrOp::If
+                    | IrOp::Else
+                    | IrOp::EndIf
+                    | IrOp::ScanRight(_)
+                    | IrOp::ScanLeft(_)
+                    | IrOp::Extension(_)
+            )
+        })?;
+        if after[start].op() != &IrOp::LoopStart {
+            return None;
+        }
+        let between = &after[..start];
+        let end = matching_end(after, start);
+        let flag: isize = between
+            .iter()
+            .map(|node| match node.op() {
+                IrOp::Move(by) => *by,
+                _ => 0,
+            })
+            .sum();
+        if flag == 0 {
+            return None;
+        }
+        // The flag is set to one, taken away from once by the first loop,
+        // which ends with the cell it tests cleared, and not touched by the
+        // code between the loops. The second loop takes it away from once.
+        let clears = matches!(
+            then.last().map(IrNode::op),
+            Some(IrOp::Clear | IrOp::CopyLoop(_) | IrOp::MulAdd(_))
+        );
+        if !clears
+            || cell_delta(then, flag)? != (0, -1)
+            || cell_delta(between, flag)? != (flag, 0)
+            || !known_one(before, flag)
+        {
+            return None;
+        }
+        let mut count = 0;
+        let otherwise = Self::rewrite(after.get(start + 1..end)?, &mut count);
+        if cell_delta(&otherwise, 0)? != (0, -1) {
+            return None;
+        }
+        *rewritten += count + 1;
+        Some((between, otherwise, end))
+    }
+}
+
+impl IrPass for IfElse {
+    fn name(&self) -> &str {
+        "ifelse"
+    }
+
+    fn run(&mut self, program: &mut IrProgram) -> usize {
+        let mut rewritten = 0;
+        let nodes = std::mem::take(program.nodes_mut());
+        *program.nodes_mut() = IfElse::rewrite(&nodes, &mut rewritten);
+        rewritten
+    }
+}
+
+/// The changes a basic block makes to a single cell, relative to the head at
+/// the start of the block.
+#[derive(Debug, Default, Clone, Copy)]
+struct CellChange {
+    /// Whether the cell is cleared, before the amount is added.
+    cleared: bool,
+    /// The amount added to the cell.
+    amount: i32,
+}
+
+/// Rewrites each basic block of adds, moves and clears so that cells are
+/// changed at offsets from the head, with a single move at the end of the
+/// block.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Offsets;
+
+impl Offsets {
+    /// Whether the node can be part of a basic block.
+    fn in_block(node: &IrNode) -> bool {
+        matches!(
+            node.op(),
+            IrOp::Add(_)
+                | IrOp::Move(_)
+                | IrOp::Clear
+                | IrOp::AddAt(..)
+                | IrOp::ClearAt(_)
+        )
+    }
+
+    /// Rewrites a single basic block, returning None if it would not get any
+    /// shorter.
+    fn rewrite(block: &[IrNode]) -> Option<Vec<IrNode>> {
+        let mut offset: isize = 0;
+        let mut changes: BTreeMap<isize, CellChange> = BTreeMap::new();
+        for node in block {
+            match *node.op() {
+                IrOp::Add(n) => Offsets::add(&mut changes, offset, n),
+                IrOp::AddAt(at, n) => {
+                    Offsets::add(&mut changes, offset + at, n)
+                }
+                IrOp::Clear => Offsets::clear(&mut changes, offset),
+                IrOp::ClearAt(at) => Offsets::clear(&mut changes, offset + at),
+                IrOp::Move(n) => offset += n,
+                _ => unreachable!("only block nodes are rewritten"),
+            }
+        }
+
+        let source = block[0].source();
+        let mut rewritten = Vec::new();
+        for (at, change) in changes {
+            if change.cleared {
+                let op = if at == 0 {
+                    IrOp::Clear
+                } else {
+                    IrOp::ClearAt(at)
+                };
+                rewritten.push(IrNode::new(op, source));
+            }
+            if change.amount != 0 {
+                let op = if at == 0 {
+                    IrOp::Add(change.amount)
+                } else {
+                    IrOp::AddAt(at, change.amount)
+                };
+                rewritten.push(IrNode::new(op, source));
+            }
+        }
+        if offset != 0 {
+            rewritten.push(IrNode::new(IrOp::Move(offset), source));
+        }
+        (rewritten.len() < block.len()).then_some(rewritten)
+    }
+
+    fn add(changes: &mut BTreeMap<isize, CellChange>, at: isize, n: i32) {
+        let change = changes.entry(at).or_default();
+        change.amount = change.amount.wrapping_add(n);
+    }
+
+    fn clear(changes: &mut BTreeMap<isize, CellChange>, at: isize) {
+        changes.insert(
+            at,
+            CellChange {
+                cleared: true,
+                amount: 0,
+            },
+        );
+    }
+}
+
+impl IrPass for Offsets {
+    fn name(&self) -> &str {
+        "offsets"
+    }
+
+    fn run(&mut self, program: &mut IrProgram) -> usize {
+        let mut rewritten = 0;
+        let nodes = std::mem::take(program.nodes_mut());
+        let mut offset: Vec<IrNode> = Vec::with_capacity(nodes.len());
+        for block in
+            nodes.chunk_by(|a, b| Offsets::in_block(a) == Offsets::in_block(b))
+        {
+            match Offsets::in_block(&block[0])
+                .then(|| Offsets::rewrite(block))
+                .flatten()
+            {
+                Some(nodes) => {
+                    offset.extend(nodes);
+                    rewritten += 1;
+                }
+                None => offset.extend_from_slice(block),
+            }
+        }
+        *program.nodes_mut() = offset;
+        rewritten
+    }
+}
+
+/// Replaces changes to runs of at least four neighbouring cells with a single
+/// `ClearRange` or `AddRange`, which change all of the cells at once. This
+/// works on the changes at offsets left by the `offsets` pass, so it runs after
+/// it.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Ranges;
+
+impl Ranges {
+    /// Whether the node changes a cell without moving the head.
+    fn in_run(node: &IrNode) -> bool {
+        matches!(
+            node.op(),
+            IrOp::Add(_) | IrOp::Clear | IrOp::AddAt(..) | IrOp::ClearAt(_)
+        )
+    }
+
+    /// Groups the offsets into runs of neighbouring cells with the same key,
+    /// leaving out those without one.
+    fn runs<K: PartialEq>(
+        changes: &BTreeMap<isize, CellChange>,
+        key: impl Fn(&CellChange) -> Option<K>,
+    ) -> Vec<(isize, usize, K)> {
+        let mut runs: Vec<(isize, usize, K)> = Vec::new();
+        for (at, change) in changes {
+            let Some(key) = key(change) else {
+                continue;
+            };
+            match runs.last_mut() {
+                Some((start, len, last))
+                    if *last == key && *start + *len as isize == *at =>
+                {
+                    *len += 1;
+                }
+                _ => runs.push((*at, 1, key)),
+            }
+        }
+        runs
+    }
+
+    /// Rewrites a run of changes to cells, returning None if there are no
+    /// ranges in it.
+    fn rewrite(run: &[IrNode]) -> Option<Vec<IrNode>> {
+        let mut changes: BTreeMap<isize, CellChange> = BTreeMap::new();
+        for node in run {
+            match *node.op() {
+                IrOp::Add(n) => Offsets::add(&mut changes, 0, n),
+                IrOp::AddAt(at, n) => Offsets::add(&mut changes, at, n),
+                IrOp::Clear => Offsets::clear(&mut changes, 0),
+                IrOp::ClearAt(at) => Offsets::clear(&mut changes, at),
+                _ => unreachable!("only changes to cells are rewritten"),
+            }
+        }
+        let clears =
+            Ranges::runs(&changes, |change| change.cleared.then_some(()));
+        let adds = Ranges::runs(&changes, |change| {
+            (change.amount != 0).then_some(change.amount)
+        });
+        let ranged = |len: &usize| *len >= MIN_RANGE_LEN;
+        if !clears.iter().any(|(_, len, _)| ranged(len))
+            && !adds.iter().any(|(_, len, _)| ranged(len))
+        {
+            return None;
+        }
+
+        // Each cell is cleared before it is added to, and cells which are not
+        // neighbours can be changed in any order.
+        let source = run[0].source();
+        let mut rewritten = Vec::new();
+        for (start, len, ()) in clears {
+            if len >= MIN_RANGE_LEN {
+                let op = IrOp::ClearRange(start, len);
+                rewritten.push(IrNode::new(op, source));
+                continue;
+            }
+            for at in start..start + len as isize {
+                let op = if at == 0 {
+                    IrOp::Clear
+                } else {
+                    IrOp::ClearAt(at)
+                };
+                rewritten.push(IrNode::new(op, source));
+            }
+        }
+        for (start, len, amount) in adds {
+            if len >= MIN_RANGE_LEN {
+                let op = IrOp::AddRange(start, len, amount);
+                rewritten.push(IrNode::new(op, source));
+                continue;
+            }
+            for at in start..start + len as isize {
+                let op = if at == 0 {
+                    IrOp::Add(amount)
+                } else {
+                    IrOp::AddAt(at, amount)
+                };
+                rewritten.push(IrNode::new(op, source));
+            }
+        }
+        Some(rewritten)
+    }
+}
+
+impl IrPass for Ranges {
+    fn name(&self) -> &str {
+        "ranges"
+    }
+
+    fn run(&mut self, program: &mut IrProgram) -> usize {
+        let mut rewritten = 0;
+        let nodes = std::mem::take(program.nodes_mut());
+        let mut ranged: Vec<IrNode> = Vec::with_capacity(nodes.len());
+        for run in nodes.chunk_by(|a, b| Ranges::in_run(a) == Ranges::in_run(b))
+        {
+            match Ranges::in_run(&run[0])
+                .then(|| Ranges::rewrite(run))
+                .flatten()
+            {
+                Some(nodes) => {
+                    rewritten += nodes
+                        .iter()
+                        .filter(|node| {
+                            matches!(
+                                node.op(),
+                                IrOp::AddRange(..) | IrOp::ClearRange(..)
+                            )
+                        })
+                        .count();
+                    ranged.extend(nodes);
+                }
+                None => ranged.extend_from_slice(run),
+            }
+        }
+        *program.nodes_mut() = ranged;
+        rewritten
+    }
+}
+
+/// Drops loops, along with clears, copies and scans, which are reached only
+/// when the cell at the head of the tape is known to be zero, and so can never
+/// do anything.
+/// ```
+/// use bft_types::BfProgram;
+/// use bft_interp::ir::{IrOp, IrProgram};
+/// use bft_interp::optimizer::{DeadLoop, IrPass};
+///
+/// let program = BfProgram::new("[comment]+[-][-]".to_string(), "dead.bf").unwrap();
+/// let mut ir = IrProgram::from_program(&program).unwrap();
+/// DeadLoop::on_fresh_tape().run(&mut ir);
+/// let ops: Vec<&IrOp> = ir.nodes().iter().map(|node| node.op()).collect();
+/// assert_eq!(
+///     ops,
+///     [&IrOp::Add(1), &IrOp::LoopStart, &IrOp::Add(-1), &IrOp::LoopEnd]
+/// );
+/// ```
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DeadLoop {
+    /// Whether the program starts with every cell of the tape at zero.
+    fresh_tape: bool,
+}
+
+impl DeadLoop {
+    /// Creates the pass for programs which start on a fresh tape, so that
+    /// loops before anything is written to the tape, such as the comment
+    /// loops at the start of many programs, are dropped too.
+    pub fn on_fresh_tape() -> Self {
+        Self { fresh_tape: true }
+    }
+}
+
+impl IrPass for DeadLoop {
+    fn name(&self) -> &str {
+        "deadloop"
+    }
+
+    fn run(&mut self, program: &mut IrProgram) -> usize {
+        let nodes = std::mem::take(program.nodes_mut());
+        let mut kept: Vec<IrNode> = Vec::with_capacity(nodes.len());
+        // Whether nothing has been written to the fresh tape yet, so that
+        // every cell is zero, and whether the cell at the head is zero.
+        let mut untouched = self.fresh_tape;
+        let mut zero = self.fresh_tape;
+        let mut position = 0;
+        while position < nodes.len() {
+            let node = &nodes[position];
+            position += 1;
+            match node.op() {
+                IrOp::LoopStart if zero => {
+                    let mut depth = 1;
+                    while depth > 0 {
+                        match nodes[position].op() {
+                            IrOp::LoopStart => depth += 1,
+                            IrOp::LoopEnd => depth -= 1,
+                            _ => {}
+                        }
+                        position += 1;
+                    }
+                    continue;
+                }
+                IrOp::Clear
+                | IrOp::CopyLoop(_)
+                | IrOp::MulAdd(_)
+                | IrOp::ScanRight(_)
+                | IrOp::ScanLeft(_)
+                    if zero =>
+                {
+                    continue
+                }
+                IrOp::LoopEnd
+                | IrOp::Clear
+                | IrOp::CopyLoop(_)
+                | IrOp::MulAdd(_)
+                | IrOp::ScanRight(_)
+                | IrOp::ScanLeft(_)
+                | IrOp::ClearAt(0) => zero = true,
+                IrOp::ClearRange(offset, len)
+                    if (*offset..*offset + *len as isize).contains(&0) =>
+                {
+                    zero = true
+                }
+                IrOp::Move(_) => zero = untouched,
+                IrOp::AddAt(offset, _) if *offset != 0 => untouched = false,
+                IrOp::AddRange(offset, len, _)
+                    if !(*offset..*offset + *len as isize).contains(&0) =>
+                {
+                    untouched = false
+                }
+                IrOp::LoopStart
+                | IrOp::Output
+                | IrOp::OutputBytes(_)
+                | IrOp::ClearAt(_)
+                | IrOp::ClearRange(..) => {}
+                IrOp::Add(_)
+                | IrOp::AddAt(..)
+                | IrOp::AddRange(..)
+                | IrOp::Input
+                | IrOp::LoadTape(_)
+                | IrOp::Extension(_)
+                | IrOp::If
+                | IrOp::Else
+                | IrOp::EndIf => {
+                    untouched = false;
+                    zero = false;
+                }
+            }
+            kept.push(node.clone());
+        }
+        *program.nodes_mut() = kept;
+        0
+    }
+}
+
+/// The cells changed by an op, relative to the head, and whether it sets them
+/// to zero rather than adding to them, for ops which change cells at offsets.
+fn stored_cells(op: &IrOp) -> Option<(std::ops::Range<isize>, bool)> {
+    match op {
+        IrOp::Add(_) => Some((0..1, false)),
+        IrOp::Clear => Some((0..1, true)),
+        IrOp::AddAt(offset, _) => Some((*offset..offset + 1, false)),
+        IrOp::ClearAt(offset) => Some((*offset..offset + 1, true)),
+        IrOp::AddRange(offset, len, _) => {
+            Some((*offset..offset + *len as isize, false))
+        }
+        IrOp::ClearRange(offset, len) => {
+            Some((*offset..offset + *len as isize, true))
+        }
+        _ => None,
+    }
+}
+
+/// Drops changes to cells which are cleared again before anything reads them,
+/// such as the add in `+>.<[-]`. Only straight-line code is looked at, so
+/// changes are kept if a loop, a scan or an extension comes before the clear,
+/// as is anything left in a cell at the end of the program, which can still
+/// be seen on the tape.
+/// ```
+/// use bft_types::BfProgram;
+/// use bft_interp::ir::{IrOp, IrProgram};
+/// use bft_interp::optimizer::Pipeline;
+///
+/// let program = BfProgram::new("+++>--<.>[-]".to_string(), "dead.bf").unwrap();
+/// let mut ir = IrProgram::from_program(&program).unwrap();
+/// Pipeline::from_spec("rle,clearloop,deadstore").unwrap().run(&mut ir);
+/// let ops: Vec<&IrOp> = ir.nodes().iter().map(|node| node.op()).collect();
+/// assert_eq!(
+///     ops,
+///     [
+///         &IrOp::Add(3),
+///         &IrOp::Move(1),
+///         &IrOp::Move(-1),
+///         &IrOp::Output,
+///         &IrOp::Move(1),
+///         &IrOp::Clear
+///     ]
+/// );
+/// ```
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DeadStore;
+
+impl DeadStore {
+    /// Marks the changes in the run of straight-line code which are cleared
+    /// before being read, working back from the end of the run.
+    fn mark(nodes: &[IrNode], dead: &mut [bool]) {
+        let mut positions = Vec::with_capacity(nodes.len());
+        let mut head = 0isize;
+        for node in nodes {
+            positions.push(head);
+            if let IrOp::Move(by) = node.op() {
+                head += by;
+            }
+        }
+        // The cells which are cleared later on without being read first.
+        let mut cleared = std::collections::BTreeSet::new();
+        for (index, node) in nodes.iter().enumerate().rev() {
+            let head = positions[index];
+            match node.op() {
+                IrOp::Output | IrOp::Input => {
+                    cleared.remove(&head);
+                }
+                IrOp::CopyLoop(targets) => {
+                    cleared.remove(&head);
+                    for (offset, _) in targets {
+                        cleared.remove(&(head + offset));
+                    }
+                }
+                IrOp::MulAdd(mul) => {
+                    cleared.remove(&head);
+                    for offset in mul.offsets() {
+                        cleared.remove(&(head + offset));
+                    }
+                }
+                op => {
+                    let Some((cells, clears)) = stored_cells(op) else {
+                        continue;
+                    };
+                    let cells = cells.start + head..cells.end + head;
+                    if cells.clone().all(|cell| cleared.contains(&cell)) {
+                        dead[index] = true;
+                    }
+                    if clears {
+                        cleared.extend(cells);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl IrPass for DeadStore {
+    fn name(&self) -> &str {
+        "deadstore"
+    }
+
+    fn run(&mut self, program: &mut IrProgram) -> usize {
+        let nodes = std::mem::take(program.nodes_mut());
+        let mut dead = vec![false; nodes.len()];
+        let mut start = 0;
+        for (index, node) in nodes.iter().enumerate() {
+            let ends_run = matches!(
+                node.op(),
+                IrOp::LoopStart
+                    | IrOp::LoopEnd
+                    | IrOp::If
+                    | IrOp::Else
+                    | IrOp::EndIf
+                    | IrOp::ScanRight(_)
+                    | IrOp::ScanLeft(_)
+                    | IrOp::LoadTape(_)
+                    | IrOp::Extension(_)
+            );
+            if ends_run {
+                Self::mark(&nodes[start..index], &mut dead[start..index]);
+                start = index + 1;
+            }
+        }
+        Self::mark(&nodes[start..], &mut dead[start..]);
+        *program.nodes_mut() = nodes
+            .into_iter()
+            .zip(dead)
+            .filter(|(_, dead)| !dead)
+            .map(|(node, _)| node)
+            .collect();
+        0
+    }
+}
+
+/// Whether a loop with the body can never end once it has started, as its
+/// body leaves the head where it found it and never changes the cell there.
+fn never_ends(body: &[IrNode]) -> bool {
+    let mut head = 0isize;
+    for node in body {
+        match node.op() {
+            IrOp::Move(by) => head += by,
+            IrOp::Output | IrOp::OutputBytes(_) => {}
+            IrOp::Input if head != 0 => {}
+            op => match stored_cells(op) {
+                Some((cells, _)) if !cells.contains(&-head) => {}
+                _ => return false,
+            },
+        }
+    }
+    head == 0
+}
+
+/// Drops the code after a loop which can never end, such as the `[]` in
+/// `[-]+[]`, which is entered with the cell at the head known to be nonzero
+/// and never changes it. The rest of the loop it is in, or of the program,
+/// can never be reached.
+/// ```
+/// use bft_types::BfProgram;
+/// use bft_interp::ir::{IrOp, IrProgram};
+/// use bft_interp::optimizer::{IrPass, Unreachable};
+///
+/// let program = BfProgram::new("[-]+[.]>+.".to_string(), "hang.bf").unwrap();
+/// let mut ir = IrProgram::from_program(&program).unwrap();
+/// Unreachable::default().run(&mut ir);
+/// let ops: Vec<&IrOp> = ir.nodes().iter().map(|node| node.op()).collect();
+/// assert_eq!(ops.len(), 7);
+/// assert_eq!(ops[6], &IrOp::LoopEnd);
+/// ```
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Unreachable {
+    /// Whether the program starts with every cell of the tape at zero.
+    fresh_tape: bool,
+}
+
+impl Unreachable {
+    /// Creates the pass for programs which start on a fresh tape, so that the
+    /// value of the cell at the head is known before anything is written to
+    /// it, as in `+[]`.
+    pub fn on_fresh_tape() -> Self {
+        Self { fresh_tape: true }
+    }
+}
+
+impl IrPass for Unreachable {
+    fn name(&self) -> &str {
+        "unreachable"
+    }
+
+    fn run(&mut self, program: &mut IrProgram) -> usize {
+        let nodes = std::mem::take(program.nodes_mut());
+        let mut kept: Vec<IrNode> = Vec::with_capacity(nodes.len());
+        // Whether nothing has been written to the fresh tape yet, and the
+        // value of the cell at the head, if it is known. Values are kept
+        // small enough to be nonzero for every width of cell.
+        let mut untouched = self.fresh_tape;
+        let mut value = self.fresh_tape.then_some(0i64);
+        let mut position = 0;
+        while position < nodes.len() {
+            let node = &nodes[position];
+            if node.op() == &IrOp::LoopStart
+                && value.is_some_and(|value| value != 0)
+            {
+                let end = matching_end(&nodes, position);
+                if end < nodes.len() && never_ends(&nodes[position + 1..end]) {
+                    kept.extend_from_slice(&nodes[position..=end]);
+                    // Skip to the end of the loop or branch this one is in, if
+                    // any.
+                    position = end + 1;
+                    let mut depth = 0;
+                    while let Some(node) = nodes.get(position) {
+                        match node.op() {
+                            IrOp::LoopStart | IrOp::If => depth += 1,
+                            IrOp::LoopEnd | IrOp::Else | IrOp::EndIf
+                                if depth == 0 =>
+                            {
+                                break
+                            }
+                            IrOp::LoopEnd | IrOp::EndIf => depth -= 1,
+                            _ => {}
+                        }
+                        position += 1;
+                    }
+                    untouched = false;
+                    value = None;
+                    continue;
+                }
+            }
+            match node.op() {
+                IrOp::Add(amount) => {
+                    value = value
+                        .map(|value| value + i64::from(*amount))
+                        .filter(|value| value.unsigned_abs() < 256);
+                    untouched = false;
+                }
+                IrOp::Move(_) => value = untouched.then_some(0),
+                IrOp::LoopEnd
+                | IrOp::Clear
+                | IrOp::ScanRight(_)
+                | IrOp::ScanLeft(_) => value = Some(0),
+                IrOp::CopyLoop(_) | IrOp::MulAdd(_) => {
+                    value = Some(0);
+                    untouched = false;
+                }
+                IrOp::Output | IrOp::OutputBytes(_) => {}
+                op => match stored_cells(op) {
+                    Some((cells, true)) if cells.contains(&0) => {
+                        value = Some(0)
+                    }
+                    Some((cells, _)) if !cells.contains(&0) => {
+                        untouched = false
+                    }
+                    _ => {
+                        untouched = false;
+                        value = None;
+                    }
+                },
+            }
+            kept.push(node.clone());
+            position += 1;
+        }
+        *program.nodes_mut() = kept;
+        0
+    }
+}
+
+/// The index of the `LoopEnd` matching the `LoopStart` at the index.
+fn matching_end(nodes: &[IrNode], start: usize) -> usize {
+    let mut depth = 0;
+    for (index, node) in nodes.iter().enumerate().skip(start) {
+        match node.op() {
+            IrOp::LoopStart => depth += 1,
+            IrOp::LoopEnd => depth -= 1,
+            _ => {}
+        }
+        if depth == 0 {
+            return index;
+        }
+    }
+    nodes.len()
+}
+
+/// Creates the built-in pass with the given name.
+pub fn builtin_pass(name: &str) -> Option<Box<dyn IrPass>> {
+    match name {
+        "rle" => Some(Box::new(RunLength)),
+        "clearloop" => Some(Box::new(ClearLoop)),
+        "copyloop" => Some(Box::new(CopyLoop)),
+        "scanloop" => Some(Box::new(ScanLoop)),
+        "mulloop" => Some(Box::new(MultiplyLoop)),
+        "ifelse" => Some(Box::new(IfElse)),
+        "offsets" => Some(Box::new(Offsets)),
+        "ranges" => Some(Box::new(Ranges)),
+        "deadstore" => Some(Box::new(DeadStore)),
+        "unreachable" => Some(Box::new(Unreachable::default())),
+        _ => None,
+    }
+}
+
+/// Creates a pass which is not built in, such as one from a plugin, for each
+/// pipeline which names it.
+pub type PassFactory = Box<dyn Fn() -> Box<dyn IrPass> + Send + Sync>;
+
+/// What a single pass did to a program.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PassStats {
+    name: String,
+    before: usize,
+    after: usize,
+    rewritten: usize,
+}
+
+impl PassStats {
+    /// The name of the pass.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The number of nodes in the program before the pass ran.
+    pub fn before(&self) -> usize {
+        self.before
+    }
+
+    /// The number of nodes in the program after the pass ran.
+    pub fn after(&self) -> usize {
+        self.after
+    }
+
+    /// The number of nodes removed by the pass overall.
+    pub fn removed(&self) -> usize {
+        self.before.saturating_sub(self.after)
+    }
+
+    /// The number of nodes the pass created to replace existing ones.
+    pub fn rewritten(&self) -> usize {
+        self.rewritten
+    }
+}
+
+impl fmt::Display for PassStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} -> {} ops ({} removed, {} rewritten)",
+            self.name,
+            self.before,
+            self.after,
+            self.removed(),
+            self.rewritten
+        )
+    }
+}
+
+/// An ordered list of passes to run over a program.
+#[derive(Default)]
+pub struct Pipeline {
+    passes: Vec<Box<dyn IrPass>>,
+}
+
+impl Pipeline {
+    /// Creates a pipeline with no passes.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a pipeline with all of the built-in passes, in their default
+    /// order.
+    pub fn builtin() -> Self {
+        let mut pipeline = Self::new();
+        for name in BUILTIN_PASSES {
+            pipeline.passes.extend(builtin_pass(name));
+        }
+        pipeline
+    }
+
+    /// Creates a pipeline from a comma separated list of built-in pass names,
+    /// as given on the command line. Named passes run in the order given, and
+    /// names prefixed with `-` are left out. If only passes to leave out are
+    /// given, the rest of the built-in passes run. `none` gives a pipeline
+    /// with no passes.
+    /// ```
+    /// use bft_interp::optimizer::Pipeline;
+    ///
+    /// let pipeline = Pipeline::from_spec("-clearloop").unwrap();
+    /// assert_eq!(
+    ///     pipeline.names(),
+    ///     [
+    ///         "rle",
+    ///         "copyloop",
+    ///         "scanloop",
+    ///         "mulloop",
+    ///         "ifelse",
+    ///         "offsets",
+    ///         "ranges",
+    ///         "deadstore",
+    ///         "unreachable"
+    ///     ]
+    /// );
+    ///
+    /// let pipeline = Pipeline::from_spec("copyloop,rle").unwrap();
+    /// assert_eq!(pipeline.names(), ["copyloop", "rle"]);
+    ///
+    /// assert!(Pipeline::from_spec("rle,unroll").is_err());
+    /// ```
+    pub fn from_spec(spec: &str) -> Result<Self, String> {
+        Self::from_spec_with(spec, &BTreeMap::new())
+    }
+
+    /// Creates a pipeline from a list of passes as `from_spec()` does, where
+    /// the passes may also be those of the given factories, by their names.
+    /// These only run when they are named, not when the built-in passes run
+    /// because only passes to leave out are given.
+    /// ```
+    /// use std::collections::BTreeMap;
+    ///
+    /// use bft_interp::optimizer::{builtin_pass, PassFactory, Pipeline};
+    ///
+    /// let mut extra: BTreeMap<String, PassFactory> = BTreeMap::new();
+    /// extra.insert(
+    ///     "rle-again".to_string(),
+    ///     Box::new(|| builtin_pass("rle").unwrap()),
+    /// );
+    /// let pipeline = Pipeline::from_spec_with("rle,rle-again", &extra);
+    /// assert_eq!(pipeline.unwrap().names(), ["rle", "rle"]);
+    /// let pipeline = Pipeline::from_spec_with("-rle", &extra).unwrap();
+    /// assert_eq!(pipeline.names().len(), 9);
+    /// ```
+    pub fn from_spec_with(
+        spec: &str,
+        extra: &BTreeMap<String, PassFactory>,
+    ) -> Result<Self, String> {
+        let mut selected: Vec<&str> = Vec::new();
+        let mut disabled: Vec<&str> = Vec::new();
+        for entry in spec.split(',').map(str::trim) {
+            if entry == "none" {
+                return Ok(Self::new());
+            }
+            let (list, name) = match entry.strip_prefix('-') {
+                Some(name) => (&mut disabled, name),
+                None => (&mut selected, entry),
+            };
+            if !BUILTIN_PASSES.contains(&name) && !extra.contains_key(name) {
+                let known: Vec<&str> = BUILTIN_PASSES
+                    .into_iter()
+                    .chain(extra.keys().map(String::as_str))
+                    .collect();
+                return Err(format!(
+                    "unknown optimization pass '{}', expected one of {}",
+                    name,
+                    known.join(", ")
+                ));
+            }
+            list.push(name);
+        }
+        if selected.is_empty() {
+            selected = BUILTIN_PASSES.to_vec();
+        }
+        let mut pipeline = Self::new();
+        for name in selected {
+            if !disabled.contains(&name) {
+                pipeline.passes.extend(
+                    builtin_pass(name)
+                        .or_else(|| extra.get(name).map(|create| create())),
+                );
+            }
+        }
+        Ok(pipeline)
+    }
+
+    /// Adds a pass to the end of the pipeline.
+    pub fn push(&mut self, pass: Box<dyn IrPass>) -> &mut Self {
+        self.passes.push(pass);
+        self
+    }
+
+    /// Inserts a pass at the given position in the pipeline.
+    pub fn insert(&mut self, index: usize, pass: Box<dyn IrPass>) -> &mut Self {
+        self.passes.insert(index, pass);
+        self
+    }
+
+    /// Removes the first pass with the given name from the pipeline.
+    pub fn remove(&mut self, name: &str) -> Option<Box<dyn IrPass>> {
+        let index = self.passes.iter().position(|pass| pass.name() == name)?;
+        Some(self.passes.remove(index))
+    }
+
+    /// The names of the passes, in the order they run.
+    pub fn names(&self) -> Vec<&str> {
+        self.passes.iter().map(|pass| pass.name()).collect()
+    }
+
+    /// Runs each pass over the program in turn, returning what each of them
+    /// did.
+    pub fn run(&mut self, program: &mut IrProgram) -> Vec<PassStats> {
+        self.passes
+            .iter_mut()
+            .map(|pass| {
+                let before = program.nodes().len();
+                let rewritten = pass.run(program);
+                PassStats {
+                    name: pass.name().to_string(),
+                    before,
+                    after: program.nodes().len(),
+                    rewritten,
+                }
+            })
+            .collect()
+    }
+}
+
+impl fmt::Debug for Pipeline {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Pipeline")
+            .field("passes", &self.names())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        ClearLoop, CopyLoop, DeadLoop, DeadStore, IfElse, IrPass, MultiplyLoop,
+        Offsets, Pipeline, Ranges, RunLength, ScanLoop, Unreachable,
+    };
+    use crate::ir::{IrNode, IrOp, IrProgram, MulLoop};
+    use bft_types::BfProgram;
+
+    fn lower(source: &str) -> IrProgram {
+        let program = BfProgram::new(source.to_string(), "opt.bf").unwrap();
+        IrProgram::from_program(&program).unwrap()
+    }
+
+    fn ops(program: &IrProgram) -> Vec<IrOp> {
+        program.nodes().iter().map(IrNode::op).cloned().collect()
+    }
+
+    #[test]
+    fn test_run_length() {
+        let mut program = lower("+++-->><<<.+-");
+        assert_eq!(RunLength.run(&mut program), 3);
+        assert_eq!(ops(&program), [IrOp::Add(1), IrOp::Move(-1), IrOp::Output]);
+        assert_eq!(program.nodes()[1].source().column(), 6);
+    }
+
+    #[test]
+    fn test_clear_loop() {
+        let mut program = lower("+[-]>[+++]>[--]");
+        RunLength.run(&mut program);
+        assert_eq!(ClearLoop.run(&mut program), 2);
+        assert_eq!(
+            ops(&program),
+            [
+                IrOp::Add(1),
+                IrOp::Clear,
+                IrOp::Move(1),
+                IrOp::Clear,
+                IrOp::Move(1),
+                IrOp::LoopStart,
+                IrOp::Add(-2),
+                IrOp::LoopEnd
+            ]
+        );
+    }
+
+    #[test]
+    fn test_copy_loop() {
+        let mut program = lower("[->+>++<<]>[->+<<<+>>]>[->+]>[-[->+<]]");
+        assert_eq!(CopyLoop.run(&mut program), 3);
+        let ops = ops(&program);
+        assert_eq!(ops[0], IrOp::CopyLoop(vec![(1, 1), (2, 2)]));
+        assert_eq!(ops[2], IrOp::CopyLoop(vec![(-2, 1), (1, 1)]));
+        // Loops which move the head, or which are not innermost, are kept.
+        assert_eq!(ops[4], IrOp::LoopStart);
+        assert!(ops.contains(&IrOp::CopyLoop(vec![(1, 1)])));
+    }
+
+    #[test]
+    fn test_multiply_loop() {
+        let lowered = |source: &str| {
+            let mut program = lower(source);
+            Pipeline::from_spec("rle,clearloop,copyloop")
+                .unwrap()
+                .run(&mut program);
+            let rewritten = MultiplyLoop.run(&mut program);
+            (rewritten, ops(&program))
+        };
+        // The spare cell is cleared before it is used, so the whole loop can
+        // be multiplied out.
+        let (rewritten, ops) = lowered("[>>>[-]<<[->+>+<<]>>[-<<+>>]<<<-]");
+        assert_eq!(rewritten, 1);
+        assert_eq!(
+            ops,
+            [IrOp::MulAdd(Box::new(MulLoop {
+                products: vec![(2, 1, 1)],
+                adds: vec![],
+                sets: vec![(3, 0)],
+            }))]
+        );
+        // Otherwise the first time around is run as it is. Here the loop
+        // multiplies by a cell on the left, through a spare cell further left,
+        // and adds the same amount each time around.
+        let (rewritten, ops) = lowered("[<[-<+>>>+++<<]<[->+<]>>>>+<<-]");
+        assert_eq!(rewritten, 1);
+        assert_eq!(ops.len(), 11);
+        assert_eq!(ops[0], IrOp::LoopStart);
+        assert_eq!(
+            ops[9..],
+            [
+                IrOp::MulAdd(Box::new(MulLoop {
+                    products: vec![(1, -1, 3)],
+                    adds: vec![(2, 1)],
+                    sets: vec![],
+                })),
+                IrOp::LoopEnd
+            ]
+        );
+        // Loops which count down by more than one, multiply by a cell they
+        // change or by the count itself, move the head, or have output, are
+        // left alone.
+        for source in [
+            "[>[->+>+<<]>>[-<<+>>]<<<--]",
+            "[>[->+<]<-]",
+            "[[->+>+<<]>>[-<<+>>]<<-]",
+            "[>[->+>+<<]>>[-<<+>>]<<-]",
+            "[>[->+>+<<]>>[-<<+>>]<.<<-]",
+            "[>>+<<-]",
+        ] {
+            assert_eq!(lowered(source).0, 0, "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_if_else() {
+        let branched = |source: &str| {
+            let mut program = lower(source);
+            Pipeline::from_spec("rle,clearloop")
+                .unwrap()
+                .run(&mut program);
+            let rewritten = IfElse.run(&mut program);
+            (rewritten, ops(&program))
+        };
+        // The move onto the flag between the loops runs on both branches.
+        let (rewritten, ops) = branched(">[-]+<[>-<.[-]]>[-<,>]");
+        assert_eq!(rewritten, 1);
+        assert_eq!(
+            ops[4..],
+            [
+                IrOp::If,
+                IrOp::Move(1),
+                IrOp::Add(-1),
+                IrOp::Move(-1),
+                IrOp::Output,
+                IrOp::Clear,
+                IrOp::Move(1),
+                IrOp::Else,
+                IrOp::Move(1),
+                IrOp::Add(-1),
+                IrOp::Move(-1),
+                IrOp::Input,
+                IrOp::Move(1),
+                IrOp::EndIf
+            ]
+        );
+        // Idioms inside loops and branches are replaced too.
+        let (rewritten, _) =
+            branched(",[>[-]+<[>>[-]+<<[>>-<<[-]]>>[-.]<-<[-]]>[-<+>]<,]");
+        assert_eq!(rewritten, 2);
+        // The flag must be known to be one, the first loop must clear the cell
+        // it tests, and each loop must take one from the flag, which nothing
+        // else may change.
+        for source in [
+            ">+<[>-<[-]]>[-]",
+            ">[-]++<[>-<[-]]>[-]",
+            ">[-]+<[>-<.]>[-]",
+            ">[-]+<[[-]]>[-]",
+            ">[-]+<[>-<[-]]>+[-]",
+            ">[-]+<[>-<[-]]>[.]",
+            ">[-]+<[>-<[-]]>[->]",
+            ">[-]+<[>-<[-]][-]",
+        ] {
+            assert_eq!(branched(source).0, 0, "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_dead_loop() {
+        // Without a fresh tape, only loops after a loop is left are dropped.
+        let mut program = lower("[.][,]>[+]+[-[-]]");
+        DeadLoop::default().run(&mut program);
+        assert_eq!(
+            ops(&program),
+            [
+                IrOp::LoopStart,
+                IrOp::Output,
+                IrOp::LoopEnd,
+                IrOp::Move(1),
+                IrOp::LoopStart,
+                IrOp::Add(1),
+                IrOp::LoopEnd,
+                IrOp::Add(1),
+                IrOp::LoopStart,
+                IrOp::Add(-1),
+                IrOp::LoopStart,
+                IrOp::Add(-1),
+                IrOp::LoopEnd,
+                IrOp::LoopEnd,
+            ]
+        );
+        let mut program = lower(">[.]<[,]+");
+        DeadLoop::on_fresh_tape().run(&mut program);
+        assert_eq!(
+            ops(&program),
+            [IrOp::Move(1), IrOp::Move(-1), IrOp::Add(1)]
+        );
+    }
+
+    #[test]
+    fn test_dead_store() {
+        // The first cell is changed twice, then cleared again after the
+        // output of the next cell, while the copy reads the cells it adds to.
+        let mut program = lower("++>.<[-]>+++[->+<]>-<<[-]+>[-]");
+        Pipeline::from_spec("rle,clearloop,copyloop")
+            .unwrap()
+            .run(&mut program);
+        DeadStore.run(&mut program);
+        assert_eq!(
+            ops(&program),
+            [
+                IrOp::Move(1),
+                IrOp::Output,
+                IrOp::Move(-1),
+                IrOp::Move(1),
+                IrOp::Add(3),
+                IrOp::CopyLoop(vec![(1, 1)]),
+                IrOp::Move(1),
+                IrOp::Add(-1),
+                IrOp::Move(-2),
+                IrOp::Clear,
+                IrOp::Add(1),
+                IrOp::Move(1),
+                IrOp::Clear,
+            ]
+        );
+        // Loops end the straight-line code looked at.
+        let mut program = lower("+[.][-]");
+        ClearLoop.run(&mut program);
+        DeadStore.run(&mut program);
+        assert_eq!(program.nodes().len(), 5);
+    }
+
+    #[test]
+    fn test_unreachable() {
+        let mut program = lower("+[>[-]+[<>.]>+]>+");
+        RunLength.run(&mut program);
+        Unreachable::default().run(&mut program);
+        assert_eq!(
+            ops(&program),
+            [
+                IrOp::Add(1),
+                IrOp::LoopStart,
+                IrOp::Move(1),
+                IrOp::LoopStart,
+                IrOp::Add(-1),
+                IrOp::LoopEnd,
+                IrOp::Add(1),
+                IrOp::LoopStart,
+                IrOp::Output,
+                IrOp::LoopEnd,
+                IrOp::LoopEnd,
+                IrOp::Move(1),
+                IrOp::Add(1),
+            ]
+        );
+        // Loops which change the cell at the head, or move away, can end.
+        for source in ["[-]+[-]>", "[-]+[>]>", "[-]+[,]>", "[-]+[+[]]>"] {
+            let mut program = lower(source);
+            Unreachable::default().run(&mut program);
+            assert_eq!(program.nodes().last().unwrap().op(), &IrOp::Move(1));
+        }
+        let mut program = lower("+[]>");
+        Unreachable::on_fresh_tape().run(&mut program);
+        assert_eq!(
+            ops(&program),
+            [IrOp::Add(1), IrOp::LoopStart, IrOp::LoopEnd]
+        );
+    }
+
+    #[test]
+    fn test_scan_loop() {
+        let mut program = lower("[>]+[<<]+[>>><]+[>-]+[<>]");
+        assert_eq!(ScanLoop.run(&mut program), 3);
+        let ops = ops(&program);
+        assert_eq!(ops[0], IrOp::ScanRight(1));
+        assert_eq!(ops[2], IrOp::ScanLeft(2));
+        assert_eq!(ops[4], IrOp::ScanRight(2));
+        // Loops which change cells, or which do not move overall, are kept.
+        assert_eq!(ops[6], IrOp::LoopStart);
+        assert_eq!(ops[11], IrOp::LoopStart);
+    }
+
+    #[test]
+    fn test_offsets() {
+        let mut program = lower(">+++<.>>[-]+<<<-->[>+<-]+>");
+        RunLength.run(&mut program);
+        ClearLoop.run(&mut program);
+        assert_eq!(Offsets.run(&mut program), 3);
+        assert_eq!(
+            ops(&program),
+            [
+                IrOp::AddAt(1, 3),
+                IrOp::Output,
+                IrOp::AddAt(-1, -2),
+                IrOp::ClearAt(2),
+                IrOp::AddAt(2, 1),
+                IrOp::LoopStart,
+                IrOp::Add(-1),
+                IrOp::AddAt(1, 1),
+                IrOp::LoopEnd,
+                // Blocks which would not get shorter are left as they are.
+                IrOp::Add(1),
+                IrOp::Move(1)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_ranges() {
+        let mut program = lower("[-]>[-]>[-]>[-]>+>+<<<<<.+>+>+>+>++");
+        RunLength.run(&mut program);
+        ClearLoop.run(&mut program);
+        Offsets.run(&mut program);
+        assert_eq!(Ranges.run(&mut program), 2);
+        assert_eq!(
+            ops(&program),
+            [
+                IrOp::ClearRange(0, 4),
+                // Runs of fewer than four cells are left as they are.
+                IrOp::AddAt(4, 1),
+                IrOp::AddAt(5, 1),
+                IrOp::Output,
+                IrOp::AddRange(0, 4, 1),
+                IrOp::AddAt(4, 2),
+                IrOp::Move(4)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_pipeline_stats() {
+        let mut program = lower("+++[->+<]>-<.>[-]");
+        let stats = Pipeline::builtin().run(&mut program);
+        let summary: Vec<String> =
+            stats.iter().map(|s| s.to_string()).collect();
+        assert_eq!(
+            summary,
+            [
+                "rle: 17 -> 15 ops (2 removed, 1 rewritten)",
+                "clearloop: 15 -> 13 ops (2 removed, 1 rewritten)",
+                "copyloop: 13 -> 8 ops (5 removed, 1 rewritten)",
+                "scanloop: 8 -> 8 ops (0 removed, 0 rewritten)",
+                "mulloop: 8 -> 8 ops (0 removed, 0 rewritten)",
+                "ifelse: 8 -> 8 ops (0 removed, 0 rewritten)",
+                "offsets: 8 -> 6 ops (2 removed, 1 rewritten)",
+                "ranges: 6 -> 6 ops (0 removed, 0 rewritten)",
+                "deadstore: 6 -> 5 ops (1 removed, 0 rewritten)",
+                "unreachable: 5 -> 5 ops (0 removed, 0 rewritten)"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_pipeline_spec() {
+        assert!(Pipeline::from_spec("none").unwrap().names().is_empty());
+        assert_eq!(
+            Pipeline::from_spec("rle,clearloop,-copyloop")
+                .unwrap()
+                .names(),
+            ["rle", "clearloop"]
+        );
+        let mut pipeline = Pipeline::builtin();
+        assert!(pipeline.remove("clearloop").is_some());
+        assert!(pipeline.remove("clearloop").is_none());
+        pipeline.insert(0, Box::new(ClearLoop));
+        assert_eq!(
+            pipeline.names(),
+            [
+                "clearloop",
+                "rle",
+                "copyloop",
+                "scanloop",
+                "mulloop",
+                "ifelse",
+                "offsets",
+                "ranges",
+                "deadstore",
+                "unreachable"
+            ]
+        );
+    }
+}
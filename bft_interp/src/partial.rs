@@ -0,0 +1,567 @@
+//! Partial evaluation, which runs the start of a program ahead of time, up to
+//! the first instruction which reads input, and replaces it with the output
+//! and tape that it produced.
+
+use std::marker::PhantomData;
+use std::ops::Range;
+
+use crate::ir::{IrNode, IrOp, IrProgram};
+use crate::optimizer::IrPass;
+use crate::CellKind;
+
+/// Runs as much of the start of a program as it can without reading any input
+/// or running any extension instructions, and replaces it with an
+/// `OutputBytes` of everything it wrote, followed by a `LoadTape` of the cells
+/// it changed and a `Move` to where it left the head. For programs which never
+/// read input, such as printing "hello world", this is the whole program.
+///
+/// The program is only run for up to `budget` operations, or a separate budget
+/// for programs which never read input, and within limits on memory and on
+/// output. It stops early at any operation that would fail, such as moving the
+/// head off the tape or writing past the output limit, so that the failure
+/// happens when the program is run. Evaluation
+/// always stops outside of any loop, backing up to the start of the outermost
+/// loop if it has to stop within one.
+///
+/// This assumes that the program starts at the start of a fresh tape of
+/// cells of type `T`. The operations which are evaluated are recorded with
+/// `IrProgram::add_evaluated_steps`, so that they still count towards the
+/// step limit of the Virtual Machine, and so the budget should be no more
+/// than that limit.
+/// ```
+/// use bft_types::BfProgram;
+/// use bft_interp::ir::{IrOp, IrProgram};
+/// use bft_interp::optimizer::{IrPass, Pipeline};
+/// use bft_interp::partial::PartialEvaluation;
+///
+/// let program = BfProgram::new("++[>+++<-]>.,".to_string(), "pe.bf").unwrap();
+/// let mut ir = IrProgram::from_program(&program).unwrap();
+/// let mut pipeline = Pipeline::builtin();
+/// pipeline.push(Box::new(PartialEvaluation::<u8>::new(1000, 30_000, false)));
+/// pipeline.run(&mut ir);
+///
+/// let ops: Vec<&IrOp> = ir.nodes().iter().map(|node| node.op()).collect();
+/// assert_eq!(
+///     ops,
+///     [
+///         &IrOp::OutputBytes(vec![6]),
+///         &IrOp::LoadTape(vec![0, 6]),
+///         &IrOp::Move(1),
+///         &IrOp::Input,
+///     ]
+/// );
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct PartialEvaluation<T> {
+    budget: u64,
+    input_free_budget: u64,
+    memory_limit: usize,
+    output_limit: u64,
+    cells: usize,
+    growable: bool,
+    cell: PhantomData<T>,
+}
+
+impl<T> PartialEvaluation<T> {
+    /// Creates a pass which runs up to `budget` operations, on a tape of
+    /// `cells` cells which may grow if `growable` is set, as the tape of the
+    /// Virtual Machine running the program does.
+    pub fn new(budget: u64, cells: usize, growable: bool) -> Self {
+        Self {
+            budget,
+            input_free_budget: budget,
+            memory_limit: usize::MAX,
+            output_limit: u64::MAX,
+            cells,
+            growable,
+            cell: PhantomData,
+        }
+    }
+
+    /// Sets the budget used instead for programs which never read any input,
+    /// which can be run to the end ahead of time if they finish within it.
+    pub fn with_input_free_budget(mut self, budget: u64) -> Self {
+        self.input_free_budget = budget;
+        self
+    }
+
+    /// Sets the most bytes which the tape and output may take up between them
+    /// as the program is evaluated, stopping evaluation if it would take more.
+    pub fn with_memory_limit(mut self, bytes: usize) -> Self {
+        self.memory_limit = bytes;
+        self
+    }
+
+    /// Sets the most bytes which the program may write as it is evaluated,
+    /// which should be the output limit of the Virtual Machine running it.
+    /// The write which would go over it is left for that to fail on.
+    pub fn with_output_limit(mut self, bytes: u64) -> Self {
+        self.output_limit = bytes;
+        self
+    }
+}
+
+/// The state of the program as it is evaluated.
+#[derive(Debug, Clone)]
+struct Snapshot<T> {
+    position: usize,
+    steps: u64,
+    tape: Vec<T>,
+    head: usize,
+    output: usize,
+}
+
+/// Runs operations of a program, without reading input, on a tape which only
+/// grows as far as the head has been.
+struct Evaluator<T> {
+    tape: Vec<T>,
+    head: usize,
+    limit: usize,
+    memory_limit: usize,
+    output_limit: u64,
+    output: Vec<u8>,
+}
+
+impl<T> Evaluator<T>
+where
+    T: CellKind + Default + Clone + Copy + PartialEq,
+{
+    /// Whether the tape, grown to `cells` cells, and the output, grown by
+    /// `output` bytes, would fit within the memory limit.
+    fn fits(&self, cells: usize, output: usize) -> bool {
+        cells
+            .checked_mul(std::mem::size_of::<T>())
+            .and_then(|tape| tape.checked_add(self.output.len()))
+            .and_then(|used| used.checked_add(output))
+            .is_some_and(|used| used <= self.memory_limit)
+    }
+
+    /// Finds the cell at the offset from the head, if it is on the tape and
+    /// there is memory for it.
+    fn target(&mut self, offset: isize) -> Option<usize> {
+        let target = self.head.checked_add_signed(offset)?;
+        if target >= self.limit {
+            return None;
+        }
+        if target >= self.tape.len() {
+            if !self.fits(target + 1, 0) {
+                return None;
+            }
+            self.tape.resize(target + 1, T::default());
+        }
+        Some(target)
+    }
+
+    /// Finds the cells in the range from the offset from the head, if they are
+    /// all on the tape and there is memory for them.
+    fn range(&mut self, offset: isize, len: usize) -> Option<Range<usize>> {
+        let start = self.target(offset)?;
+        if let Some(last) = len.checked_sub(1) {
+            self.target(offset.checked_add(last as isize)?)?;
+        }
+        Some(start..start + len)
+    }
+
+    /// Writes out bytes, if there is memory for them and they are within the
+    /// output limit.
+    fn write(&mut self, bytes: &[u8]) -> Option<()> {
+        let written = (self.output.len() + bytes.len()) as u64;
+        if written > self.output_limit
+            || !self.fits(self.tape.len(), bytes.len())
+        {
+            return None;
+        }
+        self.output.extend_from_slice(bytes);
+        Some(())
+    }
+
+    /// Runs the operation at `position`, returning the position of the next
+    /// one, or None if it cannot be run ahead of time.
+    fn step(
+        &mut self,
+        nodes: &[IrNode],
+        jumps: &[usize],
+        position: usize,
+    ) -> Option<usize> {
+        let head = self.head;
+        match nodes[position].op() {
+            IrOp::Add(delta) => {
+                self.tape[head] = self.tape[head].add_signed(*delta);
+            }
+            IrOp::Move(delta) => self.head = self.target(*delta)?,
+            IrOp::Output => self.write(&[self.tape[head].to_u8()])?,
+            IrOp::LoopStart => {
+                if self.tape[head] == T::default() {
+                    return Some(jumps[position] + 1);
+                }
+            }
+            IrOp::LoopEnd => {
+                if self.tape[head] != T::default() {
+                    return Some(jumps[position] + 1);
+                }
+            }
+            IrOp::If => {
+                if self.tape[head] == T::default() {
+                    return Some(jumps[position] + 1);
+                }
+            }
+            IrOp::Else => return Some(jumps[position] + 1),
+            IrOp::EndIf => {}
+            IrOp::Clear => self.tape[head] = T::default(),
+            IrOp::AddAt(offset, delta) => {
+                let target = self.target(*offset)?;
+                self.tape[target] = self.tape[target].add_signed(*delta);
+            }
+            IrOp::ClearAt(offset) => {
+                let target = self.target(*offset)?;
+                self.tape[target] = T::default();
+            }
+            IrOp::AddRange(offset, len, delta) => {
+                let cells = self.range(*offset, *len)?;
+                T::add_to_all(&mut self.tape[cells], *delta);
+            }
+            IrOp::ClearRange(offset, len) => {
+                let cells = self.range(*offset, *len)?;
+                self.tape[cells].fill(T::default());
+            }
+            IrOp::CopyLoop(targets) => {
+                let value = self.tape[head];
+                if value != T::default() {
+                    for (offset, factor) in targets {
+                        let target = self.target(*offset)?;
+                        self.tape[target] =
+                            self.tape[target].add_product(value, *factor);
+                    }
+                    self.tape[head] = T::default();
+                }
+            }
+            IrOp::MulAdd(mul) => {
+                if self.tape[head] != T::default() {
+                    for offset in mul.offsets() {
+                        self.target(offset)?;
+                    }
+                    mul.apply(&mut self.tape, head);
+                }
+            }
+            IrOp::ScanRight(step) => {
+                while self.tape[self.head] != T::default() {
+                    self.head = self.target(*step as isize)?;
+                }
+            }
+            IrOp::ScanLeft(step) => {
+                while self.tape[self.head] != T::default() {
+                    self.head = self.target(-(*step as isize))?;
+                }
+            }
+            IrOp::OutputBytes(bytes) => self.write(bytes)?,
+            IrOp::LoadTape(values) => {
+                if let Some(last) = values.len().checked_sub(1) {
+                    self.target(last as isize)?;
+                }
+                for (cell, value) in self.tape[head..].iter_mut().zip(values) {
+                    *cell = T::from_u32(*value);
+                }
+            }
+            IrOp::Input | IrOp::Extension(_) => return None,
+        }
+        Some(position + 1)
+    }
+}
+
+impl<T> IrPass for PartialEvaluation<T>
+where
+    T: CellKind + Default + Clone + Copy + PartialEq,
+{
+    fn name(&self) -> &str {
+        "partial"
+    }
+
+    fn run(&mut self, program: &mut IrProgram) -> usize {
+        let Ok(jumps) = program.jump_table() else {
+            return 0;
+        };
+        let nodes = program.nodes();
+        let mut evaluator = Evaluator {
+            tape: vec![T::default(); self.cells.min(1)],
+            head: 0,
+            limit: if self.growable {
+                usize::MAX
+            } else {
+                self.cells
+            },
+            memory_limit: self.memory_limit,
+            output_limit: self.output_limit,
+            output: Vec::new(),
+        };
+        let budget = if program.reads_input() {
+            self.budget
+        } else {
+            self.input_free_budget
+        };
+        if evaluator.tape.is_empty() {
+            return 0;
+        }
+
+        let mut depth = 0;
+        let mut steps = 0;
+        let mut position = 0;
+        let mut snapshot = None;
+        while position < nodes.len() {
+            if depth == 0 {
+                snapshot = Some(Snapshot {
+                    position,
+                    steps,
+                    tape: evaluator.tape.clone(),
+                    head: evaluator.head,
+                    output: evaluator.output.len(),
+                });
+            }
+            if steps == budget {
+                break;
+            }
+            let Some(next) = evaluator.step(nodes, &jumps, position) else {
+                break;
+            };
+            steps += 1;
+            depth = match nodes[position].op() {
+                IrOp::LoopStart if next == position + 1 => depth + 1,
+                IrOp::LoopStart => depth,
+                IrOp::LoopEnd if next == jumps[position] + 1 => depth,
+                IrOp::LoopEnd => depth - 1,
+                // Either branch of an `If` is entered, and left at its end.
+                IrOp::If => depth + 1,
+                IrOp::Else | IrOp::EndIf => depth - 1,
+                _ => depth,
+            };
+            position = next;
+        }
+        if position < nodes.len() {
+            let Some(snapshot) = snapshot else {
+                return 0;
+            };
+            position = snapshot.position;
+            steps = snapshot.steps;
+            evaluator.tape = snapshot.tape;
+            evaluator.head = snapshot.head;
+            evaluator.output.truncate(snapshot.output);
+        }
+        if position == 0 {
+            return 0;
+        }
+
+        let source = nodes[0].source();
+        let mut prefix = Vec::new();
+        if !evaluator.output.is_empty() {
+            prefix
+                .push(IrNode::new(IrOp::OutputBytes(evaluator.output), source));
+        }
+        let used = evaluator
+            .tape
+            .iter()
+            .rposition(|cell| *cell != T::default())
+            .map_or(0, |last| last + 1);
+        if used > 0 {
+            let values = evaluator.tape[..used].iter().map(T::to_u32).collect();
+            prefix.push(IrNode::new(IrOp::LoadTape(values), source));
+        }
+        if evaluator.head > 0 {
+            let delta = evaluator.head as isize;
+            prefix.push(IrNode::new(IrOp::Move(delta), source));
+        }
+        program.nodes_mut().splice(..position, prefix);
+        program.add_evaluated_steps(steps);
+        1
+    }
+}
+
+/// The output of a program which has been run to the end ahead of time, so
+/// that all that is left of it is its output and the tape it left behind, or
+/// None if there is more of the program left to run.
+/// ```
+/// use bft_types::BfProgram;
+/// use bft_interp::ir::IrProgram;
+/// use bft_interp::optimizer::IrPass;
+/// use bft_interp::partial::{evaluated_output, PartialEvaluation};
+///
+/// let program = BfProgram::new("+++[.-]".to_string(), "pe.bf").unwrap();
+/// let mut ir = IrProgram::from_program(&program).unwrap();
+/// assert_eq!(evaluated_output(&ir), None);
+///
+/// PartialEvaluation::<u8>::new(1000, 30_000, false).run(&mut ir);
+/// assert_eq!(evaluated_output(&ir), Some(vec![3, 2, 1]));
+/// ```
+pub fn evaluated_output(program: &IrProgram) -> Option<Vec<u8>> {
+    let mut output = Vec::new();
+    for node in program.nodes() {
+        match node.op() {
+            IrOp::OutputBytes(bytes) => output.extend_from_slice(bytes),
+            IrOp::LoadTape(_) | IrOp::Move(_) => {}
+            _ => return None,
+        }
+    }
+    Some(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{evaluated_output, PartialEvaluation};
+    use crate::ir::{IrNode, IrOp, IrProgram};
+    use crate::optimizer::{IrPass, Pipeline};
+    use bft_types::vm_error::VirtualMachineError;
+    use bft_types::BfProgram;
+
+    fn evaluate(source: &str, pass: PartialEvaluation<u8>) -> Vec<IrOp> {
+        let program = BfProgram::new(source.to_string(), "pe.bf").unwrap();
+        let mut ir = IrProgram::from_program(&program).unwrap();
+        let mut pipeline = Pipeline::builtin();
+        pipeline.push(Box::new(pass));
+        pipeline.run(&mut ir);
+        ir.nodes().iter().map(IrNode::op).cloned().collect()
+    }
+
+    #[test]
+    fn test_whole_program_evaluated() {
+        let ops = evaluate(
+            include_str!("../../bf-programs/hello-world.bf"),
+            PartialEvaluation::new(1_000_000, 30_000, false),
+        );
+        let IrOp::OutputBytes(bytes) = &ops[0] else {
+            panic!("expected the output first, found {:?}", ops[0]);
+        };
+        assert_eq!(bytes, b"hello world");
+        assert!(ops[1..]
+            .iter()
+            .all(|op| matches!(op, IrOp::LoadTape(_) | IrOp::Move(_))));
+    }
+
+    #[test]
+    fn test_stops_at_input() {
+        let ops =
+            evaluate("+++.>,.", PartialEvaluation::new(1000, 30_000, false));
+        assert_eq!(
+            ops,
+            [
+                IrOp::OutputBytes(vec![3]),
+                IrOp::LoadTape(vec![3]),
+                IrOp::Move(1),
+                IrOp::Input,
+                IrOp::Output
+            ]
+        );
+    }
+
+    #[test]
+    fn test_backs_out_of_loops() {
+        // The input inside the loop means evaluation has to stop before it.
+        let ops =
+            evaluate("++.[.,]", PartialEvaluation::new(1000, 30_000, false));
+        assert_eq!(
+            ops[..2],
+            [IrOp::OutputBytes(vec![2]), IrOp::LoadTape(vec![2])]
+        );
+        assert_eq!(ops[2], IrOp::LoopStart);
+
+        // As does running out of budget.
+        let ops = evaluate("+[++]", PartialEvaluation::new(10, 30_000, false));
+        assert_eq!(
+            ops,
+            [
+                IrOp::LoadTape(vec![1]),
+                IrOp::LoopStart,
+                IrOp::Add(2),
+                IrOp::LoopEnd
+            ]
+        );
+    }
+
+    #[test]
+    fn test_stops_before_failures() {
+        let ops = evaluate("+.<", PartialEvaluation::new(1000, 30_000, false));
+        assert_eq!(
+            ops,
+            [
+                IrOp::OutputBytes(vec![1]),
+                IrOp::LoadTape(vec![1]),
+                IrOp::Move(-1)
+            ]
+        );
+
+        let ops = evaluate(">>+", PartialEvaluation::new(1000, 2, false));
+        assert_eq!(ops, [IrOp::Move(2), IrOp::Add(1)]);
+        let ops = evaluate(">>+", PartialEvaluation::new(1000, 2, true));
+        assert_eq!(ops, [IrOp::LoadTape(vec![0, 0, 1]), IrOp::Move(2)]);
+    }
+
+    #[test]
+    fn test_input_free_budget() {
+        let source = "++++[>++++[>++++<-]<-]>>+.";
+        let pass = PartialEvaluation::new(10, 30_000, false);
+        assert_eq!(evaluate(source, pass)[0], IrOp::LoadTape(vec![4]));
+
+        let pass = pass.with_input_free_budget(1000);
+        assert_eq!(evaluate(source, pass)[0], IrOp::OutputBytes(vec![b'A']));
+
+        // Programs which read input keep to the smaller budget.
+        let ops = evaluate(&format!("{},", source), pass);
+        assert_eq!(ops[0], IrOp::LoadTape(vec![4]));
+    }
+
+    #[test]
+    fn test_evaluated_steps() {
+        // The loop is backed out of, so only the two steps before it count.
+        let program = BfProgram::new("++[.,]".to_string(), "pe.bf").unwrap();
+        let mut ir = IrProgram::from_program(&program).unwrap();
+        PartialEvaluation::<u8>::new(1000, 30_000, false).run(&mut ir);
+        assert_eq!(ir.evaluated_steps(), 2);
+
+        let mut vm = crate::VirtualMachine::<u8>::new(&program, 1, false)
+            .with_sandbox(
+                crate::sandbox::Sandbox::builder()
+                    .with_step_limit(2)
+                    .build()
+                    .unwrap(),
+            );
+        let result =
+            vm.interpret_ir(&ir, &mut std::io::empty(), &mut Vec::new());
+        assert!(matches!(
+            result,
+            Err(VirtualMachineError::StepLimitExceeded { limit: 2, .. })
+        ));
+        assert_eq!(vm.steps(), 2);
+    }
+
+    #[test]
+    fn test_memory_limit() {
+        let pass = PartialEvaluation::new(1000, 30_000, true);
+        let ops = evaluate("+>>>>>>>+.", pass.with_memory_limit(4));
+        assert_eq!(ops[0], IrOp::LoadTape(vec![1]));
+        assert!(ops.contains(&IrOp::Output));
+
+        let ops = evaluate("+.....", pass.with_memory_limit(4));
+        assert_eq!(ops[0], IrOp::OutputBytes(vec![1, 1, 1]));
+
+        let program = BfProgram::new("+.....".to_string(), "pe.bf").unwrap();
+        let mut ir = IrProgram::from_program(&program).unwrap();
+        pass.with_memory_limit(16).run(&mut ir);
+        assert_eq!(evaluated_output(&ir), Some(vec![1; 5]));
+    }
+
+    #[test]
+    fn test_output_limit() {
+        // The `.` which goes over the limit is left to fail on its own.
+        let pass = PartialEvaluation::<u8>::new(1000, 30_000, false);
+        let ops = evaluate("+.....", pass.with_output_limit(3));
+        assert_eq!(ops[0], IrOp::OutputBytes(vec![1, 1, 1]));
+        assert_eq!(ops[1], IrOp::LoadTape(vec![1]));
+        assert_eq!(ops[2..], [IrOp::Output, IrOp::Output]);
+    }
+
+    #[test]
+    fn test_nothing_evaluated() {
+        let mut pass = PartialEvaluation::<u8>::new(1000, 30_000, false);
+        let program = BfProgram::new(",.".to_string(), "pe.bf").unwrap();
+        let mut ir = IrProgram::from_program(&program).unwrap();
+        assert_eq!(pass.run(&mut ir), 0);
+        assert_eq!(ir.nodes().len(), 2);
+    }
+}
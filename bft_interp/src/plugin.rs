@@ -0,0 +1,525 @@
+//! The C ABI through which plugins extend bft without it being recompiled.
+//! A plugin is a dynamic library which exports a function named
+//! [`ENTRY_POINT`], of type [`EntryPoint`], and may add optimization passes,
+//! dialects and I/O codecs, each described by a table of `extern "C"`
+//! functions.
+//!
+//! The host calls the entry point with the range of versions of this
+//! interface it supports, as a [`HostInfo`], and the plugin answers with a
+//! [`PluginInfo`] for the version it chose from that range, or a null pointer
+//! if it supports none of them. The layout of every type here is fixed for
+//! version 1; later versions may only add fields to the end of
+//! [`PluginInfo`], or new kinds of node.
+//!
+//! All memory stays with whoever allocated it. The host hands plugins slices
+//! which are only valid for the call, and plugins hand back their results a
+//! piece at a time through the `emit` callback they are given, which copies
+//! them. Strings are nul-terminated UTF-8, and like the tables they are in
+//! must live as long as the library is loaded.
+//!
+//! A plugin written in Rust can use the types here directly:
+//! ```
+//! use std::ffi::c_void;
+//! use std::ptr;
+//!
+//! use bft_interp::plugin::{
+//!     EmitNode, HostInfo, Node, PassVTable, PluginInfo, ABI_VERSION,
+//! };
+//!
+//! /// Drops every `Output`, for benchmarking without the cost of printing.
+//! unsafe extern "C" fn silence(
+//!     nodes: *const Node,
+//!     len: usize,
+//!     out: *mut c_void,
+//!     emit: EmitNode,
+//! ) -> i64 {
+//!     let nodes = unsafe { std::slice::from_raw_parts(nodes, len) };
+//!     for node in nodes.iter().filter(|node| node.kind != Node::OUTPUT) {
+//!         unsafe { emit(out, *node) };
+//!     }
+//!     0
+//! }
+//!
+//! static PASSES: [PassVTable; 1] = [PassVTable {
+//!     name: c"silence".as_ptr(),
+//!     run: silence,
+//! }];
+//!
+//! static PLUGIN: PluginInfo = PluginInfo {
+//!     version: ABI_VERSION,
+//!     name: c"quiet".as_ptr(),
+//!     passes: PASSES.as_ptr(),
+//!     pass_count: PASSES.len(),
+//!     dialects: ptr::null(),
+//!     dialect_count: 0,
+//!     codecs: ptr::null(),
+//!     codec_count: 0,
+//! };
+//!
+//! #[no_mangle]
+//! pub extern "C" fn bft_plugin_entry(
+//!     host: *const HostInfo,
+//! ) -> *const PluginInfo {
+//!     let host = unsafe { &*host };
+//!     if host.supports(ABI_VERSION) {
+//!         &PLUGIN
+//!     } else {
+//!         ptr::null()
+//!     }
+//! }
+//!
+//! let plugin = unsafe { &*bft_plugin_entry(&HostInfo::new()) };
+//! assert_eq!(unsafe { plugin.name() }, Ok("quiet"));
+//! assert_eq!(unsafe { plugin.passes()[0].name() }, Ok("silence"));
+//! ```
+
+use std::ffi::{c_char, c_void, CStr};
+use std::slice;
+use std::str::Utf8Error;
+
+use crate::ir::IrOp;
+
+/// The newest version of the interface, which the types here describe.
+pub const ABI_VERSION: u32 = 1;
+
+/// The oldest version of the interface which is still supported.
+pub const MIN_ABI_VERSION: u32 = 1;
+
+/// The name of the function every plugin exports.
+pub const ENTRY_POINT: &str = "bft_plugin_entry";
+
+/// The function every plugin exports under the name [`ENTRY_POINT`]. It is
+/// given what the host supports, and returns what the plugin provides, or a
+/// null pointer if it cannot work with the host.
+pub type EntryPoint =
+    unsafe extern "C" fn(host: *const HostInfo) -> *const PluginInfo;
+
+/// Hands a node back to the host, which copies it. `out` is the pointer the
+/// host passed in along with the callback.
+pub type EmitNode = unsafe extern "C" fn(out: *mut c_void, node: Node);
+
+/// Hands some bytes back to the host, which copies them. `out` is the pointer
+/// the host passed in along with the callback.
+pub type EmitBytes =
+    unsafe extern "C" fn(out: *mut c_void, bytes: *const u8, len: usize);
+
+/// What the host tells a plugin about itself.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HostInfo {
+    /// The oldest version of the interface the host supports.
+    pub min_version: u32,
+    /// The newest version of the interface the host supports.
+    pub max_version: u32,
+}
+
+impl HostInfo {
+    /// Describes this version of bft as a host.
+    pub fn new() -> Self {
+        Self {
+            min_version: MIN_ABI_VERSION,
+            max_version: ABI_VERSION,
+        }
+    }
+
+    /// Whether the host supports the given version of the interface.
+    pub fn supports(&self, version: u32) -> bool {
+        (self.min_version..=self.max_version).contains(&version)
+    }
+}
+
+impl Default for HostInfo {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// What a plugin provides, returned from its entry point. Each list is given
+/// as a pointer to its first entry and the number of entries, and may be null
+/// if it is empty.
+#[repr(C)]
+#[derive(Debug)]
+pub struct PluginInfo {
+    /// The version of the interface the plugin chose, from the range the host
+    /// supports.
+    pub version: u32,
+    /// The name of the plugin.
+    pub name: *const c_char,
+    /// The optimization passes of the plugin.
+    pub passes: *const PassVTable,
+    /// The number of optimization passes.
+    pub pass_count: usize,
+    /// The dialects the plugin translates into Brainfuck.
+    pub dialects: *const DialectVTable,
+    /// The number of dialects.
+    pub dialect_count: usize,
+    /// The codecs the plugin encodes and decodes I/O with.
+    pub codecs: *const CodecVTable,
+    /// The number of codecs.
+    pub codec_count: usize,
+}
+
+// SAFETY: the tables of a plugin are never changed once it is loaded, so they
+// can be read from any thread, and plugins need them to be `Sync` to keep
+// them in statics.
+unsafe impl Sync for PluginInfo {}
+
+/// Reads a nul-terminated string from a plugin.
+///
+/// # Safety
+///
+/// The pointer must be null, or point to a nul-terminated string which lives
+/// as long as `'a`.
+unsafe fn plugin_str<'a>(string: *const c_char) -> Result<&'a str, Utf8Error> {
+    if string.is_null() {
+        return Ok("");
+    }
+    // SAFETY: the caller guarantees the string is nul-terminated and lives
+    // long enough.
+    unsafe { CStr::from_ptr(string) }.to_str()
+}
+
+/// Reads a list from a plugin.
+///
+/// # Safety
+///
+/// The pointer must be null, or point to `len` entries which live as long as
+/// `'a`.
+unsafe fn plugin_slice<'a, T>(entries: *const T, len: usize) -> &'a [T] {
+    if entries.is_null() || len == 0 {
+        return &[];
+    }
+    // SAFETY: the caller guarantees there are `len` entries which live long
+    // enough.
+    unsafe { slice::from_raw_parts(entries, len) }
+}
+
+impl PluginInfo {
+    /// The name of the plugin.
+    ///
+    /// # Safety
+    ///
+    /// The plugin must keep to the interface, with its library still loaded.
+    pub unsafe fn name(&self) -> Result<&str, Utf8Error> {
+        // SAFETY: the caller guarantees the plugin keeps to the interface.
+        unsafe { plugin_str(self.name) }
+    }
+
+    /// The optimization passes of the plugin.
+    ///
+    /// # Safety
+    ///
+    /// The plugin must keep to the interface, with its library still loaded.
+    pub unsafe fn passes(&self) -> &[PassVTable] {
+        // SAFETY: the caller guarantees the plugin keeps to the interface.
+        unsafe { plugin_slice(self.passes, self.pass_count) }
+    }
+
+    /// The dialects of the plugin.
+    ///
+    /// # Safety
+    ///
+    /// The plugin must keep to the interface, with its library still loaded.
+    pub unsafe fn dialects(&self) -> &[DialectVTable] {
+        // SAFETY: the caller guarantees the plugin keeps to the interface.
+        unsafe { plugin_slice(self.dialects, self.dialect_count) }
+    }
+
+    /// The codecs of the plugin.
+    ///
+    /// # Safety
+    ///
+    /// The plugin must keep to the interface, with its library still loaded.
+    pub unsafe fn codecs(&self) -> &[CodecVTable] {
+        // SAFETY: the caller guarantees the plugin keeps to the interface.
+        unsafe { plugin_slice(self.codecs, self.codec_count) }
+    }
+}
+
+/// A node of a program in the intermediate representation, as plugins see
+/// it. Only the simpler operations can be written this way, which are those
+/// of the built-in passes up to and including `offsets`, apart from `MulAdd`,
+/// `If` and the ranges.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Node {
+    /// What the node does, one of the constants of this type.
+    pub kind: u32,
+    /// The number of cells the node moves the head by, or the offset of the
+    /// cell it changes from the head.
+    pub offset: i64,
+    /// The amount the node adds to a cell, or the number of cells a scan
+    /// moves by each step.
+    pub amount: i64,
+    /// The index of the node given to the pass which this one came from,
+    /// used to report errors in the original program.
+    pub source: usize,
+}
+
+impl Node {
+    /// Adds `amount` to the cell at the head.
+    pub const ADD: u32 = 0;
+    /// Moves the head by `offset` cells.
+    pub const MOVE: u32 = 1;
+    /// Writes out the cell at the head.
+    pub const OUTPUT: u32 = 2;
+    /// Reads into the cell at the head.
+    pub const INPUT: u32 = 3;
+    /// Starts a loop, which the pass must end with a later `LOOP_END`.
+    pub const LOOP_START: u32 = 4;
+    /// Ends a loop.
+    pub const LOOP_END: u32 = 5;
+    /// Sets the cell at the head to zero.
+    pub const CLEAR: u32 = 6;
+    /// Adds `amount` to the cell at `offset` from the head.
+    pub const ADD_AT: u32 = 7;
+    /// Sets the cell at `offset` from the head to zero.
+    pub const CLEAR_AT: u32 = 8;
+    /// Moves the head right `amount` cells at a time until it reaches a zero
+    /// cell.
+    pub const SCAN_RIGHT: u32 = 9;
+    /// Moves the head left `amount` cells at a time until it reaches a zero
+    /// cell.
+    pub const SCAN_LEFT: u32 = 10;
+
+    /// Creates a node of the given kind.
+    pub fn new(kind: u32, offset: i64, amount: i64, source: usize) -> Self {
+        Self {
+            kind,
+            offset,
+            amount,
+            source,
+        }
+    }
+
+    /// The node for the operation, if plugins can be given it.
+    /// ```
+    /// use bft_interp::ir::IrOp;
+    /// use bft_interp::plugin::Node;
+    ///
+    /// let node = Node::from_op(&IrOp::AddAt(-2, 5), 7).unwrap();
+    /// assert_eq!(node, Node::new(Node::ADD_AT, -2, 5, 7));
+    /// assert_eq!(node.to_op(), Some(IrOp::AddAt(-2, 5)));
+    /// assert_eq!(Node::from_op(&IrOp::OutputBytes(vec![1]), 0), None);
+    /// ```
+    pub fn from_op(op: &IrOp, source: usize) -> Option<Self> {
+        let (kind, offset, amount) = match *op {
+            IrOp::Add(amount) => (Self::ADD, 0, amount.into()),
+            IrOp::Move(offset) => (Self::MOVE, offset as i64, 0),
+            IrOp::Output => (Self::OUTPUT, 0, 0),
+            IrOp::Input => (Self::INPUT, 0, 0),
+            IrOp::LoopStart => (Self::LOOP_START, 0, 0),
+            IrOp::LoopEnd => (Self::LOOP_END, 0, 0),
+            IrOp::Clear => (Self::CLEAR, 0, 0),
+            IrOp::AddAt(offset, amount) => {
+                (Self::ADD_AT, offset as i64, amount.into())
+            }
+            IrOp::ClearAt(offset) => (Self::CLEAR_AT, offset as i64, 0),
+            IrOp::ScanRight(step) => (Self::SCAN_RIGHT, 0, step as i64),
+            IrOp::ScanLeft(step) => (Self::SCAN_LEFT, 0, step as i64),
+            _ => return None,
+        };
+        Some(Self::new(kind, offset, amount, source))
+    }
+
+    /// The operation of the node, if it is of a known kind, with an offset
+    /// and amount in range.
+    pub fn to_op(&self) -> Option<IrOp> {
+        let offset = || isize::try_from(self.offset).ok();
+        let amount = || i32::try_from(self.amount).ok();
+        let step = || usize::try_from(self.amount).ok().filter(|&n| n > 0);
+        Some(match self.kind {
+            Self::ADD => IrOp::Add(amount()?),
+            Self::MOVE => IrOp::Move(offset()?),
+            Self::OUTPUT => IrOp::Output,
+            Self::INPUT => IrOp::Input,
+            Self::LOOP_START => IrOp::LoopStart,
+            Self::LOOP_END => IrOp::LoopEnd,
+            Self::CLEAR => IrOp::Clear,
+            Self::ADD_AT => IrOp::AddAt(offset()?, amount()?),
+            Self::CLEAR_AT => IrOp::ClearAt(offset()?),
+            Self::SCAN_RIGHT => IrOp::ScanRight(step()?),
+            Self::SCAN_LEFT => IrOp::ScanLeft(step()?),
+            _ => return None,
+        })
+    }
+}
+
+/// An optimization pass, which may be named in a list of passes alongside the
+/// built-in ones.
+#[repr(C)]
+#[derive(Debug)]
+pub struct PassVTable {
+    /// The name of the pass.
+    pub name: *const c_char,
+    /// Rewrites the `len` nodes at `nodes`, emitting the nodes of the program
+    /// in their place. Returns the number of nodes it created to replace
+    /// existing ones, or a negative number to leave the program as it was,
+    /// in which case anything emitted is thrown away. The loops of the nodes
+    /// emitted must be balanced.
+    pub run: unsafe extern "C" fn(
+        nodes: *const Node,
+        len: usize,
+        out: *mut c_void,
+        emit: EmitNode,
+    ) -> i64,
+}
+
+// SAFETY: as for `PluginInfo`.
+unsafe impl Sync for PassVTable {}
+
+impl PassVTable {
+    /// The name of the pass.
+    ///
+    /// # Safety
+    ///
+    /// The plugin must keep to the interface, with its library still loaded.
+    pub unsafe fn name(&self) -> Result<&str, Utf8Error> {
+        // SAFETY: the caller guarantees the plugin keeps to the interface.
+        unsafe { plugin_str(self.name) }
+    }
+}
+
+/// A language which translates into Brainfuck, used for program files with
+/// its extension.
+#[repr(C)]
+#[derive(Debug)]
+pub struct DialectVTable {
+    /// The name of the dialect.
+    pub name: *const c_char,
+    /// The extension of the files written in the dialect, without the `.`.
+    pub extension: *const c_char,
+    /// Translates the `len` bytes of source at `source`, emitting the
+    /// Brainfuck it translates to. Returns 0 if it could, and anything else
+    /// if it could not.
+    pub translate: unsafe extern "C" fn(
+        source: *const u8,
+        len: usize,
+        out: *mut c_void,
+        emit: EmitBytes,
+    ) -> i32,
+}
+
+// SAFETY: as for `PluginInfo`.
+unsafe impl Sync for DialectVTable {}
+
+impl DialectVTable {
+    /// The name of the dialect.
+    ///
+    /// # Safety
+    ///
+    /// The plugin must keep to the interface, with its library still loaded.
+    pub unsafe fn name(&self) -> Result<&str, Utf8Error> {
+        // SAFETY: the caller guarantees the plugin keeps to the interface.
+        unsafe { plugin_str(self.name) }
+    }
+
+    /// The extension of the files written in the dialect.
+    ///
+    /// # Safety
+    ///
+    /// The plugin must keep to the interface, with its library still loaded.
+    pub unsafe fn extension(&self) -> Result<&str, Utf8Error> {
+        // SAFETY: the caller guarantees the plugin keeps to the interface.
+        unsafe { plugin_str(self.extension) }
+    }
+}
+
+/// A way of encoding the output of programs, and decoding their input, a
+/// piece at a time. The state of a stream is only used by one thread at a
+/// time, though not always by the thread which started it.
+#[repr(C)]
+#[derive(Debug)]
+pub struct CodecVTable {
+    /// The name of the codec.
+    pub name: *const c_char,
+    /// Starts encoding output, if `direction` is [`CodecVTable::ENCODE`], or
+    /// decoding input, if it is [`CodecVTable::DECODE`], returning the state
+    /// to pass to the other functions, which may be null.
+    pub start: unsafe extern "C" fn(direction: u32) -> *mut c_void,
+    /// Encodes or decodes the next `len` bytes at `bytes`, emitting what they
+    /// encode or decode to. Returns 0 if it could, and anything else if it
+    /// could not.
+    pub feed: unsafe extern "C" fn(
+        state: *mut c_void,
+        bytes: *const u8,
+        len: usize,
+        out: *mut c_void,
+        emit: EmitBytes,
+    ) -> i32,
+    /// Emits whatever is left at the end of the stream, and frees the state.
+    /// Returns 0 if the stream ended where it could, and anything else if it
+    /// did not. Called exactly once for each call to `start`.
+    pub finish: unsafe extern "C" fn(
+        state: *mut c_void,
+        out: *mut c_void,
+        emit: EmitBytes,
+    ) -> i32,
+}
+
+// SAFETY: as for `PluginInfo`.
+unsafe impl Sync for CodecVTable {}
+
+impl CodecVTable {
+    /// Decodes the input of a program.
+    pub const DECODE: u32 = 0;
+    /// Encodes the output of a program.
+    pub const ENCODE: u32 = 1;
+
+    /// The name of the codec.
+    ///
+    /// # Safety
+    ///
+    /// The plugin must keep to the interface, with its library still loaded.
+    pub unsafe fn name(&self) -> Result<&str, Utf8Error> {
+        // SAFETY: the caller guarantees the plugin keeps to the interface.
+        unsafe { plugin_str(self.name) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{HostInfo, Node, ABI_VERSION};
+    use crate::ir::IrOp;
+
+    #[test]
+    fn test_node_round_trip() {
+        let ops = [
+            IrOp::Add(-3),
+            IrOp::Move(4),
+            IrOp::Output,
+            IrOp::Input,
+            IrOp::LoopStart,
+            IrOp::LoopEnd,
+            IrOp::Clear,
+            IrOp::AddAt(1, 2),
+            IrOp::ClearAt(-1),
+            IrOp::ScanRight(2),
+            IrOp::ScanLeft(1),
+        ];
+        for (index, op) in ops.iter().enumerate() {
+            let node = Node::from_op(op, index).unwrap();
+            assert_eq!(node.source, index);
+            assert_eq!(node.to_op().as_ref(), Some(op));
+        }
+        let mul = IrOp::MulAdd(Box::default());
+        assert_eq!(Node::from_op(&mul, 0), None);
+        assert_eq!(Node::from_op(&IrOp::Extension('#'), 0), None);
+    }
+
+    #[test]
+    fn test_bad_nodes() {
+        assert_eq!(Node::new(99, 0, 0, 0).to_op(), None);
+        assert_eq!(Node::new(Node::ADD, 0, i64::MAX, 0).to_op(), None);
+        assert_eq!(Node::new(Node::SCAN_RIGHT, 0, 0, 0).to_op(), None);
+        assert_eq!(Node::new(Node::SCAN_LEFT, 0, -1, 0).to_op(), None);
+    }
+
+    #[test]
+    fn test_versions() {
+        let host = HostInfo::new();
+        assert!(host.supports(ABI_VERSION));
+        assert!(!host.supports(ABI_VERSION + 1));
+        assert!(!host.supports(0));
+    }
+}
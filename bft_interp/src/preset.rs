@@ -0,0 +1,179 @@
+//! Presets which bundle up the settings of well-known kinds of Brainfuck
+//! interpreter, so that a program written for one of them can be run without
+//! working out which combination of settings it needs.
+//!
+//! A `Profile` decides the width of the cells, how long the tape is and
+//! whether it grows, what `,` does at the end of the input, and which
+//! extension instructions the program may use:
+//! ```
+//! use bft_interp::eof::EofBehavior;
+//! use bft_interp::preset::Profile;
+//! use bft_types::BfProgram;
+//!
+//! let profile: Profile = "classic".parse().unwrap();
+//! assert_eq!(profile.cell_bits(), 8);
+//! assert_eq!(profile.eof_behavior(), EofBehavior::Unchanged);
+//!
+//! let program = BfProgram::new(",.".to_string(), "cat.bf").unwrap();
+//! let mut vm = profile.virtual_machine::<u8>(&program);
+//! let mut output = Vec::new();
+//! vm.interpret(&mut std::io::empty(), &mut output).unwrap();
+//! assert_eq!(output, [0]);
+//! ```
+
+use std::fmt;
+use std::str::FromStr;
+
+use bft_types::BfProgram;
+
+use crate::eof::EofBehavior;
+use crate::syscall::SYSCALL;
+use crate::{CellKind, VirtualMachine, DEFAULT_TAPE_LENGTH};
+
+/// The settings of a well-known kind of Brainfuck interpreter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Profile {
+    /// The interpreter as first described: 30000 8 bit cells on a tape which
+    /// does not grow, with `,` leaving the cell unchanged at the end of the
+    /// input.
+    Classic,
+    /// 16 bit cells, which wrap around from 65535 to 0, on a tape of 30000
+    /// cells which does not grow, with `,` setting the cell to zero at the end
+    /// of the input. For programs which need larger numbers than a byte holds.
+    Wrap16,
+    /// 8 bit cells on a tape which grows as far as the program needs, with `,`
+    /// setting the cell to zero at the end of the input, and host functions
+    /// called with `%`.
+    Extended,
+}
+
+impl Profile {
+    /// Every profile, in the order they are listed in.
+    pub fn all() -> &'static [Profile] {
+        &[Profile::Classic, Profile::Wrap16, Profile::Extended]
+    }
+
+    /// The number of bits in each cell of the tape.
+    pub fn cell_bits(self) -> u32 {
+        match self {
+            Profile::Classic | Profile::Extended => 8,
+            Profile::Wrap16 => 16,
+        }
+    }
+
+    /// The number of cells the tape starts with.
+    pub fn tape_length(self) -> usize {
+        DEFAULT_TAPE_LENGTH
+    }
+
+    /// Whether the tape grows when the head moves off its end.
+    pub fn growable(self) -> bool {
+        self == Profile::Extended
+    }
+
+    /// What `,` does at the end of the input.
+    pub fn eof_behavior(self) -> EofBehavior {
+        match self {
+            Profile::Classic => EofBehavior::Unchanged,
+            Profile::Wrap16 | Profile::Extended => EofBehavior::Zero,
+        }
+    }
+
+    /// The extension instructions which programs may use, which must be
+    /// given to the parser.
+    pub fn extensions(self) -> &'static [char] {
+        match self {
+            Profile::Classic | Profile::Wrap16 => &[],
+            Profile::Extended => &[SYSCALL],
+        }
+    }
+
+    /// Whether programs may call host functions with `%`. See `syscall` for
+    /// how to register them.
+    pub fn syscalls(self) -> bool {
+        self == Profile::Extended
+    }
+
+    /// Creates a Virtual Machine for the program with the settings of the
+    /// profile. Its cells should be `cell_bits` wide.
+    pub fn virtual_machine<'a, T>(
+        self,
+        program: &'a BfProgram,
+    ) -> VirtualMachine<'a, T>
+    where
+        T: CellKind + Default + Clone + Copy + PartialEq,
+    {
+        VirtualMachine::new(program, self.tape_length(), self.growable())
+            .with_eof_behavior(self.eof_behavior())
+            .with_syscalls(self.syscalls())
+    }
+}
+
+impl FromStr for Profile {
+    type Err = String;
+
+    /// Parses the names used on the command line and in config files.
+    /// ```
+    /// use bft_interp::preset::Profile;
+    /// assert_eq!("wrap-tape-16bit".parse(), Ok(Profile::Wrap16));
+    /// assert!("bff4".parse::<Profile>().is_err());
+    /// ```
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "classic" => Ok(Profile::Classic),
+            "wrap-tape-16bit" => Ok(Profile::Wrap16),
+            "extended" => Ok(Profile::Extended),
+            _ => Err(format!(
+                "unknown preset '{}', expected one of classic, \
+                wrap-tape-16bit or extended",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Profile::Classic => write!(f, "classic"),
+            Profile::Wrap16 => write!(f, "wrap-tape-16bit"),
+            Profile::Extended => write!(f, "extended"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Profile;
+    use bft_types::BfProgram;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_names_round_trip() {
+        for profile in Profile::all() {
+            assert_eq!(profile.to_string().parse(), Ok(*profile));
+        }
+    }
+
+    #[test]
+    fn test_virtual_machine() {
+        let program =
+            BfProgram::new("->+,.".to_string(), "profile.bf").unwrap();
+        let mut output = Vec::new();
+        let mut vm = Profile::Wrap16.virtual_machine::<u16>(&program);
+        vm.interpret(&mut Cursor::new(Vec::new()), &mut output)
+            .unwrap();
+        assert_eq!(vm.tape()[..2], [65535, 0]);
+        let mut vm = Profile::Classic.virtual_machine::<u8>(&program);
+        vm.interpret(&mut Cursor::new(Vec::new()), &mut output)
+            .unwrap();
+        assert_eq!(output, [0, 1]);
+
+        let program = BfProgram::new(">".repeat(30_000), "far.bf").unwrap();
+        let mut input = Cursor::new(Vec::new());
+        let mut vm = Profile::Classic.virtual_machine::<u8>(&program);
+        assert!(vm.interpret(&mut input, &mut Vec::new()).is_err());
+        let mut vm = Profile::Extended.virtual_machine::<u8>(&program);
+        assert!(vm.interpret(&mut input, &mut Vec::new()).is_ok());
+    }
+}
@@ -0,0 +1,245 @@
+//! Reporting on the phases of running a program, so that a front end can show
+//! what happened while parsing, optimizing and running it in one place.
+//!
+//! A `Reporter` is told about the program once it has been parsed and once it
+//! has been optimized, and is given to the Virtual Machine with
+//! `VirtualMachine::with_reporter` to trace each step while it runs.
+
+use std::fmt;
+
+use bft_types::ops::Operation;
+use bft_types::{BfProgram, InstructionInfo};
+
+use crate::ir::IrOp;
+use crate::optimizer::PassStats;
+
+/// Receives reports from each phase of running a program. Every method does
+/// nothing by default, so implementations only pick out what they want.
+pub trait Reporter {
+    /// Called once the program has been parsed.
+    fn parsed(&mut self, _program: &BfProgram) {}
+
+    /// Called once the optimization passes have run over the program, with
+    /// what each of them did.
+    fn optimized(&mut self, _stats: &[PassStats]) {}
+
+    /// Whether the Virtual Machine should call `step` before each step of the
+    /// program. Tracing turns off the loops specialized for 8 bit cells, so
+    /// programs run more slowly while it is on.
+    fn traces(&self) -> bool {
+        false
+    }
+
+    /// Called before each step of the program is run, if `traces` is true.
+    fn step(&mut self, _step: &Step<'_>) {}
+}
+
+/// Reports to both reporters, only tracing steps to those which ask for them.
+impl<A: Reporter, B: Reporter> Reporter for (A, B) {
+    fn parsed(&mut self, program: &BfProgram) {
+        self.0.parsed(program);
+        self.1.parsed(program);
+    }
+
+    fn optimized(&mut self, stats: &[PassStats]) {
+        self.0.optimized(stats);
+        self.1.optimized(stats);
+    }
+
+    fn traces(&self) -> bool {
+        self.0.traces() || self.1.traces()
+    }
+
+    fn step(&mut self, step: &Step<'_>) {
+        if self.0.traces() {
+            self.0.step(step);
+        }
+        if self.1.traces() {
+            self.1.step(step);
+        }
+    }
+}
+
+/// Reports to the reporter if there is one, which is handy for reporters which
+/// are only sometimes wanted.
+impl<R: Reporter> Reporter for Option<R> {
+    fn parsed(&mut self, program: &BfProgram) {
+        if let Some(reporter) = self {
+            reporter.parsed(program);
+        }
+    }
+
+    fn optimized(&mut self, stats: &[PassStats]) {
+        if let Some(reporter) = self {
+            reporter.optimized(stats);
+        }
+    }
+
+    fn traces(&self) -> bool {
+        self.as_ref().is_some_and(R::traces)
+    }
+
+    fn step(&mut self, step: &Step<'_>) {
+        if let Some(reporter) = self {
+            reporter.step(step);
+        }
+    }
+}
+
+/// The operation run at a single step, which is either an instruction of the
+/// original program or a node of a lowered program.
+#[derive(Debug, Clone, Copy)]
+pub enum TracedOp<'s> {
+    /// An instruction of the original program.
+    Instruction(Operation),
+    /// A node of a lowered program.
+    Node(&'s IrOp),
+}
+
+impl TracedOp<'_> {
+    /// A short name for the kind of operation, leaving out any amounts or
+    /// offsets it has, for counting how often each kind runs.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            TracedOp::Instruction(operation) => match operation {
+                Operation::IncrementPointer | Operation::DecrementPointer => {
+                    "move"
+                }
+                Operation::IncrementByte | Operation::DecrementByte => "add",
+                Operation::OutputByte => "output",
+                Operation::InputByte => "input",
+                Operation::StartLoop => "loop_start",
+                Operation::EndLoop => "loop_end",
+                Operation::Extension(_) => "extension",
+            },
+            TracedOp::Node(op) => match op {
+                IrOp::Add(_) => "add",
+                IrOp::Move(_) => "move",
+                IrOp::Output => "output",
+                IrOp::Input => "input",
+                IrOp::LoopStart => "loop_start",
+                IrOp::LoopEnd => "loop_end",
+                IrOp::If => "if",
+                IrOp::Else => "else",
+                IrOp::EndIf => "end_if",
+                IrOp::Clear => "clear",
+                IrOp::AddAt(..) => "add_at",
+                IrOp::ClearAt(_) => "clear_at",
+                IrOp::AddRange(..) => "add_range",
+                IrOp::ClearRange(..) => "clear_range",
+                IrOp::CopyLoop(_) => "copy_loop",
+                IrOp::MulAdd(_) => "mul_add",
+                IrOp::ScanRight(_) => "scan_right",
+                IrOp::ScanLeft(_) => "scan_left",
+                IrOp::OutputBytes(_) => "output_bytes",
+                IrOp::LoadTape(_) => "load_tape",
+                IrOp::Extension(_) => "extension",
+            },
+        }
+    }
+
+    /// The number of bytes of output the operation writes.
+    pub fn output_len(&self) -> usize {
+        match self {
+            TracedOp::Instruction(Operation::OutputByte)
+            | TracedOp::Node(IrOp::Output) => 1,
+            TracedOp::Node(IrOp::OutputBytes(bytes)) => bytes.len(),
+            _ => 0,
+        }
+    }
+}
+
+impl fmt::Display for TracedOp<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TracedOp::Instruction(operation) => {
+                write!(f, "{}", operation.compact())
+            }
+            TracedOp::Node(op) => write!(f, "{:?}", op),
+        }
+    }
+}
+
+/// A single step of a program, as the Virtual Machine is about to run it.
+#[derive(Debug, Clone, Copy)]
+pub struct Step<'s> {
+    pub(crate) number: u64,
+    pub(crate) source: InstructionInfo,
+    pub(crate) operation: TracedOp<'s>,
+    pub(crate) head: usize,
+    pub(crate) cell: u32,
+    pub(crate) tape_length: usize,
+}
+
+impl Step<'_> {
+    /// The number of the step, counting from 1.
+    pub fn number(&self) -> u64 {
+        self.number
+    }
+
+    /// The instruction in the original program which the step came from.
+    pub fn source(&self) -> InstructionInfo {
+        self.source
+    }
+
+    /// The operation about to be run.
+    pub fn operation(&self) -> TracedOp<'_> {
+        self.operation
+    }
+
+    /// The position of the head of the tape.
+    pub fn head(&self) -> usize {
+        self.head
+    }
+
+    /// The value of the cell at the head of the tape.
+    pub fn cell(&self) -> u32 {
+        self.cell
+    }
+
+    /// The number of cells on the tape.
+    pub fn tape_length(&self) -> usize {
+        self.tape_length
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Reporter, Step, TracedOp};
+    use bft_types::ops::Operation;
+    use bft_types::BfProgram;
+
+    /// Counts the steps it is given, tracing only if asked to.
+    struct Counter(bool, u64);
+
+    impl Reporter for Counter {
+        fn traces(&self) -> bool {
+            self.0
+        }
+
+        fn step(&mut self, _step: &Step<'_>) {
+            self.1 += 1;
+        }
+    }
+
+    #[test]
+    fn test_combined_reporters() {
+        let program = BfProgram::new("+".to_string(), "test.bf").unwrap();
+        let step = Step {
+            number: 1,
+            source: program.instructions()[0],
+            operation: TracedOp::Instruction(Operation::IncrementByte),
+            head: 0,
+            cell: 0,
+            tape_length: 1,
+        };
+        let mut reporter = (Counter(false, 0), Some(Counter(true, 0)));
+        assert!(reporter.traces());
+        reporter.step(&step);
+        assert_eq!(reporter.0 .1, 0);
+        assert_eq!(reporter.1.as_ref().map(|counter| counter.1), Some(1));
+
+        let reporter = (Counter(false, 0), None::<Counter>);
+        assert!(!reporter.traces());
+    }
+}
@@ -0,0 +1,29 @@
+//! Running a program a piece at a time, for hosts such as GUIs and async
+//! runtimes which cannot block on a `Read` or `Write`, and would rather drive
+//! the Virtual Machine as a state machine.
+
+/// Why `VirtualMachine::interpret_resumable` stopped running the program.
+///
+/// ```
+/// use bft_types::BfProgram;
+/// use bft_interp::VirtualMachine;
+/// use bft_interp::resume::Event;
+///
+/// let program = BfProgram::new(",+.".to_string(), "next.bf").unwrap();
+/// let mut vm = VirtualMachine::<u8>::new(&program, 1, false);
+/// assert_eq!(vm.interpret_resumable().unwrap(), Event::NeedsInput);
+/// vm.provide_input(Some(b'a'));
+/// assert_eq!(vm.interpret_resumable().unwrap(), Event::ProducedOutput(b'b'));
+/// assert_eq!(vm.interpret_resumable().unwrap(), Event::Halted);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    /// The program has reached a `,`, and is waiting for
+    /// `VirtualMachine::provide_input` to give it a byte, or the end of the
+    /// input.
+    NeedsInput,
+    /// The program has written a byte with `.`.
+    ProducedOutput(u8),
+    /// The program has run to the end.
+    Halted,
+}
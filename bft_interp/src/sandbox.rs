@@ -0,0 +1,479 @@
+//! Everything a program is allowed to do, in one place, for running programs
+//! which are not trusted.
+//!
+//! A `Sandbox` holds every limit on a Virtual Machine: how many steps it may
+//! take, how much gas it may use, how long it may run for, how far its tape
+//! may grow, how much it may read and write, and which host functions it may
+//! call. It is checked when it is built, so that a policy which makes no sense
+//! is turned down before any program is run under it, and is then given to a
+//! Virtual Machine with `VirtualMachine::with_sandbox`:
+//! ```
+//! use std::io::Cursor;
+//! use std::time::Duration;
+//! use bft_types::BfProgram;
+//! use bft_types::vm_error::VirtualMachineError;
+//! use bft_interp::VirtualMachine;
+//! use bft_interp::sandbox::{Sandbox, Syscalls};
+//!
+//! let sandbox = Sandbox::builder()
+//!     .with_step_limit(1_000)
+//!     .with_time_limit(Duration::from_secs(1))
+//!     .with_memory_limit(64)
+//!     .with_tape_growth(true)
+//!     .with_syscalls(Syscalls::only([1]))
+//!     .build()
+//!     .unwrap();
+//!
+//! let program = BfProgram::new("+[>+]".to_string(), "greedy.bf").unwrap();
+//! let mut vm = VirtualMachine::<u8>::new(&program, 8, false).with_sandbox(sandbox);
+//! let result = vm.interpret(&mut Cursor::new(Vec::new()), &mut Vec::new());
+//! assert!(matches!(
+//!     result,
+//!     Err(VirtualMachineError::TapeGrowthRefused { cells: 65, .. })
+//! ));
+//! ```
+//!
+//! With the `serde` feature, sandboxes can be stored and loaded, such as to
+//! keep a policy for each tenant of a service. They are checked as they are
+//! loaded, just as when they are built, and are written with kebab-case keys,
+//! the time limit in milliseconds, and the host functions as `"none"`, `"all"`
+//! or a list of their numbers:
+//! ```json
+//! {"step-limit": 1000000, "time-limit-ms": 500, "memory-limit": 65536,
+//!  "tape-growth": true, "syscalls": [1]}
+//! ```
+
+use std::collections::BTreeSet;
+use std::time::Duration;
+
+use crate::ir::IrOp;
+
+/// The host functions a program may call with `%`.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(try_from = "SyscallsSpec", into = "SyscallsSpec")
+)]
+pub enum Syscalls {
+    /// No host functions at all, so each call fails with `SyscallsDisabled`.
+    #[default]
+    None,
+    /// Every host function registered with the Virtual Machine.
+    All,
+    /// Only the host functions with these numbers, so calls to any others
+    /// fail with `SyscallDenied`.
+    Only(BTreeSet<u32>),
+}
+
+impl Syscalls {
+    /// Allows only the host functions with the given numbers.
+    pub fn only(ids: impl IntoIterator<Item = u32>) -> Self {
+        Syscalls::Only(ids.into_iter().collect())
+    }
+
+    /// Whether any host functions may be called.
+    pub fn enabled(&self) -> bool {
+        *self != Syscalls::None
+    }
+
+    /// Whether the host function with the number may be called.
+    pub fn allows(&self, id: u32) -> bool {
+        match self {
+            Syscalls::None => false,
+            Syscalls::All => true,
+            Syscalls::Only(ids) => ids.contains(&id),
+        }
+    }
+}
+
+/// How `Syscalls` are written: `"none"`, `"all"` or a list of numbers.
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(untagged)]
+enum SyscallsSpec {
+    Named(String),
+    Only(BTreeSet<u32>),
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<SyscallsSpec> for Syscalls {
+    type Error = String;
+
+    fn try_from(spec: SyscallsSpec) -> Result<Self, String> {
+        match spec {
+            SyscallsSpec::Named(name) => match name.as_str() {
+                "none" => Ok(Syscalls::None),
+                "all" => Ok(Syscalls::All),
+                _ => Err(format!(
+                    "unknown syscalls '{}', expected none, all or a list of \
+                    numbers",
+                    name
+                )),
+            },
+            SyscallsSpec::Only(ids) => Ok(Syscalls::Only(ids)),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl From<Syscalls> for SyscallsSpec {
+    fn from(syscalls: Syscalls) -> Self {
+        match syscalls {
+            Syscalls::None => SyscallsSpec::Named("none".to_string()),
+            Syscalls::All => SyscallsSpec::Named("all".to_string()),
+            Syscalls::Only(ids) => SyscallsSpec::Only(ids),
+        }
+    }
+}
+
+/// Writes the time limit as a number of milliseconds.
+#[cfg(feature = "serde")]
+mod millis {
+    use std::time::Duration;
+
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub(super) fn serialize<S>(
+        limit: &Option<Duration>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match limit {
+            Some(limit) => serializer.serialize_some(&limit.as_millis()),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub(super) fn deserialize<'de, D>(
+        deserializer: D,
+    ) -> Result<Option<Duration>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Ok(
+            Option::<u64>::deserialize(deserializer)?
+                .map(Duration::from_millis),
+        )
+    }
+}
+
+/// Every limit on what a program run by a Virtual Machine may do, which has
+/// been checked to make sense. The default sandbox has no limits, but does not
+/// let the tape grow or the program call host functions.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(try_from = "SandboxBuilder", into = "SandboxBuilder")
+)]
+pub struct Sandbox {
+    pub(crate) step_limit: Option<u64>,
+    pub(crate) gas_limit: Option<u64>,
+    pub(crate) time_limit: Option<Duration>,
+    pub(crate) memory_limit: Option<usize>,
+    pub(crate) output_limit: Option<u64>,
+    pub(crate) input_limit: Option<u64>,
+    pub(crate) syscalls: Syscalls,
+    pub(crate) tape_growth: bool,
+}
+
+impl Sandbox {
+    /// Starts building a sandbox, from the default one.
+    pub fn builder() -> SandboxBuilder {
+        SandboxBuilder::default()
+    }
+
+    /// The most steps the program may take, after which it fails with
+    /// `StepLimitExceeded`.
+    pub fn step_limit(&self) -> Option<u64> {
+        self.step_limit
+    }
+
+    /// The most gas the program may use, after which it fails with
+    /// `GasExhausted`. See `gas_cost` for what each step costs.
+    pub fn gas_limit(&self) -> Option<u64> {
+        self.gas_limit
+    }
+
+    /// How long the program may run for, from its first step, after which it
+    /// fails with `TimeLimitExceeded`.
+    pub fn time_limit(&self) -> Option<Duration> {
+        self.time_limit
+    }
+
+    /// The most cells the tape may grow to, past which it fails with
+    /// `TapeGrowthRefused`.
+    pub fn memory_limit(&self) -> Option<usize> {
+        self.memory_limit
+    }
+
+    /// The most bytes the program may write, after which it fails with
+    /// `OutputLimitExceeded`.
+    pub fn output_limit(&self) -> Option<u64> {
+        self.output_limit
+    }
+
+    /// The most bytes the program may read, after which it is at the end of
+    /// its input.
+    pub fn input_limit(&self) -> Option<u64> {
+        self.input_limit
+    }
+
+    /// The host functions the program may call.
+    pub fn syscalls(&self) -> &Syscalls {
+        &self.syscalls
+    }
+
+    /// Whether the tape grows when the head moves off its end.
+    pub fn tape_growth(&self) -> bool {
+        self.tape_growth
+    }
+
+    /// Whether the limits need each step to be watched, which the loop for 8
+    /// bit cells does not do.
+    pub(crate) fn watches_steps(&self) -> bool {
+        self.gas_limit.is_some()
+            || self.time_limit.is_some()
+            || self.memory_limit.is_some()
+    }
+}
+
+/// Builds a `Sandbox`, checking its limits make sense once they are all given.
+/// Every limit is off unless it is given, as are tape growth and host
+/// functions.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default, deny_unknown_fields, rename_all = "kebab-case")
+)]
+pub struct SandboxBuilder {
+    step_limit: Option<u64>,
+    gas_limit: Option<u64>,
+    #[cfg_attr(
+        feature = "serde",
+        serde(rename = "time-limit-ms", with = "millis")
+    )]
+    time_limit: Option<Duration>,
+    memory_limit: Option<usize>,
+    output_limit: Option<u64>,
+    input_limit: Option<u64>,
+    syscalls: Syscalls,
+    tape_growth: bool,
+}
+
+impl SandboxBuilder {
+    /// Limits the number of steps the program may take.
+    pub fn with_step_limit(mut self, limit: u64) -> Self {
+        self.step_limit = Some(limit);
+        self
+    }
+
+    /// Limits the gas the program may use.
+    pub fn with_gas_limit(mut self, limit: u64) -> Self {
+        self.gas_limit = Some(limit);
+        self
+    }
+
+    /// Limits how long the program may run for.
+    pub fn with_time_limit(mut self, limit: Duration) -> Self {
+        self.time_limit = Some(limit);
+        self
+    }
+
+    /// Limits how many cells the tape may grow to.
+    pub fn with_memory_limit(mut self, cells: usize) -> Self {
+        self.memory_limit = Some(cells);
+        self
+    }
+
+    /// Limits the number of bytes the program may write.
+    pub fn with_output_limit(mut self, limit: u64) -> Self {
+        self.output_limit = Some(limit);
+        self
+    }
+
+    /// Limits the number of bytes the program may read.
+    pub fn with_input_limit(mut self, limit: u64) -> Self {
+        self.input_limit = Some(limit);
+        self
+    }
+
+    /// Chooses the host functions the program may call.
+    pub fn with_syscalls(mut self, syscalls: Syscalls) -> Self {
+        self.syscalls = syscalls;
+        self
+    }
+
+    /// Chooses whether the tape grows when the head moves off its end.
+    pub fn with_tape_growth(mut self, growth: bool) -> Self {
+        self.tape_growth = growth;
+        self
+    }
+
+    /// Builds the sandbox, failing if its limits do not make sense together.
+    /// ```
+    /// use std::time::Duration;
+    /// use bft_interp::sandbox::Sandbox;
+    ///
+    /// // A memory limit needs a tape which can grow up to it.
+    /// assert!(Sandbox::builder().with_memory_limit(100).build().is_err());
+    /// assert!(Sandbox::builder().with_time_limit(Duration::ZERO).build().is_err());
+    /// ```
+    pub fn build(self) -> Result<Sandbox, String> {
+        if self.memory_limit == Some(0) {
+            return Err(
+                "the memory limit must be at least one cell".to_string()
+            );
+        }
+        if self.memory_limit.is_some() && !self.tape_growth {
+            return Err("a memory limit needs the tape to be allowed to grow"
+                .to_string());
+        }
+        if self.time_limit == Some(Duration::ZERO) {
+            return Err("the time limit must be more than zero".to_string());
+        }
+        Ok(Sandbox {
+            step_limit: self.step_limit,
+            gas_limit: self.gas_limit,
+            time_limit: self.time_limit,
+            memory_limit: self.memory_limit,
+            output_limit: self.output_limit,
+            input_limit: self.input_limit,
+            syscalls: self.syscalls,
+            tape_growth: self.tape_growth,
+        })
+    }
+}
+
+impl From<Sandbox> for SandboxBuilder {
+    fn from(sandbox: Sandbox) -> Self {
+        SandboxBuilder {
+            step_limit: sandbox.step_limit,
+            gas_limit: sandbox.gas_limit,
+            time_limit: sandbox.time_limit,
+            memory_limit: sandbox.memory_limit,
+            output_limit: sandbox.output_limit,
+            input_limit: sandbox.input_limit,
+            syscalls: sandbox.syscalls,
+            tape_growth: sandbox.tape_growth,
+        }
+    }
+}
+
+impl TryFrom<SandboxBuilder> for Sandbox {
+    type Error = String;
+
+    fn try_from(builder: SandboxBuilder) -> Result<Self, String> {
+        builder.build()
+    }
+}
+
+/// The gas a step costs. Each instruction of a program costs one, as does each
+/// node of a lowered program, except those which stand for work on many cells
+/// or bytes at once, which cost one for each of them, so that optimizing a
+/// program does not let it do more work for the same gas.
+/// ```
+/// use bft_interp::ir::IrOp;
+/// use bft_interp::sandbox::gas_cost;
+///
+/// assert_eq!(gas_cost(&IrOp::Add(5)), 1);
+/// assert_eq!(gas_cost(&IrOp::OutputBytes(b"hello".to_vec())), 5);
+/// ```
+pub fn gas_cost(op: &IrOp) -> u64 {
+    let cells = match op {
+        IrOp::AddRange(_, len, _) | IrOp::ClearRange(_, len) => *len,
+        IrOp::CopyLoop(factors) => factors.len() + 1,
+        IrOp::MulAdd(mul) => {
+            mul.products.len() + mul.adds.len() + mul.sets.len() + 1
+        }
+        IrOp::OutputBytes(bytes) => bytes.len(),
+        IrOp::LoadTape(cells) => cells.len(),
+        _ => 1,
+    };
+    cells.max(1) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{gas_cost, Sandbox, Syscalls};
+    use crate::ir::{IrOp, MulLoop};
+    use std::time::Duration;
+
+    #[test]
+    fn test_build() {
+        let sandbox = Sandbox::builder()
+            .with_step_limit(10)
+            .with_gas_limit(20)
+            .with_output_limit(3)
+            .with_input_limit(4)
+            .with_syscalls(Syscalls::All)
+            .build()
+            .unwrap();
+        assert_eq!(sandbox.step_limit(), Some(10));
+        assert_eq!(sandbox.gas_limit(), Some(20));
+        assert_eq!(sandbox.output_limit(), Some(3));
+        assert_eq!(sandbox.input_limit(), Some(4));
+        assert!(!sandbox.tape_growth());
+        assert!(sandbox.watches_steps());
+        assert!(!Sandbox::default().watches_steps());
+
+        let zero_memory = Sandbox::builder()
+            .with_tape_growth(true)
+            .with_memory_limit(0);
+        assert!(zero_memory.build().unwrap_err().contains("one cell"));
+        let no_growth = Sandbox::builder().with_memory_limit(10);
+        assert!(no_growth.build().unwrap_err().contains("grow"));
+        let no_time = Sandbox::builder().with_time_limit(Duration::ZERO);
+        assert!(no_time.build().unwrap_err().contains("more than zero"));
+    }
+
+    #[test]
+    fn test_syscalls() {
+        let only = Syscalls::only([1, 3]);
+        assert!(only.enabled() && only.allows(3) && !only.allows(2));
+        assert!(Syscalls::All.allows(2));
+        assert!(!Syscalls::None.enabled() && !Syscalls::None.allows(1));
+    }
+
+    #[test]
+    fn test_gas_cost() {
+        assert_eq!(gas_cost(&IrOp::Move(3)), 1);
+        assert_eq!(gas_cost(&IrOp::ClearRange(0, 4)), 4);
+        assert_eq!(gas_cost(&IrOp::CopyLoop(vec![(1, 1), (2, 3)])), 3);
+        let mul = MulLoop {
+            products: vec![(1, 2, 3)],
+            adds: vec![(3, 1)],
+            sets: Vec::new(),
+        };
+        assert_eq!(gas_cost(&IrOp::MulAdd(Box::new(mul))), 3);
+        assert_eq!(gas_cost(&IrOp::OutputBytes(Vec::new())), 1);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde() {
+        let json = r#"{"step-limit": 100, "time-limit-ms": 250,
+            "memory-limit": 64, "tape-growth": true, "syscalls": [1]}"#;
+        let sandbox: Sandbox = serde_json::from_str(json).unwrap();
+        assert_eq!(sandbox.time_limit(), Some(Duration::from_millis(250)));
+        assert_eq!(sandbox.memory_limit(), Some(64));
+        assert_eq!(sandbox.syscalls(), &Syscalls::only([1]));
+        let written = serde_json::to_string(&sandbox).unwrap();
+        assert_eq!(serde_json::from_str::<Sandbox>(&written).unwrap(), sandbox);
+
+        let all: Sandbox =
+            serde_json::from_str(r#"{"syscalls": "all"}"#).unwrap();
+        assert_eq!(all.syscalls(), &Syscalls::All);
+        // Policies are checked as they are loaded.
+        let invalid = serde_json::from_str::<Sandbox>(r#"{"memory-limit": 8}"#);
+        assert!(invalid.unwrap_err().to_string().contains("grow"));
+        assert!(serde_json::from_str::<Sandbox>(r#"{"gas": 8}"#).is_err());
+        assert!(
+            serde_json::from_str::<Sandbox>(r#"{"syscalls": "some"}"#).is_err()
+        );
+    }
+}
@@ -0,0 +1,508 @@
+//! Running many Virtual Machines on one thread, taking turns, for simulations
+//! and servers with more small Brainfuck tasks than it is worth having a
+//! thread for each of.
+//!
+//! Each turn, a `Scheduler` runs every task which has not yet finished for up
+//! to a slice of steps, in the order they were spawned:
+//! ```
+//! use std::rc::Rc;
+//! use std::cell::RefCell;
+//! use bft_types::BfProgram;
+//! use bft_interp::scheduler::Scheduler;
+//!
+//! let program = BfProgram::new("+++[.-]".to_string(), "count.bf").unwrap();
+//! let output = Rc::new(RefCell::new(Vec::new()));
+//! let mut scheduler = Scheduler::<u8>::new(3);
+//! for _ in 0..2 {
+//!     let output = Rc::clone(&output);
+//!     scheduler.spawn(&program, || None, move |b| output.borrow_mut().push(b));
+//! }
+//! scheduler.run_until_all_halt();
+//! // The two tasks take turns at three steps each.
+//! assert_eq!(*output.borrow(), [3, 3, 2, 2, 1, 1]);
+//! ```
+//!
+//! Tasks can be given `TaskOptions` to share the thread out unevenly, or to
+//! cut off untrusted programs which run for too long. A task with a higher
+//! priority runs for that many slices in each turn, tasks with deadlines run
+//! first in each turn, soonest first, and a task can be given a quota of
+//! steps which it may take in all. Every task which has not finished runs in
+//! every turn, however low its priority, so that none of them are starved.
+
+use std::io::ErrorKind;
+use std::time::Instant;
+
+use bft_types::vm_error::VirtualMachineError;
+use bft_types::BfProgram;
+
+use crate::io::{InputSource, OutputSink};
+use crate::resume::Event;
+use crate::{CellKind, VirtualMachine, DEFAULT_TAPE_LENGTH};
+
+/// Identifies a task spawned on a `Scheduler`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TaskId(usize);
+
+/// How a task is scheduled.
+/// ```
+/// use std::time::{Duration, Instant};
+/// use bft_interp::scheduler::TaskOptions;
+///
+/// let options = TaskOptions::new()
+///     .with_priority(4)
+///     .with_quota(1_000_000)
+///     .with_deadline(Instant::now() + Duration::from_secs(1));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TaskOptions {
+    priority: u32,
+    quota: Option<u64>,
+    deadline: Option<Instant>,
+}
+
+impl TaskOptions {
+    /// Options for a task with a priority of 1, and no quota or deadline.
+    pub fn new() -> Self {
+        Self {
+            priority: 1,
+            quota: None,
+            deadline: None,
+        }
+    }
+
+    /// Sets the number of slices the task runs for in each turn.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the priority is zero, as the task would never run.
+    pub fn with_priority(mut self, priority: u32) -> Self {
+        assert!(priority > 0, "a priority must be at least one");
+        self.priority = priority;
+        self
+    }
+
+    /// Limits the number of steps the task may take under the scheduler in
+    /// all, after which it is stopped as `TaskState::OutOfQuota`.
+    pub fn with_quota(mut self, quota: u64) -> Self {
+        self.quota = Some(quota);
+        self
+    }
+
+    /// Sets when the task must have finished by. A task still running at its
+    /// deadline is stopped as `TaskState::MissedDeadline` when its next turn
+    /// comes around, so it may run for up to a turn past it.
+    pub fn with_deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+}
+
+impl Default for TaskOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Where a task has got to.
+#[derive(Debug)]
+pub enum TaskState {
+    /// The task is ready to run in its next turn.
+    Ready,
+    /// The task is waiting for input which was not ready, and will ask for it
+    /// again in its next turn.
+    Waiting,
+    /// The program ran to the end.
+    Halted,
+    /// The program failed with an error, which includes reaching the step
+    /// limit of its Virtual Machine.
+    Failed(VirtualMachineError),
+    /// The task took all of the steps of its quota without finishing.
+    OutOfQuota,
+    /// The task had not finished by its deadline.
+    MissedDeadline,
+}
+
+impl TaskState {
+    /// Whether the task has finished, and will not run again.
+    pub fn is_finished(&self) -> bool {
+        !matches!(self, TaskState::Ready | TaskState::Waiting)
+    }
+}
+
+/// A Virtual Machine run by a scheduler, along with its input and output.
+struct Task<'a, T> {
+    vm: VirtualMachine<'a, T>,
+    input: Box<dyn InputSource + 'a>,
+    output: Box<dyn OutputSink + 'a>,
+    options: TaskOptions,
+    state: TaskState,
+    /// The number of steps the task has taken under the scheduler.
+    steps: u64,
+}
+
+impl<'a, T> Task<'a, T>
+where
+    T: CellKind + Default + Clone + Copy + PartialEq,
+{
+    /// Runs the task for up to the given number of slices, or until the end
+    /// of its quota, and works out where it got to. The step limit of the
+    /// Virtual Machine itself is kept to, and put back afterwards.
+    fn run_slices(&mut self, slice: u64) {
+        let budget = slice.saturating_mul(self.options.priority.into());
+        let budget = match self.options.quota {
+            Some(quota) => budget.min(quota - self.steps),
+            None => budget,
+        };
+        let limit = self.vm.step_limit();
+        let start = self.vm.steps();
+        let end = start.saturating_add(budget);
+        self.vm
+            .set_step_limit(Some(limit.map_or(end, |limit| limit.min(end))));
+        let result = self.resume();
+        self.vm.set_step_limit(limit);
+        self.steps += self.vm.steps() - start;
+        self.state = match result {
+            Ok(state) => state,
+            Err(VirtualMachineError::StepLimitExceeded { .. })
+                if limit != Some(self.vm.steps()) =>
+            {
+                if self.options.quota == Some(self.steps) {
+                    TaskState::OutOfQuota
+                } else {
+                    TaskState::Ready
+                }
+            }
+            Err(err) => TaskState::Failed(err),
+        };
+    }
+
+    /// Runs the task until it halts, fails, reaches its step limit or waits
+    /// for input which is not ready.
+    fn resume(&mut self) -> Result<TaskState, VirtualMachineError> {
+        loop {
+            match self.vm.interpret_resumable()? {
+                Event::NeedsInput => match self.input.next_byte() {
+                    Ok(byte) => self.vm.provide_input(byte),
+                    Err(err) if err.kind() == ErrorKind::WouldBlock => {
+                        return Ok(TaskState::Waiting)
+                    }
+                    Err(err) => return Err(err.into()),
+                },
+                Event::ProducedOutput(byte) => {
+                    self.output.write_byte(byte)?;
+                    self.output.flush()?;
+                }
+                Event::Halted => return Ok(TaskState::Halted),
+            }
+        }
+    }
+}
+
+/// Runs many Virtual Machines on one thread, taking turns to run each for a
+/// slice of steps.
+///
+/// Tasks read and write through an `InputSource` and `OutputSink`, so that
+/// they never block one another. A source with no byte ready yet may give an
+/// error of kind `WouldBlock`, and the task then waits for its next turn to
+/// ask again. Extension instructions see an empty input, and anything they
+/// write is thrown away.
+pub struct Scheduler<'a, T> {
+    tasks: Vec<Task<'a, T>>,
+    /// The most steps each task runs for in a turn, for each level of its
+    /// priority.
+    slice: u64,
+}
+
+impl<'a, T> Scheduler<'a, T>
+where
+    T: CellKind + Default + Clone + Copy + PartialEq + 'a,
+{
+    /// Creates a scheduler which runs each task for up to the given number of
+    /// steps in a turn, times its priority. Smaller slices share the thread
+    /// out more fairly, and larger ones spend less time switching between
+    /// tasks.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the slice is zero, as no task would ever get anywhere.
+    pub fn new(slice: u64) -> Self {
+        assert!(slice > 0, "a slice must be at least one step");
+        Self {
+            tasks: Vec::new(),
+            slice,
+        }
+    }
+
+    /// Spawns a task running the program on a Virtual Machine with the
+    /// default tape, which does not grow.
+    pub fn spawn(
+        &mut self,
+        program: &'a BfProgram,
+        input: impl InputSource + 'a,
+        output: impl OutputSink + 'a,
+    ) -> TaskId {
+        let vm = VirtualMachine::new(program, DEFAULT_TAPE_LENGTH, false);
+        self.spawn_vm(vm, input, output)
+    }
+
+    /// Spawns a task running a Virtual Machine which has already been set
+    /// up, such as with a step limit or `EofBehavior` of its own. A task which
+    /// reaches its own step limit fails with `StepLimitExceeded`.
+    pub fn spawn_vm(
+        &mut self,
+        vm: VirtualMachine<'a, T>,
+        input: impl InputSource + 'a,
+        output: impl OutputSink + 'a,
+    ) -> TaskId {
+        self.spawn_with(vm, input, output, TaskOptions::new())
+    }
+
+    /// Spawns a task running the Virtual Machine, scheduled with the given
+    /// options.
+    pub fn spawn_with(
+        &mut self,
+        vm: VirtualMachine<'a, T>,
+        input: impl InputSource + 'a,
+        output: impl OutputSink + 'a,
+        options: TaskOptions,
+    ) -> TaskId {
+        let state = match options.quota {
+            Some(0) => TaskState::OutOfQuota,
+            _ => TaskState::Ready,
+        };
+        self.tasks.push(Task {
+            vm,
+            input: Box::new(input),
+            output: Box::new(output),
+            options,
+            state,
+            steps: 0,
+        });
+        TaskId(self.tasks.len() - 1)
+    }
+
+    /// Gives every task which has not finished a turn, those with deadlines
+    /// first, returning how many are still running afterwards.
+    pub fn run_turn(&mut self) -> usize {
+        let mut order: Vec<usize> = (0..self.tasks.len())
+            .filter(|&index| !self.tasks[index].state.is_finished())
+            .collect();
+        // Tasks without a deadline sort after those with one, as None sorts
+        // before Some.
+        order.sort_by_key(|&index| {
+            let deadline = self.tasks[index].options.deadline;
+            (deadline.is_none(), deadline)
+        });
+        let now = Instant::now();
+        let mut running = 0;
+        for index in order {
+            let task = &mut self.tasks[index];
+            if task
+                .options
+                .deadline
+                .is_some_and(|deadline| deadline <= now)
+            {
+                task.state = TaskState::MissedDeadline;
+                continue;
+            }
+            task.run_slices(self.slice);
+            running += usize::from(!task.state.is_finished());
+        }
+        running
+    }
+
+    /// Gives the tasks turns until every one of them has finished. This never
+    /// returns if a task never halts and has no step limit, quota or
+    /// deadline, or waits forever for input.
+    pub fn run_until_all_halt(&mut self) {
+        while self.run_turn() > 0 {}
+    }
+
+    /// Where the task has got to.
+    pub fn state(&self, task: TaskId) -> &TaskState {
+        &self.tasks[task.0].state
+    }
+
+    /// The number of steps the task has taken under the scheduler, which
+    /// counts towards its quota.
+    pub fn steps(&self, task: TaskId) -> u64 {
+        self.tasks[task.0].steps
+    }
+
+    /// The Virtual Machine running the task, such as to look at its tape.
+    pub fn vm(&self, task: TaskId) -> &VirtualMachine<'a, T> {
+        &self.tasks[task.0].vm
+    }
+
+    /// Every task spawned, in the order they were spawned.
+    pub fn tasks(&self) -> impl Iterator<Item = TaskId> {
+        (0..self.tasks.len()).map(TaskId)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Scheduler, TaskOptions, TaskState};
+    use crate::eof::EofBehavior;
+    use crate::VirtualMachine;
+    use bft_types::vm_error::VirtualMachineError;
+    use bft_types::BfProgram;
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+    use std::io::{self, ErrorKind};
+    use std::rc::Rc;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_step_limits() {
+        let forever = BfProgram::new("+[]".to_string(), "forever.bf").unwrap();
+        let done = BfProgram::new("+>+".to_string(), "done.bf").unwrap();
+        let mut scheduler = Scheduler::<u8>::new(10);
+        let vm = VirtualMachine::new(&forever, 1, false).with_step_limit(25);
+        let limited = scheduler.spawn_vm(vm, || None, |_| {});
+        let quick = scheduler.spawn(&done, || None, |_| {});
+        assert_eq!(scheduler.run_turn(), 1);
+        assert!(matches!(scheduler.state(quick), TaskState::Halted));
+        assert_eq!(scheduler.vm(quick).tape()[..2], [1, 1]);
+        assert!(matches!(scheduler.state(limited), TaskState::Ready));
+        assert_eq!(scheduler.vm(limited).steps(), 10);
+
+        // The task keeps to its own step limit across turns.
+        scheduler.run_until_all_halt();
+        assert!(matches!(
+            scheduler.state(limited),
+            TaskState::Failed(VirtualMachineError::StepLimitExceeded {
+                limit: 25,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_waiting_for_input() {
+        // The input arrives a byte per turn, from the output of another task.
+        let program = BfProgram::new(",[.,]".to_string(), "cat.bf").unwrap();
+        let source = BfProgram::new("+++[.-]".to_string(), "src.bf").unwrap();
+        let pipe = Rc::new(RefCell::new(VecDeque::new()));
+        let (reader, writer) = (Rc::clone(&pipe), Rc::clone(&pipe));
+        let closed = Rc::new(RefCell::new(false));
+        let eof = Rc::clone(&closed);
+        let output = Rc::new(RefCell::new(Vec::new()));
+        let written = Rc::clone(&output);
+
+        struct Reader<F>(F);
+        impl<F: FnMut() -> io::Result<Option<u8>>> crate::io::InputSource
+            for Reader<F>
+        {
+            fn next_byte(&mut self) -> io::Result<Option<u8>> {
+                (self.0)()
+            }
+        }
+
+        let mut scheduler = Scheduler::<u8>::new(2);
+        let vm = VirtualMachine::new(&program, 1, false)
+            .with_eof_behavior(EofBehavior::Zero);
+        let cat = scheduler.spawn_vm(
+            vm,
+            Reader(move || match reader.borrow_mut().pop_front() {
+                Some(byte) => Ok(Some(byte)),
+                None if *eof.borrow() => Ok(None),
+                None => Err(ErrorKind::WouldBlock.into()),
+            }),
+            move |byte| written.borrow_mut().push(byte),
+        );
+        scheduler.spawn(
+            &source,
+            || None,
+            move |byte| writer.borrow_mut().push_back(byte),
+        );
+        while scheduler.run_turn() > 1 {}
+        assert!(!scheduler.state(cat).is_finished());
+        *closed.borrow_mut() = true;
+        scheduler.run_until_all_halt();
+        assert!(matches!(scheduler.state(cat), TaskState::Halted));
+        assert_eq!(*output.borrow(), [3, 2, 1]);
+    }
+
+    #[test]
+    fn test_priorities() {
+        let forever = BfProgram::new("+[]".to_string(), "forever.bf").unwrap();
+        let mut scheduler = Scheduler::<u8>::new(5);
+        let vm = || VirtualMachine::new(&forever, 1, false);
+        let options = TaskOptions::new().with_priority(3).with_quota(100);
+        let high = scheduler.spawn_with(vm(), || None, |_| {}, options);
+        let low = scheduler.spawn_vm(vm(), || None, |_| {});
+        scheduler.run_turn();
+        assert_eq!((scheduler.steps(high), scheduler.steps(low)), (15, 5));
+
+        // The quota stops the task part way through a turn.
+        for _ in 0..6 {
+            scheduler.run_turn();
+        }
+        assert!(matches!(scheduler.state(high), TaskState::OutOfQuota));
+        assert_eq!(scheduler.steps(high), 100);
+        assert_eq!(scheduler.vm(high).steps(), 100);
+        assert_eq!(scheduler.steps(low), 35);
+        let ids: Vec<_> = scheduler.tasks().collect();
+        assert_eq!(ids, [high, low]);
+    }
+
+    #[test]
+    fn test_no_starvation() {
+        // However many greedy tasks there are, and whatever their
+        // priorities, a task with the lowest priority still gets its slice of
+        // every turn, and so finishes in as many turns as it would alone.
+        let forever = BfProgram::new("+[]".to_string(), "forever.bf").unwrap();
+        let short = BfProgram::new("+".repeat(20), "short.bf").unwrap();
+        let mut scheduler = Scheduler::<u8>::new(4);
+        for priority in 1..=10 {
+            let vm = VirtualMachine::new(&forever, 1, false);
+            let options = TaskOptions::new().with_priority(priority * 100);
+            scheduler.spawn_with(vm, || None, |_| {}, options);
+        }
+        let task = scheduler.spawn(&short, || None, |_| {});
+        for turn in 1..=5 {
+            assert!(!scheduler.state(task).is_finished());
+            scheduler.run_turn();
+            assert_eq!(scheduler.steps(task), turn * 4);
+        }
+        assert!(matches!(scheduler.state(task), TaskState::Halted));
+    }
+
+    #[test]
+    fn test_deadlines() {
+        let forever = BfProgram::new("+[]".to_string(), "forever.bf").unwrap();
+        let order = Rc::new(RefCell::new(Vec::new()));
+        let program = BfProgram::new(".".to_string(), "dot.bf").unwrap();
+        let mut scheduler = Scheduler::<u8>::new(1);
+        let now = Instant::now();
+        let missed = scheduler.spawn_with(
+            VirtualMachine::new(&forever, 1, false),
+            || None,
+            |_| {},
+            TaskOptions::new().with_deadline(now),
+        );
+        for (name, deadline) in
+            [(b'a', None), (b'b', Some(3600)), (b'c', Some(60))]
+        {
+            let order = Rc::clone(&order);
+            let vm = VirtualMachine::new(&program, 1, false);
+            let mut options = TaskOptions::new();
+            if let Some(seconds) = deadline {
+                options =
+                    options.with_deadline(now + Duration::from_secs(seconds));
+            }
+            scheduler.spawn_with(
+                vm,
+                || None,
+                move |_| order.borrow_mut().push(name),
+                options,
+            );
+        }
+        assert_eq!(scheduler.run_turn(), 0);
+        assert!(matches!(scheduler.state(missed), TaskState::MissedDeadline));
+        assert_eq!(scheduler.steps(missed), 0);
+        // Those with the soonest deadlines go first.
+        assert_eq!(*order.borrow(), b"cba");
+    }
+}
@@ -0,0 +1,427 @@
+//! A summary of a run of a program, taken from the Virtual Machine with
+//! `VirtualMachine::summary` once it has stopped, or at any point along the
+//! way.
+//!
+//! Alongside the totals kept for the limits of the sandbox, the summary counts
+//! how many times each loop of the program has gone round. The Virtual Machine
+//! counts these as it runs, by bumping a counter each time the end of a loop
+//! jumps back to its start, so unlike a `Reporter` they cost next to nothing
+//! and leave the loops specialized for 8 bit cells on. Loops which an
+//! optimization pass turned into a single operation, such as `[-]` into a
+//! clear, never jump back, so are counted as not having gone round at all.
+//! ```
+//! use bft_types::BfProgram;
+//! use bft_interp::VirtualMachine;
+//!
+//! let program = BfProgram::new("++[>+++[-]<-]".to_string(), "nested.bf").unwrap();
+//! let mut vm = VirtualMachine::<u8>::new(&program, 2, false);
+//! vm.run().unwrap();
+//! let summary = vm.summary();
+//! let counts: Vec<_> = summary
+//!     .loop_counts()
+//!     .iter()
+//!     .map(|count| (count.column(), count.iterations()))
+//!     .collect();
+//! assert_eq!(counts, [(3, 1), (8, 4)]);
+//! let hottest = summary.hottest_loop().unwrap();
+//! assert_eq!((hottest.line(), hottest.column()), (1, 8));
+//! ```
+//!
+//! When a run is stopped for going over its steps, gas or time, the summary
+//! can diagnose where it was stuck with `diagnose_hang`: the loops which went
+//! round the most, the loops it was inside of when it stopped, and how long it
+//! had been since it last read or wrote anything.
+
+use bft_types::ops::Operation;
+use bft_types::vm_error::VirtualMachineError;
+use bft_types::BfProgram;
+
+/// The most loops named by a `HangDiagnosis` as the hottest.
+const HOTTEST_LOOPS: usize = 3;
+
+/// How many times a loop of the program went round.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoopCount {
+    position: usize,
+    line: usize,
+    column: usize,
+    iterations: u64,
+}
+
+impl LoopCount {
+    /// The position in the program of the opening bracket of the loop.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// The line of the opening bracket of the loop.
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    /// The column of the opening bracket of the loop.
+    pub fn column(&self) -> usize {
+        self.column
+    }
+
+    /// The number of times the end of the loop jumped back to its start. Each
+    /// time the loop is entered, this is one fewer than the number of times
+    /// its body runs.
+    pub fn iterations(&self) -> u64 {
+        self.iterations
+    }
+}
+
+/// The totals of a run of a program, so far.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RunSummary {
+    steps: u64,
+    gas_used: u64,
+    output_written: u64,
+    input_read: u64,
+    last_io_step: Option<u64>,
+    loop_counts: Vec<LoopCount>,
+}
+
+impl RunSummary {
+    /// Sums up a run of the program, from the number of back edges counted
+    /// against each position in it.
+    pub(crate) fn new(
+        steps: u64,
+        gas_used: u64,
+        output_written: u64,
+        input_read: u64,
+        last_io_step: Option<u64>,
+        program: &BfProgram,
+        back_edges: &[u64],
+    ) -> Self {
+        let loop_counts = program
+            .instructions()
+            .iter()
+            .zip(back_edges)
+            .enumerate()
+            .filter(|(_, (instruction, _))| {
+                instruction.operation() == Operation::StartLoop
+            })
+            .map(|(position, (instruction, &iterations))| LoopCount {
+                position,
+                line: instruction.line(),
+                column: instruction.column(),
+                iterations,
+            })
+            .collect();
+        Self {
+            steps,
+            gas_used,
+            output_written,
+            input_read,
+            last_io_step,
+            loop_counts,
+        }
+    }
+
+    /// The number of steps taken.
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /// The gas used, towards the gas limit of the sandbox.
+    pub fn gas_used(&self) -> u64 {
+        self.gas_used
+    }
+
+    /// The number of bytes of output written.
+    pub fn output_written(&self) -> u64 {
+        self.output_written
+    }
+
+    /// The number of bytes of input read.
+    pub fn input_read(&self) -> u64 {
+        self.input_read
+    }
+
+    /// The step which last read input or wrote output, counting from 1, or
+    /// None if the program has done neither. Reading at the end of the input
+    /// counts too.
+    pub fn last_io_step(&self) -> Option<u64> {
+        self.last_io_step
+    }
+
+    /// How many times each loop of the program went round, in the order of
+    /// their opening brackets, including the loops which never did.
+    pub fn loop_counts(&self) -> &[LoopCount] {
+        &self.loop_counts
+    }
+
+    /// The loop which went round the most times, if any went round at all.
+    /// When a program runs out of steps, this is usually the loop it was stuck
+    /// in.
+    pub fn hottest_loop(&self) -> Option<&LoopCount> {
+        self.loop_counts
+            .iter()
+            .filter(|count| count.iterations > 0)
+            .max_by_key(|count| count.iterations)
+    }
+
+    /// Up to `n` of the loops which went round the most times, the most first,
+    /// leaving out those which never went round.
+    pub fn hottest_loops(&self, n: usize) -> Vec<LoopCount> {
+        let mut hottest: Vec<LoopCount> = self
+            .loop_counts
+            .iter()
+            .filter(|count| count.iterations > 0)
+            .copied()
+            .collect();
+        // Stable, so that loops which went round as often stay in order.
+        hottest.sort_by_key(|count| std::cmp::Reverse(count.iterations));
+        hottest.truncate(n);
+        hottest
+    }
+
+    /// Diagnoses where the program was stuck, if the error stopped it for
+    /// going over its steps, gas or time. The summary must be of a run of the
+    /// program, on which the loops it was inside of are found from where the
+    /// error says it stopped.
+    /// ```
+    /// use std::io::Cursor;
+    /// use bft_types::BfProgram;
+    /// use bft_interp::VirtualMachine;
+    ///
+    /// let program = BfProgram::new(",.+[>+[-]<]".to_string(), "stuck.bf").unwrap();
+    /// let mut vm = VirtualMachine::<u8>::new(&program, 2, false).with_step_limit(98);
+    /// let mut input = Cursor::new(vec![1]);
+    /// let err = vm.interpret(&mut input, &mut Vec::new()).unwrap_err();
+    /// let hang = vm.summary().diagnose_hang(&err, &program).unwrap();
+    /// assert_eq!(hang.hottest_loops()[0].column(), 4);
+    /// let stack: Vec<_> = hang.loop_stack().iter().map(|l| l.column()).collect();
+    /// assert_eq!(stack, [4, 7]);
+    /// assert_eq!(hang.last_io_step(), Some(2));
+    /// assert_eq!(hang.steps_since_io(), Some(96));
+    /// ```
+    pub fn diagnose_hang(
+        &self,
+        err: &VirtualMachineError,
+        program: &BfProgram,
+    ) -> Option<HangDiagnosis> {
+        if !matches!(
+            err,
+            VirtualMachineError::StepLimitExceeded { .. }
+                | VirtualMachineError::GasExhausted { .. }
+                | VirtualMachineError::TimeLimitExceeded { .. }
+        ) {
+            return None;
+        }
+        let stopped_at = err.location().and_then(|(line, column)| {
+            program
+                .instructions()
+                .binary_search_by_key(&(line, column), |instruction| {
+                    (instruction.line(), instruction.column())
+                })
+                .ok()
+        });
+        let loop_stack = match stopped_at {
+            Some(position) => self.loops_around(program, position),
+            None => Vec::new(),
+        };
+        Some(HangDiagnosis {
+            hottest_loops: self.hottest_loops(HOTTEST_LOOPS),
+            loop_stack,
+            steps: self.steps,
+            last_io_step: self.last_io_step,
+        })
+    }
+
+    /// The loops of the program which the position is inside of, outermost
+    /// first, including the loop whose bracket the position is at.
+    fn loops_around(
+        &self,
+        program: &BfProgram,
+        position: usize,
+    ) -> Vec<LoopCount> {
+        let mut stack = Vec::new();
+        let mut loops = program.loops();
+        while let Some(inner) = loops
+            .into_iter()
+            .find(|node| (node.start()..=node.end()).contains(&position))
+        {
+            let count = self
+                .loop_counts
+                .binary_search_by_key(&inner.start(), LoopCount::position)
+                .ok()
+                .map(|index| self.loop_counts[index]);
+            stack.extend(count);
+            loops = inner.children().to_vec();
+        }
+        stack
+    }
+}
+
+/// Where a program which went over its steps, gas or time was most likely
+/// stuck, from `RunSummary::diagnose_hang`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HangDiagnosis {
+    hottest_loops: Vec<LoopCount>,
+    loop_stack: Vec<LoopCount>,
+    steps: u64,
+    last_io_step: Option<u64>,
+}
+
+impl HangDiagnosis {
+    /// The loops which went round the most times, the most first.
+    pub fn hottest_loops(&self) -> &[LoopCount] {
+        &self.hottest_loops
+    }
+
+    /// The loops the program was inside of when it stopped, outermost first.
+    pub fn loop_stack(&self) -> &[LoopCount] {
+        &self.loop_stack
+    }
+
+    /// The step which last read input or wrote output, if any did.
+    pub fn last_io_step(&self) -> Option<u64> {
+        self.last_io_step
+    }
+
+    /// How many steps the program took after it last read input or wrote
+    /// output, if it ever did.
+    pub fn steps_since_io(&self) -> Option<u64> {
+        self.last_io_step.map(|step| self.steps - step)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HangDiagnosis;
+    use crate::dispatch::DispatchKind;
+    use crate::ir::IrProgram;
+    use crate::optimizer::Pipeline;
+    use crate::{CellKind, VirtualMachine};
+    use bft_types::BfProgram;
+    use std::io::Cursor;
+
+    /// Runs the program, or the lowered program with the dispatch if there is
+    /// one, and gives the iterations counted for each loop.
+    fn iterations<T>(
+        program: &BfProgram,
+        ir: Option<(&IrProgram, DispatchKind)>,
+    ) -> Vec<u64>
+    where
+        T: CellKind + Default + Clone + Copy + PartialEq,
+    {
+        let mut vm = VirtualMachine::<T>::new(program, 4, false);
+        let (mut input, mut output) = (Cursor::new(Vec::new()), Vec::new());
+        match ir {
+            Some((ir, dispatch)) => {
+                vm = vm.with_dispatch(dispatch);
+                vm.interpret_ir(ir, &mut input, &mut output).unwrap();
+            }
+            None => vm.interpret(&mut input, &mut output).unwrap(),
+        }
+        let summary = vm.summary();
+        summary
+            .loop_counts()
+            .iter()
+            .map(|c| c.iterations())
+            .collect()
+    }
+
+    #[test]
+    fn test_loop_counts() {
+        let source = "++[>+++[>+<-]<-]>>[-]".to_string();
+        let program = BfProgram::new(source, "nested.bf").unwrap();
+        let expected = [1, 4, 5];
+        assert_eq!(iterations::<u8>(&program, None), expected);
+        assert_eq!(iterations::<u16>(&program, None), expected);
+        let ir = IrProgram::from_program(&program).unwrap();
+        for dispatch in [DispatchKind::Match, DispatchKind::Threaded] {
+            let lowered = Some((&ir, dispatch));
+            assert_eq!(iterations::<u8>(&program, lowered), expected);
+            assert_eq!(iterations::<u16>(&program, lowered), expected);
+        }
+
+        // The inner loops become single operations, which never jump back.
+        let mut optimized = ir.clone();
+        Pipeline::builtin().run(&mut optimized);
+        let lowered = Some((&optimized, DispatchKind::Match));
+        assert_eq!(iterations::<u8>(&program, lowered), [1, 0, 0]);
+    }
+
+    #[test]
+    fn test_summary() {
+        let program = BfProgram::new("+[,.]".to_string(), "cat.bf").unwrap();
+        let mut vm =
+            VirtualMachine::<u8>::new(&program, 1, false).with_step_limit(20);
+        let mut output = Vec::new();
+        let mut input = Cursor::new(vec![1; 10]);
+        assert!(vm.interpret(&mut input, &mut output).is_err());
+        let summary = vm.summary();
+        assert_eq!(summary.steps(), 20);
+        assert_eq!((summary.input_read(), summary.output_written()), (6, 6));
+        let hottest = summary.hottest_loop().unwrap();
+        assert_eq!((hottest.position(), hottest.iterations()), (1, 5));
+
+        vm.reset();
+        assert_eq!(vm.summary().hottest_loop(), None);
+        assert_eq!(vm.summary().loop_counts().len(), 1);
+    }
+
+    /// Runs the program until it stops after `limit` steps, and diagnoses
+    /// where it was stuck.
+    fn diagnose<T>(
+        program: &BfProgram,
+        ir: Option<&IrProgram>,
+        limit: u64,
+    ) -> HangDiagnosis
+    where
+        T: CellKind + Default + Clone + Copy + PartialEq,
+    {
+        let mut vm =
+            VirtualMachine::<T>::new(program, 2, false).with_step_limit(limit);
+        let (mut input, mut output) = (Cursor::new(vec![1, 2]), Vec::new());
+        let err = match ir {
+            Some(ir) => vm.interpret_ir(ir, &mut input, &mut output),
+            None => vm.interpret(&mut input, &mut output),
+        }
+        .unwrap_err();
+        vm.summary().diagnose_hang(&err, program).unwrap()
+    }
+
+    #[test]
+    fn test_diagnose_hang() {
+        let source = ",.,.[>+<[-]+]".to_string();
+        let program = BfProgram::new(source, "stuck.bf").unwrap();
+        let ir = IrProgram::from_program(&program).unwrap();
+        let diagnoses = [
+            diagnose::<u8>(&program, None, 43),
+            diagnose::<u16>(&program, None, 43),
+        ];
+        assert_eq!(diagnoses[0], diagnoses[1]);
+        // A lowered program does not run the `]` which each `[` jumps to, so
+        // gets to the same place five steps sooner.
+        for lowered in [
+            diagnose::<u8>(&program, Some(&ir), 38),
+            diagnose::<u16>(&program, Some(&ir), 38),
+        ] {
+            assert_eq!(lowered.hottest_loops(), diagnoses[0].hottest_loops());
+            assert_eq!(lowered.loop_stack(), diagnoses[0].loop_stack());
+        }
+        let hang = &diagnoses[0];
+        let hottest: Vec<_> =
+            hang.hottest_loops().iter().map(|l| l.position()).collect();
+        assert_eq!(hottest, [4, 8]);
+        let stack: Vec<_> =
+            hang.loop_stack().iter().map(|l| l.position()).collect();
+        assert_eq!(stack, [4]);
+        assert_eq!(hang.last_io_step(), Some(4));
+        assert_eq!(hang.steps_since_io(), Some(39));
+
+        let program = BfProgram::new("+[]".to_string(), "spin.bf").unwrap();
+        let hang = diagnose::<u8>(&program, None, 10);
+        assert_eq!(hang.last_io_step(), None);
+        assert_eq!(hang.loop_stack()[0].iterations(), 7);
+        let program = BfProgram::new("+[-]".to_string(), "out.bf").unwrap();
+        let mut vm = VirtualMachine::<u8>::new(&program, 1, false);
+        let err = vm.move_left().unwrap_err();
+        assert_eq!(vm.summary().diagnose_hang(&err, &program), None);
+    }
+}
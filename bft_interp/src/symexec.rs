@@ -0,0 +1,770 @@
+//! Experimental symbolic execution of small programs, which runs a program
+//! over input it does not know yet, to answer questions about every input at
+//! once, such as whether the head can ever move left of the first cell, or
+//! which input makes the program write a given output.
+//!
+//! Each byte the program reads is an unknown, and each cell holds a constant
+//! plus a multiple of each unknown, wrapping at the width of the cells. Where
+//! a loop tests a cell which depends on the input, the run splits in two, one
+//! where the cell is zero and one where it is not, each remembering what it
+//! assumed. Runs whose assumptions cannot all hold are dropped, and the input
+//! for a run which answers the question is found by searching for bytes which
+//! meet every assumption it made.
+//!
+//! Loops which only move values between cells, taking one from the cell they
+//! test each time round, such as `[->+<]`, are taken in a single step, adding
+//! a multiple of the cell to the others, rather than splitting once for every
+//! value the cell might have.
+//!
+//! This is bounded: each run only takes so many steps, and only so many runs
+//! are followed, so for programs which loop on their input the answer may be
+//! that it is not known. The input is assumed to be long enough for every
+//! `,`, and extension instructions are not followed.
+
+use std::collections::BTreeMap;
+
+use bft_types::ops::Operation;
+use bft_types::{BfProgram, InstructionInfo};
+
+/// The most steps each run takes, unless told otherwise.
+const DEFAULT_MAX_STEPS: u64 = 10_000;
+
+/// The most runs which are followed, unless told otherwise.
+const DEFAULT_MAX_PATHS: usize = 1000;
+
+/// The number of guesses the search for input makes before giving up.
+const SOLVER_BUDGET: usize = 100_000;
+
+/// The mask of the bytes which are written out of cells.
+const BYTE_MASK: u64 = 0xff;
+
+/// A value which depends on the input: a constant, plus a multiple of each
+/// byte read, keyed by the number of bytes read before it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct Affine {
+    constant: u64,
+    terms: BTreeMap<usize, u64>,
+}
+
+impl Affine {
+    fn input(index: usize) -> Self {
+        Self {
+            constant: 0,
+            terms: BTreeMap::from([(index, 1)]),
+        }
+    }
+
+    /// The value, if it does not depend on the input.
+    fn as_constant(&self) -> Option<u64> {
+        self.terms.is_empty().then_some(self.constant)
+    }
+
+    /// Adds a constant, wrapping within the mask.
+    fn offset(&self, by: u64, mask: u64) -> Self {
+        Self {
+            constant: self.constant.wrapping_add(by) & mask,
+            terms: self.terms.clone(),
+        }
+    }
+
+    /// Multiplies the value, wrapping within the mask.
+    fn scaled(&self, by: u64, mask: u64) -> Self {
+        Self {
+            constant: self.constant.wrapping_mul(by) & mask,
+            terms: self
+                .terms
+                .iter()
+                .map(|(&index, &factor)| {
+                    (index, factor.wrapping_mul(by) & mask)
+                })
+                .filter(|&(_, factor)| factor != 0)
+                .collect(),
+        }
+    }
+
+    /// Adds another value, wrapping within the mask.
+    fn plus(&self, other: &Affine, mask: u64) -> Self {
+        let mut sum = self.offset(other.constant, mask);
+        for (&index, &factor) in &other.terms {
+            let term = sum.terms.entry(index).or_insert(0);
+            *term = term.wrapping_add(factor) & mask;
+            if *term == 0 {
+                sum.terms.remove(&index);
+            }
+        }
+        sum
+    }
+
+    /// The value with only the bits in the mask, such as the byte written
+    /// when a wider cell is output.
+    fn masked(&self, mask: u64) -> Self {
+        Self {
+            constant: self.constant & mask,
+            terms: self
+                .terms
+                .iter()
+                .map(|(&index, &factor)| (index, factor & mask))
+                .filter(|&(_, factor)| factor != 0)
+                .collect(),
+        }
+    }
+
+    /// Adds up the terms of the bytes which are known, returning the sum
+    /// along with the factors of those which are not.
+    fn evaluate(&self, values: &[Option<u64>]) -> (u64, Vec<(usize, u64)>) {
+        let mut sum = self.constant;
+        let mut unknown = Vec::new();
+        for (&index, &factor) in &self.terms {
+            match values[index] {
+                Some(value) => {
+                    sum = sum.wrapping_add(factor.wrapping_mul(value))
+                }
+                None => unknown.push((index, factor)),
+            }
+        }
+        (sum, unknown)
+    }
+}
+
+/// The values a byte of input can still have, given what has been assumed
+/// about it alone.
+type Domain = [bool; 256];
+
+/// Something a run assumed about more than one byte of its input: that a
+/// value is zero, or that it is not, within the mask.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Assumption {
+    value: Affine,
+    mask: u64,
+    zero: bool,
+}
+
+/// The inverse of an odd number, modulo 2 to the 64.
+fn inverse(odd: u64) -> u64 {
+    // Newton's method doubles the number of correct bits each time round.
+    let mut inverse = odd;
+    for _ in 0..6 {
+        inverse =
+            inverse.wrapping_mul(2u64.wrapping_sub(odd.wrapping_mul(inverse)));
+    }
+    inverse
+}
+
+/// What came of searching for input meeting some assumptions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Solution {
+    /// Input which meets them all.
+    Input(Vec<u8>),
+    /// No input can.
+    None,
+    /// The search gave up before finding out.
+    Unknown,
+}
+
+/// Searches for bytes meeting the assumptions, one byte at a time.
+struct Search<'a> {
+    assumptions: &'a [Assumption],
+    domains: &'a [Domain],
+    values: Vec<Option<u64>>,
+    guesses: usize,
+}
+
+impl Search<'_> {
+    /// Fills in the remaining bytes, returning whether it could, or None if
+    /// it gave up.
+    fn fill(&mut self) -> Option<bool> {
+        self.guesses += 1;
+        if self.guesses > SOLVER_BUDGET {
+            return None;
+        }
+        // A byte which must have exactly one value.
+        let mut forced = None;
+        // A byte which some assumption depends on.
+        let mut open = None;
+        for assumption in self.assumptions {
+            let (sum, unknown) = assumption.value.evaluate(&self.values);
+            match unknown[..] {
+                [] if (sum & assumption.mask == 0) != assumption.zero => {
+                    return Some(false);
+                }
+                [] => {}
+                [(index, factor)] if assumption.zero && factor % 2 == 1 => {
+                    let value =
+                        sum.wrapping_neg().wrapping_mul(inverse(factor))
+                            & assumption.mask;
+                    forced.get_or_insert((index, value));
+                }
+                [(index, _), ..] => {
+                    open.get_or_insert(index);
+                }
+            }
+        }
+        let (index, guesses) = match (forced, open) {
+            // Only bytes are read, so a byte forced to be more than 255
+            // cannot be.
+            (Some((_, value)), _) if value > BYTE_MASK => return Some(false),
+            (Some((index, value)), _) => (index, value..=value),
+            (None, Some(index)) => (index, 0..=BYTE_MASK),
+            (None, None) => return Some(true),
+        };
+        for guess in guesses {
+            if !self.domains[index][guess as usize] {
+                continue;
+            }
+            self.values[index] = Some(guess);
+            match self.fill() {
+                Some(false) => {}
+                found => return found,
+            }
+        }
+        self.values[index] = None;
+        Some(false)
+    }
+}
+
+/// Searches for bytes of input, one for each domain, which meet every
+/// assumption.
+fn solve(assumptions: &[Assumption], domains: &[Domain]) -> Solution {
+    if domains.iter().any(|domain| !domain.contains(&true)) {
+        return Solution::None;
+    }
+    let mut search = Search {
+        assumptions,
+        domains,
+        values: vec![None; domains.len()],
+        guesses: 0,
+    };
+    match search.fill() {
+        // Any other byte may as well be the first it can be.
+        Some(true) => Solution::Input(
+            search
+                .values
+                .iter()
+                .zip(domains)
+                .map(|(value, domain)| match value {
+                    Some(value) => *value as u8,
+                    None => {
+                        domain.iter().position(|&can| can).unwrap_or(0) as u8
+                    }
+                })
+                .collect(),
+        ),
+        Some(false) => Solution::None,
+        None => Solution::Unknown,
+    }
+}
+
+/// A single run of the program, as far as it has got.
+#[derive(Debug, Clone, Default)]
+struct Path {
+    position: usize,
+    head: usize,
+    tape: BTreeMap<usize, Affine>,
+    /// The values each byte read so far can have.
+    domains: Vec<Domain>,
+    output: Vec<Affine>,
+    assumptions: Vec<Assumption>,
+    steps: u64,
+}
+
+impl Path {
+    fn cell(&self) -> Affine {
+        self.tape.get(&self.head).cloned().unwrap_or_default()
+    }
+
+    /// Assumes the value is zero, or is not, returning false if that cannot
+    /// be so.
+    fn assume(&mut self, value: Affine, mask: u64, zero: bool) -> bool {
+        if let Some(constant) = value.as_constant() {
+            return (constant & mask == 0) == zero;
+        }
+        if let [(&index, &factor)] = &value.terms.iter().collect::<Vec<_>>()[..]
+        {
+            // Assumptions about a single byte rule out some of its values.
+            let domain = &mut self.domains[index];
+            for byte in 0..=BYTE_MASK {
+                let sum =
+                    value.constant.wrapping_add(factor.wrapping_mul(byte));
+                domain[byte as usize] &= (sum & mask == 0) == zero;
+            }
+            return domain.contains(&true);
+        }
+        self.assumptions.push(Assumption { value, mask, zero });
+        solve(&self.assumptions, &self.domains) != Solution::None
+    }
+}
+
+/// What the question is.
+#[derive(Debug, Clone, Copy)]
+enum Goal<'t> {
+    /// Whether the head can move left of the first cell.
+    HeadBelowZero,
+    /// Which input makes the program write the output and halt.
+    Output(&'t [u8]),
+    /// Which input makes the program run the instruction at the position.
+    Reach(usize),
+}
+
+/// Where a run stopped.
+#[derive(Debug)]
+enum Stop {
+    /// The run answered the question, at the instruction if there is one.
+    Answered(Option<InstructionInfo>),
+    /// The run can never answer the question.
+    Dead,
+    /// The run went past the bounds, so whether it answers the question is
+    /// not known.
+    Bounded,
+    /// The run split in two, and this is the other half.
+    Split(Box<Path>),
+}
+
+/// Input which answers a question, and where in the program it was answered.
+#[derive(Debug, Clone)]
+pub struct Witness {
+    input: Vec<u8>,
+    instruction: Option<InstructionInfo>,
+}
+
+impl Witness {
+    /// The input to give the program.
+    pub fn input(&self) -> &[u8] {
+        &self.input
+    }
+
+    /// The instruction at which the question was answered, such as the `<`
+    /// which moved the head left of the first cell. None for answers which
+    /// only come once the program halts.
+    pub fn instruction(&self) -> Option<InstructionInfo> {
+        self.instruction
+    }
+}
+
+/// The answer to a question about a program.
+#[derive(Debug, Clone)]
+pub enum Answer {
+    /// Yes, given this input.
+    Found(Witness),
+    /// No, for any input.
+    Impossible,
+    /// Not within the bounds of the search.
+    Unknown,
+}
+
+/// Runs a program symbolically, over every input at once. See the module
+/// documentation for how.
+/// ```
+/// use bft_types::BfProgram;
+/// use bft_interp::symexec::{Answer, SymbolicExecutor};
+///
+/// // Moves left of the first cell whatever it reads.
+/// let program = BfProgram::new(",>,<<".to_string(), "x.bf").unwrap();
+/// let executor = SymbolicExecutor::new(&program, 8);
+/// let Answer::Found(witness) = executor.head_below_zero() else {
+///     panic!("the head always goes below zero");
+/// };
+/// assert_eq!(witness.instruction().unwrap().column(), 5);
+///
+/// // Writes its input plus one.
+/// let program = BfProgram::new(",+.".to_string(), "y.bf").unwrap();
+/// let executor = SymbolicExecutor::new(&program, 8);
+/// match executor.input_for_output(b"b") {
+///     Answer::Found(witness) => assert_eq!(witness.input(), b"a"),
+///     _ => panic!("'a' plus one is 'b'"),
+/// }
+/// assert!(matches!(executor.input_for_output(b"bb"), Answer::Impossible));
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct SymbolicExecutor<'p> {
+    program: &'p BfProgram,
+    /// The mask of the bits in each cell.
+    mask: u64,
+    max_steps: u64,
+    max_paths: usize,
+}
+
+impl<'p> SymbolicExecutor<'p> {
+    /// Creates an executor for the program, with cells of the given number of
+    /// bits, from 8 to 64.
+    pub fn new(program: &'p BfProgram, bits: u32) -> Self {
+        Self {
+            program,
+            mask: u64::MAX >> (64 - bits.clamp(8, 64)),
+            max_steps: DEFAULT_MAX_STEPS,
+            max_paths: DEFAULT_MAX_PATHS,
+        }
+    }
+
+    /// Sets the most steps each run takes.
+    pub fn with_max_steps(mut self, steps: u64) -> Self {
+        self.max_steps = steps;
+        self
+    }
+
+    /// Sets the most runs which are followed.
+    pub fn with_max_paths(mut self, paths: usize) -> Self {
+        self.max_paths = paths;
+        self
+    }
+
+    /// Whether the head can ever move left of the first cell, which fails
+    /// the program.
+    pub fn head_below_zero(&self) -> Answer {
+        self.explore(Goal::HeadBelowZero)
+    }
+
+    /// Which input, if any, makes the program write exactly the output and
+    /// then halt.
+    pub fn input_for_output(&self, output: &[u8]) -> Answer {
+        self.explore(Goal::Output(output))
+    }
+
+    /// Which input, if any, makes the program run the instruction at the
+    /// position in its instructions.
+    pub fn reaches(&self, position: usize) -> Answer {
+        self.explore(Goal::Reach(position))
+    }
+
+    /// Follows every run of the program, splitting them where they branch on
+    /// the input, until one answers the question.
+    fn explore(&self, goal: Goal<'_>) -> Answer {
+        let mut paths = vec![Path::default()];
+        let mut followed = 1;
+        let mut complete = true;
+        while let Some(mut path) = paths.pop() {
+            match self.run(&mut path, goal) {
+                Stop::Answered(instruction) => {
+                    match solve(&path.assumptions, &path.domains) {
+                        Solution::Input(input) => {
+                            return Answer::Found(Witness {
+                                input,
+                                instruction,
+                            })
+                        }
+                        Solution::None => {}
+                        Solution::Unknown => complete = false,
+                    }
+                }
+                Stop::Dead => {}
+                Stop::Bounded => complete = false,
+                Stop::Split(other) => {
+                    if followed < self.max_paths {
+                        followed += 1;
+                        paths.push(*other);
+                    } else {
+                        complete = false;
+                    }
+                    paths.push(path);
+                }
+            }
+        }
+        if complete {
+            Answer::Impossible
+        } else {
+            Answer::Unknown
+        }
+    }
+
+    /// Takes the loop starting at the path's position in a single step, if it
+    /// only moves values between cells, taking one from or adding one to the
+    /// cell it tests each time round, and cannot move the head left of the
+    /// first cell. Returns whether it did.
+    fn transfer(&self, path: &mut Path, end: usize) -> bool {
+        let body = &self.program.instructions()[path.position + 1..end];
+        let (mut offset, mut lowest) = (0isize, 0isize);
+        let mut changes: BTreeMap<isize, u64> = BTreeMap::new();
+        for instruction in body {
+            let change = changes.entry(offset).or_insert(0);
+            match instruction.operation() {
+                Operation::IncrementByte => *change = change.wrapping_add(1),
+                Operation::DecrementByte => *change = change.wrapping_sub(1),
+                Operation::IncrementPointer => offset += 1,
+                Operation::DecrementPointer => offset -= 1,
+                _ => return false,
+            }
+            lowest = lowest.min(offset);
+        }
+        let change = changes.remove(&0).unwrap_or(0) & self.mask;
+        if offset != 0
+            || path.head.checked_add_signed(lowest).is_none()
+            || (change != 1 && change != self.mask)
+        {
+            return false;
+        }
+        // The number of times round, which is the cell if it is taken from,
+        // and how far it is from wrapping round if it is added to.
+        let cell = path.cell();
+        let times = if change == self.mask {
+            cell
+        } else {
+            cell.scaled(self.mask, self.mask)
+        };
+        for (offset, change) in changes {
+            let at = path.head.wrapping_add_signed(offset);
+            let value = path.tape.get(&at).cloned().unwrap_or_default();
+            let added = times.scaled(change, self.mask);
+            path.tape.insert(at, value.plus(&added, self.mask));
+        }
+        path.tape.insert(path.head, Affine::default());
+        path.steps += body.len() as u64;
+        path.position = end + 1;
+        true
+    }
+
+    /// Runs the path until it answers the question, cannot, or splits.
+    fn run(&self, path: &mut Path, goal: Goal<'_>) -> Stop {
+        let instructions = self.program.instructions();
+        loop {
+            let Some(&instruction) = instructions.get(path.position) else {
+                return match goal {
+                    Goal::Output(output)
+                        if path.output.len() == output.len() =>
+                    {
+                        Stop::Answered(None)
+                    }
+                    _ => Stop::Dead,
+                };
+            };
+            if let Goal::Reach(position) = goal {
+                if path.position == position {
+                    return Stop::Answered(Some(instruction));
+                }
+            }
+            if path.steps >= self.max_steps {
+                return Stop::Bounded;
+            }
+            path.steps += 1;
+            let cell = path.cell();
+            let mut next = path.position + 1;
+            match instruction.operation() {
+                Operation::IncrementByte => {
+                    path.tape.insert(path.head, cell.offset(1, self.mask));
+                }
+                Operation::DecrementByte => {
+                    path.tape
+                        .insert(path.head, cell.offset(self.mask, self.mask));
+                }
+                Operation::IncrementPointer => path.head += 1,
+                Operation::DecrementPointer if path.head == 0 => {
+                    return match goal {
+                        Goal::HeadBelowZero => {
+                            Stop::Answered(Some(instruction))
+                        }
+                        Goal::Output(_) | Goal::Reach(_) => Stop::Dead,
+                    };
+                }
+                Operation::DecrementPointer => path.head -= 1,
+                Operation::OutputByte => {
+                    if let Goal::Output(output) = goal {
+                        let Some(&byte) = output.get(path.output.len()) else {
+                            return Stop::Dead;
+                        };
+                        let difference = cell
+                            .offset(u64::from(byte).wrapping_neg(), self.mask)
+                            .masked(BYTE_MASK);
+                        if !path.assume(difference, BYTE_MASK, true) {
+                            return Stop::Dead;
+                        }
+                    }
+                    path.output.push(cell);
+                }
+                Operation::InputByte => {
+                    path.tape
+                        .insert(path.head, Affine::input(path.domains.len()));
+                    path.domains.push([true; 256]);
+                }
+                Operation::StartLoop | Operation::EndLoop => {
+                    let Some(target) = self.program.jump_target(path.position)
+                    else {
+                        return Stop::Bounded;
+                    };
+                    let start = instruction.operation() == Operation::StartLoop;
+                    // Taking the loop in one step would skip the instruction
+                    // being looked for, if it is within it.
+                    let within = matches!(
+                        goal,
+                        Goal::Reach(position)
+                            if (path.position..target).contains(&position)
+                    );
+                    if start && !within && self.transfer(path, target) {
+                        continue;
+                    }
+                    // Where the run goes if the cell is zero, and if not.
+                    let (zero, nonzero) = if start {
+                        (target + 1, next)
+                    } else {
+                        (next, target + 1)
+                    };
+                    let mut other = path.clone();
+                    let can_be_zero =
+                        other.assume(cell.clone(), self.mask, true);
+                    let can_be_nonzero = path.assume(cell, self.mask, false);
+                    other.position = zero;
+                    other.tape.insert(other.head, Affine::default());
+                    match (can_be_zero, can_be_nonzero) {
+                        (true, true) => {
+                            path.position = nonzero;
+                            return Stop::Split(Box::new(other));
+                        }
+                        (true, false) => {
+                            *path = other;
+                            continue;
+                        }
+                        (false, true) => next = nonzero,
+                        (false, false) => return Stop::Dead,
+                    }
+                }
+                // What an extension does is not known.
+                Operation::Extension(_) => return Stop::Bounded,
+            }
+            path.position = next;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SymbolicExecutor;
+    use super::{inverse, solve, Affine, Answer, Assumption, Solution};
+    use crate::VirtualMachine;
+    use bft_types::BfProgram;
+    use std::io::Cursor;
+
+    fn program(source: &str) -> BfProgram {
+        BfProgram::new(source.to_string(), "sym.bf").unwrap()
+    }
+
+    #[test]
+    fn test_inverse() {
+        for odd in [1u64, 3, 5, 255, 12345] {
+            assert_eq!(odd.wrapping_mul(inverse(odd)), 1);
+        }
+    }
+
+    #[test]
+    fn test_solve() {
+        // x0 + 2 * x1 == 10, and x1 != 5.
+        let mut value = Affine::input(0);
+        value.terms.insert(1, 2);
+        let assumptions = [
+            Assumption {
+                value: value.offset(10u64.wrapping_neg(), 0xff),
+                mask: 0xff,
+                zero: true,
+            },
+            Assumption {
+                value: Affine::input(1).offset(5u64.wrapping_neg(), 0xff),
+                mask: 0xff,
+                zero: false,
+            },
+        ];
+        let Solution::Input(input) = solve(&assumptions, &[[true; 256]; 3])
+        else {
+            panic!("there are solutions");
+        };
+        assert_eq!((input[0] as u32 + 2 * input[1] as u32) % 256, 10);
+        assert_ne!(input[1], 5);
+        assert_eq!(input[2], 0);
+        // 2 * x0 is never odd.
+        let odd = Assumption {
+            value: Affine::input(0).offset(0, 0xff),
+            mask: 0xff,
+            zero: true,
+        };
+        let mut doubled = odd.clone();
+        doubled.value.terms.insert(0, 2);
+        doubled.value.constant = 1;
+        assert_eq!(solve(&[doubled], &[[true; 256]]), Solution::None);
+    }
+
+    #[test]
+    fn test_head_below_zero() {
+        // Only moves left of the first cell for the input 'x'.
+        let source = ",>++++++++++[<------------>-]<[[-]>]<";
+        let guarded = program(source);
+        let executor = SymbolicExecutor::new(&guarded, 8);
+        let Answer::Found(witness) = executor.head_below_zero() else {
+            panic!("the head goes below zero for 'x'");
+        };
+        assert_eq!(witness.input(), b"x");
+        assert_eq!(witness.instruction().unwrap().column(), source.len());
+
+        let reads = program(">,[>,]<");
+        let executor = SymbolicExecutor::new(&reads, 8)
+            .with_max_steps(100)
+            .with_max_paths(10);
+        assert!(matches!(executor.head_below_zero(), Answer::Unknown));
+        let clears = program(",>,[-]<");
+        let executor = SymbolicExecutor::new(&clears, 8);
+        assert!(matches!(executor.head_below_zero(), Answer::Impossible));
+    }
+
+    #[test]
+    fn test_input_for_output() {
+        // Writes 'y' if the two bytes read add up to 'a', and 'n' otherwise.
+        let source = "\
+            ,>,[<+>-]<-------------------------------------------------------\
+            ------------------------------------------>+<[>-<[-]]>\
+            [>++++++++++[<++++++++++++>-]<.[-]]";
+        let program = program(source);
+        let executor = SymbolicExecutor::new(&program, 8);
+        let Answer::Found(witness) = executor.input_for_output(b"y") else {
+            panic!("some input writes y");
+        };
+        let mut vm = VirtualMachine::<u8>::new(&program, 10, false);
+        let mut output = Vec::new();
+        vm.interpret(&mut Cursor::new(witness.input().to_vec()), &mut output)
+            .unwrap();
+        assert_eq!(output, b"y");
+        assert!(matches!(
+            executor.input_for_output(b"n"),
+            Answer::Impossible
+        ));
+    }
+
+    #[test]
+    fn test_transfer() {
+        // Doubles its input, in one step rather than one run per value.
+        let program = program(",[->++<]>.");
+        let executor = SymbolicExecutor::new(&program, 8).with_max_paths(1);
+        let Answer::Found(witness) = executor.input_for_output(b"\x06") else {
+            panic!("3 doubled is 6");
+        };
+        assert_eq!(witness.input()[0] * 2, 6);
+        assert!(matches!(
+            executor.input_for_output(b"\x07"),
+            Answer::Impossible
+        ));
+    }
+
+    #[test]
+    fn test_reaches() {
+        // The `.` only runs for the input 3, and the `>` never does.
+        let program = program(",---[[-]+[]>]+.");
+        let executor = SymbolicExecutor::new(&program, 8).with_max_steps(1000);
+        let Answer::Found(witness) = executor.reaches(14) else {
+            panic!("the . runs for the input 3");
+        };
+        assert_eq!(witness.input(), b"\x03");
+        assert_eq!(witness.instruction().unwrap().column(), 15);
+        // The `-` of the [-] is looked for, so that loop is followed one step
+        // at a time rather than taken in one, and the `>` after the endless
+        // loop is never found.
+        assert!(matches!(executor.reaches(6), Answer::Found(_)));
+        assert!(matches!(executor.reaches(11), Answer::Unknown));
+    }
+
+    #[test]
+    fn test_wide_cells() {
+        // Counting down from zero only gives 255 with 8 bit cells.
+        let program = program("-.");
+        let narrow = SymbolicExecutor::new(&program, 8);
+        assert!(matches!(narrow.input_for_output(b"\xff"), Answer::Found(_)));
+        let wide = SymbolicExecutor::new(&program, 16);
+        assert!(matches!(wide.input_for_output(b"\xff"), Answer::Found(_)));
+        assert!(matches!(
+            SymbolicExecutor::new(&program, 16).input_for_output(b"\xfe"),
+            Answer::Impossible
+        ));
+    }
+}
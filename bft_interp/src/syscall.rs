@@ -0,0 +1,165 @@
+//! Host function calls, an opt-in `%` instruction which lets a program call
+//! functions registered by the application running it, such as to read a file,
+//! a clock or a sensor, without adding anything to Brainfuck itself.
+//!
+//! The value of the cell at the head selects the function, which is given the
+//! same `VmContext` as an extension instruction, and so can read and write the
+//! cells after the head with `VmContext::window` to take arguments and give
+//! back results.
+//!
+//! Calls need both the program to be parsed with `%` as an extension, and the
+//! Virtual Machine to be given the capability with `with_syscalls`, so they
+//! are off by default. Without the capability, each call fails with
+//! `SyscallsDisabled`. A `Sandbox` can instead allow only some of them, and
+//! calls to any others fail with `SyscallDenied`.
+//!
+//! The one host function built in is `clock`, which lets programs measure how
+//! long they take, from a real `MonotonicClock` or from a `VirtualClock` for
+//! runs which must be reproducible.
+//! ```
+//! use std::io::Cursor;
+//! use bft_types::BfProgram;
+//! use bft_types::options::ParseOptions;
+//! use bft_interp::VirtualMachine;
+//! use bft_interp::syscall::SYSCALL;
+//!
+//! // Function 3 adds the two cells after the head into the first.
+//! let options = ParseOptions::new().extension(SYSCALL);
+//! let source = ">++>+++<<+++%>.";
+//! let program =
+//!     BfProgram::new_with_options(source.to_string(), "add.bf", &options)
+//!         .unwrap();
+//! let mut vm = VirtualMachine::<u8>::new(&program, 4, false).with_syscalls(true);
+//! vm.register_syscall(3, |context| {
+//!     let window = context.window(2)?;
+//!     window[0] += window[1];
+//!     Ok(())
+//! });
+//!
+//! let mut output = Vec::new();
+//! vm.interpret(&mut Cursor::new(Vec::new()), &mut output).unwrap();
+//! assert_eq!(output, [5]);
+//! ```
+
+use std::time::Instant;
+
+use bft_types::vm_error::VirtualMachineError;
+
+use crate::extension::VmContext;
+use crate::CellKind;
+
+/// The character of the host function call instruction.
+pub const SYSCALL: char = '%';
+
+/// The number of the host function which reads the clock, as registered by
+/// `clock`.
+pub const CLOCK: u32 = 1;
+
+/// A source of the time, in milliseconds, which must never go backwards.
+/// Closures returning the time can be used as clocks, so that an embedder can
+/// drive the time itself.
+pub trait Clock {
+    /// The time now, in milliseconds.
+    fn millis(&mut self) -> u64;
+}
+
+impl<F> Clock for F
+where
+    F: FnMut() -> u64,
+{
+    fn millis(&mut self) -> u64 {
+        self()
+    }
+}
+
+/// The real time, in milliseconds since the clock was created.
+#[derive(Debug, Clone, Copy)]
+pub struct MonotonicClock {
+    start: Instant,
+}
+
+impl MonotonicClock {
+    /// Creates a clock which starts from zero now.
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Default for MonotonicClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for MonotonicClock {
+    fn millis(&mut self) -> u64 {
+        self.start.elapsed().as_millis() as u64
+    }
+}
+
+/// A clock which starts from zero and moves on by a fixed number of
+/// milliseconds each time it is read, so that programs which read the time
+/// run the same way every time, such as in tests.
+#[derive(Debug, Clone, Copy)]
+pub struct VirtualClock {
+    now: u64,
+    tick: u64,
+}
+
+impl VirtualClock {
+    /// Creates a clock which moves on by `tick` milliseconds each time it is
+    /// read.
+    pub fn new(tick: u64) -> Self {
+        Self { now: 0, tick }
+    }
+}
+
+impl Clock for VirtualClock {
+    fn millis(&mut self) -> u64 {
+        let now = self.now;
+        self.now = self.now.wrapping_add(self.tick);
+        now
+    }
+}
+
+/// Creates the host function which reads the clock, writing the time into the
+/// `cells` cells after the head a byte to each, lowest byte first, so that a
+/// single cell holds the time modulo 256 and more cells hold more of it.
+/// ```
+/// use std::io::Cursor;
+/// use bft_types::BfProgram;
+/// use bft_types::options::ParseOptions;
+/// use bft_interp::VirtualMachine;
+/// use bft_interp::syscall::{clock, VirtualClock, CLOCK, SYSCALL};
+///
+/// // Reads the clock twice, printing the low byte of the time between reads.
+/// let options = ParseOptions::new().extension(SYSCALL);
+/// let source = "+%>>+%<[->>-<<]>>.";
+/// let program =
+///     BfProgram::new_with_options(source.to_string(), "time.bf", &options)
+///         .unwrap();
+/// let mut vm = VirtualMachine::<u8>::new(&program, 4, false).with_syscalls(true);
+/// vm.register_syscall(CLOCK, clock(VirtualClock::new(40), 1));
+///
+/// let mut output = Vec::new();
+/// vm.interpret(&mut Cursor::new(Vec::new()), &mut output).unwrap();
+/// assert_eq!(output, [40]);
+/// ```
+pub fn clock<T>(
+    mut clock: impl Clock,
+    cells: usize,
+) -> impl FnMut(&mut VmContext<'_, T>) -> Result<(), VirtualMachineError>
+where
+    T: CellKind + Default + Clone + Copy,
+{
+    move |context| {
+        let mut now = clock.millis();
+        for cell in context.window(cells)? {
+            *cell = T::from_u8(now as u8);
+            now >>= 8;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,159 @@
+//! Where the tape of a Virtual Machine gets its cells from.
+//!
+//! By default each tape is a `Vec` on the heap, which grows as the head
+//! moves off its end, if it is extensible. Services running many Virtual
+//! Machines can give each one a `TapeAllocator` with
+//! `VirtualMachine::new_in`, to hand out tapes from a pool, to cap how many
+//! cells they take up between them, or just to watch them grow. `TapePool` is
+//! an allocator which does both.
+
+use std::sync::{Mutex, MutexGuard};
+
+/// Provides the storage for the tape of a Virtual Machine, and decides whether
+/// it may grow.
+pub trait TapeAllocator<T> {
+    /// Provides a tape of `len` cells, each set to its default value.
+    fn allocate(&mut self, len: usize) -> Vec<T>;
+
+    /// Grows the tape to `len` cells, setting each new cell to its default
+    /// value, returning false to refuse, in which case the program fails with
+    /// a `TapeGrowthRefused` error.
+    fn grow(&mut self, tape: &mut Vec<T>, len: usize) -> bool;
+
+    /// Takes back the tape of a Virtual Machine which has been dropped, to be
+    /// reused or counted. Tapes taken out with `into_tape` are not given back.
+    fn release(&mut self, _tape: Vec<T>) {}
+}
+
+/// The allocator used by default, which gives each tape a `Vec` of its own
+/// and always lets it grow.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HeapAllocator;
+
+impl<T> TapeAllocator<T> for HeapAllocator
+where
+    T: Default + Clone,
+{
+    fn allocate(&mut self, len: usize) -> Vec<T> {
+        vec![T::default(); len]
+    }
+
+    fn grow(&mut self, tape: &mut Vec<T>, len: usize) -> bool {
+        tape.resize(len, T::default());
+        true
+    }
+}
+
+/// The state of a pool, shared by every Virtual Machine allocating from it.
+#[derive(Debug)]
+struct PoolState<T> {
+    /// Tapes which have been given back, ready to be handed out again.
+    free: Vec<Vec<T>>,
+    /// The number of cells in the tapes which are handed out.
+    cells: usize,
+}
+
+/// A pool of tapes, shared by any number of Virtual Machines through
+/// references to it, which reuses the tapes given back to it and caps how many
+/// cells may be handed out at once.
+/// ```
+/// use std::io::Cursor;
+/// use bft_types::BfProgram;
+/// use bft_interp::VirtualMachine;
+/// use bft_interp::tape::TapePool;
+///
+/// let pool = TapePool::<u8>::new(10);
+/// let program = BfProgram::new(">>>>>>>>".to_string(), "far.bf").unwrap();
+/// let mut vm = VirtualMachine::new_in(&program, 4, true, &pool);
+/// vm.interpret(&mut Cursor::new(Vec::new()), &mut Vec::new()).unwrap();
+/// assert_eq!(pool.cells(), 9);
+///
+/// // A second Virtual Machine cannot take the pool over its limit.
+/// let mut second = VirtualMachine::new_in(&program, 1, true, &pool);
+/// assert!(second.interpret(&mut Cursor::new(Vec::new()), &mut Vec::new()).is_err());
+/// drop((vm, second));
+/// assert_eq!(pool.cells(), 0);
+/// ```
+#[derive(Debug)]
+pub struct TapePool<T> {
+    state: Mutex<PoolState<T>>,
+    /// The most cells which may be handed out at once.
+    max_cells: usize,
+}
+
+impl<T> TapePool<T> {
+    /// Creates a pool which hands out at most `max_cells` cells at once.
+    /// Allocating a new tape is never refused, as a Virtual Machine needs a
+    /// tape to be created at all, but growing one past the limit is.
+    pub fn new(max_cells: usize) -> Self {
+        Self {
+            state: Mutex::new(PoolState {
+                free: Vec::new(),
+                cells: 0,
+            }),
+            max_cells,
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, PoolState<T>> {
+        // The lock is never held across anything which could panic, so a
+        // poisoned lock still holds a consistent pool.
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// The number of cells in the tapes which are handed out.
+    pub fn cells(&self) -> usize {
+        self.lock().cells
+    }
+}
+
+impl<T> TapeAllocator<T> for &TapePool<T>
+where
+    T: Default + Clone,
+{
+    fn allocate(&mut self, len: usize) -> Vec<T> {
+        let mut state = self.lock();
+        state.cells += len;
+        let mut tape = state.free.pop().unwrap_or_default();
+        tape.clear();
+        tape.resize(len, T::default());
+        tape
+    }
+
+    fn grow(&mut self, tape: &mut Vec<T>, len: usize) -> bool {
+        let mut state = self.lock();
+        let extra = len.saturating_sub(tape.len());
+        if state.cells + extra > self.max_cells {
+            return false;
+        }
+        state.cells += extra;
+        tape.resize(len, T::default());
+        true
+    }
+
+    fn release(&mut self, tape: Vec<T>) {
+        let mut state = self.lock();
+        state.cells -= tape.len();
+        state.free.push(tape);
+    }
+}
+
+/// Grows the tape to `len` cells with the allocator, or on the heap if there
+/// is none, returning whether it grew. It does not grow past `limit` cells.
+pub(crate) fn grow<T>(
+    tape: &mut Vec<T>,
+    allocator: Option<&mut (dyn TapeAllocator<T> + '_)>,
+    len: usize,
+    limit: Option<usize>,
+) -> bool
+where
+    T: Default + Clone,
+{
+    if limit.is_some_and(|limit| len > limit) {
+        return false;
+    }
+    match allocator {
+        Some(allocator) => allocator.grow(tape, len),
+        None => HeapAllocator.grow(tape, len),
+    }
+}
@@ -0,0 +1,244 @@
+//! Property-based tests, which run randomly generated programs on random
+//! inputs and check what must hold for every program, shrinking any program
+//! which breaks them down to a minimal one. Enabled by the `proptest`
+//! feature:
+//! ```text
+//! cargo test -p bft_interp --features proptest
+//! ```
+
+#![cfg(feature = "proptest")]
+
+use std::io::Cursor;
+
+use bft_interp::dispatch::DispatchKind;
+use bft_interp::eof::EofBehavior;
+use bft_interp::ir::IrProgram;
+use bft_interp::optimizer::Pipeline;
+use bft_interp::VirtualMachine;
+use bft_types::options::{BracketValidation, ParseOptions};
+use bft_types::vm_error::VirtualMachineError;
+use bft_types::BfProgram;
+use proptest::prelude::*;
+
+/// The step limit for every run, so that programs which never halt still
+/// finish.
+const STEP_LIMIT: u64 = 10_000;
+
+/// The starting length of the tape, kept short so that programs often need
+/// it to grow.
+const TAPE_LENGTH: usize = 4;
+
+/// Generates the source of a program with balanced brackets.
+fn program() -> impl Strategy<Value = String> {
+    let command = prop::sample::select(vec!["<", ">", "+", "-", ".", ","])
+        .prop_map(str::to_string);
+    command
+        .prop_recursive(4, 64, 8, |inner| {
+            prop_oneof![
+                prop::collection::vec(inner.clone(), 1..8)
+                    .prop_map(|body| body.concat()),
+                prop::collection::vec(inner, 0..8)
+                    .prop_map(|body| format!("[{}]", body.concat())),
+            ]
+        })
+        .prop_map(|source| source.to_string())
+}
+
+/// Generates the source of a program whose brackets may not be balanced,
+/// along with comments.
+fn any_source() -> impl Strategy<Value = String> {
+    prop::collection::vec(
+        prop::sample::select(vec!['[', ']', '+', '>', '.', 'x', '\n']),
+        0..48,
+    )
+    .prop_map(|chars| chars.into_iter().collect())
+}
+
+/// Whether the brackets of the source are balanced, worked out by counting.
+fn balanced(source: &str) -> bool {
+    let mut depth = 0usize;
+    for c in source.chars() {
+        match c {
+            '[' => depth += 1,
+            ']' => match depth.checked_sub(1) {
+                Some(outer) => depth = outer,
+                None => return false,
+            },
+            _ => {}
+        }
+    }
+    depth == 0
+}
+
+/// Generates how `,` behaves at the end of the input.
+fn eof_behavior() -> impl Strategy<Value = EofBehavior> {
+    prop_oneof![
+        Just(EofBehavior::Error),
+        Just(EofBehavior::Zero),
+        Just(EofBehavior::Unchanged),
+        Just(EofBehavior::MaxValue),
+    ]
+}
+
+/// Everything observed about a run of a program.
+#[derive(Debug)]
+struct Run {
+    output: Vec<u8>,
+    result: Result<(), VirtualMachineError>,
+    tape: Vec<u8>,
+    head: usize,
+    steps: u64,
+}
+
+/// Runs the program on the input with a growable tape, directly if `ir` is
+/// None, and otherwise in its lowered form with the given dispatch.
+fn run(
+    program: &BfProgram,
+    ir: Option<(&IrProgram, DispatchKind)>,
+    input: &[u8],
+    eof: EofBehavior,
+) -> Run {
+    let mut vm = VirtualMachine::<u8>::new(program, TAPE_LENGTH, true)
+        .with_step_limit(STEP_LIMIT)
+        .with_eof_behavior(eof);
+    let mut output = Vec::new();
+    let mut input = Cursor::new(input);
+    let result = match ir {
+        None => vm.interpret(&mut input, &mut output),
+        Some((ir, dispatch)) => {
+            vm = vm.with_dispatch(dispatch);
+            vm.interpret_ir(ir, &mut input, &mut output)
+        }
+    };
+    Run {
+        output,
+        result,
+        tape: vm.tape().to_vec(),
+        head: vm.tape_head(),
+        steps: vm.steps(),
+    }
+}
+
+/// The cells of the tape up to the last one which is not zero, as a tape only
+/// grows as far as the head goes, and merged moves may not go as far.
+fn used(tape: &[u8]) -> &[u8] {
+    let len = tape
+        .iter()
+        .rposition(|cell| *cell != 0)
+        .map_or(0, |last| last + 1);
+    &tape[..len]
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(512))]
+
+    #[test]
+    fn head_stays_on_growable_tape(
+        source in program(),
+        input in prop::collection::vec(any::<u8>(), 0..8),
+        eof in eof_behavior(),
+    ) {
+        let program = BfProgram::new(source, "prop.bf").unwrap();
+        let run = run(&program, None, &input, eof);
+        prop_assert!(run.head < run.tape.len());
+    }
+
+    #[test]
+    fn engines_agree(
+        source in program(),
+        input in prop::collection::vec(any::<u8>(), 0..8),
+        eof in eof_behavior(),
+    ) {
+        let program = BfProgram::new(source, "prop.bf").unwrap();
+        let ir = IrProgram::from_program(&program).unwrap();
+        let direct = run(&program, None, &input, eof);
+        // A lowered loop starts by testing its cell, rather than with the jump
+        // to its `]` which does so, so runs which use up their steps stop in
+        // different places.
+        if matches!(
+            direct.result,
+            Err(VirtualMachineError::StepLimitExceeded { .. })
+        ) {
+            return Ok(());
+        }
+        for dispatch in [DispatchKind::Match, DispatchKind::Threaded] {
+            let lowered = run(&program, Some((&ir, dispatch)), &input, eof);
+            prop_assert_eq!(&lowered.output, &direct.output);
+            prop_assert!(lowered.steps <= direct.steps);
+            prop_assert_eq!(lowered.head, direct.head);
+            prop_assert_eq!(&lowered.tape, &direct.tape);
+            prop_assert_eq!(lowered.result.is_ok(), direct.result.is_ok());
+        }
+    }
+
+    #[test]
+    fn optimizer_keeps_output(
+        source in program(),
+        input in prop::collection::vec(any::<u8>(), 0..8),
+        eof in eof_behavior(),
+    ) {
+        let program = BfProgram::new(source, "prop.bf").unwrap();
+        let direct = run(&program, None, &input, eof);
+        // Optimized programs take fewer steps, and may merge away a move off
+        // the tape which is undone straight after, so only runs which halt
+        // are compared.
+        if direct.result.is_err() {
+            return Ok(());
+        }
+        let mut ir = IrProgram::from_program(&program).unwrap();
+        Pipeline::builtin().run(&mut ir);
+        let optimized =
+            run(&program, Some((&ir, DispatchKind::Match)), &input, eof);
+        prop_assert!(optimized.result.is_ok());
+        prop_assert_eq!(&optimized.output, &direct.output);
+        prop_assert_eq!(used(&optimized.tape), used(&direct.tape));
+    }
+
+    #[test]
+    fn brackets_always_have_targets(source in any_source()) {
+        let operations = |program: &BfProgram| {
+            program.iter().map(|i| i.operation()).collect::<Vec<_>>()
+        };
+        let strict = BfProgram::new(source.clone(), "prop.bf");
+        prop_assert_eq!(strict.is_ok(), balanced(&source));
+        if let Ok(program) = &strict {
+            prop_assert!(program.jump_table().fits(&operations(program), true));
+        }
+
+        // Lazily validated programs are always created, with whichever of
+        // their brackets are matched paired up, and fail only on reaching an
+        // unmatched one.
+        let options =
+            ParseOptions::new().bracket_validation(BracketValidation::Lazy);
+        let lazy = BfProgram::new_with_options(source, "prop.bf", &options)
+            .unwrap();
+        prop_assert!(lazy.jump_table().fits(&operations(&lazy), false));
+        if let Ok(program) = &strict {
+            prop_assert_eq!(lazy.jump_table(), program.jump_table());
+        }
+        let run = run(&lazy, None, b"", EofBehavior::Zero);
+        if let Err(err) = &run.result {
+            prop_assert!(
+                matches!(
+                    err,
+                    VirtualMachineError::UnmatchedBracket { .. }
+                        | VirtualMachineError::StepLimitExceeded { .. }
+                ),
+                "unexpected error {}",
+                err
+            );
+        }
+    }
+
+    #[test]
+    fn tokens_give_back_source(source in any_source()) {
+        let options =
+            ParseOptions::new().bracket_validation(BracketValidation::Lazy);
+        let program =
+            BfProgram::new_with_options(source.clone(), "prop.bf", &options)
+                .unwrap();
+        let written: String =
+            program.tokens().map(|token| token.to_string()).collect();
+        prop_assert_eq!(written, source);
+    }
+}
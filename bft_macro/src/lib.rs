@@ -0,0 +1,129 @@
+//! bft_macro, embedding Brainfuck programs in Rust code. The programs are
+//! parsed and their brackets checked when the code is compiled, so a mistake
+//! in one is a compile error, and running one needs no parsing at all.
+//!
+//! Each macro expands to a `bft_types::embed::EmbeddedProgram`, which can be
+//! put in a `static`:
+//! ```
+//! use bft_macro::bf_program;
+//! use bft_types::embed::EmbeddedProgram;
+//!
+//! static ECHO: EmbeddedProgram = bf_program!(",[.,]");
+//!
+//! let program = ECHO.program();
+//! assert_eq!(program.instructions().len(), 5);
+//! assert_eq!(program.jump_target(1), Some(4));
+//! ```
+
+#![deny(missing_docs)]
+
+use std::fs;
+use std::path::Path;
+
+use bft_types::ops::Operation;
+use bft_types::BfProgram;
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{parse_macro_input, LitStr};
+
+/// The filename given to programs embedded from a string.
+const EMBEDDED_FILENAME: &str = "<embedded>";
+
+/// Writes out the program as an `EmbeddedProgram`, with each of its
+/// instructions at its original position.
+fn embed(program: &BfProgram, filename: &str) -> TokenStream2 {
+    let instructions = program.instructions().iter().map(|instruction| {
+        let operation = match instruction.operation() {
+            Operation::IncrementPointer => quote!(IncrementPointer),
+            Operation::DecrementPointer => quote!(DecrementPointer),
+            Operation::IncrementByte => quote!(IncrementByte),
+            Operation::DecrementByte => quote!(DecrementByte),
+            Operation::OutputByte => quote!(OutputByte),
+            Operation::InputByte => quote!(InputByte),
+            Operation::StartLoop => quote!(StartLoop),
+            Operation::EndLoop => quote!(EndLoop),
+            Operation::Extension(name) => quote!(Extension(#name)),
+        };
+        let (line, column) = (instruction.line(), instruction.column());
+        quote! {
+            ::bft_types::InstructionInfo::new(
+                ::bft_types::ops::Operation::#operation,
+                #line,
+                #column,
+            )
+        }
+    });
+    quote! {
+        ::bft_types::embed::EmbeddedProgram::new(#filename, &[#(#instructions),*])
+    }
+}
+
+/// Embeds the program in the string literal, failing to compile if its
+/// brackets are not balanced.
+/// ```
+/// use bft_macro::bf_program;
+/// use bft_types::embed::EmbeddedProgram;
+///
+/// static CLEAR: EmbeddedProgram = bf_program!("[-] clears the cell");
+/// assert_eq!(CLEAR.instructions().len(), 3);
+/// assert_eq!(CLEAR.filename(), "<embedded>");
+/// ```
+///
+/// ```compile_fail
+/// use bft_macro::bf_program;
+/// use bft_types::embed::EmbeddedProgram;
+///
+/// static BROKEN: EmbeddedProgram = bf_program!("+[>");
+/// ```
+#[proc_macro]
+pub fn bf_program(input: TokenStream) -> TokenStream {
+    let source = parse_macro_input!(input as LitStr);
+    match BfProgram::new(source.value(), EMBEDDED_FILENAME) {
+        Ok(program) => embed(&program, EMBEDDED_FILENAME).into(),
+        Err(error) => syn::Error::new(source.span(), error)
+            .to_compile_error()
+            .into(),
+    }
+}
+
+/// Embeds the program in the file, whose path is relative to the directory of
+/// the crate being compiled, failing to compile if the file cannot be read or
+/// its brackets are not balanced. The crate is rebuilt whenever the file
+/// changes.
+/// ```
+/// use bft_macro::bf_file;
+/// use bft_types::embed::EmbeddedProgram;
+///
+/// static HELLO: EmbeddedProgram = bf_file!("../bf-programs/hello-world.bf");
+/// assert_eq!(HELLO.filename(), "../bf-programs/hello-world.bf");
+/// assert!(!HELLO.program().loops().is_empty());
+/// ```
+#[proc_macro]
+pub fn bf_file(input: TokenStream) -> TokenStream {
+    let path = parse_macro_input!(input as LitStr);
+    let root = std::env::var("CARGO_MANIFEST_DIR").unwrap_or_default();
+    let full_path = Path::new(&root).join(path.value());
+    let program = fs::read_to_string(&full_path)
+        .map_err(|error| format!("{}: {}", full_path.display(), error))
+        .and_then(|contents| {
+            BfProgram::new(contents, path.value())
+                .map_err(|error| format!("{}: {}", path.value(), error))
+        });
+    match program {
+        Ok(program) => {
+            let embedded = embed(&program, &path.value());
+            let full_path = full_path.display().to_string();
+            quote! {
+                {
+                    const _: &str = include_str!(#full_path);
+                    #embedded
+                }
+            }
+            .into()
+        }
+        Err(error) => syn::Error::new(path.span(), error)
+            .to_compile_error()
+            .into(),
+    }
+}
@@ -0,0 +1,333 @@
+//! The `.bfz` format, a compact binary container for a program: its commands
+//! packed into runs, its table of matching brackets, and a little metadata
+//! about how it runs. Loading a packed program skips parsing the source and
+//! pairing up its brackets, so large generated programs load much faster, and
+//! they take up far less space. `BfProgram::from_file()` loads files with the
+//! `.bfz` extension as packed programs.
+//!
+//! A packed program has no comments, and its instructions are numbered as if
+//! they were all written on the first line, as `BfProgram::normalized()` gives.
+//! ```
+//! use bft_types::bfz::{pack, unpack, Metadata};
+//! use bft_types::options::ParseOptions;
+//! use bft_types::BfProgram;
+//!
+//! let program =
+//!     BfProgram::new("++ add [->+<]".to_string(), "add.bf").unwrap();
+//! let packed = pack(&program, &Metadata::for_program(&program, Some(8)))
+//!     .unwrap();
+//! let (unpacked, metadata) =
+//!     unpack(&packed, "add.bfz", &ParseOptions::new()).unwrap();
+//! assert_eq!(unpacked, program);
+//! assert_eq!(unpacked.jump_target(2), Some(7));
+//! assert_eq!(metadata.cell_bits(), Some(8));
+//! assert_eq!(metadata.min_tape_cells(), 2);
+//! ```
+//!
+//! The format is, with numbers written as unsigned LEB128 varints:
+//!
+//! - the magic bytes `BFZ`, then the version of the format, which is 1;
+//! - the number of bits in each cell the program was written for, or 0 if it
+//!   does not say;
+//! - an estimate of the fewest cells of tape the program needs;
+//! - the number of instructions, then the runs of commands, each a byte with
+//!   the command in its top three bits and one less than the length of the
+//!   run in the bottom five;
+//! - for each opening bracket in turn, the distance to its closing bracket.
+
+use std::path::Path;
+
+use thiserror::Error;
+
+use crate::jumps::JumpTable;
+use crate::ops::Operation;
+use crate::options::ParseOptions;
+use crate::vm_error::VirtualMachineError;
+use crate::{BfProgram, InstructionInfo};
+
+/// The extension of packed programs.
+pub const EXTENSION: &str = "bfz";
+
+/// The bytes every packed program starts with.
+const MAGIC: &[u8] = b"BFZ";
+
+/// The version of the format written.
+const VERSION: u8 = 1;
+
+/// The longest run of a command packed into a single byte.
+const MAX_RUN: usize = 32;
+
+/// The ways reading or writing a packed program can fail.
+#[derive(Debug, Error)]
+pub enum BfzError {
+    /// The file does not start with the magic bytes.
+    #[error("this is not a packed Brainfuck program")]
+    NotPacked,
+    /// The file was written by a newer version of the format.
+    #[error("version {0} of the packed format is not supported")]
+    UnsupportedVersion(u8),
+    /// The file ends part way through.
+    #[error("the packed program is cut short")]
+    Truncated,
+    /// The file does not describe a valid program.
+    #[error("the packed program is corrupt: {0}")]
+    Corrupt(&'static str),
+    /// The program has a command which the format cannot hold.
+    #[error("the extension instruction '{0}' cannot be packed")]
+    Extension(char),
+    /// The program has unmatched brackets, which the format cannot hold.
+    #[error("only programs whose brackets all match can be packed")]
+    Unmatched,
+    /// The program is nested too deeply for the options it is loaded with.
+    #[error(transparent)]
+    Program(#[from] VirtualMachineError),
+}
+
+/// What a packed program records about how it runs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Metadata {
+    cell_bits: Option<u32>,
+    min_tape_cells: usize,
+}
+
+impl Metadata {
+    /// Creates the metadata for the program, which was written for cells of
+    /// the given number of bits, if known.
+    pub fn for_program(program: &BfProgram, cell_bits: Option<u32>) -> Self {
+        Self {
+            cell_bits,
+            min_tape_cells: program.statistics().min_tape_cells(),
+        }
+    }
+
+    /// The number of bits in each cell the program was written for, if known.
+    pub fn cell_bits(&self) -> Option<u32> {
+        self.cell_bits
+    }
+
+    /// An estimate of the fewest cells of tape the program needs. See
+    /// `ProgramStats::min_tape_cells()`.
+    pub fn min_tape_cells(&self) -> usize {
+        self.min_tape_cells
+    }
+}
+
+/// The number of a command in the packed format.
+fn command_number(operation: Operation) -> Result<u8, BfzError> {
+    Operation::all()
+        .iter()
+        .position(|&known| known == operation)
+        .map(|number| number as u8)
+        .ok_or(match operation {
+            Operation::Extension(name) => BfzError::Extension(name),
+            _ => BfzError::Corrupt("unknown command"),
+        })
+}
+
+/// Writes the number as an unsigned LEB128 varint.
+fn write_varint(bytes: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            bytes.push(byte);
+            return;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
+
+/// Reads the bytes of a packed program in order.
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl Reader<'_> {
+    fn byte(&mut self) -> Result<u8, BfzError> {
+        let (&byte, rest) =
+            self.bytes.split_first().ok_or(BfzError::Truncated)?;
+        self.bytes = rest;
+        Ok(byte)
+    }
+
+    fn varint(&mut self) -> Result<usize, BfzError> {
+        let mut value: u64 = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return usize::try_from(value)
+                    .map_err(|_| BfzError::Corrupt("number too large"));
+            }
+        }
+        Err(BfzError::Corrupt("number too long"))
+    }
+}
+
+/// Packs the program, along with its metadata.
+pub fn pack(
+    program: &BfProgram,
+    metadata: &Metadata,
+) -> Result<Vec<u8>, BfzError> {
+    let operations: Vec<Operation> =
+        program.iter().map(InstructionInfo::operation).collect();
+    if !program.jump_table().fits(&operations, true) {
+        return Err(BfzError::Unmatched);
+    }
+    let mut bytes = MAGIC.to_vec();
+    bytes.push(VERSION);
+    bytes.push(metadata.cell_bits.unwrap_or(0) as u8);
+    write_varint(&mut bytes, metadata.min_tape_cells as u64);
+    write_varint(&mut bytes, operations.len() as u64);
+    for run in operations.chunk_by(|a, b| a == b) {
+        let command = command_number(run[0])?;
+        for chunk in run.chunks(MAX_RUN) {
+            bytes.push(command << 5 | (chunk.len() - 1) as u8);
+        }
+    }
+    for (open, close) in program.jump_table().pairs() {
+        write_varint(&mut bytes, (close - open) as u64);
+    }
+    Ok(bytes)
+}
+
+/// Unpacks a program, given the filename it was read from, checking that its
+/// loops are not nested more deeply than the options allow.
+pub fn unpack<P>(
+    bytes: &[u8],
+    filename: P,
+    options: &ParseOptions,
+) -> Result<(BfProgram, Metadata), BfzError>
+where
+    P: AsRef<Path>,
+{
+    let Some(rest) = bytes.strip_prefix(MAGIC) else {
+        return Err(BfzError::NotPacked);
+    };
+    let mut reader = Reader { bytes: rest };
+    let version = reader.byte()?;
+    if version != VERSION {
+        return Err(BfzError::UnsupportedVersion(version));
+    }
+    let metadata = Metadata {
+        cell_bits: match reader.byte()? {
+            0 => None,
+            bits @ (8 | 16 | 32) => Some(bits.into()),
+            _ => return Err(BfzError::Corrupt("unknown cell width")),
+        },
+        min_tape_cells: reader.varint()?,
+    };
+    let len = reader.varint()?;
+    // Every command takes at least a byte for each run of up to 32, so a
+    // length that the rest of the file cannot hold is corrupt, and is not
+    // allocated for.
+    if len > reader.bytes.len().saturating_mul(MAX_RUN) {
+        return Err(BfzError::Truncated);
+    }
+    let mut instructions = Vec::with_capacity(len);
+    while instructions.len() < len {
+        let byte = reader.byte()?;
+        let operation = Operation::all()[usize::from(byte >> 5)];
+        let run = usize::from(byte & 0x1f) + 1;
+        if instructions.len() + run > len {
+            return Err(BfzError::Corrupt("a run goes past the end"));
+        }
+        for _ in 0..run {
+            let column = instructions.len() + 1;
+            instructions.push(InstructionInfo::new(operation, 1, column));
+        }
+    }
+
+    let mut jump_table = JumpTable::new(len);
+    for (open, instruction) in instructions.iter().enumerate() {
+        if instruction.operation() == Operation::StartLoop {
+            let close = open
+                .checked_add(reader.varint()?)
+                .filter(|&close| close > open && close < len)
+                .ok_or(BfzError::Corrupt("a loop ends outside the program"))?;
+            jump_table.insert(open, close);
+        }
+    }
+    if !reader.bytes.is_empty() {
+        return Err(BfzError::Corrupt("there are bytes after the program"));
+    }
+    let operations: Vec<Operation> = instructions
+        .iter()
+        .map(InstructionInfo::operation)
+        .collect();
+    if !jump_table.fits(&operations, true) {
+        return Err(BfzError::Corrupt("the brackets do not match"));
+    }
+    let program = BfProgram::from_packed(
+        instructions,
+        filename.as_ref().into(),
+        jump_table,
+        options.nesting_limit(),
+    )?;
+    Ok((program, metadata))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{pack, unpack, BfzError, Metadata};
+    use crate::options::ParseOptions;
+    use crate::BfProgram;
+
+    fn program(source: &str) -> BfProgram {
+        BfProgram::new(source.to_string(), "test.bf").unwrap()
+    }
+
+    fn round_trip(source: &str) -> BfProgram {
+        let original = program(source);
+        let packed = pack(&original, &Metadata::default()).unwrap();
+        let (unpacked, _) =
+            unpack(&packed, "test.bfz", &ParseOptions::new()).unwrap();
+        assert_eq!(unpacked, original);
+        assert_eq!(unpacked.jump_table(), original.jump_table());
+        unpacked
+    }
+
+    #[test]
+    fn test_round_trip() {
+        round_trip("");
+        round_trip("+[->+<]>.");
+        round_trip(&format!("{}[>[-]<-]", "+".repeat(100)));
+        let long = round_trip(&"[-]>".repeat(1000));
+        assert_eq!(long.instructions()[3999].column(), 4000);
+        // Long runs of a command take a byte for each 32.
+        let packed = pack(&program(&"+".repeat(64)), &Metadata::default());
+        assert_eq!(packed.unwrap().len(), 9);
+    }
+
+    #[test]
+    fn test_bad_files() {
+        let options = ParseOptions::new();
+        let packed = pack(&program("+[-]"), &Metadata::default()).unwrap();
+        assert!(matches!(
+            unpack(b"+[-]", "a.bfz", &options),
+            Err(BfzError::NotPacked)
+        ));
+        let mut newer = packed.clone();
+        newer[3] = 2;
+        assert!(matches!(
+            unpack(&newer, "a.bfz", &options),
+            Err(BfzError::UnsupportedVersion(2))
+        ));
+        assert!(matches!(
+            unpack(&packed[..packed.len() - 1], "a.bfz", &options),
+            Err(BfzError::Truncated)
+        ));
+        let mut wrong_loop = packed.clone();
+        *wrong_loop.last_mut().unwrap() = 1;
+        assert!(matches!(
+            unpack(&wrong_loop, "a.bfz", &options),
+            Err(BfzError::Corrupt(_))
+        ));
+        let nested = pack(&program("[[[]]]"), &Metadata::default()).unwrap();
+        let shallow = ParseOptions::new().max_nesting(2);
+        assert!(matches!(
+            unpack(&nested, "a.bfz", &shallow),
+            Err(BfzError::Program(_))
+        ));
+    }
+}
@@ -0,0 +1,162 @@
+//! A builder for constructing Brainfuck programs directly from operations,
+//! without needing to format them into a string and parse them again.
+
+use std::path::{Path, PathBuf};
+
+use crate::ops::Operation;
+use crate::options::{BracketValidation, DEFAULT_MAX_NESTING};
+use crate::vm_error::VirtualMachineError;
+use crate::{BfProgram, InstructionInfo};
+
+/// The filename given to programs created by the builder, unless another one
+/// is provided.
+const DEFAULT_FILENAME: &str = "<generated>";
+
+/// Builds up a `BfProgram` one operation at a time.
+///
+/// As there is no source text, the positions of the instructions are
+/// synthesized as if the whole program had been written on a single line.
+/// ```
+/// use bft_types::builder::BfProgramBuilder;
+/// use bft_types::ops::Operation;
+///
+/// let mut builder = BfProgramBuilder::new();
+/// builder
+///     .push(Operation::IncrementByte)
+///     .open_loop()
+///     .push(Operation::DecrementByte)
+///     .close_loop();
+/// let program = builder.finish().unwrap();
+///
+/// assert_eq!(program.instructions().len(), 4);
+/// assert_eq!(program.instructions()[3].operation(), Operation::EndLoop);
+/// assert_eq!(program.instructions()[3].column(), 4);
+/// ```
+#[derive(Debug, Clone)]
+pub struct BfProgramBuilder {
+    /// The instructions pushed so far.
+    instructions: Vec<InstructionInfo>,
+    /// The filename to give the finished program.
+    filename: PathBuf,
+    /// The deepest that the loops of the program may be nested.
+    max_nesting: usize,
+}
+
+impl Default for BfProgramBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BfProgramBuilder {
+    /// Creates a new, empty builder.
+    pub fn new() -> Self {
+        Self {
+            instructions: Vec::new(),
+            filename: PathBuf::from(DEFAULT_FILENAME),
+            max_nesting: DEFAULT_MAX_NESTING,
+        }
+    }
+
+    /// Sets the filename which the finished program will report, for example
+    /// in error messages.
+    pub fn filename<P>(mut self, filename: P) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        self.filename = filename.as_ref().to_path_buf();
+        self
+    }
+
+    /// Sets the deepest that loops may be nested in the finished program,
+    /// instead of `DEFAULT_MAX_NESTING`.
+    /// ```
+    /// use bft_types::builder::BfProgramBuilder;
+    ///
+    /// let mut builder = BfProgramBuilder::new().max_nesting(1);
+    /// builder.open_loop().open_loop().close_loop().close_loop();
+    /// assert!(builder.finish().is_err());
+    /// ```
+    pub fn max_nesting(mut self, limit: usize) -> Self {
+        self.max_nesting = limit;
+        self
+    }
+
+    /// Adds an operation to the end of the program.
+    pub fn push(&mut self, operation: Operation) -> &mut Self {
+        let column = self.instructions.len() + 1;
+        self.instructions
+            .push(InstructionInfo::new(operation, 1, column));
+        self
+    }
+
+    /// Opens a new loop, equivalent to pushing a `[`.
+    pub fn open_loop(&mut self) -> &mut Self {
+        self.push(Operation::StartLoop)
+    }
+
+    /// Closes the innermost open loop, equivalent to pushing a `]`.
+    pub fn close_loop(&mut self) -> &mut Self {
+        self.push(Operation::EndLoop)
+    }
+
+    /// Finishes the program, checking that all of its loops are balanced.
+    /// ```
+    /// use bft_types::builder::BfProgramBuilder;
+    ///
+    /// let mut builder = BfProgramBuilder::new();
+    /// builder.open_loop().open_loop().close_loop();
+    /// assert!(builder.finish().is_err());
+    /// ```
+    pub fn finish(self) -> Result<BfProgram, VirtualMachineError> {
+        BfProgram::from_instructions(
+            self.instructions,
+            self.filename.into(),
+            BracketValidation::Strict,
+            self.max_nesting,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BfProgramBuilder;
+    use crate::ops::Operation;
+    use crate::vm_error::VirtualMachineError;
+    use crate::BfProgram;
+
+    #[test]
+    fn test_builder_matches_parsed_program() {
+        let mut builder = BfProgramBuilder::new().filename("built.bf");
+        builder
+            .push(Operation::InputByte)
+            .open_loop()
+            .push(Operation::OutputByte)
+            .push(Operation::InputByte)
+            .close_loop();
+        let built = builder.finish().unwrap();
+        let parsed = BfProgram::new(String::from(",[.,]"), "built.bf").unwrap();
+
+        assert_eq!(built.filename(), parsed.filename());
+        assert_eq!(built.jump_table(), parsed.jump_table());
+        for (b, p) in built.instructions().iter().zip(parsed.instructions()) {
+            assert_eq!(b.operation(), p.operation());
+            assert_eq!(b.line(), p.line());
+            assert_eq!(b.column(), p.column());
+        }
+    }
+
+    #[test]
+    fn test_builder_unmatched_close() {
+        let mut builder = BfProgramBuilder::new();
+        builder.push(Operation::IncrementByte).close_loop();
+        assert!(matches!(
+            builder.finish(),
+            Err(VirtualMachineError::UnmatchedBracket {
+                bracket: ']',
+                line: 1,
+                column: 2
+            })
+        ));
+    }
+}
@@ -0,0 +1,216 @@
+//! A minimal parser which runs in `const` contexts, so that a program can be
+//! baked into a table at compile time, such as in flash on an embedded device,
+//! without allocating anything when it starts.
+
+use std::path::Path;
+
+use crate::ops::Operation;
+use crate::options::{BracketValidation, DEFAULT_MAX_NESTING};
+use crate::{BfProgram, InstructionInfo};
+
+/// A program parsed at compile time into at most `N` operations, along with
+/// the matching bracket of each bracket. Unlike a `BfProgram`, the positions of
+/// the operations in the source are not kept, and any character other than
+/// the eight commands is a comment.
+///
+/// Parsing panics if the program has more than `N` operations or unbalanced
+/// brackets, which in a `const` is a compile error. `count_operations` gives
+/// the smallest `N` which fits.
+/// ```
+/// use bft_types::{count_operations, ConstProgram};
+/// use bft_types::ops::Operation;
+///
+/// const SOURCE: &str = "+[->+<] moves the cell";
+/// static MOVE: ConstProgram<{ count_operations(SOURCE) }> =
+///     ConstProgram::parse(SOURCE);
+///
+/// assert_eq!(MOVE.len(), 7);
+/// assert_eq!(MOVE.operations()[2], Operation::DecrementByte);
+/// assert_eq!(MOVE.jump_target(1), Some(6));
+/// assert_eq!(MOVE.jump_target(6), Some(1));
+/// assert_eq!(MOVE.jump_target(2), None);
+/// ```
+///
+/// ```compile_fail
+/// use bft_types::ConstProgram;
+///
+/// const BROKEN: ConstProgram<4> = ConstProgram::parse("+[>");
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct ConstProgram<const N: usize> {
+    /// The operations of the program, followed by unused space.
+    operations: [Operation; N],
+    /// The position of the matching bracket of each bracket, with every other
+    /// position mapping to itself.
+    jumps: [usize; N],
+    /// The number of operations in the program.
+    len: usize,
+}
+
+/// The length of the shebang line at the start of the source, if any, which
+/// is skipped as `BfProgram` skips it.
+const fn shebang_len(source: &[u8]) -> usize {
+    if source.len() < 2 || source[0] != b'#' || source[1] != b'!' {
+        return 0;
+    }
+    let mut index = 2;
+    while index < source.len() && source[index] != b'\n' {
+        index += 1;
+    }
+    index
+}
+
+/// Counts the operations in the source, for the size of a `ConstProgram`.
+pub const fn count_operations(source: &str) -> usize {
+    let source = source.as_bytes();
+    let mut count = 0;
+    let mut index = shebang_len(source);
+    while index < source.len() {
+        if Operation::char_to_operation(source[index] as char).is_some() {
+            count += 1;
+        }
+        index += 1;
+    }
+    count
+}
+
+impl<const N: usize> ConstProgram<N> {
+    /// Parses the source, pairing up its brackets.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the source has more than `N` operations, or if its brackets
+    /// are not balanced.
+    pub const fn parse(source: &str) -> Self {
+        let source = source.as_bytes();
+        let mut operations = [Operation::IncrementPointer; N];
+        let mut jumps = [0; N];
+        // The positions of the loops still open, innermost last.
+        let mut open = [0; N];
+        let mut depth = 0;
+        let mut len = 0;
+        let mut index = shebang_len(source);
+        while index < source.len() {
+            let character = source[index] as char;
+            index += 1;
+            let Some(operation) = Operation::char_to_operation(character)
+            else {
+                continue;
+            };
+            if len == N {
+                panic!("the program has too many operations");
+            }
+            operations[len] = operation;
+            jumps[len] = len;
+            match operation {
+                Operation::StartLoop => {
+                    open[depth] = len;
+                    depth += 1;
+                }
+                Operation::EndLoop => {
+                    if depth == 0 {
+                        panic!("the program has an unmatched ]");
+                    }
+                    depth -= 1;
+                    jumps[len] = open[depth];
+                    jumps[open[depth]] = len;
+                }
+                _ => {}
+            }
+            len += 1;
+        }
+        if depth != 0 {
+            panic!("the program has an unmatched [");
+        }
+        Self {
+            operations,
+            jumps,
+            len,
+        }
+    }
+
+    /// The number of operations in the program.
+    pub const fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether the program has no operations.
+    pub const fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The operations of the program.
+    pub const fn operations(&self) -> &[Operation] {
+        self.operations.split_at(self.len).0
+    }
+
+    /// Retrieves the position of the bracket matching the bracket at the given
+    /// position, if there is a bracket there.
+    pub const fn jump_target(&self, position: usize) -> Option<usize> {
+        if position >= self.len || self.jumps[position] == position {
+            None
+        } else {
+            Some(self.jumps[position])
+        }
+    }
+
+    /// Creates a `BfProgram` with the given filename from the operations,
+    /// which are placed on a single line as the source positions are not
+    /// kept.
+    pub fn program(&self, filename: &str) -> BfProgram {
+        let instructions = self
+            .operations()
+            .iter()
+            .enumerate()
+            .map(|(n, operation)| InstructionInfo::new(*operation, 1, n + 1))
+            .collect();
+        BfProgram::from_instructions(
+            instructions,
+            Path::new(filename).into(),
+            BracketValidation::Strict,
+            DEFAULT_MAX_NESTING,
+        )
+        .expect("the brackets were paired up when the program was parsed")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{count_operations, ConstProgram};
+    use crate::BfProgram;
+
+    #[test]
+    fn test_matches_parsed_program() {
+        const SOURCE: &str = "#!/usr/bin/env bft -v\n,[.[-],] echo\n";
+        const ECHO: ConstProgram<8> = ConstProgram::parse(SOURCE);
+        assert_eq!(count_operations(SOURCE), 8);
+        let parsed = BfProgram::new(SOURCE.to_string(), "echo.bf").unwrap();
+        assert_eq!(ECHO.program("echo.bf"), parsed);
+        for position in 0..=ECHO.len() {
+            assert_eq!(
+                ECHO.jump_target(position),
+                parsed.jump_target(position)
+            );
+        }
+    }
+
+    #[test]
+    fn test_spare_room() {
+        const CLEAR: ConstProgram<16> = ConstProgram::parse("[-]");
+        assert_eq!(CLEAR.len(), 3);
+        assert_eq!(CLEAR.jump_target(3), None);
+        assert!(ConstProgram::<0>::parse("comment").is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "too many operations")]
+    fn test_too_many_operations() {
+        ConstProgram::<2>::parse("+++");
+    }
+
+    #[test]
+    #[should_panic(expected = "unmatched ]")]
+    fn test_unmatched_close() {
+        ConstProgram::<4>::parse("+]");
+    }
+}
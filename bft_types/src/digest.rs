@@ -0,0 +1,117 @@
+//! Stable hashes of programs, identifying a program by its instructions alone
+//! so that copies which differ only in comments and layout share an identity.
+
+use std::fmt;
+
+use crate::BfProgram;
+
+/// The offset basis of the 64 bit FNV-1a hash.
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+
+/// The prime of the 64 bit FNV-1a hash.
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// A 64 bit FNV-1a hash. Unlike the hashers in the standard library, its
+/// output is stable across platforms and Rust versions, so it can be stored.
+/// ```
+/// use bft_types::digest::Fnv1a;
+/// let mut hash = Fnv1a::new();
+/// hash.update(b"a");
+/// assert_eq!(hash.hex(), "fnv1a64:af63dc4c8601ec8c");
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct Fnv1a(u64);
+
+impl Fnv1a {
+    /// Creates a hash of nothing.
+    pub fn new() -> Self {
+        Fnv1a(FNV_OFFSET_BASIS)
+    }
+
+    /// Adds the bytes to the hash.
+    pub fn update(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= u64::from(*byte);
+            self.0 = self.0.wrapping_mul(FNV_PRIME);
+        }
+    }
+
+    /// The hash itself.
+    pub fn finish(&self) -> u64 {
+        self.0
+    }
+
+    /// The hash as a string, tagged with the algorithm used.
+    pub fn hex(&self) -> String {
+        format!("fnv1a64:{:016x}", self.0)
+    }
+}
+
+impl Default for Fnv1a {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The identity of a program, as returned by `BfProgram::digest()`: a hash of
+/// its instructions, normalized to their Brainfuck characters, so that it
+/// does not change with comments, whitespace or the file it was read from.
+/// Displays tagged with the algorithm used.
+/// ```
+/// use bft_types::BfProgram;
+/// let a = BfProgram::new("+[-]".to_string(), "a.bf").unwrap();
+/// let b = BfProgram::new("+ clear [-]\n".to_string(), "b.bf").unwrap();
+/// assert_eq!(a.digest(), b.digest());
+/// assert_eq!(a.digest().to_string(), "fnv1a64:ff9c8dbec7164f55");
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ProgramDigest(u64);
+
+impl ProgramDigest {
+    /// Hashes the instructions of the program.
+    pub(crate) fn new(program: &BfProgram) -> Self {
+        let mut hash = Fnv1a::new();
+        let mut buffer = [0; 4];
+        for instruction in program {
+            let c = instruction.operation().to_char();
+            hash.update(c.encode_utf8(&mut buffer).as_bytes());
+        }
+        ProgramDigest(hash.finish())
+    }
+
+    /// The hash itself.
+    pub fn value(&self) -> u64 {
+        self.0
+    }
+}
+
+impl fmt::Display for ProgramDigest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "fnv1a64:{:016x}", self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Fnv1a, ProgramDigest};
+    use crate::BfProgram;
+
+    #[test]
+    fn test_fnv1a_known_values() {
+        assert_eq!(Fnv1a::new().hex(), "fnv1a64:cbf29ce484222325");
+        let mut hash = Fnv1a::new();
+        hash.update(b"a");
+        assert_eq!(hash.hex(), "fnv1a64:af63dc4c8601ec8c");
+    }
+
+    #[test]
+    fn test_digest_ignores_comments() {
+        let a = BfProgram::new(String::from("+[-]"), "a.bf").unwrap();
+        let b = BfProgram::new(String::from("+ clear [-]"), "b.bf").unwrap();
+        let c = BfProgram::new(String::from("+[+]"), "c.bf").unwrap();
+        assert_eq!(a.digest(), b.digest());
+        assert_ne!(a.digest(), c.digest());
+        let empty = BfProgram::new(String::from("no code"), "d.bf").unwrap();
+        assert_eq!(empty.digest(), ProgramDigest(Fnv1a::new().finish()));
+    }
+}
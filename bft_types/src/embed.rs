@@ -0,0 +1,94 @@
+//! Programs embedded in Rust code, which were parsed and checked when the code
+//! was compiled, by the `bf_program!` and `bf_file!` macros of `bft_macro`.
+
+use std::path::Path;
+use std::sync::OnceLock;
+
+use crate::options::{BracketValidation, DEFAULT_MAX_NESTING};
+use crate::{BfProgram, InstructionInfo};
+
+/// A program whose instructions are stored in the binary, so that it can be
+/// put in a `static` and run without parsing it again. Embedded programs are
+/// created by the `bf_program!` and `bf_file!` macros of `bft_macro`, which
+/// check that their loops are balanced.
+#[derive(Debug)]
+pub struct EmbeddedProgram {
+    /// The name which the program reports, for example in error messages.
+    filename: &'static str,
+    /// The instructions of the program, with their original positions.
+    instructions: &'static [InstructionInfo],
+    /// The program, built from the instructions the first time it is asked
+    /// for.
+    program: OnceLock<BfProgram>,
+}
+
+impl EmbeddedProgram {
+    /// Creates an embedded program from instructions whose loops are known to
+    /// be balanced. This is what the macros of `bft_macro` expand to, once
+    /// they have checked the program, and is not meant to be called directly.
+    #[doc(hidden)]
+    pub const fn new(
+        filename: &'static str,
+        instructions: &'static [InstructionInfo],
+    ) -> Self {
+        Self {
+            filename,
+            instructions,
+            program: OnceLock::new(),
+        }
+    }
+
+    /// The name which the program reports.
+    pub fn filename(&self) -> &'static str {
+        self.filename
+    }
+
+    /// The instructions of the program.
+    pub fn instructions(&self) -> &'static [InstructionInfo] {
+        self.instructions
+    }
+
+    /// The program, pairing up its brackets without parsing any source the
+    /// first time it is asked for, and giving back the same program after
+    /// that.
+    pub fn program(&self) -> &BfProgram {
+        self.program.get_or_init(|| {
+            BfProgram::from_instructions(
+                self.instructions.to_vec(),
+                Path::new(self.filename).into(),
+                BracketValidation::Strict,
+                DEFAULT_MAX_NESTING,
+            )
+            .expect("embedded programs are checked when they are compiled")
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::EmbeddedProgram;
+    use crate::ops::Operation;
+    use crate::{BfProgram, InstructionInfo};
+
+    #[test]
+    fn test_embedded_matches_parsed_program() {
+        static ECHO: EmbeddedProgram = EmbeddedProgram::new(
+            "echo.bf",
+            &[
+                InstructionInfo::new(Operation::InputByte, 1, 1),
+                InstructionInfo::new(Operation::StartLoop, 2, 1),
+                InstructionInfo::new(Operation::OutputByte, 2, 2),
+                InstructionInfo::new(Operation::InputByte, 2, 3),
+                InstructionInfo::new(Operation::EndLoop, 2, 4),
+            ],
+        );
+        let embedded = ECHO.program();
+        let parsed =
+            BfProgram::new(String::from(",\n[.,]"), "echo.bf").unwrap();
+        assert_eq!(embedded, &parsed);
+        assert!(std::ptr::eq(embedded, ECHO.program()));
+        assert_eq!(embedded.filename(), parsed.filename());
+        assert_eq!(embedded.jump_table(), parsed.jump_table());
+        assert_eq!(embedded.instruction_at(2, 3).unwrap().column(), 3);
+    }
+}
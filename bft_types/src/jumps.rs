@@ -0,0 +1,148 @@
+//! The table of matching brackets of a Brainfuck program, which tells the
+//! interpreter where to jump to at each end of a loop.
+
+use crate::ops::Operation;
+
+/// The matching bracket of each bracket in a program, indexed by position.
+///
+/// Each opening bracket maps to its closing bracket and each closing bracket
+/// back to its opening bracket, so finding the other end of a loop from either
+/// end is a single lookup. Everything else, including the unmatched brackets
+/// of a lazily validated program, maps to itself, which is never the target of
+/// a matched bracket, so every position has an entry and looking one up cannot
+/// fail. A strictly validated program has a target for every bracket.
+/// ```
+/// use bft_types::BfProgram;
+/// let program = BfProgram::new("+[-[>]]".to_string(), "test.bf").unwrap();
+/// let jumps = program.jump_table();
+/// assert_eq!(jumps.jump_target(1), Some(6));
+/// assert_eq!(jumps.jump_target(6), Some(1));
+/// assert_eq!(jumps.jump_target(5), Some(3));
+/// assert_eq!(jumps.jump_target(0), None);
+/// assert_eq!(jumps.pairs().collect::<Vec<_>>(), [(1, 6), (3, 5)]);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct JumpTable {
+    targets: Vec<usize>,
+}
+
+impl JumpTable {
+    /// Creates a table for a program of the given number of instructions,
+    /// with no brackets matched yet.
+    pub(crate) fn new(len: usize) -> Self {
+        Self {
+            targets: (0..len).collect(),
+        }
+    }
+
+    /// Records the brackets at the two positions as matching each other.
+    pub(crate) fn insert(&mut self, open: usize, close: usize) {
+        debug_assert!(open < close, "brackets paired out of order");
+        self.targets[open] = close;
+        self.targets[close] = open;
+    }
+
+    /// Retrieves the position of the bracket matching the bracket at the given
+    /// position, if it is a matched bracket.
+    pub fn jump_target(&self, position: usize) -> Option<usize> {
+        self.targets
+            .get(position)
+            .copied()
+            .filter(|&target| target != position)
+    }
+
+    /// Retrieves the entry for the given position, which must be in the
+    /// program: the position of the matching bracket for a matched bracket,
+    /// and the position itself for anything else. This is a single lookup
+    /// with nothing to unwrap, for the loops of interpreters.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the position is past the end of the program.
+    /// ```
+    /// use bft_types::BfProgram;
+    /// let program = BfProgram::new("[-]".to_string(), "clear.bf").unwrap();
+    /// let jumps = program.jump_table();
+    /// assert_eq!((jumps.target(0), jumps.target(1)), (2, 1));
+    /// ```
+    pub fn target(&self, position: usize) -> usize {
+        self.targets[position]
+    }
+
+    /// Checks that the table fits the given operations: every entry is in the
+    /// program, each matched bracket is paired with the opposite bracket,
+    /// which is paired back with it, and everything else maps to itself. If
+    /// `complete` is set, every bracket must be matched. Tables made by
+    /// pairing up the brackets of a program always fit it.
+    pub fn fits(&self, operations: &[Operation], complete: bool) -> bool {
+        self.targets.len() == operations.len()
+            && self.targets.iter().enumerate().all(|(position, &target)| {
+                let operation = operations[position];
+                if target == position {
+                    return !complete
+                        || !matches!(
+                            operation,
+                            Operation::StartLoop | Operation::EndLoop
+                        );
+                }
+                let back = self.targets.get(target) == Some(&position);
+                match (operation, operations.get(target)) {
+                    (Operation::StartLoop, Some(Operation::EndLoop)) => {
+                        back && target > position
+                    }
+                    (Operation::EndLoop, Some(Operation::StartLoop)) => {
+                        back && target < position
+                    }
+                    _ => false,
+                }
+            })
+    }
+
+    /// Iterates over the positions of each pair of matching brackets, opening
+    /// bracket first, in the order of the opening brackets.
+    pub fn pairs(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.targets
+            .iter()
+            .enumerate()
+            .filter_map(|(position, target)| {
+                (*target > position).then_some((position, *target))
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::JumpTable;
+    use crate::ops::Operation::{EndLoop, IncrementByte, StartLoop};
+
+    #[test]
+    fn test_jump_targets() {
+        let mut jumps = JumpTable::new(4);
+        jumps.insert(0, 3);
+        assert_eq!(jumps.jump_target(0), Some(3));
+        assert_eq!(jumps.jump_target(3), Some(0));
+        assert_eq!(jumps.jump_target(1), None);
+        assert_eq!(jumps.jump_target(4), None);
+        assert_eq!(jumps.pairs().collect::<Vec<_>>(), [(0, 3)]);
+        assert_eq!((jumps.target(0), jumps.target(1)), (3, 1));
+    }
+
+    #[test]
+    fn test_fits() {
+        let operations = [StartLoop, IncrementByte, EndLoop, EndLoop];
+        let mut jumps = JumpTable::new(4);
+        jumps.insert(0, 2);
+        assert!(jumps.fits(&operations, false));
+        assert!(!jumps.fits(&operations, true));
+        assert!(!jumps.fits(&operations[..3], false));
+
+        // Pairing a bracket with something other than a bracket.
+        let mut jumps = JumpTable::new(4);
+        jumps.insert(0, 1);
+        assert!(!jumps.fits(&operations, false));
+        let mut jumps = JumpTable::new(4);
+        jumps.insert(0, 3);
+        jumps.insert(2, 3);
+        assert!(!jumps.fits(&operations, false));
+    }
+}
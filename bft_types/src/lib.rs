@@ -4,13 +4,54 @@
 #![deny(missing_docs)]
 
 use std::fs;
+use std::hash::{Hash, Hasher};
+use std::ops::{Bound, Range, RangeBounds};
 use std::path::Path;
-use std::path::PathBuf;
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
 use std::{collections::HashMap, error::Error};
 
+pub mod bfz;
+
+pub mod builder;
+
+pub mod const_program;
+pub use const_program::{count_operations, ConstProgram};
+
+pub mod digest;
+use digest::ProgramDigest;
+
+pub mod embed;
+
+pub mod jumps;
+use jumps::JumpTable;
+
+pub mod loops;
+use loops::LoopNode;
+
+pub mod memory;
+
 pub mod ops;
 use ops::Operation;
 
+pub mod options;
+use options::{
+    BracketValidation, CommentPolicy, ParseOptions, BLOCK_COMMENT, LINE_COMMENT,
+};
+
+pub mod profile;
+use profile::{shebang_len, SourceProfile};
+
+pub mod source_db;
+
+pub mod stats;
+use stats::ProgramStats;
+
+pub mod testspec;
+
+pub mod tokens;
+use tokens::{CommentIndex, Tokens};
+
 pub mod vm_error;
 
 // Thanks to Kiran for the idea of using this crate
@@ -31,7 +72,9 @@ pub struct InstructionInfo {
 }
 
 impl InstructionInfo {
-    fn new(operation: Operation, line: usize, column: usize) -> Self {
+    /// Creates the information for an instruction found at the given line and
+    /// column, for front ends which produce instructions from another source.
+    pub const fn new(operation: Operation, line: usize, column: usize) -> Self {
         Self {
             operation,
             line,
@@ -49,7 +92,8 @@ impl InstructionInfo {
         self.line
     }
 
-    /// Retrieves the column on which a given instruction is found.
+    /// Retrieves the column on which a given instruction is found. Columns
+    /// are counted in bytes from the start of the line.
     pub fn column(&self) -> usize {
         self.column
     }
@@ -58,15 +102,139 @@ impl InstructionInfo {
 /// A Brainfuck program, with the set of instructions, the filename of the
 /// program, and the pairs of opening and closing brackets representing the
 /// loops of the program.
+///
+/// Two programs are equal, and hash the same, when they have the same sequence
+/// of operations, regardless of their filenames, comments and layout.
+/// ```
+/// use bft_types::BfProgram;
+/// let first = BfProgram::new("+[-] clear".to_string(), "first.bf").unwrap();
+/// let second = BfProgram::new("+\n[\n  -\n]".to_string(), "second.bf").unwrap();
+/// assert_eq!(first, second);
+/// ```
 #[derive(Debug)]
 pub struct BfProgram {
     /// Vector of instructions that are contained in the program.
     instructions: Vec<InstructionInfo>,
-    /// The filename of the program.
-    filename: PathBuf,
-    // The pairs of brackets that are present in the program.
-    // bracket_pairs: (usize, usize),
-    bracket_matching_positions: HashMap<usize, usize>,
+    /// The filename of the program, which programs loaded through a
+    /// `SourceDb` share with every other use of the same path.
+    filename: Arc<Path>,
+    /// The matching bracket of each bracket in the program.
+    jump_table: JumpTable,
+    /// The pairs of matching brackets as a hashmap, built from the jump table
+    /// the first time they are asked for.
+    bracket_pairs: OnceLock<HashMap<usize, usize>>,
+    /// How the brackets of the program were validated when it was created.
+    bracket_validation: BracketValidation,
+    /// The range of instructions found on each line of the program, used to
+    /// look up instructions by their position.
+    line_index: HashMap<usize, Range<usize>>,
+    /// How long pairing up the brackets took when the program was created.
+    bracket_time: Duration,
+    /// The number of characters in the source of the program, including
+    /// comments.
+    source_chars: usize,
+    /// The deepest that the loops of the program may be nested.
+    max_nesting: usize,
+    /// A profile of the source of the program.
+    profile: SourceProfile,
+    /// The comments between the instructions of the program.
+    comments: CommentIndex,
+    /// The problems with the source of the program, in order, if it was
+    /// created with `BracketValidation::Recover`.
+    diagnostics: Vec<vm_error::VirtualMachineError>,
+}
+
+/// Parses the instructions out of the source of a program, skipping any
+/// shebang, along with the comments between them. When comments must be
+/// delimited, every other character must be whitespace or inside a comment.
+/// The instructions parsed from the source of a program, the comments between
+/// them, and the problems with the source which were recovered from.
+type Parsed = (
+    Vec<InstructionInfo>,
+    CommentIndex,
+    Vec<vm_error::VirtualMachineError>,
+);
+
+fn parse_instructions(
+    contents: &str,
+    options: &ParseOptions,
+) -> Result<Parsed, vm_error::VirtualMachineError> {
+    // Once again, thanks to Kiran for the idea of using this crate
+    let lookup = LineColLookup::new(contents);
+    let delimited = options.comment_policy() == CommentPolicy::Delimited;
+    let body_start = shebang_len(contents);
+    let mut instructions = Vec::new();
+    // The byte offset of the start of the comment being skipped, and the
+    // character which ends it.
+    let mut comment: Option<(usize, char)> = None;
+    let mut comments = CommentIndex::default();
+    // The byte offset of the start of the run of characters since the last
+    // instruction, which starts with the shebang if there is one.
+    let mut run_start = (body_start > 0).then_some(0);
+    // Problems are only recovered from, as if they were comments, when asked
+    // to.
+    let recover = options.brackets() == BracketValidation::Recover;
+    let mut diagnostics = Vec::new();
+    // Whether the last character was a stray one, so that a run of them, such
+    // as a word written without a comment around it, is only reported once.
+    let mut stray = false;
+
+    // The lookup works on byte offsets, so the byte offset of each character
+    // is needed rather than its index in the string. The lines and columns of
+    // the instructions after a shebang are kept as they are in the file.
+    for (n, c) in contents.char_indices().skip_while(|(n, _)| *n < body_start) {
+        let after_stray = std::mem::take(&mut stray);
+        if comment.is_some() || options.operation_for(c).is_none() {
+            run_start.get_or_insert(n);
+        } else if let Some(start) = run_start.take() {
+            let position = instructions.len();
+            comments.push(&contents[start..n], lookup.get(start), position);
+        }
+        if let Some((_, end)) = comment {
+            if c == end {
+                comment = None;
+            }
+        } else if let Some(operation) = options.operation_for(c) {
+            let (line, column) = lookup.get(n);
+            instructions.push(InstructionInfo::new(operation, line, column));
+        } else if !delimited || c.is_whitespace() {
+            continue;
+        } else if c == LINE_COMMENT {
+            comment = Some((n, '\n'));
+        } else if c == BLOCK_COMMENT.0 {
+            comment = Some((n, BLOCK_COMMENT.1));
+        } else {
+            let (line, column) = lookup.get(n);
+            let error = vm_error::VirtualMachineError::StrayCharacter {
+                character: c,
+                line,
+                column,
+            };
+            if !recover {
+                return Err(error);
+            }
+            if !after_stray {
+                diagnostics.push(error);
+            }
+            stray = true;
+        }
+    }
+    // A line comment may run to the end of the program, but a block comment
+    // must be closed.
+    if let Some((n, _)) = comment.filter(|&(_, end)| end == BLOCK_COMMENT.1) {
+        let (line, column) = lookup.get(n);
+        let error =
+            vm_error::VirtualMachineError::UnterminatedComment { line, column };
+        if !recover {
+            return Err(error);
+        }
+        diagnostics.push(error);
+    }
+    if let Some(start) = run_start {
+        let position = instructions.len();
+        comments.push(&contents[start..], lookup.get(start), position);
+    }
+    Ok((instructions, comments, diagnostics))
 }
 
 impl BfProgram {
@@ -101,33 +269,144 @@ impl BfProgram {
     where
         P: AsRef<Path>,
     {
-        // Once again, thanks to Kiran for the idea of using this crate
-        let lookup = LineColLookup::new(&contents);
+        BfProgram::new_with_options(contents, filename, &ParseOptions::new())
+    }
 
-        let instructions: Vec<InstructionInfo> = contents
-            .chars()
-            .enumerate()
-            .filter_map(|(n, c)| {
-                Operation::char_to_operation(c).map(|instruction| {
-                    InstructionInfo::new(
-                        instruction,
-                        lookup.get(n).0,
-                        lookup.get(n).1,
-                    )
-                })
-            })
-            .collect();
+    /// Creates a new Brainfuck program, as with `new()`, but using the given
+    /// set of parse options.
+    ///
+    /// For example, with lazy bracket validation a program with unmatched
+    /// brackets can still be created, and will only fail if the interpreter
+    /// reaches one of the unmatched brackets:
+    /// ```
+    /// use bft_types::BfProgram;
+    /// use bft_types::options::{BracketValidation, ParseOptions};
+    /// let options = ParseOptions::new().bracket_validation(BracketValidation::Lazy);
+    /// let program = BfProgram::new_with_options("+[>".to_string(), "lazy.bf", &options);
+    /// assert!(program.is_ok());
+    /// ```
+    pub fn new_with_options<P>(
+        contents: String,
+        filename: P,
+        options: &ParseOptions,
+    ) -> Result<Self, vm_error::VirtualMachineError>
+    where
+        P: AsRef<Path>,
+    {
+        options.check_size(filename.as_ref(), contents.len() as u64)?;
+
+        let (instructions, comments, diagnostics) =
+            parse_instructions(&contents, options)?;
+        let profile = SourceProfile::new(&contents, instructions.len());
+        let mut program = BfProgram::from_instructions(
+            instructions,
+            filename.as_ref().into(),
+            options.brackets(),
+            options.nesting_limit(),
+        )?;
+        program.source_chars = contents.chars().count();
+        program.profile = profile;
+        program.comments = comments;
+        program.diagnostics.extend(diagnostics);
+        program.diagnostics.sort_by_key(|error| error.location());
+        Ok(program)
+    }
+
+    /// Creates a program from an already parsed list of instructions, pairing
+    /// up the brackets according to the given validation mode and nesting
+    /// limit.
+    pub(crate) fn from_instructions(
+        instructions: Vec<InstructionInfo>,
+        filename: Arc<Path>,
+        bracket_validation: BracketValidation,
+        max_nesting: usize,
+    ) -> Result<Self, vm_error::VirtualMachineError> {
+        let mut line_index: HashMap<usize, Range<usize>> = HashMap::new();
+        for (position, instruction) in instructions.iter().enumerate() {
+            line_index
+                .entry(instruction.line())
+                .and_modify(|range| range.end = position + 1)
+                .or_insert(position..position + 1);
+        }
+        let instructions_len = instructions.len();
         let mut program = Self {
             instructions,
-            filename: filename.as_ref().to_path_buf(),
-            bracket_matching_positions: HashMap::new(),
+            filename,
+            jump_table: JumpTable::default(),
+            bracket_pairs: OnceLock::new(),
+            bracket_validation,
+            line_index,
+            bracket_time: Duration::ZERO,
+            source_chars: instructions_len,
+            max_nesting,
+            profile: SourceProfile::from_commands(instructions_len),
+            comments: CommentIndex::default(),
+            diagnostics: Vec::new(),
         };
-        let new_matching_positions: HashMap<usize, usize> =
-            program.bracket_check()?;
-        program.bracket_matching_positions = new_matching_positions;
+        let start = Instant::now();
+        program.jump_table = match bracket_validation {
+            BracketValidation::Strict => program.checked_jump_table()?,
+            BracketValidation::Lazy => program.pair_brackets()?.0,
+            BracketValidation::Recover => {
+                let jump_table = program.pair_brackets()?.0;
+                program.diagnostics = program.unmatched_brackets(&jump_table);
+                jump_table
+            }
+        };
+        debug_assert!(program.jump_table.fits(
+            &program.iter().map(|i| i.operation()).collect::<Vec<_>>(),
+            bracket_validation == BracketValidation::Strict
+        ));
+        program.bracket_time = start.elapsed();
         Ok(program)
     }
 
+    /// Creates a program from the instructions and table of matching brackets
+    /// of a packed program, which are already known to fit each other, so
+    /// only the nesting of its loops is checked.
+    pub(crate) fn from_packed(
+        instructions: Vec<InstructionInfo>,
+        filename: Arc<Path>,
+        jump_table: JumpTable,
+        max_nesting: usize,
+    ) -> Result<Self, vm_error::VirtualMachineError> {
+        let mut depth = 0;
+        for instruction in &instructions {
+            match instruction.operation() {
+                Operation::StartLoop => depth += 1,
+                Operation::EndLoop => depth -= 1,
+                _ => {}
+            }
+            if depth > max_nesting {
+                return Err(vm_error::VirtualMachineError::NestingTooDeep {
+                    depth,
+                    limit: max_nesting,
+                    line: instruction.line(),
+                    column: instruction.column(),
+                });
+            }
+        }
+        let mut line_index = HashMap::new();
+        if !instructions.is_empty() {
+            line_index.insert(1, 0..instructions.len());
+        }
+        let len = instructions.len();
+        Ok(BfProgram {
+            instructions,
+            filename,
+            jump_table,
+            bracket_pairs: OnceLock::new(),
+            bracket_validation: BracketValidation::Strict,
+            line_index,
+            bracket_time: Duration::ZERO,
+            source_chars: len,
+            max_nesting,
+            profile: SourceProfile::from_commands(len),
+            comments: CommentIndex::default(),
+            diagnostics: Vec::new(),
+        })
+    }
+
     /// Reads directly from a file, to produce a Brainfuck program.
     /// Given a program file named 'path/to/program.bf', we can load the
     /// program from the file as follows:
@@ -139,23 +418,345 @@ impl BfProgram {
     where
         P: AsRef<Path>,
     {
+        BfProgram::from_file_with_options(filename, &ParseOptions::new())
+    }
+
+    /// Reads directly from a file, as with `from_file()`, but using the given
+    /// set of parse options. Files with the `.bfz` extension are read as
+    /// packed programs, see `bfz`.
+    pub fn from_file_with_options<P>(
+        filename: P,
+        options: &ParseOptions,
+    ) -> Result<BfProgram, Box<dyn Error>>
+    where
+        P: AsRef<Path>,
+    {
+        // Check the size before reading, so that a huge file given by
+        // mistake is never read into memory.
+        options
+            .check_size(filename.as_ref(), fs::metadata(&filename)?.len())?;
+        if filename.as_ref().extension() == Some(bfz::EXTENSION.as_ref()) {
+            let bytes = fs::read(&filename)?;
+            return Ok(bfz::unpack(&bytes, filename, options)?.0);
+        }
         let contents = fs::read_to_string(&filename)?;
-        Ok(BfProgram::new(contents, filename)?)
+        Ok(BfProgram::new_with_options(contents, filename, options)?)
     }
 
     /// Retrieves the list of instructions present in a given program.
-    pub fn instructions(&self) -> &Vec<InstructionInfo> {
+    pub fn instructions(&self) -> &[InstructionInfo] {
         &self.instructions
     }
 
+    /// Iterates over the tokens of the source of the program: each of its
+    /// instructions, and the comments between them, in the order they were
+    /// written. Programs which were not parsed from a source, such as those
+    /// built or normalized, have no comments. See `tokens` for more.
+    /// ```
+    /// use bft_types::tokens::Token;
+    /// use bft_types::BfProgram;
+    /// let program = BfProgram::new("+ one".to_string(), "one.bf").unwrap();
+    /// let tokens: Vec<Token> = program.tokens().collect();
+    /// assert!(matches!(tokens[..], [Token::Command(_), Token::Comment(_)]));
+    /// ```
+    pub fn tokens(&self) -> Tokens<'_> {
+        Tokens::new(&self.instructions, &self.comments)
+    }
+
+    /// Iterates over the instructions present in the program, this is the same
+    /// as iterating over a reference to the program itself.
+    /// ```
+    /// use bft_types::BfProgram;
+    /// let program = BfProgram::new("+ comment -".to_string(), "test.bf").unwrap();
+    /// let columns: Vec<usize> = program.iter().map(|i| i.column()).collect();
+    /// assert_eq!(columns, vec![1, 11]);
+    /// for instruction in &program {
+    ///     assert_eq!(instruction.line(), 1);
+    /// }
+    /// ```
+    pub fn iter(&self) -> std::slice::Iter<'_, InstructionInfo> {
+        self.instructions.iter()
+    }
+
+    /// Retrieves the instructions found on the given line of the program. If
+    /// there are no instructions on the line, the slice will be empty.
+    /// ```
+    /// use bft_types::BfProgram;
+    /// let program = BfProgram::new("+-\ncomment\n.,".to_string(), "test.bf").unwrap();
+    /// assert_eq!(program.instructions_in_line(1).len(), 2);
+    /// assert!(program.instructions_in_line(2).is_empty());
+    /// assert_eq!(program.instructions_in_line(3)[0].column(), 1);
+    /// ```
+    pub fn instructions_in_line(&self, line: usize) -> &[InstructionInfo] {
+        match self.line_index.get(&line) {
+            Some(range) => &self.instructions[range.clone()],
+            None => &[],
+        }
+    }
+
+    /// Retrieves the instruction at the given line and column of the program,
+    /// if there is one there.
+    /// ```
+    /// use bft_types::BfProgram;
+    /// use bft_types::ops::Operation;
+    /// let program = BfProgram::new("+-\n x.".to_string(), "test.bf").unwrap();
+    /// let instruction = program.instruction_at(2, 3).unwrap();
+    /// assert_eq!(instruction.operation(), Operation::OutputByte);
+    /// assert!(program.instruction_at(2, 2).is_none());
+    /// ```
+    pub fn instruction_at(
+        &self,
+        line: usize,
+        column: usize,
+    ) -> Option<&InstructionInfo> {
+        let in_line = self.instructions_in_line(line);
+        in_line
+            .binary_search_by_key(&column, |instruction| instruction.column())
+            .ok()
+            .map(|position| &in_line[position])
+    }
+
     /// Retrieves the filename of the program.
     pub fn filename(&self) -> &Path {
         &self.filename
     }
 
     /// A hashmap describing the positions of pairs of matching brackets
+    #[deprecated(
+        note = "use `jump_table` or `jump_target`, which avoid the hashmap"
+    )]
     pub fn bracket_matching_positions(&self) -> &HashMap<usize, usize> {
-        &self.bracket_matching_positions
+        self.bracket_pairs
+            .get_or_init(|| self.jump_table.pairs().collect())
+    }
+
+    /// The matching bracket of each bracket in the program.
+    pub fn jump_table(&self) -> &JumpTable {
+        &self.jump_table
+    }
+
+    /// Retrieves the position of the bracket matching the bracket at the given
+    /// position, if it is a matched bracket. This works from either end of a
+    /// loop.
+    /// ```
+    /// use bft_types::BfProgram;
+    /// let program = BfProgram::new("+[-]".to_string(), "test.bf").unwrap();
+    /// assert_eq!(program.jump_target(1), Some(3));
+    /// assert_eq!(program.jump_target(3), Some(1));
+    /// assert_eq!(program.jump_target(2), None);
+    /// ```
+    pub fn jump_target(&self, position: usize) -> Option<usize> {
+        self.jump_table.jump_target(position)
+    }
+
+    /// How long pairing up the brackets of the program took when it was
+    /// created, which is part of the time taken to create it.
+    pub fn bracket_time(&self) -> Duration {
+        self.bracket_time
+    }
+
+    /// The number of characters in the source of the program, including
+    /// comments. Programs which were not parsed from a source, such as those
+    /// from `BfProgramBuilder`, have no comments.
+    pub fn source_chars(&self) -> usize {
+        self.source_chars
+    }
+
+    /// A profile of the source of the program, used to tell whether it looks
+    /// like Brainfuck at all. Programs which were not parsed from a source are
+    /// entirely made up of commands.
+    pub fn profile(&self) -> &SourceProfile {
+        &self.profile
+    }
+
+    /// Works out statistics about the program, such as how many of each
+    /// operation it has and how deeply its loops are nested.
+    pub fn statistics(&self) -> ProgramStats {
+        ProgramStats::new(self)
+    }
+
+    /// The identity of the program: a stable hash of its instructions, which
+    /// programs differing only in comments and layout share. See
+    /// `digest::ProgramDigest`.
+    pub fn digest(&self) -> ProgramDigest {
+        ProgramDigest::new(self)
+    }
+
+    /// How the brackets of the program were validated when it was created.
+    pub fn bracket_validation(&self) -> BracketValidation {
+        self.bracket_validation
+    }
+
+    /// Builds the tree of loops in the program, with one node for each of the
+    /// outermost loops. Unmatched brackets in lazily validated programs are
+    /// not part of any loop.
+    /// ```
+    /// use bft_types::BfProgram;
+    /// let program = BfProgram::new("[[-]>[-]]+[]".to_string(), "test.bf").unwrap();
+    /// let loops = program.loops();
+    /// assert_eq!(loops.len(), 2);
+    /// assert_eq!((loops[0].start(), loops[0].end()), (0, 8));
+    /// assert_eq!(loops[0].children().len(), 2);
+    /// assert_eq!(loops[0].children()[1].span(), 5..8);
+    /// assert!(loops[1].children().is_empty());
+    /// ```
+    pub fn loops(&self) -> Vec<LoopNode> {
+        let mut roots: Vec<LoopNode> = Vec::new();
+        let mut open_loops: Vec<LoopNode> = Vec::new();
+        for (position, instruction) in self.iter().enumerate() {
+            match instruction.operation() {
+                Operation::StartLoop => {
+                    if let Some(end) = self.jump_target(position) {
+                        open_loops.push(LoopNode::new(position, end));
+                    }
+                }
+                Operation::EndLoop
+                    if open_loops.last().map(LoopNode::end)
+                        == Some(position) =>
+                {
+                    let finished = open_loops.pop().unwrap();
+                    match open_loops.last_mut() {
+                        Some(parent) => parent.push_child(finished),
+                        None => roots.push(finished),
+                    }
+                }
+                _ => {}
+            }
+        }
+        roots
+    }
+
+    /// Extracts the given range of instructions as a program of its own,
+    /// keeping the original positions of the instructions. The range must
+    /// contain balanced brackets, otherwise an `UnmatchedBracket` error is
+    /// returned.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the range is out of bounds for the instructions of the
+    /// program, as with slicing.
+    /// ```
+    /// use bft_types::BfProgram;
+    /// let program = BfProgram::new("+[->+<]>.".to_string(), "test.bf").unwrap();
+    /// let loop_only = program.slice(1..7).unwrap();
+    /// assert_eq!(loop_only, BfProgram::new("[->+<]".to_string(), "other.bf").unwrap());
+    /// assert_eq!(loop_only.instructions()[0].column(), 2);
+    /// assert!(program.slice(0..3).is_err());
+    /// ```
+    pub fn slice<R>(
+        &self,
+        range: R,
+    ) -> Result<BfProgram, vm_error::VirtualMachineError>
+    where
+        R: RangeBounds<usize>,
+    {
+        let bounds: (Bound<usize>, Bound<usize>) =
+            (range.start_bound().cloned(), range.end_bound().cloned());
+        BfProgram::from_instructions(
+            self.instructions[bounds].to_vec(),
+            self.filename.clone(),
+            BracketValidation::Strict,
+            self.max_nesting,
+        )
+    }
+
+    /// Creates a copy of the program with its comments stripped out, as if
+    /// all of its instructions had been written on a single line with nothing
+    /// in between them.
+    /// ```
+    /// use bft_types::BfProgram;
+    /// let program = BfProgram::new("a comment\n+ -".to_string(), "test.bf").unwrap();
+    /// let normalized = program.normalized();
+    /// assert_eq!(normalized, program);
+    /// assert_eq!(normalized.instructions()[1].line(), 1);
+    /// assert_eq!(normalized.instructions()[1].column(), 2);
+    /// ```
+    pub fn normalized(&self) -> BfProgram {
+        let instructions: Vec<InstructionInfo> = self
+            .instructions
+            .iter()
+            .enumerate()
+            .map(|(n, instruction)| {
+                InstructionInfo::new(instruction.operation(), 1, n + 1)
+            })
+            .collect();
+        let mut line_index = HashMap::new();
+        if !instructions.is_empty() {
+            line_index.insert(1, 0..instructions.len());
+        }
+        // Only the positions change, so the brackets pair up exactly as they
+        // did before. Any other problems with the source were in its
+        // comments, which are gone.
+        let mut normalized = BfProgram {
+            instructions,
+            filename: self.filename.clone(),
+            jump_table: self.jump_table.clone(),
+            bracket_pairs: OnceLock::new(),
+            bracket_validation: self.bracket_validation,
+            line_index,
+            bracket_time: self.bracket_time,
+            source_chars: self.instructions.len(),
+            max_nesting: self.max_nesting,
+            profile: SourceProfile::from_commands(self.instructions.len()),
+            comments: CommentIndex::default(),
+            diagnostics: Vec::new(),
+        };
+        if self.bracket_validation == BracketValidation::Recover {
+            normalized.diagnostics =
+                normalized.unmatched_brackets(&normalized.jump_table);
+        }
+        normalized
+    }
+
+    /// The problems with the source of the program, in the order they appear
+    /// in it, if it was created with `BracketValidation::Recover`. Otherwise
+    /// any problem stops the program from being created, apart from the
+    /// unmatched brackets of lazily validated programs, so there are none.
+    /// ```
+    /// use bft_types::options::{BracketValidation, CommentPolicy};
+    /// use bft_types::{options::ParseOptions, BfProgram};
+    ///
+    /// let options = ParseOptions::new()
+    ///     .bracket_validation(BracketValidation::Recover)
+    ///     .comments(CommentPolicy::Delimited);
+    /// let source = "+[->+< oops\n]] {".to_string();
+    /// let program = BfProgram::new_with_options(source, "edit.bf", &options);
+    /// let program = program.unwrap();
+    /// let problems: Vec<_> = program
+    ///     .diagnostics()
+    ///     .iter()
+    ///     .map(|error| error.location().unwrap())
+    ///     .collect();
+    /// assert_eq!(problems, [(1, 8), (2, 2), (2, 4)]);
+    /// assert_eq!(program.jump_target(1), Some(6));
+    /// ```
+    pub fn diagnostics(&self) -> &[vm_error::VirtualMachineError] {
+        &self.diagnostics
+    }
+
+    /// The errors for each bracket of the program left unpaired by the given
+    /// table of matching brackets.
+    fn unmatched_brackets(
+        &self,
+        jump_table: &JumpTable,
+    ) -> Vec<vm_error::VirtualMachineError> {
+        self.instructions
+            .iter()
+            .enumerate()
+            .filter(|&(position, instruction)| {
+                matches!(
+                    instruction.operation(),
+                    Operation::StartLoop | Operation::EndLoop
+                ) && jump_table.target(position) == position
+            })
+            .map(|(_, instruction)| {
+                vm_error::VirtualMachineError::UnmatchedBracket {
+                    bracket: instruction.operation().to_char(),
+                    line: instruction.line(),
+                    column: instruction.column(),
+                }
+            })
+            .collect()
     }
 
     /// Checks the program for brackets which can be paired, these will later
@@ -192,53 +793,398 @@ impl BfProgram {
     /// let unbalanced_program = BfProgram::new(contents, filename);
     /// assert!(unbalanced_program.is_err());
     /// ```
+    #[deprecated(note = "use `checked_jump_table`, which avoids the hashmap")]
     pub fn bracket_check(
         &self,
     ) -> Result<HashMap<usize, usize>, vm_error::VirtualMachineError> {
+        Ok(self.checked_jump_table()?.pairs().collect())
+    }
+
+    /// Checks the brackets of the program as `bracket_check` does, giving the
+    /// table of matching brackets rather than a hashmap.
+    /// ```
+    /// # use bft_types::BfProgram;
+    /// let program = BfProgram::new("[]".to_string(), "test.bf").unwrap();
+    /// let jump_table = program.checked_jump_table().unwrap();
+    /// assert_eq!(jump_table.jump_target(0), Some(1));
+    /// assert_eq!(jump_table.jump_target(1), Some(0));
+    /// ```
+    pub fn checked_jump_table(
+        &self,
+    ) -> Result<JumpTable, vm_error::VirtualMachineError> {
+        match self.pair_brackets()? {
+            (jump_table, None) => Ok(jump_table),
+            (_, Some(error)) => Err(error),
+        }
+    }
+
+    /// Pairs up the matching brackets of the program, returning the positions
+    /// of each pair along with the error for the first unmatched bracket
+    /// found, if there is one. Unmatched brackets are skipped over rather than
+    /// stopping the pairing, so that lazily validated programs still know
+    /// about all of their matched brackets. Loops nested too deeply stop the
+    /// pairing straight away, however the brackets are validated.
+    fn pair_brackets(
+        &self,
+    ) -> Result<
+        (JumpTable, Option<vm_error::VirtualMachineError>),
+        vm_error::VirtualMachineError,
+    > {
         let mut bracket_stack: Vec<usize> = Vec::new();
-        let mut matching_bracket_positions: HashMap<usize, usize> =
-            HashMap::new();
+        let mut jump_table = JumpTable::new(self.instructions.len());
+        let mut first_error: Option<vm_error::VirtualMachineError> = None;
 
-        // Line number of the most recent opening bracket.
-        let mut latest_line: usize = 0;
-        // Column number of the most recent opening bracket.
-        let mut latest_column: usize = 0;
         for (position, instruction) in self.instructions().iter().enumerate() {
             match instruction.operation() {
                 Operation::StartLoop => {
                     // If we have an opening bracket, then we should add it to
-                    // the stack
+                    // the stack, as long as it is not nested too deeply
                     bracket_stack.push(position);
-                    latest_line = instruction.line();
-                    latest_column = instruction.column();
-                }
-                Operation::EndLoop => {
-                    if let Some(p) = bracket_stack.last() {
-                        matching_bracket_positions.insert(*p, position);
+                    if bracket_stack.len() > self.max_nesting {
+                        return Err(
+                            vm_error::VirtualMachineError::NestingTooDeep {
+                                depth: bracket_stack.len(),
+                                limit: self.max_nesting,
+                                line: instruction.line(),
+                                column: instruction.column(),
+                            },
+                        );
                     }
-                    // If there are too many closing brackets, then popping
-                    // will cause an error which we should percolate up.
-                    bracket_stack.pop().ok_or(
-                        vm_error::VirtualMachineError::UnmatchedBracket {
-                            bracket: ']',
-                            line: instruction.line(),
-                            column: instruction.column(),
-                        },
-                    )?;
                 }
+                Operation::EndLoop => match bracket_stack.pop() {
+                    Some(p) => {
+                        jump_table.insert(p, position);
+                    }
+                    // If there are too many closing brackets, then the stack
+                    // will be empty, which is an error we should percolate up.
+                    None => {
+                        first_error.get_or_insert(
+                            vm_error::VirtualMachineError::UnmatchedBracket {
+                                bracket: ']',
+                                line: instruction.line(),
+                                column: instruction.column(),
+                            },
+                        );
+                    }
+                },
                 _ => {}
             }
         }
 
         // If the bracket stack is not empty after the full loop, then this is
-        // due to there being too many opening brackets
-        if !bracket_stack.is_empty() {
-            return Err(vm_error::VirtualMachineError::UnmatchedBracket {
-                bracket: '[',
-                line: latest_line,
-                column: latest_column,
-            });
-        }
-        Ok(matching_bracket_positions)
+        // due to there being too many opening brackets. The most recent one is
+        // reported.
+        if let Some(p) = bracket_stack.last() {
+            let instruction = self.instructions()[*p];
+            first_error.get_or_insert(
+                vm_error::VirtualMachineError::UnmatchedBracket {
+                    bracket: '[',
+                    line: instruction.line(),
+                    column: instruction.column(),
+                },
+            );
+        }
+        Ok((jump_table, first_error))
+    }
+}
+
+impl PartialEq for BfProgram {
+    fn eq(&self, other: &Self) -> bool {
+        self.instructions.len() == other.instructions.len()
+            && self
+                .iter()
+                .zip(other)
+                .all(|(a, b)| a.operation() == b.operation())
+    }
+}
+
+impl Eq for BfProgram {}
+
+impl Hash for BfProgram {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.instructions.len().hash(state);
+        for instruction in self {
+            instruction.operation().hash(state);
+        }
+    }
+}
+
+impl<'a> IntoIterator for &'a BfProgram {
+    type Item = &'a InstructionInfo;
+    type IntoIter = std::slice::Iter<'a, InstructionInfo>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ops::Operation;
+    use super::options::{BracketValidation, CommentPolicy, ParseOptions};
+    use super::vm_error::VirtualMachineError;
+    use super::{BfProgram, InstructionInfo};
+    use std::collections::HashSet;
+    use std::path::Path;
+
+    #[test]
+    fn test_equality_ignores_comments_and_filename() {
+        let a = BfProgram::new(String::from("+>.<"), "a.bf").unwrap();
+        let b =
+            BfProgram::new(String::from("add +\nright >\n.<"), "b.bf").unwrap();
+        let c = BfProgram::new(String::from("+>.<-"), "a.bf").unwrap();
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+    }
+
+    #[test]
+    fn test_hash_deduplicates_programs() {
+        let programs = [
+            BfProgram::new(String::from("[-]"), "1.bf").unwrap(),
+            BfProgram::new(String::from("clear [ - ]"), "2.bf").unwrap(),
+            BfProgram::new(String::from("[+]"), "3.bf").unwrap(),
+        ];
+        let unique: HashSet<&BfProgram> = programs.iter().collect();
+        assert_eq!(unique.len(), 2);
+    }
+
+    #[test]
+    fn test_loops_of_lazy_program() {
+        let options =
+            ParseOptions::new().bracket_validation(BracketValidation::Lazy);
+        let program = BfProgram::new_with_options(
+            String::from("][[-]"),
+            "test.bf",
+            &options,
+        )
+        .unwrap();
+        let loops = program.loops();
+        assert_eq!(loops.len(), 1);
+        assert_eq!(loops[0].span(), 2..5);
+        assert_eq!(loops[0].depth(), 1);
+    }
+
+    #[test]
+    fn test_recover() {
+        let options = ParseOptions::new()
+            .bracket_validation(BracketValidation::Recover)
+            .comments(CommentPolicy::Delimited);
+        let source = "]+\n[- {unclosed".to_string();
+        let program =
+            BfProgram::new_with_options(source.clone(), "edit.bf", &options)
+                .unwrap();
+        assert_eq!(program.instructions().len(), 4);
+        let kinds: Vec<_> = program
+            .diagnostics()
+            .iter()
+            .map(|error| error.kind())
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                "unmatched_bracket",
+                "unmatched_bracket",
+                "unterminated_comment"
+            ]
+        );
+        // Without the comment, only the brackets are still a problem, at
+        // their new positions.
+        let normalized = program.normalized();
+        let locations: Vec<_> = normalized
+            .diagnostics()
+            .iter()
+            .map(|error| error.location())
+            .collect();
+        assert_eq!(locations, [Some((1, 1)), Some((1, 3))]);
+        // The same source is refused outright otherwise.
+        let strict = ParseOptions::new().comments(CommentPolicy::Delimited);
+        assert!(
+            BfProgram::new_with_options(source, "edit.bf", &strict).is_err()
+        );
+        assert!(BfProgram::new("+[-]".to_string(), "ok.bf")
+            .unwrap()
+            .diagnostics()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_nesting_too_deep() {
+        let deep = format!("+\n{}{}", "[".repeat(4), "]".repeat(4));
+        for validation in [BracketValidation::Strict, BracketValidation::Lazy] {
+            let options = ParseOptions::new()
+                .bracket_validation(validation)
+                .max_nesting(3);
+            let result =
+                BfProgram::new_with_options(deep.clone(), "deep.bf", &options);
+            assert!(matches!(
+                result,
+                Err(VirtualMachineError::NestingTooDeep {
+                    depth: 4,
+                    limit: 3,
+                    line: 2,
+                    column: 4,
+                })
+            ));
+        }
+        // Generated programs far deeper than anything written by hand are
+        // still allowed by default.
+        let generated = format!("{}{}", "[".repeat(5000), "]".repeat(5000));
+        assert!(BfProgram::new(generated, "generated.bf").is_ok());
+    }
+
+    #[test]
+    fn test_program_too_large() {
+        let options = ParseOptions::new().max_program_size(8);
+        let result = BfProgram::new_with_options(
+            "+ too big -".to_string(),
+            "big.bf",
+            &options,
+        );
+        assert!(matches!(
+            result,
+            Err(VirtualMachineError::ProgramTooLarge {
+                size: 11,
+                limit: 8,
+                ..
+            })
+        ));
+        // Files are checked before they are read, here with a file which is
+        // certainly not Brainfuck.
+        let manifest = Path::new(env!("CARGO_MANIFEST_DIR")).join("Cargo.toml");
+        let error =
+            BfProgram::from_file_with_options(&manifest, &options).unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<VirtualMachineError>(),
+            Some(VirtualMachineError::ProgramTooLarge { limit: 8, .. })
+        ));
+        assert!(BfProgram::from_file(&manifest).is_ok());
+    }
+
+    #[test]
+    fn test_delimited_comments() {
+        let options = ParseOptions::new()
+            .comments(CommentPolicy::Delimited)
+            .extension('#');
+        let source = "#!/usr/bin/env bft\n\
+                      { Clears a cell, then prints it. }\n\
+                      +[-] ; clear, with [brackets] ignored\n\
+                      \t.# {dump} ;"
+            .to_string();
+        let program =
+            BfProgram::new_with_options(source, "clean.bf", &options).unwrap();
+        let positions: Vec<(Operation, usize, usize)> = program
+            .iter()
+            .map(|i| (i.operation(), i.line(), i.column()))
+            .collect();
+        assert_eq!(
+            positions,
+            vec![
+                (Operation::IncrementByte, 3, 1),
+                (Operation::StartLoop, 3, 2),
+                (Operation::DecrementByte, 3, 3),
+                (Operation::EndLoop, 3, 4),
+                (Operation::OutputByte, 4, 2),
+                (Operation::Extension('#'), 4, 3),
+            ]
+        );
+
+        let stray = BfProgram::new_with_options(
+            "+ ; fine\n- oops".to_string(),
+            "stray.bf",
+            &options,
+        );
+        assert!(matches!(
+            stray,
+            Err(VirtualMachineError::StrayCharacter {
+                character: 'o',
+                line: 2,
+                column: 3,
+            })
+        ));
+        let unterminated = BfProgram::new_with_options(
+            "+\n  { never closed\n-".to_string(),
+            "open.bf",
+            &options,
+        );
+        assert!(matches!(
+            unterminated,
+            Err(VirtualMachineError::UnterminatedComment {
+                line: 2,
+                column: 3
+            })
+        ));
+        // The same sources are fine when any character may be a comment.
+        assert!(BfProgram::new("+ ; fine\n- oops".to_string(), "ok.bf").is_ok());
+    }
+
+    #[test]
+    fn test_empty_program() {
+        let program =
+            BfProgram::new("only comments\n".to_string(), "empty.bf").unwrap();
+        assert!(program.instructions().is_empty());
+        assert!(program.loops().is_empty());
+    }
+
+    #[test]
+    fn test_slice_of_loop_tree() {
+        let program =
+            BfProgram::new(String::from("++[>[-<+>]<-]"), "test.bf").unwrap();
+        let outer = &program.loops()[0];
+        assert_eq!(outer.depth(), 2);
+        let inner = &outer.children()[0];
+        let body = program.slice(inner.body()).unwrap();
+        assert_eq!(body, BfProgram::new(String::from("-<+>"), "b.bf").unwrap());
+        let whole = program.slice(..).unwrap();
+        assert_eq!(whole, program);
+    }
+
+    #[test]
+    fn test_normalized_keeps_brackets() {
+        let program =
+            BfProgram::new(String::from("x[\n y[-]\n]"), "test.bf").unwrap();
+        let normalized = program.normalized();
+        assert_eq!(normalized.jump_table(), program.jump_table());
+        assert_eq!(normalized.instructions_in_line(1).len(), 5);
+        assert!(normalized.instruction_at(1, 5).is_some());
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn test_bracket_matching_positions_from_jump_table() {
+        let options =
+            ParseOptions::new().bracket_validation(BracketValidation::Lazy);
+        let program = BfProgram::new_with_options(
+            String::from("][[-]"),
+            "lazy.bf",
+            &options,
+        )
+        .unwrap();
+        let positions = program.bracket_matching_positions();
+        assert_eq!(positions.len(), 1);
+        assert_eq!(positions[&2], 4);
+        assert_eq!(program.jump_target(0), None);
+        assert_eq!(program.jump_target(1), None);
+    }
+
+    #[test]
+    fn test_extensions_parsed() {
+        let options = ParseOptions::new().extension('#');
+        let program = BfProgram::new_with_options(
+            String::from("+#!-"),
+            "ext.bf",
+            &options,
+        )
+        .unwrap();
+        let operations: Vec<Operation> =
+            program.iter().map(InstructionInfo::operation).collect();
+        assert_eq!(
+            operations,
+            [
+                Operation::IncrementByte,
+                Operation::Extension('#'),
+                Operation::DecrementByte
+            ]
+        );
+        assert_eq!(program.instructions()[1].column(), 2);
     }
 }
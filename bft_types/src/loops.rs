@@ -0,0 +1,66 @@
+//! The loop structure of a Brainfuck program, as a tree of the loops and the
+//! loops nested within them.
+
+use std::ops::Range;
+
+/// A single loop within a Brainfuck program, along with the loops nested
+/// directly inside of it.
+///
+/// The positions are indices into the instructions of the program, so they
+/// skip over any comments.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoopNode {
+    /// The position of the opening `[` of the loop.
+    start: usize,
+    /// The position of the closing `]` of the loop.
+    end: usize,
+    /// The loops nested directly within this loop, in program order.
+    children: Vec<LoopNode>,
+}
+
+impl LoopNode {
+    pub(crate) fn new(start: usize, end: usize) -> Self {
+        Self {
+            start,
+            end,
+            children: Vec::new(),
+        }
+    }
+
+    pub(crate) fn push_child(&mut self, child: LoopNode) {
+        self.children.push(child);
+    }
+
+    /// Retrieves the position of the opening `[` of the loop.
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    /// Retrieves the position of the closing `]` of the loop.
+    pub fn end(&self) -> usize {
+        self.end
+    }
+
+    /// Retrieves the loops nested directly within this loop.
+    pub fn children(&self) -> &[LoopNode] {
+        &self.children
+    }
+
+    /// The range of positions covered by the loop, including both of its
+    /// brackets.
+    pub fn span(&self) -> Range<usize> {
+        self.start..self.end + 1
+    }
+
+    /// The range of positions of the instructions inside the loop, excluding
+    /// its brackets.
+    pub fn body(&self) -> Range<usize> {
+        self.start + 1..self.end
+    }
+
+    /// How deeply nested the deepest loop within this one is, counting this
+    /// loop itself as a depth of 1.
+    pub fn depth(&self) -> usize {
+        1 + self.children.iter().map(LoopNode::depth).max().unwrap_or(0)
+    }
+}
@@ -0,0 +1,240 @@
+//! A map of the tape, naming the regions of it which a program uses for each
+//! purpose, so that cells can be shown by name and offset rather than by
+//! their raw index.
+//!
+//! Maps are usually kept in a sidecar file next to the program, with one
+//! region on each line, and `#` starting a comment:
+//!
+//! ```text
+//! # The loop counters, then the line being read.
+//! 0..8 = "counters"
+//! 8..40 = "buffer"
+//! ```
+
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::ops::Range;
+use std::path::Path;
+
+use thiserror::Error;
+
+/// A named region of the tape.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Region {
+    /// The cells in the region.
+    range: Range<usize>,
+    /// What the region is called.
+    name: String,
+}
+
+impl Region {
+    /// The cells in the region.
+    pub fn range(&self) -> Range<usize> {
+        self.range.clone()
+    }
+
+    /// What the region is called.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// An error from building or parsing a memory map.
+#[derive(Debug, Clone, Error, PartialEq, Eq)]
+pub enum MemoryMapError {
+    /// A line of a map is not a region.
+    #[error(
+        "line {line} of the memory map is not of the form 0..8 = \"name\""
+    )]
+    Syntax {
+        /// The line of the map, starting from 1
+        line: usize,
+    },
+
+    /// A region has no cells in it.
+    #[error("the region \"{name}\" has no cells")]
+    Empty {
+        /// The name of the region
+        name: String,
+    },
+
+    /// Two regions share some cells.
+    #[error("the regions \"{name}\" and \"{other}\" overlap")]
+    Overlap {
+        /// The name of the region being added
+        name: String,
+        /// The name of the region it overlaps
+        other: String,
+    },
+}
+
+/// The named regions of a tape, which never overlap. Cells outside of every
+/// region are left unnamed.
+/// ```
+/// use bft_types::memory::MemoryMap;
+///
+/// let map: MemoryMap = "0..8 = \"counters\"\n8..40 = \"buffer\"".parse().unwrap();
+/// assert_eq!(map.name_of(3), "counters+3");
+/// assert_eq!(map.name_of(8), "buffer");
+/// assert_eq!(map.name_of(40), "40");
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MemoryMap {
+    /// The regions, in order along the tape.
+    regions: Vec<Region>,
+}
+
+impl MemoryMap {
+    /// Creates a map with no regions.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a region to the map, failing if it is empty or overlaps a region
+    /// already in the map.
+    pub fn with_region(
+        mut self,
+        range: Range<usize>,
+        name: impl Into<String>,
+    ) -> Result<Self, MemoryMapError> {
+        let name = name.into();
+        if range.is_empty() {
+            return Err(MemoryMapError::Empty { name });
+        }
+        if let Some(other) = self.regions.iter().find(|region| {
+            region.range.start < range.end && range.start < region.range.end
+        }) {
+            return Err(MemoryMapError::Overlap {
+                name,
+                other: other.name.clone(),
+            });
+        }
+        let at = self
+            .regions
+            .partition_point(|region| region.range.start < range.start);
+        self.regions.insert(at, Region { range, name });
+        Ok(self)
+    }
+
+    /// Reads a map from a sidecar file.
+    pub fn from_file<P>(filename: P) -> Result<MemoryMap, Box<dyn Error>>
+    where
+        P: AsRef<Path>,
+    {
+        Ok(fs::read_to_string(filename)?.parse()?)
+    }
+
+    /// The regions, in order along the tape.
+    pub fn regions(&self) -> &[Region] {
+        &self.regions
+    }
+
+    /// The region the cell at the index is in, if any.
+    pub fn region_of(&self, index: usize) -> Option<&Region> {
+        let at = self
+            .regions
+            .partition_point(|region| region.range.end <= index);
+        self.regions
+            .get(at)
+            .filter(|region| region.range.contains(&index))
+    }
+
+    /// The symbolic name of the cell at the index: the name of its region,
+    /// followed by its offset into the region if it is not the first cell, or
+    /// just the index if it is in no region.
+    pub fn name_of(&self, index: usize) -> String {
+        match self.region_of(index) {
+            Some(region) if index == region.range.start => region.name.clone(),
+            Some(region) => {
+                format!("{}+{}", region.name, index - region.range.start)
+            }
+            None => index.to_string(),
+        }
+    }
+}
+
+impl std::str::FromStr for MemoryMap {
+    type Err = MemoryMapError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut map = MemoryMap::new();
+        for (number, line) in (1..).zip(s.lines()) {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let syntax = MemoryMapError::Syntax { line: number };
+            let (range, name) = line.split_once('=').ok_or(syntax.clone())?;
+            let (start, end) =
+                range.trim().split_once("..").ok_or(syntax.clone())?;
+            let start = start.trim().parse().map_err(|_| syntax.clone())?;
+            let end = end.trim().parse().map_err(|_| syntax.clone())?;
+            let name = name.trim();
+            let name = name
+                .strip_prefix('"')
+                .and_then(|name| name.strip_suffix('"'))
+                .unwrap_or(name);
+            if name.is_empty() {
+                return Err(syntax);
+            }
+            map = map.with_region(start..end, name)?;
+        }
+        Ok(map)
+    }
+}
+
+impl fmt::Display for MemoryMap {
+    /// Writes the map in the form it is parsed from.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for region in &self.regions {
+            writeln!(
+                f,
+                "{}..{} = \"{}\"",
+                region.range.start, region.range.end, region.name
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MemoryMap, MemoryMapError};
+
+    #[test]
+    fn test_parse() {
+        let map: MemoryMap = "# Scratch space first.\n\
+            \n\
+            8..40 = \"buffer\" # the line being read\n\
+            0..8 = counters\n"
+            .parse()
+            .unwrap();
+        let names: Vec<&str> =
+            map.regions().iter().map(|region| region.name()).collect();
+        assert_eq!(names, ["counters", "buffer"]);
+        assert_eq!(map.region_of(39).unwrap().range(), 8..40);
+        assert_eq!(map.to_string().parse(), Ok(map));
+    }
+
+    #[test]
+    fn test_invalid_maps() {
+        assert_eq!(
+            "0..8 = \"a\"\n4..10 = \"b\"".parse::<MemoryMap>(),
+            Err(MemoryMapError::Overlap {
+                name: "b".to_string(),
+                other: "a".to_string()
+            })
+        );
+        assert_eq!(
+            "3..3 = \"none\"".parse::<MemoryMap>(),
+            Err(MemoryMapError::Empty {
+                name: "none".to_string()
+            })
+        );
+        assert_eq!(
+            "0..8 = \"a\"\n0-8 = \"b\"".parse::<MemoryMap>(),
+            Err(MemoryMapError::Syntax { line: 2 })
+        );
+    }
+}
@@ -4,9 +4,24 @@
 //! this enum.
 
 use std::fmt;
+use std::str::FromStr;
+
+use thiserror::Error;
+
+/// Every Brainfuck operation, in the order they are declared.
+const ALL_OPERATIONS: [Operation; 8] = [
+    Operation::IncrementPointer,
+    Operation::DecrementPointer,
+    Operation::IncrementByte,
+    Operation::DecrementByte,
+    Operation::OutputByte,
+    Operation::InputByte,
+    Operation::StartLoop,
+    Operation::EndLoop,
+];
 
 /// Raw Brainfuck Instruction
-#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
 pub enum Operation {
     /// Represents the `>` character
     IncrementPointer,
@@ -24,12 +39,16 @@ pub enum Operation {
     StartLoop,
     /// Represents the `]` character
     EndLoop,
+    /// Represents a character which is not part of Brainfuck, but which was
+    /// registered as an extension in the `ParseOptions` used to parse the
+    /// program. What it does is up to the Virtual Machine running it.
+    Extension(char),
 }
 
 impl Operation {
     /// Converts a character in a Brainfuck program into a raw instruction.
     /// Returns None if the character is not a valid Brainfuck instruction.
-    pub fn char_to_operation(c: char) -> Option<Operation> {
+    pub const fn char_to_operation(c: char) -> Option<Operation> {
         match c {
             '>' => Some(Operation::IncrementPointer),
             '<' => Some(Operation::DecrementPointer),
@@ -42,6 +61,101 @@ impl Operation {
             _ => None,
         }
     }
+
+    /// Converts a raw instruction back into the character representing it in
+    /// a Brainfuck program.
+    /// ```
+    /// use bft_types::ops::Operation;
+    /// assert_eq!(Operation::StartLoop.to_char(), '[');
+    /// ```
+    pub fn to_char(&self) -> char {
+        match self {
+            Operation::IncrementPointer => '>',
+            Operation::DecrementPointer => '<',
+            Operation::IncrementByte => '+',
+            Operation::DecrementByte => '-',
+            Operation::OutputByte => '.',
+            Operation::InputByte => ',',
+            Operation::StartLoop => '[',
+            Operation::EndLoop => ']',
+            Operation::Extension(c) => *c,
+        }
+    }
+
+    /// Every standard Brainfuck operation, useful for generating programs and
+    /// for building lookup tables. Extensions are not included.
+    /// ```
+    /// use bft_types::ops::Operation;
+    /// let source: String = Operation::all().iter().map(|op| op.to_char()).collect();
+    /// assert_eq!(source, "><+-.,[]");
+    /// ```
+    pub fn all() -> &'static [Operation] {
+        &ALL_OPERATIONS
+    }
+
+    /// Wraps the operation so that it is displayed in its compact form, the
+    /// single character which represents it, rather than with the explanation
+    /// given by its usual `Display` implementation.
+    /// ```
+    /// use bft_types::ops::Operation;
+    /// assert_eq!(Operation::OutputByte.compact().to_string(), ".");
+    /// ```
+    pub fn compact(&self) -> CompactOperation {
+        CompactOperation(*self)
+    }
+}
+
+/// An error from trying to turn a character, or a string, into an operation.
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum ParseOperationError {
+    /// The character is not one of the eight Brainfuck commands.
+    #[error("'{0}' is not a Brainfuck operation")]
+    NotAnOperation(char),
+
+    /// The string does not consist of exactly one character.
+    #[error("expected a single character, found \"{0}\"")]
+    NotASingleCharacter(String),
+}
+
+impl TryFrom<char> for Operation {
+    type Error = ParseOperationError;
+
+    fn try_from(c: char) -> Result<Self, Self::Error> {
+        Operation::char_to_operation(c)
+            .ok_or(ParseOperationError::NotAnOperation(c))
+    }
+}
+
+impl FromStr for Operation {
+    type Err = ParseOperationError;
+
+    /// Parses the compact, single character, form of an operation.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut chars = s.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => Operation::try_from(c),
+            _ => Err(ParseOperationError::NotASingleCharacter(s.to_string())),
+        }
+    }
+}
+
+/// An operation displayed in its compact form, as the single character which
+/// represents it in a Brainfuck program. Created by `Operation::compact()`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct CompactOperation(pub Operation);
+
+impl fmt::Display for CompactOperation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0.to_char())
+    }
+}
+
+impl FromStr for CompactOperation {
+    type Err = ParseOperationError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse().map(CompactOperation)
+    }
 }
 
 impl fmt::Display for Operation {
@@ -55,13 +169,14 @@ impl fmt::Display for Operation {
             Operation::InputByte => write!(f, ", : Accepts a byte of input, and stores the value at the current data pointer."),
             Operation::StartLoop => write!(f, "[ : Starts a loop."),
             Operation::EndLoop => write!(f, "] : Ends a loop."),
+            Operation::Extension(c) => write!(f, "{} : A custom extension instruction.", c),
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::Operation;
+    use super::{CompactOperation, Operation, ParseOperationError};
 
     #[test]
     fn test_display_increment_pointer() {
@@ -125,4 +240,49 @@ mod tests {
         let end_loop: Operation = Operation::EndLoop;
         assert_eq!(end_loop.to_string(), "] : Ends a loop.");
     }
+
+    #[test]
+    fn test_display_extension() {
+        let extension: Operation = Operation::Extension('#');
+        assert_eq!(
+            extension.to_string(),
+            "# : A custom extension instruction."
+        );
+        assert_eq!(extension.compact().to_string(), "#");
+    }
+
+    #[test]
+    fn test_char_round_trip() {
+        for operation in Operation::all() {
+            assert_eq!(
+                Operation::try_from(operation.to_char()),
+                Ok(*operation)
+            );
+        }
+    }
+
+    #[test]
+    fn test_compact_round_trip() {
+        for operation in Operation::all() {
+            let compact = operation.compact().to_string();
+            assert_eq!(compact.parse::<Operation>(), Ok(*operation));
+            assert_eq!(
+                compact.parse::<CompactOperation>(),
+                Ok(CompactOperation(*operation))
+            );
+        }
+    }
+
+    #[test]
+    fn test_invalid_operations() {
+        assert_eq!(
+            Operation::try_from('a'),
+            Err(ParseOperationError::NotAnOperation('a'))
+        );
+        assert_eq!(
+            "++".parse::<Operation>(),
+            Err(ParseOperationError::NotASingleCharacter("++".to_string()))
+        );
+        assert!("".parse::<Operation>().is_err());
+    }
 }
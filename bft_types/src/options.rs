@@ -0,0 +1,227 @@
+//! Options controlling how the source of a Brainfuck program is turned into a
+//! `BfProgram`.
+
+use std::path::Path;
+
+use crate::ops::Operation;
+use crate::vm_error::VirtualMachineError;
+
+/// How strictly the brackets of a program are validated when it is created.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BracketValidation {
+    /// Every bracket must be matched when the program is created, otherwise
+    /// creating the program fails.
+    #[default]
+    Strict,
+    /// Unmatched brackets are allowed when the program is created, and only
+    /// cause an error if execution reaches one of them. Useful for REPL
+    /// fragments and for programs assembled from several chunks.
+    Lazy,
+    /// As with `Lazy`, but rather than failing on any other problem with the
+    /// source, such as a stray character outside of a delimited comment, the
+    /// program is still created, and every problem including the unmatched
+    /// brackets is recorded in `BfProgram::diagnostics()`. Useful for tools
+    /// such as editors which work on programs part way through being written.
+    Recover,
+}
+
+/// Which characters of a program, other than commands and whitespace, are
+/// allowed as comments.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CommentPolicy {
+    /// Any character which is not a command is a comment, as in standard
+    /// Brainfuck.
+    #[default]
+    Anywhere,
+    /// Comments must be delimited, either from `;` to the end of the line or
+    /// between `{` and `}`, and any other character outside of them is an
+    /// error. Commands inside comments are ignored, so comments can use any
+    /// punctuation, while characters registered as extensions are always
+    /// commands.
+    /// ```
+    /// use bft_types::BfProgram;
+    /// use bft_types::options::{CommentPolicy, ParseOptions};
+    /// let options = ParseOptions::new().comments(CommentPolicy::Delimited);
+    /// let source = "{ add, then print. }\n++ ; two\n.".to_string();
+    /// let program = BfProgram::new_with_options(source, "add.bf", &options);
+    /// assert_eq!(program.unwrap().instructions().len(), 3);
+    /// let stray = BfProgram::new_with_options("+ two".to_string(), "add.bf", &options);
+    /// assert!(stray.is_err());
+    /// ```
+    Delimited,
+}
+
+/// The character which starts a comment running to the end of the line, when
+/// comments must be delimited.
+pub const LINE_COMMENT: char = ';';
+
+/// The characters which open and close a block comment, when comments must be
+/// delimited.
+pub const BLOCK_COMMENT: (char, char) = ('{', '}');
+
+/// The deepest loops may be nested by default, which is far deeper than any
+/// program written by hand.
+pub const DEFAULT_MAX_NESTING: usize = 10_000;
+
+/// The set of options used when parsing a Brainfuck program.
+///
+/// ```
+/// use bft_types::options::{BracketValidation, ParseOptions};
+/// let options = ParseOptions::new().bracket_validation(BracketValidation::Lazy);
+/// assert_eq!(options.brackets(), BracketValidation::Lazy);
+/// ```
+#[derive(Debug, Clone)]
+pub struct ParseOptions {
+    /// How the brackets of the program should be validated.
+    bracket_validation: BracketValidation,
+    /// The characters which are parsed as extension instructions, rather
+    /// than being ignored as comments.
+    extensions: Vec<char>,
+    /// Which characters are allowed as comments.
+    comment_policy: CommentPolicy,
+    /// The deepest loops may be nested.
+    max_nesting: usize,
+    /// The largest the source of the program may be, in bytes, if limited.
+    max_size: Option<u64>,
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        Self {
+            bracket_validation: BracketValidation::default(),
+            extensions: Vec::new(),
+            comment_policy: CommentPolicy::default(),
+            max_nesting: DEFAULT_MAX_NESTING,
+            max_size: None,
+        }
+    }
+}
+
+impl ParseOptions {
+    /// Creates the default set of parse options, which match the behaviour of
+    /// `BfProgram::new()`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets how the brackets of the program should be validated.
+    pub fn bracket_validation(mut self, validation: BracketValidation) -> Self {
+        self.bracket_validation = validation;
+        self
+    }
+
+    /// Retrieves how the brackets of the program will be validated.
+    pub fn brackets(&self) -> BracketValidation {
+        self.bracket_validation
+    }
+
+    /// Sets which characters, other than commands and whitespace, are allowed
+    /// as comments.
+    pub fn comments(mut self, policy: CommentPolicy) -> Self {
+        self.comment_policy = policy;
+        self
+    }
+
+    /// Retrieves which characters are allowed as comments.
+    pub fn comment_policy(&self) -> CommentPolicy {
+        self.comment_policy
+    }
+
+    /// Sets the deepest that loops may be nested, beyond which creating the
+    /// program fails with a `NestingTooDeep` error, whichever way its brackets
+    /// are validated.
+    /// ```
+    /// use bft_types::BfProgram;
+    /// use bft_types::options::ParseOptions;
+    /// let options = ParseOptions::new().max_nesting(2);
+    /// assert_eq!(options.nesting_limit(), 2);
+    /// assert!(BfProgram::new_with_options("[[]]".to_string(), "ok.bf", &options).is_ok());
+    /// assert!(BfProgram::new_with_options("[[[]]]".to_string(), "deep.bf", &options).is_err());
+    /// ```
+    pub fn max_nesting(mut self, limit: usize) -> Self {
+        self.max_nesting = limit;
+        self
+    }
+
+    /// Retrieves the deepest that loops may be nested.
+    pub fn nesting_limit(&self) -> usize {
+        self.max_nesting
+    }
+
+    /// Sets the largest that the source of the program may be, in bytes,
+    /// beyond which creating the program fails with a `ProgramTooLarge` error.
+    /// When reading from a file, the size is checked before the file is read.
+    /// The source is not limited by default.
+    /// ```
+    /// use bft_types::BfProgram;
+    /// use bft_types::options::ParseOptions;
+    /// let options = ParseOptions::new().max_program_size(4);
+    /// assert_eq!(options.size_limit(), Some(4));
+    /// assert!(BfProgram::new_with_options("+-+-".to_string(), "ok.bf", &options).is_ok());
+    /// assert!(BfProgram::new_with_options("+-+-+".to_string(), "big.bf", &options).is_err());
+    /// ```
+    pub fn max_program_size(mut self, bytes: u64) -> Self {
+        self.max_size = Some(bytes);
+        self
+    }
+
+    /// Retrieves the largest that the source of the program may be, in bytes,
+    /// or None if it is not limited.
+    pub fn size_limit(&self) -> Option<u64> {
+        self.max_size
+    }
+
+    /// Checks that a program of the given size, in bytes, is within the limit.
+    pub(crate) fn check_size(
+        &self,
+        filename: &Path,
+        size: u64,
+    ) -> Result<(), VirtualMachineError> {
+        match self.max_size {
+            Some(limit) if size > limit => {
+                Err(VirtualMachineError::ProgramTooLarge {
+                    filename: filename.display().to_string(),
+                    size,
+                    limit,
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Registers a character to be parsed as an `Operation::Extension`. The
+    /// eight Brainfuck characters cannot be used as extensions, and are
+    /// ignored if given.
+    /// ```
+    /// use bft_types::ops::Operation;
+    /// use bft_types::options::ParseOptions;
+    /// let options = ParseOptions::new().extension('#').extension('+');
+    /// assert_eq!(options.extensions(), &['#']);
+    /// assert_eq!(options.operation_for('#'), Some(Operation::Extension('#')));
+    /// assert_eq!(options.operation_for('+'), Some(Operation::IncrementByte));
+    /// assert_eq!(options.operation_for('!'), None);
+    /// ```
+    pub fn extension(mut self, c: char) -> Self {
+        if Operation::char_to_operation(c).is_none()
+            && !self.extensions.contains(&c)
+        {
+            self.extensions.push(c);
+        }
+        self
+    }
+
+    /// Retrieves the characters registered as extensions.
+    pub fn extensions(&self) -> &[char] {
+        &self.extensions
+    }
+
+    /// Converts a character in a Brainfuck program into an operation, taking
+    /// into account any extensions. Returns None for comment characters.
+    pub fn operation_for(&self, c: char) -> Option<Operation> {
+        Operation::char_to_operation(c).or_else(|| {
+            self.extensions
+                .contains(&c)
+                .then_some(Operation::Extension(c))
+        })
+    }
+}
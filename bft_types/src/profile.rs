@@ -0,0 +1,144 @@
+//! A profile of the source of a program, worked out while it is parsed, which
+//! front ends can use to spot files which are probably not Brainfuck at all.
+
+/// The fraction of characters which should be commands before a source is
+/// thought to be Brainfuck. Heavily commented programs are usually well above
+/// this, while source code and text in other formats usually fall below it.
+pub const DEFAULT_MIN_COMMAND_RATIO: f64 = 0.1;
+
+/// A profile of the source of a program, as returned by `BfProgram::profile()`.
+///
+/// A first line starting with `#!` is taken to be a shebang, so that programs
+/// can be run as scripts, and is skipped when parsing, even if `#` is
+/// registered as an extension.
+/// ```
+/// use bft_types::BfProgram;
+/// let source = "#!/usr/bin/env -S bft --eof zero\n,[.,]".to_string();
+/// let program = BfProgram::new(source, "cat.bf").unwrap();
+/// let profile = program.profile();
+/// assert_eq!(profile.shebang(), Some("#!/usr/bin/env -S bft --eof zero"));
+/// assert_eq!(program.instructions().len(), 5);
+/// assert_eq!(profile.command_ratio(), 1.0);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SourceProfile {
+    /// The shebang line skipped at the start of the source, if any.
+    shebang: Option<String>,
+    /// The number of characters after the shebang which are not whitespace.
+    characters: usize,
+    /// The number of those characters which were parsed as commands.
+    commands: usize,
+}
+
+/// The length in bytes of the shebang line at the start of the source, or 0 if
+/// it does not have one.
+pub(crate) fn shebang_len(contents: &str) -> usize {
+    if contents.starts_with("#!") {
+        contents.lines().next().map_or(0, str::len)
+    } else {
+        0
+    }
+}
+
+impl SourceProfile {
+    /// Profiles the given source, which was parsed into the given number of
+    /// commands.
+    pub(crate) fn new(contents: &str, commands: usize) -> Self {
+        let (shebang, body) = contents.split_at(shebang_len(contents));
+        Self {
+            shebang: (!shebang.is_empty()).then(|| shebang.to_string()),
+            characters: body.chars().filter(|c| !c.is_whitespace()).count(),
+            commands,
+        }
+    }
+
+    /// Profiles a program which was not parsed from a source, and so is
+    /// entirely made up of commands.
+    pub(crate) fn from_commands(commands: usize) -> Self {
+        Self {
+            shebang: None,
+            characters: commands,
+            commands,
+        }
+    }
+
+    /// The shebang line skipped at the start of the source, if it had one.
+    pub fn shebang(&self) -> Option<&str> {
+        self.shebang.as_deref()
+    }
+
+    /// The number of characters in the source which are not whitespace, not
+    /// counting the shebang.
+    pub fn characters(&self) -> usize {
+        self.characters
+    }
+
+    /// The number of characters in the source which were parsed as commands.
+    pub fn commands(&self) -> usize {
+        self.commands
+    }
+
+    /// The fraction of the characters in the source which are commands, from
+    /// 0 to 1, ignoring whitespace.
+    pub fn command_ratio(&self) -> f64 {
+        if self.characters == 0 {
+            0.0
+        } else {
+            self.commands as f64 / self.characters as f64
+        }
+    }
+
+    /// Whether at least the given fraction of the characters in the source
+    /// are commands. A source with nothing but whitespace gives no reason to
+    /// think it is the wrong file, so always looks like Brainfuck.
+    /// ```
+    /// use bft_types::BfProgram;
+    /// use bft_types::profile::DEFAULT_MIN_COMMAND_RATIO;
+    /// let program = BfProgram::new("fn main() {}".to_string(), "main.rs").unwrap();
+    /// assert!(!program.profile().looks_like_brainfuck(DEFAULT_MIN_COMMAND_RATIO));
+    /// ```
+    pub fn looks_like_brainfuck(&self, min_ratio: f64) -> bool {
+        self.characters == 0 || self.command_ratio() >= min_ratio
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{shebang_len, SourceProfile, DEFAULT_MIN_COMMAND_RATIO};
+
+    #[test]
+    fn test_profile() {
+        let profile = SourceProfile::new("+ - plus\n  [ ]", 4);
+        assert_eq!(profile.shebang(), None);
+        assert_eq!(profile.characters(), 8);
+        assert_eq!(profile.commands(), 4);
+        assert_eq!(profile.command_ratio(), 0.5);
+        assert!(profile.looks_like_brainfuck(DEFAULT_MIN_COMMAND_RATIO));
+        assert!(!profile.looks_like_brainfuck(0.6));
+    }
+
+    #[test]
+    fn test_shebang() {
+        let source = "#!/bin/bft-run\r\n#+";
+        assert_eq!(shebang_len(source), 14);
+        let profile = SourceProfile::new(source, 2);
+        assert_eq!(profile.shebang(), Some("#!/bin/bft-run"));
+        assert_eq!(profile.characters(), 2);
+
+        // Only the first line can be a shebang.
+        assert_eq!(shebang_len("\n#!-"), 0);
+        let profile = SourceProfile::new("\n#!-", 1);
+        assert_eq!(profile.shebang(), None);
+        assert_eq!(profile.characters(), 3);
+    }
+
+    #[test]
+    fn test_empty_source() {
+        let profile = SourceProfile::new(" \n\t", 0);
+        assert_eq!(profile.command_ratio(), 0.0);
+        assert!(profile.looks_like_brainfuck(DEFAULT_MIN_COMMAND_RATIO));
+
+        let profile = SourceProfile::new("comments", 0);
+        assert!(!profile.looks_like_brainfuck(DEFAULT_MIN_COMMAND_RATIO));
+    }
+}
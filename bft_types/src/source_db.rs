@@ -0,0 +1,271 @@
+//! A database of the programs loaded by a session, such as a language server
+//! or a program which includes others, which interns the paths of their files
+//! so that each is stored once and can be named by a small [`FileId`]. The
+//! database can be shared between threads, and looks up the position of an
+//! instruction in any of its programs, so errors can say which file they came
+//! from.
+//!
+//! ```
+//! use bft_types::source_db::SourceDb;
+//! use bft_types::BfProgram;
+//!
+//! let db = SourceDb::new();
+//! let program = BfProgram::new("+\n[-]".to_string(), "clear.bf").unwrap();
+//! let id = db.insert(program);
+//! assert_eq!(db.intern("clear.bf"), id);
+//! assert_eq!(db.program(id).unwrap().instructions().len(), 4);
+//! assert_eq!(db.location(id, 2).unwrap().to_string(), "clear.bf:2:2");
+//! ```
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::path::Path;
+use std::sync::{Arc, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use crate::options::ParseOptions;
+use crate::BfProgram;
+
+/// The name of an interned path in a [`SourceDb`]. Ids are only meaningful to
+/// the database which gave them out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct FileId(u32);
+
+/// Where an instruction is in the source of a program: its file, line and
+/// column, displayed as `file:line:column`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceLocation {
+    path: Arc<Path>,
+    line: usize,
+    column: usize,
+}
+
+impl SourceLocation {
+    /// The path of the file the instruction is in.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The line the instruction is on.
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    /// The column the instruction is in.
+    pub fn column(&self) -> usize {
+        self.column
+    }
+}
+
+impl fmt::Display for SourceLocation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.path.display(), self.line, self.column)
+    }
+}
+
+/// The contents of the database, behind its lock.
+#[derive(Debug, Default)]
+struct Files {
+    /// The path of each id, in the order they were interned.
+    paths: Vec<Arc<Path>>,
+    /// The id of each interned path.
+    ids: HashMap<Arc<Path>, FileId>,
+    /// The program loaded from each file, if it has been.
+    programs: HashMap<FileId, Arc<BfProgram>>,
+}
+
+/// The programs loaded by a session, and the interned paths of their files.
+#[derive(Debug, Default)]
+pub struct SourceDb {
+    files: RwLock<Files>,
+}
+
+impl SourceDb {
+    /// Creates an empty database.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Nothing which could panic runs while the lock is held, so a poisoned
+    // lock still holds consistent files.
+    fn read(&self) -> RwLockReadGuard<'_, Files> {
+        self.files.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, Files> {
+        self.files.write().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Interns the path, giving the id it already has if it has been interned
+    /// before. Paths are compared as they are written, so `a.bf` and `./a.bf`
+    /// are different files.
+    pub fn intern<P>(&self, path: P) -> FileId
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        if let Some(&id) = self.read().ids.get(path) {
+            return id;
+        }
+        let mut files = self.write();
+        // Another thread may have interned the path since it was looked up.
+        if let Some(&id) = files.ids.get(path) {
+            return id;
+        }
+        let id = FileId(
+            u32::try_from(files.paths.len())
+                .expect("fewer than 2^32 files are interned"),
+        );
+        let path: Arc<Path> = path.into();
+        files.paths.push(Arc::clone(&path));
+        files.ids.insert(path, id);
+        id
+    }
+
+    /// The id of the path, if it has been interned.
+    pub fn lookup<P>(&self, path: P) -> Option<FileId>
+    where
+        P: AsRef<Path>,
+    {
+        self.read().ids.get(path.as_ref()).copied()
+    }
+
+    /// The path of the file with the given id, if it came from this database.
+    pub fn path(&self, id: FileId) -> Option<Arc<Path>> {
+        self.read().paths.get(id.0 as usize).cloned()
+    }
+
+    /// The number of paths which have been interned.
+    pub fn len(&self) -> usize {
+        self.read().paths.len()
+    }
+
+    /// Whether no paths have been interned.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Adds a program to the database under its filename, replacing any
+    /// program loaded from the same file before. The program shares the
+    /// interned path from then on.
+    pub fn insert(&self, mut program: BfProgram) -> FileId {
+        let id = self.intern(program.filename());
+        let mut files = self.write();
+        program.filename = Arc::clone(&files.paths[id.0 as usize]);
+        files.programs.insert(id, Arc::new(program));
+        id
+    }
+
+    /// Loads the program from the given file, as with
+    /// `BfProgram::from_file_with_options()`, and adds it to the database.
+    /// The file is read and parsed without holding up other threads using the
+    /// database.
+    pub fn load<P>(
+        &self,
+        path: P,
+        options: &ParseOptions,
+    ) -> Result<FileId, Box<dyn Error>>
+    where
+        P: AsRef<Path>,
+    {
+        let program = BfProgram::from_file_with_options(path, options)?;
+        Ok(self.insert(program))
+    }
+
+    /// The program loaded from the file with the given id, if there is one.
+    pub fn program(&self, id: FileId) -> Option<Arc<BfProgram>> {
+        self.read().programs.get(&id).cloned()
+    }
+
+    /// Where the instruction at the given index of the program loaded from
+    /// the file is, if there is such a program and instruction.
+    pub fn location(&self, id: FileId, index: usize) -> Option<SourceLocation> {
+        let files = self.read();
+        let instruction = files.programs.get(&id)?.instructions().get(index)?;
+        Some(SourceLocation {
+            path: Arc::clone(&files.paths[id.0 as usize]),
+            line: instruction.line(),
+            column: instruction.column(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SourceDb;
+    use crate::options::ParseOptions;
+    use crate::BfProgram;
+    use std::fs;
+    use std::path::Path;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn test_intern() {
+        let db = SourceDb::new();
+        assert!(db.is_empty());
+        let first = db.intern("a.bf");
+        let second = db.intern("b.bf");
+        assert_ne!(first, second);
+        assert_eq!(db.intern(Path::new("a.bf")), first);
+        assert_eq!(db.lookup("b.bf"), Some(second));
+        assert_eq!(db.lookup("c.bf"), None);
+        assert_eq!(db.path(second).as_deref(), Some(Path::new("b.bf")));
+        assert_eq!(db.len(), 2);
+        assert!(db.program(first).is_none());
+        assert!(db.location(first, 0).is_none());
+    }
+
+    #[test]
+    fn test_shared_paths() {
+        let db = SourceDb::new();
+        let id = db.insert(BfProgram::new("+".to_string(), "a.bf").unwrap());
+        let program = db.program(id).unwrap();
+        let path = db.path(id).unwrap();
+        assert!(std::ptr::eq(program.filename(), &*path));
+        // Inserting the file again replaces its program, but keeps its id.
+        let again = BfProgram::new("-".to_string(), "a.bf").unwrap();
+        assert_eq!(db.insert(again), id);
+        assert_ne!(*db.program(id).unwrap(), *program);
+    }
+
+    #[test]
+    fn test_load() {
+        let path = std::env::temp_dir()
+            .join(format!("bft-source-db-{}.bf", std::process::id()));
+        fs::write(&path, "a comment\n  +[-]").unwrap();
+        let db = SourceDb::new();
+        let loaded = db.load(&path, &ParseOptions::new());
+        fs::remove_file(&path).unwrap();
+        let id = loaded.unwrap();
+        let location = db.location(id, 1).unwrap();
+        assert_eq!(location.path(), path);
+        assert_eq!((location.line(), location.column()), (2, 4));
+        assert!(db.load("missing.bf", &ParseOptions::new()).is_err());
+    }
+
+    #[test]
+    fn test_threads() {
+        let db = Arc::new(SourceDb::new());
+        let handles: Vec<_> = (0..8)
+            .map(|n| {
+                let db = Arc::clone(&db);
+                thread::spawn(move || {
+                    (0..100)
+                        .map(|file| {
+                            db.intern(format!("{}.bf", (file + n) % 50))
+                        })
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(db.len(), 50);
+        for file in 0..50 {
+            let id = db.lookup(format!("{}.bf", file)).unwrap();
+            assert_eq!(db.intern(format!("{}.bf", file)), id);
+        }
+    }
+}
@@ -0,0 +1,179 @@
+//! Statistics about the source of a Brainfuck program, worked out without
+//! running it.
+
+use std::collections::HashMap;
+
+use crate::ops::Operation;
+use crate::BfProgram;
+
+/// Statistics about a program, as returned by `BfProgram::statistics()`.
+/// ```
+/// use bft_types::BfProgram;
+/// use bft_types::ops::Operation;
+/// let program = BfProgram::new("add: +++[>++<-]".to_string(), "add.bf").unwrap();
+/// let stats = program.statistics();
+/// assert_eq!(stats.instructions(), 10);
+/// assert_eq!(stats.count(Operation::IncrementByte), 5);
+/// assert_eq!(stats.comment_chars(), 5);
+/// assert_eq!(stats.loops(), 1);
+/// assert_eq!(stats.longest_run(), Some((Operation::IncrementByte, 3)));
+/// assert_eq!(stats.min_tape_cells(), 2);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProgramStats {
+    /// The number of instructions of each operation.
+    counts: HashMap<Operation, usize>,
+    /// The total number of instructions.
+    instructions: usize,
+    /// The number of characters in the source, including comments.
+    source_chars: usize,
+    /// The number of matched pairs of brackets.
+    loops: usize,
+    /// How deeply the most deeply nested loop is nested.
+    max_nesting: usize,
+    /// The longest run of the same operation, and its length.
+    longest_run: Option<(Operation, usize)>,
+    /// The number of cells the head moves across, going through the program
+    /// once.
+    min_tape_cells: usize,
+}
+
+impl ProgramStats {
+    /// Works out the statistics of the program.
+    pub(crate) fn new(program: &BfProgram) -> Self {
+        let mut counts = HashMap::new();
+        let mut longest_run: Option<(Operation, usize)> = None;
+        let mut run: Option<(Operation, usize)> = None;
+        let (mut offset, mut lowest, mut highest) = (0isize, 0isize, 0isize);
+        for instruction in program.iter() {
+            let operation = instruction.operation();
+            *counts.entry(operation).or_insert(0) += 1;
+
+            run = match run {
+                Some((previous, len)) if previous == operation => {
+                    Some((operation, len + 1))
+                }
+                _ => Some((operation, 1)),
+            };
+            if run.map(|(_, len)| len) > longest_run.map(|(_, len)| len) {
+                longest_run = run;
+            }
+
+            match operation {
+                Operation::IncrementPointer => offset += 1,
+                Operation::DecrementPointer => offset -= 1,
+                _ => {}
+            }
+            lowest = lowest.min(offset);
+            highest = highest.max(offset);
+        }
+        let loops = program
+            .iter()
+            .enumerate()
+            .filter(|(position, instruction)| {
+                instruction.operation() == Operation::StartLoop
+                    && program.jump_target(*position).is_some()
+            })
+            .count();
+        let max_nesting = program
+            .loops()
+            .iter()
+            .map(|node| node.depth())
+            .max()
+            .unwrap_or(0);
+        Self {
+            counts,
+            instructions: program.instructions().len(),
+            source_chars: program.source_chars(),
+            loops,
+            max_nesting,
+            longest_run,
+            min_tape_cells: highest.abs_diff(lowest) + 1,
+        }
+    }
+
+    /// The number of instructions of the given operation.
+    pub fn count(&self, operation: Operation) -> usize {
+        self.counts.get(&operation).copied().unwrap_or(0)
+    }
+
+    /// The total number of instructions.
+    pub fn instructions(&self) -> usize {
+        self.instructions
+    }
+
+    /// The number of characters in the source which are not instructions.
+    pub fn comment_chars(&self) -> usize {
+        self.source_chars.saturating_sub(self.instructions)
+    }
+
+    /// The fraction of the characters in the source which are comments, from
+    /// 0 to 1.
+    pub fn comment_ratio(&self) -> f64 {
+        if self.source_chars == 0 {
+            0.0
+        } else {
+            self.comment_chars() as f64 / self.source_chars as f64
+        }
+    }
+
+    /// The number of loops, counting each matched pair of brackets.
+    pub fn loops(&self) -> usize {
+        self.loops
+    }
+
+    /// How deeply the most deeply nested loop is nested, where a loop which is
+    /// not inside any other has a depth of 1.
+    pub fn max_nesting(&self) -> usize {
+        self.max_nesting
+    }
+
+    /// The operation repeated the most times in a row, along with how many
+    /// times, or None for an empty program.
+    pub fn longest_run(&self) -> Option<(Operation, usize)> {
+        self.longest_run
+    }
+
+    /// An estimate of the fewest cells of tape the program needs: the number
+    /// of cells the head moves across going through the program from start
+    /// to end once, as if each loop ran once. Loops which move the head each
+    /// time around need more.
+    pub fn min_tape_cells(&self) -> usize {
+        self.min_tape_cells
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ops::Operation;
+    use crate::BfProgram;
+
+    #[test]
+    fn test_statistics() {
+        let program = BfProgram::new(
+            "<< comment >>\n+[>[-]<<<<]--".to_string(),
+            "test.bf",
+        )
+        .unwrap();
+        let stats = program.statistics();
+        assert_eq!(stats.instructions(), 17);
+        assert_eq!(stats.count(Operation::DecrementPointer), 6);
+        assert_eq!(stats.count(Operation::InputByte), 0);
+        assert_eq!(stats.comment_chars(), 10);
+        assert!((stats.comment_ratio() - 10.0 / 27.0).abs() < 1e-9);
+        assert_eq!(stats.loops(), 2);
+        assert_eq!(stats.max_nesting(), 2);
+        assert_eq!(stats.longest_run(), Some((Operation::DecrementPointer, 4)));
+        assert_eq!(stats.min_tape_cells(), 5);
+    }
+
+    #[test]
+    fn test_empty_statistics() {
+        let program = BfProgram::new("nothing".to_string(), "test.bf").unwrap();
+        let stats = program.statistics();
+        assert_eq!(stats.instructions(), 0);
+        assert_eq!(stats.comment_ratio(), 1.0);
+        assert_eq!(stats.longest_run(), None);
+        assert_eq!(stats.min_tape_cells(), 1);
+    }
+}
@@ -0,0 +1,313 @@
+//! Test cases which come with a program, written the ways collections of
+//! Brainfuck programs commonly write them, rather than in a project file.
+//! Each way is a [`Convention`], and an [`Extractor`] tries each of its
+//! conventions on a program in turn. The built in conventions are:
+//!
+//! - [`SiblingFiles`]: the expected output is in a file next to the program
+//!   with the extension `.out`, and its input, if it reads any, in one with
+//!   the extension `.in`.
+//! - [`TrailingComment`]: the comment after the last command of the program
+//!   has lines starting `Input:` and `Output:`.
+//!
+//! ```
+//! use bft_types::testspec::Extractor;
+//! use bft_types::BfProgram;
+//!
+//! let source = ",[.,]\n\nInput: hi\nOutput: hi\n";
+//! let program = BfProgram::new(source.to_string(), "echo.b").unwrap();
+//! let specs = Extractor::new().extract(&program).unwrap();
+//! assert_eq!(specs.len(), 1);
+//! assert_eq!(specs[0].name(), "trailing-comment");
+//! assert_eq!(specs[0].input(), b"hi");
+//! assert_eq!(specs[0].output(), b"hi");
+//! ```
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::tokens::Token;
+use crate::BfProgram;
+
+/// A test case: the input to run a program with, and the output it should
+/// write.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TestSpec {
+    name: String,
+    input: Vec<u8>,
+    output: Vec<u8>,
+}
+
+impl TestSpec {
+    /// Creates a test case with the given name.
+    pub fn new(name: &str, input: Vec<u8>, output: Vec<u8>) -> Self {
+        Self {
+            name: name.to_string(),
+            input,
+            output,
+        }
+    }
+
+    /// The name of the test case, which is that of the convention it was
+    /// found by.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The input to run the program with.
+    pub fn input(&self) -> &[u8] {
+        &self.input
+    }
+
+    /// The output the program should write.
+    pub fn output(&self) -> &[u8] {
+        &self.output
+    }
+}
+
+/// A way of writing down a test case alongside a program.
+pub trait Convention {
+    /// The name of the convention, which names the test cases it finds.
+    fn name(&self) -> &str;
+
+    /// Finds the test case of the program, if it has one written this way.
+    fn extract(&self, program: &BfProgram) -> io::Result<Option<TestSpec>>;
+}
+
+/// Expected output in a file next to the program with the extension `.out`,
+/// such as `hello.out` for `hello.b`, and input in one with the extension
+/// `.in`, which is empty if there is no such file.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SiblingFiles;
+
+/// Reads the file, or gives None if it does not exist.
+fn read_if_present(path: &Path) -> io::Result<Option<Vec<u8>>> {
+    match fs::read(path) {
+        Ok(contents) => Ok(Some(contents)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+impl Convention for SiblingFiles {
+    fn name(&self) -> &str {
+        "sibling-files"
+    }
+
+    fn extract(&self, program: &BfProgram) -> io::Result<Option<TestSpec>> {
+        let filename = program.filename();
+        let Some(output) = read_if_present(&filename.with_extension("out"))?
+        else {
+            return Ok(None);
+        };
+        let input = read_if_present(&filename.with_extension("in"))?
+            .unwrap_or_default();
+        Ok(Some(TestSpec::new(self.name(), input, output)))
+    }
+}
+
+/// Lines starting `Input:` or `Output:`, in any case, in the comment after the
+/// last command of the program. Commands cannot be written in a comment, so
+/// the text after the colon may use the escapes `\n`, `\r`, `\t`, `\0`, `\\`
+/// and `\xHH`, such as `\x2e` for a `.`. Each line adds its text, less the
+/// space after the colon, to the input or output, and a program needs at
+/// least one `Output:` line to have a test case.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TrailingComment;
+
+/// Replaces the escapes in the text with the bytes they stand for. Anything
+/// else after a backslash is kept as it is.
+fn unescape(text: &str) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    let mut rest = text.as_bytes();
+    while let Some((&byte, after)) = rest.split_first() {
+        rest = after;
+        if byte != b'\\' {
+            bytes.push(byte);
+            continue;
+        }
+        let escaped = match rest.first() {
+            Some(b'n') => b'\n',
+            Some(b'r') => b'\r',
+            Some(b't') => b'\t',
+            Some(b'0') => 0,
+            Some(b'\\') => b'\\',
+            Some(b'x') => {
+                let value = rest
+                    .get(1..3)
+                    .and_then(|hex| std::str::from_utf8(hex).ok())
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+                if let Some(value) = value {
+                    bytes.push(value);
+                    rest = &rest[3..];
+                } else {
+                    bytes.push(byte);
+                }
+                continue;
+            }
+            _ => {
+                bytes.push(byte);
+                continue;
+            }
+        };
+        bytes.push(escaped);
+        rest = &rest[1..];
+    }
+    bytes
+}
+
+impl Convention for TrailingComment {
+    fn name(&self) -> &str {
+        "trailing-comment"
+    }
+
+    fn extract(&self, program: &BfProgram) -> io::Result<Option<TestSpec>> {
+        let Some(Token::Comment(comment)) = program.tokens().last() else {
+            return Ok(None);
+        };
+        let mut input = Vec::new();
+        let mut output = None;
+        for line in comment.text().lines() {
+            let line = line.trim_start();
+            let Some((key, value)) = line.split_once(':') else {
+                continue;
+            };
+            let value = unescape(value.strip_prefix(' ').unwrap_or(value));
+            if key.eq_ignore_ascii_case("input") {
+                input.extend(value);
+            } else if key.eq_ignore_ascii_case("output") {
+                output.get_or_insert_with(Vec::new).extend(value);
+            }
+        }
+        Ok(output.map(|output| TestSpec::new(self.name(), input, output)))
+    }
+}
+
+/// Finds the test cases of programs, by each of its conventions in turn.
+pub struct Extractor {
+    conventions: Vec<Box<dyn Convention>>,
+}
+
+impl Default for Extractor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Extractor {
+    /// Creates an extractor with the built in conventions.
+    pub fn new() -> Self {
+        Self {
+            conventions: vec![
+                Box::new(SiblingFiles),
+                Box::new(TrailingComment),
+            ],
+        }
+    }
+
+    /// Adds a convention, which is tried after the others.
+    pub fn with_convention<C>(mut self, convention: C) -> Self
+    where
+        C: Convention + 'static,
+    {
+        self.conventions.push(Box::new(convention));
+        self
+    }
+
+    /// Finds every test case of the program, one for each convention it has a
+    /// test case written in.
+    pub fn extract(&self, program: &BfProgram) -> io::Result<Vec<TestSpec>> {
+        let mut specs = Vec::new();
+        for convention in &self.conventions {
+            specs.extend(convention.extract(program)?);
+        }
+        Ok(specs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{unescape, Convention, Extractor, TestSpec};
+    use crate::BfProgram;
+    use std::fs;
+    use std::io;
+
+    fn program(source: &str, filename: &str) -> BfProgram {
+        BfProgram::new(source.to_string(), filename).unwrap()
+    }
+
+    #[test]
+    fn test_unescape() {
+        assert_eq!(unescape("a\\nb"), b"a\nb");
+        assert_eq!(unescape("\\x2e\\\\\\0"), b".\\\0");
+        assert_eq!(unescape("\\q\\x4"), b"\\q\\x4");
+    }
+
+    #[test]
+    fn test_trailing_comment() {
+        let echo = program(
+            "Echoes its input\n,[.,]\n  OUTPUT: one\\n\nOutput:two\nnote: x\n",
+            "echo.b",
+        );
+        let specs = Extractor::new().extract(&echo).unwrap();
+        assert_eq!(
+            specs,
+            [TestSpec::new(
+                "trailing-comment",
+                vec![],
+                b"one\ntwo".to_vec()
+            )]
+        );
+        // Leading comments are not tests, and neither are trailing comments
+        // without an output.
+        let leading = program("Output: x\n+.", "leading.b");
+        assert!(Extractor::new().extract(&leading).unwrap().is_empty());
+        let no_output = program("+.\nInput: x\n", "input.b");
+        assert!(Extractor::new().extract(&no_output).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_sibling_files() {
+        let dir = std::env::temp_dir()
+            .join(format!("bft-testspec-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("echo.b");
+        fs::write(dir.join("echo.in"), "abc").unwrap();
+        fs::write(dir.join("echo.out"), "abc").unwrap();
+        let echo = program(",[.,]", path.to_str().unwrap());
+        let specs = Extractor::new().extract(&echo).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            specs,
+            [TestSpec::new(
+                "sibling-files",
+                b"abc".to_vec(),
+                b"abc".to_vec()
+            )]
+        );
+    }
+
+    #[test]
+    fn test_custom_convention() {
+        /// Expects every program to write nothing.
+        struct Silent;
+
+        impl Convention for Silent {
+            fn name(&self) -> &str {
+                "silent"
+            }
+
+            fn extract(
+                &self,
+                _program: &BfProgram,
+            ) -> io::Result<Option<TestSpec>> {
+                Ok(Some(TestSpec::new(self.name(), vec![], vec![])))
+            }
+        }
+
+        let extractor = Extractor::new().with_convention(Silent);
+        let specs = extractor.extract(&program("+", "silent.b")).unwrap();
+        assert_eq!(specs.len(), 1);
+        assert_eq!(specs[0].name(), "silent");
+    }
+}
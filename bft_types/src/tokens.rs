@@ -0,0 +1,240 @@
+//! The tokens of the source of a program, as returned by `BfProgram::tokens()`:
+//! each of its commands, and the comments between them, in the order they
+//! were written. Formatters, highlighters and the like can work from these
+//! rather than parsing the source again themselves.
+//!
+//! A comment is everything between two commands, including any whitespace and
+//! the shebang, so writing out every token gives back the source exactly:
+//! ```
+//! use bft_types::tokens::Token;
+//! use bft_types::BfProgram;
+//!
+//! let source = "#!/usr/bin/env bft\nadd: [->+<]\n";
+//! let program = BfProgram::new(source.to_string(), "add.bf").unwrap();
+//! let written: String =
+//!     program.tokens().map(|token| token.to_string()).collect();
+//! assert_eq!(written, source);
+//!
+//! let comments: Vec<&str> = program
+//!     .tokens()
+//!     .filter_map(|token| match token {
+//!         Token::Comment(comment) => Some(comment.text()),
+//!         Token::Command(_) => None,
+//!     })
+//!     .collect();
+//! assert_eq!(comments, ["#!/usr/bin/env bft\nadd: ", "\n"]);
+//! ```
+
+use std::fmt;
+
+use crate::InstructionInfo;
+
+/// A comment, with the line and column of its first character.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Comment<'a> {
+    text: &'a str,
+    line: usize,
+    column: usize,
+}
+
+impl<'a> Comment<'a> {
+    /// The text of the comment, exactly as it was written.
+    pub fn text(&self) -> &'a str {
+        self.text
+    }
+
+    /// The line the comment starts on.
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    /// The column the comment starts in.
+    pub fn column(&self) -> usize {
+        self.column
+    }
+}
+
+/// A single token of the source of a program.
+#[derive(Debug, Clone, Copy)]
+pub enum Token<'a> {
+    /// A command, which is one of the instructions of the program.
+    Command(InstructionInfo),
+    /// A comment between commands.
+    Comment(Comment<'a>),
+}
+
+impl fmt::Display for Token<'_> {
+    /// Writes the token as it was written in the source.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Command(instruction) => {
+                write!(f, "{}", instruction.operation().to_char())
+            }
+            Token::Comment(comment) => f.write_str(comment.text),
+        }
+    }
+}
+
+/// Where a comment is, kept in the index.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Span {
+    /// The end of the comment in the text of the index. It starts where the
+    /// comment before it ends.
+    end: usize,
+    line: usize,
+    column: usize,
+    /// The number of instructions before the comment.
+    position: usize,
+}
+
+/// The comments of a program, kept alongside its instructions: the text of
+/// every comment one after another in a single string, along with where each
+/// one ends in it and where it was in the source.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct CommentIndex {
+    text: String,
+    spans: Vec<Span>,
+}
+
+impl CommentIndex {
+    /// Adds the comment found at the given line and column, after the given
+    /// number of instructions. Comments must be added in order.
+    pub(crate) fn push(
+        &mut self,
+        text: &str,
+        (line, column): (usize, usize),
+        position: usize,
+    ) {
+        self.text.push_str(text);
+        self.spans.push(Span {
+            end: self.text.len(),
+            line,
+            column,
+            position,
+        });
+    }
+
+    /// The comment at the given index, along with the number of instructions
+    /// before it.
+    fn get(&self, index: usize) -> Option<(Comment<'_>, usize)> {
+        let span = self.spans.get(index)?;
+        let start = index.checked_sub(1).map_or(0, |i| self.spans[i].end);
+        let comment = Comment {
+            text: &self.text[start..span.end],
+            line: span.line,
+            column: span.column,
+        };
+        Some((comment, span.position))
+    }
+}
+
+/// An iterator over the tokens of a program, returned by
+/// `BfProgram::tokens()`.
+#[derive(Debug, Clone)]
+pub struct Tokens<'a> {
+    instructions: &'a [InstructionInfo],
+    comments: &'a CommentIndex,
+    /// The number of instructions given so far.
+    position: usize,
+    /// The number of comments given so far.
+    comment: usize,
+}
+
+impl<'a> Tokens<'a> {
+    pub(crate) fn new(
+        instructions: &'a [InstructionInfo],
+        comments: &'a CommentIndex,
+    ) -> Self {
+        Self {
+            instructions,
+            comments,
+            position: 0,
+            comment: 0,
+        }
+    }
+}
+
+impl<'a> Iterator for Tokens<'a> {
+    type Item = Token<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.comments.get(self.comment) {
+            Some((comment, position)) if position == self.position => {
+                self.comment += 1;
+                Some(Token::Comment(comment))
+            }
+            _ => {
+                let instruction = *self.instructions.get(self.position)?;
+                self.position += 1;
+                Some(Token::Command(instruction))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Comment, Token};
+    use crate::options::{CommentPolicy, ParseOptions};
+    use crate::BfProgram;
+
+    /// Describes each token as its text and position.
+    fn tokens(program: &BfProgram) -> Vec<(String, usize, usize)> {
+        program
+            .tokens()
+            .map(|token| match token {
+                Token::Command(instruction) => (
+                    token.to_string(),
+                    instruction.line(),
+                    instruction.column(),
+                ),
+                Token::Comment(comment) => {
+                    (token.to_string(), comment.line(), comment.column())
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_tokens() {
+        let program =
+            BfProgram::new("+ a\n-b.".to_string(), "tokens.bf").unwrap();
+        let expected = [
+            ("+", 1, 1),
+            (" a\n", 1, 2),
+            ("-", 2, 1),
+            ("b", 2, 2),
+            (".", 2, 3),
+        ];
+        let expected = expected
+            .map(|(text, line, column)| (text.to_string(), line, column));
+        assert_eq!(tokens(&program), expected);
+
+        let empty = BfProgram::new(String::new(), "empty.bf").unwrap();
+        assert_eq!(empty.tokens().count(), 0);
+        let comment = BfProgram::new("nothing".to_string(), "c.bf").unwrap();
+        assert!(matches!(
+            comment.tokens().collect::<Vec<_>>()[..],
+            [Token::Comment(Comment {
+                text: "nothing",
+                ..
+            })]
+        ));
+    }
+
+    #[test]
+    fn test_delimited_comments() {
+        // Commands inside a delimited comment are part of the comment.
+        let options = ParseOptions::new().comments(CommentPolicy::Delimited);
+        let source = "+ ; +1\n{ - } -";
+        let program =
+            BfProgram::new_with_options(source.to_string(), "d.bf", &options)
+                .unwrap();
+        let texts: Vec<String> =
+            program.tokens().map(|token| token.to_string()).collect();
+        assert_eq!(texts, ["+", " ; +1\n{ - } ", "-"]);
+
+        // Programs which were not parsed from a source have no comments.
+        assert_eq!(program.normalized().tokens().count(), 2);
+    }
+}
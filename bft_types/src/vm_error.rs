@@ -31,6 +31,187 @@ pub enum VirtualMachineError {
         tape_length: usize,
     },
 
+    /// The program has run for more steps than it was allowed to.
+    #[error(
+        "In {filename}: line {line}, column {column} the step limit of \
+        {limit} was reached."
+    )]
+    StepLimitExceeded {
+        /// Line of the instruction which would have exceeded the limit
+        line: usize,
+        /// Column of the instruction which would have exceeded the limit
+        column: usize,
+        /// The filename of the program
+        filename: String,
+        /// The maximum number of steps the program was allowed to take
+        limit: u64,
+    },
+
+    /// The program has done more work than its gas limit allowed.
+    #[error(
+        "In {filename}: line {line}, column {column} the gas limit of \
+        {limit} ran out."
+    )]
+    GasExhausted {
+        /// Line of the instruction which would have exceeded the limit
+        line: usize,
+        /// Column of the instruction which would have exceeded the limit
+        column: usize,
+        /// The filename of the program
+        filename: String,
+        /// The most gas the program was allowed to use
+        limit: u64,
+    },
+
+    /// The program has run for longer than it was allowed to.
+    #[error(
+        "In {filename}: line {line}, column {column} the time limit of \
+        {limit_ms} ms was reached."
+    )]
+    TimeLimitExceeded {
+        /// Line of the instruction reached when the time ran out
+        line: usize,
+        /// Column of the instruction reached when the time ran out
+        column: usize,
+        /// The filename of the program
+        filename: String,
+        /// The time the program was allowed to run for, in milliseconds
+        limit_ms: u64,
+    },
+
+    /// The program would have written more output than it was allowed to.
+    #[error(
+        "In {filename}: line {line}, column {column} the output limit of \
+        {limit} bytes was reached."
+    )]
+    OutputLimitExceeded {
+        /// Line of the instruction which would have exceeded the limit
+        line: usize,
+        /// Column of the instruction which would have exceeded the limit
+        column: usize,
+        /// The filename of the program
+        filename: String,
+        /// The maximum number of bytes the program was allowed to write
+        limit: u64,
+    },
+
+    /// The program read past the end of its input, or past as much of it as it
+    /// was allowed to read, when reading past the end is an error.
+    #[error(
+        "In {filename}: line {line}, column {column} the program read past \
+        the end of its input, after {read} bytes."
+    )]
+    InputExhausted {
+        /// Line of the instruction which read past the end of the input
+        line: usize,
+        /// Column of the instruction which read past the end of the input
+        column: usize,
+        /// The filename of the program
+        filename: String,
+        /// The number of bytes the program had read
+        read: u64,
+    },
+
+    /// The head of the tape was moved off the end of an extensible tape, but
+    /// the tape was not allowed to grow to reach it.
+    #[error(
+        "In {filename}: line {line}, column {column} the tape could not grow \
+        to {cells} cells."
+    )]
+    TapeGrowthRefused {
+        /// Line of the instruction which moved the head
+        line: usize,
+        /// Column of the instruction which moved the head
+        column: usize,
+        /// The filename of the program
+        filename: String,
+        /// The number of cells the tape would have needed
+        cells: usize,
+    },
+
+    /// The program reached an extension instruction which the Virtual Machine
+    /// has no handler registered for.
+    #[error(
+        "In {filename}: line {line}, column {column} there is no extension \
+        registered for '{name}'."
+    )]
+    UnknownExtension {
+        /// The character of the extension instruction
+        name: char,
+        /// Line of the extension instruction
+        line: usize,
+        /// Column of the extension instruction
+        column: usize,
+        /// The filename of the program
+        filename: String,
+    },
+
+    /// An extension instruction reported that it failed.
+    #[error(
+        "In {filename}: line {line}, column {column} the extension '{name}' \
+        failed: {message}"
+    )]
+    ExtensionFailed {
+        /// The character of the extension instruction
+        name: char,
+        /// Line of the extension instruction
+        line: usize,
+        /// Column of the extension instruction
+        column: usize,
+        /// The filename of the program
+        filename: String,
+        /// The reason given by the extension
+        message: String,
+    },
+
+    /// The program made a host function call, but the Virtual Machine was not
+    /// given the capability to make them.
+    #[error(
+        "In {filename}: line {line}, column {column} the program calls a host \
+        function, but host function calls are not enabled."
+    )]
+    SyscallsDisabled {
+        /// Line of the call
+        line: usize,
+        /// Column of the call
+        column: usize,
+        /// The filename of the program
+        filename: String,
+    },
+
+    /// The program called a host function which its sandbox does not allow
+    /// it to call.
+    #[error(
+        "In {filename}: line {line}, column {column} the program calls host \
+        function {id}, which it is not allowed to call."
+    )]
+    SyscallDenied {
+        /// The number of the host function, from the cell at the head
+        id: u32,
+        /// Line of the call
+        line: usize,
+        /// Column of the call
+        column: usize,
+        /// The filename of the program
+        filename: String,
+    },
+
+    /// The program called a host function which was never registered.
+    #[error(
+        "In {filename}: line {line}, column {column} there is no host \
+        function registered for {id}."
+    )]
+    UnknownSyscall {
+        /// The number of the host function, from the cell at the head
+        id: u32,
+        /// Line of the call
+        line: usize,
+        /// Column of the call
+        column: usize,
+        /// The filename of the program
+        filename: String,
+    },
+
     /// An error corresponding to the failure to read into a cell
     #[error(transparent)]
     IOError(#[from] std::io::Error),
@@ -48,10 +229,158 @@ pub enum VirtualMachineError {
         column: usize,
     },
 
-    #[error("Failure to find the brackets")]
-    /// A specific failure in the case that the bracket checker does not find a
-    /// matching bracket, yet still allows the program to run. If this were to
-    /// happen, the program would fail, and this error will indicate a failure
-    /// in the aforementioned bracket checker.
-    BracketFailure,
+    #[error(
+        "stray {character:?} on line {line} column {column}, outside of a \
+        comment"
+    )]
+    /// Corresponds to the case in which a character which is neither a command
+    /// nor whitespace is found outside of a comment, when comments must be
+    /// delimited.
+    StrayCharacter {
+        /// The character in question.
+        character: char,
+        /// The line of the stray character
+        line: usize,
+        /// The column of the stray character
+        column: usize,
+    },
+
+    #[error("in {filename}: {message} on line {line} column {column}")]
+    /// Corresponds to the case in which a Brainfuck assembly program cannot be
+    /// assembled.
+    InvalidAssembly {
+        /// The file containing the program
+        filename: String,
+        /// What is wrong with the statement
+        message: String,
+        /// The line of the statement
+        line: usize,
+        /// The column of the statement
+        column: usize,
+    },
+
+    #[error("comment opened on line {line} column {column} is never closed")]
+    /// Corresponds to the case in which a block comment is still open at the
+    /// end of the program, when comments must be delimited.
+    UnterminatedComment {
+        /// The line of the start of the comment
+        line: usize,
+        /// The column of the start of the comment
+        column: usize,
+    },
+
+    #[error(
+        "{filename} is {size} bytes, larger than the limit of {limit} bytes"
+    )]
+    /// Corresponds to the case in which the source of a program is larger than
+    /// allowed, which usually means a file which is not Brainfuck at all was
+    /// given by mistake.
+    ProgramTooLarge {
+        /// The file containing the program
+        filename: String,
+        /// The size of the source of the program, in bytes.
+        size: u64,
+        /// The largest the source of a program was allowed to be, in bytes.
+        limit: u64,
+    },
+
+    #[error(
+        "loops nested {depth} deep on line {line} column {column}, deeper \
+        than the limit of {limit}"
+    )]
+    /// Corresponds to the case in which loops are nested more deeply than
+    /// allowed, which is usually a sign of a generated program gone wrong.
+    NestingTooDeep {
+        /// How deeply the loops are nested at the offending bracket.
+        depth: usize,
+        /// The most deeply loops were allowed to be nested.
+        limit: usize,
+        /// The line of the opening bracket which went over the limit
+        line: usize,
+        /// The column of the opening bracket which went over the limit
+        column: usize,
+    },
+
+    #[error(
+        "the loops of the lowered program are unbalanced at node {position}"
+    )]
+    /// A program in the intermediate representation, such as one built from
+    /// nodes or rewritten by an optimization pass, has a loop or branch node
+    /// without its other end. Brainfuck programs themselves have the targets
+    /// of their brackets worked out when they are created, so their brackets
+    /// never fail this way.
+    UnbalancedNodes {
+        /// The position of the node without its other end.
+        position: usize,
+    },
+}
+
+impl VirtualMachineError {
+    /// A short name for the kind of error, which stays the same however the
+    /// message is worded, for tools which report errors as data.
+    /// ```
+    /// use bft_types::BfProgram;
+    ///
+    /// let err = BfProgram::new("+[".to_string(), "open.bf").unwrap_err();
+    /// assert_eq!(err.kind(), "unmatched_bracket");
+    /// ```
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::InvalidHeadPosition { .. } => "invalid_head_position",
+            Self::StepLimitExceeded { .. } => "step_limit_exceeded",
+            Self::GasExhausted { .. } => "gas_exhausted",
+            Self::TimeLimitExceeded { .. } => "time_limit_exceeded",
+            Self::OutputLimitExceeded { .. } => "output_limit_exceeded",
+            Self::InputExhausted { .. } => "input_exhausted",
+            Self::TapeGrowthRefused { .. } => "tape_growth_refused",
+            Self::UnknownExtension { .. } => "unknown_extension",
+            Self::ExtensionFailed { .. } => "extension_failed",
+            Self::SyscallsDisabled { .. } => "syscalls_disabled",
+            Self::SyscallDenied { .. } => "syscall_denied",
+            Self::UnknownSyscall { .. } => "unknown_syscall",
+            Self::IOError(_) => "io_error",
+            Self::UnmatchedBracket { .. } => "unmatched_bracket",
+            Self::StrayCharacter { .. } => "stray_character",
+            Self::InvalidAssembly { .. } => "invalid_assembly",
+            Self::UnterminatedComment { .. } => "unterminated_comment",
+            Self::ProgramTooLarge { .. } => "program_too_large",
+            Self::NestingTooDeep { .. } => "nesting_too_deep",
+            Self::UnbalancedNodes { .. } => "unbalanced_nodes",
+        }
+    }
+
+    /// The line and column in the source of the program where the error
+    /// happened, if it happened at an instruction.
+    /// ```
+    /// use bft_types::BfProgram;
+    ///
+    /// let err = BfProgram::new("+\n [".to_string(), "open.bf").unwrap_err();
+    /// assert_eq!(err.location(), Some((2, 2)));
+    /// ```
+    pub fn location(&self) -> Option<(usize, usize)> {
+        match self {
+            Self::InvalidHeadPosition { line, column, .. }
+            | Self::StepLimitExceeded { line, column, .. }
+            | Self::GasExhausted { line, column, .. }
+            | Self::TimeLimitExceeded { line, column, .. }
+            | Self::OutputLimitExceeded { line, column, .. }
+            | Self::InputExhausted { line, column, .. }
+            | Self::TapeGrowthRefused { line, column, .. }
+            | Self::UnknownExtension { line, column, .. }
+            | Self::ExtensionFailed { line, column, .. }
+            | Self::SyscallsDisabled { line, column, .. }
+            | Self::SyscallDenied { line, column, .. }
+            | Self::UnknownSyscall { line, column, .. }
+            | Self::UnmatchedBracket { line, column, .. }
+            | Self::StrayCharacter { line, column, .. }
+            | Self::InvalidAssembly { line, column, .. }
+            | Self::UnterminatedComment { line, column }
+            | Self::NestingTooDeep { line, column, .. } => {
+                Some((*line, *column))
+            }
+            Self::IOError(_)
+            | Self::ProgramTooLarge { .. }
+            | Self::UnbalancedNodes { .. } => None,
+        }
+    }
 }
@@ -0,0 +1,188 @@
+//! A sample plugin for bft, built with `cargo build --example sample_plugin`
+//! and loaded with `--plugin`, along with the `plugins` feature:
+//!
+//! ```console
+//! cargo run --features plugins -- run --plugin \
+//!     target/debug/examples/libsample_plugin.so --passes cancel,rle hello.ook
+//! ```
+//!
+//! It provides:
+//! - `cancel`, an optimization pass which drops neighbouring adds and moves
+//!   which undo each other, such as `+-` and `><`.
+//! - `ook`, a dialect for `.ook` files written in Ook!, where each pair of
+//!   `Ook.`, `Ook?` and `Ook!` is a Brainfuck command.
+//! - `rot13`, a codec which rotates the letters of the input and output of
+//!   programs by 13 places.
+
+use std::ffi::c_void;
+use std::ptr;
+use std::slice;
+
+use bft_interp::plugin::{
+    CodecVTable, DialectVTable, EmitBytes, EmitNode, HostInfo, Node,
+    PassVTable, PluginInfo, ABI_VERSION,
+};
+
+/// Whether the two nodes undo each other.
+fn cancels(first: &Node, second: &Node) -> bool {
+    match (first.kind, second.kind) {
+        (Node::ADD, Node::ADD) => first.amount + second.amount == 0,
+        (Node::MOVE, Node::MOVE) => first.offset + second.offset == 0,
+        _ => false,
+    }
+}
+
+unsafe extern "C" fn cancel(
+    nodes: *const Node,
+    len: usize,
+    out: *mut c_void,
+    emit: EmitNode,
+) -> i64 {
+    // SAFETY: bft passes `len` nodes, valid for the call.
+    let nodes = unsafe { slice::from_raw_parts(nodes, len) };
+    let mut kept: Vec<Node> = Vec::with_capacity(nodes.len());
+    for node in nodes {
+        match kept.last() {
+            Some(last) if cancels(last, node) => {
+                kept.pop();
+            }
+            _ => kept.push(*node),
+        }
+    }
+    if kept.len() == nodes.len() {
+        // Nothing cancelled out, so the program is left as it is.
+        return -1;
+    }
+    for node in kept {
+        // SAFETY: `out` is what bft passed along with `emit`.
+        unsafe { emit(out, node) };
+    }
+    0
+}
+
+/// The Brainfuck command for a pair of Ook! words, given by their marks.
+fn ook_command(first: u8, second: u8) -> Option<u8> {
+    Some(match (first, second) {
+        (b'.', b'?') => b'>',
+        (b'?', b'.') => b'<',
+        (b'.', b'.') => b'+',
+        (b'!', b'!') => b'-',
+        (b'!', b'.') => b'.',
+        (b'.', b'!') => b',',
+        (b'!', b'?') => b'[',
+        (b'?', b'!') => b']',
+        _ => return None,
+    })
+}
+
+unsafe extern "C" fn translate_ook(
+    source: *const u8,
+    len: usize,
+    out: *mut c_void,
+    emit: EmitBytes,
+) -> i32 {
+    // SAFETY: bft passes `len` bytes, valid for the call.
+    let source = unsafe { slice::from_raw_parts(source, len) };
+    let marks: Vec<u8> = source
+        .windows(4)
+        .filter(|word| word.starts_with(b"Ook") && b".?!".contains(&word[3]))
+        .map(|word| word[3])
+        .collect();
+    let pairs = marks.chunks_exact(2);
+    if !pairs.remainder().is_empty() {
+        return 1;
+    }
+    let mut program = Vec::with_capacity(pairs.len());
+    for pair in pairs {
+        match ook_command(pair[0], pair[1]) {
+            Some(command) => program.push(command),
+            None => return 1,
+        }
+    }
+    // SAFETY: `out` is what bft passed along with `emit`.
+    unsafe { emit(out, program.as_ptr(), program.len()) };
+    0
+}
+
+/// Rotating by 13 both encodes and decodes, so needs no state.
+unsafe extern "C" fn start_rot13(_direction: u32) -> *mut c_void {
+    ptr::null_mut()
+}
+
+unsafe extern "C" fn feed_rot13(
+    _state: *mut c_void,
+    bytes: *const u8,
+    len: usize,
+    out: *mut c_void,
+    emit: EmitBytes,
+) -> i32 {
+    // SAFETY: bft passes `len` bytes, valid for the call.
+    let bytes = unsafe { slice::from_raw_parts(bytes, len) };
+    let rotated: Vec<u8> = bytes
+        .iter()
+        .map(|&byte| match byte {
+            b'a'..=b'z' => (byte - b'a' + 13) % 26 + b'a',
+            b'A'..=b'Z' => (byte - b'A' + 13) % 26 + b'A',
+            _ => byte,
+        })
+        .collect();
+    // SAFETY: `out` is what bft passed along with `emit`.
+    unsafe { emit(out, rotated.as_ptr(), rotated.len()) };
+    0
+}
+
+unsafe extern "C" fn finish_rot13(
+    _state: *mut c_void,
+    _out: *mut c_void,
+    _emit: EmitBytes,
+) -> i32 {
+    0
+}
+
+static PASSES: [PassVTable; 1] = [PassVTable {
+    name: c"cancel".as_ptr(),
+    run: cancel,
+}];
+
+static DIALECTS: [DialectVTable; 1] = [DialectVTable {
+    name: c"ook".as_ptr(),
+    extension: c"ook".as_ptr(),
+    translate: translate_ook,
+}];
+
+static CODECS: [CodecVTable; 1] = [CodecVTable {
+    name: c"rot13".as_ptr(),
+    start: start_rot13,
+    feed: feed_rot13,
+    finish: finish_rot13,
+}];
+
+static PLUGIN: PluginInfo = PluginInfo {
+    version: ABI_VERSION,
+    name: c"sample".as_ptr(),
+    passes: PASSES.as_ptr(),
+    pass_count: PASSES.len(),
+    dialects: DIALECTS.as_ptr(),
+    dialect_count: DIALECTS.len(),
+    codecs: CODECS.as_ptr(),
+    codec_count: CODECS.len(),
+};
+
+/// Hands bft the plugin, if it supports the version of the interface the
+/// plugin was written for.
+///
+/// # Safety
+///
+/// `host` must point to a valid `HostInfo`, as bft's does.
+#[no_mangle]
+pub unsafe extern "C" fn bft_plugin_entry(
+    host: *const HostInfo,
+) -> *const PluginInfo {
+    // SAFETY: the caller passes a valid host.
+    let host = unsafe { &*host };
+    if host.supports(ABI_VERSION) {
+        &PLUGIN
+    } else {
+        ptr::null()
+    }
+}
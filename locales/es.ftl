@@ -0,0 +1,106 @@
+# Spanish messages for bft. The ids are those passed to `i18n::localize`, and
+# a message which is missing here is shown in English.
+
+## Errors, by the kind of error.
+
+error-invalid-head-position =
+    En { $filename }: línea { $line }, columna { $column } la orden { $operation } mueve el cabezal a una posición no válida. La posición actual es { $position }, y debería estar entre 0 y { $tape_length }.
+error-step-limit-exceeded =
+    En { $filename }: línea { $line }, columna { $column } se alcanzó el límite de { $limit } pasos.
+error-gas-exhausted =
+    En { $filename }: línea { $line }, columna { $column } se agotó el límite de gas de { $limit }.
+error-time-limit-exceeded =
+    En { $filename }: línea { $line }, columna { $column } se alcanzó el límite de tiempo de { $limit_ms } ms.
+error-output-limit-exceeded =
+    En { $filename }: línea { $line }, columna { $column } se alcanzó el límite de salida de { $limit } bytes.
+error-input-exhausted =
+    En { $filename }: línea { $line }, columna { $column } el programa leyó más allá del final de su entrada, tras { $read } bytes.
+error-tape-growth-refused =
+    En { $filename }: línea { $line }, columna { $column } la cinta no pudo crecer hasta { $cells } celdas.
+error-unknown-extension =
+    En { $filename }: línea { $line }, columna { $column } no hay ninguna extensión registrada para '{ $name }'.
+error-extension-failed =
+    En { $filename }: línea { $line }, columna { $column } la extensión '{ $name }' falló: { $message }
+error-syscalls-disabled =
+    En { $filename }: línea { $line }, columna { $column } el programa llama a una función del anfitrión, pero las llamadas al anfitrión no están activadas.
+error-syscall-denied =
+    En { $filename }: línea { $line }, columna { $column } el programa llama a la función del anfitrión { $id }, que no tiene permitido llamar.
+error-unknown-syscall =
+    En { $filename }: línea { $line }, columna { $column } no hay ninguna función del anfitrión registrada para { $id }.
+error-io-error = error de entrada o salida: { $message }
+error-unmatched-bracket =
+    { $bracket } sin pareja en la línea { $line } columna { $column }
+error-stray-character =
+    { $character } suelto en la línea { $line } columna { $column }, fuera de un comentario
+error-invalid-assembly =
+    en { $filename }: { $message } en la línea { $line } columna { $column }
+error-unterminated-comment =
+    el comentario abierto en la línea { $line } columna { $column } nunca se cierra
+error-program-too-large =
+    { $filename } ocupa { $size } bytes, más que el límite de { $limit } bytes
+error-nesting-too-deep =
+    bucles anidados { $depth } niveles en la línea { $line } columna { $column }, más que el límite de { $limit }
+error-unbalanced-nodes =
+    los bucles del programa reducido están desequilibrados en el nodo { $position }
+
+## Loading programs.
+
+load-not-brainfuck =
+    solo el { $percent }% de { $filename } son órdenes de Brainfuck, ¿es el archivo correcto?
+
+## Diagnosing a program which ran out of steps, gas or time.
+
+hang-loop = { $location } ({ $iterations } veces)
+hang-hottest-loops = bucles más recorridos: { $loops }
+hang-stopped-inside = detenido dentro de: { $loops }
+hang-last-io =
+    última entrada o salida: paso { $step }, { $since } pasos antes de detenerse
+hang-no-io = última entrada o salida: nunca
+
+## Checking runs with --expect-output and --expect-exit.
+
+expect-exit =
+    la ejecución terminó con { $code } en lugar de { $expected }
+expect-exit-because =
+    la ejecución terminó con { $code } en lugar de { $expected }. { $reason }
+expect-output = la salida no fue la esperada:
+    { $diff }
+
+## What each command does.
+
+operation-increment-pointer = > : mueve el puntero de datos una celda a la derecha.
+operation-decrement-pointer = < : mueve el puntero de datos una celda a la izquierda.
+operation-increment-byte = + : aumenta en 1 el valor guardado en la celda actual.
+operation-decrement-byte = - : disminuye en 1 el valor guardado en la celda actual.
+operation-output-byte = . : Escribe el byte del puntero de datos actual.
+operation-input-byte = , : Lee un byte de la entrada, y guarda su valor en el puntero de datos actual.
+operation-start-loop = [ : Empieza un bucle.
+operation-end-loop = ] : Termina un bucle.
+operation-extension = { $name } : Una instrucción de extensión propia.
+
+## The narration of `bft teach`.
+
+teach-step = paso { $step }: `{ $command }` en { $line }:{ $column } → { $outcome }
+teach-cell-becomes = la celda { $cell } pasa a valer { $value }
+teach-head-moves = el cabezal se mueve a la celda { $cell }
+teach-writes = escribe { $byte } "{ $text }"
+teach-loop-jumps =
+    salta a su `]` en { $location }, que decide si el bucle se ejecuta
+teach-loop-skipped = la celda { $cell } vale 0, así que se salta el bucle
+teach-loop-runs = la celda { $cell } vale { $value }, así que el bucle se ejecuta
+teach-loop-ends = la celda { $cell } vale 0, así que el bucle termina
+teach-loop-repeats = la celda { $cell } vale { $value }, así que el bucle da otra vuelta
+teach-extension = ejecuta la extensión
+teach-output = salida: "{ $text }"
+teach-halted =
+    { $steps ->
+        [one] terminó tras { $steps } paso
+       *[other] terminó tras { $steps } pasos
+    }
+teach-failed = falló: { $error }
+teach-paused = en pausa, pulsa Intro para continuar
+teach-stopped =
+    { $steps ->
+        [one] detenido tras { $steps } paso
+       *[other] detenido tras { $steps } pasos
+    }
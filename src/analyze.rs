@@ -0,0 +1,820 @@
+//! The `analyze` subcommand, which works out which behaviors of the
+//! interpreter a program depends on, such as how wide its cells are and what
+//! `,` does at the end of the input, and which presets it runs under.
+
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::process::ExitCode;
+
+use bft_interp::eof::EofBehavior;
+use bft_interp::events::VmEvent;
+use bft_interp::independence::{Independence, Segment};
+use bft_interp::ir::IrProgram;
+use bft_interp::optimizer::{DeadLoop, IrPass, Unreachable};
+use bft_interp::preset::Profile;
+use bft_interp::symexec::{Answer, SymbolicExecutor};
+use bft_types::ops::Operation;
+use bft_types::vm_error::VirtualMachineError;
+use bft_types::{BfProgram, InstructionInfo};
+
+use crate::cli::AnalyzeArgs;
+use crate::config::{CellWidth, Settings};
+use crate::harness::{execute, Execution, Outcome, DEFAULT_STEP_LIMIT};
+use crate::load_program;
+
+/// The number of cells to the left of the first cell given to the
+/// instrumented run, so that it can see how far left the program goes.
+const LEFT_PADDING: usize = 1000;
+
+/// The most bytes of the output of each preset to show.
+const OUTPUT_PREVIEW: usize = 40;
+
+/// The EOF conventions which let the program carry on at the end of the
+/// input, to compare its output under.
+const EOF_CONVENTIONS: [EofBehavior; 3] = [
+    EofBehavior::Zero,
+    EofBehavior::Unchanged,
+    EofBehavior::MaxValue,
+];
+
+/// What the instrumented run of the program saw it do.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Observed {
+    /// The furthest left of the first cell the head went.
+    left: usize,
+    /// The furthest right of the first cell the head went.
+    right: usize,
+    /// Whether a cell went below zero.
+    below_zero: bool,
+    /// Whether a cell went above 255.
+    above_byte: bool,
+}
+
+impl Observed {
+    fn see(&mut self, event: &VmEvent) {
+        match *event {
+            VmEvent::HeadMoved { to, .. } if to < LEFT_PADDING => {
+                self.left = self.left.max(LEFT_PADDING - to);
+            }
+            VmEvent::HeadMoved { to, .. } => {
+                self.right = self.right.max(to - LEFT_PADDING);
+            }
+            // Cells are 32 bits wide, so going below zero wraps around to
+            // the top half of their range.
+            VmEvent::CellChanged { new, .. } if new > u32::MAX / 2 => {
+                self.below_zero = true;
+            }
+            VmEvent::CellChanged { new, .. } if new > 255 => {
+                self.above_byte = true;
+            }
+            _ => {}
+        }
+    }
+}
+
+/// How a preset fared when running the program.
+#[derive(Debug)]
+struct PresetRun {
+    preset: Profile,
+    execution: Execution,
+    /// Whether the output was what was expected, if it was given.
+    expected: Option<bool>,
+}
+
+impl PresetRun {
+    /// Whether the program ran correctly with the preset.
+    fn compatible(&self) -> bool {
+        matches!(self.execution.outcome, Outcome::Halted)
+            && self.expected != Some(false)
+    }
+}
+
+/// The behaviors a program depends on, and the presets it runs under.
+#[derive(Debug)]
+struct Compat {
+    /// Whether the first move of the head in the source is to the left.
+    starts_left: bool,
+    /// Whether the program has a `,` at all.
+    reads_input: bool,
+    observed: Observed,
+    /// Whether the instrumented run was cut short, by the step limit or by
+    /// going further left than it could see.
+    cut_short: bool,
+    /// Whether the output differs between 8 and 32 bit cells.
+    width_matters: bool,
+    /// Whether the program reads past the end of its input.
+    reads_past_eof: bool,
+    /// Whether the output differs between the EOF conventions.
+    eof_matters: bool,
+    presets: Vec<PresetRun>,
+}
+
+/// Whether the first `<` or `>` in the source is a `<`, which moves left of
+/// the first cell unless it is in a loop which is skipped.
+fn starts_left(program: &BfProgram) -> bool {
+    program
+        .instructions()
+        .iter()
+        .map(|instruction| instruction.operation())
+        .find(|operation| {
+            matches!(
+                operation,
+                Operation::IncrementPointer | Operation::DecrementPointer
+            )
+        })
+        == Some(Operation::DecrementPointer)
+}
+
+/// Runs the program with 32 bit cells on a tape which grows, starting some
+/// way in so that moves left of the first cell can be seen, returning what
+/// it did and whether it was cut short.
+fn observe(
+    program: &BfProgram,
+    settings: &Settings,
+    input: &[u8],
+    step_limit: u64,
+) -> (Observed, bool) {
+    let settings = Settings {
+        extensible: true,
+        eof: EofBehavior::Zero,
+        ..settings.clone()
+    };
+    let mut observed = Observed::default();
+    let mut sink = |event: &VmEvent| observed.see(event);
+    let mut vm = settings
+        .virtual_machine_on_tape::<u32>(
+            program,
+            vec![0; LEFT_PADDING + 1],
+            LEFT_PADDING,
+        )
+        .with_step_limit(step_limit)
+        .with_event_sink(&mut sink);
+    let result = vm.interpret(&mut &input[..], &mut Vec::new());
+    drop(vm);
+    let cut_short = matches!(
+        result,
+        Err(VirtualMachineError::StepLimitExceeded { .. })
+            | Err(VirtualMachineError::InvalidHeadPosition { .. })
+    );
+    (observed, cut_short)
+}
+
+/// Works out which behaviors the program depends on when run on the input,
+/// and runs it under each preset, comparing the output to the expected one
+/// if it is given.
+fn analyze(
+    program: &BfProgram,
+    settings: &Settings,
+    input: &[u8],
+    expected: Option<&[u8]>,
+) -> Compat {
+    let step_limit = settings.max_steps.unwrap_or(DEFAULT_STEP_LIMIT);
+    let (observed, cut_short) = observe(program, settings, input, step_limit);
+    let run = |cell_width, eof| {
+        let settings = Settings {
+            cell_width,
+            eof,
+            extensible: true,
+            ..settings.clone()
+        };
+        execute(program, &settings, input, step_limit)
+    };
+
+    let narrow = run(CellWidth::U8, EofBehavior::Zero);
+    let wide = run(CellWidth::U32, EofBehavior::Zero);
+    let strict = run(settings.cell_width, EofBehavior::Error);
+    let outputs: Vec<Vec<u8>> = EOF_CONVENTIONS
+        .iter()
+        .map(|eof| run(settings.cell_width, *eof).output)
+        .collect();
+
+    let presets = Profile::all()
+        .iter()
+        .map(|&preset| {
+            let settings = Settings {
+                // Every preset has a valid cell width.
+                cell_width: CellWidth::try_from(preset.cell_bits()).unwrap(),
+                cells: preset.tape_length(),
+                extensible: preset.growable(),
+                eof: preset.eof_behavior(),
+                ..settings.clone()
+            };
+            let execution = execute(program, &settings, input, step_limit);
+            PresetRun {
+                preset,
+                expected: expected.map(|bytes| execution.output == bytes),
+                execution,
+            }
+        })
+        .collect();
+
+    Compat {
+        starts_left: starts_left(program),
+        reads_input: program
+            .instructions()
+            .iter()
+            .any(|instruction| instruction.operation() == Operation::InputByte),
+        observed,
+        cut_short,
+        width_matters: narrow.output != wide.output,
+        reads_past_eof: matches!(
+            strict.outcome,
+            Outcome::Error(VirtualMachineError::InputExhausted { .. })
+        ),
+        eof_matters: outputs.windows(2).any(|pair| pair[0] != pair[1]),
+        presets,
+    }
+}
+
+/// Shows the start of the output, escaped, and how long it is if there is
+/// more.
+fn preview(output: &[u8]) -> String {
+    if output.len() <= OUTPUT_PREVIEW {
+        format!("\"{}\"", output.escape_ascii())
+    } else {
+        format!(
+            "\"{}\"... ({} bytes)",
+            output[..OUTPUT_PREVIEW].escape_ascii(),
+            output.len()
+        )
+    }
+}
+
+/// Writes yes or no.
+fn yes_no(value: bool) -> &'static str {
+    if value {
+        "yes"
+    } else {
+        "no"
+    }
+}
+
+/// What symbolic execution found out about the program, for any input.
+#[derive(Debug)]
+struct Symbolic {
+    /// The most steps each symbolic run took.
+    depth: u64,
+    /// Whether the head can move left of the first cell.
+    head: Answer,
+    /// Which input writes the expected output, if it was given.
+    output: Option<Answer>,
+}
+
+impl Symbolic {
+    /// Whether no input moves the head left of the first cell, and, if there
+    /// is an expected output, some input may write it.
+    fn passed(&self) -> bool {
+        !matches!(self.head, Answer::Found(_))
+            && !matches!(self.output, Some(Answer::Impossible))
+    }
+
+    /// Describes an answer, which is "yes" if there is input which does it.
+    fn answer(&self, answer: &Answer) -> String {
+        match answer {
+            Answer::Found(witness) => match witness.instruction() {
+                Some(at) => format!(
+                    "yes, at {}:{}{}",
+                    at.line(),
+                    at.column(),
+                    given(witness.input())
+                ),
+                None => format!("yes{}", given(witness.input())),
+            },
+            Answer::Impossible => "no, for any input".to_string(),
+            Answer::Unknown => {
+                format!("not known within {} steps a run", self.depth)
+            }
+        }
+    }
+}
+
+/// Describes the input found by symbolic execution.
+fn given(input: &[u8]) -> String {
+    if input.is_empty() {
+        ", without reading input".to_string()
+    } else {
+        format!(", given the input \"{}\"", input.escape_ascii())
+    }
+}
+
+/// Finds the position of the instruction at a location given as
+/// `LINE:COL`.
+fn find_instruction(program: &BfProgram, spec: &str) -> Result<usize, String> {
+    let location = spec.split_once(':').and_then(|(line, column)| {
+        Some((line.parse::<usize>().ok()?, column.parse::<usize>().ok()?))
+    });
+    let Some((line, column)) = location else {
+        return Err(format!(
+            "invalid location '{}', expected <line>:<column>",
+            spec
+        ));
+    };
+    program
+        .instructions()
+        .iter()
+        .position(|instruction| {
+            instruction.line() == line && instruction.column() == column
+        })
+        .ok_or_else(|| format!("there is no instruction at {}", spec))
+}
+
+/// Whether the control flow of the program can get to the instruction at
+/// the position: that is, whether the passes of the optimizer which drop
+/// loops which are never entered, and code after loops which never end, keep
+/// it.
+fn in_control_flow(program: &BfProgram, position: usize) -> bool {
+    let Ok(mut ir) = IrProgram::from_program(program) else {
+        return true;
+    };
+    DeadLoop::on_fresh_tape().run(&mut ir);
+    Unreachable::on_fresh_tape().run(&mut ir);
+    let at = program.instructions()[position];
+    ir.nodes().iter().any(|node| {
+        node.source().line() == at.line()
+            && node.source().column() == at.column()
+    })
+}
+
+/// Whether an instruction of the program can ever run.
+#[derive(Debug)]
+struct Reachability {
+    instruction: InstructionInfo,
+    /// The most steps each symbolic run took.
+    depth: u64,
+    /// What symbolic execution found, if the control flow of the program can
+    /// get to the instruction at all.
+    answer: Option<Answer>,
+}
+
+impl Reachability {
+    /// Works out whether the instruction at the position can ever run,
+    /// running the executor only if the control flow can get to it.
+    fn new(
+        program: &BfProgram,
+        executor: &SymbolicExecutor,
+        depth: u64,
+        position: usize,
+    ) -> Self {
+        Self {
+            instruction: program.instructions()[position],
+            depth,
+            answer: in_control_flow(program, position)
+                .then(|| executor.reaches(position)),
+        }
+    }
+
+    /// Whether the instruction may run, rather than never running.
+    fn reachable(&self) -> bool {
+        !matches!(self.answer, None | Some(Answer::Impossible))
+    }
+}
+
+impl fmt::Display for Reachability {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let answer = match &self.answer {
+            None => "no, the control flow never gets to it".to_string(),
+            Some(Answer::Found(witness)) => {
+                format!("yes{}", given(witness.input()))
+            }
+            Some(Answer::Impossible) => "no, for any input".to_string(),
+            Some(Answer::Unknown) => {
+                format!("not known within {} steps a run", self.depth)
+            }
+        };
+        writeln!(f, "Reachability (experimental):")?;
+        writeln!(
+            f,
+            "  the `{}` at {}:{} runs: {}",
+            self.instruction.operation().to_char(),
+            self.instruction.line(),
+            self.instruction.column(),
+            answer
+        )
+    }
+}
+
+/// Describes cells compactly, such as "cells 0-2 and 5".
+fn describe_cells(cells: Option<&BTreeSet<i64>>) -> String {
+    let Some(cells) = cells else {
+        return "any cell".to_string();
+    };
+    let mut ranges: Vec<(i64, i64)> = Vec::new();
+    for &cell in cells {
+        match ranges.last_mut() {
+            Some((_, end)) if *end + 1 == cell => *end = cell,
+            _ => ranges.push((cell, cell)),
+        }
+    }
+    let mut ranges: Vec<String> = ranges
+        .into_iter()
+        .map(|(start, end)| match start == end {
+            true => start.to_string(),
+            false => format!("{}-{}", start, end),
+        })
+        .collect();
+    let last = ranges.pop().unwrap_or_default();
+    let noun = if cells.len() == 1 { "cell" } else { "cells" };
+    match ranges.is_empty() {
+        true => format!("{} {}", noun, last),
+        false => format!("{} {} and {}", noun, ranges.join(", "), last),
+    }
+}
+
+/// Describes what a segment touches.
+fn describe_segment(segment: &Segment) -> String {
+    let mut parts = Vec::new();
+    match (segment.reads(), segment.writes()) {
+        (None, None) => parts.push("touches any cell".to_string()),
+        (reads, writes) if reads == writes => {
+            parts.push(format!("reads and writes {}", describe_cells(reads)))
+        }
+        (reads, writes) => {
+            if reads.is_none_or(|cells| !cells.is_empty()) {
+                parts.push(format!("reads {}", describe_cells(reads)));
+            }
+            if writes.is_none_or(|cells| !cells.is_empty()) {
+                parts.push(format!("writes {}", describe_cells(writes)));
+            }
+        }
+    }
+    if segment.input() {
+        parts.push("reads input".to_string());
+    }
+    if segment.output() {
+        parts.push("writes output".to_string());
+    }
+    parts.join(", ")
+}
+
+/// The segments of the program, and which of them could run in parallel.
+#[derive(Debug)]
+struct IndependenceReport(Independence);
+
+impl fmt::Display for IndependenceReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let segments = self.0.segments();
+        let span = |start: &Segment, end: &Segment| {
+            format!(
+                "{}:{}-{}:{}",
+                start.start().line(),
+                start.start().column(),
+                end.end().line(),
+                end.end().column()
+            )
+        };
+        writeln!(f, "Segments:")?;
+        for (number, segment) in segments.iter().enumerate() {
+            writeln!(
+                f,
+                "  {:<4} {:<14} {}",
+                number + 1,
+                span(segment, segment),
+                describe_segment(segment)
+            )?;
+        }
+        if self.0.sections().is_empty() {
+            return writeln!(
+                f,
+                "No consecutive segments could run in parallel."
+            );
+        }
+        writeln!(f, "Sections which could run in parallel:")?;
+        for range in self.0.sections() {
+            let section = &segments[range.clone()];
+            let touched = section
+                .iter()
+                .map(Segment::touches)
+                .collect::<Option<Vec<_>>>()
+                .map(|cells| cells.into_iter().flatten().collect());
+            writeln!(
+                f,
+                "  segments {}-{} ({}), touching {}",
+                range.start + 1,
+                range.end,
+                span(&section[0], &section[section.len() - 1]),
+                describe_cells(touched.as_ref())
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Symbolic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Symbolic execution (experimental):")?;
+        writeln!(
+            f,
+            "  moves left of the first cell: {}",
+            self.answer(&self.head)
+        )?;
+        if let Some(output) = &self.output {
+            writeln!(
+                f,
+                "  writes the expected output: {}",
+                self.answer(output)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Compat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let observed = &self.observed;
+        writeln!(f, "Behaviors the program depends on:")?;
+        write!(
+            f,
+            "  moves left of the first cell: {}",
+            yes_no(observed.left > 0)
+        )?;
+        if observed.left > 0 {
+            write!(f, ", by up to {} cells", observed.left)?;
+        } else if self.starts_left {
+            write!(f, ", though its first move is to the left")?;
+        }
+        writeln!(f)?;
+        writeln!(f, "  cells used: {}", observed.right + 1)?;
+        let range = match (observed.below_zero, observed.above_byte) {
+            (false, false) => "no".to_string(),
+            (true, false) => "yes, below 0".to_string(),
+            (false, true) => "yes, above 255".to_string(),
+            (true, true) => "yes, below 0 and above 255".to_string(),
+        };
+        writeln!(f, "  cells leave 0 to 255: {}", range)?;
+        writeln!(
+            f,
+            "  output depends on the cell width: {}",
+            yes_no(self.width_matters)
+        )?;
+        writeln!(
+            f,
+            "  reads past the end of the input: {}",
+            yes_no(self.reads_past_eof)
+        )?;
+        writeln!(
+            f,
+            "  output depends on the EOF convention: {}",
+            yes_no(self.eof_matters)
+        )?;
+        if !self.reads_input {
+            writeln!(f, "  (the program never reads input)")?;
+        }
+        if self.cut_short {
+            writeln!(
+                f,
+                "  (the run was cut short, so these are only what was seen \
+                before then)"
+            )?;
+        }
+
+        writeln!(f, "Presets:")?;
+        for run in &self.presets {
+            let verdict = match run.expected {
+                Some(true) => ", as expected",
+                Some(false) => ", not as expected",
+                None => "",
+            };
+            writeln!(
+                f,
+                "  {:<16} output {} and {}{}",
+                run.preset.to_string(),
+                preview(&run.execution.output),
+                run.execution.outcome.describe(),
+                verdict
+            )?;
+        }
+        let compatible: Vec<String> = self
+            .presets
+            .iter()
+            .filter(|run| run.compatible())
+            .map(|run| run.preset.to_string())
+            .collect();
+        if compatible.is_empty() {
+            return write!(f, "Runs correctly under none of the presets.");
+        }
+        write!(f, "Runs correctly under: {}.", compatible.join(", "))?;
+        let mut halted = self
+            .presets
+            .iter()
+            .filter(|run| run.compatible())
+            .map(|run| &run.execution.output);
+        let first = halted.next();
+        if halted.any(|output| Some(output) != first) {
+            write!(
+                f,
+                "\nThe presets disagree on the output, give the output the \
+                program should write with --expect to pick between them."
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Analyzes the program, printing the behaviors it depends on and the presets
+/// it runs correctly under. Fails if it runs correctly under none of them.
+pub(crate) fn run_analyze(
+    args: &AnalyzeArgs,
+) -> Result<ExitCode, Box<dyn Error>> {
+    let settings = Settings::from_args(&args.run)?;
+    let program = load_program(&args.filename, &settings)?;
+    let input = match &args.input {
+        Some(path) => fs::read(path)?,
+        None => Vec::new(),
+    };
+    let expected = match &args.expect {
+        Some(path) => Some(fs::read(path)?),
+        None => None,
+    };
+    let mut compatible = true;
+    if args.compat {
+        let compat = analyze(&program, &settings, &input, expected.as_deref());
+        println!("{}", compat);
+        compatible = compat.presets.iter().any(PresetRun::compatible);
+    }
+    let executor = SymbolicExecutor::new(&program, settings.cell_width.bits())
+        .with_max_steps(args.depth);
+    if args.symbolic {
+        let symbolic = Symbolic {
+            depth: args.depth,
+            head: executor.head_below_zero(),
+            output: expected
+                .as_deref()
+                .map(|expected| executor.input_for_output(expected)),
+        };
+        print!("{}", symbolic);
+        compatible &= symbolic.passed();
+    }
+    if args.independence {
+        print!("{}", IndependenceReport(Independence::of(&program)));
+    }
+    if let Some(spec) = &args.reachable {
+        let position = find_instruction(&program, spec)?;
+        let reachability =
+            Reachability::new(&program, &executor, args.depth, position);
+        print!("{}", reachability);
+        compatible &= reachability.reachable();
+    }
+    if compatible {
+        Ok(ExitCode::SUCCESS)
+    } else {
+        Ok(ExitCode::FAILURE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{analyze, describe_cells, find_instruction};
+    use super::{IndependenceReport, Observed, Reachability, Symbolic};
+    use crate::config::settings;
+    use bft_interp::independence::Independence;
+    use bft_interp::preset::Profile;
+    use bft_interp::symexec::SymbolicExecutor;
+    use bft_types::BfProgram;
+    use std::collections::BTreeSet;
+
+    fn program(source: &str) -> BfProgram {
+        BfProgram::new(source.to_string(), "compat.bf").unwrap()
+    }
+
+    #[test]
+    fn test_portable_program() {
+        let compat =
+            analyze(&program("++>+++[-<+>]<."), &settings(&[]), b"", None);
+        assert_eq!(
+            compat.observed,
+            Observed {
+                left: 0,
+                right: 1,
+                below_zero: false,
+                above_byte: false,
+            }
+        );
+        assert!(!compat.width_matters);
+        assert!(!compat.reads_past_eof);
+        assert!(compat.presets.iter().all(|run| run.compatible()));
+    }
+
+    #[test]
+    fn test_dependencies() {
+        // Wraps a cell below zero, but only prints its low byte.
+        let compat = analyze(&program("-."), &settings(&[]), b"", None);
+        assert!(compat.observed.below_zero);
+        assert!(!compat.width_matters);
+
+        // Counts until a cell wraps back around to zero.
+        let compat = analyze(&program(">+[<+>+]<."), &settings(&[]), b"", None);
+        assert!(compat.observed.above_byte);
+        assert!(compat.width_matters);
+
+        // Moves left of the first cell, which no preset allows.
+        let compat = analyze(&program("<<+>>"), &settings(&[]), b"", None);
+        assert_eq!(compat.observed.left, 2);
+        assert!(compat.starts_left);
+        assert!(!compat.presets.iter().any(|run| run.compatible()));
+
+        // Prints what `,` gives at the end of the input.
+        let compat = analyze(&program("+,."), &settings(&[]), b"", None);
+        assert!(compat.reads_past_eof);
+        assert!(compat.eof_matters);
+        let compatible: Vec<Profile> =
+            analyze(&program("+,."), &settings(&[]), b"", Some(b"\x01"))
+                .presets
+                .iter()
+                .filter(|run| run.compatible())
+                .map(|run| run.preset)
+                .collect();
+        assert_eq!(compatible, [Profile::Classic]);
+    }
+
+    #[test]
+    fn test_report() {
+        let compat = analyze(&program(",[.,]"), &settings(&[]), b"hi", None);
+        let report = compat.to_string();
+        assert!(report.contains("  reads past the end of the input: yes\n"));
+        // Leaving the cell unchanged at the end of the input repeats the last
+        // byte forever.
+        assert!(report.contains("  classic          output \"hiiii"));
+        assert!(report.contains("bytes) and hit the step limit\n"));
+        assert!(report
+            .ends_with("Runs correctly under: wrap-tape-16bit, extended."));
+
+        let program =
+            BfProgram::from_file("bf-programs/cell-width.bf").unwrap();
+        let compat = analyze(&program, &settings(&[]), b"", None);
+        assert!(compat.to_string().ends_with("pick between them."));
+    }
+
+    #[test]
+    fn test_symbolic() {
+        // Moves left of the first cell when its input is zero, so it can
+        // never write zero.
+        let program = program(",[>]<.");
+        let executor = SymbolicExecutor::new(&program, 8);
+        let symbolic = Symbolic {
+            depth: 10_000,
+            head: executor.head_below_zero(),
+            output: Some(executor.input_for_output(b"\0")),
+        };
+        assert!(!symbolic.passed());
+        assert_eq!(
+            symbolic.to_string(),
+            "Symbolic execution (experimental):\n  \
+            moves left of the first cell: yes, at 1:5, given the input \
+            \"\\x00\"\n  \
+            writes the expected output: no, for any input\n"
+        );
+    }
+
+    #[test]
+    fn test_independence() {
+        let cells: BTreeSet<i64> = [0, 1, 2, 5, 7, 8].into();
+        assert_eq!(describe_cells(Some(&cells)), "cells 0-2, 5 and 7-8");
+        assert_eq!(describe_cells(Some(&[3].into())), "cell 3");
+        assert_eq!(describe_cells(None), "any cell");
+
+        let report = IndependenceReport(Independence::of(&program(
+            "+>++>+++<<[->>>++<<<]>>>.[>]",
+        )));
+        assert_eq!(
+            report.to_string(),
+            "Segments:\n\
+            \x20 1    1:1-1:1        reads and writes cell 0\n\
+            \x20 2    1:3-1:4        reads and writes cell 1\n\
+            \x20 3    1:6-1:8        reads and writes cell 2\n\
+            \x20 4    1:11-1:21      reads and writes cells 0 and 3\n\
+            \x20 5    1:25-1:25      reads cell 3, writes output\n\
+            \x20 6    1:26-1:28      touches any cell\n\
+            Sections which could run in parallel:\n\
+            \x20 segments 1-3 (1:1-1:8), touching cells 0-2\n"
+        );
+    }
+
+    #[test]
+    fn test_reachable() {
+        // The `.` only runs for the input 3, and the `>` after the endless
+        // loop never does.
+        let program = program(",---[[-]+[]>]\n+.");
+        let executor = SymbolicExecutor::new(&program, 8);
+        let reachability = |spec: &str| {
+            let position = find_instruction(&program, spec).unwrap();
+            Reachability::new(&program, &executor, 10_000, position)
+        };
+        let output = reachability("2:2");
+        assert!(output.reachable());
+        assert_eq!(
+            output.to_string(),
+            "Reachability (experimental):\n  \
+            the `.` at 2:2 runs: yes, given the input \"\\x03\"\n"
+        );
+        let after_endless_loop = reachability("1:12");
+        assert!(!after_endless_loop.reachable());
+        assert!(after_endless_loop
+            .to_string()
+            .ends_with("runs: no, the control flow never gets to it\n"));
+
+        assert!(find_instruction(&program, "2:3").is_err());
+        assert!(find_instruction(&program, "2").is_err());
+    }
+}
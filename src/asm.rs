@@ -0,0 +1,78 @@
+//! The `asm` subcommand, which assembles programs written in Brainfuck
+//! assembly down to Brainfuck or to the intermediate representation.
+
+use std::error::Error;
+use std::fs;
+use std::process::ExitCode;
+
+use bft_interp::asm::AsmProgram;
+use bft_interp::ir::{IrOp, IrProgram};
+
+use crate::cli::{AsmCommand, Emit};
+
+/// Lists the nodes of the program one to a line, along with the line and
+/// column of the statement each came from, indented by how deeply they are
+/// nested in loops.
+fn list_ir(ir: &IrProgram) -> String {
+    let mut listing = String::new();
+    let mut depth = 0;
+    for node in ir.nodes() {
+        if node.op() == &IrOp::LoopEnd {
+            depth -= 1;
+        }
+        let source = node.source();
+        listing.push_str(&format!(
+            "{:>4}:{:<3} {}{:?}\n",
+            source.line(),
+            source.column(),
+            "  ".repeat(depth),
+            node.op()
+        ));
+        if node.op() == &IrOp::LoopStart {
+            depth += 1;
+        }
+    }
+    listing
+}
+
+/// Runs the `asm` subcommand.
+pub(crate) fn run_asm(
+    command: &AsmCommand,
+) -> Result<ExitCode, Box<dyn Error>> {
+    match command {
+        AsmCommand::Build(args) => {
+            let program = AsmProgram::from_file(&args.filename)?;
+            let code = match args.emit {
+                Emit::Brainfuck => program.to_brainfuck(),
+                Emit::Ir => list_ir(&program.to_ir()),
+            };
+            match &args.output {
+                Some(path) => fs::write(path, code)?,
+                None => print!("{}", code),
+            }
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::list_ir;
+    use bft_interp::asm::AsmProgram;
+
+    #[test]
+    fn test_list_ir() {
+        let program =
+            AsmProgram::parse("IN\nLOOP\n  SET 2\n  OUT\nEND", "test.bfa")
+                .unwrap();
+        assert_eq!(
+            list_ir(&program.to_ir()),
+            "   1:1   Input\n\
+             \x20  2:1   LoopStart\n\
+             \x20  3:3     Clear\n\
+             \x20  3:3     Add(2)\n\
+             \x20  4:3     Output\n\
+             \x20  5:1   LoopEnd\n"
+        );
+    }
+}
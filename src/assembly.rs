@@ -0,0 +1,872 @@
+//! Compiles the intermediate representation into an annotated assembly
+//! listing for x86-64 or AArch64 Linux, for `bft compile --target asm`. Each
+//! operation is preceded by a comment giving the line and column it came
+//! from, so that the listing can be read alongside the program. The listing
+//! can be built into an executable with a C compiler, which links it against
+//! the C library for its input and output.
+
+use std::error::Error;
+
+use bft_interp::eof::EofBehavior;
+use bft_interp::ir::{IrOp, IrProgram};
+
+use crate::cli::Arch;
+use crate::compile::{wrap, Open};
+use crate::config::Settings;
+
+/// The number of values written on each line of a data directive.
+const VALUES_PER_LINE: usize = 12;
+
+/// The message written to stderr when the program reads past the end of its
+/// input, and reaching the end of the input is an error.
+const EOF_MESSAGE: &[u8] = b"bft: the program read past the end of its input\n";
+
+/// Escapes bytes for an assembler string.
+fn assembler_string(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|&byte| match byte {
+            b'"' | b'\\' => format!("\\{}", byte as char),
+            b' '..=b'~' => (byte as char).to_string(),
+            _ => format!("\\{:03o}", byte),
+        })
+        .collect()
+}
+
+/// Writes a line of the listing, indenting everything but labels.
+fn line(code: &mut String, text: &str) {
+    if !text.is_empty() && !text.ends_with(':') {
+        code.push('\t');
+    }
+    code.push_str(text);
+    code.push('\n');
+}
+
+/// Summarises an operation for the comment above its instructions, leaving
+/// out the contents of long constants.
+pub(crate) fn summary(op: &IrOp) -> String {
+    match op {
+        IrOp::OutputBytes(bytes) if bytes.len() > 16 => {
+            format!("OutputBytes({} bytes)", bytes.len())
+        }
+        IrOp::OutputBytes(bytes) => {
+            format!("OutputBytes(\"{}\")", bytes.escape_ascii())
+        }
+        IrOp::LoadTape(values) => format!("LoadTape({} cells)", values.len()),
+        op => format!("{:?}", op),
+    }
+}
+
+/// Builds the listing, numbering its labels.
+struct Listing {
+    arch: Arch,
+    /// The number of bits in each cell.
+    bits: u32,
+    eof: EofBehavior,
+    code: String,
+    /// The constants the code refers to, written out after it.
+    data: String,
+    labels: usize,
+    open: Vec<Open>,
+}
+
+impl Listing {
+    fn new(arch: Arch, settings: &Settings) -> Self {
+        Self {
+            arch,
+            bits: settings.cell_width.bits(),
+            eof: settings.eof,
+            code: String::new(),
+            data: String::new(),
+            labels: 0,
+            open: Vec::new(),
+        }
+    }
+
+    /// The number of bytes in each cell.
+    fn size(&self) -> i64 {
+        (self.bits / 8).into()
+    }
+
+    /// Writes an instruction or directive.
+    fn emit(&mut self, text: &str) {
+        self.code.push('\t');
+        self.code.push_str(text);
+        self.code.push('\n');
+    }
+
+    /// Writes a comment on a line of its own.
+    fn comment(&mut self, text: &str) {
+        let marker = match self.arch {
+            Arch::X86_64 => "#",
+            Arch::Aarch64 => "//",
+        };
+        self.emit(&format!("{} {}", marker, text));
+    }
+
+    /// Starts a label.
+    fn label(&mut self, label: &str) {
+        self.code.push_str(label);
+        self.code.push_str(":\n");
+    }
+
+    /// Numbers the labels of an operation.
+    fn number(&mut self) -> usize {
+        self.labels += 1;
+        self.labels
+    }
+
+    /// Adds a constant to the read only data, returning its label.
+    fn constant(&mut self, directive: &str, values: &[String]) -> String {
+        let label = format!(".Ldata{}", self.number());
+        self.data.push_str(&label);
+        self.data.push_str(":\n");
+        for values in values.chunks(VALUES_PER_LINE) {
+            self.data.push_str(&format!(
+                "\t{} {}\n",
+                directive,
+                values.join(", ")
+            ));
+        }
+        label
+    }
+
+    /// Adds the values as a constant of cells, returning its label.
+    fn cells(&mut self, values: &[u32]) -> String {
+        let directive = match self.bits {
+            8 => ".byte",
+            16 => ".short",
+            _ => ".long",
+        };
+        let values: Vec<String> =
+            values.iter().map(|value| value.to_string()).collect();
+        self.constant(directive, &values)
+    }
+
+    /// Adds the bytes as a constant, returning its label.
+    fn bytes(&mut self, bytes: &[u8]) -> String {
+        self.constant(".ascii", &[format!("\"{}\"", assembler_string(bytes))])
+    }
+
+    /// Finds the loop which a `LoopEnd` ends.
+    fn end_loop(&mut self) -> Result<usize, Box<dyn Error>> {
+        match self.open.pop() {
+            Some(Open::Loop(number)) => Ok(number),
+            _ => Err("the loops of the program do not match".into()),
+        }
+    }
+
+    /// Finds the branch which an `Else` or `EndIf` belongs to, and whether
+    /// its `Else` has been seen.
+    fn end_branch(
+        &mut self,
+        at_else: bool,
+    ) -> Result<(usize, bool), Box<dyn Error>> {
+        match self.open.pop() {
+            Some(Open::If(number, false)) if at_else => Ok((number, false)),
+            Some(Open::If(number, seen_else)) if !at_else => {
+                Ok((number, seen_else))
+            }
+            _ => Err("the branches of the program do not match".into()),
+        }
+    }
+}
+
+/// Lowers operations to x86-64, in Intel syntax. `rbx` points to the cell at
+/// the head, and `eax`, `ecx` and `edx` hold values while they are worked on.
+impl Listing {
+    /// The address of the cell at the offset from the head, or from the cell
+    /// `rcx` cells along if `indexed`.
+    fn x86_address(&self, offset: isize, indexed: bool) -> String {
+        let index = match indexed {
+            true if self.size() == 1 => " + rcx".to_string(),
+            true => format!(" + rcx*{}", self.size()),
+            false => String::new(),
+        };
+        let offset = offset as i64 * self.size();
+        let displacement = match offset {
+            0 => String::new(),
+            offset if offset < 0 => format!(" - {}", offset.unsigned_abs()),
+            offset => format!(" + {}", offset),
+        };
+        format!("[rbx{}{}]", index, displacement)
+    }
+
+    /// The operand of the cell at the offset from the head, or from the cell
+    /// `rcx` cells along if `indexed`.
+    fn x86_cell(&self, offset: isize, indexed: bool) -> String {
+        let pointer = match self.bits {
+            8 => "BYTE",
+            16 => "WORD",
+            _ => "DWORD",
+        };
+        format!("{} PTR {}", pointer, self.x86_address(offset, indexed))
+    }
+
+    /// The name of the part of the register `a`, `c` or `d` that holds a
+    /// cell.
+    fn x86_register(&self, register: char) -> String {
+        match self.bits {
+            8 => format!("{}l", register),
+            16 => format!("{}x", register),
+            _ => format!("e{}x", register),
+        }
+    }
+
+    /// Loads the cell at the offset into the 32 bit register, zero extended.
+    fn x86_load(&mut self, register: &str, offset: isize) {
+        let cell = self.x86_cell(offset, false);
+        match self.bits {
+            32 => self.emit(&format!("mov {}, {}", register, cell)),
+            _ => self.emit(&format!("movzx {}, {}", register, cell)),
+        }
+    }
+
+    /// Adds the amount to the operand, wrapping around.
+    fn x86_add(&mut self, target: &str, amount: i64) {
+        match wrap(amount, self.bits) {
+            0 => {}
+            amount if amount < 0 => {
+                self.emit(&format!("sub {}, {}", target, amount.unsigned_abs()))
+            }
+            amount => self.emit(&format!("add {}, {}", target, amount)),
+        }
+    }
+
+    /// Moves the head by the number of cells.
+    fn x86_move(&mut self, cells: i64) {
+        match cells * self.size() {
+            0 => {}
+            bytes if bytes < 0 => {
+                self.emit(&format!("sub rbx, {}", bytes.unsigned_abs()))
+            }
+            bytes => self.emit(&format!("add rbx, {}", bytes)),
+        }
+    }
+
+    /// Compares the cell at the head with zero.
+    fn x86_test(&mut self) {
+        let cell = self.x86_cell(0, false);
+        self.emit(&format!("cmp {}, 0", cell));
+    }
+
+    /// Adds the value of `eax` times each factor to the cell at each offset.
+    fn x86_products(&mut self, targets: &[(isize, i32)]) {
+        for &(offset, factor) in targets {
+            self.emit(&format!("imul ecx, eax, {}", factor));
+            let cell = self.x86_cell(offset, false);
+            let register = self.x86_register('c');
+            self.emit(&format!("add {}, {}", cell, register));
+        }
+    }
+
+    fn x86_64(&mut self, op: &IrOp) -> Result<(), Box<dyn Error>> {
+        match op {
+            IrOp::Add(delta) => self.x86_add_at(0, *delta),
+            IrOp::Move(delta) => self.x86_move(*delta as i64),
+            IrOp::Output => {
+                self.x86_load("edi", 0);
+                self.emit("call putchar@PLT");
+            }
+            IrOp::Input => {
+                let label = format!(".Linput{}", self.number());
+                self.emit("call getchar@PLT");
+                self.emit("cmp eax, -1");
+                self.emit(&format!("je {}_eof", label));
+                let cell = self.x86_cell(0, false);
+                let register = self.x86_register('a');
+                self.emit(&format!("mov {}, {}", cell, register));
+                self.emit(&format!("jmp {}_done", label));
+                self.label(&format!("{}_eof", label));
+                match self.eof {
+                    EofBehavior::Error => {
+                        self.emit("mov edi, 2");
+                        self.emit("lea rsi, [rip + .Leof_message]");
+                        self.emit(&format!("mov edx, {}", EOF_MESSAGE.len()));
+                        self.emit("call write@PLT");
+                        self.emit("mov eax, 1");
+                        self.emit("jmp .Lexit");
+                    }
+                    EofBehavior::Zero => self.emit(&format!("mov {}, 0", cell)),
+                    EofBehavior::Unchanged => {}
+                    EofBehavior::MaxValue => {
+                        self.emit(&format!("mov {}, -1", cell))
+                    }
+                }
+                self.label(&format!("{}_done", label));
+            }
+            IrOp::LoopStart => {
+                let number = self.number();
+                self.open.push(Open::Loop(number));
+                self.x86_test();
+                self.emit(&format!("je .Lloop{}_end", number));
+                self.label(&format!(".Lloop{}_body", number));
+            }
+            IrOp::LoopEnd => {
+                let number = self.end_loop()?;
+                self.x86_test();
+                self.emit(&format!("jne .Lloop{}_body", number));
+                self.label(&format!(".Lloop{}_end", number));
+            }
+            IrOp::If => {
+                let number = self.number();
+                self.open.push(Open::If(number, false));
+                self.x86_test();
+                self.emit(&format!("je .Lif{}_else", number));
+            }
+            IrOp::Else => {
+                let (number, _) = self.end_branch(true)?;
+                self.open.push(Open::If(number, true));
+                self.emit(&format!("jmp .Lif{}_end", number));
+                self.label(&format!(".Lif{}_else", number));
+            }
+            IrOp::EndIf => {
+                let (number, seen_else) = self.end_branch(false)?;
+                if !seen_else {
+                    self.label(&format!(".Lif{}_else", number));
+                }
+                self.label(&format!(".Lif{}_end", number));
+            }
+            IrOp::Clear => self.x86_clear_at(0),
+            IrOp::AddAt(offset, delta) => self.x86_add_at(*offset, *delta),
+            IrOp::ClearAt(offset) => self.x86_clear_at(*offset),
+            IrOp::AddRange(offset, len, delta) => {
+                let label = format!(".Lrange{}", self.number());
+                self.emit("xor ecx, ecx");
+                self.label(&label);
+                let cell = self.x86_cell(*offset, true);
+                self.x86_add(&cell, (*delta).into());
+                self.emit("inc rcx");
+                self.emit(&format!("cmp rcx, {}", len));
+                self.emit(&format!("jb {}", label));
+            }
+            IrOp::ClearRange(offset, len) => {
+                let address = self.x86_address(*offset, false);
+                self.emit(&format!("lea rdi, {}", address));
+                self.emit("xor esi, esi");
+                self.emit(&format!("mov edx, {}", *len as i64 * self.size()));
+                self.emit("call memset@PLT");
+            }
+            IrOp::CopyLoop(targets) => {
+                let label = format!(".Lcopy{}", self.number());
+                self.x86_load("eax", 0);
+                self.emit("test eax, eax");
+                self.emit(&format!("je {}_end", label));
+                self.x86_products(targets);
+                self.x86_clear_at(0);
+                self.label(&format!("{}_end", label));
+            }
+            IrOp::MulAdd(mul) => {
+                let label = format!(".Lmul{}", self.number());
+                self.x86_load("eax", 0);
+                self.emit("test eax, eax");
+                self.emit(&format!("je {}_end", label));
+                for &(offset, by, factor) in &mul.products {
+                    self.x86_load("ecx", by);
+                    self.emit("imul ecx, eax");
+                    self.emit(&format!("imul ecx, ecx, {}", factor));
+                    let cell = self.x86_cell(offset, false);
+                    let register = self.x86_register('c');
+                    self.emit(&format!("add {}, {}", cell, register));
+                }
+                self.x86_products(&mul.adds);
+                for &(offset, value) in &mul.sets {
+                    let cell = self.x86_cell(offset, false);
+                    let value = wrap(value.into(), self.bits);
+                    self.emit(&format!("mov {}, {}", cell, value));
+                }
+                self.x86_clear_at(0);
+                self.label(&format!("{}_end", label));
+            }
+            IrOp::ScanRight(step) | IrOp::ScanLeft(step) => {
+                let label = format!(".Lscan{}", self.number());
+                self.label(&label);
+                self.x86_test();
+                self.emit(&format!("je {}_end", label));
+                let step = *step as i64;
+                self.x86_move(match op {
+                    IrOp::ScanLeft(_) => -step,
+                    _ => step,
+                });
+                self.emit(&format!("jmp {}", label));
+                self.label(&format!("{}_end", label));
+            }
+            IrOp::OutputBytes(bytes) => {
+                let data = self.bytes(bytes);
+                self.emit(&format!("lea rdi, [rip + {}]", data));
+                self.emit("mov esi, 1");
+                self.emit(&format!("mov edx, {}", bytes.len()));
+                self.emit("mov rcx, QWORD PTR [rip + stdout@GOTPCREL]");
+                self.emit("mov rcx, QWORD PTR [rcx]");
+                self.emit("call fwrite@PLT");
+            }
+            IrOp::LoadTape(values) => {
+                let data = self.cells(values);
+                self.emit("mov rdi, rbx");
+                self.emit(&format!("lea rsi, [rip + {}]", data));
+                let length = values.len() as i64 * self.size();
+                self.emit(&format!("mov edx, {}", length));
+                self.emit("call memcpy@PLT");
+            }
+            IrOp::Extension(name) => {
+                return Err(format!(
+                    "the extension instruction '{}' cannot be compiled",
+                    name
+                )
+                .into())
+            }
+        }
+        Ok(())
+    }
+
+    fn x86_add_at(&mut self, offset: isize, delta: i32) {
+        let cell = self.x86_cell(offset, false);
+        self.x86_add(&cell, delta.into());
+    }
+
+    fn x86_clear_at(&mut self, offset: isize) {
+        let cell = self.x86_cell(offset, false);
+        self.emit(&format!("mov {}, 0", cell));
+    }
+}
+
+/// Lowers operations to AArch64. `x19` points to the cell at the head, `x10`
+/// to any other cell being worked on, and `w9` and `w11` to `w13` hold values
+/// while they are worked on.
+impl Listing {
+    /// The instructions which load and store a cell.
+    fn arm_access(&self) -> (&'static str, &'static str) {
+        match self.bits {
+            8 => ("ldrb", "strb"),
+            16 => ("ldrh", "strh"),
+            _ => ("ldr", "str"),
+        }
+    }
+
+    /// Sets the register to the constant, from the literal pool if it is too
+    /// large to fit in a `mov`.
+    fn arm_constant(&mut self, register: &str, value: i64) {
+        if (-65536..65536).contains(&value) {
+            self.emit(&format!("mov {}, #{}", register, value));
+        } else if register.starts_with('w') {
+            self.emit(&format!("ldr {}, ={}", register, value as u32));
+        } else {
+            self.emit(&format!("ldr {}, ={}", register, value));
+        }
+    }
+
+    /// Sets the first register to the second plus the constant.
+    fn arm_add(&mut self, target: &str, source: &str, amount: i64) {
+        match amount {
+            0 if target == source => {}
+            0 => self.emit(&format!("mov {}, {}", target, source)),
+            1..=4095 => {
+                self.emit(&format!("add {}, {}, #{}", target, source, amount))
+            }
+            -4095..=-1 => self.emit(&format!(
+                "sub {}, {}, #{}",
+                target,
+                source,
+                amount.unsigned_abs()
+            )),
+            amount => {
+                let scratch = if target.starts_with('w') {
+                    "w11"
+                } else {
+                    "x11"
+                };
+                self.arm_constant(scratch, amount);
+                self.emit(&format!("add {}, {}, {}", target, source, scratch));
+            }
+        }
+    }
+
+    /// The address of the cell at the offset from the head, working it out
+    /// into `x10` if it is too far away to be part of the instruction.
+    fn arm_cell(&mut self, offset: isize) -> String {
+        let offset = offset as i64 * self.size();
+        match offset {
+            0 => "[x19]".to_string(),
+            -256..=4095 => format!("[x19, #{}]", offset),
+            offset => {
+                self.arm_add("x10", "x19", offset);
+                "[x10]".to_string()
+            }
+        }
+    }
+
+    /// Loads the cell at the offset into the register.
+    fn arm_load(&mut self, register: &str, offset: isize) {
+        let cell = self.arm_cell(offset);
+        let (load, _) = self.arm_access();
+        self.emit(&format!("{} {}, {}", load, register, cell));
+    }
+
+    /// Stores the register into the cell at the offset.
+    fn arm_store(&mut self, register: &str, offset: isize) {
+        let cell = self.arm_cell(offset);
+        let (_, store) = self.arm_access();
+        self.emit(&format!("{} {}, {}", store, register, cell));
+    }
+
+    /// Adds the amount to the cell at the offset, wrapping around.
+    fn arm_add_at(&mut self, offset: isize, amount: i64) {
+        let amount = wrap(amount, self.bits);
+        if amount == 0 {
+            return;
+        }
+        let cell = self.arm_cell(offset);
+        let (load, store) = self.arm_access();
+        self.emit(&format!("{} w9, {}", load, cell));
+        self.arm_add("w9", "w9", amount);
+        self.emit(&format!("{} w9, {}", store, cell));
+    }
+
+    /// Adds the value of `w12` to the cell at the offset.
+    fn arm_accumulate(&mut self, offset: isize) {
+        let cell = self.arm_cell(offset);
+        let (load, store) = self.arm_access();
+        self.emit(&format!("{} w13, {}", load, cell));
+        self.emit("add w13, w13, w12");
+        self.emit(&format!("{} w13, {}", store, cell));
+    }
+
+    /// Moves the head by the number of cells.
+    fn arm_move(&mut self, cells: i64) {
+        self.arm_add("x19", "x19", cells * self.size());
+    }
+
+    /// Sets the register to the address of the label.
+    fn arm_address(&mut self, register: &str, label: &str) {
+        self.emit(&format!("adrp {}, {}", register, label));
+        self.emit(&format!("add {}, {}, :lo12:{}", register, register, label));
+    }
+
+    /// Adds the value of `w9` times each factor to the cell at each offset.
+    fn arm_products(&mut self, targets: &[(isize, i32)]) {
+        for &(offset, factor) in targets {
+            self.arm_constant("w11", factor.into());
+            self.emit("mul w12, w9, w11");
+            self.arm_accumulate(offset);
+        }
+    }
+
+    fn aarch64(&mut self, op: &IrOp) -> Result<(), Box<dyn Error>> {
+        match op {
+            IrOp::Add(delta) => self.arm_add_at(0, (*delta).into()),
+            IrOp::Move(delta) => self.arm_move(*delta as i64),
+            IrOp::Output => {
+                self.arm_load("w0", 0);
+                self.emit("bl putchar");
+            }
+            IrOp::Input => {
+                let label = format!(".Linput{}", self.number());
+                self.emit("bl getchar");
+                self.emit("cmn w0, #1");
+                self.emit(&format!("b.eq {}_eof", label));
+                self.arm_store("w0", 0);
+                self.emit(&format!("b {}_done", label));
+                self.label(&format!("{}_eof", label));
+                match self.eof {
+                    EofBehavior::Error => {
+                        self.emit("mov x0, #2");
+                        self.arm_address("x1", ".Leof_message");
+                        self.emit(&format!("mov x2, #{}", EOF_MESSAGE.len()));
+                        self.emit("bl write");
+                        self.emit("mov w0, #1");
+                        self.emit("b .Lexit");
+                    }
+                    EofBehavior::Zero => self.arm_store("wzr", 0),
+                    EofBehavior::Unchanged => {}
+                    EofBehavior::MaxValue => {
+                        self.emit("mov w9, #-1");
+                        self.arm_store("w9", 0);
+                    }
+                }
+                self.label(&format!("{}_done", label));
+            }
+            IrOp::LoopStart => {
+                let number = self.number();
+                self.open.push(Open::Loop(number));
+                self.arm_load("w9", 0);
+                self.emit(&format!("cbz w9, .Lloop{}_end", number));
+                self.label(&format!(".Lloop{}_body", number));
+            }
+            IrOp::LoopEnd => {
+                let number = self.end_loop()?;
+                self.arm_load("w9", 0);
+                self.emit(&format!("cbnz w9, .Lloop{}_body", number));
+                self.label(&format!(".Lloop{}_end", number));
+            }
+            IrOp::If => {
+                let number = self.number();
+                self.open.push(Open::If(number, false));
+                self.arm_load("w9", 0);
+                self.emit(&format!("cbz w9, .Lif{}_else", number));
+            }
+            IrOp::Else => {
+                let (number, _) = self.end_branch(true)?;
+                self.open.push(Open::If(number, true));
+                self.emit(&format!("b .Lif{}_end", number));
+                self.label(&format!(".Lif{}_else", number));
+            }
+            IrOp::EndIf => {
+                let (number, seen_else) = self.end_branch(false)?;
+                if !seen_else {
+                    self.label(&format!(".Lif{}_else", number));
+                }
+                self.label(&format!(".Lif{}_end", number));
+            }
+            IrOp::Clear => self.arm_store("wzr", 0),
+            IrOp::AddAt(offset, delta) => {
+                self.arm_add_at(*offset, (*delta).into())
+            }
+            IrOp::ClearAt(offset) => self.arm_store("wzr", *offset),
+            IrOp::AddRange(offset, len, delta) => {
+                let label = format!(".Lrange{}", self.number());
+                let (load, store) = self.arm_access();
+                self.arm_add("x10", "x19", *offset as i64 * self.size());
+                self.arm_constant("x12", *len as i64);
+                self.label(&label);
+                self.emit(&format!("{} w9, [x10]", load));
+                self.arm_add("w9", "w9", wrap((*delta).into(), self.bits));
+                self.emit(&format!("{} w9, [x10], #{}", store, self.size()));
+                self.emit("subs x12, x12, #1");
+                self.emit(&format!("b.ne {}", label));
+            }
+            IrOp::ClearRange(offset, len) => {
+                self.arm_add("x0", "x19", *offset as i64 * self.size());
+                self.emit("mov w1, #0");
+                self.arm_constant("x2", *len as i64 * self.size());
+                self.emit("bl memset");
+            }
+            IrOp::CopyLoop(targets) => {
+                let label = format!(".Lcopy{}", self.number());
+                self.arm_load("w9", 0);
+                self.emit(&format!("cbz w9, {}_end", label));
+                self.arm_products(targets);
+                self.arm_store("wzr", 0);
+                self.label(&format!("{}_end", label));
+            }
+            IrOp::MulAdd(mul) => {
+                let label = format!(".Lmul{}", self.number());
+                self.arm_load("w9", 0);
+                self.emit(&format!("cbz w9, {}_end", label));
+                for &(offset, by, factor) in &mul.products {
+                    self.arm_load("w12", by);
+                    self.emit("mul w12, w12, w9");
+                    self.arm_constant("w11", factor.into());
+                    self.emit("mul w12, w12, w11");
+                    self.arm_accumulate(offset);
+                }
+                self.arm_products(&mul.adds);
+                for &(offset, value) in &mul.sets {
+                    self.arm_constant("w11", wrap(value.into(), self.bits));
+                    self.arm_store("w11", offset);
+                }
+                self.arm_store("wzr", 0);
+                self.label(&format!("{}_end", label));
+            }
+            IrOp::ScanRight(step) | IrOp::ScanLeft(step) => {
+                let label = format!(".Lscan{}", self.number());
+                self.label(&label);
+                self.arm_load("w9", 0);
+                self.emit(&format!("cbz w9, {}_end", label));
+                let step = *step as i64;
+                self.arm_move(match op {
+                    IrOp::ScanLeft(_) => -step,
+                    _ => step,
+                });
+                self.emit(&format!("b {}", label));
+                self.label(&format!("{}_end", label));
+            }
+            IrOp::OutputBytes(bytes) => {
+                let data = self.bytes(bytes);
+                self.arm_address("x0", &data);
+                self.emit("mov x1, #1");
+                self.arm_constant("x2", bytes.len() as i64);
+                self.emit("adrp x3, :got:stdout");
+                self.emit("ldr x3, [x3, :got_lo12:stdout]");
+                self.emit("ldr x3, [x3]");
+                self.emit("bl fwrite");
+            }
+            IrOp::LoadTape(values) => {
+                let data = self.cells(values);
+                self.emit("mov x0, x19");
+                self.arm_address("x1", &data);
+                self.arm_constant("x2", values.len() as i64 * self.size());
+                self.emit("bl memcpy");
+            }
+            IrOp::Extension(name) => {
+                return Err(format!(
+                    "the extension instruction '{}' cannot be compiled",
+                    name
+                )
+                .into())
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Compiles the program into an assembly listing for the architecture, with
+/// the line and column of each operation in a comment above its
+/// instructions. The tape is a fixed size array in the `.bss` section.
+///
+/// Compiled programs do not check that the head stays on the tape.
+pub(crate) fn emit_asm(
+    ir: &IrProgram,
+    settings: &Settings,
+    arch: Arch,
+) -> Result<String, Box<dyn Error>> {
+    let mut listing = Listing::new(arch, settings);
+    for node in ir.nodes() {
+        let source = node.source();
+        listing.code.push('\n');
+        listing.comment(&format!(
+            "{}:{} {}",
+            source.line(),
+            source.column(),
+            summary(node.op())
+        ));
+        match arch {
+            Arch::X86_64 => listing.x86_64(node.op())?,
+            Arch::Aarch64 => listing.aarch64(node.op())?,
+        }
+    }
+
+    let (comment, syntax, start, finish, kind) = match arch {
+        Arch::X86_64 => (
+            "#",
+            Some(".intel_syntax noprefix"),
+            ["push rbx", "lea rbx, [rip + tape]"].as_slice(),
+            ["xor eax, eax", ".Lexit:", "pop rbx", "ret"].as_slice(),
+            "@",
+        ),
+        Arch::Aarch64 => (
+            "//",
+            None,
+            [
+                "stp x29, x30, [sp, #-32]!",
+                "mov x29, sp",
+                "str x19, [sp, #16]",
+                "adrp x19, tape",
+                "add x19, x19, :lo12:tape",
+            ]
+            .as_slice(),
+            [
+                "mov w0, #0",
+                ".Lexit:",
+                "ldr x19, [sp, #16]",
+                "ldp x29, x30, [sp], #32",
+                "ret",
+            ]
+            .as_slice(),
+            "%",
+        ),
+    };
+    let mut code = format!(
+        "{} Compiled from {} by bft.\n",
+        comment,
+        ir.filename().display()
+    );
+    if let Some(syntax) = syntax {
+        line(&mut code, syntax);
+    }
+    line(&mut code, ".text");
+    line(&mut code, ".globl main");
+    line(&mut code, &format!(".type main, {}function", kind));
+    line(&mut code, "main:");
+    for text in start {
+        line(&mut code, text);
+    }
+    code.push_str(&listing.code);
+    line(&mut code, "");
+    for text in finish {
+        line(&mut code, text);
+    }
+    line(&mut code, ".size main, .-main");
+    line(&mut code, "");
+    line(&mut code, ".section .rodata");
+    line(&mut code, ".Leof_message:");
+    line(
+        &mut code,
+        &format!(".ascii \"{}\"", assembler_string(EOF_MESSAGE)),
+    );
+    code.push_str(&listing.data);
+    line(&mut code, "");
+    line(&mut code, ".local tape");
+    line(
+        &mut code,
+        &format!(".comm tape, {}, 16", settings.cells as i64 * listing.size()),
+    );
+    line(
+        &mut code,
+        &format!(".section .note.GNU-stack,\"\",{}progbits", kind),
+    );
+    Ok(code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{assembler_string, emit_asm};
+    use crate::cli::Arch;
+    use crate::config::settings;
+    use bft_types::BfProgram;
+
+    fn compile(source: &str, flags: &[&str], arch: Arch) -> String {
+        let settings = settings(flags);
+        let program = BfProgram::new(source.to_string(), "a.bf").unwrap();
+        let (ir, _) = settings
+            .optimize(&program, true)
+            .unwrap()
+            .expect("tests compile optimized programs");
+        emit_asm(&ir, &settings, arch).unwrap()
+    }
+
+    #[test]
+    fn test_assembler_string() {
+        assert_eq!(assembler_string(b"hi\n"), "hi\\012");
+        assert_eq!(assembler_string(b"a\"b\\"), "a\\\"b\\\\");
+    }
+
+    #[test]
+    fn test_x86_64() {
+        let code = compile(",[->+<]>.", &["-O2"], Arch::X86_64);
+        assert!(code.starts_with("# Compiled from a.bf by bft.\n"));
+        assert!(code.contains("\t.intel_syntax noprefix\n"));
+        assert!(code.contains("\t# 1:2 CopyLoop([(1, 1)])\n"));
+        assert!(code.contains("\tadd BYTE PTR [rbx + 1], cl\n"));
+        assert!(
+            code.contains("\tmov edi, 2\n\tlea rsi, [rip + .Leof_message]\n")
+        );
+        assert!(code.contains("\tcall write@PLT\n"));
+        assert!(code.contains("\t.comm tape, 30000, 16\n"));
+    }
+
+    #[test]
+    fn test_aarch64() {
+        let code = compile(
+            "+[>-<-]",
+            &["-O1", "--cell-width", "16", "--eof", "zero"],
+            Arch::Aarch64,
+        );
+        assert!(code.starts_with("// Compiled from a.bf by bft.\n"));
+        assert!(code.contains("\t// 1:2 LoopStart\n"));
+        assert!(code.contains("\tcbz w9, .Lloop1_end\n.Lloop1_body:\n"));
+        assert!(code.contains("\tadd x19, x19, #2\n"));
+        assert!(code.contains("\tsub w9, w9, #1\n\tstrh w9, [x19]\n"));
+        assert!(code.contains("\t.comm tape, 60000, 16\n"));
+        assert!(!code.contains(".intel_syntax"));
+    }
+
+    #[test]
+    fn test_far_cells() {
+        let source =
+            format!("+[{}+{}-]", ">".repeat(100_000), "<".repeat(100_000));
+        let code = compile(&source, &["-O1"], Arch::Aarch64);
+        assert!(code.contains("\tldr x11, =100000\n\tadd x19, x19, x11\n"));
+        let code = compile(&source, &["-O1"], Arch::X86_64);
+        assert!(code.contains("\tadd rbx, 100000\n"));
+    }
+}
@@ -0,0 +1,88 @@
+//! The `bundle` subcommand, which builds a program into a standalone
+//! executable, by compiling it into C and building that with the system's C
+//! compiler, so that it can be run on machines without bft.
+
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitCode};
+
+use crate::cli::BundleArgs;
+use crate::compile::{compile_ir, emit_c};
+use crate::config::Settings;
+
+/// The C compiler to use, if neither `--cc` nor `CC` name one.
+const DEFAULT_CC: &str = "cc";
+
+/// Where to write the executable if `--output` is not given: next to the
+/// program, with its extension taken off.
+fn default_output(filename: &Path) -> Result<PathBuf, Box<dyn Error>> {
+    let output = filename.with_extension(std::env::consts::EXE_EXTENSION);
+    if output == filename {
+        return Err(format!(
+            "{} has no extension to take off, so --output is needed",
+            filename.display()
+        )
+        .into());
+    }
+    Ok(output)
+}
+
+/// Runs the `bundle` subcommand.
+pub(crate) fn run_bundle(
+    args: &BundleArgs,
+) -> Result<ExitCode, Box<dyn Error>> {
+    let settings = Settings::from_args(&args.run)?;
+    let output = match &args.output {
+        Some(output) => output.clone(),
+        None => default_output(&args.filename)?,
+    };
+    let cc = match &args.cc {
+        Some(cc) => cc.clone(),
+        None => std::env::var("CC").unwrap_or_else(|_| DEFAULT_CC.to_string()),
+    };
+    let ir = compile_ir(&args.filename, &settings)?;
+    let code = emit_c(&ir, &settings)?;
+
+    let dir =
+        std::env::temp_dir().join(format!("bft-bundle-{}", std::process::id()));
+    fs::create_dir_all(&dir)?;
+    let source = dir.join("program.c");
+    fs::write(&source, code)?;
+    let status = Command::new(&cc)
+        .arg("-O2")
+        .arg("-o")
+        .arg(&output)
+        .arg(&source)
+        .status();
+    let _ = fs::remove_dir_all(&dir);
+    let status = status.map_err(|err| {
+        format!("could not run the C compiler '{}': {}", cc, err)
+    })?;
+    if !status.success() {
+        return Err(
+            format!("the C compiler '{}' failed with {}", cc, status).into()
+        );
+    }
+    eprintln!("Wrote {}", output.display());
+    Ok(ExitCode::SUCCESS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::default_output;
+    use std::path::Path;
+
+    #[test]
+    fn test_default_output() {
+        let output = default_output(Path::new("programs/hello.bf")).unwrap();
+        assert_eq!(
+            output,
+            Path::new("programs/hello")
+                .with_extension(std::env::consts::EXE_EXTENSION)
+        );
+        if std::env::consts::EXE_EXTENSION.is_empty() {
+            assert!(default_output(Path::new("hello")).is_err());
+        }
+    }
+}
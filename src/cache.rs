@@ -0,0 +1,223 @@
+//! A cache of the output of programs which never read input, and so could be
+//! run to the end ahead of time by partial evaluation at `-O3`. Running such a
+//! program again, or compiling it, then only has to write out its output.
+//!
+//! The cache lives in `$XDG_CACHE_HOME/bft` (or `~/.cache/bft`), with one file
+//! per program and settings. Failing to read or write the cache is never an
+//! error, the program is just optimized as it would be without it.
+
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use bft_interp::ir::{IrNode, IrOp, IrProgram};
+use bft_interp::optimizer::PassStats;
+use bft_interp::partial::evaluated_output;
+use bft_types::digest::Fnv1a;
+use bft_types::vm_error::VirtualMachineError;
+use bft_types::BfProgram;
+
+use crate::config::Settings;
+
+/// The optimization level at which programs are partially evaluated, and so
+/// their output may be cached.
+const CACHED_OPT_LEVEL: u8 = 3;
+
+/// A directory of the output of programs, keyed by the program and the
+/// settings it was run with.
+#[derive(Debug, Clone)]
+pub(crate) struct OutputCache {
+    dir: PathBuf,
+}
+
+impl OutputCache {
+    pub(crate) fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    /// The cache in the user's cache directory, if there is a home for it.
+    fn user() -> Option<Self> {
+        env::var_os("XDG_CACHE_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| {
+                env::var_os("HOME").map(|home| Path::new(&home).join(".cache"))
+            })
+            .map(|dir| OutputCache::new(dir.join("bft")))
+    }
+
+    /// The user's cache, if programs are partially evaluated with the given
+    /// settings.
+    pub(crate) fn for_settings(settings: &Settings) -> Option<Self> {
+        if settings.opt_level >= CACHED_OPT_LEVEL {
+            OutputCache::user()
+        } else {
+            None
+        }
+    }
+
+    /// The key for the output of the program run with the given settings.
+    /// Only the settings which can change the output of a program that reads
+    /// no input are part of the key, along with the version of bft. The
+    /// limits of the sandbox are among them, so that output which was only
+    /// produced within a looser limit is never used under a tighter one.
+    fn key(program: &BfProgram, settings: &Settings) -> String {
+        let mut hash = Fnv1a::new();
+        for part in [
+            env!("CARGO_PKG_VERSION").to_string(),
+            program.digest().to_string(),
+            settings.cells.to_string(),
+            settings.cell_width.to_string(),
+            settings.extensible.to_string(),
+            format!("{:?}", settings.max_steps),
+            format!("{:?}", settings.max_output_bytes),
+            format!("{:?}", settings.max_input_bytes),
+        ] {
+            hash.update(part.as_bytes());
+            hash.update(b"\0");
+        }
+        format!("{:016x}", hash.finish())
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.out", key))
+    }
+
+    /// Retrieves the cached output for the key, if there is any.
+    fn get(&self, key: &str) -> Option<Vec<u8>> {
+        fs::read(self.path(key)).ok()
+    }
+
+    /// Stores the output for the key, writing it to a temporary file first so
+    /// that other runs never see it half written.
+    fn put(&self, key: &str, output: &[u8]) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        let temporary =
+            self.dir.join(format!("{}.{}.tmp", key, std::process::id()));
+        fs::write(&temporary, output)?;
+        fs::rename(temporary, self.path(key))
+    }
+
+    /// Optimizes the program as `Settings::optimize` does for a program on a
+    /// fresh tape, apart from using the cached output of the program if there
+    /// is any. Otherwise, if the program was run to the end ahead of time, its
+    /// output is cached for next time.
+    pub(crate) fn optimize(
+        &self,
+        program: &BfProgram,
+        settings: &Settings,
+    ) -> Result<Option<(IrProgram, Vec<PassStats>)>, VirtualMachineError> {
+        let key = OutputCache::key(program, settings);
+        if let Some(output) = self.get(&key) {
+            let mut ir = IrProgram::from_program(program)?;
+            let source = ir.nodes().first().map(IrNode::source);
+            let nodes = ir.nodes_mut();
+            nodes.clear();
+            if let Some(source) = source.filter(|_| !output.is_empty()) {
+                nodes.push(IrNode::new(IrOp::OutputBytes(output), source));
+            }
+            return Ok(Some((ir, Vec::new())));
+        }
+
+        let optimized = settings.optimize(program, true)?;
+        if let Some(output) =
+            optimized.as_ref().and_then(|(ir, _)| evaluated_output(ir))
+        {
+            // The cache only saves time, so the program runs without it.
+            let _ = self.put(&key, &output);
+        }
+        Ok(optimized)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::OutputCache;
+    use crate::config::{settings, Settings};
+    use bft_interp::ir::{IrNode, IrOp};
+    use bft_types::BfProgram;
+    use std::fs;
+
+    fn cache(name: &str) -> OutputCache {
+        let dir = std::env::temp_dir().join(format!(
+            "bft-cache-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        OutputCache::new(dir)
+    }
+
+    fn ops(
+        cache: &OutputCache,
+        source: &str,
+        settings: &Settings,
+    ) -> Vec<IrOp> {
+        let program = BfProgram::new(source.to_string(), "cache.bf").unwrap();
+        let (ir, _) = cache.optimize(&program, settings).unwrap().unwrap();
+        ir.nodes().iter().map(IrNode::op).cloned().collect()
+    }
+
+    #[test]
+    fn test_key_depends_on_settings() {
+        let program = BfProgram::new("+.".to_string(), "cache.bf").unwrap();
+        let commented = BfProgram::new("+ a .".to_string(), "b.bf").unwrap();
+        let key = OutputCache::key(&program, &settings(&["-O3"]));
+        assert_eq!(key, OutputCache::key(&commented, &settings(&["-O3"])));
+        assert_ne!(
+            key,
+            OutputCache::key(&program, &settings(&["-O3", "-c", "10"]))
+        );
+        assert_ne!(
+            key,
+            OutputCache::key(
+                &program,
+                &settings(&["-O3", "--cell-width", "16"])
+            )
+        );
+        assert_eq!(
+            key,
+            OutputCache::key(&program, &settings(&["-O3", "--eof", "zero"]))
+        );
+        assert_ne!(
+            key,
+            OutputCache::key(&program, &settings(&["-O3", "--max-steps", "5"]))
+        );
+        assert_ne!(
+            key,
+            OutputCache::key(
+                &program,
+                &settings(&["-O3", "--max-output-bytes", "1"])
+            )
+        );
+    }
+
+    #[test]
+    fn test_output_cached() {
+        let cache = cache("cached");
+        let settings = settings(&["-O3"]);
+        let program = "++++[>++++[>++++<-]<-]>>+.";
+        let program_ops = ops(&cache, program, &settings);
+        assert_eq!(program_ops[0], IrOp::OutputBytes(b"A".to_vec()));
+        assert!(program_ops.len() > 1);
+
+        // The second time, only the output is left.
+        assert_eq!(
+            ops(&cache, program, &settings),
+            [IrOp::OutputBytes(b"A".to_vec())]
+        );
+        fs::remove_dir_all(&cache.dir).unwrap();
+    }
+
+    #[test]
+    fn test_programs_reading_input_not_cached() {
+        let cache = cache("input");
+        let settings = settings(&["-O3"]);
+        assert_eq!(
+            ops(&cache, "+.,.", &settings),
+            ops(&cache, "+.,.", &settings)
+        );
+        assert!(!cache.dir.exists());
+    }
+}
@@ -0,0 +1,738 @@
+//! The `check` subcommand, which looks for the places a program would behave
+//! differently with cells wider than 8 bits, without running it.
+//!
+//! The program is interpreted abstractly: each cell holds the range of values
+//! it could have rather than a single value, and each loop is run over and
+//! over until the ranges stop changing, widening any which keep growing. An
+//! 8 bit cell wraps around at 256 where a wider one does not, so any `+` which
+//! could take a cell above 255, or `-` which could take it below zero, is a
+//! place the program may rely on the width of its cells.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::fmt;
+use std::ops::Range;
+use std::process::ExitCode;
+
+use bft_interp::eof::EofBehavior;
+use bft_types::ops::Operation;
+use bft_types::BfProgram;
+
+use crate::cli::CheckArgs;
+use crate::config::Settings;
+use crate::load_program;
+use crate::workspace::run_check_workspace;
+
+/// The number of instructions which may be interpreted, across every time
+/// each loop is run, before giving up on the program.
+const BUDGET: usize = 1_000_000;
+
+/// The number of times a loop is run one iteration at a time while the value
+/// of the cell it tests is known exactly, as it is for most counted loops. It
+/// is more than 256 so that loops counting until the cell wraps are caught.
+const UNROLL: usize = 1024;
+
+/// The number of times a loop is run before any ranges which are still
+/// growing are widened, so that it is run only a few more times.
+const WIDEN_AFTER: usize = 3;
+
+/// The range of values a cell could hold, where the ends of `i64` stand for
+/// no bound at all.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Interval {
+    lo: i64,
+    hi: i64,
+}
+
+impl Interval {
+    /// Any value at all.
+    const ANY: Interval = Interval {
+        lo: i64::MIN,
+        hi: i64::MAX,
+    };
+
+    /// Any byte read from the input.
+    const BYTE: Interval = Interval { lo: 0, hi: 255 };
+
+    fn exact(value: i64) -> Self {
+        Self {
+            lo: value,
+            hi: value,
+        }
+    }
+
+    fn contains(&self, value: i64) -> bool {
+        (self.lo..=self.hi).contains(&value)
+    }
+
+    fn bounded(&self) -> bool {
+        self.lo != i64::MIN && self.hi != i64::MAX
+    }
+
+    /// Adds one or subtracts one, keeping any unbounded ends unbounded.
+    fn add(&self, delta: i64) -> Self {
+        let bump = |end: i64| match end {
+            i64::MIN | i64::MAX => end,
+            end => end.saturating_add(delta),
+        };
+        Self {
+            lo: bump(self.lo),
+            hi: bump(self.hi),
+        }
+    }
+
+    /// Adds `by` times any of the values of `times`, which must be bounded.
+    fn add_times(&self, by: i64, times: &Self) -> Self {
+        let (a, b) = (by.saturating_mul(times.lo), by.saturating_mul(times.hi));
+        let sum = Self {
+            lo: self.lo.saturating_add(a.min(b)),
+            hi: self.hi.saturating_add(a.max(b)),
+        };
+        Self {
+            lo: if self.lo == i64::MIN {
+                i64::MIN
+            } else {
+                sum.lo
+            },
+            hi: if self.hi == i64::MAX {
+                i64::MAX
+            } else {
+                sum.hi
+            },
+        }
+    }
+
+    /// The smallest range holding both.
+    fn join(&self, other: &Self) -> Self {
+        Self {
+            lo: self.lo.min(other.lo),
+            hi: self.hi.max(other.hi),
+        }
+    }
+
+    /// Joins the next range, moving either end which moved out to zero or
+    /// 255, where cells of different widths part ways, or past them to no
+    /// bound at all.
+    fn widen(&self, next: &Self) -> Self {
+        let lo = match next.lo {
+            lo if lo >= self.lo => self.lo,
+            lo if lo >= 0 => 0,
+            _ => i64::MIN,
+        };
+        let hi = match next.hi {
+            hi if hi <= self.hi => self.hi,
+            hi if hi <= 255 => 255,
+            _ => i64::MAX,
+        };
+        Self { lo, hi }
+    }
+}
+
+/// What the program could look like at one point: where the head is, if it
+/// is known, and the values of the cells.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct State {
+    /// The position of the head, relative to the first cell.
+    head: Option<i64>,
+    cells: BTreeMap<i64, Interval>,
+    /// The values of the cells which are not in `cells`.
+    rest: Interval,
+}
+
+impl State {
+    /// The state the program starts in, with every cell at zero.
+    fn start() -> Self {
+        Self {
+            head: Some(0),
+            cells: BTreeMap::new(),
+            rest: Interval::exact(0),
+        }
+    }
+
+    /// A state about which nothing is known.
+    fn unknown() -> Self {
+        Self {
+            head: None,
+            cells: BTreeMap::new(),
+            rest: Interval::ANY,
+        }
+    }
+
+    /// The values of the cell at the head.
+    fn cell(&self) -> Interval {
+        match self.head {
+            Some(head) => self.cells.get(&head).copied().unwrap_or(self.rest),
+            None => self
+                .cells
+                .values()
+                .fold(self.rest, |all, cell| all.join(cell)),
+        }
+    }
+
+    /// Sets the cell at the head, or, if the head is not known, lets any
+    /// cell have the values as well as its own.
+    fn set(&mut self, value: Interval) {
+        match self.head {
+            Some(head) => {
+                self.cells.insert(head, value);
+            }
+            None => {
+                for cell in self.cells.values_mut() {
+                    *cell = cell.join(&value);
+                }
+                self.rest = self.rest.join(&value);
+            }
+        }
+    }
+
+    fn step(&mut self, by: i64) {
+        self.head = self.head.map(|head| head + by);
+    }
+
+    /// Narrows the state to where the cell at the head is zero, or is not.
+    /// Returns false if it cannot be.
+    fn refine(&mut self, nonzero: bool) -> bool {
+        if self.head.is_none() {
+            return true;
+        }
+        let mut cell = self.cell();
+        if !nonzero {
+            self.set(Interval::exact(0));
+            return cell.contains(0);
+        }
+        if cell == Interval::exact(0) {
+            return false;
+        }
+        if cell.lo == 0 {
+            cell.lo = 1;
+        } else if cell.hi == 0 {
+            cell.hi = -1;
+        }
+        self.set(cell);
+        true
+    }
+
+    /// Combines the two states cell by cell, with `merge`.
+    fn combine(
+        &self,
+        other: &Self,
+        merge: impl Fn(&Interval, &Interval) -> Interval,
+    ) -> Self {
+        let keys: BTreeSet<i64> = self
+            .cells
+            .keys()
+            .chain(other.cells.keys())
+            .copied()
+            .collect();
+        let cells = keys
+            .into_iter()
+            .map(|key| {
+                let ours = self.cells.get(&key).unwrap_or(&self.rest);
+                let theirs = other.cells.get(&key).unwrap_or(&other.rest);
+                (key, merge(ours, theirs))
+            })
+            .collect();
+        Self {
+            head: self.head.filter(|_| self.head == other.head),
+            cells,
+            rest: merge(&self.rest, &other.rest),
+        }
+    }
+
+    fn join(&self, other: &Self) -> Self {
+        self.combine(other, Interval::join)
+    }
+
+    fn widen(&self, next: &Self) -> Self {
+        self.combine(next, Interval::widen)
+    }
+}
+
+/// A way the program may rely on 8 bit cells.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Reliance {
+    /// A `-` may take a cell below zero.
+    BelowZero,
+    /// A `+` may take a cell above 255.
+    AboveByte,
+    /// A `,` at the end of the input sets the cell to its maximum value.
+    EofMax,
+}
+
+/// A place the program may rely on 8 bit cells.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Finding {
+    line: usize,
+    column: usize,
+    reliance: Reliance,
+    /// The cell, if the head is known there.
+    cell: Option<i64>,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let cell = match self.cell {
+            Some(cell) => format!("cell {}", cell),
+            None => "a cell".to_string(),
+        };
+        write!(f, "{}:{}: ", self.line, self.column)?;
+        match self.reliance {
+            Reliance::BelowZero => write!(
+                f,
+                "`-` may take {} below zero, which wraps around to 255 with \
+                8 bit cells but to 65535 or more with wider ones",
+                cell
+            ),
+            Reliance::AboveByte => write!(
+                f,
+                "`+` may take {} above 255, which wraps around to 0 with 8 bit \
+                cells but carries on with wider ones",
+                cell
+            ),
+            Reliance::EofMax => write!(
+                f,
+                "`,` at the end of the input sets {} to 255 with 8 bit cells \
+                but to 65535 or more with wider ones, under --eof max",
+                cell
+            ),
+        }
+    }
+}
+
+/// Interprets a program abstractly, collecting the places it may rely on 8
+/// bit cells.
+struct Checker<'p> {
+    program: &'p BfProgram,
+    eof: EofBehavior,
+    /// The findings, by the position of their instruction.
+    findings: BTreeMap<usize, Finding>,
+    /// The positions of the instructions which change cells whose values
+    /// could not be worked out at all.
+    unknown: BTreeSet<usize>,
+    /// The number of instructions interpreted so far.
+    work: usize,
+}
+
+impl<'p> Checker<'p> {
+    fn new(program: &'p BfProgram, eof: EofBehavior) -> Self {
+        Self {
+            program,
+            eof,
+            findings: BTreeMap::new(),
+            unknown: BTreeSet::new(),
+            work: 0,
+        }
+    }
+
+    fn exhausted(&self) -> bool {
+        self.work > BUDGET
+    }
+
+    fn find(&mut self, position: usize, reliance: Reliance, state: &State) {
+        let instruction = self.program.instructions()[position];
+        self.findings.entry(position).or_insert(Finding {
+            line: instruction.line(),
+            column: instruction.column(),
+            reliance,
+            cell: state.head,
+        });
+    }
+
+    /// Interprets the instructions in the range, returning the state after
+    /// them, or None if they can never finish.
+    fn run(&mut self, range: Range<usize>, mut state: State) -> Option<State> {
+        let mut position = range.start;
+        while position < range.end {
+            self.work += 1;
+            if self.exhausted() {
+                return Some(State::unknown());
+            }
+            let cell = state.cell();
+            match self.program.instructions()[position].operation() {
+                Operation::IncrementByte => {
+                    if cell.hi == i64::MAX {
+                        self.unknown.insert(position);
+                    } else if cell.hi >= 255 {
+                        self.find(position, Reliance::AboveByte, &state);
+                    }
+                    state.set(cell.add(1));
+                }
+                Operation::DecrementByte => {
+                    if cell.lo == i64::MIN {
+                        self.unknown.insert(position);
+                    } else if cell.lo <= 0 {
+                        self.find(position, Reliance::BelowZero, &state);
+                    }
+                    state.set(cell.add(-1));
+                }
+                Operation::IncrementPointer => state.step(1),
+                Operation::DecrementPointer => state.step(-1),
+                Operation::OutputByte => {}
+                Operation::InputByte => match self.eof {
+                    EofBehavior::Unchanged => {
+                        state.set(cell.join(&Interval::BYTE))
+                    }
+                    EofBehavior::MaxValue => {
+                        self.find(position, Reliance::EofMax, &state);
+                        state.set(Interval::BYTE);
+                    }
+                    _ => state.set(Interval::BYTE),
+                },
+                Operation::StartLoop => {
+                    if let Some(end) = self.program.jump_target(position) {
+                        state = self.run_loop(position + 1..end, state)?;
+                        position = end;
+                    }
+                }
+                Operation::EndLoop => {}
+                // What an extension does is not known.
+                Operation::Extension(_) => state = State::unknown(),
+            }
+            position += 1;
+        }
+        Some(state)
+    }
+
+    /// Whether the body of a loop is only a `-`, making it a clear loop.
+    fn clears(&self, body: &Range<usize>) -> bool {
+        body.len() == 1
+            && self.program.instructions()[body.start].operation()
+                == Operation::DecrementByte
+    }
+
+    /// Works out what each run of the body of a loop does, if it only adds to
+    /// cells and ends where it started, with the cell it tests going up or
+    /// down by one: the total added to each cell, by its offset from the
+    /// head, and the position of the first instruction adding to it.
+    fn transfer(
+        &self,
+        body: Range<usize>,
+    ) -> Option<BTreeMap<i64, (i64, usize)>> {
+        let mut offset = 0;
+        let mut deltas: BTreeMap<i64, (i64, usize)> = BTreeMap::new();
+        for position in body {
+            let by = match self.program.instructions()[position].operation() {
+                Operation::IncrementPointer => {
+                    offset += 1;
+                    continue;
+                }
+                Operation::DecrementPointer => {
+                    offset -= 1;
+                    continue;
+                }
+                Operation::IncrementByte => 1,
+                Operation::DecrementByte => -1,
+                _ => return None,
+            };
+            deltas.entry(offset).or_insert((0, position)).0 += by;
+        }
+        let counted = matches!(deltas.get(&0), Some((1 | -1, _)));
+        (offset == 0 && counted).then_some(deltas)
+    }
+
+    /// Runs a transfer loop, as worked out by `transfer`, all at once.
+    fn run_transfer(
+        &mut self,
+        deltas: &BTreeMap<i64, (i64, usize)>,
+        mut state: State,
+    ) -> State {
+        let head = state.head.expect("transfer loops need a known head");
+        let (step, counter) = deltas[&0];
+        // The number of times the loop runs, if it stops without wrapping.
+        let mut cell = state.cell();
+        if step < 0 && cell.lo < 0 {
+            self.find(counter, Reliance::BelowZero, &state);
+            cell.lo = 0;
+        } else if step > 0 && cell.hi > 0 {
+            self.find(counter, Reliance::AboveByte, &state);
+            cell.hi = 0;
+        }
+        let times = match step {
+            -1 => cell,
+            _ => Interval {
+                lo: -cell.hi,
+                hi: -cell.lo,
+            },
+        };
+        for (&offset, &(by, position)) in deltas.iter().filter(|(&o, _)| o != 0)
+        {
+            state.head = Some(head + offset);
+            let after = state.cell().add_times(by, &times);
+            if after.hi != i64::MAX && after.hi > 255 {
+                self.find(position, Reliance::AboveByte, &state);
+            } else if after.lo != i64::MIN && after.lo < 0 {
+                self.find(position, Reliance::BelowZero, &state);
+            } else if !after.bounded() {
+                self.unknown.insert(position);
+            }
+            state.set(after);
+        }
+        state.head = Some(head);
+        state.set(Interval::exact(0));
+        state
+    }
+
+    /// Interprets a loop with the given body until the state at its start
+    /// stops changing, returning the state once it is left, or None if it
+    /// never can be.
+    fn run_loop(&mut self, body: Range<usize>, state: State) -> Option<State> {
+        let mut entry = state;
+        // `[-]` leaves its cell at zero whatever the width, even if an
+        // earlier wrap has taken it below zero, as a wider cell is counted
+        // down from where the wrap left it instead.
+        if self.clears(&body) {
+            entry.refine(false);
+            return Some(entry);
+        }
+        let cell = entry.cell();
+        if entry.head.is_some() && cell.bounded() && cell.lo != cell.hi {
+            if let Some(deltas) = self.transfer(body.clone()) {
+                return Some(self.run_transfer(&deltas, entry));
+            }
+        }
+        for _ in 0..UNROLL {
+            let cell = entry.cell();
+            if entry.head.is_none() || cell.lo != cell.hi || self.exhausted() {
+                break;
+            }
+            if cell.lo == 0 {
+                return Some(entry);
+            }
+            entry = self.run(body.clone(), entry)?;
+        }
+        let mut exit: Option<State> = None;
+        for runs in 0.. {
+            let mut leaving = entry.clone();
+            if leaving.refine(false) {
+                exit = Some(match exit {
+                    Some(exit) => exit.join(&leaving),
+                    None => leaving,
+                });
+            }
+            let mut inside = entry.clone();
+            if !inside.refine(true) {
+                break;
+            }
+            let Some(after) = self.run(body.clone(), inside) else {
+                break;
+            };
+            let next = entry.join(&after);
+            if next == entry || self.exhausted() {
+                break;
+            }
+            entry = if runs >= WIDEN_AFTER {
+                entry.widen(&next)
+            } else {
+                next
+            };
+        }
+        exit
+    }
+}
+
+/// The places a program may rely on 8 bit cells, and how sure the check is.
+#[derive(Debug)]
+pub(crate) struct Portability {
+    findings: Vec<Finding>,
+    /// The number of places which change cells whose values could not be
+    /// worked out, and so could rely on 8 bit cells without being found.
+    unknown: usize,
+    /// Whether the check gave up part way through the program.
+    exhausted: bool,
+}
+
+impl Portability {
+    fn portable(&self) -> bool {
+        self.findings.is_empty()
+    }
+
+    /// The places the program may rely on 8 bit cells.
+    pub(crate) fn findings(&self) -> &[Finding] {
+        &self.findings
+    }
+}
+
+/// Checks where the program may rely on 8 bit cells.
+pub(crate) fn check(program: &BfProgram, eof: EofBehavior) -> Portability {
+    let mut checker = Checker::new(program, eof);
+    checker.run(0..program.instructions().len(), State::start());
+    let unknown = checker
+        .unknown
+        .iter()
+        .filter(|position| !checker.findings.contains_key(position))
+        .count();
+    Portability {
+        exhausted: checker.exhausted(),
+        findings: checker.findings.into_values().collect(),
+        unknown,
+    }
+}
+
+/// Counts places, as in "1 place" or "2 places".
+fn places(count: usize) -> String {
+    match count {
+        1 => "1 place".to_string(),
+        _ => format!("{} places", count),
+    }
+}
+
+impl fmt::Display for Portability {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for finding in &self.findings {
+            writeln!(f, "{}", finding)?;
+        }
+        if !self.portable() {
+            return write!(
+                f,
+                "The program may rely on 8 bit cells in {}, so keep \
+                --cell-width 8.",
+                places(self.findings.len())
+            );
+        }
+        if self.exhausted {
+            return write!(
+                f,
+                "No places relying on 8 bit cells were found, but the program \
+                was too large to check all of it, so wider cells are not \
+                certain to be safe."
+            );
+        }
+        if self.unknown > 0 {
+            return write!(
+                f,
+                "No places relying on 8 bit cells were found, but {} \
+                changed cells whose values could not be worked out, so wider \
+                cells are not certain to be safe.",
+                places(self.unknown)
+            );
+        }
+        write!(
+            f,
+            "The program behaves the same with 8, 16 and 32 bit cells, so any \
+            --cell-width is safe."
+        )
+    }
+}
+
+/// Checks the program, printing the places it may rely on 8 bit cells. Fails
+/// if there are any. With `--workspace`, checks every program of the
+/// workspace instead.
+pub(crate) fn run_check(args: &CheckArgs) -> Result<ExitCode, Box<dyn Error>> {
+    let filename = match (&args.workspace, &args.filename) {
+        (Some(workspace), _) => {
+            return run_check_workspace(args, workspace.as_deref())
+        }
+        (None, Some(filename)) => filename,
+        (None, None) => unreachable!("clap requires a filename or workspace"),
+    };
+    let settings = Settings::from_args(&args.run)?;
+    let program = load_program(filename, &settings)?;
+    let portability = check(&program, settings.eof);
+    println!("{}", portability);
+    if portability.portable() {
+        Ok(ExitCode::SUCCESS)
+    } else {
+        Ok(ExitCode::FAILURE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check, Interval, Reliance, State};
+    use bft_interp::eof::EofBehavior;
+    use bft_types::BfProgram;
+
+    fn reliances(source: &str) -> Vec<(usize, Reliance)> {
+        let program = BfProgram::new(source.to_string(), "check.bf").unwrap();
+        check(&program, EofBehavior::Zero)
+            .findings
+            .iter()
+            .map(|finding| (finding.column, finding.reliance))
+            .collect()
+    }
+
+    #[test]
+    fn test_interval() {
+        let byte = Interval::BYTE;
+        assert_eq!(byte.add(1), Interval { lo: 1, hi: 256 });
+        assert_eq!(Interval::ANY.add(-1), Interval::ANY);
+        let one = Interval::exact(1);
+        assert_eq!(
+            one.widen(&Interval { lo: 1, hi: 2 }),
+            Interval { lo: 1, hi: 255 }
+        );
+        let wider = Interval { lo: 0, hi: 300 };
+        assert_eq!(
+            byte.widen(&wider),
+            Interval {
+                lo: 0,
+                hi: i64::MAX
+            }
+        );
+        assert_eq!(byte.widen(&byte), byte);
+    }
+
+    #[test]
+    fn test_refine() {
+        let mut state = State::start();
+        assert!(!state.clone().refine(true));
+        state.set(Interval::BYTE);
+        assert!(state.refine(true));
+        assert_eq!(state.cell(), Interval { lo: 1, hi: 255 });
+    }
+
+    #[test]
+    fn test_portable() {
+        // Clearing and moving values between cells only ever counts down to
+        // zero, so works with any width.
+        assert_eq!(reliances("++++[>++<-]>[-]"), []);
+        assert_eq!(reliances(",[->+<]>."), []);
+        // Only the wrap itself is found, not clearing the cell afterwards.
+        assert_eq!(reliances("-[-]"), [(1, Reliance::BelowZero)]);
+        assert_eq!(reliances("+>-[<+>-]<[-]"), reliances("+>-[<+>-]"));
+        let program = BfProgram::new("+.".to_string(), "p.bf").unwrap();
+        let portability = check(&program, EofBehavior::Zero);
+        assert!(portability.portable());
+        assert!(portability.to_string().contains("any --cell-width is safe"));
+    }
+
+    #[test]
+    fn test_reliances() {
+        // Counting down from zero to get 255.
+        assert_eq!(reliances("-."), [(1, Reliance::BelowZero)]);
+        // Moving 255 onto a cell which already holds one.
+        assert_eq!(
+            reliances("+>-[<+>-]"),
+            [
+                (3, Reliance::BelowZero),
+                (6, Reliance::AboveByte),
+                (8, Reliance::BelowZero)
+            ]
+        );
+        // Doubling a byte of input.
+        assert_eq!(reliances(",[->++<]"), [(5, Reliance::AboveByte)]);
+        // Counting up until the cell wraps around to zero.
+        assert_eq!(reliances("+[+]"), [(3, Reliance::AboveByte)]);
+        let program = BfProgram::new(",.".to_string(), "eof.bf").unwrap();
+        let findings = check(&program, EofBehavior::MaxValue).findings;
+        assert_eq!(findings[0].reliance, Reliance::EofMax);
+        assert_eq!(
+            findings[0].to_string(),
+            "1:1: `,` at the end of the input sets cell 0 to 255 with 8 bit \
+            cells but to 65535 or more with wider ones, under --eof max"
+        );
+    }
+
+    #[test]
+    fn test_unknown_head() {
+        // After scanning, the head could be anywhere.
+        let program = BfProgram::new("+[>+]<-".to_string(), "s.bf").unwrap();
+        let portability = check(&program, EofBehavior::Zero);
+        assert_eq!(portability.unknown, 0);
+        assert_eq!(
+            portability.findings[0].to_string(),
+            "1:4: `+` may take a cell above 255, which wraps around to 0 with \
+            8 bit cells but carries on with wider ones"
+        );
+    }
+}
@@ -1,21 +1,1115 @@
 #![deny(missing_docs)]
 
-use clap::Parser;
+use clap::{Args as ClapArgs, Parser, Subcommand, ValueEnum};
+use clap_complete::Shell;
+
+use bft_interp::display::CellDisplay;
+use bft_interp::eof::EofBehavior;
+use bft_interp::io::NewlinePolicy;
+use bft_interp::preset::Profile;
+
+use crate::config::CellWidth;
 use std::path::PathBuf;
 
 /// A Brainfuck Interpreter, written in Rust.
 #[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
+#[command(
+    author,
+    version,
+    about,
+    long_about = None,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
 pub(crate) struct Args {
+    /// The subcommand to run, if not given then the program in `filename` is
+    /// interpreted, as with the `run` subcommand.
+    #[command(subcommand)]
+    pub(crate) command: Option<Command>,
+
     /// The filename of the program to interpret.
-    pub(crate) filename: PathBuf,
+    #[arg(required = true)]
+    pub(crate) filename: Option<PathBuf>,
+
+    /// The settings used to interpret the program.
+    #[command(flatten)]
+    pub(crate) run: RunArgs,
+
+    /// The flags which only apply when running a single program.
+    #[command(flatten)]
+    pub(crate) run_only: RunOnlyArgs,
+}
+
+/// The subcommands of bft.
+#[derive(Subcommand, Debug)]
+pub(crate) enum Command {
+    /// Interpret a Brainfuck program.
+    Run {
+        /// The filename of the program to interpret.
+        filename: PathBuf,
+
+        /// The settings used to interpret the program.
+        #[command(flatten)]
+        run: RunArgs,
+
+        /// The flags which only apply when running a single program.
+        #[command(flatten)]
+        run_only: RunOnlyArgs,
+    },
+
+    /// Check whether two programs produce the same output for the same input.
+    Equiv(EquivArgs),
+
+    /// Compare the instructions of two programs, leaving out their comments
+    /// and layout, and show where each change is in both files.
+    Diff(DiffArgs),
+
+    /// Apply the changes written by `bft diff` to a program, keeping its
+    /// comments and layout.
+    Patch(PatchArgs),
+
+    /// Shrink a failing program down to a minimal program which still fails.
+    Shrink(ShrinkArgs),
+
+    /// Run several programs as a pipeline, feeding the output of each one into
+    /// the input of the next.
+    Pipe(PipeArgs),
+
+    /// Compile a Brainfuck program into another language.
+    Compile(CompileArgs),
+
+    /// Build a Brainfuck program into a standalone executable which runs
+    /// without bft, using the C compiler.
+    Bundle(BundleArgs),
+
+    /// Pack a program into the compact `.bfz` format, which loads faster and
+    /// can be run like any other program.
+    Pack(PackArgs),
+
+    /// Turn a packed `.bfz` program back into plain Brainfuck.
+    Unpack(UnpackArgs),
+
+    /// Rewrite a program as plain Brainfuck which does the same thing, but is
+    /// usually shorter and faster.
+    Optimize(OptimizeArgs),
+
+    /// Describe the make-up of a program, such as how many of each
+    /// instruction it has, without running it.
+    Stats(StatsArgs),
+
+    /// Print the identity of a program, a hash of its instructions which
+    /// copies differing only in comments and layout share.
+    Id(StatsArgs),
+
+    /// Score a program for code golf: its number of commands, and its size
+    /// once minified and gzipped, broken down by loop.
+    Golf(GolfArgs),
+
+    /// Turn a program into readable pseudo-code, with common idioms such as
+    /// clearing and copying cells named.
+    Decompile(DecompileArgs),
+
+    /// Walk through a program line by line, describing what each command
+    /// does and how its loops fit together.
+    Explain(ExplainArgs),
+
+    /// Run a program slowly, narrating what each step does. Press Enter to
+    /// pause and resume it, or q and Enter to stop it.
+    Teach(TeachArgs),
+
+    /// Work with programs written in Brainfuck assembly, which has named
+    /// operations and labelled cells.
+    Asm {
+        /// What to do with the assembly program.
+        #[command(subcommand)]
+        command: AsmCommand,
+    },
+
+    /// Run a program again from a replay file written by `--record`, checking
+    /// that it does exactly what it did when it was recorded.
+    Replay(ReplayArgs),
+
+    /// Jump to a step of a run recorded with `--record-steps`, and carry on
+    /// from there in the debugger.
+    ReplaySteps(ReplayStepsArgs),
+
+    /// Work out which behaviors of the interpreter a program depends on.
+    Analyze(AnalyzeArgs),
+
+    /// Check a program without running it, such as for the places it may
+    /// rely on 8 bit cells.
+    Check(CheckArgs),
 
-    /// The number of cells in the tape of the Virtual Machine.
-    // #[clap(name = "cell", short, long, value_parser, default_value_t = 30000)]
-    #[arg(short, long, default_value_t = 30_000)]
-    pub(crate) cells: usize,
+    /// Run a program under dbfi, a Brainfuck interpreter written in
+    /// Brainfuck, and check that it does the same as when run directly.
+    Selfhost(SelfhostArgs),
+
+    /// Serve an interactive session over stdin and stdout, taking JSON-RPC
+    /// requests to load, step through and run programs.
+    ServeStdio(ServeStdioArgs),
+
+    /// Run programs sent over HTTP, within the limits of the server.
+    #[cfg(feature = "http")]
+    ServeHttp(ServeHttpArgs),
+
+    /// List the optimization passes, dialects and codecs of the plugins
+    /// given with `--plugin`.
+    #[cfg(feature = "plugins")]
+    Plugins(PluginsArgs),
+
+    /// Check that every program of a project described by `bfproject.toml`
+    /// parses, and optimize them.
+    Build(BuildArgs),
+
+    /// Run the test cases declared for the programs of a project.
+    Test(ProjectArgs),
+
+    /// Set up a new project, with a starter program and a test for it.
+    New(NewArgs),
+
+    /// Generate a shell completion script for bft.
+    Completions {
+        /// The shell to generate the completion script for.
+        shell: Shell,
+
+        /// The directory to write the script to, instead of stdout.
+        #[arg(long)]
+        dir: Option<PathBuf>,
+    },
+
+    /// Generate the man page for bft.
+    Manpage {
+        /// The directory to write man pages to, one for bft and one for each of
+        /// its subcommands, instead of writing the page for bft to stdout.
+        #[arg(long)]
+        dir: Option<PathBuf>,
+    },
+}
+
+/// The settings used to interpret a program, shared by each of the
+/// subcommands which run programs. Any settings which are not given fall back
+/// to those in the config files, and then to the defaults.
+#[derive(ClapArgs, Debug, Clone)]
+pub(crate) struct RunArgs {
+    /// Start from the settings of a well-known kind of interpreter: classic,
+    /// wrap-tape-16bit or extended. Other flags given alongside it override
+    /// its settings.
+    #[arg(long)]
+    pub(crate) preset: Option<Profile>,
+
+    /// The number of cells in the tape of the Virtual Machine [default: 30000]
+    #[arg(short, long)]
+    pub(crate) cells: Option<usize>,
+
+    /// The number of bits in each cell of the tape: 8, 16 or 32 [default: 8]
+    #[arg(long)]
+    pub(crate) cell_width: Option<CellWidth>,
 
     /// Whether or not the tape of the Virtual Machine can be extensible.
-    #[arg(short, long, default_value_t = false)]
+    #[arg(
+        short,
+        long,
+        default_value_t = false,
+        overrides_with = "no_extensible"
+    )]
     pub(crate) extensible: bool,
+
+    /// Keep the tape a fixed length, even if a configuration file or preset
+    /// makes it extensible.
+    #[arg(long, overrides_with = "extensible")]
+    pub(crate) no_extensible: bool,
+
+    /// What `,` does at the end of the input: error, zero, unchanged or max
+    /// [default: error]
+    #[arg(long)]
+    pub(crate) eof: Option<EofBehavior>,
+
+    /// Turn CR LF into LF in the input, and LF into CR LF in the output, for
+    /// programs which expect `,` to read LF when Enter is pressed.
+    #[arg(long, conflicts_with = "lf_to_crlf")]
+    pub(crate) crlf_to_lf: bool,
+
+    /// Turn LF into CR LF in the input, and CR LF into LF in the output, for
+    /// programs which expect `,` to read CR LF when Enter is pressed.
+    #[arg(long)]
+    pub(crate) lf_to_crlf: bool,
+
+    /// Echo the input which the program reads into its output, dimmed on a
+    /// terminal and in square brackets otherwise, to keep a transcript of an
+    /// interactive session.
+    #[arg(long, default_value_t = false)]
+    pub(crate) echo: bool,
+
+    /// Fail a `,` which has waited this many milliseconds for input from
+    /// stdin, rather than waiting forever. The wait sleeps between checks, so
+    /// it takes no CPU.
+    #[arg(long, value_name = "MS")]
+    pub(crate) input_timeout: Option<u64>,
+
+    /// How the output of the program is shown.
+    #[arg(long, value_enum, default_value_t = IoMode::Raw)]
+    pub(crate) io: IoMode,
+
+    /// Whether to end the output with a newline: always, if-missing or never
+    /// [default: if-missing]
+    #[arg(long)]
+    pub(crate) trailing_newline: Option<NewlinePolicy>,
+
+    /// Only report unmatched brackets once execution reaches them, rather
+    /// than refusing to run the program at all.
+    #[arg(long, default_value_t = false, overrides_with = "no_lazy_brackets")]
+    pub(crate) lazy_brackets: bool,
+
+    /// Refuse to run a program with unmatched brackets, even if a
+    /// configuration file asks for `lazy-brackets`.
+    #[arg(long, overrides_with = "lazy_brackets")]
+    pub(crate) no_lazy_brackets: bool,
+
+    /// The deepest that loops may be nested before the program is refused
+    /// [default: 10000]
+    #[arg(long)]
+    pub(crate) max_nesting: Option<usize>,
+
+    /// The largest, in bytes, that the program file may be before it is
+    /// refused without being read, to guard against loading a file which is
+    /// not Brainfuck by mistake
+    #[arg(long, value_name = "BYTES")]
+    pub(crate) max_program_size: Option<u64>,
+
+    /// The fraction of the non-whitespace characters of the program which
+    /// should be commands, below which it is reported as probably not being
+    /// Brainfuck [default: 0.1]
+    #[arg(long, value_name = "RATIO")]
+    pub(crate) min_command_ratio: Option<f64>,
+
+    /// Refuse to run a program which looks like it is not Brainfuck, rather
+    /// than only warning about it
+    #[arg(long, default_value_t = false, overrides_with = "no_strict_source")]
+    pub(crate) strict_source: bool,
+
+    /// Only warn about a program which looks like it is not Brainfuck, even
+    /// if a configuration file asks for `strict-source`.
+    #[arg(long, overrides_with = "strict_source")]
+    pub(crate) no_strict_source: bool,
+
+    /// Only allow comments from `;` to the end of the line or between `{` and
+    /// `}`, and refuse any other character which is not a command or
+    /// whitespace
+    #[arg(long, default_value_t = false, overrides_with = "no_strict")]
+    pub(crate) strict: bool,
+
+    /// Allow any character as a comment, even if a configuration file asks
+    /// for `strict`.
+    #[arg(long, overrides_with = "strict")]
+    pub(crate) no_strict: bool,
+
+    /// The maximum number of instructions to execute before giving up.
+    #[arg(long)]
+    pub(crate) max_steps: Option<u64>,
+
+    /// The maximum number of bytes the program may write, after which it is
+    /// stopped with an error
+    #[arg(long)]
+    pub(crate) max_output_bytes: Option<u64>,
+
+    /// The maximum number of bytes the program may read, after which it is at
+    /// the end of its input, as `--eof` says, even if more could be read
+    #[arg(long)]
+    pub(crate) max_input_bytes: Option<u64>,
+
+    /// Let the program read a millisecond clock with `%`: with 1 in the
+    /// current cell, it writes the time since the program started into the
+    /// cells after the head, a byte to each, lowest byte first
+    #[arg(long)]
+    pub(crate) clock: bool,
+
+    /// The number of cells the clock writes the time across [default: 1]
+    #[arg(long, value_name = "CELLS", value_parser = clap::value_parser!(u8).range(1..=8))]
+    pub(crate) clock_cells: Option<u8>,
+
+    /// Read a virtual clock instead of the real one, which starts from zero
+    /// and moves on by the given number of milliseconds each time it is read,
+    /// so that runs can be reproduced. Implies `--clock`
+    #[arg(long, value_name = "MS")]
+    pub(crate) virtual_clock: Option<u64>,
+
+    /// Optimize the program before running it, with a comma separated list of
+    /// passes: rle, clearloop, copyloop, scanloop, mulloop, ifelse, offsets,
+    /// ranges, deadstore and unreachable. Passes prefixed with `-` are left
+    /// out, and leaving out passes alone keeps the rest, while `none` lowers
+    /// the program without optimizing it.
+    #[arg(long, allow_hyphen_values = true)]
+    pub(crate) passes: Option<String>,
+
+    /// The optimization level: 0 runs the program as it is, 1 merges runs of
+    /// instructions, 2 runs all of the passes, and 3 also runs the start of the
+    /// program, up to where it first reads input, ahead of time. Passes given
+    /// with `--passes` take the place of those for the level [default: 0]
+    #[arg(short = 'O', long, value_parser = clap::value_parser!(u8).range(0..=3))]
+    pub(crate) opt_level: Option<u8>,
+
+    /// The config file to read defaults from, instead of searching for a
+    /// `bft.toml` in the current directory and its parents.
+    #[arg(long)]
+    pub(crate) config: Option<PathBuf>,
+
+    /// Load a plugin, a dynamic library which can add optimization passes to
+    /// name in `--passes`, dialects which programs with their extensions are
+    /// written in, and codecs for `--codec`. May be given more than once
+    #[cfg(feature = "plugins")]
+    #[arg(long = "plugin", value_name = "PATH")]
+    pub(crate) plugins: Vec<PathBuf>,
+
+    /// Encode the output of the program, and decode its input, with the named
+    /// codec of a plugin
+    #[cfg(feature = "plugins")]
+    #[arg(long)]
+    pub(crate) codec: Option<String>,
+}
+
+/// The flags which only apply when running a single program, either directly
+/// or with the `run` subcommand.
+#[derive(ClapArgs, Debug, Clone)]
+pub(crate) struct RunOnlyArgs {
+    /// Print more about the run to stderr: once to describe the program, twice
+    /// to also show what each optimization pass did, and three times to also
+    /// trace each step of the program.
+    #[arg(short, long, action = clap::ArgAction::Count)]
+    pub(crate) verbose: u8,
+
+    /// Print how long parsing, pairing brackets, optimizing and running the
+    /// program took to stderr, along with how much tape it used.
+    #[arg(long)]
+    pub(crate) timings: bool,
+
+    /// Count the steps run in each loop, and print the loops which the most
+    /// steps were run in to stderr. Turns on tracing, which makes programs run
+    /// more slowly.
+    #[arg(long)]
+    pub(crate) profile: bool,
+
+    /// Write the profile as a flamegraph to the given file: an SVG image if
+    /// it ends in `.svg`, and otherwise the folded stacks read by flamegraph
+    /// tools.
+    #[arg(long, value_name = "FILE", requires = "profile")]
+    pub(crate) flamegraph: Option<PathBuf>,
+
+    /// Sample the steps, output bytes and tape cells of the run every
+    /// `--metrics-interval` steps, writing them to the given file as rows of
+    /// CSV, or if it ends in `.prom`, keeping the latest sample in it in the
+    /// Prometheus text format, along with the steps run of each kind of
+    /// operation. Turns on tracing, which makes programs run more slowly.
+    #[arg(long, value_name = "FILE")]
+    pub(crate) metrics: Option<PathBuf>,
+
+    /// How many steps apart the samples for `--metrics` are taken.
+    #[arg(long, default_value_t = 100_000, requires = "metrics")]
+    pub(crate) metrics_interval: u64,
+
+    /// Write a trace of the loops and input and output of the run to the given
+    /// file, in Chrome's trace-event format for viewing in Perfetto. Turns on
+    /// tracing, which makes programs run more slowly.
+    #[arg(long, value_name = "FILE")]
+    pub(crate) trace_out: Option<PathBuf>,
+
+    /// Record the run to the given replay file, along with the input the
+    /// program read, so that it can be reproduced with `bft replay`.
+    #[arg(long, value_name = "FILE")]
+    pub(crate) record: Option<PathBuf>,
+
+    /// Record every step of the run to the given step log, usually ending in
+    /// `.bfsteps`, so that `bft replay-steps` can jump to any step of it.
+    /// Turns on tracing, which makes programs run more slowly, and cannot be
+    /// used with the optimizer.
+    #[arg(long, value_name = "FILE")]
+    pub(crate) record_steps: Option<PathBuf>,
+
+    /// Write a JSON manifest describing the run to the given file once the
+    /// program has finished.
+    #[arg(long)]
+    pub(crate) emit_manifest: Option<PathBuf>,
+
+    /// A file naming regions of the tape, one on each line as
+    /// `0..8 = "counters"`, so that errors name the cells they happened at.
+    #[arg(long, value_name = "FILE")]
+    pub(crate) memory_map: Option<PathBuf>,
+
+    /// Check that the program writes exactly this output, failing with a diff
+    /// of the lines which differ if it does not. The output is read from the
+    /// file if one has this name, and is otherwise the text itself, in which
+    /// `\n`, `\r`, `\t`, `\0`, `\\` and `\xNN` are escapes.
+    #[arg(long, value_name = "FILE|STRING")]
+    pub(crate) expect_output: Option<String>,
+
+    /// Check that the run exits with this code: 0 if the program halts, or 1
+    /// if it fails, in which case the run passes the check rather than
+    /// failing.
+    #[arg(
+        long,
+        value_name = "CODE",
+        value_parser = clap::value_parser!(u8).range(0..=1)
+    )]
+    pub(crate) expect_exit: Option<u8>,
+}
+
+/// The arguments for the `stats` and `id` subcommands.
+#[derive(ClapArgs, Debug)]
+pub(crate) struct StatsArgs {
+    /// The filename of the program to describe.
+    pub(crate) filename: PathBuf,
+
+    /// The settings used to parse the program.
+    #[command(flatten)]
+    pub(crate) run: RunArgs,
+}
+
+/// The arguments for the `optimize` subcommand.
+#[derive(ClapArgs, Debug)]
+pub(crate) struct OptimizeArgs {
+    /// The filename of the program to optimize.
+    pub(crate) filename: PathBuf,
+
+    /// Where to write the optimized program, instead of stdout.
+    #[arg(short, long)]
+    pub(crate) output: Option<PathBuf>,
+
+    /// Carry the shebang and comments of the program through to the
+    /// optimized program, each comment before the code for the instruction it
+    /// was written before. Any commands in comments are taken out.
+    #[arg(long)]
+    pub(crate) keep_comments: bool,
+
+    /// The settings used to parse the program. The width of the cells decides
+    /// how amounts wrap around, and `--passes` picks the passes to run.
+    #[command(flatten)]
+    pub(crate) run: RunArgs,
+}
+
+/// The arguments for the `golf` subcommand.
+#[derive(ClapArgs, Debug)]
+pub(crate) struct GolfArgs {
+    /// The filename of the program to score.
+    pub(crate) filename: PathBuf,
+
+    /// Another version of the program to compare the score against.
+    #[arg(long, value_name = "FILE")]
+    pub(crate) baseline: Option<PathBuf>,
+
+    /// How many levels of nested loops to break the size down into, or 0 to
+    /// leave out the breakdown.
+    #[arg(long, default_value_t = 1)]
+    pub(crate) depth: usize,
+
+    /// The settings used to parse the program.
+    #[command(flatten)]
+    pub(crate) run: RunArgs,
+}
+
+/// The formats which the `decompile` subcommand can write.
+#[derive(ValueEnum, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum DecompileFormat {
+    /// Indented pseudo-code.
+    #[default]
+    Text,
+    /// The tree of statements as JSON.
+    Json,
+}
+
+/// The arguments for the `decompile` subcommand.
+#[derive(ClapArgs, Debug)]
+pub(crate) struct DecompileArgs {
+    /// The filename of the program to decompile.
+    pub(crate) filename: PathBuf,
+
+    /// The format to write the pseudo-code in.
+    #[arg(long, value_enum, default_value_t = DecompileFormat::Text)]
+    pub(crate) format: DecompileFormat,
+
+    /// Where to write the pseudo-code, instead of stdout.
+    #[arg(short, long)]
+    pub(crate) output: Option<PathBuf>,
+
+    /// The settings used to parse the program. The idioms recognized are
+    /// those of the passes given to `--passes`, or every built-in pass.
+    #[command(flatten)]
+    pub(crate) run: RunArgs,
+}
+
+/// The formats which the `explain` subcommand can write.
+#[derive(ValueEnum, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ExplainFormat {
+    /// Plain text.
+    #[default]
+    Text,
+    /// A Markdown document, with a table for each line.
+    Markdown,
+    /// A standalone HTML page.
+    Html,
+}
+
+/// The arguments for the `explain` subcommand.
+#[derive(ClapArgs, Debug)]
+pub(crate) struct ExplainArgs {
+    /// The filename of the program to explain.
+    pub(crate) filename: PathBuf,
+
+    /// The format to write the walkthrough in.
+    #[arg(long, value_enum, default_value_t = ExplainFormat::Text)]
+    pub(crate) format: ExplainFormat,
+
+    /// Where to write the walkthrough, instead of stdout.
+    #[arg(short, long)]
+    pub(crate) output: Option<PathBuf>,
+
+    /// The settings used to parse the program.
+    #[command(flatten)]
+    pub(crate) run: RunArgs,
+}
+
+/// The arguments for the `teach` subcommand.
+#[derive(ClapArgs, Debug)]
+pub(crate) struct TeachArgs {
+    /// The filename of the program to run.
+    pub(crate) filename: PathBuf,
+
+    /// How long to wait after each step which is narrated, in milliseconds.
+    #[arg(long, value_name = "MS", default_value_t = 500)]
+    pub(crate) delay: u64,
+
+    /// Only narrate every Nth step, for longer programs.
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 1,
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    pub(crate) narrate_every: u64,
+
+    /// A file to read the input of the program from. Defaults to an empty
+    /// input.
+    #[arg(long, value_name = "FILE")]
+    pub(crate) input: Option<PathBuf>,
+
+    /// The settings used to run the program.
+    #[command(flatten)]
+    pub(crate) run: RunArgs,
+}
+
+/// The subcommands of the `asm` subcommand.
+#[derive(Subcommand, Debug)]
+pub(crate) enum AsmCommand {
+    /// Assemble a program down to Brainfuck, or to the intermediate
+    /// representation.
+    Build(AsmBuildArgs),
+}
+
+/// What the `asm build` subcommand assembles programs into.
+#[derive(ValueEnum, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Emit {
+    /// Brainfuck source.
+    #[default]
+    Brainfuck,
+    /// A listing of the intermediate representation, one node to a line.
+    Ir,
+}
+
+/// The arguments for the `asm build` subcommand.
+#[derive(ClapArgs, Debug)]
+pub(crate) struct AsmBuildArgs {
+    /// The filename of the assembly program.
+    pub(crate) filename: PathBuf,
+
+    /// What to assemble the program into.
+    #[arg(long, value_enum, default_value_t = Emit::Brainfuck)]
+    pub(crate) emit: Emit,
+
+    /// Where to write the assembled program, instead of stdout.
+    #[arg(short, long)]
+    pub(crate) output: Option<PathBuf>,
+}
+
+/// The arguments for the `replay` subcommand.
+#[derive(ClapArgs, Debug)]
+pub(crate) struct ReplayArgs {
+    /// The replay file to run, usually ending in `.bftrun`.
+    pub(crate) file: PathBuf,
+
+    /// The program to run, instead of the one at the path which was recorded.
+    /// It must still have the same instructions.
+    #[arg(long)]
+    pub(crate) program: Option<PathBuf>,
+}
+
+/// The arguments for the `replay-steps` subcommand.
+#[derive(ClapArgs, Debug)]
+pub(crate) struct ReplayStepsArgs {
+    /// The step log to read, usually ending in `.bfsteps`.
+    pub(crate) file: PathBuf,
+
+    /// The step to jump to, counting from 1. The debugger starts just before
+    /// it runs.
+    #[arg(long, default_value_t = 1)]
+    pub(crate) step: u64,
+
+    /// The program to run, instead of the one at the path which was recorded.
+    /// It must still have the same instructions.
+    #[arg(long)]
+    pub(crate) program: Option<PathBuf>,
+}
+
+/// The arguments for the `serve-stdio` subcommand.
+#[derive(ClapArgs, Debug)]
+pub(crate) struct ServeStdioArgs {
+    /// The settings used for every program loaded. The step limit is how many
+    /// steps `run` takes at most, unless the request gives its own.
+    #[command(flatten)]
+    pub(crate) run: RunArgs,
+}
+
+/// The arguments for the `serve-http` subcommand.
+#[cfg(feature = "http")]
+#[derive(ClapArgs, Debug)]
+pub(crate) struct ServeHttpArgs {
+    /// The address to listen on. `:8080` listens on port 8080 of every
+    /// interface.
+    #[arg(long, default_value = "127.0.0.1:8080")]
+    pub(crate) addr: String,
+
+    /// The number of requests run at once.
+    #[arg(long, default_value_t = 4)]
+    pub(crate) workers: usize,
+
+    /// The settings used for every program run. The step limit, the number of
+    /// cells and the program size limit are the most a request may ask for.
+    #[command(flatten)]
+    pub(crate) run: RunArgs,
+}
+
+/// The arguments for the `plugins` subcommand.
+#[cfg(feature = "plugins")]
+#[derive(ClapArgs, Debug)]
+pub(crate) struct PluginsArgs {
+    /// The settings naming the plugins to load.
+    #[command(flatten)]
+    pub(crate) run: RunArgs,
+}
+
+/// The arguments shared by the subcommands which work on a project.
+#[derive(ClapArgs, Debug)]
+pub(crate) struct ProjectArgs {
+    /// The names of the programs to work on, instead of all of them.
+    pub(crate) names: Vec<String>,
+
+    /// The project file to use, instead of the first `bfproject.toml` found
+    /// in the current directory or any of its parents.
+    #[arg(long, value_name = "FILE")]
+    pub(crate) project: Option<PathBuf>,
+
+    /// The settings used for every program. They take precedence over the
+    /// settings in the project file.
+    #[command(flatten)]
+    pub(crate) run: RunArgs,
+}
+
+/// The arguments for the `build` subcommand.
+#[derive(ClapArgs, Debug)]
+pub(crate) struct BuildArgs {
+    /// The directory to write each optimized program to, as plain Brainfuck
+    /// named after the program.
+    #[arg(long, value_name = "DIR")]
+    pub(crate) out_dir: Option<PathBuf>,
+
+    /// The programs to build, and the settings used for them.
+    #[command(flatten)]
+    pub(crate) project: ProjectArgs,
+}
+
+/// The arguments for the `new` subcommand.
+#[derive(ClapArgs, Debug)]
+pub(crate) struct NewArgs {
+    /// The directory to create the project in, which is named after it.
+    pub(crate) dir: PathBuf,
+
+    /// Also add a README, a .gitignore and a GitHub Actions workflow which
+    /// builds and tests the project.
+    #[arg(long)]
+    pub(crate) github: bool,
+}
+
+/// The arguments for the `analyze` subcommand.
+#[derive(ClapArgs, Debug)]
+pub(crate) struct AnalyzeArgs {
+    /// The filename of the program to analyze.
+    pub(crate) filename: PathBuf,
+
+    /// Report which behaviors the program depends on, such as moving left of
+    /// the first cell, cells wrapping at 256 and what `,` does at the end of
+    /// the input, and which presets it runs correctly under.
+    #[arg(
+        long,
+        required_unless_present_any = ["symbolic", "reachable", "independence"]
+    )]
+    pub(crate) compat: bool,
+
+    /// Run the program symbolically, over every input at once, to find
+    /// whether any input moves the head left of the first cell and, with
+    /// `--expect`, which input writes the expected output. Experimental.
+    #[arg(long)]
+    pub(crate) symbolic: bool,
+
+    /// Report whether the instruction at the line and column can ever run,
+    /// and the input which runs it if it can, from the control flow of the
+    /// program and symbolic execution.
+    #[arg(long, value_name = "LINE:COL")]
+    pub(crate) reachable: Option<String>,
+
+    /// Report the segments of the program and the cells each touches, and
+    /// the sections of consecutive segments which do not interfere with each
+    /// other, so could run in parallel.
+    #[arg(long)]
+    pub(crate) independence: bool,
+
+    /// The most steps each symbolic run of the program takes, for
+    /// `--symbolic` and `--reachable`.
+    #[arg(long, value_name = "STEPS", default_value_t = 10_000)]
+    pub(crate) depth: u64,
+
+    /// A file to read the input of the program from. Defaults to an empty
+    /// input.
+    #[arg(long, value_name = "FILE")]
+    pub(crate) input: Option<PathBuf>,
+
+    /// A file holding the output the program should write, so that only the
+    /// presets it writes it under count as running it correctly, and which
+    /// symbolic execution looks for the input of.
+    #[arg(long, value_name = "FILE")]
+    pub(crate) expect: Option<PathBuf>,
+
+    /// The settings used to run the program, apart from those which are
+    /// varied to see what the program depends on.
+    #[command(flatten)]
+    pub(crate) run: RunArgs,
+}
+
+/// The arguments for the `check` subcommand.
+#[derive(ClapArgs, Debug)]
+pub(crate) struct CheckArgs {
+    /// The filename of the program to check.
+    #[arg(required_unless_present = "workspace")]
+    pub(crate) filename: Option<PathBuf>,
+
+    /// Report the places the program may behave differently with cells wider
+    /// than 8 bits, such as counting down from zero to get 255, to tell
+    /// whether a wider `--cell-width` is safe.
+    #[arg(long, required_unless_present = "workspace")]
+    pub(crate) portability: bool,
+
+    /// Check every program of a project, given its project file or a
+    /// directory with one, or every program in a directory, reporting every
+    /// problem found in each. Defaults to the project of the current
+    /// directory, or else the current directory.
+    #[arg(
+        long,
+        value_name = "PATH",
+        num_args = 0..=1,
+        conflicts_with = "filename"
+    )]
+    pub(crate) workspace: Option<Option<PathBuf>>,
+
+    /// The settings used to parse the program, and what `,` does at the end
+    /// of the input.
+    #[command(flatten)]
+    pub(crate) run: RunArgs,
+}
+
+/// The arguments for the `selfhost` subcommand.
+#[derive(ClapArgs, Debug)]
+pub(crate) struct SelfhostArgs {
+    /// The filename of the program to run.
+    pub(crate) program: PathBuf,
+
+    /// A file to read the input of the program from. Defaults to an empty
+    /// input.
+    #[arg(long, value_name = "FILE")]
+    pub(crate) input: Option<PathBuf>,
+
+    /// The settings used to interpret both dbfi and the program. If no step
+    /// limit is given, then a default one is used for the program, and dbfi
+    /// is left to run for as long as it takes.
+    #[command(flatten)]
+    pub(crate) run: RunArgs,
+}
+
+/// The arguments for the `equiv` subcommand.
+#[derive(ClapArgs, Debug)]
+pub(crate) struct EquivArgs {
+    /// The filename of the first program.
+    pub(crate) first: PathBuf,
+
+    /// The filename of the second program.
+    pub(crate) second: PathBuf,
+
+    /// The inputs to run both programs with. Either `fuzz:<count>` for a
+    /// number of randomly generated inputs, or `file:<path>` for the contents
+    /// of a file. May be given more than once. Defaults to an empty input.
+    #[arg(long = "inputs")]
+    pub(crate) inputs: Vec<String>,
+
+    /// The seed used to generate fuzzed inputs.
+    #[arg(long, default_value_t = 0x5eed)]
+    pub(crate) seed: u64,
+
+    /// The maximum length of each fuzzed input.
+    #[arg(long, default_value_t = 16)]
+    pub(crate) max_input_len: usize,
+
+    /// Also require both programs to finish with identical tapes.
+    #[arg(long, default_value_t = false)]
+    pub(crate) compare_tape: bool,
+
+    /// How the cells of the tapes are shown when they differ: decimal, hex
+    /// or ascii, which shows each cell as a character with escapes.
+    #[arg(long, default_value_t = CellDisplay::Decimal, requires = "compare_tape")]
+    pub(crate) display: CellDisplay,
+
+    /// A file naming regions of the tape, one on each line as
+    /// `0..8 = "counters"`, to group the cells of the tapes by when they
+    /// differ.
+    #[arg(long, value_name = "FILE", requires = "compare_tape")]
+    pub(crate) memory_map: Option<PathBuf>,
+
+    /// When the programs differ, write a replay of each of them on the input
+    /// they differ on to this directory, named after the programs.
+    #[arg(long, value_name = "DIR")]
+    pub(crate) save_replays: Option<PathBuf>,
+
+    /// The settings used to interpret both programs. If no step limit is
+    /// given, then a default one is used so that hanging programs are caught.
+    #[command(flatten)]
+    pub(crate) run: RunArgs,
+}
+
+/// The ways in which the output of a program can be shown.
+#[derive(ValueEnum, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum IoMode {
+    /// Write the bytes as they are.
+    #[default]
+    Raw,
+    /// Show the bytes as a hex dump, sixteen to a line, which is safer for
+    /// binary output than writing it to a terminal.
+    Hexdump,
+}
+
+/// The ways in which the `pipe` subcommand can join programs together.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Composition {
+    /// Run the programs at the same time, with the output of each program fed
+    /// into the input of the next.
+    Stream,
+    /// Run the programs one after another, each starting on the tape the
+    /// previous program finished with. They all share stdin and stdout.
+    Tape,
+}
+
+/// The arguments for the `pipe` subcommand.
+#[derive(ClapArgs, Debug)]
+pub(crate) struct PipeArgs {
+    /// The filenames of the programs, in the order that data flows through
+    /// them. The first reads from stdin and the last writes to stdout.
+    #[arg(required = true)]
+    pub(crate) programs: Vec<PathBuf>,
+
+    /// How the programs are joined together.
+    #[arg(long, value_enum, default_value_t = Composition::Stream)]
+    pub(crate) compose: Composition,
+
+    /// The most bytes which may be waiting between two programs at once,
+    /// before the earlier program is made to wait for the later one. Only used
+    /// when composing with `stream`.
+    #[arg(long, default_value_t = 4096)]
+    pub(crate) buffer_size: usize,
+
+    /// Start each program with the head where the previous program left it,
+    /// rather than at the start of the tape. Only used when composing with
+    /// `tape`.
+    #[arg(long)]
+    pub(crate) keep_head: bool,
+
+    /// The settings used to interpret each of the programs.
+    #[command(flatten)]
+    pub(crate) run: RunArgs,
+}
+
+/// The languages which the `compile` subcommand can compile programs into.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Target {
+    /// C, with the tape as a fixed size array.
+    C,
+    /// Textual LLVM IR, to build with clang or optimize with opt, with the
+    /// tape as a fixed size array.
+    LlvmIr,
+    /// An assembly listing for Linux, with the line and column of each
+    /// operation in a comment, to read or to build with a C compiler.
+    Asm,
+}
+
+/// The architectures which `--target asm` can write assembly for.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Arch {
+    /// x86-64, in Intel syntax.
+    #[value(name = "x86-64")]
+    X86_64,
+    /// AArch64, also known as ARM64.
+    Aarch64,
+}
+
+impl Arch {
+    /// The architecture bft was built for, or x86-64 if it was built for
+    /// another.
+    pub(crate) fn host() -> Self {
+        if cfg!(target_arch = "aarch64") {
+            Arch::Aarch64
+        } else {
+            Arch::X86_64
+        }
+    }
+}
+
+/// The arguments for the `compile` subcommand.
+#[derive(ClapArgs, Debug)]
+pub(crate) struct CompileArgs {
+    /// The filename of the program to compile.
+    pub(crate) filename: PathBuf,
+
+    /// The language to compile the program into.
+    #[arg(long, value_enum, default_value_t = Target::C)]
+    pub(crate) target: Target,
+
+    /// The architecture to write assembly for, with `--target asm`. Defaults
+    /// to the one bft was built for.
+    #[arg(long, value_enum)]
+    pub(crate) arch: Option<Arch>,
+
+    /// Where to write the compiled program, instead of stdout.
+    #[arg(short, long)]
+    pub(crate) output: Option<PathBuf>,
+
+    /// The settings used to compile the program. Compiled programs cannot grow
+    /// their tape, and do not have a step limit.
+    #[command(flatten)]
+    pub(crate) run: RunArgs,
+}
+
+/// The arguments for the `bundle` subcommand.
+#[derive(ClapArgs, Debug)]
+pub(crate) struct BundleArgs {
+    /// The filename of the program to bundle.
+    pub(crate) filename: PathBuf,
+
+    /// Where to write the executable. Defaults to the filename of the program
+    /// without its extension.
+    #[arg(short, long)]
+    pub(crate) output: Option<PathBuf>,
+
+    /// The C compiler to build the executable with. Defaults to the `CC`
+    /// environment variable, or `cc`.
+    #[arg(long)]
+    pub(crate) cc: Option<String>,
+
+    /// The settings used to compile the program. Compiled programs cannot grow
+    /// their tape, and do not have a step limit.
+    #[command(flatten)]
+    pub(crate) run: RunArgs,
+}
+
+/// The arguments for the `pack` subcommand.
+#[derive(ClapArgs, Debug)]
+pub(crate) struct PackArgs {
+    /// The filename of the program to pack.
+    pub(crate) filename: PathBuf,
+
+    /// Where to write the packed program. Defaults to the filename of the
+    /// program with the extension `.bfz`.
+    #[arg(short, long)]
+    pub(crate) output: Option<PathBuf>,
+
+    /// The settings used to load the program. The cell width is recorded in
+    /// the packed program.
+    #[command(flatten)]
+    pub(crate) run: RunArgs,
+}
+
+/// The arguments for the `unpack` subcommand.
+#[derive(ClapArgs, Debug)]
+pub(crate) struct UnpackArgs {
+    /// The filename of the packed program.
+    pub(crate) filename: PathBuf,
+
+    /// Where to write the program, instead of stdout.
+    #[arg(short, long)]
+    pub(crate) output: Option<PathBuf>,
+}
+
+/// The arguments for the `diff` subcommand.
+#[derive(ClapArgs, Debug)]
+pub(crate) struct DiffArgs {
+    /// The filename of the first program.
+    pub(crate) first: PathBuf,
+
+    /// The filename of the second program.
+    pub(crate) second: PathBuf,
+
+    /// Compare the programs once lowered and optimized at the level given by
+    /// `-O`, so that changes which optimize to the same thing are left out.
+    #[arg(long)]
+    pub(crate) ir: bool,
+
+    /// The settings used to load the programs, and to optimize them with
+    /// `--ir`.
+    #[command(flatten)]
+    pub(crate) run: RunArgs,
+}
+
+/// The arguments for the `patch` subcommand.
+#[derive(ClapArgs, Debug)]
+pub(crate) struct PatchArgs {
+    /// The filename of the program to patch.
+    pub(crate) filename: PathBuf,
+
+    /// The filename of the patch, as written by `bft diff`.
+    pub(crate) patch: PathBuf,
+
+    /// Where to write the patched program, instead of stdout.
+    #[arg(short, long)]
+    pub(crate) output: Option<PathBuf>,
+
+    /// The settings used to parse the program.
+    #[command(flatten)]
+    pub(crate) run: RunArgs,
+}
+
+/// The arguments for the `shrink` subcommand.
+#[derive(ClapArgs, Debug)]
+pub(crate) struct ShrinkArgs {
+    /// The filename of the failing program.
+    pub(crate) filename: PathBuf,
+
+    /// The failure to preserve: `exit:error` for any error, `exit:hang` for
+    /// hitting the step limit, or `error:<text>` for an error whose message
+    /// contains the given text.
+    #[arg(long, default_value = "exit:error")]
+    pub(crate) check: String,
+
+    /// A file containing the input to run the program with.
+    #[arg(long)]
+    pub(crate) input: Option<PathBuf>,
+
+    /// Where to write the shrunk program, instead of stdout.
+    #[arg(short, long)]
+    pub(crate) output: Option<PathBuf>,
+
+    /// The settings used to interpret the program. If no step limit is given,
+    /// then a default one is used so that hanging programs are caught.
+    #[command(flatten)]
+    pub(crate) run: RunArgs,
 }
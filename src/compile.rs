@@ -0,0 +1,964 @@
+//! The `compile` subcommand, which translates a program into another language
+//! by way of the intermediate representation, so that it can be built into a
+//! native executable.
+
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::process::ExitCode;
+
+use bft_interp::eof::EofBehavior;
+use bft_interp::io::Newlines;
+use bft_interp::ir::{IrOp, IrProgram};
+
+use crate::assembly::emit_asm;
+use crate::cache::OutputCache;
+use crate::cli::{Arch, CompileArgs, IoMode, Target};
+use crate::config::{CellWidth, Settings};
+use crate::load_program;
+
+/// The number of values written on each line of an initialized array.
+const VALUES_PER_LINE: usize = 12;
+
+/// The message LLVM IR writes to stderr when the program reads past the end of
+/// its input, and reaching the end of the input is an error.
+const EOF_MESSAGE: &[u8] = b"bft: the program read past the end of its input\n";
+
+/// Writes a single line of code, indented to the given depth.
+fn line(code: &mut String, depth: usize, text: &str) {
+    code.push_str(&"    ".repeat(depth));
+    code.push_str(text);
+    code.push('\n');
+}
+
+/// Formats adding a signed amount with `+=` or `-=`. Amounts are unsigned in
+/// C, so that overflowing them wraps around as the cells do.
+fn add_assign(target: &str, amount: i64) -> String {
+    if amount < 0 {
+        format!("{} -= {}u;", target, amount.unsigned_abs())
+    } else {
+        format!("{} += {}u;", target, amount)
+    }
+}
+
+/// Formats the position of the cell at the offset from the head.
+fn index_at(offset: isize) -> String {
+    match offset {
+        0 => "head".to_string(),
+        offset if offset < 0 => format!("head - {}", offset.unsigned_abs()),
+        offset => format!("head + {}", offset),
+    }
+}
+
+/// Formats the cell at the offset from the head.
+fn cell_at(offset: isize) -> String {
+    format!("tape[{}]", index_at(offset))
+}
+
+/// Formats bytes as C string literals, starting a new literal after each
+/// newline so that the text of the output is readable.
+fn string_literals(bytes: &[u8]) -> Vec<String> {
+    let mut literals = vec![String::new()];
+    for byte in bytes {
+        let literal = literals.last_mut().expect("there is always a literal");
+        match byte {
+            b'"' | b'\\' | b'?' => {
+                literal.push('\\');
+                literal.push(*byte as char);
+            }
+            b' '..=b'~' => literal.push(*byte as char),
+            b'\n' => {
+                literal.push_str("\\n");
+                literals.push(String::new());
+            }
+            _ => {
+                literal.push_str(&format!("\\{:03o}", byte));
+            }
+        }
+    }
+    if literals.len() > 1 && literals.last().is_some_and(String::is_empty) {
+        literals.pop();
+    }
+    literals
+        .into_iter()
+        .map(|literal| format!("\"{}\"", literal))
+        .collect()
+}
+
+/// Formats values as the contents of an initialized array.
+fn array_lines(values: &[u32]) -> Vec<String> {
+    values
+        .chunks(VALUES_PER_LINE)
+        .map(|chunk| {
+            let values: Vec<String> =
+                chunk.iter().map(|value| format!("{}u,", value)).collect();
+            values.join(" ")
+        })
+        .collect()
+}
+
+/// Compiles the program into C. The tape is a fixed size array, so any
+/// `LoadTape` at the very start of the program, as left by partial
+/// evaluation, becomes its initial contents.
+///
+/// Compiled programs do not check that the head stays on the tape.
+pub(crate) fn emit_c(
+    ir: &IrProgram,
+    settings: &Settings,
+) -> Result<String, Box<dyn Error>> {
+    let cell = match settings.cell_width {
+        CellWidth::U8 => "uint8_t",
+        CellWidth::U16 => "uint16_t",
+        CellWidth::U32 => "uint32_t",
+    };
+    let nodes = ir.nodes();
+    let initial = nodes
+        .iter()
+        .position(|node| !matches!(node.op(), IrOp::OutputBytes(_)))
+        .filter(|&at| matches!(nodes[at].op(), IrOp::LoadTape(_)));
+
+    let mut code = String::new();
+    let filename = ir.filename().display().to_string().replace("*/", "* /");
+    line(
+        &mut code,
+        0,
+        &format!("/* Compiled from {} by bft. */", filename),
+    );
+    for header in ["stdint.h", "stdio.h", "string.h"] {
+        line(&mut code, 0, &format!("#include <{}>", header));
+    }
+    line(&mut code, 0, "");
+    line(&mut code, 0, &format!("typedef {} cell;", cell));
+    line(&mut code, 0, "");
+    match initial.map(|at| nodes[at].op()) {
+        Some(IrOp::LoadTape(values)) => {
+            line(
+                &mut code,
+                0,
+                &format!("static cell tape[{}] = {{", settings.cells),
+            );
+            for values in array_lines(values) {
+                line(&mut code, 1, &values);
+            }
+            line(&mut code, 0, "};");
+        }
+        _ => line(
+            &mut code,
+            0,
+            &format!("static cell tape[{}];", settings.cells),
+        ),
+    }
+    line(&mut code, 0, "");
+    line(&mut code, 0, "int main(void) {");
+    line(&mut code, 1, "size_t head = 0;");
+    line(&mut code, 1, "int c;");
+    // Short programs may not use any of these.
+    line(&mut code, 1, "(void)tape;");
+    line(&mut code, 1, "(void)head;");
+    line(&mut code, 1, "(void)c;");
+
+    let mut depth = 1;
+    for (position, node) in nodes.iter().enumerate() {
+        if Some(position) == initial {
+            continue;
+        }
+        match node.op() {
+            IrOp::Add(delta) => line(
+                &mut code,
+                depth,
+                &add_assign("tape[head]", (*delta).into()),
+            ),
+            IrOp::Move(delta) => {
+                line(&mut code, depth, &add_assign("head", *delta as i64))
+            }
+            IrOp::Output => line(&mut code, depth, "putchar(tape[head]);"),
+            IrOp::Input => {
+                line(&mut code, depth, "c = getchar();");
+                line(&mut code, depth, "if (c != EOF) {");
+                line(&mut code, depth + 1, "tape[head] = (cell)c;");
+                match settings.eof {
+                    EofBehavior::Error => {
+                        line(&mut code, depth, "} else {");
+                        line(
+                            &mut code,
+                            depth + 1,
+                            "fputs(\"bft: the program read past the end of its input\\n\", stderr);",
+                        );
+                        line(&mut code, depth + 1, "return 1;");
+                    }
+                    EofBehavior::Zero => {
+                        line(&mut code, depth, "} else {");
+                        line(&mut code, depth + 1, "tape[head] = 0;");
+                    }
+                    EofBehavior::Unchanged => {}
+                    EofBehavior::MaxValue => {
+                        line(&mut code, depth, "} else {");
+                        line(&mut code, depth + 1, "tape[head] = (cell)-1;");
+                    }
+                }
+                line(&mut code, depth, "}");
+            }
+            IrOp::LoopStart => {
+                line(&mut code, depth, "while (tape[head]) {");
+                depth += 1;
+            }
+            IrOp::LoopEnd => {
+                depth -= 1;
+                line(&mut code, depth, "}");
+            }
+            IrOp::If => {
+                line(&mut code, depth, "if (tape[head]) {");
+                depth += 1;
+            }
+            IrOp::Else => line(&mut code, depth - 1, "} else {"),
+            IrOp::EndIf => {
+                depth -= 1;
+                line(&mut code, depth, "}");
+            }
+            IrOp::Clear => line(&mut code, depth, "tape[head] = 0;"),
+            IrOp::AddAt(offset, delta) => line(
+                &mut code,
+                depth,
+                &add_assign(&cell_at(*offset), (*delta).into()),
+            ),
+            IrOp::ClearAt(offset) => {
+                line(&mut code, depth, &format!("{} = 0;", cell_at(*offset)))
+            }
+            IrOp::AddRange(offset, len, delta) => {
+                line(
+                    &mut code,
+                    depth,
+                    &format!("for (size_t i = 0; i < {}; i++) {{", len),
+                );
+                let target = format!("tape[{} + i]", index_at(*offset));
+                line(
+                    &mut code,
+                    depth + 1,
+                    &add_assign(&target, (*delta).into()),
+                );
+                line(&mut code, depth, "}");
+            }
+            IrOp::ClearRange(offset, len) => line(
+                &mut code,
+                depth,
+                &format!(
+                    "memset(&{}, 0, {} * sizeof(cell));",
+                    cell_at(*offset),
+                    len
+                ),
+            ),
+            IrOp::CopyLoop(targets) => {
+                line(&mut code, depth, "if (tape[head]) {");
+                for (offset, factor) in targets {
+                    let target = cell_at(*offset);
+                    let product = format!(
+                        "(uint32_t)tape[head] * {}u;",
+                        factor.unsigned_abs()
+                    );
+                    let operator = if *factor < 0 { "-=" } else { "+=" };
+                    line(
+                        &mut code,
+                        depth + 1,
+                        &format!("{} {} {}", target, operator, product),
+                    );
+                }
+                line(&mut code, depth + 1, "tape[head] = 0;");
+                line(&mut code, depth, "}");
+            }
+            IrOp::MulAdd(mul) => {
+                line(&mut code, depth, "if (tape[head]) {");
+                for (offset, by, factor) in &mul.products {
+                    let product = format!(
+                        "(uint32_t)tape[head] * {} * {}u;",
+                        cell_at(*by),
+                        factor.unsigned_abs()
+                    );
+                    let operator = if *factor < 0 { "-=" } else { "+=" };
+                    line(
+                        &mut code,
+                        depth + 1,
+                        &format!(
+                            "{} {} {}",
+                            cell_at(*offset),
+                            operator,
+                            product
+                        ),
+                    );
+                }
+                for (offset, amount) in &mul.adds {
+                    let product = format!(
+                        "(uint32_t)tape[head] * {}u;",
+                        amount.unsigned_abs()
+                    );
+                    let operator = if *amount < 0 { "-=" } else { "+=" };
+                    line(
+                        &mut code,
+                        depth + 1,
+                        &format!(
+                            "{} {} {}",
+                            cell_at(*offset),
+                            operator,
+                            product
+                        ),
+                    );
+                }
+                for (offset, value) in &mul.sets {
+                    line(
+                        &mut code,
+                        depth + 1,
+                        &format!("{} = (cell){};", cell_at(*offset), value),
+                    );
+                }
+                line(&mut code, depth + 1, "tape[head] = 0;");
+                line(&mut code, depth, "}");
+            }
+            IrOp::ScanRight(step) => line(
+                &mut code,
+                depth,
+                &format!("while (tape[head]) head += {};", step),
+            ),
+            IrOp::ScanLeft(step) => line(
+                &mut code,
+                depth,
+                &format!("while (tape[head]) head -= {};", step),
+            ),
+            IrOp::OutputBytes(bytes) => {
+                line(&mut code, depth, "fwrite(");
+                for literal in string_literals(bytes) {
+                    line(&mut code, depth + 1, &literal);
+                }
+                line(
+                    &mut code,
+                    depth + 1,
+                    &format!(", 1, {}, stdout);", bytes.len()),
+                );
+            }
+            IrOp::LoadTape(values) => {
+                line(&mut code, depth, "{");
+                line(&mut code, depth + 1, "static const cell data[] = {");
+                for values in array_lines(values) {
+                    line(&mut code, depth + 2, &values);
+                }
+                line(&mut code, depth + 1, "};");
+                line(
+                    &mut code,
+                    depth + 1,
+                    "memcpy(&tape[head], data, sizeof data);",
+                );
+                line(&mut code, depth, "}");
+            }
+            IrOp::Extension(name) => {
+                return Err(format!(
+                    "the extension instruction '{}' cannot be compiled",
+                    name
+                )
+                .into());
+            }
+        }
+    }
+    line(&mut code, 1, "return 0;");
+    line(&mut code, 0, "}");
+    Ok(code)
+}
+
+/// Wraps an amount to the range of a signed integer of the given number of
+/// bits, as LLVM and assemblers require of constants. Adding the wrapped
+/// amount wraps the same way as adding the amount itself.
+pub(crate) fn wrap(amount: i64, bits: u32) -> i64 {
+    let shift = 64 - bits;
+    (amount << shift) >> shift
+}
+
+/// Escapes bytes for an LLVM string constant.
+fn llvm_string(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|&byte| match byte {
+            b'"' | b'\\' => format!("\\{:02X}", byte),
+            b' '..=b'~' => (byte as char).to_string(),
+            _ => format!("\\{:02X}", byte),
+        })
+        .collect()
+}
+
+/// The loops and branches of the program which have been started but not
+/// ended, numbered so that their blocks can be told apart.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Open {
+    Loop(usize),
+    /// A branch, and whether its `Else` has been seen.
+    If(usize, bool),
+}
+
+/// Builds the body of the `main` function, numbering its values and blocks.
+struct LlvmBuilder {
+    code: String,
+    /// The constants the code refers to, written out after the function.
+    globals: Vec<String>,
+    /// The LLVM type of the cells.
+    cell: String,
+    values: usize,
+    blocks: usize,
+    open: Vec<Open>,
+}
+
+impl LlvmBuilder {
+    fn new(bits: u32) -> Self {
+        Self {
+            code: String::new(),
+            globals: Vec::new(),
+            cell: format!("i{}", bits),
+            values: 0,
+            blocks: 0,
+            open: Vec::new(),
+        }
+    }
+
+    /// Writes an instruction.
+    fn emit(&mut self, text: &str) {
+        line(&mut self.code, 1, text);
+    }
+
+    /// Starts a block.
+    fn block(&mut self, label: &str) {
+        self.code.push_str(label);
+        self.code.push_str(":\n");
+    }
+
+    /// Writes an instruction which has a result, returning its name.
+    fn value(&mut self, text: &str) -> String {
+        self.values += 1;
+        let name = format!("%v{}", self.values);
+        self.emit(&format!("{} = {}", name, text));
+        name
+    }
+
+    /// Numbers a new loop or branch.
+    fn number(&mut self) -> usize {
+        self.blocks += 1;
+        self.blocks
+    }
+
+    /// Finds the cell at the offset from the head.
+    fn pointer(&mut self, offset: isize) -> String {
+        let head = self.value("load i64, ptr %head");
+        let index = match offset {
+            0 => head,
+            offset => self.value(&format!("add i64 {}, {}", head, offset)),
+        };
+        let cell = self.cell.clone();
+        self.value(&format!(
+            "getelementptr inbounds {}, ptr @tape, i64 {}",
+            cell, index
+        ))
+    }
+
+    fn load(&mut self, offset: isize) -> String {
+        let pointer = self.pointer(offset);
+        let cell = self.cell.clone();
+        self.value(&format!("load {}, ptr {}", cell, pointer))
+    }
+
+    fn store(&mut self, offset: isize, value: &str) {
+        let pointer = self.pointer(offset);
+        let text = format!("store {} {}, ptr {}", self.cell, value, pointer);
+        self.emit(&text);
+    }
+
+    /// Adds an amount to the cell at the offset, wrapping around.
+    fn add(&mut self, offset: isize, amount: &str) {
+        let value = self.load(offset);
+        let cell = self.cell.clone();
+        let sum = self.value(&format!("add {} {}, {}", cell, value, amount));
+        self.store(offset, &sum);
+    }
+
+    /// Multiplies two values, wrapping around.
+    fn multiply(&mut self, left: &str, right: &str) -> String {
+        let cell = self.cell.clone();
+        self.value(&format!("mul {} {}, {}", cell, left, right))
+    }
+
+    /// Branches to `{label}.body` if the cell at the head is not zero, and
+    /// `{label}.{otherwise}` if it is.
+    fn branch_on_head(&mut self, label: &str, otherwise: &str) {
+        let value = self.load(0);
+        let cell = self.cell.clone();
+        let nonzero = self.value(&format!("icmp ne {} {}, 0", cell, value));
+        self.emit(&format!(
+            "br i1 {}, label %{}.body, label %{}.{}",
+            nonzero, label, label, otherwise
+        ));
+        self.block(&format!("{}.body", label));
+    }
+
+    /// Adds a constant to the module, returning its name.
+    fn constant(&mut self, ty: &str, value: &str) -> String {
+        let name = format!("@data{}", self.globals.len() + 1);
+        self.globals.push(format!(
+            "{} = private unnamed_addr constant {} {}",
+            name, ty, value
+        ));
+        name
+    }
+}
+
+/// Compiles the program into textual LLVM IR, to be built with clang or
+/// optimized further with opt. Cells are integers of their width, so the
+/// plain `add` and `mul` instructions wrap around as they do, and ranges of
+/// cells are cleared and loaded with the `llvm.memset` and `llvm.memcpy`
+/// intrinsics. The head is kept in memory for opt to promote to a register.
+///
+/// Compiled programs do not check that the head stays on the tape.
+pub(crate) fn emit_llvm_ir(
+    ir: &IrProgram,
+    settings: &Settings,
+) -> Result<String, Box<dyn Error>> {
+    let bits = settings.cell_width.bits();
+    let bytes = i64::from(bits / 8);
+    let mut builder = LlvmBuilder::new(bits);
+    let cell = builder.cell.clone();
+    for node in ir.nodes() {
+        match node.op() {
+            IrOp::Add(delta) => {
+                builder.add(0, &wrap((*delta).into(), bits).to_string())
+            }
+            IrOp::Move(delta) => {
+                let head = builder.value("load i64, ptr %head");
+                let moved =
+                    builder.value(&format!("add i64 {}, {}", head, delta));
+                builder.emit(&format!("store i64 {}, ptr %head", moved));
+            }
+            IrOp::Output => {
+                let value = builder.load(0);
+                let byte = match bits {
+                    32 => value,
+                    _ => builder
+                        .value(&format!("zext {} {} to i32", cell, value)),
+                };
+                builder.emit(&format!("call i32 @putchar(i32 {})", byte));
+            }
+            IrOp::Input => {
+                let label = format!("input{}", builder.number());
+                let c = builder.value("call i32 @getchar()");
+                let eof = builder.value(&format!("icmp eq i32 {}, -1", c));
+                builder.emit(&format!(
+                    "br i1 {}, label %{}.eof, label %{}.read",
+                    eof, label, label
+                ));
+                builder.block(&format!("{}.read", label));
+                let value = match bits {
+                    32 => c,
+                    _ => builder.value(&format!("trunc i32 {} to {}", c, cell)),
+                };
+                builder.store(0, &value);
+                builder.emit(&format!("br label %{}.done", label));
+                builder.block(&format!("{}.eof", label));
+                match settings.eof {
+                    EofBehavior::Error => {
+                        builder.emit(&format!(
+                            "call i64 @write(i32 2, ptr @eof_message, i64 {})",
+                            EOF_MESSAGE.len()
+                        ));
+                        builder.emit("ret i32 1");
+                    }
+                    EofBehavior::Zero => {
+                        builder.store(0, "0");
+                        builder.emit(&format!("br label %{}.done", label));
+                    }
+                    EofBehavior::Unchanged => {
+                        builder.emit(&format!("br label %{}.done", label));
+                    }
+                    EofBehavior::MaxValue => {
+                        builder.store(0, "-1");
+                        builder.emit(&format!("br label %{}.done", label));
+                    }
+                }
+                builder.block(&format!("{}.done", label));
+            }
+            IrOp::LoopStart => {
+                let number = builder.number();
+                builder.open.push(Open::Loop(number));
+                builder.emit(&format!("br label %loop{}.test", number));
+                builder.block(&format!("loop{}.test", number));
+                builder.branch_on_head(&format!("loop{}", number), "end");
+            }
+            IrOp::LoopEnd => {
+                let Some(Open::Loop(number)) = builder.open.pop() else {
+                    return Err("the loops of the program do not match".into());
+                };
+                builder.emit(&format!("br label %loop{}.test", number));
+                builder.block(&format!("loop{}.end", number));
+            }
+            IrOp::If => {
+                let number = builder.number();
+                builder.open.push(Open::If(number, false));
+                builder.branch_on_head(&format!("if{}", number), "else");
+            }
+            IrOp::Else => {
+                let Some(Open::If(number, false)) = builder.open.pop() else {
+                    return Err(
+                        "the branches of the program do not match".into()
+                    );
+                };
+                builder.open.push(Open::If(number, true));
+                builder.emit(&format!("br label %if{}.end", number));
+                builder.block(&format!("if{}.else", number));
+            }
+            IrOp::EndIf => {
+                let Some(Open::If(number, seen_else)) = builder.open.pop()
+                else {
+                    return Err(
+                        "the branches of the program do not match".into()
+                    );
+                };
+                builder.emit(&format!("br label %if{}.end", number));
+                if !seen_else {
+                    builder.block(&format!("if{}.else", number));
+                    builder.emit(&format!("br label %if{}.end", number));
+                }
+                builder.block(&format!("if{}.end", number));
+            }
+            IrOp::Clear => builder.store(0, "0"),
+            IrOp::AddAt(offset, delta) => {
+                builder.add(*offset, &wrap((*delta).into(), bits).to_string())
+            }
+            IrOp::ClearAt(offset) => builder.store(*offset, "0"),
+            IrOp::AddRange(offset, len, delta) => {
+                let amount = wrap((*delta).into(), bits).to_string();
+                for at in 0..*len as isize {
+                    builder.add(offset + at, &amount);
+                }
+            }
+            IrOp::ClearRange(offset, len) => {
+                let pointer = builder.pointer(*offset);
+                builder.emit(&format!(
+                    "call void @llvm.memset.p0.i64(ptr {}, i8 0, i64 {}, \
+                    i1 false)",
+                    pointer,
+                    *len as i64 * bytes
+                ));
+            }
+            IrOp::CopyLoop(targets) => {
+                let label = format!("copy{}", builder.number());
+                builder.branch_on_head(&label, "end");
+                let count = builder.load(0);
+                for (offset, factor) in targets {
+                    let factor = wrap((*factor).into(), bits).to_string();
+                    let product = builder.multiply(&count, &factor);
+                    builder.add(*offset, &product);
+                }
+                builder.store(0, "0");
+                builder.emit(&format!("br label %{}.end", label));
+                builder.block(&format!("{}.end", label));
+            }
+            IrOp::MulAdd(mul) => {
+                let label = format!("mul{}", builder.number());
+                builder.branch_on_head(&label, "end");
+                let count = builder.load(0);
+                for (offset, by, factor) in &mul.products {
+                    let by = builder.load(*by);
+                    let factor = wrap((*factor).into(), bits).to_string();
+                    let product = builder.multiply(&count, &by);
+                    let product = builder.multiply(&product, &factor);
+                    builder.add(*offset, &product);
+                }
+                for (offset, amount) in &mul.adds {
+                    let amount = wrap((*amount).into(), bits).to_string();
+                    let product = builder.multiply(&count, &amount);
+                    builder.add(*offset, &product);
+                }
+                for (offset, value) in &mul.sets {
+                    builder.store(
+                        *offset,
+                        &wrap((*value).into(), bits).to_string(),
+                    );
+                }
+                builder.store(0, "0");
+                builder.emit(&format!("br label %{}.end", label));
+                builder.block(&format!("{}.end", label));
+            }
+            IrOp::ScanRight(step) | IrOp::ScanLeft(step) => {
+                let step = match node.op() {
+                    IrOp::ScanLeft(_) => -(*step as i64),
+                    _ => *step as i64,
+                };
+                let label = format!("scan{}", builder.number());
+                builder.emit(&format!("br label %{}.test", label));
+                builder.block(&format!("{}.test", label));
+                builder.branch_on_head(&label, "end");
+                let head = builder.value("load i64, ptr %head");
+                let moved =
+                    builder.value(&format!("add i64 {}, {}", head, step));
+                builder.emit(&format!("store i64 {}, ptr %head", moved));
+                builder.emit(&format!("br label %{}.test", label));
+                builder.block(&format!("{}.end", label));
+            }
+            IrOp::OutputBytes(output) => {
+                let data = builder.constant(
+                    &format!("[{} x i8]", output.len()),
+                    &format!("c\"{}\"", llvm_string(output)),
+                );
+                builder.emit(&format!(
+                    "call void @write_bytes(ptr {}, i64 {})",
+                    data,
+                    output.len()
+                ));
+            }
+            IrOp::LoadTape(values) => {
+                let values: Vec<String> = values
+                    .iter()
+                    .map(|value| {
+                        format!("{} {}", cell, wrap((*value).into(), bits))
+                    })
+                    .collect();
+                let data = builder.constant(
+                    &format!("[{} x {}]", values.len(), cell),
+                    &format!("[{}]", values.join(", ")),
+                );
+                let pointer = builder.pointer(0);
+                builder.emit(&format!(
+                    "call void @llvm.memcpy.p0.p0.i64(ptr {}, ptr {}, \
+                    i64 {}, i1 false)",
+                    pointer,
+                    data,
+                    values.len() as i64 * bytes
+                ));
+            }
+            IrOp::Extension(name) => {
+                return Err(format!(
+                    "the extension instruction '{}' cannot be compiled",
+                    name
+                )
+                .into());
+            }
+        }
+    }
+
+    let mut code = String::new();
+    let filename = ir.filename().display().to_string();
+    line(
+        &mut code,
+        0,
+        &format!("; Compiled from {} by bft.", filename),
+    );
+    line(
+        &mut code,
+        0,
+        &format!("source_filename = \"{}\"", llvm_string(filename.as_bytes())),
+    );
+    line(&mut code, 0, "");
+    line(
+        &mut code,
+        0,
+        &format!(
+            "@tape = internal global [{} x {}] zeroinitializer",
+            settings.cells, cell
+        ),
+    );
+    line(
+        &mut code,
+        0,
+        &format!(
+            "@eof_message = private unnamed_addr constant [{} x i8] c\"{}\"",
+            EOF_MESSAGE.len(),
+            llvm_string(EOF_MESSAGE)
+        ),
+    );
+    for global in &builder.globals {
+        line(&mut code, 0, global);
+    }
+    line(&mut code, 0, "");
+    for declaration in [
+        "declare i32 @putchar(i32)",
+        "declare i32 @getchar()",
+        "declare i64 @write(i32, ptr, i64)",
+        "declare void @llvm.memset.p0.i64(ptr, i8, i64, i1)",
+        "declare void @llvm.memcpy.p0.p0.i64(ptr, ptr, i64, i1)",
+    ] {
+        line(&mut code, 0, declaration);
+    }
+    line(&mut code, 0, "");
+    // Writes bytes which were worked out ahead of time.
+    for text in [
+        "define internal void @write_bytes(ptr %bytes, i64 %length) {",
+        "entry:",
+        "  br label %test",
+        "test:",
+        "  %i = phi i64 [ 0, %entry ], [ %next, %body ]",
+        "  %more = icmp ult i64 %i, %length",
+        "  br i1 %more, label %body, label %done",
+        "body:",
+        "  %at = getelementptr inbounds i8, ptr %bytes, i64 %i",
+        "  %byte = load i8, ptr %at",
+        "  %c = zext i8 %byte to i32",
+        "  call i32 @putchar(i32 %c)",
+        "  %next = add i64 %i, 1",
+        "  br label %test",
+        "done:",
+        "  ret void",
+        "}",
+        "",
+        "define i32 @main() {",
+        "entry:",
+        "  %head = alloca i64",
+        "  store i64 0, ptr %head",
+    ] {
+        line(&mut code, 0, text);
+    }
+    code.push_str(&builder.code);
+    line(&mut code, 1, "ret i32 0");
+    line(&mut code, 0, "}");
+    Ok(code)
+}
+
+/// Loads and optimizes the program to be compiled, after checking that the
+/// settings are ones compiled programs support.
+pub(crate) fn compile_ir(
+    filename: &Path,
+    settings: &Settings,
+) -> Result<IrProgram, Box<dyn Error>> {
+    if settings.extensible {
+        return Err("compiled programs cannot grow their tape".into());
+    }
+    if settings.newlines != Newlines::Unchanged {
+        return Err("compiled programs do not translate newlines".into());
+    }
+    if settings.echo {
+        return Err("compiled programs do not echo their input".into());
+    }
+    if settings.io != IoMode::Raw {
+        return Err("compiled programs only write raw output".into());
+    }
+    let program = load_program(filename, settings)?;
+    let optimized = match OutputCache::for_settings(settings) {
+        Some(cache) => cache.optimize(&program, settings)?,
+        None => settings.optimize(&program, true)?,
+    };
+    Ok(match optimized {
+        Some((ir, _)) => ir,
+        None => IrProgram::from_program(&program)?,
+    })
+}
+
+/// Runs the `compile` subcommand.
+pub(crate) fn run_compile(
+    args: &CompileArgs,
+) -> Result<ExitCode, Box<dyn Error>> {
+    let settings = Settings::from_args(&args.run)?;
+    if args.arch.is_some() && args.target != Target::Asm {
+        return Err("--arch only applies to --target asm".into());
+    }
+    let ir = compile_ir(&args.filename, &settings)?;
+    let code = match args.target {
+        Target::C => emit_c(&ir, &settings)?,
+        Target::LlvmIr => emit_llvm_ir(&ir, &settings)?,
+        Target::Asm => {
+            emit_asm(&ir, &settings, args.arch.unwrap_or_else(Arch::host))?
+        }
+    };
+    match &args.output {
+        Some(path) => fs::write(path, code)?,
+        None => print!("{}", code),
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{emit_c, emit_llvm_ir, llvm_string, string_literals, wrap};
+    use crate::config::{settings, Settings};
+    use bft_interp::ir::IrProgram;
+    use bft_types::BfProgram;
+
+    fn optimize(source: &str, flags: &[&str]) -> (IrProgram, Settings) {
+        let settings = settings(flags);
+        let program = BfProgram::new(source.to_string(), "c.bf").unwrap();
+        let (ir, _) = settings
+            .optimize(&program, true)
+            .unwrap()
+            .expect("tests compile optimized programs");
+        (ir, settings)
+    }
+
+    fn compile(source: &str, flags: &[&str]) -> String {
+        let (ir, settings) = optimize(source, flags);
+        emit_c(&ir, &settings).unwrap()
+    }
+
+    fn compile_llvm_ir(source: &str, flags: &[&str]) -> String {
+        let (ir, settings) = optimize(source, flags);
+        emit_llvm_ir(&ir, &settings).unwrap()
+    }
+
+    #[test]
+    fn test_string_literals() {
+        assert_eq!(string_literals(b"hi\n"), ["\"hi\\n\""]);
+        assert_eq!(
+            string_literals(b"a\nb\"\x07"),
+            ["\"a\\n\"", "\"b\\\"\\007\""]
+        );
+    }
+
+    #[test]
+    fn test_compile_loops() {
+        let code = compile(",[->+<]>.", &["-O2", "--cell-width", "16"]);
+        assert!(code.contains("typedef uint16_t cell;"));
+        assert!(code.contains("static cell tape[30000];"));
+        assert!(code.contains("tape[head + 1] += (uint32_t)tape[head] * 1u;"));
+        assert!(code.contains(
+            "fputs(\"bft: the program read past the end of its input\\n\", \
+            stderr);"
+        ));
+        assert!(code.contains("head += 1u;"));
+    }
+
+    #[test]
+    fn test_compile_evaluated_prefix() {
+        let code = compile("++[>+++<-]>.,.", &["-O3", "-c", "10"]);
+        assert!(code.contains("static cell tape[10] = {\n    0u, 6u,\n};"));
+        assert!(code
+            .contains("fwrite(\n        \"\\006\"\n        , 1, 1, stdout);"));
+        assert!(!code.contains("while"));
+    }
+
+    #[test]
+    fn test_wrap() {
+        assert_eq!(wrap(255, 8), -1);
+        assert_eq!(wrap(-1, 8), -1);
+        assert_eq!(wrap(256, 8), 0);
+        assert_eq!(wrap(65535, 16), -1);
+        assert_eq!(wrap(70000, 32), 70000);
+    }
+
+    #[test]
+    fn test_llvm_string() {
+        assert_eq!(llvm_string(b"hi\n"), "hi\\0A");
+        assert_eq!(llvm_string(b"a\"b\\"), "a\\22b\\5C");
+    }
+
+    #[test]
+    fn test_compile_llvm_ir() {
+        let code =
+            compile_llvm_ir(",[->+<]>[-].", &["-O2", "--cell-width", "16"]);
+        assert!(code
+            .contains("@tape = internal global [30000 x i16] zeroinitializer"));
+        assert!(code.contains("call i32 @getchar()"));
+        assert!(
+            code.contains("call i64 @write(i32 2, ptr @eof_message, i64 48)")
+        );
+        assert!(code.contains("mul i16"));
+        assert!(code.contains("declare void @llvm.memset.p0.i64"));
+        assert!(code.contains("ret i32 0"));
+    }
+
+    #[test]
+    fn test_compile_llvm_ir_loops() {
+        let code = compile_llvm_ir("+[>,.<-]", &["-O1", "--eof", "zero"]);
+        assert!(code.contains("loop1.test:"));
+        assert!(code.contains("loop1.body:"));
+        assert!(code.contains("loop1.end:"));
+        assert!(code.contains("add i8"));
+        assert!(!code.contains("ptr @eof_message, i64"));
+    }
+}
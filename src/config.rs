@@ -0,0 +1,964 @@
+//! Loading default settings from `bft.toml` config files, and merging them with
+//! the flags given on the command line.
+//!
+//! Settings are taken from, in order of precedence:
+//! 1. Flags given on the command line.
+//! 2. The project config, the first `bft.toml` found in the current directory
+//!    or any of its parents, or the file given with `--config`.
+//! 3. The user config, `$XDG_CONFIG_HOME/bft/bft.toml` (or
+//!    `~/.config/bft/bft.toml`).
+//! 4. The built-in defaults.
+
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use bft_interp::compiled::{compile_with, CompiledProgram};
+use bft_interp::eof::EofBehavior;
+use bft_interp::io::{InputSource, NewlinePolicy, Newlines, OutputSink};
+use bft_interp::ir::IrProgram;
+use bft_interp::optimizer::{IrPass, PassStats, Pipeline};
+use bft_interp::partial::PartialEvaluation;
+use bft_interp::preset::Profile;
+use bft_interp::sandbox::{Sandbox, Syscalls};
+use bft_interp::syscall::{self, MonotonicClock, VirtualClock};
+use bft_interp::{CellKind, VirtualMachine};
+use bft_types::options::DEFAULT_MAX_NESTING;
+use bft_types::profile::DEFAULT_MIN_COMMAND_RATIO;
+use bft_types::vm_error::VirtualMachineError;
+use bft_types::BfProgram;
+use serde::Deserialize;
+
+use crate::cli::{IoMode, RunArgs};
+
+/// The name of the config file.
+const CONFIG_FILENAME: &str = "bft.toml";
+
+/// The number of cells in the tape when none is configured.
+const DEFAULT_CELLS: usize = 30_000;
+
+/// The highest optimization level.
+const MAX_OPT_LEVEL: u8 = 3;
+
+/// The most operations run ahead of time by partial evaluation at `-O3`.
+const PARTIAL_EVALUATION_BUDGET: u64 = 10_000_000;
+
+/// The most operations run ahead of time by partial evaluation at `-O3`, for
+/// programs which never read input and so can be run to the end.
+const INPUT_FREE_BUDGET: u64 = 100_000_000;
+
+/// The most bytes the tape and output may take up during partial evaluation.
+const PARTIAL_EVALUATION_MEMORY: usize = 64 * 1024 * 1024;
+
+/// The width of each cell in the tape of the Virtual Machine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) enum CellWidth {
+    /// 8 bit cells, as in classic Brainfuck.
+    #[default]
+    U8,
+    /// 16 bit cells.
+    U16,
+    /// 32 bit cells.
+    U32,
+}
+
+impl CellWidth {
+    /// The number of bits in each cell.
+    pub(crate) fn bits(self) -> u32 {
+        match self {
+            CellWidth::U8 => 8,
+            CellWidth::U16 => 16,
+            CellWidth::U32 => 32,
+        }
+    }
+}
+
+impl TryFrom<u32> for CellWidth {
+    type Error = String;
+
+    fn try_from(bits: u32) -> Result<Self, Self::Error> {
+        match bits {
+            8 => Ok(CellWidth::U8),
+            16 => Ok(CellWidth::U16),
+            32 => Ok(CellWidth::U32),
+            _ => Err(format!(
+                "unsupported cell width {}, expected 8, 16 or 32",
+                bits
+            )),
+        }
+    }
+}
+
+impl FromStr for CellWidth {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse::<u32>()
+            .map_err(|_| format!("invalid cell width '{}'", s))
+            .and_then(CellWidth::try_from)
+    }
+}
+
+impl fmt::Display for CellWidth {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CellWidth::U8 => write!(f, "8"),
+            CellWidth::U16 => write!(f, "16"),
+            CellWidth::U32 => write!(f, "32"),
+        }
+    }
+}
+
+/// How a program reads the clock, when it may.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct ClockSettings {
+    /// The milliseconds a virtual clock moves on by each time it is read, or
+    /// None to read the real clock.
+    pub(crate) tick: Option<u64>,
+    /// The number of cells the time is written across.
+    pub(crate) cells: u8,
+}
+
+/// The contents of a config file, where every setting is optional.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub(crate) struct Config {
+    preset: Option<String>,
+    cells: Option<usize>,
+    cell_width: Option<u32>,
+    extensible: Option<bool>,
+    eof: Option<String>,
+    newlines: Option<String>,
+    trailing_newline: Option<String>,
+    lazy_brackets: Option<bool>,
+    max_nesting: Option<usize>,
+    max_program_size: Option<u64>,
+    min_command_ratio: Option<f64>,
+    strict_source: Option<bool>,
+    strict: Option<bool>,
+    max_steps: Option<u64>,
+    max_output_bytes: Option<u64>,
+    max_input_bytes: Option<u64>,
+    passes: Option<String>,
+    opt_level: Option<u8>,
+}
+
+impl Config {
+    /// Reads a config from the given file.
+    pub(crate) fn from_file(path: &Path) -> Result<Config, Box<dyn Error>> {
+        let contents = fs::read_to_string(path)?;
+        toml::from_str(&contents)
+            .map_err(|err| format!("in {}: {}", path.display(), err).into())
+    }
+
+    /// Combines two configs, taking each setting from `self` where it is set,
+    /// and from `fallback` otherwise.
+    pub(crate) fn or(self, fallback: Config) -> Config {
+        Config {
+            preset: self.preset.or(fallback.preset),
+            cells: self.cells.or(fallback.cells),
+            cell_width: self.cell_width.or(fallback.cell_width),
+            extensible: self.extensible.or(fallback.extensible),
+            eof: self.eof.or(fallback.eof),
+            newlines: self.newlines.or(fallback.newlines),
+            trailing_newline: self
+                .trailing_newline
+                .or(fallback.trailing_newline),
+            lazy_brackets: self.lazy_brackets.or(fallback.lazy_brackets),
+            max_nesting: self.max_nesting.or(fallback.max_nesting),
+            max_program_size: self
+                .max_program_size
+                .or(fallback.max_program_size),
+            min_command_ratio: self
+                .min_command_ratio
+                .or(fallback.min_command_ratio),
+            strict_source: self.strict_source.or(fallback.strict_source),
+            strict: self.strict.or(fallback.strict),
+            max_steps: self.max_steps.or(fallback.max_steps),
+            max_output_bytes: self
+                .max_output_bytes
+                .or(fallback.max_output_bytes),
+            max_input_bytes: self.max_input_bytes.or(fallback.max_input_bytes),
+            passes: self.passes.or(fallback.passes),
+            opt_level: self.opt_level.or(fallback.opt_level),
+        }
+    }
+
+    /// Finds the project config, by looking for a config file in `dir` and
+    /// then each of its parents.
+    fn find_project_config(dir: &Path) -> Option<PathBuf> {
+        dir.ancestors()
+            .map(|ancestor| ancestor.join(CONFIG_FILENAME))
+            .find(|candidate| candidate.is_file())
+    }
+
+    /// The location of the user config, if there is a home for it.
+    fn user_config_path() -> Option<PathBuf> {
+        env::var_os("XDG_CONFIG_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| {
+                env::var_os("HOME").map(|home| Path::new(&home).join(".config"))
+            })
+            .map(|dir| dir.join("bft").join(CONFIG_FILENAME))
+    }
+
+    /// Loads and merges the user config and project config, or the given
+    /// config file in place of the project config.
+    pub(crate) fn load(
+        explicit: Option<&Path>,
+    ) -> Result<Config, Box<dyn Error>> {
+        let project = match explicit {
+            Some(path) => Some(path.to_path_buf()),
+            None => Config::find_project_config(&env::current_dir()?),
+        };
+        let user = Config::user_config_path().filter(|path| path.is_file());
+        let mut config = Config::default();
+        for path in [project, user].into_iter().flatten() {
+            config = config.or(Config::from_file(&path)?);
+        }
+        Ok(config)
+    }
+}
+
+/// The settings used to interpret a program, once the command line flags and
+/// config files have been merged.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Settings {
+    pub(crate) cells: usize,
+    pub(crate) cell_width: CellWidth,
+    pub(crate) extensible: bool,
+    pub(crate) eof: EofBehavior,
+    /// How the newlines of the input are translated. The output is translated
+    /// the opposite way.
+    pub(crate) newlines: Newlines,
+    /// Whether the input is echoed into the output.
+    pub(crate) echo: bool,
+    /// How long `,` waits for input from stdin before failing, if limited.
+    pub(crate) input_timeout: Option<Duration>,
+    /// How the output is shown.
+    pub(crate) io: IoMode,
+    /// Whether the output is ended with a newline.
+    pub(crate) trailing_newline: NewlinePolicy,
+    pub(crate) lazy_brackets: bool,
+    /// The deepest that loops may be nested.
+    pub(crate) max_nesting: usize,
+    /// The largest that the program file may be, in bytes, if limited.
+    pub(crate) max_program_size: Option<u64>,
+    /// The fraction of the program which should be commands, below which it
+    /// is reported as probably not being Brainfuck.
+    pub(crate) min_command_ratio: f64,
+    /// Whether a program which looks like it is not Brainfuck is refused,
+    /// rather than only warned about.
+    pub(crate) strict_source: bool,
+    /// Whether comments must be delimited.
+    pub(crate) strict: bool,
+    pub(crate) max_steps: Option<u64>,
+    /// The most bytes the program may write, if limited.
+    pub(crate) max_output_bytes: Option<u64>,
+    /// The most bytes the program may read, if limited.
+    pub(crate) max_input_bytes: Option<u64>,
+    /// How the program reads the clock with `%`, or None if it may not, in
+    /// which case `%` is a comment.
+    pub(crate) clock: Option<ClockSettings>,
+    /// The optimization passes to run, as given to `--passes`, or None to run
+    /// the program without lowering it.
+    pub(crate) passes: Option<String>,
+    /// The optimization level, from 0 to 3, which picks the passes to run
+    /// when none are given, and whether to run partial evaluation.
+    pub(crate) opt_level: u8,
+    /// The codec of a plugin to encode output and decode input with, if any.
+    #[cfg(feature = "plugins")]
+    pub(crate) codec: Option<String>,
+}
+
+/// Creates a pipeline from a list of passes, as given to `--passes`, which
+/// with the `plugins` feature may name those of the plugins loaded.
+pub(crate) fn pipeline(spec: &str) -> Result<Pipeline, String> {
+    #[cfg(feature = "plugins")]
+    {
+        crate::plugin::pipeline(spec)
+    }
+    #[cfg(not(feature = "plugins"))]
+    {
+        Pipeline::from_spec(spec)
+    }
+}
+
+/// Resolves a boolean setting from its flag, the `--no-` flag which turns it
+/// off, and the value of the configuration file or preset otherwise.
+fn switch(on: bool, off: bool, fallback: bool) -> bool {
+    match (on, off) {
+        (true, _) => true,
+        (_, true) => false,
+        (false, false) => fallback,
+    }
+}
+
+impl Settings {
+    /// Merges the flags from the command line with the given config, where
+    /// flags that were given take precedence.
+    pub(crate) fn resolve(
+        args: &RunArgs,
+        config: Config,
+    ) -> Result<Settings, Box<dyn Error>> {
+        // A preset takes the place of the settings in the config files which
+        // it covers, while flags still take its place.
+        let preset: Option<Profile> = match (args.preset, config.preset) {
+            (Some(preset), _) => Some(preset),
+            (None, Some(name)) => Some(name.parse()?),
+            (None, None) => None,
+        };
+        let cell_width = match (args.cell_width, preset, config.cell_width) {
+            (Some(width), _, _) => width,
+            (None, Some(preset), _) => CellWidth::try_from(preset.cell_bits())?,
+            (None, None, Some(bits)) => CellWidth::try_from(bits)?,
+            (None, None, None) => CellWidth::default(),
+        };
+        let eof = match (args.eof, preset, config.eof) {
+            (Some(eof), _, _) => eof,
+            (None, Some(preset), _) => preset.eof_behavior(),
+            (None, None, Some(name)) => name.parse()?,
+            (None, None, None) => EofBehavior::default(),
+        };
+        let cells = match preset {
+            Some(preset) => preset.tape_length(),
+            None => config.cells.unwrap_or(DEFAULT_CELLS),
+        };
+        let extensible = match preset {
+            Some(preset) => preset.growable(),
+            None => config.extensible.unwrap_or(false),
+        };
+        let clock = args.clock
+            || args.virtual_clock.is_some()
+            || preset.is_some_and(Profile::syscalls);
+        let newlines = match (args.crlf_to_lf, args.lf_to_crlf, config.newlines)
+        {
+            (true, _, _) => Newlines::CrlfToLf,
+            (_, true, _) => Newlines::LfToCrlf,
+            (false, false, Some(name)) => name.parse()?,
+            (false, false, None) => Newlines::default(),
+        };
+        let trailing_newline =
+            match (args.trailing_newline, config.trailing_newline) {
+                (Some(policy), _) => policy,
+                (None, Some(name)) => name.parse()?,
+                (None, None) => NewlinePolicy::default(),
+            };
+        // Plugins come first, as passes may name theirs.
+        #[cfg(feature = "plugins")]
+        crate::plugin::load(&args.plugins)?;
+        #[cfg(feature = "plugins")]
+        if let Some(codec) = &args.codec {
+            if !crate::plugin::has_codec(codec) {
+                return Err(format!("no plugin has a codec '{}'", codec).into());
+            }
+        }
+        let passes = args.passes.clone().or(config.passes);
+        if let Some(spec) = &passes {
+            pipeline(spec)?;
+        }
+        let opt_level = args.opt_level.or(config.opt_level).unwrap_or(0);
+        if opt_level > MAX_OPT_LEVEL {
+            return Err(format!(
+                "unsupported optimization level {}, expected 0 to {}",
+                opt_level, MAX_OPT_LEVEL
+            )
+            .into());
+        }
+        let min_command_ratio = args
+            .min_command_ratio
+            .or(config.min_command_ratio)
+            .unwrap_or(DEFAULT_MIN_COMMAND_RATIO);
+        if !(0.0..=1.0).contains(&min_command_ratio) {
+            return Err(format!(
+                "unsupported command ratio {}, expected 0 to 1",
+                min_command_ratio
+            )
+            .into());
+        }
+        Ok(Settings {
+            cells: args.cells.unwrap_or(cells),
+            cell_width,
+            extensible: switch(args.extensible, args.no_extensible, extensible),
+            eof,
+            newlines,
+            echo: args.echo,
+            input_timeout: args.input_timeout.map(Duration::from_millis),
+            io: args.io,
+            trailing_newline,
+            lazy_brackets: switch(
+                args.lazy_brackets,
+                args.no_lazy_brackets,
+                config.lazy_brackets.unwrap_or(false),
+            ),
+            max_nesting: args
+                .max_nesting
+                .or(config.max_nesting)
+                .unwrap_or(DEFAULT_MAX_NESTING),
+            max_program_size: args.max_program_size.or(config.max_program_size),
+            min_command_ratio,
+            strict_source: switch(
+                args.strict_source,
+                args.no_strict_source,
+                config.strict_source.unwrap_or(false),
+            ),
+            strict: switch(
+                args.strict,
+                args.no_strict,
+                config.strict.unwrap_or(false),
+            ),
+            max_steps: args.max_steps.or(config.max_steps),
+            max_output_bytes: args.max_output_bytes.or(config.max_output_bytes),
+            max_input_bytes: args.max_input_bytes.or(config.max_input_bytes),
+            clock: clock.then(|| ClockSettings {
+                tick: args.virtual_clock,
+                cells: args.clock_cells.unwrap_or(1),
+            }),
+            passes,
+            opt_level,
+            #[cfg(feature = "plugins")]
+            codec: args.codec.clone(),
+        })
+    }
+
+    /// Loads the config files and merges them with the flags given on the
+    /// command line.
+    pub(crate) fn from_args(
+        args: &RunArgs,
+    ) -> Result<Settings, Box<dyn Error>> {
+        Settings::resolve(args, Config::load(args.config.as_deref())?)
+    }
+
+    /// Lowers the program and runs the selected optimization passes over it,
+    /// along with what each pass did, if any passes were selected. The passes
+    /// given with `--passes` are used if there are any, and otherwise those
+    /// for the optimization level: none at 0, `rle` at 1, and all of the
+    /// built-in passes at 2 and above.
+    ///
+    /// At level 3, partial evaluation runs after the other passes, but only
+    /// if `fresh_tape` is set, since it assumes that the program starts on a
+    /// fresh tape.
+    pub(crate) fn optimize(
+        &self,
+        program: &BfProgram,
+        fresh_tape: bool,
+    ) -> Result<Option<(IrProgram, Vec<PassStats>)>, VirtualMachineError> {
+        let Some(mut pipeline) = self.passes(fresh_tape) else {
+            return Ok(None);
+        };
+        let mut ir = IrProgram::from_program(program)?;
+        let stats = pipeline.run(&mut ir);
+        Ok(Some((ir, stats)))
+    }
+
+    /// The optimization passes these settings select, as `optimize` runs
+    /// them, if any are selected.
+    fn passes(&self, fresh_tape: bool) -> Option<Pipeline> {
+        let mut pipeline = match (&self.passes, self.opt_level) {
+            (Some(spec), _) => pipeline(spec)
+                .expect("passes are checked when the settings are resolved"),
+            (None, 0) => return None,
+            (None, 1) => {
+                Pipeline::from_spec("rle").expect("rle is a built-in pass")
+            }
+            (None, _) => Pipeline::builtin(),
+        };
+        if self.opt_level >= MAX_OPT_LEVEL && fresh_tape {
+            pipeline.push(self.partial_evaluation());
+        }
+        Some(pipeline)
+    }
+
+    /// Compiles the program with the passes these settings select, to be run
+    /// many times on a fresh tape with `spawn_vm`.
+    pub(crate) fn compile(
+        &self,
+        program: &BfProgram,
+    ) -> Result<CompiledProgram, VirtualMachineError> {
+        let mut pipeline = self.passes(true).unwrap_or_default();
+        Ok(compile_with(program, &mut pipeline)?.with_tape_length(self.cells))
+    }
+
+    /// Creates the partial evaluation pass for the width of the cells.
+    fn partial_evaluation(&self) -> Box<dyn IrPass> {
+        match self.cell_width {
+            CellWidth::U8 => self.partial_evaluation_as::<u8>(),
+            CellWidth::U16 => self.partial_evaluation_as::<u16>(),
+            CellWidth::U32 => self.partial_evaluation_as::<u32>(),
+        }
+    }
+
+    /// Creates the partial evaluation pass for cells of type `T`. The steps
+    /// it runs count towards the step limit, so it never runs more of them
+    /// than the limit allows, and it leaves any output past the output limit
+    /// to fail when the program is run.
+    fn partial_evaluation_as<T>(&self) -> Box<dyn IrPass>
+    where
+        T: CellKind + Default + Clone + Copy + PartialEq + 'static,
+    {
+        let limit = self.max_steps.unwrap_or(u64::MAX);
+        let pass = PartialEvaluation::<T>::new(
+            PARTIAL_EVALUATION_BUDGET.min(limit),
+            self.cells,
+            self.extensible,
+        );
+        Box::new(
+            pass.with_input_free_budget(INPUT_FREE_BUDGET.min(limit))
+                .with_memory_limit(PARTIAL_EVALUATION_MEMORY)
+                .with_output_limit(self.max_output_bytes.unwrap_or(u64::MAX)),
+        )
+    }
+
+    /// Creates a Virtual Machine for the program using these settings.
+    pub(crate) fn virtual_machine<'a, T>(
+        &self,
+        program: &'a BfProgram,
+    ) -> VirtualMachine<'a, T>
+    where
+        T: CellKind + Default + Clone + Copy + PartialEq + 'a,
+    {
+        self.configure(VirtualMachine::<T>::new(
+            program,
+            self.cells,
+            self.extensible,
+        ))
+    }
+
+    /// Creates a Virtual Machine for the program using these settings, which
+    /// starts on the given tape rather than a fresh one.
+    pub(crate) fn virtual_machine_on_tape<'a, T>(
+        &self,
+        program: &'a BfProgram,
+        tape: Vec<T>,
+        tape_head: usize,
+    ) -> VirtualMachine<'a, T>
+    where
+        T: CellKind + Default + Clone + Copy + PartialEq + 'a,
+    {
+        self.configure(VirtualMachine::<T>::from_tape(
+            program,
+            tape,
+            tape_head,
+            self.extensible,
+        ))
+    }
+
+    /// The sandbox programs run in with these settings, holding their limits.
+    fn sandbox(&self) -> Sandbox {
+        let mut sandbox = Sandbox::builder().with_tape_growth(self.extensible);
+        if self.clock.is_some() {
+            sandbox = sandbox.with_syscalls(Syscalls::only([syscall::CLOCK]));
+        }
+        if let Some(limit) = self.max_steps {
+            sandbox = sandbox.with_step_limit(limit);
+        }
+        if let Some(limit) = self.max_output_bytes {
+            sandbox = sandbox.with_output_limit(limit);
+        }
+        if let Some(limit) = self.max_input_bytes {
+            sandbox = sandbox.with_input_limit(limit);
+        }
+        sandbox
+            .build()
+            .expect("settings have no limits which clash")
+    }
+
+    /// Creates a Virtual Machine for a run of the compiled program using these
+    /// settings, reading from and writing to the given source and sink.
+    pub(crate) fn spawn_vm<'a, T>(
+        &self,
+        compiled: &'a CompiledProgram,
+        input: impl InputSource + 'a,
+        output: impl OutputSink + 'a,
+    ) -> VirtualMachine<'a, T>
+    where
+        T: CellKind + Default + Clone + Copy + PartialEq + 'a,
+    {
+        let mut vm = compiled
+            .spawn_vm(self.sandbox(), input, output)
+            .with_eof_behavior(self.eof);
+        self.register_clock(&mut vm);
+        vm
+    }
+
+    /// Applies the settings which do not affect the tape.
+    fn configure<'a, T>(
+        &self,
+        vm: VirtualMachine<'a, T>,
+    ) -> VirtualMachine<'a, T>
+    where
+        T: CellKind + Default + Clone + Copy + PartialEq + 'a,
+    {
+        let mut vm =
+            vm.with_eof_behavior(self.eof).with_sandbox(self.sandbox());
+        self.register_clock(&mut vm);
+        vm
+    }
+
+    /// Registers the clock as a host function, if these settings give one.
+    fn register_clock<'a, T>(&self, vm: &mut VirtualMachine<'a, T>)
+    where
+        T: CellKind + Default + Clone + Copy + PartialEq + 'a,
+    {
+        if let Some(clock) = self.clock {
+            let cells = clock.cells.into();
+            match clock.tick {
+                Some(tick) => vm.register_syscall(
+                    syscall::CLOCK,
+                    syscall::clock(VirtualClock::new(tick), cells),
+                ),
+                None => vm.register_syscall(
+                    syscall::CLOCK,
+                    syscall::clock(MonotonicClock::new(), cells),
+                ),
+            }
+        }
+    }
+}
+
+/// Parses the flags of `bft run` for a program, for tests.
+#[cfg(test)]
+pub(crate) fn run_args(flags: &[&str]) -> RunArgs {
+    use clap::Parser;
+
+    let mut argv = vec!["bft"];
+    argv.extend_from_slice(flags);
+    argv.push("program.bf");
+    crate::cli::Args::parse_from(argv).run
+}
+
+/// Resolves the settings given by the flags of `bft run`, without a config
+/// file, for tests.
+#[cfg(test)]
+pub(crate) fn settings(flags: &[&str]) -> Settings {
+    Settings::resolve(&run_args(flags), Config::default()).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{run_args, CellWidth, ClockSettings, Config, Settings};
+    use bft_interp::eof::EofBehavior;
+    use bft_interp::io::{NewlinePolicy, Newlines};
+    use bft_types::options::DEFAULT_MAX_NESTING;
+    use bft_types::profile::DEFAULT_MIN_COMMAND_RATIO;
+    use bft_types::vm_error::VirtualMachineError;
+    use bft_types::BfProgram;
+    use std::fs;
+    use std::path::PathBuf;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "bft-config-{}-{}",
+            name,
+            std::process::id()
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_parse_config() {
+        let config: Config = toml::from_str(
+            "cells = 10\ncell-width = 16\neof = \"zero\"\nextensible = true",
+        )
+        .unwrap();
+        let settings = Settings::resolve(&run_args(&[]), config).unwrap();
+        assert_eq!(settings.cells, 10);
+        assert_eq!(settings.cell_width, CellWidth::U16);
+        assert_eq!(settings.eof, EofBehavior::Zero);
+        assert!(settings.extensible);
+        assert!(!settings.lazy_brackets);
+        assert_eq!(settings.max_nesting, DEFAULT_MAX_NESTING);
+
+        // A `--no-` flag turns off what the config turns on, and the last of
+        // a flag and its `--no-` flag wins.
+        let config: Config = toml::from_str("lazy-brackets = true").unwrap();
+        let lazy = |flags: &[&str]| {
+            Settings::resolve(&run_args(flags), config.clone())
+                .unwrap()
+                .lazy_brackets
+        };
+        assert!(lazy(&[]));
+        assert!(!lazy(&["--no-lazy-brackets"]));
+        assert!(lazy(&["--no-lazy-brackets", "--lazy-brackets"]));
+        assert!(!lazy(&["--lazy-brackets", "--no-lazy-brackets"]));
+
+        let config: Config = toml::from_str("max-nesting = 64").unwrap();
+        let settings = Settings::resolve(
+            &run_args(&["--max-nesting", "8"]),
+            config.clone(),
+        )
+        .unwrap();
+        assert_eq!(settings.max_nesting, 8);
+        let settings = Settings::resolve(&run_args(&[]), config).unwrap();
+        assert_eq!(settings.max_nesting, 64);
+        assert_eq!(settings.max_program_size, None);
+
+        let config: Config =
+            toml::from_str("max-program-size = 1048576").unwrap();
+        let settings = Settings::resolve(
+            &run_args(&["--max-program-size", "4096"]),
+            config.clone(),
+        )
+        .unwrap();
+        assert_eq!(settings.max_program_size, Some(4096));
+        let settings = Settings::resolve(&run_args(&[]), config).unwrap();
+        assert_eq!(settings.max_program_size, Some(1_048_576));
+    }
+
+    #[test]
+    fn test_strict_source() {
+        let settings =
+            Settings::resolve(&run_args(&[]), Config::default()).unwrap();
+        assert_eq!(settings.min_command_ratio, DEFAULT_MIN_COMMAND_RATIO);
+        assert!(!settings.strict_source);
+
+        let config: Config =
+            toml::from_str("min-command-ratio = 0.5\nstrict-source = true")
+                .unwrap();
+        let settings =
+            Settings::resolve(&run_args(&[]), config.clone()).unwrap();
+        assert_eq!(settings.min_command_ratio, 0.5);
+        assert!(settings.strict_source);
+        assert!(!settings.strict);
+        let settings = Settings::resolve(
+            &run_args(&["--min-command-ratio", "0.25"]),
+            config,
+        )
+        .unwrap();
+        assert_eq!(settings.min_command_ratio, 0.25);
+        let config: Config = toml::from_str("strict = true").unwrap();
+        assert!(
+            Settings::resolve(&run_args(&[]), config.clone())
+                .unwrap()
+                .strict
+        );
+        assert!(
+            !Settings::resolve(&run_args(&["--no-strict"]), config)
+                .unwrap()
+                .strict
+        );
+        assert!(Settings::resolve(
+            &run_args(&["--min-command-ratio", "2"]),
+            Config::default()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_unknown_keys_rejected() {
+        assert!(toml::from_str::<Config>("cell = 10").is_err());
+    }
+
+    #[test]
+    fn test_flags_win() {
+        let config: Config =
+            toml::from_str("cells = 10\ncell-width = 16\neof = \"zero\"")
+                .unwrap();
+        let args = run_args(&["-c", "5", "--cell-width", "32", "--eof", "max"]);
+        let settings = Settings::resolve(&args, config).unwrap();
+        assert_eq!(settings.cells, 5);
+        assert_eq!(settings.cell_width, CellWidth::U32);
+        assert_eq!(settings.eof, EofBehavior::MaxValue);
+    }
+
+    #[test]
+    fn test_newlines() {
+        let config: Config =
+            toml::from_str("newlines = \"lf-to-crlf\"").unwrap();
+        let settings =
+            Settings::resolve(&run_args(&[]), config.clone()).unwrap();
+        assert_eq!(settings.newlines, Newlines::LfToCrlf);
+
+        let args = run_args(&["--crlf-to-lf"]);
+        let settings = Settings::resolve(&args, config).unwrap();
+        assert_eq!(settings.newlines, Newlines::CrlfToLf);
+
+        let settings =
+            Settings::resolve(&run_args(&[]), Config::default()).unwrap();
+        assert_eq!(settings.newlines, Newlines::Unchanged);
+        assert_eq!(settings.trailing_newline, NewlinePolicy::IfMissing);
+
+        let config: Config =
+            toml::from_str("trailing-newline = \"never\"").unwrap();
+        let settings =
+            Settings::resolve(&run_args(&[]), config.clone()).unwrap();
+        assert_eq!(settings.trailing_newline, NewlinePolicy::Never);
+        let args = run_args(&["--trailing-newline", "always"]);
+        let settings = Settings::resolve(&args, config).unwrap();
+        assert_eq!(settings.trailing_newline, NewlinePolicy::Always);
+    }
+
+    #[test]
+    fn test_passes() {
+        let config: Config = toml::from_str("passes = \"rle\"").unwrap();
+        let settings =
+            Settings::resolve(&run_args(&[]), config.clone()).unwrap();
+        assert_eq!(settings.passes.as_deref(), Some("rle"));
+
+        let args = run_args(&["--passes", "-copyloop"]);
+        let settings = Settings::resolve(&args, config).unwrap();
+        assert_eq!(settings.passes.as_deref(), Some("-copyloop"));
+
+        let args = run_args(&["--passes", "unroll"]);
+        assert!(Settings::resolve(&args, Config::default()).is_err());
+    }
+
+    #[test]
+    fn test_preset() {
+        let args = run_args(&["--preset", "wrap-tape-16bit"]);
+        let settings = Settings::resolve(&args, Config::default()).unwrap();
+        assert_eq!(settings.cell_width, CellWidth::U16);
+        assert_eq!(settings.eof, EofBehavior::Zero);
+        assert!(!settings.extensible);
+        assert_eq!(settings.clock, None);
+
+        // Flags win over the preset, which wins over the config.
+        let config: Config =
+            toml::from_str("extensible = true\ncells = 10").unwrap();
+        let args = run_args(&["--preset", "classic", "--eof", "zero"]);
+        let settings = Settings::resolve(&args, config.clone()).unwrap();
+        assert_eq!(settings.eof, EofBehavior::Zero);
+        assert!(!settings.extensible);
+        assert_eq!(settings.cells, 30_000);
+
+        let config: Config = toml::from_str("preset = \"extended\"").unwrap();
+        let settings = Settings::resolve(&run_args(&[]), config).unwrap();
+        assert!(settings.extensible);
+        assert!(settings.clock.is_some());
+
+        let args = run_args(&["--no-extensible"]);
+        let config: Config = toml::from_str("preset = \"extended\"").unwrap();
+        let settings = Settings::resolve(&args, config).unwrap();
+        assert!(!settings.extensible);
+
+        let config: Config = toml::from_str("preset = \"bff\"").unwrap();
+        assert!(Settings::resolve(&run_args(&[]), config).is_err());
+    }
+
+    #[test]
+    fn test_clock() {
+        let clock = |flags: &[&str]| {
+            Settings::resolve(&run_args(flags), Config::default())
+                .unwrap()
+                .clock
+        };
+        assert_eq!(clock(&[]), None);
+        assert_eq!(
+            clock(&["--clock"]),
+            Some(ClockSettings {
+                tick: None,
+                cells: 1
+            })
+        );
+        assert_eq!(
+            clock(&["--virtual-clock", "5", "--clock-cells", "2"]),
+            Some(ClockSettings {
+                tick: Some(5),
+                cells: 2
+            })
+        );
+    }
+
+    #[test]
+    fn test_opt_level() {
+        let program = BfProgram::new("++[>+<-]>.".to_string(), "o.bf").unwrap();
+        let optimized = |flags: &[&str], fresh_tape| {
+            let settings =
+                Settings::resolve(&run_args(flags), Config::default()).unwrap();
+            settings
+                .optimize(&program, fresh_tape)
+                .unwrap()
+                .map(|(_, stats)| stats.len())
+        };
+        assert_eq!(optimized(&[], true), None);
+        assert_eq!(optimized(&["-O1"], true), Some(1));
+        assert_eq!(optimized(&["-O", "2"], true), Some(10));
+        assert_eq!(optimized(&["-O3"], true), Some(11));
+        assert_eq!(optimized(&["-O3"], false), Some(10));
+        assert_eq!(optimized(&["-O3", "--passes", "none"], true), Some(1));
+
+        let config: Config = toml::from_str("opt-level = 4").unwrap();
+        assert!(Settings::resolve(&run_args(&[]), config).is_err());
+    }
+
+    #[test]
+    fn test_partial_evaluation_within_step_limit() {
+        let source = include_str!("../bf-programs/hello-world.bf");
+        let program = BfProgram::new(source.to_string(), "hw.bf").unwrap();
+        let run = |flags: &[&str]| {
+            let settings =
+                Settings::resolve(&run_args(flags), Config::default()).unwrap();
+            let (ir, _) = settings.optimize(&program, true).unwrap().unwrap();
+            let mut vm = settings.virtual_machine::<u8>(&program);
+            let mut output = Vec::new();
+            let result =
+                vm.interpret_ir(&ir, &mut std::io::empty(), &mut output);
+            (result, output)
+        };
+        let (result, output) = run(&["-O3"]);
+        assert!(result.is_ok());
+        assert_eq!(output, b"hello world");
+        let (result, _) = run(&["-O3", "--max-steps", "50"]);
+        assert!(matches!(
+            result,
+            Err(VirtualMachineError::StepLimitExceeded { limit: 50, .. })
+        ));
+    }
+
+    #[test]
+    fn test_defaults_without_config() {
+        let settings =
+            Settings::resolve(&run_args(&[]), Config::default()).unwrap();
+        assert_eq!(settings.cells, 30_000);
+        assert_eq!(settings.cell_width, CellWidth::U8);
+        assert_eq!(settings.eof, EofBehavior::Error);
+        assert_eq!(settings.max_steps, None);
+        assert_eq!(settings.max_output_bytes, None);
+        assert_eq!(settings.max_input_bytes, None);
+    }
+
+    #[test]
+    fn test_project_config_overrides_user_config() {
+        let project: Config = toml::from_str("cells = 1").unwrap();
+        let user: Config =
+            toml::from_str("cells = 2\nmax-steps = 3\nmax-output-bytes = 4")
+                .unwrap();
+        let merged = project.or(user);
+        let settings = Settings::resolve(&run_args(&[]), merged).unwrap();
+        assert_eq!(settings.cells, 1);
+        assert_eq!(settings.max_steps, Some(3));
+        assert_eq!(settings.max_output_bytes, Some(4));
+        let args = run_args(&["--max-input-bytes", "5"]);
+        let settings = Settings::resolve(&args, Config::default()).unwrap();
+        assert_eq!(settings.max_input_bytes, Some(5));
+    }
+
+    #[test]
+    fn test_find_project_config_in_parent() {
+        let root = temp_dir("find");
+        let nested = root.join("a").join("b");
+        fs::create_dir_all(&nested).unwrap();
+        fs::write(root.join("bft.toml"), "cells = 7").unwrap();
+
+        let found = Config::find_project_config(&nested).unwrap();
+        assert_eq!(found, root.join("bft.toml"));
+        assert_eq!(Config::from_file(&found).unwrap().cells, Some(7));
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_invalid_values_rejected() {
+        let config: Config = toml::from_str("cell-width = 12").unwrap();
+        assert!(Settings::resolve(&run_args(&[]), config).is_err());
+        let config: Config = toml::from_str("eof = \"sometimes\"").unwrap();
+        assert!(Settings::resolve(&run_args(&[]), config).is_err());
+        let config: Config = toml::from_str("newlines = \"cr\"").unwrap();
+        assert!(Settings::resolve(&run_args(&[]), config).is_err());
+    }
+}
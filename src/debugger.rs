@@ -0,0 +1,234 @@
+//! A small debugger which steps through a program from part way through a
+//! run, reading commands one to a line. `bft replay-steps` starts it at a step
+//! of a run recorded with `--record-steps`.
+
+use std::io::{self, BufRead, Cursor, Write};
+
+use bft_interp::{CellKind, VirtualMachine};
+use bft_types::vm_error::VirtualMachineError;
+use bft_types::BfProgram;
+
+use crate::config::Settings;
+use crate::steplog::StepState;
+
+/// The commands the debugger understands, as shown by `help`.
+const HELP: &str = "\
+step [N]   run the next N steps, or just the next one (s)
+continue   run until the program halts (c)
+tape       show the cells around the head (t)
+where      show the instruction about to run (w)
+quit       stop debugging (q)";
+
+/// The number of cells `tape` shows on each side of the head.
+const TAPE_CONTEXT: usize = 8;
+
+/// Runs a program a few steps at a time, showing where it has got to.
+pub(crate) struct Debugger<'a, T> {
+    program: &'a BfProgram,
+    vm: VirtualMachine<'a, T>,
+    input: Cursor<&'a [u8]>,
+    /// The step limit `continue` runs the program with.
+    max_steps: Option<u64>,
+    /// Whether the program has halted or failed, after which it cannot run
+    /// any further.
+    finished: bool,
+}
+
+impl<'a, T> Debugger<'a, T>
+where
+    T: CellKind + Default + Clone + Copy + PartialEq + 'a,
+{
+    /// Creates a debugger for the program with the given settings, which
+    /// reads the given input.
+    pub(crate) fn new(
+        program: &'a BfProgram,
+        settings: &Settings,
+        input: &'a [u8],
+    ) -> Self {
+        Self {
+            program,
+            vm: settings.virtual_machine(program),
+            input: Cursor::new(input),
+            max_steps: settings.max_steps,
+            finished: false,
+        }
+    }
+
+    /// Puts the program where a run had got to just before one of its steps,
+    /// failing if the tape cannot grow to hold the cells of that step.
+    pub(crate) fn restore(
+        mut self,
+        state: &StepState,
+    ) -> Result<Self, VirtualMachineError> {
+        let mut cells: Vec<T> =
+            state.cells.iter().map(|&cell| T::from_u32(cell)).collect();
+        if state.head >= cells.len() {
+            cells.resize(state.head + 1, T::default());
+        }
+        self.vm
+            .restore(&cells, state.head, state.position, state.step - 1)?;
+        Ok(self)
+    }
+
+    /// Shows the instruction about to run, and the cell at the head.
+    fn show_where(&self, out: &mut impl Write) -> io::Result<()> {
+        let position = self.vm.program_position();
+        let Some(instruction) = self.program.instructions().get(position)
+        else {
+            return writeln!(out, "at the end of the program");
+        };
+        writeln!(
+            out,
+            "step {}: '{}' at line {} column {}, head at {} holding {}",
+            self.vm.steps() + 1,
+            instruction.operation().to_char(),
+            instruction.line(),
+            instruction.column(),
+            self.vm.tape_head(),
+            self.vm.value_at_tape_head().to_u32()
+        )
+    }
+
+    /// Shows the cells around the head, with the cell at the head in
+    /// brackets.
+    fn show_tape(&self, out: &mut impl Write) -> io::Result<()> {
+        let (tape, head) = (self.vm.tape(), self.vm.tape_head());
+        let start = head.saturating_sub(TAPE_CONTEXT);
+        let end = tape.len().min(head + TAPE_CONTEXT + 1);
+        write!(out, "{}:", start)?;
+        for (index, cell) in tape[start..end].iter().enumerate() {
+            if start + index == head {
+                write!(out, " [{}]", cell.to_u32())?;
+            } else {
+                write!(out, " {}", cell.to_u32())?;
+            }
+        }
+        writeln!(out)
+    }
+
+    /// Runs the given number of steps, or until the program halts if no
+    /// number is given, showing any output the program wrote.
+    fn run_for(
+        &mut self,
+        steps: Option<u64>,
+        out: &mut impl Write,
+    ) -> io::Result<()> {
+        if self.finished {
+            return writeln!(out, "the program has finished");
+        }
+        let limit = match steps {
+            Some(steps) => Some(self.vm.steps().saturating_add(steps)),
+            None => self.max_steps,
+        };
+        self.vm.set_step_limit(limit);
+        let mut output = Vec::new();
+        let result = self.vm.interpret(&mut self.input, &mut output);
+        if !output.is_empty() {
+            writeln!(out, "output: \"{}\"", output.escape_ascii())?;
+        }
+        match result {
+            Ok(()) => {
+                self.finished = true;
+                writeln!(out, "halted after {} steps", self.vm.steps())
+            }
+            Err(VirtualMachineError::StepLimitExceeded { .. })
+                if steps.is_some() =>
+            {
+                self.show_where(out)
+            }
+            Err(err) => {
+                self.finished = true;
+                writeln!(out, "failed: {}", err)
+            }
+        }
+    }
+
+    /// Reads commands until told to quit or there are none left, showing the
+    /// prompt and everything the commands show on `out`.
+    pub(crate) fn run(
+        mut self,
+        commands: &mut impl BufRead,
+        out: &mut impl Write,
+    ) -> io::Result<()> {
+        self.show_where(out)?;
+        let mut line = String::new();
+        loop {
+            write!(out, "(bft) ")?;
+            out.flush()?;
+            line.clear();
+            if commands.read_line(&mut line)? == 0 {
+                return writeln!(out);
+            }
+            let mut words = line.split_whitespace();
+            match (words.next(), words.next()) {
+                (None, _) => {}
+                (Some("s" | "step"), count) => {
+                    match count.map_or(Ok(1), str::parse) {
+                        Ok(count) => self.run_for(Some(count), out)?,
+                        Err(err) => writeln!(out, "bad count: {}", err)?,
+                    }
+                }
+                (Some("c" | "continue"), _) => self.run_for(None, out)?,
+                (Some("t" | "tape"), _) => self.show_tape(out)?,
+                (Some("w" | "where"), _) => self.show_where(out)?,
+                (Some("q" | "quit"), _) => return Ok(()),
+                (Some("h" | "help"), _) => writeln!(out, "{}", HELP)?,
+                (Some(command), _) => {
+                    writeln!(out, "unknown command '{}', try 'help'", command)?
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Debugger;
+    use crate::config::settings;
+    use crate::steplog::StepState;
+    use bft_types::BfProgram;
+
+    /// Runs the debugger on the commands from the given state, returning
+    /// what it showed.
+    fn debug(source: &str, state: &StepState, commands: &str) -> String {
+        let program = BfProgram::new(source.to_string(), "debug.bf").unwrap();
+        let settings = settings(&["--eof", "zero"]);
+        let mut out = Vec::new();
+        Debugger::<u8>::new(&program, &settings, b"yz")
+            .restore(state)
+            .unwrap()
+            .run(&mut commands.as_bytes(), &mut out)
+            .unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_debugger() {
+        let state = StepState {
+            step: 3,
+            position: 2,
+            head: 1,
+            cells: vec![2, 5],
+            inputs: 0,
+        };
+        let shown = debug("+>+.,.", &state, "w\ns 2\nt\nstep x\nc\nc\nq\n");
+        assert_eq!(
+            shown,
+            "step 3: '+' at line 1 column 3, head at 1 holding 5\n\
+            (bft) step 3: '+' at line 1 column 3, head at 1 holding 5\n\
+            (bft) output: \"\\x06\"\n\
+            step 5: ',' at line 1 column 5, head at 1 holding 6\n\
+            (bft) 0: 2 [6] 0 0 0 0 0 0 0 0\n\
+            (bft) bad count: invalid digit found in string\n\
+            (bft) output: \"y\"\nhalted after 6 steps\n\
+            (bft) the program has finished\n\
+            (bft) "
+        );
+
+        // The commands can run out without quitting.
+        let state = StepState { step: 1, ..state };
+        let shown = debug("+>+.,.", &state, "oops\n");
+        assert!(shown.contains("unknown command 'oops'"));
+        assert!(shown.ends_with("(bft) \n"));
+    }
+}
@@ -0,0 +1,432 @@
+//! The `decompile` subcommand, which turns a program into structured
+//! pseudo-code by way of the optimized intermediate representation, so that
+//! the idioms it uses are named rather than spelt out instruction by
+//! instruction.
+
+use std::error::Error;
+use std::fs;
+use std::process::ExitCode;
+
+use bft_interp::ir::{IrNode, IrOp, IrProgram};
+use bft_interp::optimizer::Pipeline;
+use serde::Serialize;
+
+use crate::cli::{DecompileArgs, DecompileFormat};
+use crate::config::{pipeline, Settings};
+use crate::load_program;
+
+/// The number of spaces each loop is indented by.
+const INDENT: &str = "    ";
+
+/// A cell which a copy adds to.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct CopyTarget {
+    offset: isize,
+    factor: i32,
+}
+
+/// A cell which a multiplication adds the product of the count and another
+/// cell to.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct Product {
+    offset: isize,
+    by: isize,
+    factor: i32,
+}
+
+/// A cell which a multiplication sets to a value.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct SetCell {
+    offset: isize,
+    value: i32,
+}
+
+/// A single statement of the pseudo-code.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum Statement {
+    /// Moves the head by the amount.
+    Move {
+        by: isize,
+    },
+    /// Adds the amount to each of `len` cells from the offset.
+    Add {
+        offset: isize,
+        len: usize,
+        amount: i32,
+    },
+    /// Clears each of `len` cells from the offset.
+    Clear {
+        offset: isize,
+        len: usize,
+    },
+    /// Adds the current cell times each factor to the targets, and then clears
+    /// it.
+    Copy {
+        targets: Vec<CopyTarget>,
+    },
+    /// If the current cell is not zero, adds its value times each other cell
+    /// and factor to the products, and times each factor to the targets, sets
+    /// the cells to their values, and then clears it.
+    Multiply {
+        products: Vec<Product>,
+        targets: Vec<CopyTarget>,
+        sets: Vec<SetCell>,
+    },
+    /// Moves the head by the step until it reaches a zero cell.
+    Scan {
+        step: isize,
+    },
+    Input,
+    Output,
+    /// Writes out bytes which were worked out ahead of time.
+    OutputBytes {
+        bytes: Vec<u8>,
+    },
+    /// Sets the cells from the head to values worked out ahead of time.
+    LoadTape {
+        values: Vec<u32>,
+    },
+    Extension {
+        name: char,
+    },
+    While {
+        body: Vec<Node>,
+    },
+    /// Runs the first branch if the current cell is not zero, and the second
+    /// otherwise.
+    If {
+        then: Vec<Node>,
+        #[serde(rename = "else")]
+        otherwise: Vec<Node>,
+    },
+}
+
+/// A statement, along with where in the original program it came from.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct Node {
+    line: usize,
+    column: usize,
+    #[serde(flatten)]
+    statement: Statement,
+}
+
+/// Turns a single node of the intermediate representation into a statement,
+/// other than the ends of loops and branches.
+fn statement(op: &IrOp) -> Statement {
+    match op {
+        IrOp::Add(amount) => Statement::Add {
+            offset: 0,
+            len: 1,
+            amount: *amount,
+        },
+        IrOp::Move(by) => Statement::Move { by: *by },
+        IrOp::Output => Statement::Output,
+        IrOp::Input => Statement::Input,
+        IrOp::Clear => Statement::Clear { offset: 0, len: 1 },
+        IrOp::AddAt(offset, amount) => Statement::Add {
+            offset: *offset,
+            len: 1,
+            amount: *amount,
+        },
+        IrOp::ClearAt(offset) => Statement::Clear {
+            offset: *offset,
+            len: 1,
+        },
+        IrOp::AddRange(offset, len, amount) => Statement::Add {
+            offset: *offset,
+            len: *len,
+            amount: *amount,
+        },
+        IrOp::ClearRange(offset, len) => Statement::Clear {
+            offset: *offset,
+            len: *len,
+        },
+        IrOp::CopyLoop(targets) => Statement::Copy {
+            targets: targets
+                .iter()
+                .map(|&(offset, factor)| CopyTarget { offset, factor })
+                .collect(),
+        },
+        IrOp::MulAdd(mul) => Statement::Multiply {
+            products: mul
+                .products
+                .iter()
+                .map(|&(offset, by, factor)| Product { offset, by, factor })
+                .collect(),
+            targets: mul
+                .adds
+                .iter()
+                .map(|&(offset, factor)| CopyTarget { offset, factor })
+                .collect(),
+            sets: mul
+                .sets
+                .iter()
+                .map(|&(offset, value)| SetCell { offset, value })
+                .collect(),
+        },
+        IrOp::ScanRight(step) => Statement::Scan {
+            step: *step as isize,
+        },
+        IrOp::ScanLeft(step) => Statement::Scan {
+            step: -(*step as isize),
+        },
+        IrOp::OutputBytes(bytes) => Statement::OutputBytes {
+            bytes: bytes.clone(),
+        },
+        IrOp::LoadTape(values) => Statement::LoadTape {
+            values: values.clone(),
+        },
+        IrOp::Extension(name) => Statement::Extension { name: *name },
+        IrOp::LoopStart | IrOp::LoopEnd => {
+            Statement::While { body: Vec::new() }
+        }
+        IrOp::If | IrOp::Else | IrOp::EndIf => Statement::If {
+            then: Vec::new(),
+            otherwise: Vec::new(),
+        },
+    }
+}
+
+/// Builds the tree of statements from the nodes of a program, whose loops and
+/// branches are always balanced.
+fn decompile(nodes: &[IrNode]) -> Vec<Node> {
+    // The statements of each loop or branch which is still open, innermost
+    // last, along with the node which opened it.
+    let mut open: Vec<(Option<&IrNode>, Vec<Node>)> = vec![(None, Vec::new())];
+    // The first branch of each `If` whose second branch is open.
+    let mut thens: Vec<Vec<Node>> = Vec::new();
+    for node in nodes {
+        match node.op() {
+            IrOp::LoopStart | IrOp::If => open.push((Some(node), Vec::new())),
+            IrOp::Else => {
+                let (start, then) =
+                    open.pop().expect("branches are always balanced");
+                thens.push(then);
+                open.push((start, Vec::new()));
+            }
+            IrOp::EndIf => {
+                let (start, otherwise) =
+                    open.pop().expect("branches are always balanced");
+                let start = start.expect("branches are always balanced");
+                let then = thens.pop().expect("branches are always balanced");
+                let parent = &mut open.last_mut().expect("loops balance").1;
+                parent.push(Node {
+                    line: start.source().line(),
+                    column: start.source().column(),
+                    statement: Statement::If { then, otherwise },
+                });
+            }
+            IrOp::LoopEnd => {
+                let (start, body) =
+                    open.pop().expect("loops are always balanced");
+                let start = start.expect("loops are always balanced");
+                let parent = &mut open.last_mut().expect("loops balance").1;
+                parent.push(Node {
+                    line: start.source().line(),
+                    column: start.source().column(),
+                    statement: Statement::While { body },
+                });
+            }
+            op => {
+                let parent = &mut open.last_mut().expect("loops balance").1;
+                parent.push(Node {
+                    line: node.source().line(),
+                    column: node.source().column(),
+                    statement: statement(op),
+                });
+            }
+        }
+    }
+    open.pop().expect("the program is always open").1
+}
+
+/// Formats the cell at the offset from the head, such as `cell[p+2]`.
+fn cell(offset: isize) -> String {
+    match offset {
+        0 => "cell[p]".to_string(),
+        offset if offset < 0 => format!("cell[p{}]", offset),
+        offset => format!("cell[p+{}]", offset),
+    }
+}
+
+/// Formats `len` cells from the offset from the head, such as `cell[p+1..p+4]`.
+fn cells(offset: isize, len: usize) -> String {
+    if len == 1 {
+        return cell(offset);
+    }
+    let bound = |offset: isize| match offset {
+        0 => "p".to_string(),
+        offset if offset < 0 => format!("p{}", offset),
+        offset => format!("p+{}", offset),
+    };
+    format!("cell[{}..{}]", bound(offset), bound(offset + len as isize))
+}
+
+/// Formats adding a signed amount with `+=` or `-=`, such as `-= 3`.
+fn add_assign(amount: i64) -> String {
+    let operator = if amount < 0 { '-' } else { '+' };
+    format!("{}= {}", operator, amount.unsigned_abs())
+}
+
+/// Writes the statements as pseudo-code, indented to the given depth.
+fn render(nodes: &[Node], depth: usize, text: &mut String) {
+    for node in nodes {
+        text.push_str(&INDENT.repeat(depth));
+        let line = match &node.statement {
+            Statement::Move { by } => format!("p {}", add_assign(*by as i64)),
+            Statement::Add {
+                offset,
+                len,
+                amount,
+            } => format!(
+                "{} {}",
+                cells(*offset, *len),
+                add_assign(*amount as i64)
+            ),
+            Statement::Clear { offset, len } => {
+                format!("clear {}", cells(*offset, *len))
+            }
+            Statement::Copy { targets } => {
+                let targets: Vec<String> = targets
+                    .iter()
+                    .map(|target| match target.factor {
+                        1 => cell(target.offset),
+                        factor => {
+                            format!("{} * {}", cell(target.offset), factor)
+                        }
+                    })
+                    .collect();
+                format!("copy cell[p] to {}", targets.join(", "))
+            }
+            Statement::Multiply {
+                products,
+                targets,
+                sets,
+            } => {
+                let products = products.iter().map(|product| {
+                    let by = match product.factor {
+                        1 => cell(product.by),
+                        factor => format!("{} * {}", cell(product.by), factor),
+                    };
+                    format!("{} += cell[p] * {}", cell(product.offset), by)
+                });
+                let targets = targets.iter().map(|target| {
+                    format!(
+                        "{} += cell[p] * {}",
+                        cell(target.offset),
+                        target.factor
+                    )
+                });
+                let sets = sets
+                    .iter()
+                    .map(|set| format!("{} = {}", cell(set.offset), set.value));
+                let changes: Vec<String> =
+                    products.chain(targets).chain(sets).collect();
+                format!("multiply {}, clear cell[p]", changes.join(", "))
+            }
+            Statement::Scan { step } => {
+                format!("scan for a zero cell, p {}", add_assign(*step as i64))
+            }
+            Statement::Input => "input cell[p]".to_string(),
+            Statement::Output => "output cell[p]".to_string(),
+            Statement::OutputBytes { bytes } => {
+                format!("output \"{}\"", bytes.escape_ascii())
+            }
+            Statement::LoadTape { values } => {
+                format!("load cell[p..] = {:?}", values)
+            }
+            Statement::Extension { name } => format!("extension {:?}", name),
+            Statement::While { body } => {
+                text.push_str("while cell[p] != 0 {\n");
+                render(body, depth + 1, text);
+                format!("{}}}", INDENT.repeat(depth))
+            }
+            Statement::If { then, otherwise } => {
+                text.push_str("if cell[p] != 0 {\n");
+                render(then, depth + 1, text);
+                text.push_str(&format!("{}}} else {{\n", INDENT.repeat(depth)));
+                render(otherwise, depth + 1, text);
+                format!("{}}}", INDENT.repeat(depth))
+            }
+        };
+        text.push_str(&line);
+        text.push('\n');
+    }
+}
+
+/// Runs the `decompile` subcommand.
+pub(crate) fn run_decompile(
+    args: &DecompileArgs,
+) -> Result<ExitCode, Box<dyn Error>> {
+    let settings = Settings::from_args(&args.run)?;
+    let program = load_program(&args.filename, &settings)?;
+    let mut ir = IrProgram::from_program(&program)?;
+    let mut pipeline = match &settings.passes {
+        Some(spec) => pipeline(spec)?,
+        None => Pipeline::builtin(),
+    };
+    pipeline.run(&mut ir);
+    let nodes = decompile(ir.nodes());
+    let text = match args.format {
+        DecompileFormat::Text => {
+            let mut text = String::new();
+            render(&nodes, 0, &mut text);
+            text
+        }
+        DecompileFormat::Json => serde_json::to_string_pretty(&nodes)? + "\n",
+    };
+    match &args.output {
+        Some(path) => fs::write(path, text)?,
+        None => print!("{}", text),
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decompile, render};
+    use bft_interp::ir::IrProgram;
+    use bft_interp::optimizer::Pipeline;
+    use bft_types::BfProgram;
+
+    /// Decompiles the source with the built-in passes.
+    fn decompiled(source: &str) -> Vec<super::Node> {
+        let program = BfProgram::new(source.to_string(), "test.bf").unwrap();
+        let mut ir = IrProgram::from_program(&program).unwrap();
+        Pipeline::builtin().run(&mut ir);
+        decompile(ir.nodes())
+    }
+
+    #[test]
+    fn test_text() {
+        let nodes = decompiled("+++>>[-]<<[->+>--<<]>-[>.<,]<<[<]");
+        let mut text = String::new();
+        render(&nodes, 0, &mut text);
+        assert_eq!(
+            text,
+            "cell[p] += 3\n\
+             clear cell[p+2]\n\
+             copy cell[p] to cell[p+1], cell[p+2] * -2\n\
+             p += 1\n\
+             cell[p] -= 1\n\
+             while cell[p] != 0 {\n\
+             \x20   p += 1\n\
+             \x20   output cell[p]\n\
+             \x20   p -= 1\n\
+             \x20   input cell[p]\n\
+             }\n\
+             p -= 2\n\
+             scan for a zero cell, p -= 1\n"
+        );
+    }
+
+    #[test]
+    fn test_json() {
+        let nodes = decompiled("+\n[>]");
+        assert_eq!(
+            serde_json::to_string(&nodes).unwrap(),
+            r#"[{"line":1,"column":1,"op":"add","offset":0,"len":1,"amount":1},{"line":2,"column":1,"op":"scan","step":1}]"#
+        );
+    }
+}
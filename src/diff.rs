@@ -0,0 +1,479 @@
+//! The `diff` subcommand, which compares the instructions of two programs
+//! rather than their text. Comments and layout are left out, so a change to a
+//! golfed program, where the whole text is often reflowed, shows up as just
+//! the instructions which changed, with where they are in both files.
+//!
+//! The instructions are compared with Myers' algorithm, which finds the
+//! fewest instructions to remove and add to turn one program into the other.
+
+use std::error::Error;
+use std::fmt::Write;
+use std::ops::Range;
+use std::path::Path;
+use std::process::ExitCode;
+
+use bft_interp::ir::IrProgram;
+use bft_types::BfProgram;
+
+use crate::assembly::summary;
+use crate::cli::DiffArgs;
+use crate::config::Settings;
+use crate::load_program;
+
+/// The most edits searched for between the programs. Walking back through the
+/// search needs memory growing with the square of the number of edits, so
+/// programs which differ by more than this are shown as replacing the whole
+/// of the part which changed.
+const MAX_EDITS: usize = 2048;
+
+/// What to do with each instruction to turn the first program into the
+/// second.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Edit {
+    /// The instruction is in both programs.
+    Keep,
+    /// The instruction of the first program is not in the second.
+    Remove,
+    /// The instruction of the second program is not in the first.
+    Add,
+}
+
+/// Finds the shortest list of edits turning `a` into `b`.
+pub(crate) fn edits<T: PartialEq>(a: &[T], b: &[T]) -> Vec<Edit> {
+    // Most changes are small, so the common start and end are skipped before
+    // searching, which keeps the search to the part which changed.
+    let prefix = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    let suffix = a[prefix..]
+        .iter()
+        .rev()
+        .zip(b[prefix..].iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+    let middle = shortest_edits(
+        &a[prefix..a.len() - suffix],
+        &b[prefix..b.len() - suffix],
+    );
+    let mut edits = vec![Edit::Keep; prefix];
+    edits.extend(middle);
+    edits.extend(vec![Edit::Keep; suffix]);
+    slide(&mut edits, a, b);
+    edits
+}
+
+/// Moves each run of only removed or only added instructions as late as it
+/// can go, as other diff tools do, so that `+.` changed to `+..` is shown as
+/// adding the last `.` rather than the first.
+fn slide<T: PartialEq>(edits: &mut [Edit], a: &[T], b: &[T]) {
+    let (mut i, mut x, mut y) = (0, 0, 0);
+    while i < edits.len() {
+        let edit = edits[i];
+        if edit == Edit::Keep {
+            i += 1;
+            x += 1;
+            y += 1;
+            continue;
+        }
+        let len = edits[i..].iter().take_while(|&&e| e == edit).count();
+        let (items, mut first) = match edit {
+            Edit::Add => (b, y),
+            _ => (a, x),
+        };
+        // The run can move down by one whenever the instruction kept after it
+        // is the same as its first one.
+        let (mut start, mut end) = (i, i + len);
+        while edits.get(end) == Some(&Edit::Keep)
+            && items[first] == items[first + len]
+        {
+            edits.swap(start, end);
+            start += 1;
+            end += 1;
+            first += 1;
+        }
+        x += start - i;
+        y += start - i;
+        match edit {
+            Edit::Add => y += len,
+            _ => x += len,
+        }
+        i = end;
+    }
+}
+
+/// Myers' algorithm: searches each diagonal `k = x - y` of the grid of edits
+/// for the furthest point reachable with `d` edits, for increasing `d`, then
+/// walks back through the furthest points to find the edits taken.
+fn shortest_edits<T: PartialEq>(a: &[T], b: &[T]) -> Vec<Edit> {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let max = n + m;
+    let offset = max + 1;
+    let mut furthest = vec![0isize; 2 * offset as usize + 1];
+    let at = |k: isize| (k + offset) as usize;
+    // The furthest points on the diagonals -d..=d before each round, which are
+    // all that walking back needs.
+    let mut rounds: Vec<Vec<isize>> = Vec::new();
+    let mut done = None;
+    for d in 0..=max.min(MAX_EDITS as isize) {
+        rounds.push(furthest[at(-d)..=at(d)].to_vec());
+        for k in (-d..=d).step_by(2) {
+            let mut x = if k == -d
+                || (k != d && furthest[at(k - 1)] < furthest[at(k + 1)])
+            {
+                furthest[at(k + 1)]
+            } else {
+                furthest[at(k - 1)] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            furthest[at(k)] = x;
+            if x >= n && y >= m {
+                done = Some(d);
+                break;
+            }
+        }
+        if done.is_some() {
+            break;
+        }
+    }
+
+    let Some(done) = done else {
+        let mut edits = vec![Edit::Remove; a.len()];
+        edits.extend(vec![Edit::Add; b.len()]);
+        return edits;
+    };
+    let mut edits = Vec::new();
+    let (mut x, mut y) = (n, m);
+    for d in (1..=done).rev() {
+        let round = &rounds[d as usize];
+        let before = |k: isize| round[(k + d) as usize];
+        let k = x - y;
+        let previous = if k == -d || (k != d && before(k - 1) < before(k + 1)) {
+            k + 1
+        } else {
+            k - 1
+        };
+        let previous_x = before(previous);
+        let previous_y = previous_x - previous;
+        while x > previous_x && y > previous_y {
+            edits.push(Edit::Keep);
+            x -= 1;
+            y -= 1;
+        }
+        edits.push(if x == previous_x {
+            Edit::Add
+        } else {
+            Edit::Remove
+        });
+        (x, y) = (previous_x, previous_y);
+    }
+    edits.extend((0..x).map(|_| Edit::Keep));
+    edits.reverse();
+    edits
+}
+
+/// A change between the programs: a run of instructions of the first program
+/// replaced by a run of the second, either of which may be empty.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Hunk {
+    pub(crate) removed: Range<usize>,
+    pub(crate) added: Range<usize>,
+}
+
+/// Groups the edits into changes, each made up of the edits between two
+/// instructions which are kept.
+pub(crate) fn hunks(edits: &[Edit]) -> Vec<Hunk> {
+    let mut hunks: Vec<Hunk> = Vec::new();
+    let (mut a, mut b) = (0, 0);
+    let mut last = Edit::Keep;
+    for &edit in edits {
+        if edit != Edit::Keep && last == Edit::Keep {
+            hunks.push(Hunk {
+                removed: a..a,
+                added: b..b,
+            });
+        }
+        match edit {
+            Edit::Keep => {
+                a += 1;
+                b += 1;
+            }
+            Edit::Remove => {
+                a += 1;
+                hunks.last_mut().expect("a hunk was started").removed.end = a;
+            }
+            Edit::Add => {
+                b += 1;
+                hunks.last_mut().expect("a hunk was started").added.end = b;
+            }
+        }
+        last = edit;
+    }
+    hunks
+}
+
+/// One of the programs being compared: what each of its instructions or nodes
+/// is compared by and shown as, and where each came from in its file.
+struct Side<'a> {
+    filename: &'a Path,
+    keys: Vec<String>,
+    items: Vec<String>,
+    positions: Vec<(usize, usize)>,
+}
+
+impl<'a> Side<'a> {
+    /// The instructions of the program, as their commands.
+    fn instructions(program: &'a BfProgram) -> Self {
+        let items: Vec<String> = program
+            .iter()
+            .map(|i| i.operation().compact().to_string())
+            .collect();
+        Self {
+            filename: program.filename(),
+            keys: items.clone(),
+            items,
+            positions: program.iter().map(|i| (i.line(), i.column())).collect(),
+        }
+    }
+
+    /// The nodes of the program once lowered and optimized. Long constants
+    /// are shown in short, but still compared in full.
+    fn nodes(ir: &'a IrProgram) -> Self {
+        Self {
+            filename: ir.filename(),
+            keys: ir
+                .nodes()
+                .iter()
+                .map(|node| format!("{:?}", node.op()))
+                .collect(),
+            items: ir.nodes().iter().map(|node| summary(node.op())).collect(),
+            positions: ir
+                .nodes()
+                .iter()
+                .map(|node| (node.source().line(), node.source().column()))
+                .collect(),
+        }
+    }
+
+    /// Where the change starting at the position is in the file, which is at
+    /// the instruction after it for a change which only adds instructions.
+    fn location(&self, position: usize) -> String {
+        match self.positions.get(position) {
+            Some((line, column)) => {
+                format!("{}:{}:{}", self.filename.display(), line, column)
+            }
+            None => format!("{}:end", self.filename.display()),
+        }
+    }
+}
+
+/// Describes each change between the programs, with the instructions of a
+/// change written together on one line, or the nodes each on their own line.
+fn report(first: &Side, second: &Side, hunks: &[Hunk], nodes: bool) -> String {
+    let mut report = String::new();
+    for hunk in hunks {
+        writeln!(
+            report,
+            "@@ {} {} @@",
+            first.location(hunk.removed.start),
+            second.location(hunk.added.start)
+        )
+        .unwrap();
+        for (sign, items) in [
+            ('-', &first.items[hunk.removed.clone()]),
+            ('+', &second.items[hunk.added.clone()]),
+        ] {
+            if nodes {
+                for item in items {
+                    writeln!(report, "{} {}", sign, item).unwrap();
+                }
+            } else if !items.is_empty() {
+                writeln!(report, "{} {}", sign, items.concat()).unwrap();
+            }
+        }
+    }
+    report
+}
+
+/// Runs the `diff` subcommand.
+pub(crate) fn run_diff(args: &DiffArgs) -> Result<ExitCode, Box<dyn Error>> {
+    let settings = Settings::from_args(&args.run)?;
+    let first = load_program(&args.first, &settings)?;
+    let second = load_program(&args.second, &settings)?;
+    let lower = |program: &BfProgram| -> Result<IrProgram, Box<dyn Error>> {
+        Ok(match settings.optimize(program, true)? {
+            Some((ir, _)) => ir,
+            None => IrProgram::from_program(program)?,
+        })
+    };
+    let irs = if args.ir {
+        Some((lower(&first)?, lower(&second)?))
+    } else {
+        None
+    };
+    let (first, second) = match &irs {
+        Some((first, second)) => (Side::nodes(first), Side::nodes(second)),
+        None => (Side::instructions(&first), Side::instructions(&second)),
+    };
+    let edits = edits(&first.keys, &second.keys);
+    let hunks = hunks(&edits);
+    let kind = if args.ir { "nodes" } else { "instructions" };
+    if hunks.is_empty() {
+        println!("The programs have the same {}.", kind);
+        return Ok(ExitCode::SUCCESS);
+    }
+    print!("{}", report(&first, &second, &hunks, args.ir));
+    let count = |edit| edits.iter().filter(|&&e| e == edit).count();
+    println!(
+        "Changes: {}, {} removed: {}, added: {}",
+        hunks.len(),
+        kind,
+        count(Edit::Remove),
+        count(Edit::Add)
+    );
+    Ok(ExitCode::FAILURE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{edits, hunks, report, Edit, Hunk, Side};
+    use bft_interp::ir::IrProgram;
+    use bft_types::BfProgram;
+
+    fn program(source: &str, filename: &str) -> BfProgram {
+        BfProgram::new(source.to_string(), filename).unwrap()
+    }
+
+    /// Applies the edits to `a`, which should give `b`.
+    fn apply(a: &[char], b: &[char], edits: &[Edit]) -> Vec<char> {
+        let (mut i, mut j) = (0, 0);
+        let mut result = Vec::new();
+        for edit in edits {
+            match edit {
+                Edit::Keep => {
+                    assert_eq!(a[i], b[j]);
+                    result.push(a[i]);
+                    i += 1;
+                    j += 1;
+                }
+                Edit::Remove => i += 1,
+                Edit::Add => {
+                    result.push(b[j]);
+                    j += 1;
+                }
+            }
+        }
+        assert_eq!((i, j), (a.len(), b.len()));
+        result
+    }
+
+    /// The length of the longest common subsequence, the most that the
+    /// shortest edits can keep.
+    fn longest_common(a: &[char], b: &[char]) -> usize {
+        let mut row = vec![0; b.len() + 1];
+        for x in a {
+            let mut diagonal = 0;
+            for (j, y) in b.iter().enumerate() {
+                let above = row[j + 1];
+                row[j + 1] = if x == y {
+                    diagonal + 1
+                } else {
+                    above.max(row[j])
+                };
+                diagonal = above;
+            }
+        }
+        row[b.len()]
+    }
+
+    #[test]
+    fn test_edits() {
+        let mut cases: Vec<(String, String)> = [
+            ("", ""),
+            ("abc", ""),
+            ("", "abc"),
+            ("+[->+<]", "+[->++<]>."),
+            ("xaxbx", "ayb"),
+        ]
+        .iter()
+        .map(|(a, b)| (a.to_string(), b.to_string()))
+        .collect();
+        let mut seed: u32 = 1;
+        let mut random = |len: u32| -> String {
+            (0..len)
+                .map(|_| {
+                    seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                    ['+', '-', '>', '<'][(seed >> 16) as usize % 4]
+                })
+                .collect()
+        };
+        for len in 0..100 {
+            cases.push((random(len % 20), random(len % 13)));
+        }
+        for (a, b) in cases {
+            let a: Vec<char> = a.chars().collect();
+            let b: Vec<char> = b.chars().collect();
+            let edits = edits(&a, &b);
+            assert_eq!(apply(&a, &b, &edits), b);
+            let kept = edits.iter().filter(|&&e| e == Edit::Keep).count();
+            assert_eq!(kept, longest_common(&a, &b));
+        }
+        // The example from Myers' paper, which needs five edits.
+        let a: Vec<char> = "abcabba".chars().collect();
+        let b: Vec<char> = "cbabac".chars().collect();
+        let edits = edits(&a, &b);
+        assert_eq!(edits.iter().filter(|&&e| e != Edit::Keep).count(), 5);
+        // Programs too different to search are replaced in one go.
+        let a = vec!['+'; 3000];
+        let b = vec!['-'; 3000];
+        assert_eq!(hunks(&super::edits(&a, &b)).len(), 1);
+    }
+
+    #[test]
+    fn test_hunks() {
+        use Edit::{Add, Keep, Remove};
+        let found = hunks(&[Keep, Remove, Add, Add, Keep, Keep, Add]);
+        assert_eq!(
+            found,
+            [
+                Hunk {
+                    removed: 1..2,
+                    added: 1..3
+                },
+                Hunk {
+                    removed: 4..4,
+                    added: 5..6
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn test_report() {
+        let first = program("Adds\n++>+++[-<+>]<.", "a.bf");
+        let second = program("Adds  them\n++>++++[-<+>]\n<.>.", "b.bf");
+        let (a, b) = (Side::instructions(&first), Side::instructions(&second));
+        let found = hunks(&edits(&a.keys, &b.keys));
+        assert_eq!(
+            report(&a, &b, &found, false),
+            "@@ a.bf:2:7 b.bf:2:7 @@\n+ +\n@@ a.bf:end b.bf:3:3 @@\n+ >.\n"
+        );
+        // Only comments and layout differ.
+        let moved = program("++\n>\n+++ [-<+>] <.", "c.bf");
+        let c = Side::instructions(&moved);
+        assert!(hunks(&edits(&a.keys, &c.keys)).is_empty());
+    }
+
+    #[test]
+    fn test_report_nodes() {
+        let first = IrProgram::from_program(&program("+++.", "a.bf")).unwrap();
+        let second = IrProgram::from_program(&program("++.", "b.bf")).unwrap();
+        let (a, b) = (Side::nodes(&first), Side::nodes(&second));
+        let found = hunks(&edits(&a.keys, &b.keys));
+        assert_eq!(
+            report(&a, &b, &found, true),
+            "@@ a.bf:1:3 b.bf:1:3 @@\n- Add(1)\n"
+        );
+    }
+}
@@ -0,0 +1,360 @@
+//! The `equiv` subcommand, which checks whether two programs behave the same
+//! way by running them both across a set of inputs.
+
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use bft_interp::display::{format_mapped_cells, CellFormatter};
+use bft_types::memory::MemoryMap;
+use bft_types::BfProgram;
+
+use crate::cli::EquivArgs;
+use crate::config::{CellWidth, Settings};
+use crate::harness::{
+    execute_prepared, prepare, Execution, Outcome, DEFAULT_STEP_LIMIT,
+};
+use crate::load_program;
+use crate::output_diff::{color_enabled, output_diff};
+use crate::replay::Replay;
+
+/// A source of inputs to run both programs with.
+#[derive(Debug, PartialEq, Eq)]
+enum InputSpec {
+    /// A number of randomly generated inputs.
+    Fuzz(usize),
+    /// A single input, read from a file.
+    File(PathBuf),
+}
+
+impl InputSpec {
+    fn parse(spec: &str) -> Result<InputSpec, String> {
+        match spec.split_once(':') {
+            Some(("fuzz", count)) => count
+                .parse()
+                .map(InputSpec::Fuzz)
+                .map_err(|_| format!("invalid fuzz count in '{}'", spec)),
+            Some(("file", path)) => Ok(InputSpec::File(PathBuf::from(path))),
+            _ => Err(format!(
+                "invalid input '{}', expected fuzz:<count> or file:<path>",
+                spec
+            )),
+        }
+    }
+}
+
+/// A small xorshift generator, so that fuzzed inputs can be reproduced from
+/// their seed.
+struct XorShift(u64);
+
+impl XorShift {
+    fn new(seed: u64) -> Self {
+        // Xorshift gets stuck on a state of zero.
+        XorShift(seed.max(1))
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// Generates an input of up to `max_len` bytes, mostly printable ASCII
+    /// with the occasional arbitrary byte.
+    fn input(&mut self, max_len: usize) -> Vec<u8> {
+        let len = (self.next() % (max_len as u64 + 1)) as usize;
+        (0..len)
+            .map(|_| {
+                let value = self.next();
+                if value.is_multiple_of(8) {
+                    (value >> 8) as u8
+                } else {
+                    b' ' + ((value >> 8) % 95) as u8
+                }
+            })
+            .collect()
+    }
+}
+
+/// Whether the runs of the two programs can be compared at all, which is not
+/// the case when either was cut short by the step limit.
+fn conclusive(a: &Execution, b: &Execution) -> bool {
+    !matches!(a.outcome, Outcome::StepLimit)
+        && !matches!(b.outcome, Outcome::StepLimit)
+}
+
+/// Whether the runs of the two programs differ.
+fn diverges(a: &Execution, b: &Execution, compare_tape: bool) -> bool {
+    a.output != b.output
+        || !a.outcome.same_kind(&b.outcome)
+        || (compare_tape && a.tape != b.tape)
+}
+
+/// Shrinks an input on which two programs diverge, by repeatedly removing
+/// chunks of it for as long as they still diverge.
+fn minimize<F>(mut input: Vec<u8>, still_diverges: F) -> Vec<u8>
+where
+    F: Fn(&[u8]) -> bool,
+{
+    let mut chunk = (input.len() / 2).max(1);
+    loop {
+        let mut start = 0;
+        while start < input.len() {
+            let end = (start + chunk).min(input.len());
+            let candidate: Vec<u8> = [&input[..start], &input[end..]].concat();
+            if still_diverges(&candidate) {
+                input = candidate;
+            } else {
+                start += chunk;
+            }
+        }
+        if chunk == 1 {
+            return input;
+        }
+        chunk /= 2;
+    }
+}
+
+/// How tapes are shown in the divergence report.
+#[derive(Clone, Copy)]
+struct TapeView<'a> {
+    /// How each cell is written out.
+    formatter: &'a dyn CellFormatter,
+    /// The width of the cells.
+    width: CellWidth,
+    /// The regions to group the cells by, if any.
+    map: Option<&'a MemoryMap>,
+}
+
+/// Describes a single run for the divergence report, showing the tape if the
+/// tapes are compared.
+fn describe(
+    name: &str,
+    execution: &Execution,
+    tape: Option<TapeView<'_>>,
+) -> String {
+    let mut description = format!(
+        "  {}: output \"{}\" and {}",
+        name,
+        execution.output.escape_ascii(),
+        execution.outcome.describe()
+    );
+    if let Some(view) = tape {
+        let used = execution
+            .tape
+            .iter()
+            .rposition(|cell| *cell != 0)
+            .map_or(0, |last| last + 1);
+        let cells = &execution.tape[..used];
+        let bits = view.width.bits();
+        let shown = match view.map {
+            Some(map) => format_mapped_cells(view.formatter, cells, bits, map),
+            None => view.formatter.format_cells(cells, bits),
+        };
+        description.push_str(&format!(", tape {}", shown));
+    }
+    description
+}
+
+/// Writes a replay of each program running on the input to the directory, as
+/// `1-<name>.bftrun` and `2-<name>.bftrun`, so that the divergence can be
+/// archived and reproduced with `bft replay`.
+fn save_replays(
+    dir: &Path,
+    args: &EquivArgs,
+    programs: [&BfProgram; 2],
+    settings: &Settings,
+    input: &[u8],
+) -> Result<(), Box<dyn Error>> {
+    fs::create_dir_all(dir)?;
+    // The step limit is always applied, so it must be recorded too.
+    let settings = Settings {
+        max_steps: Some(settings.max_steps.unwrap_or(DEFAULT_STEP_LIMIT)),
+        ..settings.clone()
+    };
+    for (number, program) in (1..).zip(programs) {
+        let name = program
+            .filename()
+            .file_stem()
+            .map_or("program".into(), |stem| stem.to_string_lossy());
+        let path = dir.join(format!("{}-{}.bftrun", number, name));
+        Replay::record(program, &settings, input)?
+            .with_seed("equiv", args.seed)
+            .write_to(&path)?;
+        println!("Saved a replay of {} to {}", name, path.display());
+    }
+    Ok(())
+}
+
+/// Runs both programs on every input, reporting the first input on which they
+/// differ.
+pub(crate) fn run_equiv(args: &EquivArgs) -> Result<ExitCode, Box<dyn Error>> {
+    let settings = Settings::from_args(&args.run)?;
+    let first: BfProgram = load_program(&args.first, &settings)?;
+    let second: BfProgram = load_program(&args.second, &settings)?;
+    let step_limit = settings.max_steps.unwrap_or(DEFAULT_STEP_LIMIT);
+
+    let mut inputs: Vec<Vec<u8>> = Vec::new();
+    let mut rng = XorShift::new(args.seed);
+    for spec in &args.inputs {
+        match InputSpec::parse(spec)? {
+            InputSpec::Fuzz(count) => {
+                inputs.extend((0..count).map(|_| rng.input(args.max_input_len)))
+            }
+            InputSpec::File(path) => inputs.push(fs::read(path)?),
+        }
+    }
+    if inputs.is_empty() {
+        inputs.push(Vec::new());
+    }
+
+    // Each program is compiled once, for every input it is run with.
+    let prepared = [prepare(&first, &settings), prepare(&second, &settings)];
+    let run_both = |input: &[u8]| {
+        (
+            execute_prepared(&prepared[0], &settings, input, step_limit),
+            execute_prepared(&prepared[1], &settings, input, step_limit),
+        )
+    };
+
+    let memory_map = match &args.memory_map {
+        Some(path) => Some(MemoryMap::from_file(path)?),
+        None => None,
+    };
+    let tape = args.compare_tape.then_some(TapeView {
+        formatter: &args.display,
+        width: settings.cell_width,
+        map: memory_map.as_ref(),
+    });
+    let mut inconclusive = 0;
+    for input in &inputs {
+        let (a, b) = run_both(input);
+        if !conclusive(&a, &b) {
+            inconclusive += 1;
+            continue;
+        }
+        if diverges(&a, &b, args.compare_tape) {
+            let minimal = minimize(input.clone(), |candidate| {
+                let (a, b) = run_both(candidate);
+                conclusive(&a, &b) && diverges(&a, &b, args.compare_tape)
+            });
+            let (a, b) = run_both(&minimal);
+            println!(
+                "Programs differ on input \"{}\" (minimized from {} bytes):",
+                minimal.escape_ascii(),
+                input.len()
+            );
+            println!(
+                "{}",
+                describe(&args.first.display().to_string(), &a, tape)
+            );
+            println!(
+                "{}",
+                describe(&args.second.display().to_string(), &b, tape)
+            );
+            if a.output != b.output {
+                let first_name = args.first.display().to_string();
+                let second_name = args.second.display().to_string();
+                let labels = [first_name.as_str(), second_name.as_str()];
+                println!(
+                    "{}",
+                    output_diff(&a.output, &b.output, labels, color_enabled())
+                );
+            }
+            if let Some(dir) = &args.save_replays {
+                save_replays(
+                    dir,
+                    args,
+                    [&first, &second],
+                    &settings,
+                    &minimal,
+                )?;
+            }
+            return Ok(ExitCode::FAILURE);
+        }
+    }
+
+    println!(
+        "Programs agree on {} of {} inputs ({} inconclusive due to the step \
+        limit).",
+        inputs.len() - inconclusive,
+        inputs.len(),
+        inconclusive
+    );
+    Ok(ExitCode::SUCCESS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{describe, minimize, InputSpec, TapeView, XorShift};
+    use crate::config::CellWidth;
+    use crate::harness::{Execution, Outcome};
+    use bft_interp::display::CellDisplay;
+    use bft_types::memory::MemoryMap;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_parse_input_specs() {
+        assert_eq!(InputSpec::parse("fuzz:10"), Ok(InputSpec::Fuzz(10)));
+        assert_eq!(
+            InputSpec::parse("file:in.txt"),
+            Ok(InputSpec::File(PathBuf::from("in.txt")))
+        );
+        assert!(InputSpec::parse("fuzz:many").is_err());
+        assert!(InputSpec::parse("in.txt").is_err());
+    }
+
+    #[test]
+    fn test_fuzz_is_reproducible() {
+        let mut a = XorShift::new(42);
+        let mut b = XorShift::new(42);
+        for _ in 0..10 {
+            let input = a.input(8);
+            assert!(input.len() <= 8);
+            assert_eq!(input, b.input(8));
+        }
+    }
+
+    #[test]
+    fn test_minimize_keeps_divergence() {
+        let input = b"hello, world".to_vec();
+        let minimal = minimize(input, |candidate| candidate.contains(&b','));
+        assert_eq!(minimal, b",".to_vec());
+    }
+
+    #[test]
+    fn test_describe_tape() {
+        let execution = Execution {
+            output: b"hi".to_vec(),
+            outcome: Outcome::Halted,
+            tape: vec![104, 0x263a, 0, 0],
+            steps: 5,
+        };
+        assert_eq!(
+            describe("a.bf", &execution, None),
+            "  a.bf: output \"hi\" and halted"
+        );
+        let hex = TapeView {
+            formatter: &CellDisplay::Hex,
+            width: CellWidth::U16,
+            map: None,
+        };
+        assert_eq!(
+            describe("a.bf", &execution, Some(hex)),
+            "  a.bf: output \"hi\" and halted, tape [0x0068, 0x263a]"
+        );
+        let map = MemoryMap::new().with_region(1..4, "smile").unwrap();
+        let ascii = TapeView {
+            formatter: &CellDisplay::Ascii,
+            width: CellWidth::U16,
+            map: Some(&map),
+        };
+        assert_eq!(
+            describe("a.bf", &execution, Some(ascii)),
+            "  a.bf: output \"hi\" and halted, tape 0..1 ['h'], \
+            smile ['\\u{263a}']"
+        );
+    }
+}
@@ -0,0 +1,249 @@
+//! Checking a run against what it was expected to do, with `--expect-output`
+//! and `--expect-exit`, so that a shell script can check a program in one line
+//! without setting up a project for `bft test`.
+//!
+//! The output is compared with what the program wrote, before newlines are
+//! translated or a trailing newline is added for the terminal, and a mismatch
+//! is shown with `output_diff`.
+
+use std::error::Error;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+use crate::cli::RunOnlyArgs;
+use crate::i18n;
+use crate::output_diff::{color_enabled, output_diff};
+
+/// What a run was expected to do. Without any expectations, a run is only
+/// expected to halt without an error.
+#[derive(Debug, Default)]
+pub(crate) struct Expectation {
+    output: Option<Vec<u8>>,
+    exit: Option<u8>,
+    color: bool,
+}
+
+impl Expectation {
+    /// The expectations given on the command line, reading the expected
+    /// output from its file if it names one.
+    pub(crate) fn from_args(
+        run_only: &RunOnlyArgs,
+    ) -> Result<Self, Box<dyn Error>> {
+        let output = match &run_only.expect_output {
+            Some(value) if Path::new(value).is_file() => Some(fs::read(value)?),
+            Some(value) => Some(unescape(value).map_err(|err| {
+                format!("--expect-output is not a file, and {}", err)
+            })?),
+            None => None,
+        };
+        Ok(Self {
+            output,
+            exit: run_only.expect_exit,
+            color: color_enabled(),
+        })
+    }
+
+    /// Whether the output of the run needs to be kept to be checked.
+    pub(crate) fn checks_output(&self) -> bool {
+        self.output.is_some()
+    }
+
+    /// Checks the run, which wrote the output and failed with the message if
+    /// it failed. A run which fails as it was expected to passes the check.
+    pub(crate) fn check(
+        &self,
+        output: &[u8],
+        failure: Option<String>,
+    ) -> Result<(), Box<dyn Error>> {
+        let code = u8::from(failure.is_some());
+        match (self.exit, failure) {
+            (Some(expected), None) if expected != code => {
+                return Err(i18n::localize(
+                    "expect-exit",
+                    &[("code", &code), ("expected", &expected)],
+                    || {
+                        format!(
+                            "the run exited with {} rather than {}",
+                            code, expected
+                        )
+                    },
+                )
+                .into());
+            }
+            (Some(expected), Some(reason)) if expected != code => {
+                return Err(i18n::localize(
+                    "expect-exit-because",
+                    &[
+                        ("code", &code),
+                        ("expected", &expected),
+                        ("reason", &reason),
+                    ],
+                    // The reason is an error message, which is a sentence of
+                    // its own.
+                    || {
+                        format!(
+                            "the run exited with {} rather than {}. {}",
+                            code, expected, reason
+                        )
+                    },
+                )
+                .into());
+            }
+            (None, Some(failure)) => return Err(failure.into()),
+            _ => {}
+        }
+        match &self.output {
+            Some(expected) if expected != output => {
+                let diff = output_diff(
+                    expected,
+                    output,
+                    ["expected", "output"],
+                    self.color,
+                );
+                Err(i18n::localize("expect-output", &[("diff", &diff)], || {
+                    format!("the output was not as expected:\n{}", diff)
+                })
+                .into())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Turns the escapes `\n`, `\r`, `\t`, `\0`, `\\` and `\xNN` of the text into
+/// the bytes they stand for.
+fn unescape(text: &str) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buffer = [0; 4];
+            bytes.extend_from_slice(c.encode_utf8(&mut buffer).as_bytes());
+            continue;
+        }
+        let byte = match chars.next() {
+            Some('n') => b'\n',
+            Some('r') => b'\r',
+            Some('t') => b'\t',
+            Some('0') => 0,
+            Some('\\') => b'\\',
+            Some('x') => {
+                let digits: String = chars.by_ref().take(2).collect();
+                u8::from_str_radix(&digits, 16)
+                    .ok()
+                    .filter(|_| digits.len() == 2)
+                    .ok_or_else(|| {
+                        format!("\\x{} is not a byte in hex", digits)
+                    })?
+            }
+            Some(other) => return Err(format!("\\{} is not an escape", other)),
+            None => return Err("it ends with a lone \\".to_string()),
+        };
+        bytes.push(byte);
+    }
+    Ok(bytes)
+}
+
+/// Passes output along to the writer, keeping a copy of it if asked to, so
+/// that it can be checked once the run is over.
+pub(crate) struct CapturingWriter<W> {
+    writer: W,
+    captured: Option<Vec<u8>>,
+}
+
+impl<W> CapturingWriter<W> {
+    pub(crate) fn new(writer: W, capture: bool) -> Self {
+        Self {
+            writer,
+            captured: capture.then(Vec::new),
+        }
+    }
+
+    /// Everything written so far, if it is being kept.
+    pub(crate) fn captured(&self) -> &[u8] {
+        self.captured.as_deref().unwrap_or_default()
+    }
+}
+
+impl<W> Write for CapturingWriter<W>
+where
+    W: Write,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.writer.write(buf)?;
+        if let Some(captured) = &mut self.captured {
+            captured.extend_from_slice(&buf[..written]);
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{unescape, CapturingWriter, Expectation};
+    use std::io::Write;
+
+    #[test]
+    fn test_unescape() {
+        assert_eq!(unescape("Hi\\n\\x41\\\\é").unwrap(), b"Hi\nA\\\xc3\xa9");
+        assert!(unescape("\\q").is_err());
+        assert!(unescape("\\x4").is_err());
+        assert!(unescape("\\xzz").is_err());
+        assert!(unescape("end\\").is_err());
+    }
+
+    #[test]
+    fn test_check() {
+        let halt = Expectation::default();
+        assert!(halt.check(b"", None).is_ok());
+        assert_eq!(
+            halt.check(b"", Some("stuck".to_string()))
+                .unwrap_err()
+                .to_string(),
+            "stuck"
+        );
+
+        let fail = Expectation {
+            output: Some(b"ok\n".to_vec()),
+            exit: Some(1),
+            color: false,
+        };
+        assert!(fail.check(b"ok\n", Some("stuck".to_string())).is_ok());
+        assert_eq!(
+            fail.check(b"ok\n", None).unwrap_err().to_string(),
+            "the run exited with 0 rather than 1"
+        );
+        let pass = Expectation {
+            exit: Some(0),
+            ..Expectation::default()
+        };
+        assert_eq!(
+            pass.check(b"", Some("In a.bf: it got stuck.".to_string()))
+                .unwrap_err()
+                .to_string(),
+            "the run exited with 1 rather than 0. In a.bf: it got stuck."
+        );
+        let err = fail.check(b"no\n", Some("stuck".to_string())).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "the output was not as expected:\n--- expected\n+++ output\
+             \n@@ -1 +1 @@\n-ok\n+no"
+        );
+    }
+
+    #[test]
+    fn test_capturing_writer() {
+        let mut writer = CapturingWriter::new(Vec::new(), true);
+        writer.write_all(b"ab").unwrap();
+        assert_eq!(writer.captured(), b"ab");
+        assert_eq!(writer.writer, b"ab");
+        let mut writer = CapturingWriter::new(Vec::new(), false);
+        writer.write_all(b"ab").unwrap();
+        assert_eq!(writer.captured(), b"");
+    }
+}
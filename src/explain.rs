@@ -0,0 +1,337 @@
+//! The `explain` subcommand, which walks through a program line by line,
+//! describing what each of its commands does in plain English, and how its
+//! loops fit together, for teaching from real programs.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::Write;
+use std::fs;
+use std::process::ExitCode;
+
+use bft_types::loops::LoopNode;
+use bft_types::ops::Operation;
+use bft_types::tokens::Token;
+use bft_types::{BfProgram, InstructionInfo};
+
+use crate::cli::{ExplainArgs, ExplainFormat};
+use crate::config::Settings;
+use crate::load_program;
+
+/// A run of the same command on one line, and what it does.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Note {
+    /// The commands, as written.
+    commands: String,
+    description: String,
+}
+
+/// A line of the program with commands on it, and what they do.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Line {
+    number: usize,
+    /// The line as written, comments and all.
+    source: String,
+    notes: Vec<Note>,
+}
+
+/// A loop of the program, numbered in the order the loops start.
+#[derive(Debug, Clone, Copy)]
+struct Loop {
+    number: usize,
+    /// The number of the loop this one is nested directly within.
+    parent: Option<usize>,
+    start: InstructionInfo,
+    end: InstructionInfo,
+}
+
+/// Numbers the loops in the order they start, keyed by the positions of both
+/// of their brackets.
+fn number_loops(
+    program: &BfProgram,
+    nodes: &[LoopNode],
+    parent: Option<usize>,
+    loops: &mut HashMap<usize, Loop>,
+) {
+    for node in nodes {
+        let number = loops.len() / 2 + 1;
+        let found = Loop {
+            number,
+            parent,
+            start: program.instructions()[node.start()],
+            end: program.instructions()[node.end()],
+        };
+        loops.insert(node.start(), found);
+        loops.insert(node.end(), found);
+        number_loops(program, node.children(), Some(number), loops);
+    }
+}
+
+/// Describes `count` of the operation in a row, along with where its loop
+/// goes if it is a bracket.
+fn describe(
+    operation: Operation,
+    count: usize,
+    found: Option<&Loop>,
+) -> String {
+    let mut description = match count {
+        1 => operation.to_string(),
+        _ => format!("{} x {}", count, operation),
+    };
+    match (operation, found) {
+        (Operation::StartLoop, Some(found)) => {
+            let _ = write!(description, " This is loop {}", found.number);
+            if let Some(parent) = found.parent {
+                let _ = write!(description, ", nested in loop {}", parent);
+            }
+            let _ = write!(
+                description,
+                ", which runs while the current cell is not zero, up to the ] \
+                at line {}, column {}.",
+                found.end.line(),
+                found.end.column()
+            );
+        }
+        (Operation::EndLoop, Some(found)) => {
+            let _ = write!(
+                description,
+                " It ends loop {}: while the current cell is not zero, the \
+                program goes back to the [ at line {}, column {}.",
+                found.number,
+                found.start.line(),
+                found.start.column()
+            );
+        }
+        _ => {}
+    }
+    description
+}
+
+/// Walks through the lines of the program which have commands on them.
+fn explain(program: &BfProgram) -> Vec<Line> {
+    let mut loops = HashMap::new();
+    number_loops(program, &program.loops(), None, &mut loops);
+    let source: String =
+        program.tokens().map(|token| token.to_string()).collect();
+    let source: Vec<&str> = source.split('\n').collect();
+
+    let mut lines: Vec<Line> = Vec::new();
+    // The run of commands being described: the position of its first
+    // command, the command itself, and how many of it there are.
+    let mut run: Option<(usize, InstructionInfo, usize)> = None;
+    let mut finish = |run: Option<(usize, InstructionInfo, usize)>| {
+        let Some((position, instruction, count)) = run else {
+            return;
+        };
+        let operation = instruction.operation();
+        let note = Note {
+            commands: operation.to_char().to_string().repeat(count),
+            description: describe(operation, count, loops.get(&position)),
+        };
+        match lines.last_mut() {
+            Some(line) if line.number == instruction.line() => {
+                line.notes.push(note)
+            }
+            _ => lines.push(Line {
+                number: instruction.line(),
+                source: source
+                    .get(instruction.line() - 1)
+                    .map_or("", |line| line.trim_end_matches('\r'))
+                    .to_string(),
+                notes: vec![note],
+            }),
+        }
+    };
+    for (position, token) in program.tokens().scan(0, |position, token| {
+        let at = *position;
+        *position += matches!(token, Token::Command(_)) as usize;
+        Some((at, token))
+    }) {
+        match (token, &mut run) {
+            (Token::Command(instruction), Some((_, first, count)))
+                if first.operation() == instruction.operation()
+                    && first.line() == instruction.line()
+                    && !matches!(
+                        instruction.operation(),
+                        Operation::StartLoop | Operation::EndLoop
+                    ) =>
+            {
+                *count += 1;
+            }
+            (Token::Command(instruction), _) => {
+                finish(run.replace((position, instruction, 1)));
+            }
+            (Token::Comment(_), _) => finish(run.take()),
+        }
+    }
+    finish(run);
+    lines
+}
+
+/// Lays out the walkthrough as plain text, with the commands of each line
+/// lined up beside what they do.
+fn render_text(program: &BfProgram, lines: &[Line]) -> String {
+    let mut text = format!("{}\n", program.filename().display());
+    for line in lines {
+        let _ = writeln!(text, "\nline {}: {}", line.number, line.source);
+        let width = line
+            .notes
+            .iter()
+            .map(|note| note.commands.chars().count())
+            .max()
+            .unwrap_or(0);
+        for note in &line.notes {
+            let _ = writeln!(
+                text,
+                "  {:<width$}  {}",
+                note.commands, note.description
+            );
+        }
+    }
+    text
+}
+
+/// Lays out the walkthrough as Markdown, with a table for each line.
+fn render_markdown(program: &BfProgram, lines: &[Line]) -> String {
+    let cell = |text: &str| text.replace('|', "\\|");
+    let mut text = format!("# {}\n", program.filename().display());
+    for line in lines {
+        let _ = write!(
+            text,
+            "\n## Line {}\n\n```text\n{}\n```\n\n\
+            | Commands | What they do |\n| --- | --- |\n",
+            line.number, line.source
+        );
+        for note in &line.notes {
+            let _ = writeln!(
+                text,
+                "| `{}` | {} |",
+                cell(&note.commands),
+                cell(&note.description)
+            );
+        }
+    }
+    text
+}
+
+/// Escapes the characters which mean something in HTML.
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Lays out the walkthrough as a standalone HTML page, with a section for
+/// each line.
+fn render_html(program: &BfProgram, lines: &[Line]) -> String {
+    let title = escape(&program.filename().display().to_string());
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+        <title>{0}</title>\n</head>\n<body>\n<h1>{0}</h1>\n",
+        title
+    );
+    for line in lines {
+        let _ = write!(
+            html,
+            "<section>\n<h2>Line {}</h2>\n<pre><code>{}</code></pre>\n<dl>\n",
+            line.number,
+            escape(&line.source)
+        );
+        for note in &line.notes {
+            let _ = writeln!(
+                html,
+                "<dt><code>{}</code></dt>\n<dd>{}</dd>",
+                escape(&note.commands),
+                escape(&note.description)
+            );
+        }
+        html.push_str("</dl>\n</section>\n");
+    }
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+/// Runs the `explain` subcommand.
+pub(crate) fn run_explain(
+    args: &ExplainArgs,
+) -> Result<ExitCode, Box<dyn Error>> {
+    let settings = Settings::from_args(&args.run)?;
+    let program = load_program(&args.filename, &settings)?;
+    let lines = explain(&program);
+    let text = match args.format {
+        ExplainFormat::Text => render_text(&program, &lines),
+        ExplainFormat::Markdown => render_markdown(&program, &lines),
+        ExplainFormat::Html => render_html(&program, &lines),
+    };
+    match &args.output {
+        Some(path) => fs::write(path, text)?,
+        None => print!("{}", text),
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{explain, render_html, render_markdown, render_text};
+    use bft_types::BfProgram;
+
+    fn program() -> BfProgram {
+        let source = "add: ++[>+<-]\n\n[ clear ] -- <";
+        BfProgram::new(source.to_string(), "add.bf").unwrap()
+    }
+
+    #[test]
+    fn test_explain() {
+        let lines = explain(&program());
+        let numbers: Vec<_> = lines.iter().map(|line| line.number).collect();
+        assert_eq!(numbers, [1, 3]);
+        assert_eq!(lines[0].source, "add: ++[>+<-]");
+        let commands: Vec<_> = lines[1]
+            .notes
+            .iter()
+            .map(|note| note.commands.as_str())
+            .collect();
+        assert_eq!(commands, ["[", "]", "--", "<"]);
+        assert_eq!(
+            lines[0].notes[0].description,
+            "2 x + : increases the value stored at the current cell by 1."
+        );
+        assert_eq!(
+            lines[0].notes[1].description,
+            "[ : Starts a loop. This is loop 1, which runs while the current \
+            cell is not zero, up to the ] at line 1, column 13."
+        );
+        assert_eq!(
+            lines[1].notes[1].description,
+            "] : Ends a loop. It ends loop 2: while the current cell is not \
+            zero, the program goes back to the [ at line 3, column 1."
+        );
+    }
+
+    #[test]
+    fn test_nested_loops() {
+        let program = BfProgram::new("[[-]]".to_string(), "n.bf").unwrap();
+        let lines = explain(&program);
+        assert!(lines[0].notes[1]
+            .description
+            .contains("This is loop 2, nested in loop 1,"));
+    }
+
+    #[test]
+    fn test_render() {
+        let program = program();
+        let lines = explain(&program);
+        let text = render_text(&program, &lines);
+        assert!(text.starts_with("add.bf\n\nline 1: add: ++[>+<-]\n  ++  2 x"));
+        assert!(text.contains("\n  --  2 x - : decreases"));
+
+        let markdown = render_markdown(&program, &lines);
+        assert!(markdown.contains("## Line 3\n\n```text\n[ clear ] -- <\n```"));
+        assert!(markdown.contains("| `<` | < : moves the data pointer"));
+
+        let html = render_html(&program, &lines);
+        assert!(html.contains("<pre><code>add: ++[&gt;+&lt;-]</code></pre>"));
+        assert!(html.contains("<dt><code>&lt;</code></dt>"));
+        assert!(html.ends_with("</body>\n</html>\n"));
+    }
+}
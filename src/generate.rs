@@ -0,0 +1,70 @@
+//! The `completions` and `manpage` subcommands, which generate supporting
+//! files for bft from the definition of its command line.
+
+use std::error::Error;
+use std::fs;
+use std::io::stdout;
+use std::path::Path;
+use std::process::ExitCode;
+
+use clap::{crate_name, CommandFactory};
+use clap_complete::Shell;
+use clap_mangen::Man;
+
+use crate::cli::Args;
+
+/// Writes the completion script for the given shell, either to stdout or into
+/// the given directory.
+pub(crate) fn run_completions(
+    shell: Shell,
+    dir: Option<&Path>,
+) -> Result<ExitCode, Box<dyn Error>> {
+    let mut command = Args::command();
+    match dir {
+        Some(dir) => {
+            fs::create_dir_all(dir)?;
+            let path = clap_complete::generate_to(
+                shell,
+                &mut command,
+                crate_name!(),
+                dir,
+            )?;
+            eprintln!("Wrote {}", path.display());
+        }
+        None => clap_complete::generate(
+            shell,
+            &mut command,
+            crate_name!(),
+            &mut stdout(),
+        ),
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Writes the man page for bft to stdout, or the man pages for bft and all of
+/// its subcommands into the given directory.
+pub(crate) fn run_manpage(
+    dir: Option<&Path>,
+) -> Result<ExitCode, Box<dyn Error>> {
+    let command = Args::command();
+    match dir {
+        Some(dir) => {
+            fs::create_dir_all(dir)?;
+            clap_mangen::generate_to(command, dir)?;
+            eprintln!("Wrote man pages to {}", dir.display());
+        }
+        None => Man::new(command).render(&mut stdout())?,
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cli::Args;
+    use clap::CommandFactory;
+
+    #[test]
+    fn test_cli_definition() {
+        Args::command().debug_assert();
+    }
+}
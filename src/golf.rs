@@ -0,0 +1,265 @@
+//! The `golf` subcommand, which scores a program for code golf: how many
+//! commands it has, how small it can be written, and which parts of it the
+//! size goes on.
+
+use std::error::Error;
+use std::fmt::Write as _;
+use std::io::Write;
+use std::ops::Range;
+use std::process::ExitCode;
+
+use bft_types::loops::LoopNode;
+use bft_types::ops::Operation;
+use bft_types::BfProgram;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+
+use crate::cli::GolfArgs;
+use crate::config::Settings;
+use crate::load_program;
+
+/// The sizes a program is scored on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Score {
+    /// The number of command characters in the source.
+    commands: usize,
+    /// The length of the program with its comments and any commands which
+    /// cannot have an effect removed.
+    minified: usize,
+    /// The length of the minified program once gzipped.
+    gzipped: usize,
+}
+
+impl Score {
+    fn new(program: &BfProgram) -> Result<Self, Box<dyn Error>> {
+        let minified = minify(program);
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+        encoder.write_all(minified.as_bytes())?;
+        Ok(Self {
+            commands: program.instructions().len(),
+            minified: minified.len(),
+            gzipped: encoder.finish()?.len(),
+        })
+    }
+}
+
+/// Whether the two operations undo each other when run one after the other.
+fn cancels(a: Operation, b: Operation) -> bool {
+    matches!(
+        (a, b),
+        (Operation::IncrementByte, Operation::DecrementByte)
+            | (Operation::DecrementByte, Operation::IncrementByte)
+            | (Operation::IncrementPointer, Operation::DecrementPointer)
+            | (Operation::DecrementPointer, Operation::IncrementPointer)
+    )
+}
+
+/// Writes the program with only its commands, dropping pairs of commands
+/// which undo each other, such as `+-`, and any loops at the very start of the
+/// program, which never run as every cell starts at zero.
+fn minify(program: &BfProgram) -> String {
+    let mut kept: Vec<Operation> = Vec::new();
+    for instruction in program.iter() {
+        let operation = instruction.operation();
+        match kept.last() {
+            Some(&last) if cancels(last, operation) => {
+                kept.pop();
+            }
+            _ => kept.push(operation),
+        }
+    }
+    let mut start = 0;
+    while kept.get(start) == Some(&Operation::StartLoop) {
+        let mut depth = 0;
+        for (position, operation) in kept.iter().enumerate().skip(start) {
+            match operation {
+                Operation::StartLoop => depth += 1,
+                Operation::EndLoop => depth -= 1,
+                _ => {}
+            }
+            if depth == 0 {
+                start = position + 1;
+                break;
+            }
+        }
+        if depth != 0 {
+            // An unmatched bracket in a lazily validated program.
+            break;
+        }
+    }
+    kept[start..]
+        .iter()
+        .map(|operation| operation.to_char())
+        .collect()
+}
+
+/// Writes the score, along with the change from the baseline if there is one.
+fn render_score(rendered: &mut String, score: Score, baseline: Option<Score>) {
+    let sizes = [
+        ("commands", score.commands, baseline.map(|b| b.commands)),
+        ("minified", score.minified, baseline.map(|b| b.minified)),
+        ("gzipped", score.gzipped, baseline.map(|b| b.gzipped)),
+    ];
+    for (name, size, baseline) in sizes {
+        let _ = write!(rendered, "{}: {}", name, size);
+        if let Some(baseline) = baseline {
+            let _ = write!(
+                rendered,
+                " ({:+} from {})",
+                size as i64 - baseline as i64,
+                baseline
+            );
+        }
+        rendered.push('\n');
+    }
+}
+
+/// Writes a line for a section of the program, saying where it starts and
+/// how many of the program's commands are in it.
+fn render_section(
+    rendered: &mut String,
+    program: &BfProgram,
+    kind: &str,
+    span: Range<usize>,
+    depth: usize,
+) {
+    let instruction = program.instructions()[span.start];
+    let location = format!("{}:{}", instruction.line(), instruction.column());
+    let share = span.len() as f64 / program.instructions().len() as f64;
+    // Nested sections are indented within the first column, so that the
+    // columns after it still line up.
+    let kind = format!("{:indent$}{}", "", kind, indent = 2 * depth);
+    let _ = writeln!(
+        rendered,
+        "{:<12} {:<9} {:>6} {:>5.1}%",
+        kind,
+        location,
+        span.len(),
+        share * 100.0
+    );
+}
+
+/// Writes the loops and the code between them, down to the given depth of
+/// nesting, covering the given span of the program.
+fn render_sections(
+    rendered: &mut String,
+    program: &BfProgram,
+    loops: &[LoopNode],
+    span: Range<usize>,
+    depth: usize,
+    max_depth: usize,
+) {
+    let mut position = span.start;
+    for node in loops {
+        if node.start() > position {
+            render_section(
+                rendered,
+                program,
+                "code",
+                position..node.start(),
+                depth,
+            );
+        }
+        render_section(rendered, program, "loop", node.span(), depth);
+        if depth + 1 < max_depth {
+            render_sections(
+                rendered,
+                program,
+                node.children(),
+                node.body(),
+                depth + 1,
+                max_depth,
+            );
+        }
+        position = node.span().end;
+    }
+    if span.end > position {
+        render_section(rendered, program, "code", position..span.end, depth);
+    }
+}
+
+/// Lays out the score of the program, followed by which sections of it the
+/// commands go on.
+fn render(
+    program: &BfProgram,
+    score: Score,
+    baseline: Option<Score>,
+    depth: usize,
+) -> String {
+    let mut rendered = String::new();
+    let _ = writeln!(rendered, "{}", program.filename().display());
+    render_score(&mut rendered, score, baseline);
+    if !program.instructions().is_empty() && depth > 0 {
+        rendered.push_str("sections:\n");
+        render_sections(
+            &mut rendered,
+            program,
+            &program.loops(),
+            0..program.instructions().len(),
+            1,
+            depth + 1,
+        );
+    }
+    rendered.trim_end().to_string()
+}
+
+/// Runs the `golf` subcommand.
+pub(crate) fn run_golf(args: &GolfArgs) -> Result<ExitCode, Box<dyn Error>> {
+    let settings = Settings::from_args(&args.run)?;
+    let program = load_program(&args.filename, &settings)?;
+    let baseline = match &args.baseline {
+        Some(path) => Some(Score::new(&load_program(path, &settings)?)?),
+        None => None,
+    };
+    println!(
+        "{}",
+        render(&program, Score::new(&program)?, baseline, args.depth)
+    );
+    Ok(ExitCode::SUCCESS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{minify, render, Score};
+    use bft_types::BfProgram;
+
+    #[test]
+    fn test_minify() {
+        let program = BfProgram::new(
+            "[comment loop] [+] start: ++-+ >< <>> [-<+>] done".to_string(),
+            "golf.bf",
+        )
+        .unwrap();
+        assert_eq!(minify(&program), "++>[-<+>]");
+    }
+
+    #[test]
+    fn test_render() {
+        let program =
+            BfProgram::new("++\n[>+[-]<-]\n.".to_string(), "golf.bf").unwrap();
+        let score = Score {
+            commands: 12,
+            minified: 12,
+            gzipped: 33,
+        };
+        let baseline = Score {
+            commands: 14,
+            minified: 12,
+            gzipped: 30,
+        };
+        assert_eq!(
+            render(&program, score, Some(baseline), 2),
+            "golf.bf\n\
+             commands: 12 (-2 from 14)\n\
+             minified: 12 (+0 from 12)\n\
+             gzipped: 33 (+3 from 30)\n\
+             sections:\n\
+             \x20 code       1:1            2  16.7%\n\
+             \x20 loop       2:1            9  75.0%\n\
+             \x20   code     2:2            2  16.7%\n\
+             \x20   loop     2:4            3  25.0%\n\
+             \x20   code     2:7            2  16.7%\n\
+             \x20 code       3:1            1   8.3%"
+        );
+    }
+}
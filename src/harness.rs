@@ -0,0 +1,179 @@
+//! Running programs against in-memory input, for the subcommands which need
+//! to inspect how a run went rather than just passing its output along.
+
+use std::io::Cursor;
+use std::mem::discriminant;
+
+use bft_interp::compiled::CompiledProgram;
+use bft_interp::{CellKind, VirtualMachine};
+use bft_types::vm_error::VirtualMachineError;
+use bft_types::BfProgram;
+
+use crate::config::{CellWidth, Settings};
+
+/// The step limit used by subcommands which run programs many times when none
+/// is given, so that programs which never halt are still caught.
+pub(crate) const DEFAULT_STEP_LIMIT: u64 = 1_000_000;
+
+/// How a run of a program came to an end.
+#[derive(Debug)]
+pub(crate) enum Outcome {
+    /// The program ran to completion.
+    Halted,
+    /// The program was stopped by the step limit.
+    StepLimit,
+    /// The program failed with an error.
+    Error(VirtualMachineError),
+}
+
+impl Outcome {
+    /// Whether two outcomes are of the same kind. Errors are the same kind if
+    /// they are the same variant, regardless of where in the program they
+    /// happened.
+    pub(crate) fn same_kind(&self, other: &Outcome) -> bool {
+        match (self, other) {
+            (Outcome::Halted, Outcome::Halted) => true,
+            (Outcome::StepLimit, Outcome::StepLimit) => true,
+            (Outcome::Error(a), Outcome::Error(b)) => {
+                discriminant(a) == discriminant(b)
+            }
+            _ => false,
+        }
+    }
+
+    /// Describes the outcome for reports.
+    pub(crate) fn describe(&self) -> String {
+        match self {
+            Outcome::Halted => "halted".to_string(),
+            Outcome::StepLimit => "hit the step limit".to_string(),
+            Outcome::Error(err) => format!("failed: {}", err),
+        }
+    }
+}
+
+/// Everything observed about a single run of a program.
+#[derive(Debug)]
+pub(crate) struct Execution {
+    /// The bytes written by the program.
+    pub(crate) output: Vec<u8>,
+    /// How the run came to an end.
+    pub(crate) outcome: Outcome,
+    /// The tape of the Virtual Machine once the run had ended.
+    pub(crate) tape: Vec<u32>,
+    /// The number of steps the run took.
+    pub(crate) steps: u64,
+}
+
+/// A program made ready to be run many times with `execute_prepared`, so
+/// that it is only optimized once.
+pub(crate) enum Prepared<'p> {
+    /// The program, compiled with the passes the settings select.
+    Compiled(Box<CompiledProgram>),
+    /// A program which cannot be compiled, as it has unmatched brackets and
+    /// was parsed lazily, so is interpreted on each run, only failing if it
+    /// reaches one of them.
+    Interpreted(&'p BfProgram),
+}
+
+/// Makes the program ready to be run many times using the settings.
+pub(crate) fn prepare<'p>(
+    program: &'p BfProgram,
+    settings: &Settings,
+) -> Prepared<'p> {
+    match settings.compile(program) {
+        Ok(compiled) => Prepared::Compiled(Box::new(compiled)),
+        Err(_) => Prepared::Interpreted(program),
+    }
+}
+
+/// What is observed of a run once it has ended.
+fn observe<T>(
+    vm: &VirtualMachine<'_, T>,
+    result: Result<(), VirtualMachineError>,
+) -> (Outcome, Vec<u32>, u64)
+where
+    T: CellKind + Default + Clone + Copy + PartialEq + Into<u32>,
+{
+    let outcome = match result {
+        Ok(()) => Outcome::Halted,
+        Err(VirtualMachineError::StepLimitExceeded { .. }) => {
+            Outcome::StepLimit
+        }
+        Err(err) => Outcome::Error(err),
+    };
+    let tape = vm.tape().iter().map(|cell| (*cell).into()).collect();
+    (outcome, tape, vm.steps())
+}
+
+/// Runs the prepared program with the given input, using cells of type `T`.
+fn execute_as<T>(
+    prepared: &Prepared<'_>,
+    settings: &Settings,
+    input: &[u8],
+    step_limit: u64,
+) -> Execution
+where
+    T: CellKind + Default + Clone + Copy + PartialEq + Into<u32>,
+{
+    let mut output = Vec::new();
+    let (outcome, tape, steps) = match prepared {
+        Prepared::Compiled(compiled) => {
+            let mut bytes = input.iter().copied();
+            let mut vm = settings
+                .spawn_vm::<T>(
+                    compiled,
+                    move || bytes.next(),
+                    |byte| output.push(byte),
+                )
+                .with_step_limit(step_limit);
+            let result = vm.run();
+            observe(&vm, result)
+        }
+        Prepared::Interpreted(program) => {
+            let mut vm = settings
+                .virtual_machine::<T>(program)
+                .with_step_limit(step_limit);
+            let result = vm.interpret(&mut Cursor::new(input), &mut output);
+            observe(&vm, result)
+        }
+    };
+    Execution {
+        output,
+        outcome,
+        tape,
+        steps,
+    }
+}
+
+/// Runs the prepared program with the given input, using the given settings,
+/// apart from the step limit which is always given explicitly. The settings
+/// must be those the program was prepared with.
+pub(crate) fn execute_prepared(
+    prepared: &Prepared<'_>,
+    settings: &Settings,
+    input: &[u8],
+    step_limit: u64,
+) -> Execution {
+    match settings.cell_width {
+        CellWidth::U8 => {
+            execute_as::<u8>(prepared, settings, input, step_limit)
+        }
+        CellWidth::U16 => {
+            execute_as::<u16>(prepared, settings, input, step_limit)
+        }
+        CellWidth::U32 => {
+            execute_as::<u32>(prepared, settings, input, step_limit)
+        }
+    }
+}
+
+/// Runs the program with the given input, using the given settings, apart
+/// from the step limit which is always given explicitly.
+pub(crate) fn execute(
+    program: &BfProgram,
+    settings: &Settings,
+    input: &[u8],
+    step_limit: u64,
+) -> Execution {
+    execute_prepared(&prepare(program, settings), settings, input, step_limit)
+}
@@ -0,0 +1,300 @@
+//! The `serve-http` subcommand, which runs programs sent to it over HTTP, for
+//! playgrounds and other services which run programs on behalf of others.
+//!
+//! Programs are sent to `POST /run` as JSON, along with their input and any
+//! limits to run them under:
+//!
+//! ```text
+//! {"program": ",[.,]", "input": "hi", "limits": {"max_steps": 1000}}
+//! ```
+//!
+//! The response gives how the run ended, the output and some statistics, or
+//! the error the program failed with:
+//!
+//! ```text
+//! {"status": "halted", "output": "hi", "truncated": false,
+//!  "stats": {"instructions": 5, "steps": 8, "cells": 30000}}
+//! ```
+//!
+//! Every request is sandboxed by the limits of the server, which are those
+//! of the flags it was started with: a request may ask for a lower step
+//! limit, fewer cells or less output, but never more. A run which reaches its
+//! limit on output stops there, with the status `output_limit`.
+
+use std::error::Error;
+use std::io::Read;
+use std::process::ExitCode;
+use std::thread;
+
+use bft_types::vm_error::VirtualMachineError;
+use bft_types::BfProgram;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use crate::cli::ServeHttpArgs;
+use crate::config::Settings;
+use crate::harness::{execute, Outcome, DEFAULT_STEP_LIMIT};
+use crate::parse_options;
+
+/// The largest request body accepted, in bytes.
+const MAX_BODY: u64 = 1024 * 1024;
+
+/// The most output a run may write when no limit is given, in bytes.
+const DEFAULT_MAX_OUTPUT: u64 = 64 * 1024;
+
+/// The limits a request asks to run its program under.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Limits {
+    max_steps: Option<u64>,
+    cells: Option<usize>,
+    max_output: Option<u64>,
+}
+
+/// The body of a request to `/run`.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RunRequest {
+    program: String,
+    #[serde(default)]
+    input: String,
+    #[serde(default)]
+    limits: Limits,
+}
+
+/// Describes an error as JSON, with where it happened if it happened at an
+/// instruction.
+fn error_json(err: &VirtualMachineError) -> Value {
+    let mut error = json!({ "kind": err.kind(), "message": err.to_string() });
+    if let Some((line, column)) = err.location() {
+        error["line"] = json!(line);
+        error["column"] = json!(column);
+    }
+    error
+}
+
+/// An error with the request itself, rather than the program in it.
+fn bad_request(message: impl ToString) -> (u16, Value) {
+    let error =
+        json!({ "kind": "bad_request", "message": message.to_string() });
+    (400, json!({ "error": error }))
+}
+
+/// Runs the program in the body of a request to `/run`, within the limits
+/// of the server, giving the status code and body of the response.
+fn run_request(body: &[u8], settings: &Settings) -> (u16, Value) {
+    let request: RunRequest = match serde_json::from_slice(body) {
+        Ok(request) => request,
+        Err(err) => return bad_request(err),
+    };
+    let limits = request.limits;
+    let mut settings = settings.clone();
+    settings.cells = limits
+        .cells
+        .map_or(settings.cells, |cells| cells.clamp(1, settings.cells));
+    let ceiling = settings.max_steps.unwrap_or(DEFAULT_STEP_LIMIT);
+    let step_limit = limits.max_steps.map_or(ceiling, |max| max.min(ceiling));
+    let ceiling = settings
+        .max_output_bytes
+        .map_or(DEFAULT_MAX_OUTPUT, |max| max.min(DEFAULT_MAX_OUTPUT));
+    let max_output = limits.max_output.map_or(ceiling, |max| max.min(ceiling));
+    // The limits go into the sandbox, which also keeps partial evaluation
+    // within them when the program is optimized.
+    settings.max_steps = Some(step_limit);
+    settings.max_output_bytes = Some(max_output);
+    let options = parse_options(&settings);
+    let program = match BfProgram::new_with_options(
+        request.program,
+        "run.bf",
+        &options,
+    ) {
+        Ok(program) => program,
+        Err(err) => {
+            return (422, json!({ "error": error_json(&err) }));
+        }
+    };
+    let execution =
+        execute(&program, &settings, request.input.as_bytes(), step_limit);
+    let truncated = matches!(
+        execution.outcome,
+        Outcome::Error(VirtualMachineError::OutputLimitExceeded { .. })
+    );
+    let mut body = json!({
+        "output": String::from_utf8_lossy(&execution.output),
+        "truncated": truncated,
+        "stats": {
+            "instructions": program.instructions().len(),
+            "steps": execution.steps,
+            "cells": execution.tape.len(),
+        },
+    });
+    match execution.outcome {
+        Outcome::Halted => body["status"] = json!("halted"),
+        Outcome::StepLimit => body["status"] = json!("step_limit"),
+        Outcome::Error(_) if truncated => {
+            body["status"] = json!("output_limit");
+        }
+        Outcome::Error(err) => {
+            body["status"] = json!("failed");
+            body["error"] = error_json(&err);
+        }
+    }
+    (200, body)
+}
+
+/// Responds to a single request.
+fn handle(mut request: Request, settings: &Settings) {
+    let (status, body) = match (request.method(), request.url()) {
+        (Method::Post, "/run") => {
+            let mut body = Vec::new();
+            let read = request
+                .as_reader()
+                .take(MAX_BODY + 1)
+                .read_to_end(&mut body);
+            match read {
+                Err(err) => bad_request(err),
+                Ok(_) if body.len() as u64 > MAX_BODY => {
+                    let message =
+                        format!("bodies may be at most {} bytes", MAX_BODY);
+                    let error =
+                        json!({ "kind": "too_large", "message": message });
+                    (413, json!({ "error": error }))
+                }
+                Ok(_) => run_request(&body, settings),
+            }
+        }
+        (_, "/run") => (
+            405,
+            json!({ "error": {
+                "kind": "method_not_allowed",
+                "message": "programs are run with POST",
+            }}),
+        ),
+        (_, url) => (
+            404,
+            json!({ "error": {
+                "kind": "not_found",
+                "message": format!("there is nothing at {}", url),
+            }}),
+        ),
+    };
+    let content_type = Header::from_bytes("Content-Type", "application/json")
+        .expect("the header is valid");
+    let response = Response::from_string(body.to_string())
+        .with_status_code(status)
+        .with_header(content_type);
+    // The client hanging up is no reason to stop serving others.
+    let _ = request.respond(response);
+}
+
+/// Turns an address given as `:port` into one on every interface.
+fn bind_address(addr: &str) -> String {
+    match addr.strip_prefix(':') {
+        Some(port) => format!("0.0.0.0:{}", port),
+        None => addr.to_string(),
+    }
+}
+
+/// Runs the `serve-http` subcommand.
+pub(crate) fn run_serve_http(
+    args: &ServeHttpArgs,
+) -> Result<ExitCode, Box<dyn Error>> {
+    let settings = Settings::from_args(&args.run)?;
+    let server = Server::http(bind_address(&args.addr))
+        .map_err(|err| format!("cannot listen on {}: {}", args.addr, err))?;
+    eprintln!("Listening on {}", server.server_addr());
+    thread::scope(|scope| {
+        for _ in 0..args.workers.max(1) {
+            scope.spawn(|| {
+                while let Ok(request) = server.recv() {
+                    handle(request, &settings);
+                }
+            });
+        }
+    });
+    Ok(ExitCode::SUCCESS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{bind_address, run_request};
+    use crate::config::settings;
+    use serde_json::{json, Value};
+
+    fn run(flags: &[&str], request: Value) -> (u16, Value) {
+        run_request(request.to_string().as_bytes(), &settings(flags))
+    }
+
+    #[test]
+    fn test_run_request() {
+        let request = json!({ "program": ",[.,]", "input": "hi" });
+        let (status, body) = run(&["--eof", "zero"], request);
+        assert_eq!(status, 200);
+        assert_eq!(body["status"], "halted");
+        assert_eq!(body["output"], "hi");
+        assert_eq!(body["stats"]["steps"], 8);
+
+        let request = json!({ "program": "+\n[.]", "limits": {
+            "max_steps": 50, "max_output": 3, "cells": 2,
+        }});
+        let (status, body) = run(&[], request);
+        assert_eq!((status, &body["status"]), (200, &json!("output_limit")));
+        assert_eq!(
+            (&body["output"], &body["truncated"]),
+            (&json!("\u{1}\u{1}\u{1}"), &json!(true))
+        );
+        assert_eq!(body["stats"]["cells"], 2);
+        let request = json!({ "program": "+\n[]", "limits": {
+            "max_steps": 50, "max_output": 3,
+        }});
+        let (_, body) = run(&[], request);
+        assert_eq!(body["status"], "step_limit");
+
+        // Programs which are run ahead of time keep to the limits too.
+        let request = json!({ "program": "++++++++[>++++++++<-]>+.+.+.+.",
+            "limits": { "max_output": 2 }});
+        let (_, body) = run(&["-O3"], request);
+        assert_eq!(
+            (&body["status"], &body["output"]),
+            (&json!("output_limit"), &json!("AB"))
+        );
+        let request = json!({ "program": "++++++++[>++++++++<-]>+.+.+.+.",
+            "limits": { "max_steps": 5 }});
+        let (_, body) = run(&["-O3"], request);
+        assert_eq!(
+            (&body["status"], &body["output"]),
+            (&json!("step_limit"), &json!(""))
+        );
+
+        // A request cannot raise the limits of the server.
+        let request =
+            json!({ "program": "+[]", "limits": { "max_steps": 900 }});
+        let (_, body) = run(&["--max-steps", "100"], request);
+        assert_eq!(body["stats"]["steps"], 100);
+    }
+
+    #[test]
+    fn test_errors() {
+        let (status, body) = run(&[], json!({ "program": "+<" }));
+        assert_eq!(status, 200);
+        assert_eq!(body["status"], "failed");
+        assert_eq!(body["error"]["kind"], "invalid_head_position");
+        assert_eq!(body["error"]["column"], 2);
+
+        let (status, body) = run(&[], json!({ "program": "+\n]" }));
+        assert_eq!(status, 422);
+        assert_eq!(body["error"]["kind"], "unmatched_bracket");
+        assert_eq!(body["error"]["line"], 2);
+
+        let (status, body) = run(&[], json!({ "source": "+" }));
+        assert_eq!(status, 400);
+        assert_eq!(body["error"]["kind"], "bad_request");
+    }
+
+    #[test]
+    fn test_bind_address() {
+        assert_eq!(bind_address(":8080"), "0.0.0.0:8080");
+        assert_eq!(bind_address("127.0.0.1:80"), "127.0.0.1:80");
+    }
+}
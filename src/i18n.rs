@@ -0,0 +1,334 @@
+//! Localized messages, so that errors, diagnostics and the narration of
+//! `bft teach` can be read in the language of the class using them.
+//!
+//! The English text of each message stays where it is written, and is what is
+//! shown unless the `i18n` feature is enabled and there is a catalog for the
+//! language asked for. Catalogs are Fluent files in `locales/`, built into the
+//! binary, which translate messages by id. A message missing from a catalog
+//! is shown in English, so a catalog can be filled in a little at a time.
+//!
+//! The language is the first of `BFT_LANG`, `LC_ALL`, `LC_MESSAGES` and
+//! `LANG` which is set, such as `es` or `es_ES.UTF-8`. The errors of the
+//! library stay as data, and are rendered here from their kind and fields.
+
+use std::fmt::Display;
+
+use bft_types::ops::Operation;
+use bft_types::vm_error::VirtualMachineError;
+
+/// The catalogs built into the binary, by the language they translate into.
+#[cfg(feature = "i18n")]
+const CATALOGS: &[(&str, &str)] = &[("es", include_str!("../locales/es.ftl"))];
+
+/// The message with the given id, in the language asked for, with the
+/// arguments filled in. Falls back to the English text when the message has
+/// not been translated into it.
+pub(crate) fn localize(
+    id: &str,
+    args: &[(&str, &dyn Display)],
+    english: impl FnOnce() -> String,
+) -> String {
+    #[cfg(feature = "i18n")]
+    if let Some(message) =
+        CATALOG.with(|catalog| catalog.as_ref()?.format(id, args))
+    {
+        return message;
+    }
+    #[cfg(not(feature = "i18n"))]
+    let _ = (id, args);
+    english()
+}
+
+/// The message for an error of the interpreter, in the language asked for.
+/// Its id is `error-` followed by the kind of error, and its arguments are
+/// the fields of the error.
+pub(crate) fn error_message(err: &VirtualMachineError) -> String {
+    let id = format!("error-{}", err.kind().replace('_', "-"));
+    let args = error_args(err);
+    let args: Vec<(&str, &dyn Display)> = args
+        .iter()
+        .map(|(name, value)| (*name, value as &dyn Display))
+        .collect();
+    localize(&id, &args, || err.to_string())
+}
+
+/// The fields of an error, by the names its message uses for them.
+fn error_args(err: &VirtualMachineError) -> Vec<(&'static str, String)> {
+    let mut args = Vec::new();
+    if let Some((line, column)) = err.location() {
+        args.push(("line", line.to_string()));
+        args.push(("column", column.to_string()));
+    }
+    match err {
+        VirtualMachineError::InvalidHeadPosition {
+            operation,
+            filename,
+            position,
+            tape_length,
+            ..
+        } => args.extend([
+            ("operation", operation.to_char().to_string()),
+            ("filename", filename.clone()),
+            ("position", position.to_string()),
+            ("tape_length", tape_length.to_string()),
+        ]),
+        VirtualMachineError::StepLimitExceeded {
+            filename, limit, ..
+        }
+        | VirtualMachineError::GasExhausted {
+            filename, limit, ..
+        }
+        | VirtualMachineError::OutputLimitExceeded {
+            filename, limit, ..
+        } => args.extend([
+            ("filename", filename.clone()),
+            ("limit", limit.to_string()),
+        ]),
+        VirtualMachineError::TimeLimitExceeded {
+            filename, limit_ms, ..
+        } => args.extend([
+            ("filename", filename.clone()),
+            ("limit_ms", limit_ms.to_string()),
+        ]),
+        VirtualMachineError::InputExhausted { filename, read, .. } => args
+            .extend([
+                ("filename", filename.clone()),
+                ("read", read.to_string()),
+            ]),
+        VirtualMachineError::TapeGrowthRefused {
+            filename, cells, ..
+        } => args.extend([
+            ("filename", filename.clone()),
+            ("cells", cells.to_string()),
+        ]),
+        VirtualMachineError::UnknownExtension { name, filename, .. } => args
+            .extend([
+                ("name", name.to_string()),
+                ("filename", filename.clone()),
+            ]),
+        VirtualMachineError::ExtensionFailed {
+            name,
+            filename,
+            message,
+            ..
+        } => args.extend([
+            ("name", name.to_string()),
+            ("filename", filename.clone()),
+            ("message", message.clone()),
+        ]),
+        VirtualMachineError::SyscallsDisabled { filename, .. } => {
+            args.push(("filename", filename.clone()))
+        }
+        VirtualMachineError::SyscallDenied { id, filename, .. }
+        | VirtualMachineError::UnknownSyscall { id, filename, .. } => args
+            .extend([("id", id.to_string()), ("filename", filename.clone())]),
+        VirtualMachineError::IOError(err) => {
+            args.push(("message", err.to_string()))
+        }
+        VirtualMachineError::UnmatchedBracket { bracket, .. } => {
+            args.push(("bracket", bracket.to_string()))
+        }
+        VirtualMachineError::StrayCharacter { character, .. } => {
+            args.push(("character", format!("{:?}", character)))
+        }
+        VirtualMachineError::InvalidAssembly {
+            filename, message, ..
+        } => args.extend([
+            ("filename", filename.clone()),
+            ("message", message.clone()),
+        ]),
+        VirtualMachineError::UnterminatedComment { .. } => {}
+        VirtualMachineError::ProgramTooLarge {
+            filename,
+            size,
+            limit,
+        } => args.extend([
+            ("filename", filename.clone()),
+            ("size", size.to_string()),
+            ("limit", limit.to_string()),
+        ]),
+        VirtualMachineError::NestingTooDeep { depth, limit, .. } => args
+            .extend([
+                ("depth", depth.to_string()),
+                ("limit", limit.to_string()),
+            ]),
+        VirtualMachineError::UnbalancedNodes { position } => {
+            args.push(("position", position.to_string()))
+        }
+    }
+    args
+}
+
+/// What an operation does, in the language asked for.
+pub(crate) fn describe_operation(operation: Operation) -> String {
+    let id = match operation {
+        Operation::IncrementPointer => "operation-increment-pointer",
+        Operation::DecrementPointer => "operation-decrement-pointer",
+        Operation::IncrementByte => "operation-increment-byte",
+        Operation::DecrementByte => "operation-decrement-byte",
+        Operation::OutputByte => "operation-output-byte",
+        Operation::InputByte => "operation-input-byte",
+        Operation::StartLoop => "operation-start-loop",
+        Operation::EndLoop => "operation-end-loop",
+        Operation::Extension(_) => "operation-extension",
+    };
+    localize(id, &[("name", &operation.to_char())], || {
+        operation.to_string()
+    })
+}
+
+#[cfg(feature = "i18n")]
+thread_local! {
+    /// The catalog for the language asked for, if there is one.
+    static CATALOG: Option<Catalog> = requested_language()
+        .and_then(|language| Catalog::for_language(&language));
+}
+
+/// The language asked for by the environment, if any.
+#[cfg(feature = "i18n")]
+fn requested_language() -> Option<String> {
+    ["BFT_LANG", "LC_ALL", "LC_MESSAGES", "LANG"]
+        .iter()
+        .find_map(|name| std::env::var(name).ok().filter(|v| !v.is_empty()))
+}
+
+/// The messages of one language.
+#[cfg(feature = "i18n")]
+struct Catalog {
+    bundle: fluent::FluentBundle<fluent::FluentResource>,
+}
+
+#[cfg(feature = "i18n")]
+impl Catalog {
+    /// The catalog for a language such as `es`, `es-MX` or `es_ES.UTF-8`, if
+    /// one is built in.
+    fn for_language(language: &str) -> Option<Self> {
+        let tag = language.split(['.', '@']).next()?.replace('_', "-");
+        let id: unic_langid::LanguageIdentifier = tag.parse().ok()?;
+        let (_, source) = CATALOGS
+            .iter()
+            .find(|(language, _)| id.language.as_str() == *language)?;
+        // The catalogs are checked by the tests, so any error in one would
+        // only lose the messages it affects.
+        let resource = fluent::FluentResource::try_new(source.to_string())
+            .unwrap_or_else(|(resource, _)| resource);
+        let mut bundle = fluent::FluentBundle::new(vec![id]);
+        bundle.set_use_isolating(false);
+        bundle.add_resource_overriding(resource);
+        Some(Self { bundle })
+    }
+
+    /// The message with the given id and arguments, if it is translated.
+    fn format(
+        &self,
+        id: &str,
+        args: &[(&str, &dyn Display)],
+    ) -> Option<String> {
+        let pattern = self.bundle.get_message(id)?.value()?;
+        let values: Vec<(&str, String)> = args
+            .iter()
+            .map(|(name, value)| (*name, value.to_string()))
+            .collect();
+        let mut fluent_args = fluent::FluentArgs::new();
+        for (name, value) in &values {
+            fluent_args
+                .set(*name, fluent::FluentValue::try_number(value.as_str()));
+        }
+        let mut errors = Vec::new();
+        let message = self.bundle.format_pattern(
+            pattern,
+            Some(&fluent_args),
+            &mut errors,
+        );
+        errors.is_empty().then(|| message.into_owned())
+    }
+}
+
+#[cfg(all(test, feature = "i18n"))]
+mod tests {
+    use super::{error_args, Catalog, CATALOGS};
+    use bft_types::BfProgram;
+    use std::fmt::Display;
+
+    #[test]
+    fn test_catalogs_parse() {
+        for (language, source) in CATALOGS {
+            assert!(
+                fluent::FluentResource::try_new(source.to_string()).is_ok(),
+                "the {} catalog does not parse",
+                language
+            );
+        }
+    }
+
+    #[test]
+    fn test_for_language() {
+        assert!(Catalog::for_language("es").is_some());
+        assert!(Catalog::for_language("es_ES.UTF-8").is_some());
+        assert!(Catalog::for_language("es-MX").is_some());
+        assert!(Catalog::for_language("fr").is_none());
+        assert!(Catalog::for_language("C").is_none());
+    }
+
+    #[test]
+    fn test_format() {
+        let catalog = Catalog::for_language("es").unwrap();
+        assert_eq!(
+            catalog.format("teach-halted", &[("steps", &1)]).unwrap(),
+            "terminó tras 1 paso"
+        );
+        assert_eq!(
+            catalog.format("teach-halted", &[("steps", &8)]).unwrap(),
+            "terminó tras 8 pasos"
+        );
+        assert_eq!(catalog.format("no-such-message", &[]), None);
+        // A message missing an argument is left to the English text.
+        assert_eq!(catalog.format("teach-halted", &[]), None);
+    }
+
+    #[test]
+    fn test_every_error_is_translated() {
+        let catalog = Catalog::for_language("es").unwrap();
+        let kinds = [
+            "invalid_head_position",
+            "step_limit_exceeded",
+            "gas_exhausted",
+            "time_limit_exceeded",
+            "output_limit_exceeded",
+            "input_exhausted",
+            "tape_growth_refused",
+            "unknown_extension",
+            "extension_failed",
+            "syscalls_disabled",
+            "syscall_denied",
+            "unknown_syscall",
+            "io_error",
+            "unmatched_bracket",
+            "stray_character",
+            "invalid_assembly",
+            "unterminated_comment",
+            "program_too_large",
+            "nesting_too_deep",
+            "unbalanced_nodes",
+        ];
+        for kind in kinds {
+            let id = format!("error-{}", kind.replace('_', "-"));
+            assert!(catalog.bundle.has_message(&id), "{} is missing", id);
+        }
+    }
+
+    #[test]
+    fn test_error_args() {
+        let catalog = Catalog::for_language("es").unwrap();
+        let err = BfProgram::new("+\n [".to_string(), "open.bf").unwrap_err();
+        let args = error_args(&err);
+        let args: Vec<(&str, &dyn Display)> = args
+            .iter()
+            .map(|(name, value)| (*name, value as &dyn Display))
+            .collect();
+        assert_eq!(
+            catalog.format("error-unmatched-bracket", &args).unwrap(),
+            "[ sin pareja en la línea 2 columna 2"
+        );
+    }
+}
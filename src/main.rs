@@ -3,63 +3,208 @@
 #![deny(missing_docs)]
 #![cfg(not(tarpaulin_include))]
 
-use bft_interp::VirtualMachine;
+use bft_interp::syscall;
+use bft_types::options::{BracketValidation, CommentPolicy, ParseOptions};
+use bft_types::vm_error::VirtualMachineError;
 use bft_types::BfProgram;
 use clap::{crate_name, Parser};
 use std::error::Error;
-use std::io::{stdin, stdout, Write};
+use std::path::Path;
 use std::process::ExitCode;
 
+mod analyze;
+mod asm;
+mod assembly;
+mod bundle;
+mod cache;
+mod check;
 mod cli;
+mod compile;
+mod config;
+mod debugger;
+mod decompile;
+mod diff;
+mod equiv;
+mod expect;
+mod explain;
+mod generate;
+mod golf;
+mod harness;
+#[cfg(feature = "http")]
+mod http;
+mod i18n;
+mod manifest;
+mod optimize;
+mod output_diff;
+mod pack;
+mod patch;
+mod pipeline;
+#[cfg(feature = "plugins")]
+mod plugin;
+mod profile;
+mod project;
+mod replay;
+mod report;
+mod run;
+mod scaffold;
+mod selfhost;
+mod serve;
+mod shrink;
+mod stats;
+mod steplog;
+mod teach;
+mod timings;
+mod trace;
+mod workspace;
 
-/// A wrapper around Write to ensure that a new line is written.
-struct WriterWrapper<T> {
-    writer: T,
-    last_byte: u8,
-}
+use config::Settings;
 
-impl<T> Write for WriterWrapper<T>
-where
-    T: Write,
-{
-    /// Wrapped write command which keeps aa eye on the last byte.
-    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
-        if let Some(b) = buf.last() {
-            self.last_byte = *b;
-        }
-        self.writer.write(buf)
+/// The options to parse programs with, according to the settings given.
+pub(crate) fn parse_options(settings: &Settings) -> ParseOptions {
+    let bracket_validation = if settings.lazy_brackets {
+        BracketValidation::Lazy
+    } else {
+        BracketValidation::Strict
+    };
+    let comment_policy = if settings.strict {
+        CommentPolicy::Delimited
+    } else {
+        CommentPolicy::Anywhere
+    };
+    let mut parse_options = ParseOptions::new()
+        .bracket_validation(bracket_validation)
+        .comments(comment_policy)
+        .max_nesting(settings.max_nesting);
+    if let Some(limit) = settings.max_program_size {
+        parse_options = parse_options.max_program_size(limit);
     }
-
-    /// Wrapped flush method, no real difference from the original flush method.
-    fn flush(&mut self) -> std::io::Result<()> {
-        self.writer.flush()
+    if settings.clock.is_some() {
+        parse_options = parse_options.extension(syscall::SYSCALL);
     }
+    parse_options
 }
 
-impl<T> Drop for WriterWrapper<T> {
-    /// When the wrapper ends, a new line is added if there is not one already.
-    fn drop(&mut self) {
-        if self.last_byte != b'\n' {
-            println!()
+/// Loads the program from the given file, parsing it according to the
+/// settings given. A program which looks like it is not Brainfuck is warned
+/// about, or refused with `--strict-source`. Programs written in the dialect
+/// of a plugin are translated into Brainfuck first.
+pub(crate) fn load_program(
+    filename: &Path,
+    settings: &Settings,
+) -> Result<BfProgram, Box<dyn Error>> {
+    #[cfg(feature = "plugins")]
+    if let Some(source) = plugin::translate(filename)? {
+        let options = parse_options(settings);
+        return Ok(BfProgram::new_with_options(source, filename, &options)?);
+    }
+    let program =
+        BfProgram::from_file_with_options(filename, &parse_options(settings))?;
+    let profile = program.profile();
+    if !profile.looks_like_brainfuck(settings.min_command_ratio) {
+        let percent = format!("{:.1}", profile.command_ratio() * 100.0);
+        let message = i18n::localize(
+            "load-not-brainfuck",
+            &[("percent", &percent), ("filename", &filename.display())],
+            || {
+                format!(
+                    "only {}% of {} is Brainfuck commands, is it the right \
+                    file?",
+                    percent,
+                    filename.display()
+                )
+            },
+        );
+        if settings.strict_source {
+            return Err(message.into());
         }
+        eprintln!("{}: warning: {}", crate_name!(), message);
     }
+    Ok(program)
 }
 
 /// Main entry point of the program. This takes the arguments passed in via the
-/// CLI and interprets the program.
-fn run_bft(arguments: &cli::Args) -> Result<(), Box<dyn Error>> {
-    let bf_program = BfProgram::from_file(&arguments.filename)?;
-    let mut interpreter = VirtualMachine::<u8>::new(
-        &bf_program,
-        arguments.cells,
-        arguments.extensible,
-    );
-    let mut writer_wrapper = WriterWrapper {
-        writer: stdout(),
-        last_byte: 0u8,
-    };
-    interpreter.interpret(&mut stdin(), &mut writer_wrapper)?;
-    Ok(())
+/// CLI and runs the chosen subcommand.
+fn run_bft(arguments: &cli::Args) -> Result<ExitCode, Box<dyn Error>> {
+    match &arguments.command {
+        Some(cli::Command::Run {
+            filename,
+            run,
+            run_only,
+        }) => run::run_program(filename, run, run_only),
+        Some(cli::Command::Equiv(equiv_args)) => equiv::run_equiv(equiv_args),
+        Some(cli::Command::Diff(diff_args)) => diff::run_diff(diff_args),
+        Some(cli::Command::Patch(patch_args)) => patch::run_patch(patch_args),
+        Some(cli::Command::Pipe(pipe_args)) => pipeline::run_pipe(pipe_args),
+        Some(cli::Command::Shrink(shrink_args)) => {
+            shrink::run_shrink(shrink_args)
+        }
+        Some(cli::Command::Compile(compile_args)) => {
+            compile::run_compile(compile_args)
+        }
+        Some(cli::Command::Bundle(bundle_args)) => {
+            bundle::run_bundle(bundle_args)
+        }
+        Some(cli::Command::Pack(pack_args)) => pack::run_pack(pack_args),
+        Some(cli::Command::Unpack(unpack_args)) => {
+            pack::run_unpack(unpack_args)
+        }
+        Some(cli::Command::Optimize(optimize_args)) => {
+            optimize::run_optimize(optimize_args)
+        }
+        Some(cli::Command::Stats(stats_args)) => stats::run_stats(stats_args),
+        Some(cli::Command::Id(id_args)) => stats::run_id(id_args),
+        Some(cli::Command::Golf(golf_args)) => golf::run_golf(golf_args),
+        Some(cli::Command::Decompile(decompile_args)) => {
+            decompile::run_decompile(decompile_args)
+        }
+        Some(cli::Command::Explain(explain_args)) => {
+            explain::run_explain(explain_args)
+        }
+        Some(cli::Command::Teach(teach_args)) => teach::run_teach(teach_args),
+        Some(cli::Command::Asm { command }) => asm::run_asm(command),
+        Some(cli::Command::Replay(replay_args)) => {
+            replay::run_replay(replay_args)
+        }
+        Some(cli::Command::ReplaySteps(replay_steps_args)) => {
+            steplog::run_replay_steps(replay_steps_args)
+        }
+        Some(cli::Command::Analyze(analyze_args)) => {
+            analyze::run_analyze(analyze_args)
+        }
+        Some(cli::Command::Check(check_args)) => check::run_check(check_args),
+        Some(cli::Command::Selfhost(selfhost_args)) => {
+            selfhost::run_selfhost(selfhost_args)
+        }
+        Some(cli::Command::ServeStdio(serve_args)) => {
+            serve::run_serve_stdio(serve_args)
+        }
+        #[cfg(feature = "http")]
+        Some(cli::Command::ServeHttp(serve_args)) => {
+            http::run_serve_http(serve_args)
+        }
+        #[cfg(feature = "plugins")]
+        Some(cli::Command::Plugins(plugins_args)) => {
+            plugin::run_plugins(plugins_args)
+        }
+        Some(cli::Command::Build(build_args)) => project::run_build(build_args),
+        Some(cli::Command::Test(project_args)) => {
+            project::run_test(project_args)
+        }
+        Some(cli::Command::New(new_args)) => scaffold::run_new(new_args),
+        Some(cli::Command::Completions { shell, dir }) => {
+            generate::run_completions(*shell, dir.as_deref())
+        }
+        Some(cli::Command::Manpage { dir }) => {
+            generate::run_manpage(dir.as_deref())
+        }
+        None => match &arguments.filename {
+            Some(filename) => {
+                run::run_program(filename, &arguments.run, &arguments.run_only)
+            }
+            // Clap requires the filename when there is no subcommand.
+            None => unreachable!("no filename or subcommand given"),
+        },
+    }
 }
 
 #[cfg(not(tarpaulin_include))]
@@ -69,9 +214,13 @@ fn main() -> ExitCode {
 
     // Deal with the error that could arise from executing the program
     match run_bft(&arguments) {
-        Ok(_) => ExitCode::SUCCESS,
+        Ok(code) => code,
         Err(err) => {
-            println!("{}: {}", crate_name!(), err);
+            let message = match err.downcast_ref::<VirtualMachineError>() {
+                Some(err) => i18n::error_message(err),
+                None => err.to_string(),
+            };
+            println!("{}: {}", crate_name!(), message);
             ExitCode::FAILURE
         }
     }
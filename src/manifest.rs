@@ -0,0 +1,186 @@
+//! The JSON manifest written by `--emit-manifest`, recording exactly how a
+//! program was run so that CI systems and benchmarks can reproduce it.
+
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::time::Duration;
+
+use bft_types::digest::Fnv1a;
+use bft_types::vm_error::VirtualMachineError;
+use bft_types::BfProgram;
+use serde::Serialize;
+
+use crate::config::Settings;
+
+/// A wrapper around Write which hashes and counts everything written through
+/// it.
+pub(crate) struct HashingWriter<W> {
+    writer: W,
+    hash: Fnv1a,
+    len: u64,
+}
+
+impl<W> HashingWriter<W> {
+    pub(crate) fn new(writer: W) -> Self {
+        Self {
+            writer,
+            hash: Fnv1a::new(),
+            len: 0,
+        }
+    }
+
+    /// The hash of everything written so far.
+    pub(crate) fn hash(&self) -> Fnv1a {
+        self.hash
+    }
+
+    /// The number of bytes written so far.
+    pub(crate) fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// The writer being written to.
+    pub(crate) fn get_ref(&self) -> &W {
+        &self.writer
+    }
+}
+
+impl<W> Write for HashingWriter<W>
+where
+    W: Write,
+{
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.writer.write(buf)?;
+        self.hash.update(&buf[..written]);
+        self.len += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct ProgramRecord {
+    path: String,
+    hash: String,
+    instructions: usize,
+}
+
+#[derive(Debug, Serialize)]
+struct SettingsRecord {
+    cells: usize,
+    cell_width: String,
+    extensible: bool,
+    eof: String,
+    newlines: String,
+    lazy_brackets: bool,
+    max_steps: Option<u64>,
+    max_output_bytes: Option<u64>,
+    max_input_bytes: Option<u64>,
+    passes: Option<String>,
+    opt_level: u8,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+enum ExitRecord {
+    Success,
+    Error { message: String },
+}
+
+#[derive(Debug, Serialize)]
+struct OutputRecord {
+    bytes: u64,
+    hash: String,
+}
+
+/// A record of a single run of a program.
+#[derive(Debug, Serialize)]
+pub(crate) struct Manifest {
+    program: ProgramRecord,
+    settings: SettingsRecord,
+    duration_secs: f64,
+    steps: u64,
+    exit: ExitRecord,
+    output: Option<OutputRecord>,
+}
+
+impl Manifest {
+    /// Starts a manifest for running the program with the given settings.
+    pub(crate) fn new(program: &BfProgram, settings: &Settings) -> Self {
+        Self {
+            program: ProgramRecord {
+                path: program.filename().display().to_string(),
+                hash: program.digest().to_string(),
+                instructions: program.instructions().len(),
+            },
+            settings: SettingsRecord {
+                cells: settings.cells,
+                cell_width: settings.cell_width.to_string(),
+                extensible: settings.extensible,
+                eof: settings.eof.to_string(),
+                newlines: settings.newlines.to_string(),
+                lazy_brackets: settings.lazy_brackets,
+                max_steps: settings.max_steps,
+                max_output_bytes: settings.max_output_bytes,
+                max_input_bytes: settings.max_input_bytes,
+                passes: settings.passes.clone(),
+                opt_level: settings.opt_level,
+            },
+            duration_secs: 0.0,
+            steps: 0,
+            exit: ExitRecord::Success,
+            output: None,
+        }
+    }
+
+    /// Records how the run went.
+    pub(crate) fn finished<W>(
+        mut self,
+        duration: Duration,
+        steps: u64,
+        result: &Result<(), VirtualMachineError>,
+        output: &HashingWriter<W>,
+    ) -> Self {
+        self.duration_secs = duration.as_secs_f64();
+        self.steps = steps;
+        self.exit = match result {
+            Ok(()) => ExitRecord::Success,
+            Err(err) => ExitRecord::Error {
+                message: err.to_string(),
+            },
+        };
+        self.output = Some(OutputRecord {
+            bytes: output.len,
+            hash: output.hash.hex(),
+        });
+        self
+    }
+
+    /// Writes the manifest to the given file as JSON.
+    pub(crate) fn write_to(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(&mut writer, self)?;
+        writeln!(writer)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HashingWriter;
+    use std::io::Write;
+
+    #[test]
+    fn test_hashing_writer() {
+        let mut writer = HashingWriter::new(Vec::new());
+        writer.write_all(b"a").unwrap();
+        assert_eq!(writer.len, 1);
+        assert_eq!(writer.hash.hex(), "fnv1a64:af63dc4c8601ec8c");
+        assert_eq!(writer.writer, b"a");
+    }
+}
@@ -0,0 +1,439 @@
+//! The `optimize` subcommand, which rewrites a program as plain Brainfuck by
+//! way of the optimized intermediate representation, so that it behaves the
+//! same but is usually shorter, and faster on interpreters without an
+//! optimizer of their own.
+
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fs;
+use std::process::ExitCode;
+
+use bft_interp::ir::{IrOp, IrProgram};
+use bft_interp::optimizer::{DeadLoop, Pipeline, Unreachable};
+use bft_types::ops::Operation;
+use bft_types::tokens::Token;
+use bft_types::BfProgram;
+
+use crate::cli::OptimizeArgs;
+use crate::config::{pipeline, Settings};
+use crate::load_program;
+
+/// What a block of ops does to a single cell: whether it is cleared, and then
+/// how much is added to it.
+#[derive(Debug, Default, Clone, Copy)]
+struct CellChange {
+    clear: bool,
+    add: i64,
+}
+
+/// Writes Brainfuck for the intermediate representation.
+///
+/// The adds and clears between two ops which need the head in place, such as
+/// loops and input and output, are gathered up by the cell they change, and
+/// written out in one sweep across the cells, so that the head moves no
+/// further than it has to. Positions are relative to where the head was at
+/// the start of the block.
+struct Emitter {
+    code: String,
+    /// The number of bits in each cell, which amounts wrap around at.
+    bits: u32,
+    /// Where the head really is.
+    cursor: isize,
+    /// Where the program expects the head to be.
+    head: isize,
+    /// The changes to each cell made by the block so far.
+    changes: BTreeMap<isize, CellChange>,
+}
+
+impl Emitter {
+    fn new(bits: u32) -> Self {
+        Self {
+            code: String::new(),
+            bits,
+            cursor: 0,
+            head: 0,
+            changes: BTreeMap::new(),
+        }
+    }
+
+    fn repeat(&mut self, command: char, times: u64) {
+        self.code
+            .extend(std::iter::repeat_n(command, times as usize));
+    }
+
+    /// Moves the head to the position.
+    fn seek(&mut self, position: isize) {
+        let by = position - self.cursor;
+        if by < 0 {
+            self.repeat('<', by.unsigned_abs() as u64);
+        } else {
+            self.repeat('>', by as u64);
+        }
+        self.cursor = position;
+    }
+
+    /// Adds the amount to the cell at the head, going whichever way around
+    /// the cell's range of values is shorter.
+    fn add(&mut self, amount: i64) {
+        let modulus = 1i128 << self.bits;
+        let up = (amount as i128).rem_euclid(modulus);
+        if up <= modulus - up {
+            self.repeat('+', up as u64);
+        } else {
+            self.repeat('-', (modulus - up) as u64);
+        }
+    }
+
+    /// Records a change to the cell at the offset from the head.
+    fn change(&mut self, offset: isize, clear: bool, amount: i64) {
+        let change = self.changes.entry(self.head + offset).or_default();
+        if clear {
+            *change = CellChange {
+                clear: true,
+                add: 0,
+            };
+        }
+        change.add += amount;
+    }
+
+    /// Writes out the changes made by the block, sweeping across the cells in
+    /// whichever direction leaves the head closest to where it should end up,
+    /// if anywhere.
+    fn flush(&mut self, end: Option<isize>) {
+        let changes = std::mem::take(&mut self.changes);
+        let (Some((&low, _)), Some((&high, _))) =
+            (changes.first_key_value(), changes.last_key_value())
+        else {
+            return;
+        };
+        let distance = |from: isize, to: Option<isize>| {
+            to.map_or(0, |to| (to - from).unsigned_abs())
+        };
+        let upwards = (self.cursor - low).unsigned_abs() + distance(high, end);
+        let downwards =
+            (self.cursor - high).unsigned_abs() + distance(low, end);
+        let mut cells: Vec<(isize, CellChange)> = changes.into_iter().collect();
+        if downwards < upwards {
+            cells.reverse();
+        }
+        for (position, change) in cells {
+            self.seek(position);
+            if change.clear {
+                self.code.push_str("[-]");
+            }
+            self.add(change.add);
+        }
+    }
+
+    /// Writes the commands, with the head where the program expects it.
+    fn push(&mut self, commands: &str) {
+        self.flush(Some(self.head));
+        self.seek(self.head);
+        self.code.push_str(commands);
+        self.cursor = 0;
+        self.head = 0;
+    }
+
+    /// Writes the comment on lines of its own, after writing out the changes
+    /// made so far so that they stay on the side of the comment they were on.
+    fn comment(&mut self, text: &str) {
+        self.push("");
+        if !self.code.is_empty() && !self.code.ends_with('\n') {
+            self.code.push('\n');
+        }
+        self.code.push_str(text);
+        self.code.push('\n');
+    }
+
+    fn emit(&mut self, op: &IrOp) -> Result<(), Box<dyn Error>> {
+        match op {
+            IrOp::Add(amount) => self.change(0, false, (*amount).into()),
+            IrOp::Move(by) => self.head += by,
+            IrOp::Output => self.push("."),
+            IrOp::Input => self.push(","),
+            IrOp::LoopStart => self.push("["),
+            IrOp::LoopEnd => self.push("]"),
+            IrOp::Clear => self.change(0, true, 0),
+            IrOp::AddAt(offset, amount) => {
+                self.change(*offset, false, (*amount).into())
+            }
+            IrOp::ClearAt(offset) => self.change(*offset, true, 0),
+            IrOp::AddRange(offset, len, amount) => {
+                for cell in 0..*len as isize {
+                    self.change(offset + cell, false, (*amount).into());
+                }
+            }
+            IrOp::ClearRange(offset, len) => {
+                for cell in 0..*len as isize {
+                    self.change(offset + cell, true, 0);
+                }
+            }
+            IrOp::CopyLoop(targets) => {
+                self.push("[-");
+                for (offset, factor) in targets {
+                    self.change(*offset, false, (*factor).into());
+                }
+                self.push("]");
+            }
+            IrOp::ScanRight(step) => {
+                self.push("[");
+                self.repeat('>', *step as u64);
+                self.code.push(']');
+            }
+            IrOp::ScanLeft(step) => {
+                self.push("[");
+                self.repeat('<', *step as u64);
+                self.code.push(']');
+            }
+            IrOp::Extension(name) => self.push(&name.to_string()),
+            IrOp::OutputBytes(_) | IrOp::LoadTape(_) => {
+                return Err("partially evaluated programs cannot be written \
+                    as Brainfuck"
+                    .into());
+            }
+            IrOp::MulAdd(_) => {
+                return Err("multiplications need spare cells to be written \
+                    as Brainfuck"
+                    .into());
+            }
+            IrOp::If | IrOp::Else | IrOp::EndIf => {
+                return Err("branches need flag cells to be written as \
+                    Brainfuck"
+                    .into());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The comments of a program, each with the position of the instruction it
+/// was written before, so that they can be carried through to the optimized
+/// program. Commands are taken out of them, including any extensions the
+/// program uses, so that they stay comments however the output is parsed.
+/// The shebang is left out, as it is written at the top of the output as it
+/// was.
+fn comments(program: &BfProgram) -> Vec<(usize, String)> {
+    let extensions: Vec<char> = program
+        .iter()
+        .filter_map(|instruction| match instruction.operation() {
+            Operation::Extension(name) => Some(name),
+            _ => None,
+        })
+        .collect();
+    let shebang = program.profile().shebang().map_or(0, str::len);
+    let mut comments = Vec::new();
+    let mut position = 0;
+    for token in program.tokens() {
+        match token {
+            Token::Command(_) => position += 1,
+            Token::Comment(comment) => {
+                let text: String = comment
+                    .text()
+                    .get(if position == 0 { shebang } else { 0 }..)
+                    .unwrap_or_default()
+                    .chars()
+                    .filter(|&c| {
+                        Operation::char_to_operation(c).is_none()
+                            && !extensions.contains(&c)
+                    })
+                    .collect();
+                if !text.trim().is_empty() {
+                    comments.push((position, text.trim().to_string()));
+                }
+            }
+        }
+    }
+    comments
+}
+
+/// Writes the program as plain Brainfuck for cells of the given width. Moves
+/// left over at the end of the program are dropped, as they cannot change
+/// what it does.
+///
+/// If the original program is given, its shebang and comments are carried
+/// through, each comment on lines of its own before the code for the
+/// instruction it was written before, or for the next one which was kept.
+pub(crate) fn emit_bf(
+    ir: &IrProgram,
+    bits: u32,
+    original: Option<&BfProgram>,
+) -> Result<String, Box<dyn Error>> {
+    let mut emitter = Emitter::new(bits);
+    let mut comments = original.map(comments).unwrap_or_default();
+    comments.reverse();
+    // Nodes only know the line and column of the instruction they came from.
+    let positions: HashMap<(usize, usize), usize> = original
+        .into_iter()
+        .flat_map(|program| program.iter().enumerate())
+        .map(|(n, instruction)| ((instruction.line(), instruction.column()), n))
+        .collect();
+    // Writes the comments written before the instruction at the position.
+    let mut write_comments = |emitter: &mut Emitter, before: usize| {
+        while let Some((_, text)) =
+            comments.pop_if(|(position, _)| *position <= before)
+        {
+            emitter.comment(&text);
+        }
+    };
+    if let Some(shebang) = original.and_then(|p| p.profile().shebang()) {
+        emitter.code.push_str(shebang);
+        emitter.code.push('\n');
+    }
+    for node in ir.nodes() {
+        let source = node.source();
+        if let Some(&position) =
+            positions.get(&(source.line(), source.column()))
+        {
+            write_comments(&mut emitter, position);
+        }
+        emitter.emit(node.op())?;
+    }
+    emitter.flush(None);
+    // Where the head ends up no longer matters, so it is not moved back for
+    // the comments at the end.
+    emitter.head = emitter.cursor;
+    write_comments(&mut emitter, usize::MAX);
+    if !emitter.code.ends_with('\n') {
+        emitter.code.push('\n');
+    }
+    Ok(emitter.code)
+}
+
+/// Lowers the program and runs the passes given to `--passes` over it, or the
+/// built-in passes, followed by dropping loops which can never run and code
+/// which can never be reached, knowing that the program starts on a fresh tape.
+/// Multiply loops are left as they are, as writing a multiplication back out
+/// needs spare cells to count with, which only the original loop knows of, and
+/// so are if/else idioms, which need their flag cells to branch with.
+pub(crate) fn optimize(
+    program: &BfProgram,
+    settings: &Settings,
+) -> Result<IrProgram, Box<dyn Error>> {
+    let mut pipeline = match &settings.passes {
+        Some(spec) => pipeline(spec)?,
+        None => Pipeline::builtin(),
+    };
+    pipeline.remove("mulloop");
+    pipeline.remove("ifelse");
+    pipeline.push(Box::new(DeadLoop::on_fresh_tape()));
+    pipeline.push(Box::new(Unreachable::on_fresh_tape()));
+    let mut ir = IrProgram::from_program(program)?;
+    pipeline.run(&mut ir);
+    Ok(ir)
+}
+
+/// Runs the `optimize` subcommand.
+pub(crate) fn run_optimize(
+    args: &OptimizeArgs,
+) -> Result<ExitCode, Box<dyn Error>> {
+    let settings = Settings::from_args(&args.run)?;
+    let program = load_program(&args.filename, &settings)?;
+    let ir = optimize(&program, &settings)?;
+    let original = args.keep_comments.then_some(&program);
+    let code = emit_bf(&ir, settings.cell_width.bits(), original)?;
+    match &args.output {
+        Some(path) => fs::write(path, code)?,
+        None => print!("{}", code),
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{emit_bf, optimize};
+    use crate::config::{settings, Settings};
+    use crate::harness::execute;
+    use bft_types::BfProgram;
+
+    fn recompile(source: &str, settings: &Settings) -> String {
+        let program = BfProgram::new(source.to_string(), "in.bf").unwrap();
+        let ir = optimize(&program, settings).unwrap();
+        emit_bf(&ir, settings.cell_width.bits(), None).unwrap()
+    }
+
+    #[test]
+    fn test_emit() {
+        let bytes = settings(&[]);
+        // Loops and clears are dropped while the tape is known to be clear.
+        assert_eq!(
+            recompile("[comment] +++-- >>>++<<< [-]>[-]", &bytes),
+            ">[-]>>++\n"
+        );
+        // Changes to cells are made in one sweep, wherever they were made.
+        assert_eq!(
+            recompile(">,+++-- >>>++<<< [-]>[-] <<++>> .", &bytes),
+            ">,<++>[-]>[-]>>++<<.\n"
+        );
+        assert_eq!(recompile("+[->>+++<<]>[<]-", &bytes), "+[->>+++<<]>[<]-\n");
+        // Changes which are cleared before being read, and code after a loop
+        // which never ends, are dropped.
+        assert_eq!(recompile(">+++<.>[-]-<+[.]>+", &bytes), ".+>[-]-<[.]\n");
+        // Amounts go whichever way around the cell is shorter.
+        assert_eq!(
+            recompile(&"+".repeat(250), &bytes),
+            format!("{}\n", "-".repeat(6))
+        );
+        assert_eq!(
+            recompile(&"+".repeat(250), &settings(&["--cell-width", "16"])),
+            format!("{}\n", "+".repeat(250))
+        );
+    }
+
+    #[test]
+    fn test_keep_comments() {
+        let source = "#!/usr/bin/env -S bft --eof zero\nadd three\n+++\n\
+            move it (a loop) over and print it\n[->+<] >.\n\
+            {the end}\n";
+        let program = BfProgram::new(source.to_string(), "in.bf").unwrap();
+        let settings = settings(&[]);
+        let ir = optimize(&program, &settings).unwrap();
+        assert_eq!(
+            emit_bf(&ir, 8, Some(&program)).unwrap(),
+            "#!/usr/bin/env -S bft --eof zero\nadd three\n+++\n\
+            move it (a loop) over and print it\n[->+<]>.\n{the end}\n"
+        );
+        // Comments in code which was dropped go before the next code kept.
+        let program =
+            BfProgram::new("[never] + once".to_string(), "in.bf").unwrap();
+        let ir = optimize(&program, &settings).unwrap();
+        assert_eq!(
+            emit_bf(&ir, 8, Some(&program)).unwrap(),
+            "never\n+\nonce\n"
+        );
+    }
+
+    #[test]
+    fn test_round_trip() {
+        // Each program, along with the inputs to try it on.
+        let programs: [(&str, &[&[u8]]); 5] = [
+            (include_str!("../bf-programs/hello-world.bf"), &[b""]),
+            (include_str!("../bf-programs/primes.bf"), &[b""]),
+            (include_str!("../bf-programs/cell-width.bf"), &[b""]),
+            (
+                ",[>+>++<<-]>[-<+>]>[>>>]<<<,[.,]",
+                &[b"", b"7\n", b"\x05hello"],
+            ),
+            (">>++++[<++++>-]<[<+>-]+<[>-<[-]]>[-]", &[b""]),
+        ];
+        for width in ["8", "16", "32"] {
+            let settings = settings(&["--cell-width", width, "--eof", "0"]);
+            for (source, inputs) in programs {
+                let original =
+                    BfProgram::new(source.to_string(), "in.bf").unwrap();
+                let optimized =
+                    BfProgram::new(recompile(source, &settings), "out.bf")
+                        .unwrap();
+                assert!(
+                    optimized.instructions().len()
+                        <= original.instructions().len()
+                );
+                for input in inputs.iter().copied() {
+                    let before = execute(&original, &settings, input, 1 << 24);
+                    let after = execute(&optimized, &settings, input, 1 << 24);
+                    assert_eq!(before.output, after.output);
+                    assert!(before.outcome.same_kind(&after.outcome));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,217 @@
+//! Showing how the output of a run differs from what was expected, or from the
+//! output of another run, for `--expect-output`, `bft test` and `bft equiv`.
+//!
+//! Outputs which are text are compared line by line, with any byte which is
+//! not printable shown in hex, so that a stray `\r` or a missing newline is
+//! never hidden. Outputs which are not text are compared byte by byte, and
+//! shown as rows of hex with the offset of each row. Either way only the parts
+//! which differ are shown, as a diff from the first output to the second.
+
+use std::env;
+use std::fmt::Write;
+use std::io::{stdout, IsTerminal};
+
+use crate::diff::{edits, hunks, Hunk};
+
+/// The most bytes shown on each row of a diff of outputs which are not text.
+const HEX_ROW: usize = 16;
+
+/// The escape codes the parts of a diff are colored with.
+const BOLD: &str = "\x1b[1m";
+const CYAN: &str = "\x1b[36m";
+const RED: &str = "\x1b[31m";
+const GREEN: &str = "\x1b[32m";
+const RESET: &str = "\x1b[0m";
+
+/// Whether diffs written to stdout should be colored: only when it is a
+/// terminal, and `NO_COLOR` is not set.
+pub(crate) fn color_enabled() -> bool {
+    stdout().is_terminal() && env::var_os("NO_COLOR").is_none()
+}
+
+/// Describes how the second output differs from the first, with the outputs
+/// named by the labels, coloring the diff if asked to.
+pub(crate) fn output_diff(
+    first: &[u8],
+    second: &[u8],
+    labels: [&str; 2],
+    color: bool,
+) -> String {
+    let mut diff = Diff {
+        text: String::new(),
+        color,
+    };
+    diff.line(BOLD, &format!("--- {}", labels[0]));
+    diff.line(BOLD, &format!("+++ {}", labels[1]));
+    if is_text(first) && is_text(second) {
+        diff.lines(first, second, labels);
+    } else {
+        diff.bytes(first, second);
+    }
+    diff.text
+}
+
+/// Whether the output reads as text: UTF-8, without any control characters
+/// other than newlines, carriage returns and tabs.
+fn is_text(output: &[u8]) -> bool {
+    std::str::from_utf8(output).is_ok_and(|text| {
+        !text
+            .chars()
+            .any(|c| c.is_control() && !matches!(c, '\n' | '\r' | '\t'))
+    })
+}
+
+/// Shows a line of text without its newline, with everything which is not
+/// printable in hex.
+fn show_line(line: &[u8]) -> String {
+    let line = line.strip_suffix(b"\n").unwrap_or(line);
+    String::from_utf8_lossy(line)
+        .chars()
+        .map(|c| match c {
+            c if c.is_control() => format!("\\x{:02x}", c as u32),
+            c => c.to_string(),
+        })
+        .collect()
+}
+
+/// A diff being written.
+struct Diff {
+    text: String,
+    color: bool,
+}
+
+impl Diff {
+    /// Writes a line of the diff, in the color if colors are used.
+    fn line(&mut self, color: &str, line: &str) {
+        if !self.text.is_empty() {
+            self.text.push('\n');
+        }
+        if self.color && !color.is_empty() {
+            write!(self.text, "{}{}{}", color, line, RESET).unwrap();
+        } else {
+            self.text.push_str(line);
+        }
+    }
+
+    /// Writes the header of a change, with where it starts in each output.
+    fn header(&mut self, hunk: &Hunk, start: impl Fn(usize) -> String) {
+        let header = format!(
+            "@@ -{} +{} @@",
+            start(hunk.removed.start),
+            start(hunk.added.start)
+        );
+        self.line(CYAN, &header);
+    }
+
+    /// Writes the lines of text which differ, with the line each change
+    /// starts at.
+    fn lines(&mut self, first: &[u8], second: &[u8], labels: [&str; 2]) {
+        let split = |output: &[u8]| -> Vec<Vec<u8>> {
+            output
+                .split_inclusive(|&b| b == b'\n')
+                .map(<[u8]>::to_vec)
+                .collect()
+        };
+        let (first, second) = (split(first), split(second));
+        for hunk in hunks(&edits(&first, &second)) {
+            self.header(&hunk, |line| (line + 1).to_string());
+            for (sign, color, lines, label) in [
+                ('-', RED, &first[hunk.removed.clone()], labels[0]),
+                ('+', GREEN, &second[hunk.added.clone()], labels[1]),
+            ] {
+                for line in lines {
+                    self.line(color, &format!("{}{}", sign, show_line(line)));
+                    if !line.ends_with(b"\n") {
+                        let note = format!("\\ No newline at end of {}", label);
+                        self.line("", &note);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Writes the bytes which differ as rows of hex, each with the offset of
+    /// its first byte and the bytes which are printable.
+    fn bytes(&mut self, first: &[u8], second: &[u8]) {
+        for hunk in hunks(&edits(first, second)) {
+            self.header(&hunk, |offset| format!("{:#x}", offset));
+            for (sign, color, output, range) in [
+                ('-', RED, first, hunk.removed.clone()),
+                ('+', GREEN, second, hunk.added.clone()),
+            ] {
+                for start in range.clone().step_by(HEX_ROW) {
+                    let row = &output[start..(start + HEX_ROW).min(range.end)];
+                    let hex: Vec<String> =
+                        row.iter().map(|b| format!("{:02x}", b)).collect();
+                    let ascii: String = row
+                        .iter()
+                        .map(|&b| match b {
+                            b' '..=b'~' => b as char,
+                            _ => '.',
+                        })
+                        .collect();
+                    let line = format!(
+                        "{}{:08x}  {:<width$}  |{}|",
+                        sign,
+                        start,
+                        hex.join(" "),
+                        ascii,
+                        width = HEX_ROW * 3 - 1
+                    );
+                    self.line(color, &line);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_text, output_diff};
+
+    #[test]
+    fn test_is_text() {
+        assert!(is_text(b"caf\xc3\xa9\r\n\tok"));
+        assert!(!is_text(b"\x1b[1m"));
+        assert!(!is_text(b"\xff"));
+    }
+
+    #[test]
+    fn test_text_diff() {
+        let diff =
+            output_diff(b"a\nb\nc\n", b"a\nB\r\nc", ["want", "got"], false);
+        assert_eq!(
+            diff,
+            "--- want\n+++ got\n@@ -2 +2 @@\n-b\n-c\n+B\\x0d\n+c\
+             \n\\ No newline at end of got"
+        );
+        assert_eq!(output_diff(b"same", b"same", ["a", "b"], false), {
+            "--- a\n+++ b"
+        });
+    }
+
+    #[test]
+    fn test_byte_diff() {
+        let first: Vec<u8> = (0..20).collect();
+        let mut second = first.clone();
+        second[17] = b'A';
+        let diff = output_diff(&first, &second, ["a", "b"], false);
+        assert_eq!(
+            diff,
+            "--- a\n+++ b\n@@ -0x11 +0x11 @@\
+             \n-00000011  11                                               |.|\
+             \n+00000011  41                                               |A|"
+        );
+    }
+
+    #[test]
+    fn test_color() {
+        let diff = output_diff(b"a\n", b"b\n", ["a", "b"], true);
+        assert_eq!(
+            diff,
+            "\x1b[1m--- a\x1b[0m\n\x1b[1m+++ b\x1b[0m\
+             \n\x1b[36m@@ -1 +1 @@\x1b[0m\
+             \n\x1b[31m-a\x1b[0m\n\x1b[32m+b\x1b[0m"
+        );
+    }
+}
@@ -0,0 +1,115 @@
+//! The `pack` and `unpack` subcommands, which write programs in the compact
+//! `.bfz` format, and turn them back into plain Brainfuck. Packed programs can
+//! be run like any other, as they are loaded by their extension.
+
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use bft_types::bfz::{self, Metadata};
+use bft_types::options::ParseOptions;
+use bft_types::BfProgram;
+
+use crate::cli::{PackArgs, UnpackArgs};
+use crate::config::Settings;
+use crate::load_program;
+
+/// Where to write the packed program if `--output` is not given: next to the
+/// program, with the extension `.bfz`.
+fn default_output(filename: &Path) -> PathBuf {
+    filename.with_extension(bfz::EXTENSION)
+}
+
+/// Writes only the commands of the program, on a single line.
+fn commands(program: &BfProgram) -> String {
+    let mut code: String = program
+        .iter()
+        .map(|instruction| instruction.operation().compact().to_string())
+        .collect();
+    code.push('\n');
+    code
+}
+
+/// Runs the `pack` subcommand.
+pub(crate) fn run_pack(args: &PackArgs) -> Result<ExitCode, Box<dyn Error>> {
+    let settings = Settings::from_args(&args.run)?;
+    let program = load_program(&args.filename, &settings)?;
+    let metadata =
+        Metadata::for_program(&program, Some(settings.cell_width.bits()));
+    let packed = bfz::pack(&program, &metadata)?;
+    let output = match &args.output {
+        Some(output) => output.clone(),
+        None => default_output(&args.filename),
+    };
+    if output == args.filename {
+        return Err(format!(
+            "{} is already packed, so --output is needed",
+            args.filename.display()
+        )
+        .into());
+    }
+    fs::write(&output, packed)?;
+    eprintln!("Wrote {}", output.display());
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Runs the `unpack` subcommand.
+pub(crate) fn run_unpack(
+    args: &UnpackArgs,
+) -> Result<ExitCode, Box<dyn Error>> {
+    let bytes = fs::read(&args.filename)?;
+    let (program, metadata) =
+        bfz::unpack(&bytes, &args.filename, &ParseOptions::new())?;
+    match metadata.cell_bits() {
+        Some(bits) => eprintln!("Written for {} bit cells", bits),
+        None => eprintln!("Written for cells of unknown width"),
+    }
+    eprintln!("Needs at least {} cells", metadata.min_tape_cells());
+    let code = commands(&program);
+    match &args.output {
+        Some(path) => fs::write(path, code)?,
+        None => print!("{}", code),
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{commands, default_output};
+    use bft_types::bfz::{pack, unpack, Metadata};
+    use bft_types::options::ParseOptions;
+    use bft_types::BfProgram;
+    use std::fs;
+    use std::path::Path;
+
+    #[test]
+    fn test_default_output() {
+        assert_eq!(
+            default_output(Path::new("programs/hello.bf")),
+            Path::new("programs/hello.bfz")
+        );
+    }
+
+    #[test]
+    fn test_load_packed() {
+        let program = BfProgram::new(
+            "Adds two cells\n++>+++[-<+>]<.".to_string(),
+            "add.bf",
+        )
+        .unwrap();
+        let packed = pack(&program, &Metadata::default()).unwrap();
+        let path = std::env::temp_dir()
+            .join(format!("bft-pack-{}.bfz", std::process::id()));
+        fs::write(&path, packed).unwrap();
+        let loaded = BfProgram::from_file(&path);
+        fs::remove_file(&path).unwrap();
+        let loaded = loaded.unwrap();
+        assert_eq!(loaded, program);
+        assert_eq!(commands(&loaded), "++>+++[-<+>]<.\n");
+        // Anything else with the extension is refused, rather than parsed as
+        // source.
+        let options = ParseOptions::new();
+        assert!(unpack(b"++>+++[-<+>]<.", "add.bfz", &options).is_err());
+    }
+}
@@ -0,0 +1,298 @@
+//! The `patch` subcommand, which applies the changes written by `bft diff` to
+//! a program. Only the commands named by each change are removed or added, so
+//! the comments and layout around them are kept as they are, and programs can
+//! be changed by tools without losing the way they were written.
+//!
+//! Each change of a patch starts with a line `@@ <first> <second> @@`, naming
+//! where it is in the first and second programs, as `file:line:column` or
+//! `file:end`. Only where it is in the first program is used. The change then
+//! has lines starting `- ` with the commands it removes, and `+ ` with those
+//! it adds. Any other lines, such as the summary `bft diff` ends with, are
+//! left out.
+
+use std::error::Error;
+use std::fs;
+use std::process::ExitCode;
+
+use bft_types::options::ParseOptions;
+use bft_types::BfProgram;
+
+use crate::cli::PatchArgs;
+use crate::config::Settings;
+use crate::parse_options;
+
+/// Where a change is in the program it applies to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum At {
+    /// At the instruction with the line and column.
+    Instruction(usize, usize),
+    /// After the last instruction.
+    End,
+}
+
+impl At {
+    /// Reads a location written by `bft diff`, as `file:line:column` or
+    /// `file:end`.
+    fn parse(location: &str) -> Option<Self> {
+        if location.ends_with(":end") {
+            return Some(At::End);
+        }
+        let mut parts = location.rsplitn(3, ':');
+        let column = parts.next()?.parse().ok()?;
+        let line = parts.next()?.parse().ok()?;
+        parts.next()?;
+        Some(At::Instruction(line, column))
+    }
+}
+
+/// A change to make to a program: the commands to remove from where it is,
+/// and the commands to add in their place.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Change {
+    at: At,
+    removed: String,
+    added: String,
+}
+
+/// Reads the changes of a patch, in the format `bft diff` writes.
+fn parse_patch(patch: &str) -> Result<Vec<Change>, Box<dyn Error>> {
+    let mut changes: Vec<Change> = Vec::new();
+    let mut in_change = false;
+    for (n, line) in patch.lines().enumerate() {
+        if let Some(header) = line.strip_prefix("@@ ") {
+            let locations = header.strip_suffix(" @@").unwrap_or(header);
+            // Filenames may have spaces in, so the first location ends at the
+            // first space after which it reads as one.
+            let at = locations
+                .match_indices(' ')
+                .find_map(|(space, _)| At::parse(&locations[..space]))
+                .ok_or_else(|| {
+                    format!("line {} of the patch has no location", n + 1)
+                })?;
+            changes.push(Change {
+                at,
+                removed: String::new(),
+                added: String::new(),
+            });
+            in_change = true;
+            continue;
+        }
+        let (sign, commands) = match line.split_at_checked(2) {
+            Some((sign @ ("- " | "+ "), commands)) if in_change => {
+                (sign, commands)
+            }
+            _ => {
+                in_change = false;
+                continue;
+            }
+        };
+        let change = changes.last_mut().expect("a change was started");
+        match sign {
+            "- " => change.removed.push_str(commands),
+            _ => change.added.push_str(commands),
+        }
+    }
+    Ok(changes)
+}
+
+/// Describes where a change is, for errors.
+fn describe(at: At) -> String {
+    match at {
+        At::Instruction(line, column) => format!("{}:{}", line, column),
+        At::End => "the end".to_string(),
+    }
+}
+
+/// Applies the changes to the source of the program, which must come in the
+/// order they are in the program. Commands are added in place of the first
+/// command removed, or straight after the command before the change if there
+/// are none, so that they join the run of commands they follow.
+fn apply(
+    source: &str,
+    program: &BfProgram,
+    changes: &[Change],
+    options: &ParseOptions,
+) -> Result<String, Box<dyn Error>> {
+    let instructions = program.instructions();
+    // Columns count bytes from the start of their line.
+    let mut line_starts = vec![0, 0];
+    line_starts.extend(source.match_indices('\n').map(|(n, _)| n + 1));
+    let offsets: Vec<usize> = instructions
+        .iter()
+        .map(|i| line_starts[i.line()] + i.column() - 1)
+        .collect();
+    let end_of = |index: usize| {
+        let offset = offsets[index];
+        offset + source[offset..].chars().next().map_or(0, char::len_utf8)
+    };
+
+    let mut patched = String::new();
+    let mut copied = 0;
+    let mut next = 0;
+    for change in changes {
+        let index = match change.at {
+            At::Instruction(line, column) => instructions
+                .binary_search_by_key(&(line, column), |i| {
+                    (i.line(), i.column())
+                })
+                .map_err(|_| {
+                    format!(
+                        "there is no instruction at {}",
+                        describe(change.at)
+                    )
+                })?,
+            At::End => instructions.len(),
+        };
+        if index < next {
+            return Err(format!(
+                "the change at {} overlaps the one before it",
+                describe(change.at)
+            )
+            .into());
+        }
+        for c in change.removed.chars().chain(change.added.chars()) {
+            if options.operation_for(c).is_none() {
+                return Err(format!(
+                    "the change at {} has {:?}, which is not a command",
+                    describe(change.at),
+                    c
+                )
+                .into());
+            }
+        }
+        let removed = index..index + change.removed.chars().count();
+        let found = instructions.get(removed.clone()).map(|found| {
+            found.iter().map(|i| i.operation()).eq(change
+                .removed
+                .chars()
+                .filter_map(|c| options.operation_for(c)))
+        });
+        if found != Some(true) {
+            return Err(format!(
+                "the change at {} does not match the program, which does not \
+                have `{}` there",
+                describe(change.at),
+                change.removed
+            )
+            .into());
+        }
+
+        let insert_at = match index {
+            _ if !removed.is_empty() => offsets[index],
+            0 => offsets.first().copied().unwrap_or(source.len()),
+            _ => end_of(index - 1),
+        };
+        patched.push_str(&source[copied..insert_at]);
+        patched.push_str(&change.added);
+        copied = insert_at;
+        for position in removed.clone() {
+            patched.push_str(&source[copied..offsets[position]]);
+            copied = end_of(position);
+        }
+        next = removed.end;
+    }
+    patched.push_str(&source[copied..]);
+    Ok(patched)
+}
+
+/// Runs the `patch` subcommand.
+pub(crate) fn run_patch(args: &PatchArgs) -> Result<ExitCode, Box<dyn Error>> {
+    let settings = Settings::from_args(&args.run)?;
+    let options = parse_options(&settings);
+    let source = fs::read_to_string(&args.filename)?;
+    let program =
+        BfProgram::new_with_options(source.clone(), &args.filename, &options)?;
+    let changes = parse_patch(&fs::read_to_string(&args.patch)?)?;
+    let patched = apply(&source, &program, &changes, &options)?;
+    // The changes may leave brackets unmatched, which is better found now
+    // than when the program is next run.
+    BfProgram::new_with_options(patched.clone(), &args.filename, &options)?;
+    match &args.output {
+        Some(path) => fs::write(path, patched)?,
+        None => print!("{}", patched),
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{apply, parse_patch, At, Change};
+    use bft_types::options::ParseOptions;
+    use bft_types::BfProgram;
+
+    fn patch(source: &str, patch: &str) -> Result<String, String> {
+        let program = BfProgram::new(source.to_string(), "program.bf").unwrap();
+        let changes = parse_patch(patch).map_err(|err| err.to_string())?;
+        apply(source, &program, &changes, &ParseOptions::new())
+            .map_err(|err| err.to_string())
+    }
+
+    #[test]
+    fn test_parse_patch() {
+        let changes = parse_patch(
+            "@@ my program.bf:2:7 new.bf:2:7 @@\n+ +\n\
+            @@ my program.bf:end new.bf:3:3 @@\n- \n+ >.\n+ <\n\
+            Changes: 2, instructions removed: 0, added: 4\n- ignored\n",
+        )
+        .unwrap();
+        assert_eq!(
+            changes,
+            [
+                Change {
+                    at: At::Instruction(2, 7),
+                    removed: String::new(),
+                    added: "+".to_string()
+                },
+                Change {
+                    at: At::End,
+                    removed: String::new(),
+                    added: ">.<".to_string()
+                }
+            ]
+        );
+        assert!(parse_patch("@@ nowhere @@\n").is_err());
+    }
+
+    #[test]
+    fn test_apply() {
+        let source = "Adds two\n++>+++  [-<+>]  sum\n<. done\n";
+        // The patch bft diff writes for this change.
+        let patched = patch(
+            source,
+            "@@ a.bf:2:9 b.bf:2:9 @@\n+ +\n@@ a.bf:end b.bf:3:3 @@\n+ >.\n",
+        );
+        assert_eq!(
+            patched.unwrap(),
+            "Adds two\n++>++++  [-<+>]  sum\n<.>. done\n"
+        );
+        let replaced =
+            patch(source, "@@ a.bf:2:10 b.bf:2:10 @@\n- -<+>\n+ <++>-\n");
+        assert_eq!(
+            replaced.unwrap(),
+            "Adds two\n++>+++  [<++>-]  sum\n<. done\n"
+        );
+        let removed = patch(source, "@@ a.bf:3:1 b.bf:end @@\n- <.\n");
+        assert_eq!(removed.unwrap(), "Adds two\n++>+++  [-<+>]  sum\n done\n");
+        // Commands removed from either side of a comment leave it alone.
+        let spanning = patch(source, "@@ a.bf:2:14 b.bf:2:14 @@\n- ]<\n");
+        assert_eq!(spanning.unwrap(), "Adds two\n++>+++  [-<+>  sum\n. done\n");
+        let start = patch(source, "@@ a.bf:2:1 b.bf:2:1 @@\n+ >\n");
+        assert_eq!(start.unwrap(), "Adds two\n>++>+++  [-<+>]  sum\n<. done\n");
+    }
+
+    #[test]
+    fn test_apply_errors() {
+        let source = "+[-]";
+        let wrong = patch(source, "@@ a.bf:1:2 b.bf:1:2 @@\n- +\n");
+        assert!(wrong.unwrap_err().contains("does not match"));
+        let missing = patch(source, "@@ a.bf:2:1 b.bf:2:1 @@\n+ +\n");
+        assert!(missing.unwrap_err().contains("no instruction at 2:1"));
+        let not_command = patch(source, "@@ a.bf:1:1 b.bf:1:1 @@\n+ x\n");
+        assert!(not_command.unwrap_err().contains("not a command"));
+        let overlapping = patch(
+            source,
+            "@@ a.bf:1:1 b.bf:1:1 @@\n- +[\n@@ a.bf:1:2 b.bf:1:1 @@\n- [\n",
+        );
+        assert!(overlapping.unwrap_err().contains("overlaps"));
+    }
+}
@@ -0,0 +1,267 @@
+//! The `pipe` subcommand, which chains programs together either like a shell
+//! pipeline, with each program running on its own thread, or by handing the
+//! tape of each program on to the next.
+
+use std::error::Error;
+use std::io::{ErrorKind, Read, Write};
+use std::process::ExitCode;
+use std::thread;
+
+use bft_interp::io::pipe;
+use bft_interp::ir::IrProgram;
+use bft_interp::CellKind;
+use bft_types::vm_error::VirtualMachineError;
+use bft_types::BfProgram;
+
+use crate::cli::{Composition, PipeArgs};
+use crate::config::{CellWidth, Settings};
+use crate::load_program;
+use crate::run::{interpret_vm, standard_streams};
+
+/// Whether an error is only a program noticing that the next program in the
+/// pipeline has stopped reading. As in a shell pipeline, this is not treated
+/// as a failure of the earlier program.
+fn is_broken_pipe(err: &VirtualMachineError) -> bool {
+    matches!(
+        err,
+        VirtualMachineError::IOError(e) if e.kind() == ErrorKind::BrokenPipe
+    )
+}
+
+/// A program in the pipeline, along with its optimized form if it is to be
+/// run optimized. Programs which start on the tape of the previous program
+/// are not partially evaluated, as that assumes a fresh tape.
+struct Stage {
+    program: BfProgram,
+    ir: Option<IrProgram>,
+}
+
+impl Stage {
+    fn new(
+        program: BfProgram,
+        settings: &Settings,
+        fresh_tape: bool,
+    ) -> Result<Self, Box<dyn Error>> {
+        let ir = settings.optimize(&program, fresh_tape)?.map(|(ir, _)| ir);
+        Ok(Stage { program, ir })
+    }
+}
+
+/// Runs a single stage of the pipeline.
+fn run_stage<T>(
+    stage: &Stage,
+    settings: &Settings,
+    mut input: impl Read,
+    mut output: impl Write,
+) -> Result<(), VirtualMachineError>
+where
+    T: CellKind + Default + Clone + Copy + PartialEq,
+{
+    let mut vm = settings.virtual_machine::<T>(&stage.program);
+    interpret_vm(&mut vm, stage.ir.as_ref(), &mut input, &mut output)
+}
+
+/// Runs the programs as a pipeline, using cells of type `T`, returning the
+/// result of each stage in order. The first program reads from `input`, and
+/// the last writes to `output`.
+fn run_stages<T>(
+    stages: &[Stage],
+    settings: &Settings,
+    buffer_size: usize,
+    input: impl Read + Send,
+    output: impl Write + Send,
+) -> Vec<Result<(), VirtualMachineError>>
+where
+    T: CellKind + Default + Clone + Copy + PartialEq + Send,
+{
+    let Some((last, rest)) = stages.split_last() else {
+        return Vec::new();
+    };
+    thread::scope(|scope| {
+        let mut handles = Vec::with_capacity(stages.len());
+        let mut input: Box<dyn Read + Send + '_> = Box::new(input);
+        for stage in rest {
+            let (writer, reader) = pipe(buffer_size);
+            let stage_input = std::mem::replace(&mut input, Box::new(reader));
+            handles.push(scope.spawn(move || {
+                run_stage::<T>(stage, settings, stage_input, writer)
+            }));
+        }
+        handles.push(
+            scope.spawn(move || run_stage::<T>(last, settings, input, output)),
+        );
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("pipeline stage panicked"))
+            .collect()
+    })
+}
+
+/// Runs the programs one after another, using cells of type `T`, with each
+/// program starting on the tape that the previous program finished with. All
+/// of the programs read from `input` and write to `output`.
+fn run_on_shared_tape<T>(
+    stages: &[Stage],
+    settings: &Settings,
+    keep_head: bool,
+    mut input: impl Read,
+    mut output: impl Write,
+) -> Result<Vec<T>, VirtualMachineError>
+where
+    T: CellKind + Default + Clone + Copy + PartialEq,
+{
+    let Some((first, rest)) = stages.split_first() else {
+        return Ok(Vec::new());
+    };
+    let mut vm = settings.virtual_machine::<T>(&first.program);
+    interpret_vm(&mut vm, first.ir.as_ref(), &mut input, &mut output)?;
+    for stage in rest {
+        let (tape, head) = vm.into_tape();
+        let head = if keep_head { head } else { 0 };
+        vm = settings.virtual_machine_on_tape(&stage.program, tape, head);
+        interpret_vm(&mut vm, stage.ir.as_ref(), &mut input, &mut output)?;
+    }
+    Ok(vm.into_tape().0)
+}
+
+/// Runs the `pipe` subcommand.
+pub(crate) fn run_pipe(args: &PipeArgs) -> Result<ExitCode, Box<dyn Error>> {
+    let settings = Settings::from_args(&args.run)?;
+    let fresh_tape = args.compose == Composition::Stream;
+    let stages = args
+        .programs
+        .iter()
+        .map(|filename| {
+            let program = load_program(filename, &settings)?;
+            Stage::new(program, &settings, fresh_tape)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if args.compose == Composition::Tape {
+        let (input, output) = standard_streams(&settings);
+        let keep_head = args.keep_head;
+        match settings.cell_width {
+            CellWidth::U8 => run_on_shared_tape::<u8>(
+                &stages, &settings, keep_head, input, output,
+            )
+            .map(drop),
+            CellWidth::U16 => run_on_shared_tape::<u16>(
+                &stages, &settings, keep_head, input, output,
+            )
+            .map(drop),
+            CellWidth::U32 => run_on_shared_tape::<u32>(
+                &stages, &settings, keep_head, input, output,
+            )
+            .map(drop),
+        }?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let buffer_size = args.buffer_size;
+    let (input, output) = standard_streams(&settings);
+    let results = match settings.cell_width {
+        CellWidth::U8 => {
+            run_stages::<u8>(&stages, &settings, buffer_size, input, output)
+        }
+        CellWidth::U16 => {
+            run_stages::<u16>(&stages, &settings, buffer_size, input, output)
+        }
+        CellWidth::U32 => {
+            run_stages::<u32>(&stages, &settings, buffer_size, input, output)
+        }
+    };
+
+    match results
+        .into_iter()
+        .filter_map(Result::err)
+        .find(|err| !is_broken_pipe(err))
+    {
+        Some(err) => Err(err.into()),
+        None => Ok(ExitCode::SUCCESS),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_broken_pipe, run_on_shared_tape, run_stages, Stage};
+    use crate::config::{settings, Settings};
+    use bft_types::BfProgram;
+    use std::io::Cursor;
+
+    fn stages(sources: &[&str], settings: &Settings) -> Vec<Stage> {
+        sources
+            .iter()
+            .map(|source| {
+                let program =
+                    BfProgram::new(source.to_string(), "stage.bf").unwrap();
+                Stage::new(program, settings, false).unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_output_feeds_next_program() {
+        let settings = settings(&["--eof", "zero"]);
+        let stages = stages(&[",[.,]", ",[+.,]", ",[+.,]"], &settings);
+        let mut output = Vec::new();
+        let results = run_stages::<u8>(
+            &stages,
+            &settings,
+            1,
+            Cursor::new(b"HAL".to_vec()),
+            &mut output,
+        );
+        assert!(results.iter().all(Result::is_ok));
+        assert_eq!(output, b"JCN");
+    }
+
+    #[test]
+    fn test_early_exit_breaks_pipe() {
+        let settings = settings(&["--eof", "zero"]);
+        let stages = stages(&["+[.>+]", ",."], &settings);
+        let mut output = Vec::new();
+        let results = run_stages::<u8>(
+            &stages,
+            &settings,
+            4,
+            Cursor::new(Vec::new()),
+            &mut output,
+        );
+        assert!(is_broken_pipe(results[0].as_ref().unwrap_err()));
+        assert!(results[1].is_ok());
+        assert_eq!(output, [1]);
+    }
+
+    #[test]
+    fn test_tape_handed_on() {
+        let settings =
+            settings(&["--eof", "zero", "--passes", "rle,clearloop"]);
+        let stages = stages(&["+>++", "+>+", "[-]"], &settings);
+        let tape = run_on_shared_tape::<u8>(
+            &stages,
+            &settings,
+            false,
+            Cursor::new(Vec::new()),
+            Vec::new(),
+        )
+        .unwrap();
+        assert_eq!(&tape[..3], [0, 3, 0]);
+    }
+
+    #[test]
+    fn test_tape_handed_on_keeping_head() {
+        let settings = settings(&["--eof", "zero"]);
+        let stages = stages(&["+>++", "+>+", ">+."], &settings);
+        let mut output = Vec::new();
+        let tape = run_on_shared_tape::<u8>(
+            &stages,
+            &settings,
+            true,
+            Cursor::new(Vec::new()),
+            &mut output,
+        )
+        .unwrap();
+        assert_eq!(&tape[..4], [1, 3, 1, 1]);
+        assert_eq!(output, [1]);
+    }
+}
@@ -0,0 +1,826 @@
+//! Loading plugins, dynamic libraries which add optimization passes, dialects
+//! and I/O codecs to bft through the C ABI of `bft_interp::plugin`. Plugins
+//! are given with `--plugin`, and once loaded stay loaded until bft exits, so
+//! that their passes can be named in `--passes`, programs with the extensions
+//! of their dialects are translated into Brainfuck as they are loaded, and
+//! their codecs can be given to `--codec`.
+//!
+//! A plugin's optimization passes only see programs whose nodes can all be
+//! handed to plugins, which are those written with the simpler operations, so
+//! they are best run before the built-in passes which make the others, as in
+//! `--passes mypass,rle,clearloop`. Passes which hand back a program that is
+//! not valid are warned about, and leave the program as it was.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::ffi::c_void;
+use std::fs;
+use std::io::{self, ErrorKind, Read, Write};
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::slice;
+use std::sync::{PoisonError, RwLock, RwLockReadGuard};
+
+use bft_interp::ir::{IrNode, IrProgram};
+use bft_interp::optimizer::{IrPass, PassFactory, Pipeline, BUILTIN_PASSES};
+use bft_interp::plugin::{
+    CodecVTable, DialectVTable, EntryPoint, HostInfo, Node, PassVTable,
+    PluginInfo, ENTRY_POINT,
+};
+use clap::crate_name;
+use libloading::Library;
+
+use crate::cli::PluginsArgs;
+use crate::config::Settings;
+
+/// How many bytes of input are read at a time to be decoded.
+const DECODE_CHUNK: usize = 4096;
+
+/// A plugin which has been loaded.
+struct Plugin {
+    /// Where the plugin was loaded from.
+    path: PathBuf,
+    name: String,
+    info: &'static PluginInfo,
+}
+
+/// Everything the loaded plugins provide, by name, or by extension for the
+/// dialects.
+struct Registry {
+    plugins: Vec<Plugin>,
+    passes: BTreeMap<String, PassFactory>,
+    dialects: BTreeMap<String, &'static DialectVTable>,
+    codecs: BTreeMap<String, &'static CodecVTable>,
+}
+
+static REGISTRY: RwLock<Registry> = RwLock::new(Registry {
+    plugins: Vec::new(),
+    passes: BTreeMap::new(),
+    dialects: BTreeMap::new(),
+    codecs: BTreeMap::new(),
+});
+
+// Nothing which could panic runs while the lock is held, so a poisoned lock
+// still holds a consistent registry.
+fn registry() -> RwLockReadGuard<'static, Registry> {
+    REGISTRY.read().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Reads a name from a plugin, which must be UTF-8 and not empty.
+fn plugin_name(
+    name: Result<&'static str, std::str::Utf8Error>,
+    what: &str,
+) -> Result<String, String> {
+    match name {
+        Ok("") => Err(format!("a {} has no name", what)),
+        Ok(name) => Ok(name.to_string()),
+        Err(_) => Err(format!("the name of a {} is not UTF-8", what)),
+    }
+}
+
+/// Adds what the plugin provides to the registry, unless any of it clashes
+/// with what is already there, in which case none of it is added.
+///
+/// # Safety
+///
+/// The plugin must keep to the interface, and stay loaded until bft exits.
+unsafe fn register(
+    path: &Path,
+    info: &'static PluginInfo,
+) -> Result<(), Box<dyn Error>> {
+    let host = HostInfo::new();
+    if !host.supports(info.version) {
+        return Err(format!(
+            "{} chose version {} of the plugin interface, but only versions \
+            {} to {} are supported",
+            path.display(),
+            info.version,
+            host.min_version,
+            host.max_version
+        )
+        .into());
+    }
+    let in_plugin = |err: String| format!("in {}: {}", path.display(), err);
+    // SAFETY: the caller guarantees the plugin keeps to the interface, and so
+    // its tables and strings live as long as it is loaded.
+    let name =
+        plugin_name(unsafe { info.name() }, "plugin").map_err(in_plugin)?;
+    let mut registry = REGISTRY.write().unwrap_or_else(PoisonError::into_inner);
+
+    let mut passes = Vec::new();
+    // SAFETY: as above.
+    for vtable in unsafe { info.passes() } {
+        let pass =
+            plugin_name(unsafe { vtable.name() }, "pass").map_err(in_plugin)?;
+        if BUILTIN_PASSES.contains(&pass.as_str())
+            || registry.passes.contains_key(&pass)
+            || passes.iter().any(|(known, _)| *known == pass)
+        {
+            return Err(in_plugin(format!(
+                "there is already a pass '{}'",
+                pass
+            ))
+            .into());
+        }
+        passes.push((pass, vtable));
+    }
+    let mut dialects = Vec::new();
+    // SAFETY: as above.
+    for vtable in unsafe { info.dialects() } {
+        plugin_name(unsafe { vtable.name() }, "dialect").map_err(in_plugin)?;
+        let extension = plugin_name(unsafe { vtable.extension() }, "dialect")
+            .map_err(in_plugin)?;
+        if registry.dialects.contains_key(&extension)
+            || dialects.iter().any(|(known, _)| *known == extension)
+        {
+            return Err(in_plugin(format!(
+                "there is already a dialect for .{} files",
+                extension
+            ))
+            .into());
+        }
+        dialects.push((extension, vtable));
+    }
+    let mut codecs = Vec::new();
+    // SAFETY: as above.
+    for vtable in unsafe { info.codecs() } {
+        let codec = plugin_name(unsafe { vtable.name() }, "codec")
+            .map_err(in_plugin)?;
+        if registry.codecs.contains_key(&codec)
+            || codecs.iter().any(|(known, _)| *known == codec)
+        {
+            return Err(in_plugin(format!(
+                "there is already a codec '{}'",
+                codec
+            ))
+            .into());
+        }
+        codecs.push((codec, vtable));
+    }
+
+    for (pass, vtable) in passes {
+        let factory_name = pass.clone();
+        let factory: PassFactory = Box::new(move || {
+            Box::new(PluginPass {
+                name: factory_name.clone(),
+                vtable,
+            })
+        });
+        registry.passes.insert(pass, factory);
+    }
+    registry.dialects.extend(dialects);
+    registry.codecs.extend(codecs);
+    registry.plugins.push(Plugin {
+        path: path.to_path_buf(),
+        name,
+        info,
+    });
+    Ok(())
+}
+
+/// Loads the plugins in the given files, leaving out any already loaded.
+pub(crate) fn load(paths: &[PathBuf]) -> Result<(), Box<dyn Error>> {
+    for path in paths {
+        if registry().plugins.iter().any(|plugin| plugin.path == *path) {
+            continue;
+        }
+        let cannot_load =
+            |err| format!("cannot load plugin {}: {}", path.display(), err);
+        // SAFETY: loading a library runs its initialisers, which is as safe
+        // as the library is. Plugins are trusted as much as bft itself.
+        let library = unsafe { Library::new(path) }.map_err(cannot_load)?;
+        // SAFETY: every plugin exports its entry point with this type.
+        let entry: EntryPoint =
+            *unsafe { library.get::<EntryPoint>(ENTRY_POINT.as_bytes()) }
+                .map_err(cannot_load)?;
+        // The library is never unloaded, as its passes and codecs may be in
+        // use anywhere until bft exits.
+        mem::forget(library);
+        let host = HostInfo::new();
+        // SAFETY: the entry point keeps to the interface.
+        let info = unsafe { entry(&host) };
+        // SAFETY: the plugin is either null or lives as long as the library.
+        let Some(info) = (unsafe { info.as_ref() }) else {
+            return Err(format!(
+                "{} supports none of versions {} to {} of the plugin \
+                interface",
+                path.display(),
+                host.min_version,
+                host.max_version
+            )
+            .into());
+        };
+        // SAFETY: the library stays loaded, and keeps to the interface.
+        unsafe { register(path, info) }?;
+    }
+    Ok(())
+}
+
+/// Creates a pipeline from a list of passes, as given to `--passes`, which
+/// may name those of the plugins loaded.
+pub(crate) fn pipeline(spec: &str) -> Result<Pipeline, String> {
+    Pipeline::from_spec_with(spec, &registry().passes)
+}
+
+/// Collects the nodes a plugin emits into the `Vec<Node>` at `out`.
+unsafe extern "C" fn emit_node(out: *mut c_void, node: Node) {
+    // SAFETY: the host always passes a vector of nodes along with this.
+    unsafe { &mut *out.cast::<Vec<Node>>() }.push(node);
+}
+
+/// Collects the bytes a plugin emits into the `Vec<u8>` at `out`.
+unsafe extern "C" fn emit_bytes(
+    out: *mut c_void,
+    bytes: *const u8,
+    len: usize,
+) {
+    if bytes.is_null() || len == 0 {
+        return;
+    }
+    // SAFETY: the host always passes a vector of bytes along with this, and
+    // the plugin passes `len` bytes.
+    unsafe { &mut *out.cast::<Vec<u8>>() }
+        .extend_from_slice(unsafe { slice::from_raw_parts(bytes, len) });
+}
+
+/// An optimization pass of a plugin.
+struct PluginPass {
+    name: String,
+    vtable: &'static PassVTable,
+}
+
+impl PluginPass {
+    /// Runs the pass, giving the number of nodes it rewrote, or why what it
+    /// gave back is not a valid program.
+    fn rewrite(&self, program: &mut IrProgram) -> Result<usize, String> {
+        let nodes: Option<Vec<Node>> = program
+            .nodes()
+            .iter()
+            .enumerate()
+            .map(|(index, node)| Node::from_op(node.op(), index))
+            .collect();
+        let Some(nodes) = nodes else {
+            return Ok(0);
+        };
+        let mut emitted: Vec<Node> = Vec::new();
+        // SAFETY: the nodes are valid for the call, and `emit_node` is given
+        // the vector of nodes it expects.
+        let rewritten = unsafe {
+            (self.vtable.run)(
+                nodes.as_ptr(),
+                nodes.len(),
+                (&mut emitted as *mut Vec<Node>).cast(),
+                emit_node,
+            )
+        };
+        let Ok(rewritten) = usize::try_from(rewritten) else {
+            return Ok(0);
+        };
+        let nodes = emitted
+            .iter()
+            .map(|node| {
+                let op = node.to_op().ok_or_else(|| {
+                    format!(
+                        "it gave a node of kind {} with an offset of {} and \
+                        an amount of {}, which is not valid",
+                        node.kind, node.offset, node.amount
+                    )
+                })?;
+                let source =
+                    program.nodes().get(node.source).ok_or_else(|| {
+                        format!("it gave a node from node {}", node.source)
+                    })?;
+                Ok(IrNode::new(op, source.source()))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        *program = IrProgram::from_nodes(nodes, program.filename())
+            .map_err(|err| err.to_string())?;
+        Ok(rewritten)
+    }
+}
+
+impl IrPass for PluginPass {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn run(&mut self, program: &mut IrProgram) -> usize {
+        match self.rewrite(program) {
+            Ok(rewritten) => rewritten,
+            Err(err) => {
+                eprintln!(
+                    "{}: warning: the {} pass left the program as it was, as \
+                    {}",
+                    crate_name!(),
+                    self.name,
+                    err
+                );
+                0
+            }
+        }
+    }
+}
+
+/// Translates the program in the given file into Brainfuck, if a plugin has a
+/// dialect for its extension.
+pub(crate) fn translate(path: &Path) -> Result<Option<String>, Box<dyn Error>> {
+    let Some(extension) = path.extension().and_then(|ext| ext.to_str()) else {
+        return Ok(None);
+    };
+    let Some(&vtable) = registry().dialects.get(extension) else {
+        return Ok(None);
+    };
+    let source = fs::read(path)?;
+    let mut translated: Vec<u8> = Vec::new();
+    // SAFETY: the source is valid for the call, and `emit_bytes` is given the
+    // vector of bytes it expects.
+    let status = unsafe {
+        (vtable.translate)(
+            source.as_ptr(),
+            source.len(),
+            (&mut translated as *mut Vec<u8>).cast(),
+            emit_bytes,
+        )
+    };
+    // SAFETY: the dialect was registered, so its plugin is still loaded.
+    let dialect = unsafe { vtable.name() }.unwrap_or_default();
+    if status != 0 {
+        return Err(format!(
+            "{} could not be translated from {}",
+            path.display(),
+            dialect
+        )
+        .into());
+    }
+    let translated = String::from_utf8(translated).map_err(|_| {
+        format!(
+            "{} translated {} into text which is not UTF-8",
+            dialect,
+            path.display()
+        )
+    })?;
+    Ok(Some(translated))
+}
+
+/// Whether a plugin has a codec with the given name.
+pub(crate) fn has_codec(name: &str) -> bool {
+    registry().codecs.contains_key(name)
+}
+
+/// One direction of a codec, encoding or decoding a stream a piece at a time.
+struct CodecStream {
+    vtable: &'static CodecVTable,
+    state: *mut c_void,
+    finished: bool,
+}
+
+// SAFETY: the state of a codec is only used by one thread at a time, which
+// the interface allows to change.
+unsafe impl Send for CodecStream {}
+
+impl CodecStream {
+    fn start(vtable: &'static CodecVTable, direction: u32) -> Self {
+        Self {
+            vtable,
+            // SAFETY: the codec was registered, so its plugin is loaded.
+            state: unsafe { (vtable.start)(direction) },
+            finished: false,
+        }
+    }
+
+    fn failed(&self) -> io::Error {
+        // SAFETY: as for `start()`.
+        let name = unsafe { self.vtable.name() }.unwrap_or_default();
+        io::Error::new(
+            ErrorKind::InvalidData,
+            format!("the {} codec could not carry on", name),
+        )
+    }
+
+    /// Encodes or decodes the bytes, adding what they become to `out`.
+    fn feed(&mut self, bytes: &[u8], out: &mut Vec<u8>) -> io::Result<()> {
+        // SAFETY: the state came from `start`, and has not been finished,
+        // the bytes are valid for the call, and `emit_bytes` is given the
+        // vector of bytes it expects.
+        let status = unsafe {
+            (self.vtable.feed)(
+                self.state,
+                bytes.as_ptr(),
+                bytes.len(),
+                (out as *mut Vec<u8>).cast(),
+                emit_bytes,
+            )
+        };
+        match status {
+            0 => Ok(()),
+            _ => Err(self.failed()),
+        }
+    }
+
+    /// Ends the stream, adding what is left of it to `out`, if it has not
+    /// already ended.
+    fn finish(&mut self, out: &mut Vec<u8>) -> io::Result<()> {
+        if mem::replace(&mut self.finished, true) {
+            return Ok(());
+        }
+        // SAFETY: as for `feed()`, and the state is not used again.
+        let status = unsafe {
+            (self.vtable.finish)(
+                self.state,
+                (out as *mut Vec<u8>).cast(),
+                emit_bytes,
+            )
+        };
+        match status {
+            0 => Ok(()),
+            _ => Err(self.failed()),
+        }
+    }
+}
+
+impl Drop for CodecStream {
+    /// Frees the state of the codec, if the stream was never finished.
+    fn drop(&mut self) {
+        let _ = self.finish(&mut Vec::new());
+    }
+}
+
+/// Decodes what is read from the inner reader.
+struct CodecReader<R> {
+    reader: R,
+    stream: CodecStream,
+    decoded: Vec<u8>,
+    position: usize,
+}
+
+impl<R> Read for CodecReader<R>
+where
+    R: Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.decoded.len() {
+            if self.stream.finished {
+                return Ok(0);
+            }
+            self.decoded.clear();
+            self.position = 0;
+            let mut chunk = [0; DECODE_CHUNK];
+            match self.reader.read(&mut chunk)? {
+                0 => self.stream.finish(&mut self.decoded)?,
+                n => self.stream.feed(&chunk[..n], &mut self.decoded)?,
+            }
+        }
+        let n = buf.len().min(self.decoded.len() - self.position);
+        buf[..n]
+            .copy_from_slice(&self.decoded[self.position..self.position + n]);
+        self.position += n;
+        Ok(n)
+    }
+}
+
+/// Encodes what is written before writing it to the inner writer.
+struct CodecWriter<W>
+where
+    W: Write,
+{
+    writer: W,
+    stream: CodecStream,
+}
+
+impl<W> Write for CodecWriter<W>
+where
+    W: Write,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut encoded = Vec::new();
+        self.stream.feed(buf, &mut encoded)?;
+        self.writer.write_all(&encoded)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+impl<W> Drop for CodecWriter<W>
+where
+    W: Write,
+{
+    /// Writes out the end of the encoded stream.
+    fn drop(&mut self) {
+        let mut encoded = Vec::new();
+        if self.stream.finish(&mut encoded).is_ok() {
+            let _ = self.writer.write_all(&encoded);
+        }
+        let _ = self.writer.flush();
+    }
+}
+
+/// Wraps the streams a program reads from and writes to with the codec given
+/// to `--codec`, if there is one, so that its input is decoded and its output
+/// encoded.
+pub(crate) fn with_codec(
+    settings: &Settings,
+    input: Box<dyn Read + Send>,
+    output: Box<dyn Write + Send>,
+) -> (Box<dyn Read + Send>, Box<dyn Write + Send>) {
+    let Some(name) = &settings.codec else {
+        return (input, output);
+    };
+    let vtable = *registry()
+        .codecs
+        .get(name)
+        .expect("codecs are checked when the settings are resolved");
+    let input = CodecReader {
+        reader: input,
+        stream: CodecStream::start(vtable, CodecVTable::DECODE),
+        decoded: Vec::new(),
+        position: 0,
+    };
+    let output = CodecWriter {
+        writer: output,
+        stream: CodecStream::start(vtable, CodecVTable::ENCODE),
+    };
+    (Box::new(input), Box::new(output))
+}
+
+/// Describes the plugins loaded, and what each of them provides.
+fn describe() -> String {
+    let mut description = String::new();
+    for plugin in &registry().plugins {
+        description.push_str(&format!(
+            "{} (version {} of the plugin interface), from {}\n",
+            plugin.name,
+            plugin.info.version,
+            plugin.path.display()
+        ));
+        // SAFETY: the plugin is registered, so it is loaded and keeps to the
+        // interface.
+        let info = plugin.info;
+        for pass in unsafe { info.passes() } {
+            let name = unsafe { pass.name() }.unwrap_or_default();
+            description.push_str(&format!("  pass {}\n", name));
+        }
+        for dialect in unsafe { info.dialects() } {
+            let name = unsafe { dialect.name() }.unwrap_or_default();
+            let extension = unsafe { dialect.extension() }.unwrap_or_default();
+            description
+                .push_str(&format!("  dialect {} (.{})\n", name, extension));
+        }
+        for codec in unsafe { info.codecs() } {
+            let name = unsafe { codec.name() }.unwrap_or_default();
+            description.push_str(&format!("  codec {}\n", name));
+        }
+    }
+    description
+}
+
+/// Runs the `plugins` subcommand.
+pub(crate) fn run_plugins(
+    args: &PluginsArgs,
+) -> Result<ExitCode, Box<dyn Error>> {
+    Settings::from_args(&args.run)?;
+    if args.run.plugins.is_empty() {
+        println!("No plugins were given, load them with --plugin.");
+    }
+    print!("{}", describe());
+    Ok(ExitCode::SUCCESS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        pipeline, register, translate, CodecReader, CodecStream, CodecWriter,
+    };
+    use bft_interp::ir::{IrNode, IrOp, IrProgram};
+    use bft_interp::plugin::{
+        CodecVTable, DialectVTable, EmitBytes, EmitNode, Node, PassVTable,
+        PluginInfo, ABI_VERSION,
+    };
+    use bft_types::BfProgram;
+    use std::ffi::c_void;
+    use std::fs;
+    use std::io::{Read, Write};
+    use std::path::Path;
+    use std::ptr;
+    use std::slice;
+
+    /// Swaps adds and moves for their opposites.
+    unsafe extern "C" fn negate(
+        nodes: *const Node,
+        len: usize,
+        out: *mut c_void,
+        emit: EmitNode,
+    ) -> i64 {
+        let nodes = unsafe { slice::from_raw_parts(nodes, len) };
+        for node in nodes {
+            let node = Node::new(node.kind, -node.offset, -node.amount, 0);
+            unsafe { emit(out, node) };
+        }
+        nodes.len() as i64
+    }
+
+    /// Leaves a loop open, which is not a valid program.
+    unsafe extern "C" fn unbalance(
+        _nodes: *const Node,
+        _len: usize,
+        out: *mut c_void,
+        emit: EmitNode,
+    ) -> i64 {
+        unsafe { emit(out, Node::new(Node::LOOP_START, 0, 0, 0)) };
+        1
+    }
+
+    /// Translates `p` to `+` and `o` to `.`.
+    unsafe extern "C" fn letters(
+        source: *const u8,
+        len: usize,
+        out: *mut c_void,
+        emit: EmitBytes,
+    ) -> i32 {
+        let source = unsafe { slice::from_raw_parts(source, len) };
+        for byte in source {
+            let command: &[u8] = match byte {
+                b'p' => b"+",
+                b'o' => b".",
+                b' ' | b'\n' => b"",
+                _ => return 1,
+            };
+            unsafe { emit(out, command.as_ptr(), command.len()) };
+        }
+        0
+    }
+
+    /// Counts the bytes of the stream, emitting how many there were at the
+    /// end, so that encoding `abc` gives `abc3`.
+    unsafe extern "C" fn start_count(_direction: u32) -> *mut c_void {
+        Box::into_raw(Box::new(0usize)).cast()
+    }
+
+    unsafe extern "C" fn feed_count(
+        state: *mut c_void,
+        bytes: *const u8,
+        len: usize,
+        out: *mut c_void,
+        emit: EmitBytes,
+    ) -> i32 {
+        unsafe { *state.cast::<usize>() += len };
+        unsafe { emit(out, bytes, len) };
+        0
+    }
+
+    unsafe extern "C" fn finish_count(
+        state: *mut c_void,
+        out: *mut c_void,
+        emit: EmitBytes,
+    ) -> i32 {
+        let count = unsafe { Box::from_raw(state.cast::<usize>()) };
+        let count = count.to_string();
+        unsafe { emit(out, count.as_ptr(), count.len()) };
+        0
+    }
+
+    static PASSES: [PassVTable; 2] = [
+        PassVTable {
+            name: c"test-negate".as_ptr(),
+            run: negate,
+        },
+        PassVTable {
+            name: c"test-unbalance".as_ptr(),
+            run: unbalance,
+        },
+    ];
+
+    static DIALECTS: [DialectVTable; 1] = [DialectVTable {
+        name: c"letters".as_ptr(),
+        extension: c"test-letters".as_ptr(),
+        translate: letters,
+    }];
+
+    static CODECS: [CodecVTable; 1] = [CodecVTable {
+        name: c"test-count".as_ptr(),
+        start: start_count,
+        feed: feed_count,
+        finish: finish_count,
+    }];
+
+    static PLUGIN: PluginInfo = PluginInfo {
+        version: ABI_VERSION,
+        name: c"test".as_ptr(),
+        passes: PASSES.as_ptr(),
+        pass_count: PASSES.len(),
+        dialects: DIALECTS.as_ptr(),
+        dialect_count: DIALECTS.len(),
+        codecs: CODECS.as_ptr(),
+        codec_count: CODECS.len(),
+    };
+
+    static NEWER: PluginInfo = PluginInfo {
+        version: ABI_VERSION + 1,
+        name: c"newer".as_ptr(),
+        passes: ptr::null(),
+        pass_count: 0,
+        dialects: ptr::null(),
+        dialect_count: 0,
+        codecs: ptr::null(),
+        codec_count: 0,
+    };
+
+    static CLASHING: PluginInfo = PluginInfo {
+        version: ABI_VERSION,
+        name: c"clashing".as_ptr(),
+        passes: ptr::null(),
+        pass_count: 0,
+        dialects: ptr::null(),
+        dialect_count: 0,
+        codecs: CODECS.as_ptr(),
+        codec_count: CODECS.len(),
+    };
+
+    fn ops(program: &IrProgram) -> Vec<IrOp> {
+        program
+            .nodes()
+            .iter()
+            .map(|node| node.op().clone())
+            .collect()
+    }
+
+    #[test]
+    fn test_plugin() {
+        // The tests share the registry, so the plugin is only registered by
+        // this test.
+        unsafe { register(Path::new("test.so"), &PLUGIN) }.unwrap();
+        let newer = unsafe { register(Path::new("newer.so"), &NEWER) };
+        assert!(newer.unwrap_err().to_string().contains("version 2"));
+        let clashing = unsafe { register(Path::new("clash.so"), &CLASHING) };
+        assert!(clashing
+            .unwrap_err()
+            .to_string()
+            .contains("already a codec"));
+
+        let program = BfProgram::new("+>[-]<.".to_string(), "p.bf").unwrap();
+        let mut ir = IrProgram::from_program(&program).unwrap();
+        let mut negate = pipeline("test-negate").unwrap();
+        let stats = negate.run(&mut ir);
+        assert_eq!(stats[0].rewritten(), 7);
+        assert_eq!(ir.nodes()[1].op(), &IrOp::Move(-1));
+        // Every node is said to come from the first.
+        assert_eq!(ir.nodes()[6].source().column(), 1);
+        // Invalid programs are not taken up.
+        let before = ops(&ir);
+        pipeline("test-unbalance").unwrap().run(&mut ir);
+        assert_eq!(ops(&ir), before);
+        // Nor are programs with nodes which cannot be handed over.
+        let source = ir.nodes()[0].source();
+        let nodes = vec![
+            IrNode::new(IrOp::OutputBytes(vec![1]), source),
+            IrNode::new(IrOp::Add(1), source),
+        ];
+        let mut unsupported = IrProgram::from_nodes(nodes, "p.bf").unwrap();
+        assert_eq!(negate.run(&mut unsupported)[0].rewritten(), 0);
+        assert_eq!(unsupported.nodes()[1].op(), &IrOp::Add(1));
+        // Plugin passes only run when they are named.
+        let names = pipeline("-rle").unwrap().names().join(",");
+        assert!(!names.contains("test-negate"));
+
+        let path = std::env::temp_dir()
+            .join(format!("bft-plugin-{}.test-letters", std::process::id()));
+        fs::write(&path, "ppp o\n").unwrap();
+        let translated = translate(&path);
+        fs::write(&path, "pq").unwrap();
+        let failed = translate(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(translated.unwrap().as_deref(), Some("+++."));
+        assert!(failed.is_err());
+        assert!(translate(Path::new("p.bf")).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_codec_streams() {
+        let vtable = &CODECS[0];
+        let mut encoded = Vec::new();
+        {
+            let mut writer = CodecWriter {
+                writer: &mut encoded,
+                stream: CodecStream::start(vtable, CodecVTable::ENCODE),
+            };
+            writer.write_all(b"ab").unwrap();
+            writer.write_all(b"c").unwrap();
+        }
+        assert_eq!(encoded, b"abc3");
+
+        let mut reader = CodecReader {
+            reader: &b"hello"[..],
+            stream: CodecStream::start(vtable, CodecVTable::DECODE),
+            decoded: Vec::new(),
+            position: 0,
+        };
+        let mut decoded = String::new();
+        reader.read_to_string(&mut decoded).unwrap();
+        assert_eq!(decoded, "hello5");
+        // A stream dropped before it ends still frees its state.
+        drop(CodecStream::start(vtable, CodecVTable::DECODE));
+    }
+}
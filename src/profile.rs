@@ -0,0 +1,270 @@
+//! Profiling which loops a run spends its steps in with `--profile`, treating
+//! each loop of the source as a function, and drawing the profile as a
+//! flamegraph with `--flamegraph`.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+
+use bft_interp::report::{Reporter, Step};
+use bft_types::BfProgram;
+
+use crate::trace::{loop_name, LoopStack, Position};
+
+/// The most loops listed in the summary of a profile.
+const SUMMARY_LOOPS: usize = 10;
+
+/// The width of a flamegraph, in pixels.
+const FLAMEGRAPH_WIDTH: f64 = 1200.0;
+
+/// The height of each frame of a flamegraph, in pixels.
+const FRAME_HEIGHT: usize = 16;
+
+/// The narrowest frame of a flamegraph which is drawn, in pixels.
+const MIN_FRAME_WIDTH: f64 = 0.1;
+
+/// Counts the steps of a run by which loops were running at each step.
+#[derive(Debug, Default)]
+pub(crate) struct LoopProfile {
+    /// The name of the frame for the whole program.
+    root: String,
+    loops: LoopStack,
+    /// The number of steps run with each stack of loops running, outermost
+    /// first.
+    counts: HashMap<Vec<Position>, u64>,
+}
+
+impl Reporter for LoopProfile {
+    fn parsed(&mut self, program: &BfProgram) {
+        self.root = program.filename().display().to_string();
+        self.loops.parsed(program);
+    }
+
+    fn traces(&self) -> bool {
+        true
+    }
+
+    fn step(&mut self, step: &Step<'_>) {
+        // Entering a loop counts towards the loops around it, and leaving a
+        // loop counts towards the loop itself.
+        match self.counts.get_mut(self.loops.open()) {
+            Some(count) => *count += 1,
+            None => {
+                self.counts.insert(self.loops.open().to_vec(), 1);
+            }
+        }
+        self.loops.step(step);
+    }
+}
+
+/// A frame of a flamegraph, with the frames called from it.
+#[derive(Debug, Default)]
+struct Frame {
+    /// The steps run in this frame, including those in the frames it called.
+    total: u64,
+    children: BTreeMap<Position, Frame>,
+}
+
+impl LoopProfile {
+    /// The stacks of loops with their counts, sorted by stack.
+    fn sorted(&self) -> Vec<(&Vec<Position>, u64)> {
+        let mut stacks: Vec<_> =
+            self.counts.iter().map(|(s, &count)| (s, count)).collect();
+        stacks.sort();
+        stacks
+    }
+
+    /// The profile in the folded stacks format read by flamegraph tools: one
+    /// line for each stack of loops, with its frames separated by `;`, and
+    /// the number of steps run in it.
+    pub(crate) fn folded(&self) -> String {
+        let mut folded = String::new();
+        for (stack, count) in self.sorted() {
+            folded.push_str(&self.root);
+            for &position in stack {
+                folded.push(';');
+                folded.push_str(&loop_name(position));
+            }
+            writeln!(folded, " {}", count).expect("strings can be written to");
+        }
+        folded
+    }
+
+    /// The loops which the most steps were run in, including the loops inside
+    /// them, as lines to print.
+    pub(crate) fn summary(&self) -> String {
+        let total: u64 = self.counts.values().sum();
+        let mut inclusive: HashMap<Position, u64> = HashMap::new();
+        for (stack, count) in &self.counts {
+            for &position in stack {
+                *inclusive.entry(position).or_insert(0) += count;
+            }
+        }
+        let mut loops: Vec<(Position, u64)> = inclusive.into_iter().collect();
+        loops.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        let mut summary = format!("{:>12}  {:>6}  loop\n", "steps", "share");
+        for (position, steps) in loops.into_iter().take(SUMMARY_LOOPS) {
+            writeln!(
+                summary,
+                "{:>12}  {:>5.1}%  {}",
+                steps,
+                steps as f64 * 100.0 / total.max(1) as f64,
+                loop_name(position)
+            )
+            .expect("strings can be written to");
+        }
+        summary.push_str(&format!("{:>12}  total", total));
+        summary
+    }
+
+    /// Builds the tree of frames from the stacks of loops.
+    fn frames(&self) -> Frame {
+        let mut root = Frame::default();
+        for (stack, count) in &self.counts {
+            root.total += count;
+            let mut frame = &mut root;
+            for &position in stack {
+                frame = frame.children.entry(position).or_default();
+                frame.total += count;
+            }
+        }
+        root
+    }
+
+    /// Draws the profile as an SVG flamegraph, with the whole program at the
+    /// bottom and each loop drawn on top of the loop it runs in, as wide as
+    /// the share of the steps run in it.
+    pub(crate) fn flamegraph(&self) -> String {
+        let root = self.frames();
+        let depth = depth(&root);
+        let height = (depth + 1) * FRAME_HEIGHT;
+        let scale = FLAMEGRAPH_WIDTH / root.total.max(1) as f64;
+        let mut svg = format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" \
+            height=\"{}\" font-family=\"monospace\" font-size=\"11\">\n",
+            FLAMEGRAPH_WIDTH, height
+        );
+        let mut frames = vec![(self.root.clone(), &root, 0.0, 0)];
+        while let Some((name, frame, x, level)) = frames.pop() {
+            let width = frame.total as f64 * scale;
+            if width < MIN_FRAME_WIDTH {
+                continue;
+            }
+            draw_frame(&mut svg, &name, frame.total, x, height, level, width);
+            let mut child_x = x;
+            for (&position, child) in &frame.children {
+                frames.push((loop_name(position), child, child_x, level + 1));
+                child_x += child.total as f64 * scale;
+            }
+        }
+        svg.push_str("</svg>\n");
+        svg
+    }
+}
+
+/// How many loops deep the most deeply nested frame is.
+fn depth(frame: &Frame) -> usize {
+    frame
+        .children
+        .values()
+        .map(|child| depth(child) + 1)
+        .max()
+        .unwrap_or(0)
+}
+
+/// Draws a single frame, with a tooltip giving its name and steps, and its
+/// name written on it if it fits.
+fn draw_frame(
+    svg: &mut String,
+    name: &str,
+    steps: u64,
+    x: f64,
+    height: usize,
+    level: usize,
+    width: f64,
+) {
+    let y = height - (level + 1) * FRAME_HEIGHT;
+    // Warm colours, which stay the same for the same loop from run to run.
+    let hue = name
+        .bytes()
+        .fold(0u32, |h, b| h.wrapping_mul(31) + b as u32)
+        % 50;
+    let name = name
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;");
+    writeln!(
+        svg,
+        "<g><title>{} ({} steps)</title>\
+        <rect x=\"{:.1}\" y=\"{}\" width=\"{:.1}\" height=\"{}\" \
+        fill=\"hsl({},80%,60%)\" stroke=\"white\"/>",
+        name,
+        steps,
+        x,
+        y,
+        width,
+        FRAME_HEIGHT - 1,
+        hue
+    )
+    .expect("strings can be written to");
+    // Roughly how many characters fit in the frame.
+    let fits = (width / 7.0) as usize;
+    if fits >= 3 {
+        let label: String = name.chars().take(fits).collect();
+        writeln!(
+            svg,
+            "<text x=\"{:.1}\" y=\"{}\">{}</text>",
+            x + 3.0,
+            y + FRAME_HEIGHT - 4,
+            label
+        )
+        .expect("strings can be written to");
+    }
+    svg.push_str("</g>\n");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LoopProfile;
+    use bft_interp::report::Reporter;
+    use bft_interp::VirtualMachine;
+    use bft_types::BfProgram;
+    use std::io::Cursor;
+
+    /// Profiles a run of the program.
+    fn profile(source: &str) -> LoopProfile {
+        let program = BfProgram::new(source.to_string(), "test.bf").unwrap();
+        let mut profile = LoopProfile::default();
+        profile.parsed(&program);
+        let mut vm = VirtualMachine::<u8>::new(&program, 0, false)
+            .with_reporter(&mut profile);
+        vm.interpret(&mut Cursor::new(Vec::new()), &mut Vec::new())
+            .unwrap();
+        drop(vm);
+        profile
+    }
+
+    #[test]
+    fn test_folded() {
+        // Three times around the outer loop, each running the inner loop
+        // twice.
+        let profile = profile("+++[>++[-]<-]");
+        assert_eq!(
+            profile.folded(),
+            "test.bf 4\n\
+             test.bf;loop 1:4 22\n\
+             test.bf;loop 1:4;loop 1:8 15\n"
+        );
+        let summary = profile.summary();
+        assert!(summary.contains("          37   90.2%  loop 1:4\n"));
+        assert!(summary.ends_with("          41  total"));
+    }
+
+    #[test]
+    fn test_flamegraph() {
+        let svg = profile("+[-]>").flamegraph();
+        assert!(svg.starts_with("<svg"));
+        assert!(svg.contains("<title>test.bf (6 steps)</title>"));
+        assert!(svg.contains("<title>loop 1:2 (3 steps)</title>"));
+        assert!(svg.trim_end().ends_with("</svg>"));
+    }
+}
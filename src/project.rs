@@ -0,0 +1,577 @@
+//! Projects of many programs, described by a `bfproject.toml` file, which
+//! `bft build` checks and optimizes all at once and `bft test` runs the test
+//! cases of. A project file looks like:
+//!
+//! ```toml
+//! # Directories to look for programs and test files in, after the directory
+//! # of the project file.
+//! include = ["lib"]
+//!
+//! # Settings for every program, with the same keys as bft.toml.
+//! [settings]
+//! preset = "classic"
+//!
+//! [[program]]
+//! name = "echo"
+//! path = "echo.bf"
+//! # Settings for just this program, which take precedence over the others.
+//! settings = { eof = "zero" }
+//!
+//! [[program.test]]
+//! name = "hello"
+//! input = "hello"
+//! output = "hello"
+//!
+//! [[program.test]]
+//! name = "story"
+//! input-file = "tests/story.txt"
+//! output-file = "tests/story.txt"
+//! ```
+//!
+//! Settings are taken from the command line first, then the settings of the
+//! program, then those of the project, then the usual config files.
+//!
+//! `bft test` also runs the test cases written alongside each program, which
+//! are found by `bft_types::testspec`.
+
+use std::collections::HashSet;
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use bft_types::testspec::Extractor;
+use bft_types::BfProgram;
+use serde::Deserialize;
+
+use crate::cli::{BuildArgs, ProjectArgs, RunArgs};
+use crate::config::{Config, Settings};
+use crate::harness::{
+    execute_prepared, prepare, Outcome, Prepared, DEFAULT_STEP_LIMIT,
+};
+use crate::load_program;
+use crate::optimize::{emit_bf, optimize};
+use crate::output_diff::{color_enabled, output_diff};
+
+/// The name of the project file.
+const PROJECT_FILENAME: &str = "bfproject.toml";
+
+/// A test case of a program: the input to run it with, and the output it
+/// should write. A case without an expected output only checks that the
+/// program halts.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct TestCase {
+    name: String,
+    input: Option<String>,
+    input_file: Option<PathBuf>,
+    output: Option<String>,
+    output_file: Option<PathBuf>,
+}
+
+/// A program of the project.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct ProjectProgram {
+    name: String,
+    path: PathBuf,
+    /// Directories to look for the files of this program in, before those of
+    /// the project.
+    #[serde(default)]
+    include: Vec<PathBuf>,
+    #[serde(default)]
+    settings: Config,
+    #[serde(default, rename = "test")]
+    tests: Vec<TestCase>,
+}
+
+/// The contents of a project file.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub(crate) struct Project {
+    /// The directory of the project file, which paths are relative to.
+    #[serde(skip)]
+    root: PathBuf,
+    #[serde(default)]
+    include: Vec<PathBuf>,
+    #[serde(default)]
+    settings: Config,
+    #[serde(default, rename = "program")]
+    programs: Vec<ProjectProgram>,
+}
+
+impl Project {
+    /// Parses a project file, whose paths are relative to `root`.
+    fn parse(contents: &str, root: &Path) -> Result<Project, String> {
+        let mut project: Project =
+            toml::from_str(contents).map_err(|err| err.to_string())?;
+        project.root = root.to_path_buf();
+        let mut names = HashSet::new();
+        for program in &project.programs {
+            if !names.insert(&program.name) {
+                return Err(format!(
+                    "there is more than one program named '{}'",
+                    program.name
+                ));
+            }
+            for case in &program.tests {
+                if case.input.is_some() && case.input_file.is_some() {
+                    return Err(format!(
+                        "test '{}' of '{}' has both an input and an input file",
+                        case.name, program.name
+                    ));
+                }
+                if case.output.is_some() && case.output_file.is_some() {
+                    return Err(format!(
+                        "test '{}' of '{}' has both an output and an output \
+                        file",
+                        case.name, program.name
+                    ));
+                }
+            }
+        }
+        Ok(project)
+    }
+
+    /// Finds the project file in the directory or the nearest of its parents
+    /// which has one.
+    pub(crate) fn find(dir: &Path) -> Option<PathBuf> {
+        dir.ancestors()
+            .map(|ancestor| ancestor.join(PROJECT_FILENAME))
+            .find(|candidate| candidate.is_file())
+    }
+
+    /// Loads the given project file, or the first one found in the current
+    /// directory or any of its parents.
+    pub(crate) fn load(
+        explicit: Option<&Path>,
+    ) -> Result<Project, Box<dyn Error>> {
+        let path = match explicit {
+            Some(path) => path.to_path_buf(),
+            None => Project::find(&env::current_dir()?).ok_or_else(|| {
+                format!("no {} found in this directory", PROJECT_FILENAME)
+            })?,
+        };
+        let contents = fs::read_to_string(&path)?;
+        let root = path.parent().unwrap_or(Path::new(""));
+        Project::parse(&contents, root)
+            .map_err(|err| format!("in {}: {}", path.display(), err).into())
+    }
+
+    /// The programs with the given names, in the order they were given, or
+    /// every program if none were.
+    fn select(&self, names: &[String]) -> Result<Vec<&ProjectProgram>, String> {
+        if names.is_empty() {
+            return Ok(self.programs.iter().collect());
+        }
+        names
+            .iter()
+            .map(|name| {
+                self.programs
+                    .iter()
+                    .find(|program| &program.name == name)
+                    .ok_or_else(|| format!("no program named '{}'", name))
+            })
+            .collect()
+    }
+
+    /// Finds a file of the program: in the directory of the project file,
+    /// then each include directory of the program, then each of the project.
+    fn locate(
+        &self,
+        program: &ProjectProgram,
+        path: &Path,
+    ) -> Result<PathBuf, String> {
+        let dirs = program.include.iter().chain(&self.include);
+        [self.root.clone()]
+            .into_iter()
+            .chain(dirs.map(|dir| self.root.join(dir)))
+            .map(|dir| dir.join(path))
+            .find(|candidate| candidate.is_file())
+            .ok_or_else(|| {
+                format!(
+                    "cannot find {} for '{}' in the project or its include \
+                    directories",
+                    path.display(),
+                    program.name
+                )
+            })
+    }
+
+    /// The settings of the program, taking each one from the command line,
+    /// then the program, then the project and then `fallback`.
+    fn settings(
+        &self,
+        program: &ProjectProgram,
+        run: &RunArgs,
+        fallback: Config,
+    ) -> Result<Settings, Box<dyn Error>> {
+        let config = program
+            .settings
+            .clone()
+            .or(self.settings.clone())
+            .or(fallback);
+        Settings::resolve(run, config)
+            .map_err(|err| format!("'{}': {}", program.name, err).into())
+    }
+
+    /// The file of every program, with its settings or why they could not be
+    /// worked out. Files which cannot be found are given relative to the root
+    /// of the project.
+    pub(crate) fn targets(
+        &self,
+        run: &RunArgs,
+        fallback: &Config,
+    ) -> Vec<(PathBuf, Result<Settings, String>)> {
+        self.programs
+            .iter()
+            .map(|program| match self.locate(program, &program.path) {
+                Ok(path) => {
+                    let settings = self
+                        .settings(program, run, fallback.clone())
+                        .map_err(|err| err.to_string());
+                    (path, settings)
+                }
+                Err(err) => (self.root.join(&program.path), Err(err)),
+            })
+            .collect()
+    }
+
+    /// Loads the program with its settings.
+    fn load_program(
+        &self,
+        program: &ProjectProgram,
+        args: &ProjectArgs,
+        fallback: Config,
+    ) -> Result<(BfProgram, Settings), Box<dyn Error>> {
+        let settings = self.settings(program, &args.run, fallback)?;
+        let path = self.locate(program, &program.path)?;
+        Ok((load_program(&path, &settings)?, settings))
+    }
+
+    /// Reads a file of the program, or gives the text written out in the
+    /// project file, or nothing if there is neither.
+    fn read(
+        &self,
+        program: &ProjectProgram,
+        text: &Option<String>,
+        file: &Option<PathBuf>,
+    ) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+        match (text, file) {
+            (Some(text), _) => Ok(Some(text.clone().into_bytes())),
+            (None, Some(path)) => {
+                Ok(Some(fs::read(self.locate(program, path)?)?))
+            }
+            (None, None) => Ok(None),
+        }
+    }
+
+    /// Runs a test case of the program, returning why it failed if it did.
+    fn run_case(
+        &self,
+        program: &ProjectProgram,
+        case: &TestCase,
+        prepared: &Prepared<'_>,
+        settings: &Settings,
+    ) -> Result<(), Box<dyn Error>> {
+        let input = self.read(program, &case.input, &case.input_file)?;
+        let expected = self.read(program, &case.output, &case.output_file)?;
+        let input = input.unwrap_or_default();
+        check(prepared, settings, &input, expected.as_deref())
+    }
+}
+
+/// Runs the prepared program with the input, returning why it failed if it
+/// did not halt, or did not write the expected output.
+fn check(
+    prepared: &Prepared<'_>,
+    settings: &Settings,
+    input: &[u8],
+    expected: Option<&[u8]>,
+) -> Result<(), Box<dyn Error>> {
+    let step_limit = settings.max_steps.unwrap_or(DEFAULT_STEP_LIMIT);
+    let execution = execute_prepared(prepared, settings, input, step_limit);
+    if !matches!(execution.outcome, Outcome::Halted) {
+        return Err(execution.outcome.describe().into());
+    }
+    match expected {
+        Some(expected) if execution.output != expected => Err(format!(
+            "the output was not as expected:\n{}",
+            output_diff(
+                expected,
+                &execution.output,
+                ["expected", "output"],
+                color_enabled()
+            )
+        )
+        .into()),
+        _ => Ok(()),
+    }
+}
+
+/// Checks that the program parses and optimizes it, returning the optimized
+/// program as plain Brainfuck.
+fn build_program(
+    program: &BfProgram,
+    settings: &Settings,
+) -> Result<String, Box<dyn Error>> {
+    let ir = optimize(program, settings)?;
+    emit_bf(&ir, settings.cell_width.bits(), None)
+}
+
+/// Runs the `build` subcommand.
+pub(crate) fn run_build(args: &BuildArgs) -> Result<ExitCode, Box<dyn Error>> {
+    let project = Project::load(args.project.project.as_deref())?;
+    let fallback = Config::load(args.project.run.config.as_deref())?;
+    if let Some(dir) = &args.out_dir {
+        fs::create_dir_all(dir)?;
+    }
+    let mut failed = 0;
+    let programs = project.select(&args.project.names)?;
+    for program in &programs {
+        let built = project
+            .load_program(program, &args.project, fallback.clone())
+            .and_then(|(bf_program, settings)| {
+                let code = build_program(&bf_program, &settings)?;
+                Ok((bf_program.instructions().len(), code))
+            });
+        match built {
+            Ok((commands, code)) => {
+                println!(
+                    "built {}: {} commands, {} once optimized",
+                    program.name,
+                    commands,
+                    code.trim_end().len()
+                );
+                if let Some(dir) = &args.out_dir {
+                    let path = dir.join(&program.name).with_extension("bf");
+                    fs::write(path, code)?;
+                }
+            }
+            Err(err) => {
+                failed += 1;
+                println!("failed {}: {}", program.name, err);
+            }
+        }
+    }
+    println!("{} built, {} failed", programs.len() - failed, failed);
+    Ok(if failed == 0 {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    })
+}
+
+/// Runs the `test` subcommand.
+pub(crate) fn run_test(args: &ProjectArgs) -> Result<ExitCode, Box<dyn Error>> {
+    let project = Project::load(args.project.as_deref())?;
+    let fallback = Config::load(args.run.config.as_deref())?;
+    let (mut passed, mut failed) = (0, 0);
+    let mut report =
+        |program: &str, case: &str, result: Result<(), Box<dyn Error>>| {
+            match result {
+                Ok(()) => {
+                    passed += 1;
+                    println!("test {}/{} ... ok", program, case);
+                }
+                Err(err) => {
+                    failed += 1;
+                    println!("test {}/{} ... FAILED: {}", program, case, err);
+                }
+            }
+        };
+    let extractor = Extractor::new();
+    for program in project.select(&args.names)? {
+        let (bf_program, settings) =
+            match project.load_program(program, args, fallback.clone()) {
+                Ok(loaded) => loaded,
+                Err(err) => {
+                    for case in &program.tests {
+                        report(
+                            &program.name,
+                            &case.name,
+                            Err(err.to_string().into()),
+                        );
+                    }
+                    continue;
+                }
+            };
+        // The program is compiled once, for all of its test cases.
+        let prepared = prepare(&bf_program, &settings);
+        for case in &program.tests {
+            let result = project.run_case(program, case, &prepared, &settings);
+            report(&program.name, &case.name, result);
+        }
+        // Test cases written alongside the program, such as in a `.out` file.
+        let specs = extractor
+            .extract(&bf_program)
+            .map_err(|err| format!("'{}': {}", program.name, err))?;
+        for spec in &specs {
+            let result =
+                check(&prepared, &settings, spec.input(), Some(spec.output()));
+            report(&program.name, spec.name(), result);
+        }
+    }
+    println!("{} passed, {} failed", passed, failed);
+    Ok(if failed == 0 {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{build_program, check, Project};
+    use crate::cli::Args;
+    use crate::config::{CellWidth, Config};
+    use crate::harness::prepare;
+    use bft_interp::eof::EofBehavior;
+    use bft_types::testspec::{Extractor, TestSpec};
+    use clap::Parser;
+    use std::fs;
+    use std::path::PathBuf;
+
+    const PROJECT: &str = "\
+        include = [\"lib\"]
+
+        [settings]
+        cell-width = 16
+        eof = \"zero\"
+
+        [[program]]
+        name = \"echo\"
+        path = \"echo.bf\"
+        settings = { eof = \"unchanged\" }
+
+        [[program.test]]
+        name = \"inline\"
+        input = \"hi\"
+        output = \"hi\"
+
+        [[program.test]]
+        name = \"files\"
+        input-file = \"in.txt\"
+        output = \"wrong\"
+
+        [[program]]
+        name = \"lib\"
+        path = \"shared.bf\"
+    ";
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "bft-project-{}-{}",
+            name,
+            std::process::id()
+        ));
+        fs::create_dir_all(dir.join("lib")).unwrap();
+        dir
+    }
+
+    fn project_args(flags: &[&str]) -> crate::cli::ProjectArgs {
+        let mut argv = vec!["bft", "test"];
+        argv.extend_from_slice(flags);
+        match Args::parse_from(argv).command {
+            Some(crate::cli::Command::Test(args)) => args,
+            command => panic!("parsed {:?}", command),
+        }
+    }
+
+    #[test]
+    fn test_parse_project() {
+        let root = PathBuf::from("root");
+        let project = Project::parse(PROJECT, &root).unwrap();
+        assert_eq!(project.programs.len(), 2);
+        assert_eq!(project.programs[0].tests.len(), 2);
+        assert_eq!(project.select(&[]).unwrap().len(), 2);
+        let names = ["lib".to_string(), "echo".to_string()];
+        let selected = project.select(&names).unwrap();
+        assert_eq!(selected[0].name, "lib");
+        assert!(project.select(&["cat".to_string()]).is_err());
+
+        // Settings come from the command line, the program and the project.
+        let args = project_args(&["--cell-width", "32"]);
+        let echo = &project.programs[0];
+        let settings = project
+            .settings(echo, &args.run, Config::default())
+            .unwrap();
+        assert_eq!(settings.cell_width, CellWidth::U32);
+        assert_eq!(settings.eof, EofBehavior::Unchanged);
+        let lib = &project.programs[1];
+        let settings =
+            project.settings(lib, &project_args(&[]).run, Config::default());
+        assert_eq!(settings.unwrap().cell_width, CellWidth::U16);
+
+        let twice = "[[program]]\nname = \"a\"\npath = \"a.bf\"\n\
+            [[program]]\nname = \"a\"\npath = \"b.bf\"";
+        assert!(Project::parse(twice, &root).is_err());
+        let both = "[[program]]\nname = \"a\"\npath = \"a.bf\"\n\
+            [[program.test]]\nname = \"t\"\ninput = \"\"\ninput-file = \"x\"";
+        assert!(Project::parse(both, &root).is_err());
+        assert!(Project::parse("programs = []", &root).is_err());
+    }
+
+    #[test]
+    fn test_run_cases() {
+        let root = temp_dir("cases");
+        fs::write(root.join("echo.bf"), ",[.,]").unwrap();
+        fs::write(root.join("lib").join("shared.bf"), "+[").unwrap();
+        fs::write(root.join("lib").join("in.txt"), "right").unwrap();
+        let project = Project::parse(PROJECT, &root).unwrap();
+        let args = project_args(&["--eof", "zero"]);
+
+        let echo = &project.programs[0];
+        let loaded = project
+            .load_program(echo, &args, Config::default())
+            .unwrap();
+        let (program, settings) = loaded;
+        let prepared = prepare(&program, &settings);
+        let run = |case| project.run_case(echo, case, &prepared, &settings);
+        assert!(run(&echo.tests[0]).is_ok());
+        let err = run(&echo.tests[1]).unwrap_err();
+        let err = err.to_string();
+        assert!(err.starts_with("the output was not as expected"));
+        assert!(err.contains("-wrong") && err.contains("+right"));
+        assert_eq!(build_program(&program, &settings).unwrap(), ",[.,]\n");
+
+        // Programs are looked for in the include directories, and must parse.
+        let lib = &project.programs[1];
+        assert!(project.locate(lib, &lib.path).is_ok());
+        assert!(project.load_program(lib, &args, Config::default()).is_err());
+        assert!(project.locate(lib, "missing.bf".as_ref()).is_err());
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_extracted_cases() {
+        let root = temp_dir("extracted");
+        fs::write(root.join("echo.bf"), ",[.,]\nInput: hi\nOutput: hi\n")
+            .unwrap();
+        fs::write(root.join("echo.in"), "right").unwrap();
+        fs::write(root.join("echo.out"), "wrong").unwrap();
+        let project = Project::parse(PROJECT, &root).unwrap();
+        let echo = &project.programs[0];
+        let loaded = project
+            .load_program(
+                echo,
+                &project_args(&["--eof", "zero"]),
+                Config::default(),
+            )
+            .unwrap();
+        let (program, settings) = loaded;
+        let specs = Extractor::new().extract(&program).unwrap();
+        assert_eq!(specs.len(), 2);
+        let prepared = prepare(&program, &settings);
+        let check = |spec: &TestSpec| {
+            check(&prepared, &settings, spec.input(), Some(spec.output()))
+        };
+        let err = check(&specs[0]).unwrap_err();
+        let err = err.to_string();
+        assert!(err.starts_with("the output was not as expected"));
+        assert!(err.contains("-wrong") && err.contains("+right"));
+        assert!(check(&specs[1]).is_ok());
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,730 @@
+//! Replay files, written by `--record` and by `equiv --save-replays`, which
+//! capture everything needed to reproduce a run exactly: the program, the
+//! settings, the input it read and the seeds of anything random which went
+//! into it. `bft replay` runs the program again from the file, and checks that
+//! it does exactly what it did before.
+//!
+//! The Virtual Machine itself is deterministic, so given the same program,
+//! settings and input, a run always takes the same steps and writes the same
+//! output. Host functions, such as the clock, are not: each call a program
+//! makes to one is recorded, tagged with the step and the position in the
+//! source it was made at, along with what the host gave back. A replay gives
+//! back the recorded values, and stops at the first call which is not made
+//! at the same step and position as it was in the recorded run, rather than
+//! quietly drifting from it.
+
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{stdout, BufWriter, Cursor, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::rc::Rc;
+
+use bft_interp::io::{NewlinePolicy, Newlines};
+use bft_interp::syscall::{self, Clock, MonotonicClock, VirtualClock};
+use bft_interp::{CellKind, VirtualMachine};
+use bft_types::vm_error::VirtualMachineError;
+use bft_types::BfProgram;
+use clap::crate_name;
+use serde::{Deserialize, Serialize};
+
+use crate::cache::OutputCache;
+use crate::cli::{IoMode, ReplayArgs};
+use crate::config::{CellWidth, ClockSettings, Settings};
+use crate::load_program;
+use crate::manifest::HashingWriter;
+use crate::run::interpret_vm;
+
+/// The version of the replay format, which is bumped whenever replays written
+/// by older versions of bft could run differently.
+const REPLAY_FORMAT: u32 = 1;
+
+/// A wrapper around Read which keeps a copy of everything read through it, if
+/// asked to.
+pub(crate) struct RecordingReader<R> {
+    reader: R,
+    recorded: Option<Vec<u8>>,
+}
+
+impl<R> RecordingReader<R> {
+    /// Wraps the reader, only keeping what is read if `record` is set.
+    pub(crate) fn new(reader: R, record: bool) -> Self {
+        Self {
+            reader,
+            recorded: record.then(Vec::new),
+        }
+    }
+
+    /// Everything read so far, which is empty if nothing is being recorded.
+    pub(crate) fn recorded(&self) -> &[u8] {
+        self.recorded.as_deref().unwrap_or_default()
+    }
+}
+
+impl<R> Read for RecordingReader<R>
+where
+    R: Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.reader.read(buf)?;
+        if let Some(recorded) = &mut self.recorded {
+            recorded.extend_from_slice(&buf[..read]);
+        }
+        Ok(read)
+    }
+}
+
+/// A call the program made to a host function, with where it was made and
+/// what the host gave back.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct HostCall {
+    /// The name of the host function, such as `clock`.
+    function: String,
+    step: u64,
+    line: usize,
+    column: usize,
+    value: u64,
+}
+
+impl HostCall {
+    /// Describes where the call was made, for reports.
+    fn describe(&self) -> String {
+        format!(
+            "{} at step {} (line {}, column {})",
+            self.function, self.step, self.line, self.column
+        )
+    }
+}
+
+/// The calls made to host functions during a run.
+#[derive(Debug, Default)]
+struct HostCallLog {
+    calls: Vec<HostCall>,
+    /// Whether the calls were recorded before, and are being checked against
+    /// the calls made again, rather than being recorded now.
+    replaying: bool,
+    /// The number of recorded calls which have been made again.
+    made: usize,
+    /// How the replay first differed from the recorded calls.
+    divergence: Option<String>,
+}
+
+impl HostCallLog {
+    /// Records the call, with the value `read` gives back, or checks it
+    /// against the next recorded call and gives back its value. Fails with
+    /// how it differs if it does.
+    fn call(
+        &mut self,
+        mut call: HostCall,
+        read: impl FnOnce() -> u64,
+    ) -> Result<u64, String> {
+        if !self.replaying {
+            call.value = read();
+            self.calls.push(call);
+            return Ok(self.calls[self.calls.len() - 1].value);
+        }
+        let divergence = match self.calls.get(self.made) {
+            Some(recorded)
+                if HostCall {
+                    value: 0,
+                    ..recorded.clone()
+                } == call =>
+            {
+                self.made += 1;
+                return Ok(recorded.value);
+            }
+            Some(recorded) => format!(
+                "the program called {}, where the recorded run called {}",
+                call.describe(),
+                recorded.describe()
+            ),
+            None => format!(
+                "the program called {}, which the recorded run never did",
+                call.describe()
+            ),
+        };
+        Err(self.divergence.insert(divergence).clone())
+    }
+}
+
+/// The calls a run makes to host functions, shared with the handlers of the
+/// Virtual Machine which make them. They are either recorded as they are
+/// made, or checked against those of a recorded run.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct HostCalls {
+    log: Rc<RefCell<HostCallLog>>,
+}
+
+impl HostCalls {
+    /// Checks the calls made against those recorded.
+    fn replaying(calls: Vec<HostCall>) -> Self {
+        let log = HostCallLog {
+            calls,
+            replaying: true,
+            ..HostCallLog::default()
+        };
+        Self {
+            log: Rc::new(RefCell::new(log)),
+        }
+    }
+
+    /// Registers the host functions the settings give the program, so that
+    /// each call to them goes through the log.
+    pub(crate) fn register<'a, T>(
+        &self,
+        vm: &mut VirtualMachine<'a, T>,
+        settings: &Settings,
+    ) where
+        T: CellKind + Default + Clone + Copy + PartialEq + 'a,
+    {
+        let Some(clock) = settings.clock else {
+            return;
+        };
+        let mut source: Box<dyn Clock> = match clock.tick {
+            Some(tick) => Box::new(VirtualClock::new(tick)),
+            None => Box::new(MonotonicClock::new()),
+        };
+        // The clock reads the value the log gives back for each call.
+        let now = Rc::new(Cell::new(0));
+        let mut read_clock = {
+            let now = Rc::clone(&now);
+            syscall::clock(move || now.get(), clock.cells.into())
+        };
+        let log = Rc::clone(&self.log);
+        vm.register_syscall(syscall::CLOCK, move |context| {
+            let instruction = context.instruction();
+            let call = HostCall {
+                function: "clock".to_string(),
+                step: context.steps(),
+                line: instruction.line(),
+                column: instruction.column(),
+                value: 0,
+            };
+            let value = log
+                .borrow_mut()
+                .call(call, || source.millis())
+                .map_err(|divergence| context.fail(divergence))?;
+            now.set(value);
+            read_clock(context)
+        });
+    }
+
+    /// How the calls made differed from those recorded, if they did,
+    /// including recorded calls which were never made again.
+    fn divergence(&self) -> Option<String> {
+        let log = self.log.borrow();
+        if let Some(divergence) = &log.divergence {
+            return Some(divergence.clone());
+        }
+        let missed = log.calls.get(log.made).filter(|_| log.replaying)?;
+        Some(format!(
+            "the recorded run called {}, which the program never did",
+            missed.describe()
+        ))
+    }
+}
+
+/// The program which was run.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct ReplayProgram {
+    path: PathBuf,
+    /// The hash of the instructions of the program, so that changes to the
+    /// program since it was recorded are caught.
+    hash: String,
+}
+
+/// The virtual clock a recorded program read. Runs which read the real clock
+/// cannot be recorded.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+struct ReplayClock {
+    tick: u64,
+    cells: u8,
+}
+
+/// Every setting which changes how a program runs. Those which only change
+/// how its output is shown, such as `--io`, are left out.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct ReplaySettings {
+    cells: usize,
+    cell_width: String,
+    extensible: bool,
+    eof: String,
+    newlines: String,
+    trailing_newline: String,
+    lazy_brackets: bool,
+    max_nesting: usize,
+    strict: bool,
+    max_steps: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    max_output_bytes: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    max_input_bytes: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    clock: Option<ReplayClock>,
+    passes: Option<String>,
+    opt_level: u8,
+}
+
+impl ReplaySettings {
+    fn new(settings: &Settings) -> Self {
+        Self {
+            cells: settings.cells,
+            cell_width: settings.cell_width.to_string(),
+            extensible: settings.extensible,
+            eof: settings.eof.to_string(),
+            newlines: settings.newlines.to_string(),
+            trailing_newline: settings.trailing_newline.to_string(),
+            lazy_brackets: settings.lazy_brackets,
+            max_nesting: settings.max_nesting,
+            strict: settings.strict,
+            max_steps: settings.max_steps,
+            max_output_bytes: settings.max_output_bytes,
+            max_input_bytes: settings.max_input_bytes,
+            clock: settings.clock.and_then(|clock| {
+                clock.tick.map(|tick| ReplayClock {
+                    tick,
+                    cells: clock.cells,
+                })
+            }),
+            passes: settings.passes.clone(),
+            opt_level: settings.opt_level,
+        }
+    }
+
+    /// The settings to run the program with again. A recorded program is
+    /// never refused for not looking like Brainfuck, since it was run before.
+    fn settings(&self) -> Result<Settings, Box<dyn Error>> {
+        Ok(Settings {
+            cells: self.cells,
+            cell_width: self.cell_width.parse()?,
+            extensible: self.extensible,
+            eof: self.eof.parse()?,
+            newlines: self.newlines.parse::<Newlines>()?,
+            echo: false,
+            input_timeout: None,
+            io: IoMode::Raw,
+            trailing_newline: self.trailing_newline.parse::<NewlinePolicy>()?,
+            lazy_brackets: self.lazy_brackets,
+            max_nesting: self.max_nesting,
+            max_program_size: None,
+            min_command_ratio: 0.0,
+            strict_source: false,
+            strict: self.strict,
+            max_steps: self.max_steps,
+            max_output_bytes: self.max_output_bytes,
+            max_input_bytes: self.max_input_bytes,
+            clock: self.clock.map(|clock| ClockSettings {
+                tick: Some(clock.tick),
+                cells: clock.cells,
+            }),
+            passes: self.passes.clone(),
+            opt_level: self.opt_level,
+            // The input recorded is what the program read, after decoding.
+            #[cfg(feature = "plugins")]
+            codec: None,
+        })
+    }
+}
+
+/// What a run did, which a replay must do again exactly.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct ReplayResult {
+    steps: u64,
+    /// The error the run failed with, or None if it halted.
+    error: Option<String>,
+    output_bytes: u64,
+    output_hash: String,
+}
+
+impl ReplayResult {
+    fn new<W>(
+        steps: u64,
+        result: &Result<(), VirtualMachineError>,
+        output: &HashingWriter<W>,
+    ) -> Self {
+        Self {
+            steps,
+            error: result.as_ref().err().map(ToString::to_string),
+            output_bytes: output.len(),
+            output_hash: output.hash().hex(),
+        }
+    }
+
+    /// Describes the result for reports.
+    fn describe(&self) -> String {
+        let exit = match &self.error {
+            Some(error) => format!("failed: {}", error),
+            None => "halted".to_string(),
+        };
+        format!(
+            "{} after {} steps, writing {} bytes ({})",
+            exit, self.steps, self.output_bytes, self.output_hash
+        )
+    }
+}
+
+/// A single run of a program, as stored in a `.bftrun` file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Replay {
+    format: u32,
+    program: ReplayProgram,
+    settings: ReplaySettings,
+    /// The seeds used to generate the run, such as the seed `equiv` fuzzed
+    /// its inputs with, by what used them.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    seeds: BTreeMap<String, u64>,
+    /// The bytes the program read, after newlines were translated.
+    input: Vec<u8>,
+    /// The calls the program made to host functions, if it could make any.
+    /// Replays recorded before calls were recorded have none, and their calls
+    /// are not checked.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    host_calls: Option<Vec<HostCall>>,
+    result: ReplayResult,
+}
+
+impl Replay {
+    /// Records a run of the program with the given settings, which read the
+    /// given input.
+    pub(crate) fn new<W>(
+        program: &BfProgram,
+        settings: &Settings,
+        input: &[u8],
+        steps: u64,
+        result: &Result<(), VirtualMachineError>,
+        output: &HashingWriter<W>,
+    ) -> Self {
+        Self {
+            format: REPLAY_FORMAT,
+            program: ReplayProgram {
+                path: program.filename().to_path_buf(),
+                hash: program.digest().to_string(),
+            },
+            settings: ReplaySettings::new(settings),
+            seeds: BTreeMap::new(),
+            input: input.to_vec(),
+            host_calls: None,
+            result: ReplayResult::new(steps, result, output),
+        }
+    }
+
+    /// Checks that runs with the given settings can be recorded, which they
+    /// cannot if the program reads the real clock.
+    pub(crate) fn check_settings(
+        settings: &Settings,
+    ) -> Result<(), Box<dyn Error>> {
+        match settings.clock {
+            Some(clock) if clock.tick.is_none() => Err("runs which read the \
+                real clock cannot be replayed, use --virtual-clock to record \
+                them"
+                .into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Runs the program with the given settings and input, and records the
+    /// run.
+    pub(crate) fn record(
+        program: &BfProgram,
+        settings: &Settings,
+        input: &[u8],
+    ) -> Result<Self, Box<dyn Error>> {
+        Replay::check_settings(settings)?;
+        let mut output = HashingWriter::new(Vec::new());
+        let host_calls = HostCalls::default();
+        let (steps, result) =
+            rerun(program, settings, input, &host_calls, &mut output)?;
+        Ok(
+            Replay::new(program, settings, input, steps, &result, &output)
+                .with_host_calls(settings, &host_calls),
+        )
+    }
+
+    /// Records the calls the program made to host functions, if the settings
+    /// gave it any to call.
+    pub(crate) fn with_host_calls(
+        mut self,
+        settings: &Settings,
+        host_calls: &HostCalls,
+    ) -> Self {
+        if settings.clock.is_some() {
+            self.host_calls = Some(host_calls.log.borrow().calls.clone());
+        }
+        self
+    }
+
+    /// Records a seed which went into the run.
+    pub(crate) fn with_seed(mut self, name: &str, seed: u64) -> Self {
+        self.seeds.insert(name.to_string(), seed);
+        self
+    }
+
+    /// Reads a replay from the given file.
+    pub(crate) fn from_file(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let replay: Replay = serde_json::from_slice(&fs::read(path)?)
+            .map_err(|err| format!("in {}: {}", path.display(), err))?;
+        if replay.format != REPLAY_FORMAT {
+            return Err(format!(
+                "{} uses replay format {}, but only format {} is supported",
+                path.display(),
+                replay.format,
+                REPLAY_FORMAT
+            )
+            .into());
+        }
+        Ok(replay)
+    }
+
+    /// Loads the program which was recorded, or the one at the given path
+    /// instead, along with the settings it was run with. Fails if the program
+    /// has changed since it was recorded.
+    pub(crate) fn load(
+        &self,
+        program: Option<&Path>,
+    ) -> Result<(BfProgram, Settings), Box<dyn Error>> {
+        let settings = self.settings.settings()?;
+        let path = program.unwrap_or(&self.program.path);
+        let program = load_program(path, &settings)?;
+        let hash = program.digest().to_string();
+        if hash != self.program.hash {
+            return Err(format!(
+                "{} has changed since the run was recorded ({} rather than {})",
+                path.display(),
+                hash,
+                self.program.hash
+            )
+            .into());
+        }
+        Ok((program, settings))
+    }
+
+    /// The bytes the program read.
+    pub(crate) fn input(&self) -> &[u8] {
+        &self.input
+    }
+
+    /// Writes the replay to the given file as JSON.
+    pub(crate) fn write_to(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(&mut writer, self)?;
+        writeln!(writer)?;
+        Ok(())
+    }
+}
+
+/// Runs the program in memory using cells of type `T`, returning the number of
+/// steps taken along with the result.
+fn rerun_as<T>(
+    program: &BfProgram,
+    settings: &Settings,
+    input: &[u8],
+    host_calls: &HostCalls,
+    output: &mut impl Write,
+) -> Result<(u64, Result<(), VirtualMachineError>), Box<dyn Error>>
+where
+    T: CellKind + Default + Clone + Copy + PartialEq,
+{
+    // Optimized just as `run` optimizes it, since the passes change how many
+    // steps are taken.
+    let optimized = match OutputCache::for_settings(settings) {
+        Some(cache) => cache.optimize(program, settings)?,
+        None => settings.optimize(program, true)?,
+    };
+    let ir = optimized.map(|(ir, _)| ir);
+    let mut vm = settings.virtual_machine::<T>(program);
+    host_calls.register(&mut vm, settings);
+    let result =
+        interpret_vm(&mut vm, ir.as_ref(), &mut Cursor::new(input), output);
+    Ok((vm.steps(), result))
+}
+
+/// Runs the program in memory with the given settings and input.
+fn rerun(
+    program: &BfProgram,
+    settings: &Settings,
+    input: &[u8],
+    host_calls: &HostCalls,
+    output: &mut impl Write,
+) -> Result<(u64, Result<(), VirtualMachineError>), Box<dyn Error>> {
+    let (program, calls) = (program, host_calls);
+    match settings.cell_width {
+        CellWidth::U8 => {
+            rerun_as::<u8>(program, settings, input, calls, output)
+        }
+        CellWidth::U16 => {
+            rerun_as::<u16>(program, settings, input, calls, output)
+        }
+        CellWidth::U32 => {
+            rerun_as::<u32>(program, settings, input, calls, output)
+        }
+    }
+}
+
+/// Runs the `replay` subcommand, writing the output of the program to stdout
+/// and failing if the run differs in any way from the recorded one.
+pub(crate) fn run_replay(
+    args: &ReplayArgs,
+) -> Result<ExitCode, Box<dyn Error>> {
+    let replay = Replay::from_file(&args.file)?;
+    let (program, settings) = replay.load(args.program.as_deref())?;
+
+    let mut output = HashingWriter::new(Vec::new());
+    let host_calls = match &replay.host_calls {
+        Some(calls) => HostCalls::replaying(calls.clone()),
+        None => HostCalls::default(),
+    };
+    let (steps, result) =
+        rerun(&program, &settings, &replay.input, &host_calls, &mut output)?;
+    let replayed = ReplayResult::new(steps, &result, &output);
+    stdout().write_all(output.get_ref())?;
+    stdout().flush()?;
+    if let Some(divergence) = host_calls.divergence() {
+        eprintln!(
+            "{}: the replay diverged from the recorded run: {}",
+            crate_name!(),
+            divergence
+        );
+        return Ok(ExitCode::FAILURE);
+    }
+    if replayed != replay.result {
+        eprintln!(
+            "{}: the replay differs from the recorded run\n  recorded: {}\n  \
+            replayed: {}",
+            crate_name!(),
+            replay.result.describe(),
+            replayed.describe()
+        );
+        return Ok(ExitCode::FAILURE);
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        rerun, HashingWriter, HostCall, HostCalls, RecordingReader, Replay,
+        ReplaySettings,
+    };
+    use crate::config::{settings, Settings};
+    use crate::parse_options;
+    use bft_types::BfProgram;
+    use std::io::Read;
+
+    #[test]
+    fn test_recording_reader() {
+        let mut reader = RecordingReader::new(&b"abc"[..], true);
+        let mut buf = [0; 2];
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(reader.recorded(), b"ab");
+
+        let mut reader = RecordingReader::new(&b"abc"[..], false);
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(reader.recorded(), b"");
+    }
+
+    #[test]
+    fn test_settings_round_trip() {
+        let settings = settings(&[
+            "--cell-width",
+            "16",
+            "--eof",
+            "zero",
+            "--crlf-to-lf",
+            "--trailing-newline",
+            "never",
+            "--max-steps",
+            "100",
+            "-O2",
+        ]);
+        let recorded = ReplaySettings::new(&settings).settings().unwrap();
+        // Only the checks made when loading the program are not recorded.
+        assert_eq!(
+            recorded,
+            Settings {
+                min_command_ratio: 0.0,
+                ..settings
+            }
+        );
+    }
+
+    #[test]
+    fn test_replay_round_trip() {
+        let program = BfProgram::new(",[.-]".to_string(), "count.bf").unwrap();
+        let settings = settings(&["--eof", "zero"]);
+        let replay = Replay::record(&program, &settings, b"\x03")
+            .unwrap()
+            .with_seed("equiv", 7);
+        assert_eq!(replay.result.steps, 12);
+        assert_eq!(replay.result.output_bytes, 3);
+        assert_eq!(replay.result.error, None);
+
+        let json = serde_json::to_string(&replay).unwrap();
+        assert!(json.contains(r#""seeds":{"equiv":7}"#));
+        let read: Replay = serde_json::from_str(&json).unwrap();
+        assert_eq!(read, replay);
+        let again = Replay::record(
+            &program,
+            &read.settings.settings().unwrap(),
+            &read.input,
+        )
+        .unwrap();
+        assert_eq!(again.result, replay.result);
+    }
+
+    #[test]
+    fn test_host_calls() {
+        let clock = settings(&["--virtual-clock", "5"]);
+        let program = BfProgram::new_with_options(
+            "+%>.<%>.".to_string(),
+            "time.bf",
+            &parse_options(&clock),
+        )
+        .unwrap();
+        let replay = Replay::record(&program, &clock, b"").unwrap();
+        let calls = replay.host_calls.clone().unwrap();
+        let positions: Vec<_> =
+            calls.iter().map(|call| (call.step, call.column)).collect();
+        assert_eq!(positions, [(2, 2), (6, 6)]);
+        assert!(Replay::record(&program, &settings(&[]), b"")
+            .unwrap()
+            .host_calls
+            .is_none());
+
+        let replay_with = |calls: Vec<HostCall>| {
+            let host_calls = HostCalls::replaying(calls);
+            let mut output = HashingWriter::new(Vec::new());
+            let (_, result) =
+                rerun(&program, &clock, b"", &host_calls, &mut output).unwrap();
+            (
+                output.get_ref().clone(),
+                result.is_ok(),
+                host_calls.divergence(),
+            )
+        };
+        // The recorded values are given back, rather than the clock's.
+        let mut later = calls.clone();
+        later[1].value = 100;
+        assert_eq!(replay_with(later), (vec![0, 100], true, None));
+
+        let mut moved = calls.clone();
+        moved[1].step = 8;
+        let (_, finished, divergence) = replay_with(moved);
+        assert!(!finished);
+        assert_eq!(
+            divergence.unwrap(),
+            "the program called clock at step 6 (line 1, column 6), where the \
+            recorded run called clock at step 8 (line 1, column 6)"
+        );
+        let (_, _, divergence) = replay_with(calls[..1].to_vec());
+        assert!(divergence
+            .unwrap()
+            .ends_with("which the recorded run never did"));
+        let mut extra = calls.clone();
+        extra.push(calls[1].clone());
+        let (_, finished, divergence) = replay_with(extra);
+        assert!(finished);
+        assert!(divergence.unwrap().ends_with("which the program never did"));
+    }
+}
@@ -0,0 +1,89 @@
+//! Reporting on each phase of running a program to stderr, in as much detail
+//! as asked for with `-v`.
+
+use bft_interp::optimizer::PassStats;
+use bft_interp::report::{Reporter, Step};
+use bft_types::BfProgram;
+
+/// The verbosity at which the parsed program is described.
+const PARSE_LEVEL: u8 = 1;
+
+/// The verbosity at which what each optimization pass did is printed.
+const OPTIMIZE_LEVEL: u8 = 2;
+
+/// The verbosity at which each step of the program is traced.
+const TRACE_LEVEL: u8 = 3;
+
+/// Reports to stderr, at the given verbosity, where each level also prints
+/// everything from the levels below it.
+pub(crate) struct StderrReporter {
+    level: u8,
+}
+
+impl StderrReporter {
+    pub(crate) fn new(level: u8) -> Self {
+        Self { level }
+    }
+}
+
+/// Counts the instructions and loops of the program, and how deeply the loops
+/// are nested, as a line to print.
+fn describe(program: &BfProgram) -> String {
+    let stats = program.statistics();
+    format!(
+        "{}: {} instructions, {} loops, nested at most {} deep",
+        program.filename().display(),
+        stats.instructions(),
+        stats.loops(),
+        stats.max_nesting()
+    )
+}
+
+impl Reporter for StderrReporter {
+    fn parsed(&mut self, program: &BfProgram) {
+        if self.level >= PARSE_LEVEL {
+            eprintln!("{}", describe(program));
+        }
+    }
+
+    fn optimized(&mut self, stats: &[PassStats]) {
+        if self.level >= OPTIMIZE_LEVEL {
+            for pass in stats {
+                eprintln!("{}", pass);
+            }
+        }
+    }
+
+    fn traces(&self) -> bool {
+        self.level >= TRACE_LEVEL
+    }
+
+    fn step(&mut self, step: &Step<'_>) {
+        let source = step.source();
+        eprintln!(
+            "{:>8} {}:{} {} head={} cell={}",
+            step.number(),
+            source.line(),
+            source.column(),
+            step.operation(),
+            step.head(),
+            step.cell()
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::describe;
+    use bft_types::BfProgram;
+
+    #[test]
+    fn test_describe() {
+        let program =
+            BfProgram::new("+[>[-]<[>]]-[]".to_string(), "test.bf").unwrap();
+        assert_eq!(
+            describe(&program),
+            "test.bf: 14 instructions, 4 loops, nested at most 2 deep"
+        );
+    }
+}
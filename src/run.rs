@@ -0,0 +1,440 @@
+//! Interpreting a single program, reading from stdin and writing to stdout.
+//! This is what bft does when given just a filename, or the `run` subcommand.
+
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{stdin, stdout, BufWriter, IsTerminal, Read, Write};
+use std::path::Path;
+use std::process::ExitCode;
+use std::time::{Duration, Instant};
+
+use bft_interp::display::describe_error;
+use bft_interp::io::{
+    echo, CancelToken, EchoStyle, HexDump, NewlineReader, NewlineWriter,
+    TrailingNewline, WaitStrategy,
+};
+use bft_interp::ir::IrProgram;
+use bft_interp::metrics::{CsvSink, Metrics, MetricsSink, PrometheusSink};
+use bft_interp::report::Reporter;
+use bft_interp::summary::RunSummary;
+use bft_interp::{CellKind, VirtualMachine};
+use bft_types::memory::MemoryMap;
+use bft_types::vm_error::VirtualMachineError;
+use bft_types::BfProgram;
+
+use crate::cache::OutputCache;
+use crate::cli::{IoMode, RunArgs, RunOnlyArgs};
+use crate::config::{CellWidth, Settings};
+use crate::expect::{CapturingWriter, Expectation};
+use crate::i18n;
+use crate::load_program;
+use crate::manifest::{HashingWriter, Manifest};
+use crate::profile::LoopProfile;
+use crate::replay::{HostCalls, RecordingReader, Replay};
+use crate::report::StderrReporter;
+use crate::steplog::StepRecorder;
+use crate::timings::{TapeUsage, Timings};
+use crate::trace::ChromeTrace;
+
+/// How often a `,` waiting on stdin with a timeout wakes up to check it.
+const INPUT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The streams which programs read from and write to: stdin and stdout, with
+/// their newlines translated, with stdout shown as a hex dump or ended with a
+/// newline as asked for, and with the input echoed into the output if asked
+/// for. The style of the echo depends on whether stdout is a terminal.
+pub(crate) fn standard_streams(
+    settings: &Settings,
+) -> (Box<dyn Read + Send>, Box<dyn Write + Send>) {
+    let wait = match settings.input_timeout {
+        Some(timeout) => WaitStrategy::Poll {
+            interval: INPUT_POLL_INTERVAL,
+            timeout: Some(timeout),
+            cancel: CancelToken::new(),
+        },
+        None => WaitStrategy::Block,
+    };
+    let input: Box<dyn Read + Send> =
+        Box::new(NewlineReader::new(wait.reader(stdin()), settings.newlines));
+    let output: Box<dyn Write + Send> = match settings.io {
+        IoMode::Raw => Box::new(TrailingNewline::new(
+            NewlineWriter::new(stdout(), settings.newlines.reverse()),
+            settings.trailing_newline,
+        )),
+        // Each line of the dump already ends with a newline.
+        IoMode::Hexdump => Box::new(NewlineWriter::new(
+            HexDump::new(stdout()),
+            settings.newlines.reverse(),
+        )),
+    };
+    #[cfg(feature = "plugins")]
+    let (input, output) = crate::plugin::with_codec(settings, input, output);
+    if !settings.echo {
+        return (input, output);
+    }
+    let style = if stdout().is_terminal() {
+        EchoStyle::Dim
+    } else {
+        EchoStyle::Brackets
+    };
+    let (input, output) = echo(input, output, style);
+    (Box::new(input), Box::new(output))
+}
+
+/// Interprets either the lowered program, if there is one, or the original
+/// program which the Virtual Machine was created with.
+pub(crate) fn interpret_vm<T>(
+    vm: &mut VirtualMachine<'_, T>,
+    ir: Option<&IrProgram>,
+    input: &mut impl Read,
+    output: &mut impl Write,
+) -> Result<(), VirtualMachineError>
+where
+    T: CellKind + Default + Clone + Copy + PartialEq,
+{
+    match ir {
+        Some(ir) => vm.interpret_ir(ir, input, output),
+        None => vm.interpret(input, output),
+    }
+}
+
+/// Interprets the program using cells of type `T`, reading from the given
+/// input and writing to the given output, and tracing each step to the
+/// reporter if it asks for them, and the calls made to host functions to
+/// `host_calls` if given. Returns a summary of the run and how much of the tape
+/// was used, along with the result of interpreting the program.
+fn interpret_as<T>(
+    bf_program: &BfProgram,
+    ir: Option<&IrProgram>,
+    settings: &Settings,
+    host_calls: Option<&HostCalls>,
+    reporter: &mut dyn Reporter,
+    input: &mut impl Read,
+    output: &mut impl Write,
+) -> (RunSummary, TapeUsage, Result<(), VirtualMachineError>)
+where
+    T: CellKind + Default + Clone + Copy + PartialEq,
+{
+    let mut interpreter = settings
+        .virtual_machine::<T>(bf_program)
+        .with_reporter(reporter);
+    if let Some(host_calls) = host_calls {
+        host_calls.register(&mut interpreter, settings);
+    }
+    let result = interpret_vm(&mut interpreter, ir, input, output);
+    let tape = TapeUsage {
+        peak_bytes: interpreter.tape_allocation(),
+        final_cells: interpreter.tape().len(),
+    };
+    (interpreter.summary(), tape, result)
+}
+
+/// Describes where a program was most likely stuck, when it ran out of steps,
+/// gas or time: the loops which went round the most, the loops it was inside
+/// of, and how long it had been since it last read or wrote anything.
+fn hang_note(
+    err: &VirtualMachineError,
+    summary: &RunSummary,
+    program: &BfProgram,
+) -> Option<String> {
+    let hang = summary.diagnose_hang(err, program)?;
+    let mut note = String::new();
+    if !hang.hottest_loops().is_empty() {
+        let hottest: Vec<String> = hang
+            .hottest_loops()
+            .iter()
+            .map(|count| {
+                let location = format!("{}:{}", count.line(), count.column());
+                let iterations = abbreviate(count.iterations());
+                i18n::localize(
+                    "hang-loop",
+                    &[("location", &location), ("iterations", &iterations)],
+                    || format!("{} ({} times)", location, iterations),
+                )
+            })
+            .collect();
+        let loops = hottest.join(", ");
+        note += "\n  ";
+        note +=
+            &i18n::localize("hang-hottest-loops", &[("loops", &loops)], || {
+                format!("hottest loops: {}", loops)
+            });
+    }
+    if !hang.loop_stack().is_empty() {
+        let stack: Vec<String> = hang
+            .loop_stack()
+            .iter()
+            .map(|count| format!("{}:{}", count.line(), count.column()))
+            .collect();
+        let loops = stack.join(" > ");
+        note += "\n  ";
+        note += &i18n::localize(
+            "hang-stopped-inside",
+            &[("loops", &loops)],
+            || format!("stopped inside: {}", loops),
+        );
+    }
+    note += "\n  ";
+    note += &match (hang.last_io_step(), hang.steps_since_io()) {
+        (Some(step), Some(since)) => {
+            let since = abbreviate(since);
+            i18n::localize(
+                "hang-last-io",
+                &[("step", &step), ("since", &since)],
+                || {
+                    format!(
+                        "last input or output: step {}, {} steps before \
+                        stopping",
+                        step, since
+                    )
+                },
+            )
+        }
+        _ => i18n::localize("hang-no-io", &[], || {
+            "last input or output: never".to_string()
+        }),
+    };
+    Some(note)
+}
+
+/// Shortens large counts to a few figures, such as 2.1B for 2,123,456,789.
+fn abbreviate(count: u64) -> String {
+    const UNITS: [(u64, &str); 4] = [
+        (1_000_000_000_000, "T"),
+        (1_000_000_000, "B"),
+        (1_000_000, "M"),
+        (1_000, "K"),
+    ];
+    match UNITS.iter().find(|(unit, _)| count >= *unit) {
+        Some((unit, suffix)) => {
+            format!("{:.1}{}", count as f64 / *unit as f64, suffix)
+        }
+        None => count.to_string(),
+    }
+}
+
+/// Interprets the program in the given file, reading from stdin and writing to
+/// stdout.
+pub(crate) fn run_program(
+    filename: &Path,
+    arguments: &RunArgs,
+    run_only: &RunOnlyArgs,
+) -> Result<ExitCode, Box<dyn Error>> {
+    let settings = Settings::from_args(arguments)?;
+    let recording =
+        run_only.record.is_some() || run_only.record_steps.is_some();
+    if recording {
+        Replay::check_settings(&settings)?;
+    }
+    let expectation = Expectation::from_args(run_only)?;
+    let memory_map = match &run_only.memory_map {
+        Some(path) => Some(MemoryMap::from_file(path)?),
+        None => None,
+    };
+    let trace = match &run_only.trace_out {
+        Some(path) => {
+            Some(ChromeTrace::new(BufWriter::new(File::create(path)?)))
+        }
+        None => None,
+    };
+    let profile = run_only.profile.then(LoopProfile::default);
+    let metrics = match &run_only.metrics {
+        Some(path) => {
+            let sink: Box<dyn MetricsSink> = match path.extension() {
+                Some(extension) if extension == "prom" => {
+                    Box::new(PrometheusSink::new(path))
+                }
+                _ => {
+                    Box::new(CsvSink::new(BufWriter::new(File::create(path)?)))
+                }
+            };
+            Some(Metrics::new(sink, run_only.metrics_interval))
+        }
+        None => None,
+    };
+    let steps = match &run_only.record_steps {
+        Some(path) => {
+            Some(StepRecorder::new(BufWriter::new(File::create(path)?))?)
+        }
+        None => None,
+    };
+    let mut reporter = (
+        StderrReporter::new(run_only.verbose),
+        (trace, (profile, (steps, metrics))),
+    );
+    let start = Instant::now();
+    let bf_program = load_program(filename, &settings)?;
+    let loaded = start.elapsed();
+    reporter.parsed(&bf_program);
+    let start = Instant::now();
+    let optimized = match OutputCache::for_settings(&settings) {
+        Some(cache) => cache.optimize(&bf_program, &settings)?,
+        None => settings.optimize(&bf_program, true)?,
+    };
+    let ir = match optimized {
+        Some((ir, stats)) => {
+            reporter.optimized(&stats);
+            Some(ir)
+        }
+        None => None,
+    };
+    // Step logs are positions in the original program, which the lowered
+    // program does not keep.
+    if ir.is_some() && run_only.record_steps.is_some() {
+        return Err("--record-steps cannot be used with the optimizer".into());
+    }
+    let optimize = start.elapsed();
+
+    let start = Instant::now();
+    let (input, output) = standard_streams(&settings);
+    let mut input = RecordingReader::new(input, recording);
+    let output = CapturingWriter::new(output, expectation.checks_output());
+    let mut output = HashingWriter::new(output);
+    let host_calls = HostCalls::default();
+    let recorded_calls = recording.then_some(&host_calls);
+    let (summary, tape, result) = match settings.cell_width {
+        CellWidth::U8 => interpret_as::<u8>(
+            &bf_program,
+            ir.as_ref(),
+            &settings,
+            recorded_calls,
+            &mut reporter,
+            &mut input,
+            &mut output,
+        ),
+        CellWidth::U16 => interpret_as::<u16>(
+            &bf_program,
+            ir.as_ref(),
+            &settings,
+            recorded_calls,
+            &mut reporter,
+            &mut input,
+            &mut output,
+        ),
+        CellWidth::U32 => interpret_as::<u32>(
+            &bf_program,
+            ir.as_ref(),
+            &settings,
+            recorded_calls,
+            &mut reporter,
+            &mut input,
+            &mut output,
+        ),
+    };
+    let duration = start.elapsed();
+    let steps = summary.steps();
+
+    let (_, (trace, (profile, (step_log, metrics)))) = reporter;
+    if let Some(trace) = trace {
+        trace.finish()?;
+    }
+    if let Some(metrics) = metrics {
+        metrics.finish()?;
+    }
+    if recording {
+        let replay = Replay::new(
+            &bf_program,
+            &settings,
+            input.recorded(),
+            steps,
+            &result,
+            &output,
+        )
+        .with_host_calls(&settings, &host_calls);
+        if let Some(path) = &run_only.record {
+            replay.write_to(path)?;
+        }
+        if let Some(step_log) = step_log {
+            step_log.finish(replay)?;
+        }
+    }
+    if let Some(path) = &run_only.emit_manifest {
+        Manifest::new(&bf_program, &settings)
+            .finished(duration, steps, &result, &output)
+            .write_to(path)?;
+    }
+    let written = output.get_ref().captured().to_vec();
+    // Ends the output, with its trailing newline, before anything else is
+    // printed.
+    drop((input, output));
+
+    if run_only.timings {
+        let timings = Timings {
+            parse: loaded.saturating_sub(bf_program.bracket_time()),
+            brackets: bf_program.bracket_time(),
+            optimize,
+            execute: duration,
+            tape,
+        };
+        eprintln!("{}", timings);
+    }
+    if let Some(profile) = profile {
+        eprintln!("{}", profile.summary());
+        if let Some(path) = &run_only.flamegraph {
+            let graph = match path.extension() {
+                Some(extension) if extension == "svg" => profile.flamegraph(),
+                _ => profile.folded(),
+            };
+            fs::write(path, graph)?;
+        }
+    }
+    let failure = result.err().map(|err| {
+        let mut message = match &memory_map {
+            Some(map) => describe_error(&err, map),
+            None => i18n::error_message(&err),
+        };
+        if let Some(note) = hang_note(&err, &summary, &bf_program) {
+            message += &note;
+        }
+        message
+    });
+    expectation.check(&written, failure)?;
+    Ok(ExitCode::SUCCESS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{abbreviate, hang_note};
+    use bft_interp::VirtualMachine;
+    use bft_types::BfProgram;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_abbreviate() {
+        assert_eq!(abbreviate(0), "0");
+        assert_eq!(abbreviate(999), "999");
+        assert_eq!(abbreviate(12_345), "12.3K");
+        assert_eq!(abbreviate(2_123_456_789), "2.1B");
+    }
+
+    #[test]
+    fn test_hang_note() {
+        let source = "+[>+<]\n+[]".to_string();
+        let program = BfProgram::new(source, "stuck.bf").unwrap();
+        let mut vm =
+            VirtualMachine::<u8>::new(&program, 2, false).with_step_limit(50);
+        let mut input = Cursor::new(Vec::new());
+        let err = vm.interpret(&mut input, &mut Vec::new()).unwrap_err();
+        let note = hang_note(&err, &vm.summary(), &program).unwrap();
+        assert_eq!(
+            note,
+            "\n  hottest loops: 1:2 (11 times)\
+             \n  stopped inside: 1:2\
+             \n  last input or output: never"
+        );
+
+        let program = BfProgram::new(",.+[]".to_string(), "echo.bf").unwrap();
+        let mut vm =
+            VirtualMachine::<u8>::new(&program, 1, false).with_step_limit(20);
+        let mut input = Cursor::new(vec![1]);
+        let err = vm.interpret(&mut input, &mut Vec::new()).unwrap_err();
+        let note = hang_note(&err, &vm.summary(), &program).unwrap();
+        assert!(note.ends_with("output: step 2, 18 steps before stopping"));
+
+        let program = BfProgram::new(",".to_string(), "read.bf").unwrap();
+        let mut vm = VirtualMachine::<u8>::new(&program, 1, false);
+        let mut input = Cursor::new(Vec::new());
+        let err = vm.interpret(&mut input, &mut Vec::new()).unwrap_err();
+        assert_eq!(hang_note(&err, &vm.summary(), &program), None);
+    }
+}
@@ -0,0 +1,176 @@
+//! The `new` subcommand, which sets up a directory with a starter program, a
+//! project file and a test for it, from the templates built into bft.
+
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use crate::cli::NewArgs;
+
+/// The placeholder in the templates which is replaced with the name of the
+/// project.
+const NAME: &str = "{{name}}";
+
+/// The files of every new project, as where each is written and its template.
+const TEMPLATES: &[(&str, &str)] = &[
+    (
+        "bfproject.toml",
+        include_str!("../templates/new/bfproject.toml"),
+    ),
+    ("{{name}}.bf", include_str!("../templates/new/program.bf")),
+    (
+        "tests/input.txt",
+        include_str!("../templates/new/tests/input.txt"),
+    ),
+    (
+        "tests/expected.txt",
+        include_str!("../templates/new/tests/expected.txt"),
+    ),
+];
+
+/// The files added for `--github`.
+const GITHUB_TEMPLATES: &[(&str, &str)] = &[
+    (
+        "README.md",
+        include_str!("../templates/new/github/README.md"),
+    ),
+    (
+        ".gitignore",
+        include_str!("../templates/new/github/gitignore"),
+    ),
+    (
+        ".github/workflows/bft.yml",
+        include_str!("../templates/new/github/workflow.yml"),
+    ),
+];
+
+/// Checks that the name can be used for the project, and as the name of a
+/// file and a program in it.
+fn check_name(name: &str) -> Result<(), String> {
+    let valid = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+    if name.is_empty() || !name.chars().all(valid) {
+        return Err(format!(
+            "'{}' cannot be used as the name of a project, which may only \
+            have letters, digits, '-' and '_'",
+            name
+        ));
+    }
+    Ok(())
+}
+
+/// The files of a new project with the given name, as where each goes in
+/// the directory of the project and what it holds.
+fn scaffold(name: &str, github: bool) -> Vec<(PathBuf, String)> {
+    let github = if github { GITHUB_TEMPLATES } else { &[] };
+    TEMPLATES
+        .iter()
+        .chain(github)
+        .map(|(path, template)| {
+            (
+                PathBuf::from(path.replace(NAME, name)),
+                template.replace(NAME, name),
+            )
+        })
+        .collect()
+}
+
+/// Writes the files of a new project into the directory, which must not
+/// already have anything in it.
+fn write_project(
+    dir: &Path,
+    files: &[(PathBuf, String)],
+) -> Result<(), Box<dyn Error>> {
+    if dir.exists() && fs::read_dir(dir)?.next().is_some() {
+        return Err(format!("{} is not empty", dir.display()).into());
+    }
+    for (path, contents) in files {
+        let path = dir.join(path);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, contents)?;
+    }
+    Ok(())
+}
+
+/// Runs the `new` subcommand.
+pub(crate) fn run_new(args: &NewArgs) -> Result<ExitCode, Box<dyn Error>> {
+    let name = args
+        .dir
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or_default();
+    check_name(name)?;
+    write_project(&args.dir, &scaffold(name, args.github))?;
+    println!(
+        "Created {}, try `bft test` in it to run its test.",
+        args.dir.display()
+    );
+    Ok(ExitCode::SUCCESS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_name, scaffold, write_project};
+    use crate::config::{run_args, Config, Settings};
+    use crate::harness::{execute, Outcome};
+    use bft_types::BfProgram;
+    use std::fs;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_check_name() {
+        assert!(check_name("hello_world-2").is_ok());
+        assert!(check_name("").is_err());
+        assert!(check_name("two words").is_err());
+        assert!(check_name("a+b").is_err());
+    }
+
+    #[test]
+    fn test_scaffold() {
+        let files = scaffold("greet", false);
+        let paths: Vec<_> = files.iter().map(|(path, _)| path).collect();
+        assert_eq!(
+            paths,
+            [
+                "bfproject.toml",
+                "greet.bf",
+                "tests/input.txt",
+                "tests/expected.txt"
+            ]
+            .map(PathBuf::from)
+            .iter()
+            .collect::<Vec<_>>()
+        );
+        assert!(files[0].1.contains("path = \"greet.bf\""));
+        let github = scaffold("greet", true);
+        assert!(github.iter().any(|(path, contents)| {
+            path.ends_with("README.md") && contents.starts_with("# greet\n")
+        }));
+
+        // The starter program passes its test under the project settings.
+        let config: Config = toml::from_str("eof = \"zero\"").unwrap();
+        let settings = Settings::resolve(&run_args(&[]), config).unwrap();
+        let program = BfProgram::new(files[1].1.clone(), "greet.bf").unwrap();
+        let ratio = settings.min_command_ratio;
+        assert!(program.profile().looks_like_brainfuck(ratio));
+        let execution =
+            execute(&program, &settings, files[2].1.as_bytes(), 1000);
+        assert!(matches!(execution.outcome, Outcome::Halted));
+        assert_eq!(execution.output, files[3].1.as_bytes());
+    }
+
+    #[test]
+    fn test_write_project() {
+        let dir = std::env::temp_dir()
+            .join(format!("bft-new-{}", std::process::id()))
+            .join("greet");
+        let files = scaffold("greet", true);
+        write_project(&dir, &files).unwrap();
+        let workflow = dir.join(".github").join("workflows").join("bft.yml");
+        assert!(fs::read_to_string(workflow).unwrap().contains("bft test"));
+        assert!(write_project(&dir, &files).is_err());
+        fs::remove_dir_all(dir.parent().unwrap()).unwrap();
+    }
+}
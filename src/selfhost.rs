@@ -0,0 +1,159 @@
+//! The `selfhost` subcommand, which runs a program under dbfi, Daniel B
+//! Cristofani's Brainfuck interpreter written in Brainfuck, and checks that
+//! it does the same as when run directly. The interpreter, its input handling
+//! and the optimizer all get a thorough workout from running dbfi, and the
+//! number of steps it takes shows how much work the optimizer saves.
+
+use std::error::Error;
+use std::fs;
+use std::process::ExitCode;
+
+use bft_types::ops::Operation;
+use bft_types::BfProgram;
+
+use crate::cli::SelfhostArgs;
+use crate::config::Settings;
+use crate::harness::{execute, Execution, Outcome, DEFAULT_STEP_LIMIT};
+use crate::load_program;
+
+/// The source of dbfi, which reads a program, then `!`, then the input of the
+/// program.
+const DBFI: &str = include_str!("../bf-programs/dbfi.bf");
+
+/// The input for dbfi to run the program on the input with: the commands of
+/// the program, without any comments which could contain a `!`, then `!`,
+/// then the input.
+fn hosted_input(program: &BfProgram, input: &[u8]) -> Result<Vec<u8>, String> {
+    let mut hosted = Vec::with_capacity(program.instructions().len() + 1);
+    for instruction in program.instructions() {
+        match instruction.operation() {
+            Operation::Extension(name) => {
+                return Err(format!(
+                    "dbfi cannot run the extension '{}' on line {} column {}",
+                    name,
+                    instruction.line(),
+                    instruction.column()
+                ))
+            }
+            operation => hosted.push(operation.to_char() as u8),
+        }
+    }
+    hosted.push(b'!');
+    hosted.extend_from_slice(input);
+    Ok(hosted)
+}
+
+/// Runs the program on the input directly, then under dbfi unless the direct
+/// run was cut short by the step limit. Each instruction takes dbfi hundreds
+/// of steps, and the optimizer may leave few steps for the direct run, so
+/// dbfi is only limited by the step limit if one was given.
+fn run_both(
+    program: &BfProgram,
+    settings: &Settings,
+    input: &[u8],
+) -> Result<(Execution, Option<Execution>), Box<dyn Error>> {
+    let dbfi = BfProgram::new(DBFI.to_string(), "dbfi.bf")?;
+    let step_limit = settings.max_steps.unwrap_or(DEFAULT_STEP_LIMIT);
+    let direct = execute(program, settings, input, step_limit);
+    if matches!(direct.outcome, Outcome::StepLimit) {
+        return Ok((direct, None));
+    }
+    let hosted = execute(
+        &dbfi,
+        settings,
+        &hosted_input(program, input)?,
+        settings.max_steps.unwrap_or(u64::MAX),
+    );
+    Ok((direct, Some(hosted)))
+}
+
+/// Describes a single run for the report.
+fn describe(name: &str, execution: &Execution) -> String {
+    format!(
+        "  {}: output \"{}\" and {} after {} steps",
+        name,
+        execution.output.escape_ascii(),
+        execution.outcome.describe(),
+        execution.steps
+    )
+}
+
+/// Runs the program directly and under dbfi, reporting whether the two runs
+/// agree.
+pub(crate) fn run_selfhost(
+    args: &SelfhostArgs,
+) -> Result<ExitCode, Box<dyn Error>> {
+    let settings = Settings::from_args(&args.run)?;
+    let program = load_program(&args.program, &settings)?;
+    let input = match &args.input {
+        Some(path) => fs::read(path)?,
+        None => Vec::new(),
+    };
+    let (direct, hosted) = run_both(&program, &settings, &input)?;
+    println!("{}", describe("directly", &direct));
+    let Some(hosted) = hosted else {
+        println!("Inconclusive due to the step limit.");
+        return Ok(ExitCode::SUCCESS);
+    };
+    println!("{}", describe("under dbfi", &hosted));
+
+    if matches!(hosted.outcome, Outcome::StepLimit) {
+        println!("Inconclusive due to the step limit.");
+    } else if direct.output != hosted.output
+        || !direct.outcome.same_kind(&hosted.outcome)
+    {
+        println!("dbfi disagrees with running the program directly.");
+        return Ok(ExitCode::FAILURE);
+    } else {
+        println!("dbfi agrees with running the program directly.");
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{hosted_input, run_both};
+    use crate::config::settings;
+    use crate::harness::Outcome;
+    use bft_types::options::ParseOptions;
+    use bft_types::BfProgram;
+
+    #[test]
+    fn test_hosted_input() {
+        let program =
+            BfProgram::new("echo! ,[.,]".to_string(), "echo.bf").unwrap();
+        assert_eq!(hosted_input(&program, b"hi").unwrap(), b",[.,]!hi");
+
+        let options = ParseOptions::new().extension('#');
+        let program =
+            BfProgram::new_with_options("+#".to_string(), "debug.bf", &options)
+                .unwrap();
+        assert!(hosted_input(&program, b"").is_err());
+    }
+
+    #[test]
+    fn test_run_both() {
+        let program = BfProgram::new(",[.,]".to_string(), "echo.bf").unwrap();
+        for flags in [&["--eof", "zero"][..], &["-O3", "--eof", "zero"]] {
+            let (direct, hosted) =
+                run_both(&program, &settings(flags), b"hi").unwrap();
+            let hosted = hosted.unwrap();
+            assert_eq!(direct.output, b"hi");
+            assert_eq!(hosted.output, b"hi");
+            assert!(matches!(hosted.outcome, Outcome::Halted));
+            assert!(hosted.steps > direct.steps);
+        }
+
+        // Running out of input fails the same way in both.
+        let (direct, hosted) =
+            run_both(&program, &settings(&[]), b"hi").unwrap();
+        assert!(matches!(direct.outcome, Outcome::Error(_)));
+        assert!(direct.outcome.same_kind(&hosted.unwrap().outcome));
+
+        // dbfi is not run when the program itself is cut short.
+        let program = BfProgram::new("+[]".to_string(), "hang.bf").unwrap();
+        let settings = settings(&["--max-steps", "100"]);
+        let (_, hosted) = run_both(&program, &settings, b"").unwrap();
+        assert!(hosted.is_none());
+    }
+}
@@ -0,0 +1,608 @@
+//! The `serve-stdio` subcommand, which lets GUIs and web frontends written in
+//! any language drive the interpreter as a subprocess. Requests are read from
+//! stdin and responses written to stdout, one JSON-RPC 2.0 message to a line:
+//!
+//! ```text
+//! --> {"jsonrpc": "2.0", "id": 1, "method": "load", "params": {"path": "cat.bf"}}
+//! <-- {"jsonrpc": "2.0", "id": 1, "result": {"instructions": 5, ...}}
+//! ```
+//!
+//! The methods are:
+//! - `load`, with `path`, or `source` and an optional `filename`: loads a
+//!   program, starting it from the beginning on a fresh tape.
+//! - `step`, with an optional `count`: runs that many steps, or one.
+//! - `run`, with an optional `max_steps`: runs until the program halts, fails,
+//!   reaches a breakpoint or needs input which has not been given, or has
+//!   taken `max_steps` steps, which defaults to the step limit.
+//! - `set_breakpoints`, with `positions` of instructions and `lines`: replaces
+//!   the breakpoints. A breakpoint on a line is on its first instruction.
+//! - `read_tape`, with optional `start` and `length`: the cells of the tape.
+//! - `provide_input`, with `data` and `eof`: adds to the input, and says
+//!   whether it has ended.
+//! - `take_output`: everything written since it was last taken.
+//! - `state`: where the program has got to, as `step` and `run` give.
+//! - `shutdown`: stops the server.
+
+use std::collections::{BTreeSet, VecDeque};
+use std::error::Error;
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use bft_interp::resume::Event;
+use bft_interp::CellKind;
+use bft_types::vm_error::VirtualMachineError;
+use bft_types::BfProgram;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::cli::ServeStdioArgs;
+use crate::config::{CellWidth, Settings};
+use crate::harness::DEFAULT_STEP_LIMIT;
+use crate::{load_program, parse_options};
+
+/// The error code for a line which is not JSON.
+const PARSE_ERROR: i64 = -32700;
+
+/// The error code for JSON which is not a request.
+const INVALID_REQUEST: i64 = -32600;
+
+/// The error code for a method which does not exist.
+const METHOD_NOT_FOUND: i64 = -32601;
+
+/// The error code for parameters which do not suit the method.
+const INVALID_PARAMS: i64 = -32602;
+
+/// The error code for requests which cannot be carried out, such as running
+/// a program before one is loaded.
+const SERVER_ERROR: i64 = -32000;
+
+/// An error to respond to a request with.
+#[derive(Debug)]
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl ToString) -> Self {
+        Self {
+            code,
+            message: message.to_string(),
+        }
+    }
+}
+
+/// A request, or a notification if it has no id.
+#[derive(Debug, Deserialize)]
+struct Request {
+    id: Option<Value>,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LoadParams {
+    path: Option<PathBuf>,
+    source: Option<String>,
+    filename: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct StepParams {
+    #[serde(default = "one")]
+    count: u64,
+}
+
+/// The number of steps `step` takes when no count is given.
+fn one() -> u64 {
+    1
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RunParams {
+    max_steps: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BreakpointParams {
+    #[serde(default)]
+    positions: Vec<usize>,
+    #[serde(default)]
+    lines: Vec<usize>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ReadTapeParams {
+    #[serde(default)]
+    start: usize,
+    length: Option<usize>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct InputParams {
+    #[serde(default)]
+    data: String,
+    #[serde(default)]
+    eof: bool,
+}
+
+/// Why the program stopped running.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Status {
+    /// It took as many steps as it was asked to, or has not run yet.
+    Paused,
+    /// It reached an instruction with a breakpoint on it.
+    Breakpoint,
+    /// It reached a `,` with no input to read.
+    WaitingForInput,
+    /// It ran to the end.
+    Halted,
+    /// It failed with an error.
+    Failed,
+}
+
+/// Where the program has got to, as given in responses.
+#[derive(Debug, Serialize)]
+struct StateRecord {
+    status: Status,
+    steps: u64,
+    position: usize,
+    /// The line and column of the instruction about to run, if there is one.
+    line: Option<usize>,
+    column: Option<usize>,
+    head: usize,
+    cell: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// The program being run, and everything about the run which lasts between
+/// requests. The Virtual Machine itself is rebuilt from the tape for each
+/// request, as it borrows the program.
+struct Session<T> {
+    settings: Settings,
+    program: Option<BfProgram>,
+    tape: Vec<T>,
+    head: usize,
+    position: usize,
+    steps: u64,
+    input: VecDeque<u8>,
+    /// Whether the input has ended once `input` is used up.
+    eof: bool,
+    output: Vec<u8>,
+    breakpoints: BTreeSet<usize>,
+    status: Status,
+    error: Option<String>,
+}
+
+impl<T> Session<T>
+where
+    T: CellKind + Default + Clone + Copy + PartialEq,
+{
+    fn new(settings: Settings) -> Self {
+        Self {
+            settings,
+            program: None,
+            tape: Vec::new(),
+            head: 0,
+            position: 0,
+            steps: 0,
+            input: VecDeque::new(),
+            eof: false,
+            output: Vec::new(),
+            breakpoints: BTreeSet::new(),
+            status: Status::Paused,
+            error: None,
+        }
+    }
+
+    /// The program, or an error if none has been loaded.
+    fn program(&self) -> Result<&BfProgram, RpcError> {
+        self.program
+            .as_ref()
+            .ok_or_else(|| RpcError::new(SERVER_ERROR, "no program is loaded"))
+    }
+
+    fn load(&mut self, params: LoadParams) -> Result<Value, RpcError> {
+        let program = match params {
+            LoadParams {
+                path: Some(path),
+                source: None,
+                filename: None,
+            } => load_program(&path, &self.settings),
+            LoadParams {
+                path: None,
+                source: Some(source),
+                filename,
+            } => BfProgram::new_with_options(
+                source,
+                filename.as_deref().unwrap_or("<source>"),
+                &parse_options(&self.settings),
+            )
+            .map_err(Into::into),
+            _ => {
+                return Err(RpcError::new(
+                    INVALID_PARAMS,
+                    "expected either a path, or a source and filename",
+                ))
+            }
+        }
+        .map_err(|err| RpcError::new(SERVER_ERROR, err))?;
+        self.tape =
+            self.settings.virtual_machine::<T>(&program).tape().to_vec();
+        self.program = Some(program);
+        (self.head, self.position, self.steps) = (0, 0, 0);
+        self.input.clear();
+        self.eof = false;
+        self.output.clear();
+        self.breakpoints.clear();
+        self.status = Status::Paused;
+        self.error = None;
+        let instructions = self.program()?.instructions().len();
+        Ok(json!({ "instructions": instructions, "state": self.state()? }))
+    }
+
+    /// Runs the program for up to the given number of steps, stopping at
+    /// breakpoints if asked to, other than one it starts at.
+    fn execute(
+        &mut self,
+        budget: u64,
+        breakpoints: bool,
+    ) -> Result<Value, RpcError> {
+        if matches!(self.status, Status::Halted | Status::Failed) {
+            return Err(RpcError::new(
+                SERVER_ERROR,
+                "the program has finished, load it again to run it again",
+            ));
+        }
+        // The program is taken out while it runs, as the Virtual Machine
+        // borrows it while the rest of the session changes.
+        self.program()?;
+        let program = self.program.take().expect("a program is loaded");
+        let mut vm = self.settings.virtual_machine::<T>(&program);
+        let restored =
+            vm.restore(&self.tape, self.head, self.position, self.steps);
+        if let Err(err) = restored {
+            drop(vm);
+            self.program = Some(program);
+            return Err(RpcError::new(SERVER_ERROR, err));
+        }
+        let (start, end) = (self.steps, self.steps.saturating_add(budget));
+        // With breakpoints, the program runs a step at a time so that it can
+        // stop at them.
+        let single = breakpoints && !self.breakpoints.is_empty();
+        let status = loop {
+            if vm.steps() == end {
+                break Status::Paused;
+            }
+            if single
+                && vm.steps() != start
+                && self.breakpoints.contains(&vm.program_position())
+            {
+                break Status::Breakpoint;
+            }
+            let limit = if single { vm.steps() + 1 } else { end };
+            vm.set_step_limit(Some(limit));
+            match vm.interpret_resumable() {
+                Ok(Event::NeedsInput) => match self.input.pop_front() {
+                    Some(byte) => vm.provide_input(Some(byte)),
+                    None if self.eof => vm.provide_input(None),
+                    None => break Status::WaitingForInput,
+                },
+                Ok(Event::ProducedOutput(byte)) => self.output.push(byte),
+                Ok(Event::Halted) => break Status::Halted,
+                Err(VirtualMachineError::StepLimitExceeded { .. }) => {}
+                Err(err) => {
+                    self.error = Some(err.to_string());
+                    break Status::Failed;
+                }
+            }
+        };
+        self.tape = vm.tape().to_vec();
+        (self.head, self.position, self.steps) =
+            (vm.tape_head(), vm.program_position(), vm.steps());
+        drop(vm);
+        self.program = Some(program);
+        self.status = status;
+        Ok(serde_json::to_value(self.state()?).expect("states serialize"))
+    }
+
+    fn set_breakpoints(
+        &mut self,
+        params: BreakpointParams,
+    ) -> Result<Value, RpcError> {
+        let instructions = self.program()?.instructions();
+        let mut breakpoints = BTreeSet::new();
+        for position in params.positions {
+            if position >= instructions.len() {
+                return Err(RpcError::new(
+                    INVALID_PARAMS,
+                    format!("there is no instruction {}", position),
+                ));
+            }
+            breakpoints.insert(position);
+        }
+        for line in params.lines {
+            let position = instructions
+                .iter()
+                .position(|instruction| instruction.line() == line)
+                .ok_or_else(|| {
+                    RpcError::new(
+                        INVALID_PARAMS,
+                        format!("there are no instructions on line {}", line),
+                    )
+                })?;
+            breakpoints.insert(position);
+        }
+        self.breakpoints = breakpoints;
+        Ok(json!({ "positions": self.breakpoints }))
+    }
+
+    fn read_tape(&self, params: ReadTapeParams) -> Result<Value, RpcError> {
+        self.program()?;
+        let start = params.start.min(self.tape.len());
+        let end = match params.length {
+            Some(length) => start.saturating_add(length).min(self.tape.len()),
+            None => self.tape.len(),
+        };
+        let cells: Vec<u32> = self.tape[start..end]
+            .iter()
+            .map(|cell| cell.to_u32())
+            .collect();
+        Ok(json!({ "start": start, "head": self.head, "cells": cells }))
+    }
+
+    fn provide_input(&mut self, params: InputParams) -> Value {
+        self.input.extend(params.data.bytes());
+        self.eof |= params.eof;
+        json!({ "buffered": self.input.len(), "eof": self.eof })
+    }
+
+    fn take_output(&mut self) -> Value {
+        let output = std::mem::take(&mut self.output);
+        json!({
+            "text": String::from_utf8_lossy(&output),
+            "bytes": output,
+        })
+    }
+
+    fn state(&self) -> Result<StateRecord, RpcError> {
+        let instruction = self.program()?.instructions().get(self.position);
+        Ok(StateRecord {
+            status: self.status,
+            steps: self.steps,
+            position: self.position,
+            line: instruction.map(|instruction| instruction.line()),
+            column: instruction.map(|instruction| instruction.column()),
+            head: self.head,
+            cell: self.tape.get(self.head).map_or(0, |cell| cell.to_u32()),
+            error: self.error.clone(),
+        })
+    }
+
+    /// Carries out a request, giving its result.
+    fn call(&mut self, method: &str, params: Value) -> Result<Value, RpcError> {
+        match method {
+            "load" => self.load(parse_params(params)?),
+            "step" => {
+                let params: StepParams = parse_params(params)?;
+                self.execute(params.count, false)
+            }
+            "run" => {
+                let params: RunParams = parse_params(params)?;
+                let budget = params
+                    .max_steps
+                    .or(self.settings.max_steps)
+                    .unwrap_or(DEFAULT_STEP_LIMIT);
+                self.execute(budget, true)
+            }
+            "set_breakpoints" => self.set_breakpoints(parse_params(params)?),
+            "read_tape" => self.read_tape(parse_params(params)?),
+            "provide_input" => Ok(self.provide_input(parse_params(params)?)),
+            "take_output" => Ok(self.take_output()),
+            "state" => {
+                Ok(serde_json::to_value(self.state()?)
+                    .expect("states serialize"))
+            }
+            "shutdown" => Ok(Value::Null),
+            _ => Err(RpcError::new(
+                METHOD_NOT_FOUND,
+                format!("unknown method '{}'", method),
+            )),
+        }
+    }
+
+    /// Handles a line of input, giving the response to write, if any, and
+    /// whether the server should stop.
+    fn handle(&mut self, line: &str) -> (Option<Value>, bool) {
+        let value: Value = match serde_json::from_str(line) {
+            Ok(value) => value,
+            Err(err) => {
+                let error = RpcError::new(PARSE_ERROR, err);
+                return (Some(response(Value::Null, Err(error))), false);
+            }
+        };
+        let request: Request = match serde_json::from_value(value) {
+            Ok(request) => request,
+            Err(err) => {
+                let error = RpcError::new(INVALID_REQUEST, err);
+                return (Some(response(Value::Null, Err(error))), false);
+            }
+        };
+        let result = self.call(&request.method, request.params);
+        let shutdown = request.method == "shutdown";
+        (request.id.map(|id| response(id, result)), shutdown)
+    }
+}
+
+/// Reads the parameters of a method, which may be left out if every one of
+/// them is optional.
+fn parse_params<P: DeserializeOwned>(params: Value) -> Result<P, RpcError> {
+    let params = match params {
+        Value::Null => json!({}),
+        params => params,
+    };
+    serde_json::from_value(params)
+        .map_err(|err| RpcError::new(INVALID_PARAMS, err))
+}
+
+/// The response to the request with the given id.
+fn response(id: Value, result: Result<Value, RpcError>) -> Value {
+    match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(err) => json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "error": { "code": err.code, "message": err.message },
+        }),
+    }
+}
+
+/// Serves requests from the input until it ends or the server is shut down.
+fn serve<T>(
+    settings: Settings,
+    input: impl BufRead,
+    output: &mut impl Write,
+) -> io::Result<()>
+where
+    T: CellKind + Default + Clone + Copy + PartialEq,
+{
+    let mut session = Session::<T>::new(settings);
+    for line in input.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let (response, shutdown) = session.handle(&line);
+        if let Some(response) = response {
+            writeln!(output, "{}", response)?;
+            output.flush()?;
+        }
+        if shutdown {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Runs the `serve-stdio` subcommand.
+pub(crate) fn run_serve_stdio(
+    args: &ServeStdioArgs,
+) -> Result<ExitCode, Box<dyn Error>> {
+    let settings = Settings::from_args(&args.run)?;
+    let (input, mut output) = (io::stdin().lock(), io::stdout().lock());
+    match settings.cell_width {
+        CellWidth::U8 => serve::<u8>(settings, input, &mut output)?,
+        CellWidth::U16 => serve::<u16>(settings, input, &mut output)?,
+        CellWidth::U32 => serve::<u32>(settings, input, &mut output)?,
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::serve;
+    use crate::config::settings;
+    use serde_json::{json, Value};
+
+    /// Serves the requests, returning the responses.
+    fn session(requests: &[Value]) -> Vec<Value> {
+        let settings = settings(&["--eof", "zero"]);
+        let input: String = requests
+            .iter()
+            .map(|request| format!("{}\n", request))
+            .collect();
+        let mut output = Vec::new();
+        serve::<u8>(settings, input.as_bytes(), &mut output).unwrap();
+        String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    /// A request for the method with the given parameters.
+    fn call(id: u64, method: &str, params: Value) -> Value {
+        json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params })
+    }
+
+    #[test]
+    fn test_session() {
+        let responses = session(&[
+            call(1, "load", json!({ "source": ",[.,]\n+", "filename": "c" })),
+            call(2, "set_breakpoints", json!({ "positions": [2] })),
+            call(3, "run", Value::Null),
+            call(4, "provide_input", json!({ "data": "hi", "eof": true })),
+            call(5, "run", Value::Null),
+            call(6, "run", Value::Null),
+            call(7, "take_output", Value::Null),
+            call(8, "set_breakpoints", json!({ "lines": [2] })),
+            call(9, "run", Value::Null),
+            call(10, "step", json!({ "count": 5 })),
+            call(11, "read_tape", json!({ "length": 2 })),
+            call(12, "shutdown", Value::Null),
+            call(13, "state", Value::Null),
+        ]);
+        assert_eq!(responses.len(), 12);
+        assert_eq!(responses[0]["result"]["instructions"], 6);
+        assert_eq!(responses[2]["result"]["status"], "waiting_for_input");
+        assert_eq!(responses[4]["result"]["status"], "breakpoint");
+        assert_eq!(responses[4]["result"]["position"], 2);
+        assert_eq!(responses[4]["result"]["cell"], b'h');
+        // Carrying on from a breakpoint stops at it the next time round.
+        assert_eq!(responses[5]["result"]["steps"], 6);
+        assert_eq!(responses[5]["result"]["position"], 2);
+        assert_eq!(responses[6]["result"]["text"], "h");
+        assert_eq!(responses[7]["result"]["positions"], json!([5]));
+        assert_eq!(responses[8]["result"]["status"], "breakpoint");
+        assert_eq!(responses[8]["result"]["line"], 2);
+        assert_eq!(responses[9]["result"]["status"], "halted");
+        assert_eq!(responses[10]["result"]["cells"], json!([1, 0]));
+        assert_eq!(responses[11]["id"], 12);
+    }
+
+    #[test]
+    fn test_errors() {
+        let responses = session(&[
+            json!("not a request"),
+            call(1, "run", Value::Null),
+            call(2, "fly", Value::Null),
+            call(3, "load", json!({ "source": "[" })),
+            call(4, "load", json!({ "source": "+", "path": "a.bf" })),
+            call(5, "load", json!({ "source": "+" })),
+            call(6, "step", json!({ "count": "two" })),
+            call(7, "run", Value::Null),
+            call(8, "run", Value::Null),
+            json!({ "jsonrpc": "2.0", "method": "state" }),
+        ]);
+        let codes: Vec<_> = responses
+            .iter()
+            .map(|response| response["error"]["code"].clone())
+            .collect();
+        assert_eq!(
+            codes,
+            [
+                json!(-32600),
+                json!(-32000),
+                json!(-32601),
+                json!(-32000),
+                json!(-32602),
+                Value::Null,
+                json!(-32602),
+                Value::Null,
+                json!(-32000),
+            ]
+        );
+        assert_eq!(responses[7]["result"]["status"], "halted");
+    }
+}
@@ -0,0 +1,251 @@
+//! The `shrink` subcommand, which reduces a failing program to a minimal
+//! reproducer by removing balanced chunks of it while it still fails.
+
+use std::error::Error;
+use std::fs;
+use std::ops::Range;
+use std::process::ExitCode;
+
+use bft_types::builder::BfProgramBuilder;
+use bft_types::loops::LoopNode;
+use bft_types::ops::Operation;
+use bft_types::BfProgram;
+
+use crate::cli::ShrinkArgs;
+use crate::config::Settings;
+use crate::harness::{execute, Outcome, DEFAULT_STEP_LIMIT};
+use crate::load_program;
+
+/// The failure which the shrunk program must still show.
+#[derive(Debug, PartialEq, Eq)]
+enum Check {
+    /// Any error from the Virtual Machine.
+    AnyError,
+    /// Running into the step limit.
+    Hang,
+    /// An error whose message contains the given text.
+    ErrorContaining(String),
+}
+
+impl Check {
+    fn parse(check: &str) -> Result<Check, String> {
+        match check.split_once(':') {
+            Some(("exit", "error")) => Ok(Check::AnyError),
+            Some(("exit", "hang")) => Ok(Check::Hang),
+            Some(("error", text)) => {
+                Ok(Check::ErrorContaining(text.to_string()))
+            }
+            _ => Err(format!(
+                "invalid check '{}', expected exit:error, exit:hang or \
+                error:<text>",
+                check
+            )),
+        }
+    }
+
+    fn matches(&self, outcome: &Outcome) -> bool {
+        match (self, outcome) {
+            (Check::AnyError, Outcome::Error(_)) => true,
+            (Check::Hang, Outcome::StepLimit) => true,
+            (Check::ErrorContaining(text), Outcome::Error(err)) => {
+                err.to_string().contains(text.as_str())
+            }
+            _ => false,
+        }
+    }
+}
+
+/// A change to a program which keeps its brackets balanced.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Edit {
+    /// Remove a run of whole instructions and loops.
+    Remove(Range<usize>),
+    /// Remove the brackets of a loop, keeping its body.
+    Unwrap(usize, usize),
+}
+
+impl Edit {
+    fn apply(&self, operations: &[Operation]) -> Vec<Operation> {
+        match self {
+            Edit::Remove(range) => {
+                [&operations[..range.start], &operations[range.end..]].concat()
+            }
+            Edit::Unwrap(start, end) => operations
+                .iter()
+                .enumerate()
+                .filter(|(n, _)| n != start && n != end)
+                .map(|(_, op)| *op)
+                .collect(),
+        }
+    }
+}
+
+/// Splits the instructions in `range` into items, where each item is either a
+/// single instruction or one of the given loops.
+fn items(range: Range<usize>, loops: &[LoopNode]) -> Vec<Range<usize>> {
+    let mut items = Vec::new();
+    let mut loops = loops.iter().peekable();
+    let mut position = range.start;
+    while position < range.end {
+        match loops.peek() {
+            Some(node) if node.start() == position => {
+                items.push(node.span());
+                position = node.end() + 1;
+                loops.next();
+            }
+            _ => {
+                items.push(position..position + 1);
+                position += 1;
+            }
+        }
+    }
+    items
+}
+
+/// Collects the edits to try at one level of the loop tree, removing chunks
+/// of its items from largest to smallest, and then recurses into its loops.
+fn collect_edits(
+    range: Range<usize>,
+    loops: &[LoopNode],
+    edits: &mut Vec<Edit>,
+) {
+    let items = items(range, loops);
+    let mut chunk = items.len();
+    while chunk > 0 {
+        for group in items.chunks(chunk) {
+            let (first, last) = (&group[0], &group[group.len() - 1]);
+            edits.push(Edit::Remove(first.start..last.end));
+        }
+        chunk /= 2;
+    }
+    for node in loops {
+        edits.push(Edit::Unwrap(node.start(), node.end()));
+        collect_edits(node.body(), node.children(), edits);
+    }
+}
+
+/// Builds a program from a list of operations, which are known to have
+/// balanced brackets.
+fn build(operations: &[Operation]) -> BfProgram {
+    let mut builder = BfProgramBuilder::new();
+    for operation in operations {
+        builder.push(*operation);
+    }
+    builder
+        .finish()
+        .expect("shrinking edits always keep the brackets balanced")
+}
+
+/// Repeatedly applies the first edit which keeps the program failing, until
+/// no edit does.
+fn shrink<F>(mut operations: Vec<Operation>, still_fails: F) -> Vec<Operation>
+where
+    F: Fn(&BfProgram) -> bool,
+{
+    'shrinking: loop {
+        let program = build(&operations);
+        let mut edits = Vec::new();
+        collect_edits(0..operations.len(), &program.loops(), &mut edits);
+        for edit in edits {
+            let candidate = edit.apply(&operations);
+            if still_fails(&build(&candidate)) {
+                operations = candidate;
+                continue 'shrinking;
+            }
+        }
+        return operations;
+    }
+}
+
+/// Shrinks the program given on the command line, writing out the minimal
+/// program which still fails in the same way.
+pub(crate) fn run_shrink(
+    args: &ShrinkArgs,
+) -> Result<ExitCode, Box<dyn Error>> {
+    let check = Check::parse(&args.check)?;
+    let settings = Settings::from_args(&args.run)?;
+    let program = load_program(&args.filename, &settings)?;
+    let input = match &args.input {
+        Some(path) => fs::read(path)?,
+        None => Vec::new(),
+    };
+    let step_limit = settings.max_steps.unwrap_or(DEFAULT_STEP_LIMIT);
+    let still_fails = |candidate: &BfProgram| {
+        check
+            .matches(&execute(candidate, &settings, &input, step_limit).outcome)
+    };
+
+    if !still_fails(&program) {
+        eprintln!(
+            "{} does not fail the check '{}', so there is nothing to shrink.",
+            args.filename.display(),
+            args.check
+        );
+        return Ok(ExitCode::FAILURE);
+    }
+
+    let operations: Vec<Operation> = program
+        .iter()
+        .map(|instruction| instruction.operation())
+        .collect();
+    let original_len = operations.len();
+    let shrunk = shrink(operations, still_fails);
+    let mut source: String = shrunk.iter().map(Operation::to_char).collect();
+    source.push('\n');
+
+    match &args.output {
+        Some(path) => fs::write(path, source)?,
+        None => print!("{}", source),
+    }
+    eprintln!(
+        "Shrunk from {} to {} instructions.",
+        original_len,
+        shrunk.len()
+    );
+    Ok(ExitCode::SUCCESS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{shrink, Check};
+    use bft_types::ops::Operation;
+    use bft_types::BfProgram;
+
+    fn operations(source: &str) -> Vec<Operation> {
+        source
+            .chars()
+            .filter_map(Operation::char_to_operation)
+            .collect()
+    }
+
+    fn source(operations: &[Operation]) -> String {
+        operations.iter().map(Operation::to_char).collect()
+    }
+
+    #[test]
+    fn test_parse_checks() {
+        assert_eq!(Check::parse("exit:error"), Ok(Check::AnyError));
+        assert_eq!(Check::parse("exit:hang"), Ok(Check::Hang));
+        assert_eq!(
+            Check::parse("error:unmatched"),
+            Ok(Check::ErrorContaining("unmatched".to_string()))
+        );
+        assert!(Check::parse("exit:ok").is_err());
+    }
+
+    #[test]
+    fn test_shrink_keeps_balance() {
+        // Pretend that any program containing a `<` directly inside a loop
+        // fails.
+        let fails = |program: &BfProgram| {
+            let loops = program.loops();
+            loops.iter().any(|node| {
+                program.instructions()[node.body()]
+                    .iter()
+                    .any(|i| i.operation() == Operation::DecrementPointer)
+            })
+        };
+        let shrunk = shrink(operations("++[>+[-]<-]>>.[<]"), fails);
+        assert_eq!(source(&shrunk), "[<]");
+    }
+}
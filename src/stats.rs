@@ -0,0 +1,99 @@
+//! The `stats` and `id` subcommands, which describe the make-up and identity
+//! of a program without running it.
+
+use std::error::Error;
+use std::fmt::Write;
+use std::process::ExitCode;
+
+use bft_types::ops::Operation;
+use bft_types::BfProgram;
+
+use crate::cli::StatsArgs;
+use crate::config::Settings;
+use crate::load_program;
+
+/// Lays out the statistics of the program, one on each line.
+fn render(program: &BfProgram) -> String {
+    let stats = program.statistics();
+    let mut rendered = String::new();
+    let _ = writeln!(rendered, "{}", program.filename().display());
+    let _ = writeln!(
+        rendered,
+        "instructions: {} in {} characters ({:.1}% comments)",
+        stats.instructions(),
+        stats.instructions() + stats.comment_chars(),
+        stats.comment_ratio() * 100.0
+    );
+    for operation in Operation::all() {
+        let _ = writeln!(
+            rendered,
+            "  {} {:>8}",
+            operation.compact(),
+            stats.count(*operation)
+        );
+    }
+    let _ = writeln!(
+        rendered,
+        "loops: {}, nested at most {} deep",
+        stats.loops(),
+        stats.max_nesting()
+    );
+    if let Some((operation, len)) = stats.longest_run() {
+        let _ = writeln!(
+            rendered,
+            "longest run: {} x {}",
+            len,
+            operation.compact()
+        );
+    }
+    let _ = write!(
+        rendered,
+        "tape: at least {} cells (estimated)",
+        stats.min_tape_cells()
+    );
+    rendered
+}
+
+/// Runs the `stats` subcommand.
+pub(crate) fn run_stats(args: &StatsArgs) -> Result<ExitCode, Box<dyn Error>> {
+    let settings = Settings::from_args(&args.run)?;
+    let program = load_program(&args.filename, &settings)?;
+    println!("{}", render(&program));
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Runs the `id` subcommand.
+pub(crate) fn run_id(args: &StatsArgs) -> Result<ExitCode, Box<dyn Error>> {
+    let settings = Settings::from_args(&args.run)?;
+    let program = load_program(&args.filename, &settings)?;
+    println!("{}", program.digest());
+    Ok(ExitCode::SUCCESS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::render;
+    use bft_types::BfProgram;
+
+    #[test]
+    fn test_render() {
+        let program =
+            BfProgram::new("add: +++[>++<-]".to_string(), "add.bf").unwrap();
+        assert_eq!(
+            render(&program),
+            "add.bf\n\
+             instructions: 10 in 15 characters (33.3% comments)\n\
+             \x20 >        1\n\
+             \x20 <        1\n\
+             \x20 +        5\n\
+             \x20 -        1\n\
+             \x20 .        0\n\
+             \x20 ,        0\n\
+             \x20 [        1\n\
+             \x20 ]        1\n\
+             loops: 1, nested at most 1 deep\n\
+             longest run: 3 x +\n\
+             tape: at least 2 cells (estimated)"
+        );
+    }
+}
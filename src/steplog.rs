@@ -0,0 +1,485 @@
+//! Step logs, written by `--record-steps`, which keep where the program was,
+//! where the head was and how the cell at the head had changed at every step
+//! of a run. `bft replay-steps` uses them to jump straight to any step of the
+//! run, and to carry on from there in the debugger.
+//!
+//! A log is a series of chunks, each compressed on its own and starting with
+//! the cells of the tape as they were before its first step, so that seeking
+//! to a step only reads the chunk the step is in. The log ends with an index
+//! of where each chunk starts, along with a replay of the run, which has the
+//! program, its settings and its input.
+//!
+//! Each step is three numbers: the position of the instruction in the program,
+//! the position of the head, and how much the cell at the head has changed
+//! since the log last saw it, which is what the step before changed it by
+//! unless an extension changed it from elsewhere on the tape.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::process::ExitCode;
+
+use bft_interp::report::{Reporter, Step, TracedOp};
+use bft_types::ops::Operation;
+use bft_types::BfProgram;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
+
+use crate::cli::ReplayStepsArgs;
+use crate::config::CellWidth;
+use crate::debugger::Debugger;
+use crate::replay::Replay;
+
+/// The bytes both the start and the end of a step log are marked with.
+const MAGIC: &[u8; 8] = b"BFSTEPS1";
+
+/// The number of steps in each chunk of a log. Seeking decompresses a whole
+/// chunk, so this keeps seeking quick while still compressing well.
+const CHUNK_STEPS: u64 = 1 << 16;
+
+/// Writes a variable length integer, seven bits to a byte, lowest first.
+fn write_varint(buffer: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buffer.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buffer.push(value as u8);
+}
+
+/// Reads a variable length integer written by `write_varint`.
+fn read_varint(bytes: &mut &[u8]) -> io::Result<u64> {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = bytes.split_first().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::UnexpectedEof, "truncated step log")
+        })?;
+        *bytes = rest;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        "overlong number in step log",
+    ))
+}
+
+/// Reads a number which must fit in a `usize`, such as a position.
+fn read_usize(bytes: &mut &[u8]) -> io::Result<usize> {
+    usize::try_from(read_varint(bytes)?).map_err(|_| {
+        io::Error::new(io::ErrorKind::InvalidData, "position out of range")
+    })
+}
+
+/// Where a chunk is in the log, and which steps it holds.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+struct Chunk {
+    /// The number of the first step in the chunk, counting from 1.
+    first_step: u64,
+    /// The number of `,` run before the first step in the chunk.
+    inputs: u64,
+    offset: u64,
+    length: u64,
+}
+
+/// The end of a step log.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Trailer {
+    steps: u64,
+    chunks: Vec<Chunk>,
+    replay: Replay,
+}
+
+/// Records each step it is given to a step log. The log is not finished, and
+/// cannot be read, until `finish` is called.
+pub(crate) struct StepRecorder<W: Write> {
+    writer: W,
+    /// The number of bytes written so far.
+    written: u64,
+    /// For the line and column of each instruction, its position in the
+    /// program.
+    positions: HashMap<(usize, usize), usize>,
+    /// The value of each cell as the log last saw it.
+    cells: Vec<u32>,
+    /// The steps of the chunk being recorded, uncompressed.
+    chunk: Vec<u8>,
+    /// The chunk being recorded, if it has any steps yet.
+    current: Option<Chunk>,
+    chunks: Vec<Chunk>,
+    steps: u64,
+    inputs: u64,
+    /// The first error from writing the log, which is returned by `finish`
+    /// as steps cannot fail.
+    error: Option<io::Error>,
+}
+
+impl<W: Write> StepRecorder<W> {
+    /// Starts a step log written to the given writer, which should be
+    /// buffered.
+    pub(crate) fn new(mut writer: W) -> io::Result<Self> {
+        writer.write_all(MAGIC)?;
+        Ok(Self {
+            writer,
+            written: MAGIC.len() as u64,
+            positions: HashMap::new(),
+            cells: Vec::new(),
+            chunk: Vec::new(),
+            current: None,
+            chunks: Vec::new(),
+            steps: 0,
+            inputs: 0,
+            error: None,
+        })
+    }
+
+    /// Starts a chunk with the cells which are not zero, as they are before
+    /// its first step.
+    fn start_chunk(&mut self, first_step: u64) {
+        let cells = self.cells.iter().enumerate().filter(|(_, &v)| v != 0);
+        write_varint(&mut self.chunk, cells.clone().count() as u64);
+        for (index, &value) in cells {
+            write_varint(&mut self.chunk, index as u64);
+            write_varint(&mut self.chunk, u64::from(value));
+        }
+        self.current = Some(Chunk {
+            first_step,
+            inputs: self.inputs,
+            offset: self.written,
+            length: 0,
+        });
+    }
+
+    /// Compresses the chunk being recorded and writes it out.
+    fn end_chunk(&mut self) {
+        let Some(mut chunk) = self.current.take() else {
+            return;
+        };
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::fast());
+        let compressed = encoder
+            .write_all(&self.chunk)
+            .and_then(|()| encoder.finish())
+            .and_then(|compressed| {
+                self.writer.write_all(&compressed).map(|()| compressed)
+            });
+        self.chunk.clear();
+        match compressed {
+            Ok(compressed) => {
+                chunk.length = compressed.len() as u64;
+                self.written += chunk.length;
+                self.chunks.push(chunk);
+            }
+            Err(err) => {
+                self.error.get_or_insert(err);
+            }
+        }
+    }
+
+    /// Ends the log with its index and the replay of the run, which must have
+    /// been recorded alongside it.
+    pub(crate) fn finish(mut self, replay: Replay) -> io::Result<W> {
+        self.end_chunk();
+        if let Some(err) = self.error {
+            return Err(err);
+        }
+        let trailer = Trailer {
+            steps: self.steps,
+            chunks: self.chunks,
+            replay,
+        };
+        serde_json::to_writer(&mut self.writer, &trailer)?;
+        self.writer.write_all(&self.written.to_le_bytes())?;
+        self.writer.write_all(MAGIC)?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+impl<W: Write> Reporter for StepRecorder<W> {
+    fn parsed(&mut self, program: &BfProgram) {
+        for (position, instruction) in program.instructions().iter().enumerate()
+        {
+            self.positions
+                .insert((instruction.line(), instruction.column()), position);
+        }
+    }
+
+    fn traces(&self) -> bool {
+        true
+    }
+
+    fn step(&mut self, step: &Step<'_>) {
+        if self.current.is_none() {
+            self.start_chunk(step.number());
+        }
+        let source = step.source();
+        let position = self.positions[&(source.line(), source.column())];
+        let head = step.head();
+        if head >= self.cells.len() {
+            self.cells.resize(head + 1, 0);
+        }
+        let delta = i64::from(step.cell()) - i64::from(self.cells[head]);
+        self.cells[head] = step.cell();
+        write_varint(&mut self.chunk, position as u64);
+        write_varint(&mut self.chunk, head as u64);
+        // Zigzag encoded, so that small changes either way stay small.
+        write_varint(&mut self.chunk, ((delta << 1) ^ (delta >> 63)) as u64);
+
+        self.steps = step.number();
+        if let TracedOp::Instruction(Operation::InputByte) = step.operation() {
+            self.inputs += 1;
+        }
+        if self.current.is_some_and(|chunk| {
+            self.steps - chunk.first_step + 1 == CHUNK_STEPS
+        }) {
+            self.end_chunk();
+        }
+    }
+}
+
+/// Where a run had got to just before one of its steps.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct StepState {
+    /// The number of the step about to be run, counting from 1.
+    pub(crate) step: u64,
+    /// The position in the program of the instruction about to be run.
+    pub(crate) position: usize,
+    pub(crate) head: usize,
+    /// The cells of the tape, up to the furthest the head had been.
+    pub(crate) cells: Vec<u32>,
+    /// The number of `,` which had been run.
+    pub(crate) inputs: u64,
+}
+
+/// A step log which has been opened for seeking.
+pub(crate) struct StepLog<R> {
+    reader: R,
+    trailer: Trailer,
+}
+
+impl StepLog<BufReader<File>> {
+    /// Opens the step log in the given file.
+    pub(crate) fn open(path: &Path) -> Result<Self, Box<dyn Error>> {
+        StepLog::new(BufReader::new(File::open(path)?))
+            .map_err(|err| format!("in {}: {}", path.display(), err).into())
+    }
+}
+
+impl<R: Read + Seek> StepLog<R> {
+    /// Reads the index and the replay from the end of the log.
+    fn new(mut reader: R) -> Result<Self, Box<dyn Error>> {
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        let end = reader.seek(SeekFrom::End(-16))?;
+        let mut footer = [0; 16];
+        reader.read_exact(&mut footer)?;
+        if &magic != MAGIC || &footer[8..] != MAGIC {
+            return Err("not a step log written by --record-steps".into());
+        }
+        let start = u64::from_le_bytes(footer[..8].try_into()?);
+        reader.seek(SeekFrom::Start(start))?;
+        let mut trailer = Vec::new();
+        reader
+            .by_ref()
+            .take(end.saturating_sub(start))
+            .read_to_end(&mut trailer)?;
+        let trailer = serde_json::from_slice(&trailer)?;
+        Ok(Self { reader, trailer })
+    }
+
+    /// The number of steps the run took.
+    pub(crate) fn steps(&self) -> u64 {
+        self.trailer.steps
+    }
+
+    /// The replay of the run, with its program, settings and input.
+    pub(crate) fn replay(&self) -> &Replay {
+        &self.trailer.replay
+    }
+
+    /// Works out where the run had got to just before the given step, by
+    /// reading only the chunk which the step is in.
+    pub(crate) fn seek(
+        &mut self,
+        program: &BfProgram,
+        step: u64,
+    ) -> Result<StepState, Box<dyn Error>> {
+        if step == 0 || step > self.steps() {
+            return Err(format!(
+                "there is no step {}, the run took {} steps",
+                step,
+                self.steps()
+            )
+            .into());
+        }
+        let chunks = &self.trailer.chunks;
+        let chunk = chunks[chunks
+            .partition_point(|chunk| chunk.first_step <= step)
+            .saturating_sub(1)];
+        self.reader.seek(SeekFrom::Start(chunk.offset))?;
+        let mut bytes = Vec::new();
+        GzDecoder::new(self.reader.by_ref().take(chunk.length))
+            .read_to_end(&mut bytes)?;
+        let mut bytes = &bytes[..];
+
+        let mut cells = Vec::new();
+        for _ in 0..read_varint(&mut bytes)? {
+            let index = read_usize(&mut bytes)?;
+            let value = u32::try_from(read_varint(&mut bytes)?)?;
+            if index >= cells.len() {
+                cells.resize(index + 1, 0);
+            }
+            cells[index] = value;
+        }
+        let mut state = StepState {
+            step,
+            position: 0,
+            head: 0,
+            cells,
+            inputs: chunk.inputs,
+        };
+        for number in chunk.first_step..=step {
+            let position = read_usize(&mut bytes)?;
+            let head = read_usize(&mut bytes)?;
+            let zigzag = read_varint(&mut bytes)?;
+            let delta = (zigzag >> 1) as i64 ^ -((zigzag & 1) as i64);
+            if head >= state.cells.len() {
+                state.cells.resize(head + 1, 0);
+            }
+            let cell = &mut state.cells[head];
+            *cell = (i64::from(*cell) + delta) as u32;
+            (state.position, state.head) = (position, head);
+            let operation = program
+                .instructions()
+                .get(position)
+                .ok_or("the step log does not match the program")?
+                .operation();
+            if number < step && operation == Operation::InputByte {
+                state.inputs += 1;
+            }
+        }
+        Ok(state)
+    }
+}
+
+/// Runs the `replay-steps` subcommand, jumping to the step asked for and
+/// carrying on from there in the debugger.
+pub(crate) fn run_replay_steps(
+    args: &ReplayStepsArgs,
+) -> Result<ExitCode, Box<dyn Error>> {
+    let mut log = StepLog::open(&args.file)?;
+    let (program, settings) = log.replay().load(args.program.as_deref())?;
+    let state = log.seek(&program, args.step)?;
+    let input = log.replay().input();
+    let input = &input[input.len().min(state.inputs as usize)..];
+    let (mut commands, mut out) = (io::stdin().lock(), io::stdout().lock());
+    match settings.cell_width {
+        CellWidth::U8 => Debugger::<u8>::new(&program, &settings, input)
+            .restore(&state)?
+            .run(&mut commands, &mut out)?,
+        CellWidth::U16 => Debugger::<u16>::new(&program, &settings, input)
+            .restore(&state)?
+            .run(&mut commands, &mut out)?,
+        CellWidth::U32 => Debugger::<u32>::new(&program, &settings, input)
+            .restore(&state)?
+            .run(&mut commands, &mut out)?,
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        read_varint, write_varint, StepLog, StepRecorder, CHUNK_STEPS,
+    };
+    use crate::config::settings;
+    use crate::manifest::HashingWriter;
+    use crate::replay::Replay;
+    use bft_interp::report::Reporter;
+    use bft_interp::VirtualMachine;
+    use bft_types::BfProgram;
+    use std::io::Cursor;
+
+    /// Runs the program with a step recorder, returning the log.
+    fn record(source: &str, input: &[u8]) -> (BfProgram, Vec<u8>) {
+        let program = BfProgram::new(source.to_string(), "log.bf").unwrap();
+        let settings = settings(&["--eof", "zero"]);
+        let mut recorder = StepRecorder::new(Vec::new()).unwrap();
+        recorder.parsed(&program);
+        let mut vm = settings
+            .virtual_machine::<u8>(&program)
+            .with_reporter(&mut recorder);
+        let mut output = HashingWriter::new(Vec::new());
+        let result = vm.interpret(&mut Cursor::new(input), &mut output);
+        let steps = vm.steps();
+        drop(vm);
+        let replay =
+            Replay::new(&program, &settings, input, steps, &result, &output);
+        (program, recorder.finish(replay).unwrap())
+    }
+
+    #[test]
+    fn test_varints() {
+        let mut buffer = Vec::new();
+        for value in [0, 127, 128, 300, u64::MAX] {
+            write_varint(&mut buffer, value);
+        }
+        let mut bytes = &buffer[..];
+        for value in [0, 127, 128, 300, u64::MAX] {
+            assert_eq!(read_varint(&mut bytes).unwrap(), value);
+        }
+        assert!(read_varint(&mut bytes).is_err());
+    }
+
+    #[test]
+    fn test_seek() {
+        let (program, log) = record("+++>,-<[->>+<<]", b"a");
+        let mut log = StepLog::new(Cursor::new(log)).unwrap();
+        assert_eq!(log.steps(), 30);
+        assert_eq!(log.replay().input(), b"a");
+
+        let state = log.seek(&program, 1).unwrap();
+        assert_eq!((state.position, state.head), (0, 0));
+        assert!(state.cells.iter().all(|&cell| cell == 0));
+        // Before the `-`, after the `,` read an `a`.
+        let state = log.seek(&program, 6).unwrap();
+        assert_eq!((state.position, state.head, state.inputs), (5, 1, 1));
+        assert_eq!(state.cells[..2], [3, 97]);
+        // Before the last `]`, with the loop run three times.
+        let state = log.seek(&program, 30).unwrap();
+        assert_eq!((state.position, state.head), (14, 0));
+        assert_eq!(state.cells, [0, 96, 3]);
+
+        assert!(log.seek(&program, 0).is_err());
+        assert!(log.seek(&program, 31).is_err());
+    }
+
+    #[test]
+    fn test_seek_across_chunks() {
+        // 16 to the power of 4 runs of the innermost loop, which is several
+        // chunks of steps.
+        let source = "++++++++++++++++[>++++++++++++++++[>++++++++++++++++\
+            [>++++++++++++++++[>+<-]<-]<-]<-]";
+        let (program, log) = record(source, b"");
+        let mut log = StepLog::new(Cursor::new(log)).unwrap();
+        assert!(log.trailer.chunks.len() > 4);
+        let last = log.steps();
+        for step in [1, 2, CHUNK_STEPS, CHUNK_STEPS + 1, 200_000, last] {
+            let state = log.seek(&program, step).unwrap();
+            let mut vm = VirtualMachine::<u8>::new(&program, 8, false)
+                .with_step_limit(step - 1);
+            assert!(vm.run().is_err());
+            assert_eq!(vm.program_position(), state.position);
+            assert_eq!(vm.tape_head(), state.head);
+            let (cells, rest) = vm.tape().split_at(state.cells.len());
+            assert!(cells.iter().map(|&cell| u32::from(cell)).eq(state.cells));
+            assert!(rest.iter().all(|&cell| cell == 0));
+        }
+
+        assert!(StepLog::new(Cursor::new(b"BFSTEPS1".repeat(3))).is_err());
+    }
+}
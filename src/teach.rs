@@ -0,0 +1,430 @@
+//! The `teach` subcommand, which runs a program slowly, one step at a time,
+//! narrating what each step does so that a class can follow along. Pressing
+//! Enter pauses and resumes it, and `q` stops it.
+
+use std::collections::HashSet;
+use std::error::Error;
+use std::fmt::Display;
+use std::fs;
+use std::io::{self, BufRead, Cursor, Write};
+use std::process::ExitCode;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+
+use bft_interp::{CellKind, VirtualMachine};
+use bft_types::ops::Operation;
+use bft_types::vm_error::VirtualMachineError;
+use bft_types::BfProgram;
+
+use crate::cli::TeachArgs;
+use crate::config::{CellWidth, Settings};
+use crate::i18n;
+use crate::load_program;
+
+/// What the person watching can ask for while the program runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Control {
+    /// Pause the program, or resume it if it is paused.
+    Toggle,
+    /// Stop the program.
+    Quit,
+}
+
+/// Reads controls from the lines of stdin in the background: an empty line,
+/// or `p`, pauses and resumes the program, and `q` stops it.
+fn read_controls() -> Receiver<Control> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        for line in io::stdin().lock().lines() {
+            let Ok(line) = line else {
+                return;
+            };
+            let control = match line.trim() {
+                "q" | "quit" => Control::Quit,
+                _ => Control::Toggle,
+            };
+            if sender.send(control).is_err() {
+                return;
+            }
+        }
+    });
+    receiver
+}
+
+/// What came of taking a step.
+#[derive(Debug)]
+enum Taken {
+    /// The step was taken, and is described by the narration.
+    Step(String),
+    /// The program halted.
+    Halted,
+    /// The program failed.
+    Failed(VirtualMachineError),
+}
+
+/// Runs a program one step at a time, describing each step.
+struct Teacher<'a, T> {
+    program: &'a BfProgram,
+    vm: VirtualMachine<'a, T>,
+    input: Cursor<Vec<u8>>,
+    max_steps: Option<u64>,
+    /// Everything the program has written so far.
+    output: Vec<u8>,
+    /// The operations which have been described already.
+    described: HashSet<Operation>,
+    /// Whether the last step was a `[`, which jumps to its `]` for that to
+    /// decide whether the loop runs.
+    entering: bool,
+}
+
+impl<'a, T> Teacher<'a, T>
+where
+    T: CellKind + Default + Clone + Copy + PartialEq + 'a,
+{
+    fn new(
+        program: &'a BfProgram,
+        settings: &Settings,
+        input: Vec<u8>,
+    ) -> Self {
+        Self {
+            program,
+            vm: settings.virtual_machine(program),
+            input: Cursor::new(input),
+            max_steps: settings.max_steps,
+            output: Vec::new(),
+            described: HashSet::new(),
+            entering: false,
+        }
+    }
+
+    /// Takes the next step, describing it if `narrate` is true, along with
+    /// what its command does if it has not been described before.
+    fn step(&mut self, narrate: bool) -> Taken {
+        let position = self.vm.program_position();
+        let Some(&instruction) = self.program.instructions().get(position)
+        else {
+            return Taken::Halted;
+        };
+        let steps = self.vm.steps();
+        if self.max_steps == Some(steps) {
+            self.vm.set_step_limit(self.max_steps);
+        } else {
+            self.vm.set_step_limit(Some(steps + 1));
+        }
+        let written = self.output.len();
+        let result = self.vm.interpret(&mut self.input, &mut self.output);
+        match result {
+            Err(VirtualMachineError::StepLimitExceeded { .. })
+                if self.vm.steps() > steps => {}
+            Ok(()) => {}
+            Err(err) => return Taken::Failed(err),
+        }
+        let entering = std::mem::replace(
+            &mut self.entering,
+            instruction.operation() == Operation::StartLoop,
+        );
+        if !narrate {
+            return Taken::Step(String::new());
+        }
+
+        let operation = instruction.operation();
+        let (head, cell) =
+            (self.vm.tape_head(), self.vm.value_at_tape_head().to_u32());
+        let args: [(&str, &dyn Display); 2] =
+            [("cell", &head), ("value", &cell)];
+        let outcome = match operation {
+            Operation::IncrementByte
+            | Operation::DecrementByte
+            | Operation::InputByte => {
+                i18n::localize("teach-cell-becomes", &args, || {
+                    format!("cell {} becomes {}", head, cell)
+                })
+            }
+            Operation::IncrementPointer | Operation::DecrementPointer => {
+                i18n::localize("teach-head-moves", &args, || {
+                    format!("the head moves to cell {}", head)
+                })
+            }
+            Operation::OutputByte => {
+                let bytes = &self.output[written..];
+                let byte = bytes.first().copied().unwrap_or_default();
+                let text = bytes.escape_ascii();
+                i18n::localize(
+                    "teach-writes",
+                    &[("byte", &byte), ("text", &text)],
+                    || format!("writes {} \"{}\"", byte, text),
+                )
+            }
+            Operation::StartLoop => {
+                let close =
+                    self.program.instructions()[self.vm.program_position()];
+                let location = format!("{}:{}", close.line(), close.column());
+                i18n::localize(
+                    "teach-loop-jumps",
+                    &[("location", &location)],
+                    || {
+                        format!(
+                            "jumps to its `]` at {}, which decides whether \
+                            the loop runs",
+                            location
+                        )
+                    },
+                )
+            }
+            Operation::EndLoop if entering && cell == 0 => {
+                i18n::localize("teach-loop-skipped", &args, || {
+                    format!("cell {} is 0, so the loop is skipped", head)
+                })
+            }
+            Operation::EndLoop if entering => {
+                i18n::localize("teach-loop-runs", &args, || {
+                    format!("cell {} is {}, so the loop runs", head, cell)
+                })
+            }
+            Operation::EndLoop if cell == 0 => {
+                i18n::localize("teach-loop-ends", &args, || {
+                    format!("cell {} is 0, so the loop ends", head)
+                })
+            }
+            Operation::EndLoop => {
+                i18n::localize("teach-loop-repeats", &args, || {
+                    format!(
+                        "cell {} is {}, so the loop goes round again",
+                        head, cell
+                    )
+                })
+            }
+            Operation::Extension(_) => {
+                i18n::localize("teach-extension", &[], || {
+                    "runs the extension".to_string()
+                })
+            }
+        };
+        let (step, command) = (self.vm.steps(), operation.to_char());
+        let (line, column) = (instruction.line(), instruction.column());
+        let mut narration = i18n::localize(
+            "teach-step",
+            &[
+                ("step", &step),
+                ("command", &command),
+                ("line", &line),
+                ("column", &column),
+                ("outcome", &outcome),
+            ],
+            || {
+                format!(
+                    "step {}: `{}` at {}:{} \u{2192} {}",
+                    step, command, line, column, outcome
+                )
+            },
+        );
+        if self.described.insert(operation) {
+            let description = i18n::describe_operation(operation);
+            narration.push_str(&format!("\n    ({})", description));
+        }
+        Taken::Step(narration)
+    }
+
+    /// Runs the program to the end, narrating every `every` steps and waiting
+    /// `delay` after each narration, pausing, resuming and stopping as the
+    /// controls say. Returns whether the program halted successfully.
+    fn run(
+        mut self,
+        every: u64,
+        delay: Duration,
+        controls: &Receiver<Control>,
+        out: &mut impl Write,
+    ) -> io::Result<bool> {
+        loop {
+            let narrate = (self.vm.steps() + 1).is_multiple_of(every);
+            match self.step(narrate) {
+                Taken::Step(narration) if narrate => {
+                    writeln!(out, "{}", narration)?;
+                    out.flush()?;
+                }
+                Taken::Step(_) => continue,
+                Taken::Halted => {
+                    self.finish(out)?;
+                    let message = steps_message(
+                        "teach-halted",
+                        "halted",
+                        self.vm.steps(),
+                    );
+                    writeln!(out, "{}", message)?;
+                    return Ok(true);
+                }
+                Taken::Failed(err) => {
+                    self.finish(out)?;
+                    let error = i18n::error_message(&err);
+                    let message = i18n::localize(
+                        "teach-failed",
+                        &[("error", &error)],
+                        || format!("failed: {}", error),
+                    );
+                    writeln!(out, "{}", message)?;
+                    return Ok(false);
+                }
+            }
+            let control = match controls.recv_timeout(delay) {
+                Ok(control) => control,
+                Err(RecvTimeoutError::Timeout) => continue,
+                // Without controls, the program runs to the end.
+                Err(RecvTimeoutError::Disconnected) => {
+                    thread::sleep(delay);
+                    continue;
+                }
+            };
+            let control = match control {
+                Control::Toggle => {
+                    let message = i18n::localize("teach-paused", &[], || {
+                        "paused, press Enter to resume".to_string()
+                    });
+                    writeln!(out, "{}", message)?;
+                    out.flush()?;
+                    controls.recv().unwrap_or(Control::Toggle)
+                }
+                Control::Quit => Control::Quit,
+            };
+            if control == Control::Quit {
+                let message =
+                    steps_message("teach-stopped", "stopped", self.vm.steps());
+                writeln!(out, "{}", message)?;
+                return Ok(true);
+            }
+        }
+    }
+
+    /// Shows everything the program wrote.
+    fn finish(&self, out: &mut impl Write) -> io::Result<()> {
+        if self.output.is_empty() {
+            return Ok(());
+        }
+        let text = self.output.escape_ascii();
+        let message =
+            i18n::localize("teach-output", &[("text", &text)], || {
+                format!("output: \"{}\"", text)
+            });
+        writeln!(out, "{}", message)
+    }
+}
+
+/// Says how many steps the program took before it halted or was stopped.
+fn steps_message(id: &str, verb: &str, steps: u64) -> String {
+    i18n::localize(id, &[("steps", &steps)], || {
+        format!("{} after {} steps", verb, steps)
+    })
+}
+
+/// Runs the `teach` subcommand.
+pub(crate) fn run_teach(args: &TeachArgs) -> Result<ExitCode, Box<dyn Error>> {
+    let settings = Settings::from_args(&args.run)?;
+    let program = load_program(&args.filename, &settings)?;
+    let input = match &args.input {
+        Some(path) => fs::read(path)?,
+        None => Vec::new(),
+    };
+    let (every, delay) =
+        (args.narrate_every, Duration::from_millis(args.delay));
+    let controls = read_controls();
+    let mut out = io::stdout().lock();
+    let halted = match settings.cell_width {
+        CellWidth::U8 => Teacher::<u8>::new(&program, &settings, input)
+            .run(every, delay, &controls, &mut out)?,
+        CellWidth::U16 => Teacher::<u16>::new(&program, &settings, input)
+            .run(every, delay, &controls, &mut out)?,
+        CellWidth::U32 => Teacher::<u32>::new(&program, &settings, input)
+            .run(every, delay, &controls, &mut out)?,
+    };
+    Ok(if halted {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Control, Taken, Teacher};
+    use crate::config::settings;
+    use bft_types::BfProgram;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    fn narrations(source: &str, input: &[u8]) -> Vec<String> {
+        let program = BfProgram::new(source.to_string(), "teach.bf").unwrap();
+        let settings = settings(&["--eof", "zero"]);
+        let mut teacher = Teacher::<u8>::new(&program, &settings, input.into());
+        let mut narrations = Vec::new();
+        while let Taken::Step(narration) = teacher.step(true) {
+            narrations.push(narration);
+        }
+        narrations
+    }
+
+    #[test]
+    fn test_step() {
+        let narrations = narrations("+[>,.<-]", b"h");
+        assert_eq!(
+            narrations,
+            [
+                "step 1: `+` at 1:1 \u{2192} cell 0 becomes 1\n    \
+                (+ : increases the value stored at the current cell by 1.)",
+                "step 2: `[` at 1:2 \u{2192} jumps to its `]` at 1:8, which \
+                decides whether the loop runs\n    ([ : Starts a loop.)",
+                "step 3: `]` at 1:8 \u{2192} cell 0 is 1, so the loop \
+                runs\n    (] : Ends a loop.)",
+                "step 4: `>` at 1:3 \u{2192} the head moves to cell 1\n    \
+                (> : moves the data pointer to the right by one cell.)",
+                "step 5: `,` at 1:4 \u{2192} cell 1 becomes 104\n    \
+                (, : Accepts a byte of input, and stores the value at the \
+                current data pointer.)",
+                "step 6: `.` at 1:5 \u{2192} writes 104 \"h\"\n    \
+                (. : Outputs the byte at the current data pointer.)",
+                "step 7: `<` at 1:6 \u{2192} the head moves to cell 0\n    \
+                (< : moves the data pointer to the left by one cell.)",
+                "step 8: `-` at 1:7 \u{2192} cell 0 becomes 0\n    \
+                (- : decreases the value stored at the current cell by 1.)",
+                "step 9: `]` at 1:8 \u{2192} cell 0 is 0, so the loop ends",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_run() {
+        let program = BfProgram::new("++[-].".to_string(), "t.bf").unwrap();
+        let (sender, controls) = mpsc::channel();
+        let mut out = Vec::new();
+        let teacher = Teacher::<u8>::new(&program, &settings(&[]), Vec::new());
+        drop(sender);
+        assert!(teacher.run(3, Duration::ZERO, &controls, &mut out).unwrap());
+        let out = String::from_utf8(out).unwrap();
+        let steps: Vec<_> = out
+            .lines()
+            .filter(|line| line.starts_with("step"))
+            .map(|line| &line[..7])
+            .collect();
+        assert_eq!(steps, ["step 3:", "step 6:", "step 9:"]);
+        assert!(out.ends_with("output: \"\\x00\"\nhalted after 9 steps\n"));
+
+        let (sender, controls) = mpsc::channel();
+        sender.send(Control::Quit).unwrap();
+        let mut out = Vec::new();
+        let teacher = Teacher::<u8>::new(&program, &settings(&[]), Vec::new());
+        assert!(teacher.run(1, Duration::ZERO, &controls, &mut out).unwrap());
+        assert!(String::from_utf8(out)
+            .unwrap()
+            .ends_with("stopped after 1 steps\n"));
+    }
+
+    #[test]
+    fn test_step_limit() {
+        let program = BfProgram::new("+[]".to_string(), "t.bf").unwrap();
+        let settings = settings(&["--max-steps", "5"]);
+        let mut teacher = Teacher::<u8>::new(&program, &settings, Vec::new());
+        for _ in 0..5 {
+            assert!(matches!(teacher.step(false), Taken::Step(_)));
+        }
+        assert!(matches!(teacher.step(false), Taken::Failed(_)));
+    }
+}
@@ -0,0 +1,68 @@
+//! How long each phase of a run took, and how much tape it used, as printed
+//! to stderr with `--timings`.
+
+use std::fmt;
+use std::time::Duration;
+
+/// How much of the tape a run used.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct TapeUsage {
+    /// The most bytes allocated for the tape at once.
+    pub(crate) peak_bytes: usize,
+    /// The number of cells in the tape once the run finished.
+    pub(crate) final_cells: usize,
+}
+
+/// The wall-clock time of each phase of a run, along with its tape usage.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct Timings {
+    /// Reading and parsing the program, apart from pairing its brackets.
+    pub(crate) parse: Duration,
+    /// Pairing the brackets of the program.
+    pub(crate) brackets: Duration,
+    /// Lowering and optimizing the program, if it was.
+    pub(crate) optimize: Duration,
+    /// Running the program.
+    pub(crate) execute: Duration,
+    pub(crate) tape: TapeUsage,
+}
+
+impl fmt::Display for Timings {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "parse:      {:?}", self.parse)?;
+        writeln!(f, "brackets:   {:?}", self.brackets)?;
+        writeln!(f, "optimize:   {:?}", self.optimize)?;
+        writeln!(f, "execute:    {:?}", self.execute)?;
+        writeln!(f, "peak tape:  {} bytes", self.tape.peak_bytes)?;
+        write!(f, "final tape: {} cells", self.tape.final_cells)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TapeUsage, Timings};
+    use std::time::Duration;
+
+    #[test]
+    fn test_display() {
+        let timings = Timings {
+            parse: Duration::from_micros(1500),
+            brackets: Duration::from_micros(20),
+            optimize: Duration::ZERO,
+            execute: Duration::from_secs(2),
+            tape: TapeUsage {
+                peak_bytes: 60_000,
+                final_cells: 30_000,
+            },
+        };
+        assert_eq!(
+            timings.to_string(),
+            "parse:      1.5ms\n\
+             brackets:   20µs\n\
+             optimize:   0ns\n\
+             execute:    2s\n\
+             peak tape:  60000 bytes\n\
+             final tape: 30000 cells"
+        );
+    }
+}
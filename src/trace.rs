@@ -0,0 +1,308 @@
+//! Exporting a trace of a run in Chrome's trace-event format with
+//! `--trace-out`, so that it can be opened as a timeline in Perfetto or
+//! `chrome://tracing`.
+//!
+//! Each loop is a span from the step which enters it to the step which leaves
+//! it, and each input and output is an instant event. Timestamps count steps,
+//! so one microsecond on the timeline is one step of the program.
+
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use bft_interp::ir::IrOp;
+use bft_interp::report::{Reporter, Step, TracedOp};
+use bft_types::ops::Operation;
+use bft_types::BfProgram;
+use serde::Serialize;
+
+/// The position of an instruction in the original program, as a line and a
+/// column.
+pub(crate) type Position = (usize, usize);
+
+/// How a step changed which loops are running.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum LoopChange {
+    /// The loop starting at the position was entered.
+    Entered(Position),
+    /// The innermost loop was left.
+    Left,
+}
+
+/// Keeps track of which loops are running as a program is traced, by the
+/// position of the `[` of each loop.
+#[derive(Debug, Default)]
+pub(crate) struct LoopStack {
+    /// For the position of each `]`, the position of its matching `[`.
+    loop_starts: HashMap<Position, Position>,
+    /// The positions of the `[` of each loop which has been entered and not
+    /// yet left, innermost last.
+    open: Vec<Position>,
+}
+
+impl LoopStack {
+    /// Pairs up the loops of the program, which must be done before any steps
+    /// are given.
+    pub(crate) fn parsed(&mut self, program: &BfProgram) {
+        let instructions = program.instructions();
+        for (position, instruction) in instructions.iter().enumerate() {
+            if instruction.operation() == Operation::EndLoop {
+                if let Some(start) = program.jump_target(position) {
+                    let start = instructions[start];
+                    self.loop_starts.insert(
+                        (instruction.line(), instruction.column()),
+                        (start.line(), start.column()),
+                    );
+                }
+            }
+        }
+    }
+
+    /// Updates the loops which are running for the step about to be run.
+    pub(crate) fn step(&mut self, step: &Step<'_>) -> Option<LoopChange> {
+        let source = step.source();
+        let position = (source.line(), source.column());
+        match step.operation() {
+            TracedOp::Instruction(Operation::StartLoop)
+            | TracedOp::Node(IrOp::LoopStart)
+                if step.cell() != 0 =>
+            {
+                self.open.push(position);
+                Some(LoopChange::Entered(position))
+            }
+            // A loop which is skipped over may still run its `]`, which only
+            // leaves the loop if it was entered.
+            TracedOp::Instruction(Operation::EndLoop)
+            | TracedOp::Node(IrOp::LoopEnd)
+                if step.cell() == 0
+                    && self.open.last() == self.loop_starts.get(&position) =>
+            {
+                self.open.pop();
+                Some(LoopChange::Left)
+            }
+            _ => None,
+        }
+    }
+
+    /// The positions of the loops which are running, outermost first.
+    pub(crate) fn open(&self) -> &[Position] {
+        &self.open
+    }
+
+    /// Leaves the innermost loop, returning false if none were running.
+    pub(crate) fn leave(&mut self) -> bool {
+        self.open.pop().is_some()
+    }
+}
+
+/// Names a loop after the position of its `[`.
+pub(crate) fn loop_name((line, column): Position) -> String {
+    format!("loop {}:{}", line, column)
+}
+
+/// A single event of the trace.
+#[derive(Debug, Serialize)]
+struct Event {
+    name: String,
+    ph: &'static str,
+    ts: u64,
+    pid: u32,
+    tid: u32,
+    /// The scope of an instant event, which is always the thread.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    s: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    args: Option<serde_json::Value>,
+}
+
+impl Event {
+    /// An event of the given phase, at the given step.
+    fn new(name: String, ph: &'static str, ts: u64) -> Self {
+        Self {
+            name,
+            ph,
+            ts,
+            pid: 1,
+            tid: 1,
+            s: None,
+            args: None,
+        }
+    }
+
+    /// An instant event, such as reading input, at the given step.
+    fn instant(name: &str, ts: u64, args: serde_json::Value) -> Self {
+        Self {
+            s: Some("t"),
+            args: Some(args),
+            ..Self::new(name.to_string(), "i", ts)
+        }
+    }
+}
+
+/// Writes a trace of each step it is given as a JSON array of trace events,
+/// one event to a line.
+pub(crate) struct ChromeTrace<W: Write> {
+    writer: W,
+    loops: LoopStack,
+    /// The number of events written so far.
+    events: u64,
+    /// The number of the last step traced.
+    last_step: u64,
+    /// The first error from writing the trace, which is returned by `finish`
+    /// as steps cannot fail.
+    error: Option<io::Error>,
+}
+
+impl<W: Write> ChromeTrace<W> {
+    /// Creates a trace which writes to the given writer, which should be
+    /// buffered.
+    pub(crate) fn new(writer: W) -> Self {
+        Self {
+            writer,
+            loops: LoopStack::default(),
+            events: 0,
+            last_step: 0,
+            error: None,
+        }
+    }
+
+    /// Writes an event, keeping the first error.
+    fn write(&mut self, event: &Event) {
+        if self.error.is_some() {
+            return;
+        }
+        let separator = if self.events == 0 { "[\n" } else { ",\n" };
+        let result =
+            self.writer.write_all(separator.as_bytes()).and_then(|_| {
+                serde_json::to_writer(&mut self.writer, event)
+                    .map_err(io::Error::from)
+            });
+        self.events += 1;
+        if let Err(err) = result {
+            self.error = Some(err);
+        }
+    }
+
+    /// Ends any loops still open, such as when the program failed, and ends
+    /// the trace.
+    pub(crate) fn finish(mut self) -> io::Result<W> {
+        while self.loops.leave() {
+            self.write(&Event::new(String::new(), "E", self.last_step + 1));
+        }
+        if self.events == 0 {
+            self.writer.write_all(b"[")?;
+        }
+        self.writer.write_all(b"\n]\n")?;
+        self.writer.flush()?;
+        match self.error {
+            Some(err) => Err(err),
+            None => Ok(self.writer),
+        }
+    }
+}
+
+impl<W: Write> Reporter for ChromeTrace<W> {
+    fn parsed(&mut self, program: &BfProgram) {
+        self.loops.parsed(program);
+    }
+
+    fn traces(&self) -> bool {
+        true
+    }
+
+    fn step(&mut self, step: &Step<'_>) {
+        let number = step.number();
+        self.last_step = number;
+        match self.loops.step(step) {
+            Some(LoopChange::Entered(position)) => {
+                self.write(&Event::new(loop_name(position), "B", number));
+                return;
+            }
+            Some(LoopChange::Left) => {
+                self.write(&Event::new(String::new(), "E", number + 1));
+                return;
+            }
+            None => {}
+        }
+        match step.operation() {
+            TracedOp::Instruction(Operation::OutputByte)
+            | TracedOp::Node(IrOp::Output) => {
+                let args = serde_json::json!({ "value": step.cell() });
+                self.write(&Event::instant("output", number, args));
+            }
+            TracedOp::Node(IrOp::OutputBytes(bytes)) => {
+                let args = serde_json::json!({ "bytes": bytes.len() });
+                self.write(&Event::instant("output", number, args));
+            }
+            TracedOp::Instruction(Operation::InputByte)
+            | TracedOp::Node(IrOp::Input) => {
+                let args = serde_json::json!({ "head": step.head() });
+                self.write(&Event::instant("input", number, args));
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ChromeTrace;
+    use bft_interp::ir::IrProgram;
+    use bft_interp::optimizer::Pipeline;
+    use bft_interp::report::Reporter;
+    use bft_interp::VirtualMachine;
+    use bft_types::BfProgram;
+    use std::io::Cursor;
+
+    /// Traces the program, optionally lowered with the given passes, and
+    /// returns the events of the trace.
+    fn trace(source: &str, passes: Option<&str>) -> serde_json::Value {
+        let program = BfProgram::new(source.to_string(), "test.bf").unwrap();
+        let mut trace = ChromeTrace::new(Vec::new());
+        trace.parsed(&program);
+        let ir = passes.map(|spec| {
+            let mut ir = IrProgram::from_program(&program).unwrap();
+            Pipeline::from_spec(spec).unwrap().run(&mut ir);
+            ir
+        });
+        let mut vm = VirtualMachine::<u8>::new(&program, 0, false)
+            .with_reporter(&mut trace);
+        let mut input = Cursor::new(vec![7]);
+        let mut output = Vec::new();
+        match &ir {
+            Some(ir) => vm.interpret_ir(ir, &mut input, &mut output),
+            None => vm.interpret(&mut input, &mut output),
+        }
+        .unwrap();
+        drop(vm);
+        serde_json::from_slice(&trace.finish().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_loop_spans_and_io() {
+        // The second loop is skipped, so only the first has a span.
+        let source = ",[>+<-]>.<[-]";
+        for passes in [None, Some("none"), Some("rle")] {
+            let events = trace(source, passes);
+            let events = events.as_array().unwrap();
+            let phases: Vec<&str> =
+                events.iter().map(|e| e["ph"].as_str().unwrap()).collect();
+            assert_eq!(phases, ["i", "B", "E", "i"], "{:?}", passes);
+            assert_eq!(events[0]["name"], "input");
+            assert_eq!(events[1]["name"], "loop 1:2");
+            assert_eq!(events[3]["name"], "output");
+            assert_eq!(events[3]["args"]["value"], 7);
+        }
+    }
+
+    #[test]
+    fn test_unfinished_loops_are_closed() {
+        let mut trace = ChromeTrace::new(Vec::new());
+        trace.loops.open.push((1, 2));
+        let events: serde_json::Value =
+            serde_json::from_slice(&trace.finish().unwrap()).unwrap();
+        assert_eq!(events[0]["ph"], "E");
+
+        let empty = ChromeTrace::new(Vec::new()).finish().unwrap();
+        assert_eq!(empty, b"[\n]\n");
+    }
+}
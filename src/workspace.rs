@@ -0,0 +1,315 @@
+//! `bft check --workspace`, which checks every program of a project, or of a
+//! directory, at once. Each program is parsed in recovery mode, so that every
+//! problem with it is found rather than just the first, and with
+//! `--portability` is also checked for the places it may rely on 8 bit cells.
+//! The programs are checked in parallel, and the problems are reported
+//! grouped by file, followed by a count of them all.
+
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use bft_types::bfz;
+use bft_types::options::BracketValidation;
+use bft_types::source_db::{FileId, SourceDb};
+use bft_types::BfProgram;
+
+use crate::check::check;
+use crate::cli::CheckArgs;
+use crate::config::{Config, Settings};
+use crate::parse_options;
+use crate::project::Project;
+
+/// The extensions of the files taken to be programs, when checking a
+/// directory without a project file.
+const PROGRAM_EXTENSIONS: [&str; 3] = ["bf", "b", bfz::EXTENSION];
+
+/// A program to check, with the settings to check it with, or why it could
+/// not be found.
+struct Target {
+    path: PathBuf,
+    settings: Result<Settings, String>,
+}
+
+/// The problems found with one file.
+struct FileReport {
+    file: FileId,
+    problems: Vec<String>,
+}
+
+/// Finds every program under the directory, leaving out hidden files and
+/// directories, in order of their paths.
+fn find_programs(dir: &Path) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let mut programs = Vec::new();
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            let hidden = path
+                .file_name()
+                .is_some_and(|name| name.to_string_lossy().starts_with('.'));
+            if hidden {
+                continue;
+            }
+            if path.is_dir() {
+                dirs.push(path);
+            } else if path.extension().is_some_and(|extension| {
+                PROGRAM_EXTENSIONS.iter().any(|known| extension == *known)
+            }) {
+                programs.push(path);
+            }
+        }
+    }
+    programs.sort();
+    Ok(programs)
+}
+
+/// The programs of the workspace: those of the project file given, or of the
+/// project file in the directory given, or else every program in the
+/// directory. Without a path, the project of the current directory is used,
+/// or else the current directory.
+fn targets(
+    args: &CheckArgs,
+    workspace: Option<&Path>,
+) -> Result<Vec<Target>, Box<dyn Error>> {
+    let project = match workspace {
+        Some(path) if path.is_file() => Some(path.to_path_buf()),
+        Some(path) => {
+            Project::find(path).filter(|found| found.parent() == Some(path))
+        }
+        None => Project::find(&std::env::current_dir()?),
+    };
+    let fallback = Config::load(args.run.config.as_deref())?;
+    if let Some(project) = project {
+        let project = Project::load(Some(&project))?;
+        return Ok(project
+            .targets(&args.run, &fallback)
+            .into_iter()
+            .map(|(path, settings)| Target { path, settings })
+            .collect());
+    }
+    let dir = workspace.unwrap_or(Path::new("."));
+    let settings = Settings::resolve(&args.run, fallback)?;
+    Ok(find_programs(dir)?
+        .into_iter()
+        .map(|path| Target {
+            path,
+            settings: Ok(settings.clone()),
+        })
+        .collect())
+}
+
+/// Checks one program, adding it to the database.
+fn check_target(
+    target: &Target,
+    portability: bool,
+    db: &SourceDb,
+) -> FileReport {
+    let settings = match &target.settings {
+        Ok(settings) => settings,
+        Err(err) => {
+            return FileReport {
+                file: db.intern(&target.path),
+                problems: vec![err.clone()],
+            };
+        }
+    };
+    let options =
+        parse_options(settings).bracket_validation(BracketValidation::Recover);
+    let program =
+        match BfProgram::from_file_with_options(&target.path, &options) {
+            Ok(program) => program,
+            Err(err) => {
+                return FileReport {
+                    file: db.intern(&target.path),
+                    problems: vec![err.to_string()],
+                };
+            }
+        };
+    let mut problems: Vec<String> = program
+        .diagnostics()
+        .iter()
+        .map(|diagnostic| diagnostic.to_string())
+        .collect();
+    // The portability check needs every loop of the program to be whole.
+    if portability && problems.is_empty() {
+        problems.extend(
+            check(&program, settings.eof)
+                .findings()
+                .iter()
+                .map(|finding| finding.to_string()),
+        );
+    }
+    FileReport {
+        file: db.insert(program),
+        problems,
+    }
+}
+
+/// Checks every target, sharing them out between as many threads as there
+/// are processors, giving their reports in the order of the targets.
+fn check_all(
+    targets: &[Target],
+    portability: bool,
+    db: &SourceDb,
+) -> Vec<FileReport> {
+    let next = AtomicUsize::new(0);
+    let workers = thread::available_parallelism()
+        .map_or(1, |count| count.get())
+        .min(targets.len());
+    let mut reports: Vec<(usize, FileReport)> = thread::scope(|scope| {
+        let handles: Vec<_> = (0..workers)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut reports = Vec::new();
+                    loop {
+                        let index = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(target) = targets.get(index) else {
+                            return reports;
+                        };
+                        reports.push((
+                            index,
+                            check_target(target, portability, db),
+                        ));
+                    }
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().expect("checking panicked"))
+            .collect()
+    });
+    reports.sort_by_key(|(index, _)| *index);
+    reports.into_iter().map(|(_, report)| report).collect()
+}
+
+/// Writes the problems with each file which has any, then a count of them.
+fn summarize(reports: &[FileReport], db: &SourceDb) -> String {
+    let mut summary = String::new();
+    for report in reports.iter().filter(|report| !report.problems.is_empty()) {
+        let path = db.path(report.file).expect("files are interned");
+        summary.push_str(&format!("{}:\n", path.display()));
+        for problem in &report.problems {
+            summary.push_str(&format!("  {}\n", problem));
+        }
+    }
+    let failed = reports
+        .iter()
+        .filter(|report| !report.problems.is_empty())
+        .count();
+    let problems: usize =
+        reports.iter().map(|report| report.problems.len()).sum();
+    summary.push_str(&format!(
+        "Checked {} programs: {} with problems, {} problems in all.\n",
+        reports.len(),
+        failed,
+        problems
+    ));
+    summary
+}
+
+/// Runs `bft check --workspace`, failing if any program has a problem.
+pub(crate) fn run_check_workspace(
+    args: &CheckArgs,
+    workspace: Option<&Path>,
+) -> Result<ExitCode, Box<dyn Error>> {
+    let targets = targets(args, workspace)?;
+    let db = SourceDb::new();
+    let reports = check_all(&targets, args.portability, &db);
+    print!("{}", summarize(&reports, &db));
+    if reports.iter().all(|report| report.problems.is_empty()) {
+        Ok(ExitCode::SUCCESS)
+    } else {
+        Ok(ExitCode::FAILURE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_all, summarize, targets};
+    use crate::cli::{Args, CheckArgs};
+    use bft_types::source_db::SourceDb;
+    use clap::Parser;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "bft-workspace-{}-{}",
+            name,
+            std::process::id()
+        ));
+        fs::create_dir_all(dir.join("nested")).unwrap();
+        fs::create_dir_all(dir.join(".hidden")).unwrap();
+        dir
+    }
+
+    fn check_args(flags: &[&str]) -> CheckArgs {
+        let mut argv = vec!["bft", "check"];
+        argv.extend_from_slice(flags);
+        match Args::parse_from(argv).command {
+            Some(crate::cli::Command::Check(args)) => args,
+            command => panic!("parsed {:?}", command),
+        }
+    }
+
+    fn check_workspace(dir: &Path, portability: bool) -> String {
+        let mut flags = vec!["--workspace", dir.to_str().unwrap()];
+        if portability {
+            flags.push("--portability");
+        }
+        let args = check_args(&flags);
+        let targets = targets(&args, Some(dir)).unwrap();
+        let db = SourceDb::new();
+        let reports = check_all(&targets, portability, &db);
+        summarize(&reports, &db).replace(&format!("{}/", dir.display()), "")
+    }
+
+    #[test]
+    fn test_check_directory() {
+        let dir = temp_dir("directory");
+        fs::write(dir.join("wraps.bf"), "-.").unwrap();
+        fs::write(dir.join("notes.txt"), "[[[").unwrap();
+        fs::write(dir.join(".hidden").join("bad.bf"), "[").unwrap();
+        fs::write(dir.join("nested").join("bad.b"), "+]\n[[-]").unwrap();
+        let summary = check_workspace(&dir, false);
+        let wrapping = check_workspace(&dir, true);
+        fs::remove_dir_all(&dir).unwrap();
+        let lines: Vec<&str> = summary.lines().collect();
+        assert_eq!(lines[0], "nested/bad.b:");
+        assert_eq!(lines.len(), 4);
+        assert!(lines[1].starts_with("  ") && lines[2].starts_with("  "));
+        assert_eq!(
+            lines[3],
+            "Checked 2 programs: 1 with problems, 2 problems in all."
+        );
+        // Only programs which parse are checked for portability.
+        assert!(wrapping.contains("wraps.bf:\n"));
+        assert!(wrapping.ends_with("2 with problems, 3 problems in all.\n"));
+    }
+
+    #[test]
+    fn test_check_project() {
+        let dir = temp_dir("project");
+        fs::write(
+            dir.join("bfproject.toml"),
+            "[[program]]\nname = \"good\"\npath = \"good.bf\"\n\n\
+            [[program]]\nname = \"missing\"\npath = \"missing.bf\"\n",
+        )
+        .unwrap();
+        fs::write(dir.join("good.bf"), "+[-]").unwrap();
+        fs::write(dir.join("nested").join("bad.bf"), "[").unwrap();
+        let summary = check_workspace(&dir, false);
+        fs::remove_dir_all(&dir).unwrap();
+        // Only the programs of the project are checked.
+        assert!(summary.starts_with("missing.bf:\n  cannot find"));
+        assert!(!summary.contains("bad.bf"));
+        assert!(summary.ends_with(
+            "Checked 2 programs: 1 with problems, 1 problems in all.\n"
+        ));
+    }
+}
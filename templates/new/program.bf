@@ -0,0 +1,3 @@
+Writes its input back out until there is none left
+
+,[.,]